@@ -0,0 +1,445 @@
+//! Command line arguments
+//!
+//! Every mode is a subcommand, like `goesbox-ui run tcp://localhost:5004 /srv/goes`.  For older
+//! scripts, arguments that don't start with a subcommand are taken as `run` arguments.
+//!
+//! Every `run` option can also be set with an environment variable, like `GOESBOX_OUTPUT_ROOT`
+//! (which is handy for containers and systemd units).  Options on the command line take precedence.
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use goeslib::lrit::DecodeMode;
+use goeslib::quota::ProductClass;
+use goeslib::stream::StreamFormat;
+
+/// Receives, decodes, and archives the GOES HRIT/LRIT downlink
+#[derive(Debug, Parser)]
+#[command(name = "goesbox-ui", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// Parses the arguments the program was started with
+    pub fn from_env() -> Cli {
+        Cli::from_args(std::env::args_os())
+    }
+
+    /// Parses `args` (including the program name), exiting with a usage message if they're invalid
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> Cli {
+        let mut args: Vec<OsString> = args.into_iter().collect();
+        let first = args.get(1).and_then(|a| a.to_str()).map(str::to_string);
+        match first {
+            Some(first) => {
+                let known =
+                    first.starts_with('-') || first == "help" || Cli::command().find_subcommand(&first).is_some();
+                if !known {
+                    args.insert(1, "run".into());
+                }
+            }
+            // everything can come from the environment
+            None if std::env::var_os("GOESBOX_TARGETS").is_some() => args.push("run".into()),
+            None => {}
+        }
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Receive VCDUs from goesrecv, with the live UI
+    Run(RunArgs),
+    /// Run a raw capture (VCDUs back to back) through the decoder and handlers
+    Replay(ReplayArgs),
+    /// Print the headers of LRIT files
+    Dump(DumpArgs),
+    /// Search the product index
+    Query(QueryArgs),
+    /// Check the product index and daily archives of an output root
+    Verify(VerifyArgs),
+    /// Make the reduced size copies (and other outputs derived from images) again, for products
+    /// that were written by an older version
+    Regen(RegenArgs),
+    /// Parse the names (and bulletins) of the EMWIN products in an output root again, and count
+    /// the WMO codes the parsers don't know
+    Reparse(ReparseArgs),
+    /// Publish a synthetic HRIT stream, for testing and demos without an antenna
+    Simulate(SimulateArgs),
+    /// Write a completeness report for a single day
+    Report(ReportArgs),
+    /// Build an animated GIF out of the last 24 hours of images (or the images a query finds)
+    Timelapse(TimelapseArgs),
+    /// Run saved LRIT files (like the dead-letter directory) back through the handlers
+    Reprocess(ReprocessArgs),
+    /// Merge raw captures of the same time period into a best-of capture
+    Merge(MergeArgs),
+    /// Mirror the output root to a local directory, another machine, or an S3 bucket
+    Sync(SyncArgs),
+    /// Receive VCDUs from goesrecv without the UI, writing a record for each product to stdout
+    Stream(StreamArgs),
+    /// Run known-good LRIT, EMWIN, and DCS data through the decoder and handlers, and check what
+    /// comes out, to make sure this build works here
+    Selftest(SelftestArgs),
+    /// Run a raw capture through the handlers of two config files side by side, and report the
+    /// files they write differently
+    Compare(CompareArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Where goesrecv publishes VCDUs, like tcp://localhost:5004
+    ///
+    /// Several receivers watching the same satellite can be given, separated by commas; duplicate
+    /// VCDUs are dropped.  A raw capture can be read instead, like file:capture.vcdu.
+    #[arg(env = "GOESBOX_TARGETS")]
+    pub targets: String,
+    #[arg(env = "GOESBOX_OUTPUT_ROOT")]
+    pub output_root: String,
+    /// Without the UI, read every target (which all have to be file: captures) to the end, print
+    /// a summary, and exit, with a nonzero status if anything couldn't be read or written
+    #[arg(long, env = "GOESBOX_ONCE")]
+    pub once: bool,
+    /// Publish product events (as JSON) on this nanomsg address, like tcp://*:5005
+    #[arg(env = "GOESBOX_EVENTS")]
+    pub events: Option<String>,
+    /// Serve LRIT files, recent products, and events over gRPC on this address, like
+    /// 0.0.0.0:50051 (needs the grpc feature)
+    #[arg(long, value_name = "ADDRESS", env = "GOESBOX_GRPC")]
+    pub grpc: Option<std::net::SocketAddr>,
+    /// Write stats snapshots (as lines of JSON) to this file, for external monitoring
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "GOESBOX_STATS_FILE",
+        conflicts_with = "stats_socket"
+    )]
+    pub stats_file: Option<PathBuf>,
+    /// Serve stats snapshots on this unix socket
+    #[arg(long, value_name = "PATH", env = "GOESBOX_STATS_SOCKET")]
+    pub stats_socket: Option<PathBuf>,
+    /// How often to write stats snapshots
+    #[arg(long, value_name = "SECONDS", env = "GOESBOX_STATS_INTERVAL", default_value_t = 10)]
+    pub stats_interval: u64,
+    /// Warn when a handler takes longer than this
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        env = "GOESBOX_HANDLER_BUDGET",
+        default_value_t = 1000
+    )]
+    pub handler_budget: u64,
+    /// The config file (see the config module), instead of ~/.config/goesbox/config.toml
+    #[arg(long, value_name = "PATH", env = "GOESBOX_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// "strict" or "tolerant", which overrides the config
+    #[arg(long, env = "GOESBOX_DECODE")]
+    pub decode: Option<DecodeMode>,
+    /// Decode everything, but only keep stats and the index in memory, for judging reception
+    /// (like when trying out antennas) without filling the output root
+    #[arg(long, env = "GOESBOX_NO_WRITE")]
+    pub no_write: bool,
+    /// Also append log messages to this file
+    #[arg(long, value_name = "PATH", env = "GOESBOX_LOG_FILE")]
+    pub log_file: Option<PathBuf>,
+    /// Show debug messages from these modules, separated by commas, like goeslib::lrit
+    #[arg(long, value_name = "TARGETS", env = "GOESBOX_LOG_DEBUG")]
+    pub debug: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    pub capture: PathBuf,
+    pub output_root: String,
+}
+
+#[derive(Debug, Args)]
+pub struct DumpArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// The VCID to report, for files without a dead-letter sidecar file
+    #[arg(long, default_value_t = 0)]
+    pub vcid: u8,
+}
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    pub output_root: String,
+    /// What to search for, like `band=13 region=FD since=6h` or `*TOR* since=2d`
+    ///
+    /// The keys are pattern, vcid, type, product, band, region (FD, CONUS, M1, or M2), since, and
+    /// until.  Times are dates (2022-05-04), times (2022-05-04T18:00), or how long ago (30m, 6h, 2d).
+    pub query: Vec<String>,
+    /// The first day to search (today, or the day of `since=`, by default)
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    pub from: Option<NaiveDate>,
+    /// The last day to search (the same as --from by default)
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    pub to: Option<NaiveDate>,
+    /// Only products whose annotation matches, where `*` matches anything and `?` matches one
+    /// character
+    #[arg(long)]
+    pub pattern: Option<String>,
+    #[arg(long)]
+    pub vcid: Option<u8>,
+    #[arg(long)]
+    pub filetype: Option<u8>,
+    /// The NOAA product ID
+    #[arg(long)]
+    pub product_id: Option<u16>,
+    /// Print each record as a line of JSON
+    #[arg(long)]
+    pub json: bool,
+    /// Print the paths of the files written for each product (that are still there), for scripts
+    #[arg(long, conflicts_with = "json")]
+    pub files: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    pub output_root: String,
+    /// Only check this day (every indexed day by default)
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Args)]
+pub struct RegenArgs {
+    pub output_root: String,
+    /// Only regenerate this day (every indexed day before today by default, since goesbox may
+    /// still be adding to today's index)
+    #[arg(long, value_name = "YYYY-MM-DD")]
+    pub date: Option<NaiveDate>,
+    /// The config file, for how images are written (see the config module)
+    #[arg(long, value_name = "PATH", env = "GOESBOX_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Only count the products that are out of date
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReparseArgs {
+    pub output_root: PathBuf,
+    /// Also print every file that couldn't be parsed or decoded
+    #[arg(long)]
+    pub list: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    /// Where to publish, like tcp://*:5004
+    pub address: String,
+    /// Pace the stream at this rate (the real downlink rate by default)
+    pub bits_per_second: Option<u32>,
+    /// The chance (0 to 1) that each VCDU is dropped
+    #[arg(long = "drop", default_value_t = 0.0)]
+    pub drop_rate: f64,
+    /// The chance (0 to 1) that each VCDU has a bit flipped
+    #[arg(long = "flip", default_value_t = 0.0)]
+    pub flip_rate: f64,
+    /// The chance (0 to 1) that each VCDU is swapped with the next one
+    #[arg(long = "reorder", default_value_t = 0.0)]
+    pub reorder_rate: f64,
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    pub output_root: String,
+    /// Yesterday by default
+    pub date: Option<NaiveDate>,
+    /// A JSON list of expectations (`report::default_expectations()` by default)
+    pub schedule: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct TimelapseArgs {
+    pub output_root: String,
+    /// An annotation pattern (like CMIPF-M6C02), a directory under the output root (like
+    /// suvi/Fe195) to use every image in, or a query (like "band=13 region=FD since=6h")
+    pub pattern: String,
+    pub output: String,
+    /// Skip frames where the sun is down at the sub-satellite point (useful for visible band loops)
+    #[arg(allow_negative_numbers = true)]
+    pub satellite_longitude: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReprocessArgs {
+    /// A directory of LRIT files
+    pub dir: PathBuf,
+    pub output_root: String,
+    /// The VCID of files without a dead-letter sidecar file
+    #[arg(default_value_t = 0)]
+    pub default_vcid: u8,
+    /// The config file, for which handlers to run and how they write (see the config module)
+    #[arg(long, value_name = "PATH", env = "GOESBOX_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct MergeArgs {
+    pub output: String,
+    #[arg(num_args = 2.., required = true)]
+    pub captures: Vec<PathBuf>,
+    /// Then run the merged capture through the decoder, writing products into this output root
+    #[arg(long, value_name = "OUTPUT_ROOT")]
+    pub replay: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    pub output_root: String,
+    /// A directory, `host:dir` (like `pi@antenna.local:/srv/goes`), `rsync:host:dir`,
+    /// `s3://bucket/prefix`, or an `https://` URL that accepts PUTs
+    pub target: String,
+    /// Repeat the sync forever, this often
+    #[arg(long, value_name = "SECONDS")]
+    pub every: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct StreamArgs {
+    /// Where goesrecv publishes VCDUs, separated by commas
+    #[arg(env = "GOESBOX_TARGETS")]
+    pub targets: String,
+    /// Only stream products of these classes: imagery, text, or other (every product by default)
+    #[arg(long, value_delimiter = ',')]
+    pub class: Vec<ProductClass>,
+    /// "json" for a line of JSON per product, or "length-prefixed" to put the length of each
+    /// record (as a 4 byte big-endian integer) before it instead
+    #[arg(long, default_value = "json")]
+    pub format: StreamFormat,
+    /// Save product data here, and give its path in each record, instead of including the data
+    /// (base64 encoded) in the record
+    #[arg(long, value_name = "DIR")]
+    pub save_dir: Option<PathBuf>,
+    /// "strict" or "tolerant"
+    #[arg(long, env = "GOESBOX_DECODE")]
+    pub decode: Option<DecodeMode>,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    pub capture: PathBuf,
+    /// The config file for the first set of handlers (like the current one)
+    pub config_a: PathBuf,
+    /// The config file for the second set of handlers (like one trying out a change)
+    pub config_b: PathBuf,
+    /// Where the products are written, in `a` and `b` directories under it (which should be
+    /// empty)
+    pub output_root: String,
+    /// Also leave out files matching this pattern, like "debug/*" (the index and image timing
+    /// sidecars, which have receive times in them, always are)
+    #[arg(long, value_name = "PATTERN")]
+    pub ignore: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// Write the handlers' output into this (empty) directory and keep it, instead of a temporary
+    /// directory that's removed afterwards
+    #[arg(long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Command, ProductClass};
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli =
+            Cli::from_args(["goesbox-ui", "tcp://a:5004,tcp://b:5004", "/srv/goes", "--no-write"].map(Into::into));
+        assert!(matches!(cli.command, Command::Run(run) if run.no_write && run.targets.contains(',')));
+
+        let cli = Cli::from_args(
+            [
+                "goesbox-ui",
+                "run",
+                "tcp://a:5004",
+                "/srv/goes",
+                "--debug",
+                "goeslib::lrit",
+            ]
+            .map(Into::into),
+        );
+        assert!(matches!(cli.command, Command::Run(run) if run.debug.as_deref() == Some("goeslib::lrit")));
+
+        let cli = Cli::from_args(["goesbox-ui", "file:a.vcdu", "/srv/goes", "--once"].map(Into::into));
+        assert!(matches!(cli.command, Command::Run(run) if run.once && !run.no_write));
+
+        let cli = Cli::from_args(
+            [
+                "goesbox-ui",
+                "query",
+                "/srv/goes",
+                "--from",
+                "2022-05-04",
+                "--filetype",
+                "2",
+                "band=13",
+                "region=FD",
+            ]
+            .map(Into::into),
+        );
+        match cli.command {
+            Command::Query(query) => {
+                assert_eq!(query.from.unwrap().to_string(), "2022-05-04");
+                assert_eq!(query.filetype, Some(2));
+                assert_eq!(query.query, ["band=13", "region=FD"]);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let cli = Cli::from_args(["goesbox-ui", "timelapse", "/srv/goes", "CMIPF", "out.gif", "-75.2"].map(Into::into));
+        assert!(matches!(cli.command, Command::Timelapse(t) if t.satellite_longitude == Some(-75.2)));
+
+        let cli = Cli::from_args(["goesbox-ui", "regen", "/srv/goes", "--dry-run"].map(Into::into));
+        assert!(matches!(cli.command, Command::Regen(r) if r.dry_run && r.date.is_none()));
+
+        let cli = Cli::from_args(
+            [
+                "goesbox-ui",
+                "reprocess",
+                "dead",
+                "/srv/goes",
+                "--config",
+                "goesbox.toml",
+            ]
+            .map(Into::into),
+        );
+        assert!(matches!(cli.command, Command::Reprocess(r) if r.config.is_some() && r.default_vcid == 0));
+
+        let cli = Cli::from_args(["goesbox-ui", "reparse", "/srv/goes", "--list"].map(Into::into));
+        assert!(matches!(cli.command, Command::Reparse(r) if r.list));
+
+        let cli = Cli::from_args(["goesbox-ui", "stream", "tcp://a:5004", "--class", "text,other"].map(Into::into));
+        assert!(matches!(cli.command, Command::Stream(s) if s.class == [ProductClass::Text, ProductClass::Other]));
+
+        let cli = Cli::from_args(["goesbox-ui", "selftest", "--output", "/tmp/selftest"].map(Into::into));
+        assert!(matches!(cli.command, Command::Selftest(s) if s.output.is_some()));
+
+        let cli = Cli::from_args(
+            [
+                "goesbox-ui",
+                "compare",
+                "a.vcdu",
+                "old.toml",
+                "new.toml",
+                "/tmp/compare",
+                "--ignore",
+                "debug/*",
+            ]
+            .map(Into::into),
+        );
+        assert!(matches!(cli.command, Command::Compare(c) if c.ignore == ["debug/*"]));
+    }
+}
@@ -0,0 +1,1730 @@
+//! The goesbox config file
+//!
+//! The config file is TOML, and is given with `--config <path>` (or `GOESBOX_CONFIG`).  Without
+//! one, `$XDG_CONFIG_HOME/goesbox/config.toml` (normally `~/.config/goesbox/config.toml`) is used
+//! if it exists.  Everything in it is optional, and any setting can be overridden with an
+//! environment variable like `GOESBOX__CACHE__PRODUCTS=20` (see [`Config::resolve`]).
+//!
+//! Command line options take precedence over the environment, which takes precedence over the
+//! file.  For example:
+//!
+//! ```toml
+//! mode = "hrit"
+//! decode = "tolerant"
+//! priority_vcids = [20, 21, 22]
+//!
+//! [output]
+//! umask = "027"
+//! user = "goes"
+//! group = "www-data"
+//! storage = "s3://goes-products/station1"
+//!
+//! [keys]
+//! quit = ["q", "Ctrl-c"]
+//! help = ["?", "F1"]
+//!
+//! [board]
+//! path = "/var/www/html/board.html"
+//! products = [
+//!     { title = "Area Forecast Discussion", pattern = "AFDPHI*" },
+//!     { title = "Boston METARs", pattern = "MTRBOS*" },
+//! ]
+//!
+//! [text]
+//! history = ["ZFPPHI*", "AFDPHI*"]
+//! duplicates = "version"
+//! collision_minutes = 60
+//!
+//! [glm]
+//! overlays = true
+//!
+//! [change]
+//! threshold_percent = 2.5
+//! difference_images = true
+//!
+//! [levels]
+//! percentiles = [2.0, 98.0]
+//! bands = { C02 = [1.0, 99.0], C13 = false }
+//! regions = { "Mesoscale 1" = [5.0, 95.0] }
+//!
+//! [[storm_watch]]
+//! name = "Philadelphia"
+//! south = 39.5
+//! north = 40.5
+//! west = -76.0
+//! east = -74.5
+//! threshold = 215.0
+//! command = "notify-send \"Storms over $STORM_WATCH\""
+//!
+//! [dcs]
+//! sources = ["UP", "NP"]
+//!
+//! [dcs_drift]
+//! interval_minutes = 10
+//!
+//! [dcs_coverage]
+//! platforms = "/etc/goesbox/platforms.csv"
+//! window_hours = 24
+//!
+//! [survey]
+//! window_minutes = 1440
+//!
+//! [telemetry]
+//! interval_minutes = 60
+//!
+//! [rates]
+//! interval_minutes = 5
+//! spike_factor = 3.0
+//!
+//! [quarantine]
+//! checksums = true
+//! prometheus = "/var/lib/node_exporter/textfile/goesbox.prom"
+//!
+//! [test_products]
+//! keep = true
+//!
+//! [replay]
+//! max_age_minutes = 180
+//! separate = true
+//!
+//! [clock]
+//! trust = "product"
+//! max_skew_minutes = 20
+//!
+//! [metar]
+//! format = "csv"
+//! stations = ["KBOS", "KJFK"]
+//! station_files = true
+//!
+//! [soundings]
+//! format = "json"
+//!
+//! [space_weather]
+//! alert_command = "notify-send \"$SWPC_HEADLINE\""
+//! alert_scales = ["G3", "R3"]
+//!
+//! [shef]
+//! sqlite = "/var/lib/goesbox/hydro.db"
+//!
+//! [raw]
+//! filetypes = [130]
+//! vcids = [20, 21, 22]
+//! duplicates = "skip"
+//!
+//! [emwin_feed]
+//! address = "emwin.example.com:2211"
+//! xor = true
+//!
+//! [relay]
+//! address = "remote.example.com:5010"
+//! max_kbps = 256
+//!
+//! [[forward]]
+//! name = "archive"
+//! target = "s3://goes-archive/station1"
+//! patterns = ["emwin/*", "*.png"]
+//!
+//! [influx]
+//! output = "http://localhost:8086/api/v2/write?org=home&bucket=goes"
+//! token = "..."
+//! stats = true
+//!
+//! [cache]
+//! products = 20
+//! ram_only = false
+//!
+//! [[notify]]
+//! title = "Tornado Warning"
+//! patterns = ["*-TOR*"]
+//! zones = ["PAC017", "NJZ*"]
+//!
+//! [[notify]]
+//! title = "Warning for home"
+//! patterns = ["*-TOR*", "*-SVR*", "*-FFW*"]
+//! location = [40.31, -75.13]
+//!
+//! [zones]
+//! shapefiles = ["/usr/share/nws/c_05mr24.shp", "/usr/share/nws/z_05mr24.shp"]
+//!
+//! [[schedule]]
+//! product = "AFDPHI"
+//! cron = "30 */6 * * *"
+//! grace_minutes = 30
+//!
+//! [format]
+//! pyramid_levels = 3
+//! headers = "sidecar"
+//! timing_sidecar = true
+//! streaming_assembly = true
+//!
+//! [profiles.web]
+//! root = "/var/www/html/goes"
+//! filetypes = [0]
+//! patterns = ["*CMIPF*"]
+//! retention_days = 2
+//! format = { pyramid_levels = 1 }
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use goeslib::clock::{ClockSkew, TimeSource};
+use goeslib::emwin::interleaved::InterleavedAssembler;
+use goeslib::emwin::swpc::NoaaScale;
+use goeslib::emwin::zones::ZoneMap;
+use goeslib::events::EventSender;
+use goeslib::forward::{ForwardSpool, Forwarder};
+use goeslib::handlers::{
+    BoardHandler, ChangeDetector, ChecksumVerifier, CollisionDetector, DcsCoverageHandler, DcsDriftHandler, DcsHandler,
+    DcsSource, Dispatcher, DuplicatePolicy, HeaderPassthrough, LatLonBox, MetarHandler, NotifyHandler, NotifyRule,
+    ObservationFormat, Quarantine, RateMonitor, RawLritHandler, ShefHandler, SoundingHandler, SpaceWeatherHandler,
+    StormWatch,
+};
+use goeslib::i18n::Language;
+use goeslib::influx::{InfluxOutput, InfluxSender, InfluxWriter};
+use goeslib::levels::{AutoLevels, Stretch};
+use goeslib::lrit::{DecodeMode, DownlinkMode, Vcid};
+use goeslib::mirror::parse_target;
+use goeslib::permissions::{parse_mode, OutputPermissions};
+use goeslib::profile::{Retention, Route};
+use goeslib::quota::{ProductClass, Quotas};
+use goeslib::relay::{Relay, RelayOptions};
+use goeslib::replay::ReplayFilter;
+use goeslib::schedule::{Cadence, Expected};
+use goeslib::storage::{parse_storage, SharedStorage};
+use goeslib::survey::SurveyHandler;
+use goeslib::telemetry::{UnknownCodeHandler, UnknownCodes};
+use goeslib::testproducts::TestProductFilter;
+use serde::{Deserialize, Deserializer};
+use termion::event::Key;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// "hrit" or "lrit", if it shouldn't be detected from the downlink
+    pub mode: Option<DownlinkMode>,
+    /// "strict" (the default) to stop on decoder bugs, or "tolerant" to drop the data in question
+    /// and carry on, see [`DecodeMode`]
+    pub decode: DecodeMode,
+    /// Always skip this many bytes before the primary header of an LRIT file, instead of looking
+    /// for it (it's normally after [`PREAMBLE_LEN`](goeslib::lrit::PREAMBLE_LEN) bytes)
+    pub preamble: Option<usize>,
+    /// "en" or "es", for the UI and product descriptions (by default, from `LANG`)
+    pub language: Option<Language>,
+    /// Ownership and permissions of output files, see [`OutputPermissions`]
+    pub output: Option<OutputConfig>,
+    pub keys: KeyConfig,
+    /// Text products, see [`TextHandler`](goeslib::handlers::TextHandler)
+    pub text: TextConfig,
+    /// SUVI solar images, see [`SuviHandler`](goeslib::handlers::SuviHandler)
+    pub suvi: SuviConfig,
+    /// Lightning mapper products, see [`GlmHandler`](goeslib::handlers::GlmHandler)
+    pub glm: GlmConfig,
+    /// Compare each segmented image with the one before it, see [`ChangeDetector`]
+    pub change: Option<ChangeConfig>,
+    /// Stretch the contrast of images before they're written, see [`AutoLevels`]
+    pub levels: Option<LevelsConfig>,
+    /// Areas to watch for cold cloud tops in infrared images, see [`StormWatch`]
+    pub storm_watch: Vec<StormWatchConfig>,
+    /// DCS messages, see [`DcsHandler`]
+    pub dcs: DcsConfig,
+    /// Frequency offsets of DCS channels over time, see [`DcsDriftHandler`]
+    pub dcs_drift: Option<DcsDriftConfig>,
+    /// A map of the DCS platforms that have been heard, see [`DcsCoverageHandler`]
+    pub dcs_coverage: Option<DcsCoverageConfig>,
+    /// A board of the latest EMWIN products, see [`BoardHandler`]
+    pub board: Option<BoardConfig>,
+    /// Decoded METAR and TAF reports, see [`MetarHandler`]
+    pub metar: Option<MetarConfig>,
+    /// Decoded upper air soundings, see [`SoundingHandler`]
+    pub soundings: Option<SoundingConfig>,
+    /// SWPC space weather messages, see [`SpaceWeatherHandler`]
+    pub space_weather: Option<SpaceWeatherConfig>,
+    /// Decoded SHEF hydrologic data, see [`ShefHandler`]
+    pub shef: Option<ShefConfig>,
+    /// Complete LRIT files, see [`RawLritHandler`]
+    pub raw: Option<RawConfig>,
+    /// Checks that LRIT files have to pass before they're handled, see [`Quarantine`]
+    pub quarantine: QuarantineConfig,
+    /// Test products (like required weekly tests) kept out of the archive, see [`TestProductFilter`]
+    pub test_products: Option<TestProductsConfig>,
+    /// Replayed data (like stored mission data sent again), see [`ReplayFilter`]
+    pub replay: Option<ReplayConfig>,
+    /// Hourly limits on what's written, for small disks, see [`Quotas`]
+    pub quotas: Option<QuotasConfig>,
+    /// A report of every kind of product that's received, see [`SurveyHandler`]
+    pub survey: Option<SurveyConfig>,
+    /// A local report of the codes the parsers don't know, see [`UnknownCodeHandler`]
+    pub telemetry: Option<TelemetryConfig>,
+    /// Flag classes of products that stop arriving or spike, see [`RateMonitor`]
+    pub rates: Option<RatesConfig>,
+    /// Also receive EMWIN products from a terrestrial feed, see [`InterleavedAssembler`]
+    pub emwin_feed: Option<EmwinFeedConfig>,
+    /// Re-send products to another receiver, see [`Relay`]
+    pub relay: Option<RelayConfig>,
+    /// Upload new products elsewhere, retrying until they're sent, see [`goeslib::forward`]
+    pub forward: Vec<ForwardConfig>,
+    /// Export observations in InfluxDB line protocol, see [`goeslib::influx`]
+    pub influx: Option<InfluxConfig>,
+    /// Products to show a desktop notification for, see [`NotifyHandler`]
+    pub notify: Vec<NotifyConfig>,
+    /// The boundaries of counties and zones, for notifications by location, see [`ZoneMap`]
+    pub zones: ZonesConfig,
+    /// Products that are expected on a schedule, which are flagged when they're overdue
+    pub schedule: Vec<ScheduleConfig>,
+    /// Recent products kept in memory, see [`ProductCache`](goeslib::cache::ProductCache)
+    pub cache: Option<CacheConfig>,
+    /// How products are written to the output root
+    pub format: ProductFormat,
+    /// LRIT files from these virtual channels are handled before any others (the EMWIN channels,
+    /// 20 to 22, by default), see [`DispatchQueue`](goeslib::handlers::DispatchQueue)
+    pub priority_vcids: Option<Vec<Vcid>>,
+    /// More output roots, each with its own products, format, and retention, see
+    /// [`goeslib::profile`]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    /// Flag a local clock that disagrees with product times, see [`ClockSkew`]
+    pub clock: ClockConfig,
+}
+
+/// Environment variables starting with this override settings from the config file
+const ENV_PREFIX: &str = "GOESBOX__";
+
+impl Config {
+    /// Loads the config from `path` (from `--config` or `GOESBOX_CONFIG`), or from the default
+    /// location if there's a file there, and then applies `GOESBOX__` environment variables
+    pub fn resolve(path: Option<&Path>) -> Result<Config, Box<dyn std::error::Error>> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| default_path().filter(|p| p.exists()));
+        let text = match &path {
+            Some(path) => std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+            None => String::new(),
+        };
+        let source = match &path {
+            Some(path) => path.display().to_string(),
+            None => "config".to_string(),
+        };
+        Config::parse(&text, utf8_vars(std::env::vars_os())).map_err(|e| format!("{}: {}", source, e).into())
+    }
+
+    /// Parses a config file, with overrides from environment variables
+    ///
+    /// A variable like `GOESBOX__CACHE__PRODUCTS=20` sets `products` in the `[cache]` section.
+    /// Values are TOML (like `["UP", "NP"]`), and anything that isn't valid TOML is taken as a
+    /// string, so `GOESBOX__DECODE=tolerant` works without quotes.
+    fn parse(text: &str, env: impl IntoIterator<Item = (String, String)>) -> Result<Config, String> {
+        let mut table: toml::value::Table = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (name, value) in env {
+            let keys = match name.strip_prefix(ENV_PREFIX) {
+                Some(keys) => keys.to_ascii_lowercase(),
+                None => continue,
+            };
+            let value = toml::from_str::<toml::value::Table>(&format!("v = {}", value))
+                .ok()
+                .and_then(|mut t| t.remove("v"))
+                .unwrap_or(toml::Value::String(value));
+            set_key(&mut table, &keys, value).map_err(|e| format!("{}: {}", name, e))?;
+        }
+        let config: Config = toml::Value::Table(table).try_into().map_err(|e| e.to_string())?;
+        if matches!(&config.metar, Some(m) if m.station_files && m.stations.is_empty()) {
+            return Err("metar.station_files needs a list of stations".to_string());
+        }
+        if config.notify.iter().any(|n| n.location.is_some()) && config.zones.shapefiles.is_empty() {
+            return Err("notify.location needs zones.shapefiles".to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/goesbox/config.toml`, or `~/.config/goesbox/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("goesbox").join("config.toml"))
+}
+
+/// The environment variables that are valid UTF-8 (the rest can't be `GOESBOX__` overrides)
+fn utf8_vars(
+    vars: impl IntoIterator<Item = (std::ffi::OsString, std::ffi::OsString)>,
+) -> impl Iterator<Item = (String, String)> {
+    vars.into_iter()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+}
+
+/// Sets a value in a table, where `keys` are separated by `__` (like `cache__products`), creating
+/// sections as needed
+fn set_key(table: &mut toml::value::Table, keys: &str, value: toml::Value) -> Result<(), String> {
+    match keys.split_once("__") {
+        Some((section, rest)) => {
+            let entry = table
+                .entry(section.to_string())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+            match entry {
+                toml::Value::Table(section) => set_key(section, rest, value),
+                _ => Err(format!("{} is not a section", section)),
+            }
+        }
+        None => {
+            table.insert(keys.to_string(), value);
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextConfig {
+    /// EMWIN products to keep a history of in `history/<name>.log`, with `*` and `?` wildcards
+    pub history: Vec<String>,
+    /// What to do when a text product or GTS bulletin's file already exists: "overwrite" (the
+    /// default), "skip", or "version", see [`DuplicatePolicy`]
+    pub duplicates: DuplicatePolicy,
+    /// Write a product under another name if a different product was written to the same file in
+    /// the last this many minutes, see [`CollisionDetector`]
+    pub collision_minutes: Option<u64>,
+}
+
+impl TextConfig {
+    pub fn collision_detector(&self) -> Option<CollisionDetector> {
+        let window = std::time::Duration::from_secs(self.collision_minutes? * 60);
+        Some(CollisionDetector::new().with_window(ProductClass::Text, window))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SuviConfig {
+    /// Also recognize SUVI images by this NOAA product ID, not just by their GOES-R product name
+    pub product_id: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How many of the most recent products of each class (imagery, text, and other) to keep
+    pub products: usize,
+    /// Only keep products in memory, instead of running the built in handlers that write them to
+    /// disk (handlers that are set up in this config, like `metar`, still run)
+    #[serde(default)]
+    pub ram_only: bool,
+}
+
+/// How products are written, which can be different for each output profile
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProductFormat {
+    /// How many reduced size copies of each image to write
+    pub pyramid_levels: u8,
+    /// How the original LRIT headers are kept: "none", "prepend", "sidecar", or "json", see
+    /// [`HeaderPassthrough`]
+    pub headers: HeaderPassthrough,
+    /// Write the scan and arrival times of each segmented image next to it (they're always in
+    /// the product index)
+    pub timing_sidecar: bool,
+    /// Assemble segmented images in memory-mapped files under `<output root>/.assembly`, instead
+    /// of in memory, for boards with little RAM
+    pub streaming_assembly: bool,
+}
+
+impl Default for ProductFormat {
+    fn default() -> Self {
+        ProductFormat {
+            pyramid_levels: 3,
+            headers: HeaderPassthrough::None,
+            timing_sidecar: false,
+            streaming_assembly: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub root: String,
+    /// Only these file types (everything, if empty)
+    #[serde(default)]
+    pub filetypes: Vec<u8>,
+    #[serde(default)]
+    pub vcids: Vec<Vcid>,
+    /// Only products whose annotation matches one of these, like "*CMIPF*"
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Delete files once they're this old (files are kept forever by default)
+    pub retention_days: Option<u32>,
+    #[serde(default)]
+    pub format: ProductFormat,
+}
+
+impl ProfileConfig {
+    pub fn route(&self) -> Route {
+        Route {
+            filetypes: self.filetypes.clone(),
+            vcids: self.vcids.clone(),
+            patterns: self.patterns.clone(),
+        }
+    }
+
+    pub fn retention(&self) -> Option<Retention> {
+        let days = self.retention_days?;
+        Some(Retention::new(&self.root, chrono::Duration::days(days as i64)))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GlmConfig {
+    /// Also write GLM images as overlays on the ABI full disk grid
+    pub overlays: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChangeConfig {
+    /// Send an event when more than this percent of an image has changed (5 if not set)
+    pub threshold_percent: Option<f32>,
+    /// How much a pixel has to change by to count (32 if not set)
+    pub pixel_threshold: Option<u8>,
+    /// Compare images at no more than this many pixels on a side (1024 if not set)
+    pub max_dimension: Option<u32>,
+    /// Write the difference of each image and the one before it, as `<name>.diff.jpg`
+    pub difference_images: bool,
+}
+
+impl ChangeConfig {
+    pub fn detector(&self) -> ChangeDetector {
+        let mut detector = ChangeDetector::new();
+        if let Some(percent) = self.threshold_percent {
+            detector = detector.with_threshold(percent);
+        }
+        if let Some(threshold) = self.pixel_threshold {
+            detector = detector.with_pixel_threshold(threshold);
+        }
+        if let Some(pixels) = self.max_dimension {
+            detector = detector.with_max_dimension(pixels);
+        }
+        if self.difference_images {
+            detector = detector.with_difference_images();
+        }
+        detector
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StormWatchConfig {
+    /// What to call the area, in logs and events
+    pub name: String,
+    /// The edges of the area, in degrees
+    pub south: f64,
+    pub north: f64,
+    pub west: f64,
+    pub east: f64,
+    /// Count pixels colder than this, in kelvin (220 if not set)
+    pub threshold: Option<f64>,
+    /// How many cold pixels it takes (4 if not set)
+    pub min_pixels: Option<usize>,
+    /// The bands to watch (C13 if not set)
+    pub bands: Option<Vec<String>>,
+    /// A shell command to run when cold cloud tops show up or go away
+    pub command: Option<String>,
+}
+
+impl StormWatchConfig {
+    pub fn watch(&self) -> StormWatch {
+        let area = LatLonBox {
+            south: self.south,
+            north: self.north,
+            west: self.west,
+            east: self.east,
+        };
+        let mut watch = StormWatch::new(self.name.as_str(), area);
+        if let Some(kelvin) = self.threshold {
+            watch = watch.with_threshold(kelvin);
+        }
+        if let Some(pixels) = self.min_pixels {
+            watch = watch.with_min_pixels(pixels);
+        }
+        if let Some(bands) = &self.bands {
+            watch = watch.with_bands(bands.clone());
+        }
+        match &self.command {
+            Some(command) => watch.with_command(command.as_str()),
+            None => watch,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LevelsConfig {
+    /// The percentiles to stretch images between (2 and 98 if not set), or `false` to only
+    /// stretch the bands and regions listed here
+    pub percentiles: Option<LevelsSetting>,
+    /// Settings for bands, like "C02", which take precedence over `regions`
+    pub bands: HashMap<String, LevelsSetting>,
+    /// Settings for regions, like "Full Disk" or "Mesoscale 1"
+    pub regions: HashMap<String, LevelsSetting>,
+}
+
+/// Percentiles to stretch between, like `[2.0, 98.0]`, or `false` for no stretch (and `true`
+/// for the default one)
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum LevelsSetting {
+    Percentiles([f32; 2]),
+    Enabled(bool),
+}
+
+impl LevelsSetting {
+    fn stretch(self, default: Option<Stretch>) -> Option<Stretch> {
+        match self {
+            LevelsSetting::Percentiles([low, high]) => Some(Stretch::new(low, high)),
+            LevelsSetting::Enabled(true) => default,
+            LevelsSetting::Enabled(false) => None,
+        }
+    }
+}
+
+impl LevelsConfig {
+    pub fn auto_levels(&self) -> AutoLevels {
+        let standard = Some(Stretch::new(2.0, 98.0));
+        let default = match self.percentiles {
+            Some(setting) => setting.stretch(standard),
+            None => standard,
+        };
+        // `true` means the standard stretch, even if there's no default
+        let enabled = default.or(standard);
+        let mut levels = AutoLevels::new(default);
+        for (band, setting) in &self.bands {
+            levels = levels.with_band(band.as_str(), setting.stretch(enabled));
+        }
+        for (region, setting) in &self.regions {
+            levels = levels.with_region(region.as_str(), setting.stretch(enabled));
+        }
+        levels
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcsConfig {
+    /// Only pass on messages from these uplink sites, like "UP" or "NP" (all of them if empty)
+    #[serde(deserialize_with = "deserialize_sources")]
+    pub sources: Vec<DcsSource>,
+}
+
+impl DcsConfig {
+    pub fn handler(&self, output_root: &str) -> DcsHandler {
+        DcsHandler::new(output_root).with_sources(self.sources.iter().copied())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcsDriftConfig {
+    /// How long to average the frequency offsets over (10 minutes by default)
+    pub interval_minutes: Option<u64>,
+    /// Also write the latest means as Prometheus gauges to this file, for node_exporter's
+    /// textfile collector
+    pub prometheus: Option<PathBuf>,
+}
+
+impl DcsDriftConfig {
+    pub fn handler(&self, output_root: &str) -> DcsDriftHandler {
+        let mut handler = DcsDriftHandler::new(output_root);
+        if let Some(minutes) = self.interval_minutes {
+            handler = handler.with_interval(std::time::Duration::from_secs(minutes * 60));
+        }
+        match &self.prometheus {
+            Some(path) => handler.with_prometheus(path),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DcsCoverageConfig {
+    /// A CSV file of where the platforms are, see
+    /// [`PlatformLocations`](goeslib::handlers::PlatformLocations)
+    pub platforms: PathBuf,
+    /// How often to write the map (hourly by default)
+    pub interval_minutes: Option<u64>,
+    /// How long a platform stays on the map after it's heard (24 hours by default)
+    pub window_hours: Option<u64>,
+}
+
+impl DcsCoverageConfig {
+    pub fn handler(&self, output_root: &str) -> Result<DcsCoverageHandler, String> {
+        let mut platforms = goeslib::handlers::PlatformLocations::new();
+        let count = platforms
+            .load_csv(&self.platforms)
+            .map_err(|e| format!("{}: {}", self.platforms.display(), e))?;
+        log::info!(
+            "Loaded {} DCS platform locations from {}",
+            count,
+            self.platforms.display()
+        );
+        let mut handler = DcsCoverageHandler::new(output_root, platforms);
+        if let Some(minutes) = self.interval_minutes {
+            handler = handler.with_interval(std::time::Duration::from_secs(minutes * 60));
+        }
+        if let Some(hours) = self.window_hours {
+            handler = handler.with_window(std::time::Duration::from_secs(hours * 3600));
+        }
+        Ok(handler)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuarantineConfig {
+    /// Quarantine compressed text products whose checksums don't match, see [`ChecksumVerifier`]
+    pub checksums: bool,
+}
+
+impl QuarantineConfig {
+    /// Adds the verifiers to a dispatcher, with a quarantine in `output_root` if there are any
+    pub fn apply(&self, dispatcher: Dispatcher, output_root: &str) -> Dispatcher {
+        if !self.checksums {
+            return dispatcher;
+        }
+        dispatcher
+            .with_verifier(ChecksumVerifier)
+            .with_quarantine(Quarantine::new(output_root).with_index(output_root))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestProductsConfig {
+    /// The NWS product categories that are tests (RWT, RMT, and DMO by default)
+    pub categories: Option<Vec<String>>,
+    /// Save test products in a `test` directory, instead of dropping them
+    pub keep: bool,
+}
+
+impl TestProductsConfig {
+    /// The filter, saving test products in `output_root` if they're kept and there is one
+    pub fn filter(&self, output_root: Option<&str>) -> TestProductFilter {
+        let mut filter = TestProductFilter::new();
+        if let Some(categories) = &self.categories {
+            filter = filter.with_categories(categories.clone());
+        }
+        match output_root {
+            Some(root) if self.keep => filter.with_dir(root),
+            _ => filter,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplayConfig {
+    /// Also treat products that were taken longer ago than this as replays
+    pub max_age_minutes: Option<u64>,
+    /// Write replays in a `replay` directory with its own index, instead of with live products
+    pub separate: bool,
+}
+
+impl ReplayConfig {
+    pub fn filter(&self) -> ReplayFilter {
+        let filter = ReplayFilter::new();
+        match self.max_age_minutes {
+            Some(minutes) => filter.with_max_age(std::time::Duration::from_secs(minutes * 60)),
+            None => filter,
+        }
+    }
+
+    /// Where replays are written under `output_root`, if they're kept separate
+    pub fn root(&self, output_root: &str) -> Option<String> {
+        self.separate
+            .then(|| Path::new(output_root).join("replay").to_string_lossy().into_owned())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotasConfig {
+    /// Drop low priority imagery once this much has been written in the last hour
+    pub imagery_mb_per_hour: Option<u64>,
+    /// Drop low priority text products once this many have been written in the last hour
+    pub text_files_per_hour: Option<usize>,
+}
+
+impl QuotasConfig {
+    pub fn quotas(&self) -> Quotas {
+        let mut quotas = Quotas::new();
+        if let Some(mb) = self.imagery_mb_per_hour {
+            quotas = quotas.max_imagery_mb_per_hour(mb);
+        }
+        match self.text_files_per_hour {
+            Some(files) => quotas.max_text_files_per_hour(files),
+            None => quotas,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClockConfig {
+    /// "local" (the default) to always go by the local clock, or "product" to go by the times in
+    /// products when the local clock disagrees with them
+    pub trust: TimeSource,
+    /// How far the local clock can be from product times before it's flagged (30 by default)
+    pub max_skew_minutes: Option<u64>,
+}
+
+impl ClockConfig {
+    pub fn skew(&self) -> ClockSkew {
+        let skew = ClockSkew::new().with_source(self.trust);
+        match self.max_skew_minutes {
+            Some(minutes) => skew.with_max_skew(std::time::Duration::from_secs(minutes * 60)),
+            None => skew,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SurveyConfig {
+    /// How long each report covers (a day by default)
+    pub window_minutes: Option<u64>,
+}
+
+impl SurveyConfig {
+    pub fn handler(&self, output_root: &str) -> SurveyHandler {
+        let handler = SurveyHandler::new(output_root);
+        match self.window_minutes {
+            Some(minutes) => handler.with_window(std::time::Duration::from_secs(minutes * 60)),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// How often the report is written (an hour by default)
+    pub interval_minutes: Option<u64>,
+}
+
+impl TelemetryConfig {
+    pub fn handler(&self, output_root: &str, codes: UnknownCodes) -> UnknownCodeHandler {
+        let handler = UnknownCodeHandler::new(output_root, codes);
+        match self.interval_minutes {
+            Some(minutes) => handler.with_interval(std::time::Duration::from_secs(minutes * 60)),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatesConfig {
+    /// How long each interval that products are counted in is (5 minutes by default)
+    pub interval_minutes: Option<u64>,
+    /// Flag an interval with more than this many times the usual number of products (3 by default)
+    pub spike_factor: Option<f64>,
+}
+
+impl RatesConfig {
+    pub fn handler(&self, events: EventSender) -> RateMonitor {
+        let mut handler = RateMonitor::new().with_events(events);
+        if let Some(minutes) = self.interval_minutes {
+            handler = handler.with_interval(std::time::Duration::from_secs(minutes * 60));
+        }
+        match self.spike_factor {
+            Some(factor) => handler.with_spike_factor(factor),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardConfig {
+    /// Where to write the board; it's Markdown if this ends in `.md`, and HTML otherwise
+    pub path: PathBuf,
+    pub products: Vec<BoardProduct>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoardProduct {
+    pub title: String,
+    /// The short EMWIN product name, with `*` and `?` wildcards
+    pub pattern: String,
+}
+
+impl BoardConfig {
+    pub fn handler(&self) -> BoardHandler {
+        self.products.iter().fold(BoardHandler::new(&self.path), |board, p| {
+            board.product(&p.title, &p.pattern)
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetarConfig {
+    /// "csv" or "json" (the default)
+    pub format: Option<OutputFormat>,
+    /// Only keep reports from these stations (all stations if empty)
+    #[serde(default)]
+    pub stations: Vec<String>,
+    /// Also keep each station's reports in `stations/<station>/`, with `latest-<station>-METAR`
+    /// (and `-TAF`) symlinks
+    #[serde(default)]
+    pub station_files: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoundingConfig {
+    /// "csv" (the default) or "json"
+    pub format: Option<OutputFormat>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+impl From<OutputFormat> for ObservationFormat {
+    fn from(format: OutputFormat) -> Self {
+        match format {
+            OutputFormat::Csv => ObservationFormat::Csv,
+            OutputFormat::Json => ObservationFormat::Json,
+        }
+    }
+}
+
+impl SoundingConfig {
+    pub fn handler(&self, output_root: &str) -> SoundingHandler {
+        let handler = SoundingHandler::new(output_root);
+        match self.format {
+            Some(format) => handler.with_format(format.into()),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpaceWeatherConfig {
+    /// A shell command to run for new messages
+    pub alert_command: Option<String>,
+    /// Only run `alert_command` for messages at one of these levels or above, like "G3" (for all
+    /// messages if empty)
+    #[serde(deserialize_with = "deserialize_scales")]
+    pub alert_scales: Vec<NoaaScale>,
+}
+
+impl SpaceWeatherConfig {
+    pub fn handler(&self, output_root: &str, events: EventSender) -> SpaceWeatherHandler {
+        let handler = SpaceWeatherHandler::new(output_root).with_events(events);
+        match &self.alert_command {
+            Some(command) => handler.with_alert_command(command, self.alert_scales.iter().copied()),
+            None => handler,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShefConfig {
+    /// Also keep values in this SQLite database (needs the `sqlite` feature)
+    pub sqlite: Option<PathBuf>,
+}
+
+impl ShefConfig {
+    pub fn handler(&self, output_root: &str) -> Result<ShefHandler, Box<dyn std::error::Error>> {
+        let handler = ShefHandler::new(output_root);
+        match &self.sqlite {
+            #[cfg(feature = "sqlite")]
+            Some(path) => Ok(handler.with_database(path)?),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => Err("shef.sqlite needs goesbox to be built with the sqlite feature".into()),
+            None => Ok(handler),
+        }
+    }
+}
+
+/// Which LRIT files to archive; a file has to match every list that isn't empty
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawConfig {
+    pub filetypes: Vec<u8>,
+    pub apids: Vec<u16>,
+    pub vcids: Vec<Vcid>,
+    /// What to do when a file has already been archived under the same name, see
+    /// [`DuplicatePolicy`]
+    pub duplicates: DuplicatePolicy,
+}
+
+impl RawConfig {
+    pub fn handler(&self, output_root: &str) -> RawLritHandler {
+        RawLritHandler::new(output_root)
+            .with_filetypes(self.filetypes.iter().copied())
+            .with_apids(self.apids.iter().copied())
+            .with_vcids(self.vcids.iter().copied())
+            .with_duplicate_policy(self.duplicates)
+            .with_index(output_root)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    /// An octal umask, like "022" (files readable by everyone) or "027" (only by the group)
+    pub umask: Option<String>,
+    /// Run as this user, if started as root
+    pub user: Option<String>,
+    /// Run as this group, if started as root (the user's primary group by default)
+    pub group: Option<String>,
+    /// Where products are written, like "s3://bucket/prefix" (the output root by default), see
+    /// [`goeslib::storage`]
+    pub storage: Option<String>,
+}
+
+impl OutputConfig {
+    pub fn permissions(&self) -> Result<OutputPermissions, String> {
+        Ok(OutputPermissions {
+            umask: self.umask.as_deref().map(parse_mode).transpose()?,
+            user: self.user.clone(),
+            group: self.group.clone(),
+        })
+    }
+
+    /// The storage for products under `root`
+    pub fn storage(&self, root: &str) -> SharedStorage {
+        match &self.storage {
+            Some(url) => parse_storage(url, root),
+            None => goeslib::storage::local(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmwinFeedConfig {
+    /// Where to connect for the byte-interleaved EMWIN stream, like "emwin.example.com:2211"
+    pub address: String,
+    /// Every byte of the stream is XORed with 0xFF (like the ByteBlaster protocol)
+    #[serde(default)]
+    pub xor: bool,
+}
+
+impl EmwinFeedConfig {
+    pub fn assembler(&self) -> InterleavedAssembler {
+        InterleavedAssembler::new().with_xor(self.xor)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Where to send products, like "remote.example.com:5010"
+    pub address: String,
+    /// The most to send, in kilobits per second (no limit if not set)
+    pub max_kbps: Option<u64>,
+    /// Drop the oldest imagery once this many megabytes are waiting to be sent
+    pub max_bulk_mb: Option<usize>,
+}
+
+impl RelayConfig {
+    pub fn spawn(&self) -> std::io::Result<Relay> {
+        let mut options = RelayOptions {
+            bytes_per_second: self.max_kbps.map(|kbps| kbps * 1000 / 8),
+            ..Default::default()
+        };
+        if let Some(mb) = self.max_bulk_mb {
+            options.max_bulk_bytes = mb * 1024 * 1024;
+        }
+        Relay::spawn(&self.address, options)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardConfig {
+    /// Names the spool directory, `forward/<name>` in the output root
+    pub name: String,
+    /// Where to send products: an `https://` URL that accepts PUTs, `s3://bucket/prefix`,
+    /// `rsync:host:dir`, `host:dir` (over ssh), or a directory
+    pub target: String,
+    /// Only products whose path in the output root matches one of these (everything, if empty)
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// How long to wait before sending a product (5 if not set)
+    pub delay_seconds: Option<u64>,
+}
+
+impl ForwardConfig {
+    /// Starts forwarding from the spool in `output_root`, returning the spool to add products to
+    pub fn spawn(&self, output_root: &str) -> std::io::Result<ForwardSpool> {
+        let spool = ForwardSpool::new(output_root, &self.name).with_patterns(&self.patterns);
+        let mut forwarder = Forwarder::new(spool.clone(), parse_target(&self.target));
+        if let Some(seconds) = self.delay_seconds {
+            forwarder = forwarder.with_delay(std::time::Duration::from_secs(seconds));
+        }
+        forwarder.spawn()?;
+        Ok(spool)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxConfig {
+    /// An InfluxDB write URL (`http://` or `https://`), or a file to append to
+    pub output: String,
+    /// An InfluxDB API token
+    pub token: Option<String>,
+    /// How often to write a batch of points (10 if not set)
+    pub interval_seconds: Option<u64>,
+    /// Also export the receiver's packet counts, with every batch
+    #[serde(default)]
+    pub stats: bool,
+}
+
+impl InfluxConfig {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds.unwrap_or(10))
+    }
+
+    /// Starts the thread that writes points, which stops once every sender has been dropped
+    pub fn spawn(&self) -> std::io::Result<(InfluxSender, std::thread::JoinHandle<()>)> {
+        InfluxWriter::new(InfluxOutput::parse(&self.output, self.token.clone())).spawn(self.interval())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// The title of the notification, like "Tornado Warning"
+    pub title: String,
+    /// Only these file types (everything, if empty)
+    #[serde(default)]
+    pub filetypes: Vec<u8>,
+    #[serde(default)]
+    pub vcids: Vec<Vcid>,
+    /// Only products whose annotation matches one of these, like "*-TOR*"
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Only text products for one of these counties or zones, like "PAZ070" or "NJZ*"
+    #[serde(default)]
+    pub zones: Vec<String>,
+    /// Only text products for a county or zone that includes this point, as [latitude, longitude]
+    pub location: Option<[f64; 2]>,
+    /// A shell command to run instead of showing a desktop notification
+    pub command: Option<String>,
+}
+
+impl NotifyConfig {
+    /// The handler for all of the rules, if there are any
+    pub fn handler(rules: &[NotifyConfig]) -> Option<NotifyHandler> {
+        if rules.is_empty() {
+            return None;
+        }
+        let rules = rules
+            .iter()
+            .map(|rule| NotifyRule {
+                title: rule.title.clone(),
+                route: Route {
+                    filetypes: rule.filetypes.clone(),
+                    vcids: rule.vcids.clone(),
+                    patterns: rule.patterns.clone(),
+                },
+                zones: rule.zones.clone(),
+                location: rule.location.map(|[lat, lon]| (lat, lon)),
+                command: rule.command.clone(),
+            })
+            .collect();
+        Some(NotifyHandler::new(rules))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZonesConfig {
+    /// NWS county and zone shapefiles (the `.shp` files, with their `.dbf` files next to them)
+    pub shapefiles: Vec<PathBuf>,
+}
+
+impl ZonesConfig {
+    pub fn load(&self) -> Result<ZoneMap, String> {
+        let mut zone_map = ZoneMap::new();
+        for path in &self.shapefiles {
+            let count = zone_map
+                .load_shapefile(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            log::info!("Loaded {} counties or zones from {}", count, path.display());
+        }
+        Ok(zone_map)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// An AWIPS ID (like "AFDPHI") or WMO heading (like "FXUS61 KPHI")
+    pub product: String,
+    /// When it's sent, as a cron expression in UTC, see [`Cadence`]
+    #[serde(deserialize_with = "deserialize_cadence")]
+    pub cron: Cadence,
+    /// How many minutes late it can be before it's overdue (10 if not set)
+    pub grace_minutes: Option<u64>,
+}
+
+impl ScheduleConfig {
+    pub fn expected(&self) -> Expected {
+        Expected {
+            product: self.product.clone(),
+            cadence: self.cron.clone(),
+            grace: std::time::Duration::from_secs(self.grace_minutes.unwrap_or(10) * 60),
+        }
+    }
+}
+
+fn deserialize_cadence<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cadence, D::Error> {
+    Cadence::parse(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_scales<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<NoaaScale>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e))))
+        .collect()
+}
+
+fn deserialize_sources<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<DcsSource>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| s.parse().map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e))))
+        .collect()
+}
+
+impl MetarConfig {
+    pub fn handler(&self, output_root: &str) -> MetarHandler {
+        let mut handler = MetarHandler::new(output_root);
+        if let Some(format) = self.format {
+            handler = handler.with_format(format.into());
+        }
+        if !self.stations.is_empty() {
+            handler = handler.with_stations(&self.stations);
+        }
+        if self.station_files {
+            handler = handler.with_station_files();
+        }
+        handler
+    }
+}
+
+/// Something that can be done from the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    ClearMessages,
+    Help,
+    /// Stop (or start) following new messages
+    Pause,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    /// Start typing a search for messages
+    Search,
+    /// Jump to the next older message that matches the search
+    NextMatch,
+    /// Switch between the virtual channel and APID charts
+    ToggleChart,
+    /// Switch between the APID totals and the hourly product mix
+    ToggleProducts,
+    /// Show (or hide) the big signal quality display, for pointing an antenna
+    TogglePointing,
+    /// Show (or hide) the text of the most recent EMWIN warning
+    ShowWarning,
+}
+
+impl Action {
+    /// All actions, in the order they're listed in the help
+    pub const ALL: [Action; 14] = [
+        Action::Help,
+        Action::Quit,
+        Action::ClearMessages,
+        Action::Pause,
+        Action::ScrollUp,
+        Action::ScrollDown,
+        Action::PageUp,
+        Action::PageDown,
+        Action::Search,
+        Action::NextMatch,
+        Action::ToggleChart,
+        Action::ToggleProducts,
+        Action::TogglePointing,
+        Action::ShowWarning,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Quit => "Quit",
+            Action::ClearMessages => "Clear messages",
+            Action::Help => "Show or hide this help",
+            Action::Pause => "Pause or resume the message log",
+            Action::ScrollUp => "Scroll messages up",
+            Action::ScrollDown => "Scroll messages down",
+            Action::PageUp => "Scroll messages up a page",
+            Action::PageDown => "Scroll messages down a page",
+            Action::Search => "Search messages (Enter to find, Esc to cancel)",
+            Action::NextMatch => "Find the next older match",
+            Action::ToggleChart => "Show VC or APID receive rates",
+            Action::ToggleProducts => "Show APID totals or products by hour",
+            Action::TogglePointing => "Show or hide signal quality for pointing",
+            Action::ShowWarning => "Show or hide the latest warning's text",
+        }
+    }
+}
+
+/// The keys for each action, as names like "q", "Ctrl-c", "Esc", or "PageUp"
+///
+/// Actions that aren't in the config file keep their default keys.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyConfig {
+    pub quit: Vec<String>,
+    pub clear: Vec<String>,
+    pub help: Vec<String>,
+    pub pause: Vec<String>,
+    pub scroll_up: Vec<String>,
+    pub scroll_down: Vec<String>,
+    pub page_up: Vec<String>,
+    pub page_down: Vec<String>,
+    pub search: Vec<String>,
+    pub next_match: Vec<String>,
+    pub toggle_chart: Vec<String>,
+    pub toggle_products: Vec<String>,
+    pub toggle_pointing: Vec<String>,
+    pub show_warning: Vec<String>,
+}
+
+impl Default for KeyConfig {
+    fn default() -> Self {
+        let keys = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        KeyConfig {
+            quit: keys(&["q", "Esc", "Ctrl-c"]),
+            clear: keys(&["c"]),
+            help: keys(&["?"]),
+            pause: keys(&["p"]),
+            scroll_up: keys(&["Up", "k"]),
+            scroll_down: keys(&["Down", "j"]),
+            page_up: keys(&["PageUp"]),
+            page_down: keys(&["PageDown"]),
+            search: keys(&["/"]),
+            next_match: keys(&["n"]),
+            toggle_chart: keys(&["a"]),
+            toggle_products: keys(&["m"]),
+            toggle_pointing: keys(&["P"]),
+            show_warning: keys(&["w"]),
+        }
+    }
+}
+
+/// Maps keys to actions
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    keys: HashMap<Key, Action>,
+}
+
+impl KeyBindings {
+    pub fn new(config: &KeyConfig) -> Result<KeyBindings, String> {
+        let mut keys = HashMap::new();
+        for (names, action) in [
+            (&config.quit, Action::Quit),
+            (&config.clear, Action::ClearMessages),
+            (&config.help, Action::Help),
+            (&config.pause, Action::Pause),
+            (&config.scroll_up, Action::ScrollUp),
+            (&config.scroll_down, Action::ScrollDown),
+            (&config.page_up, Action::PageUp),
+            (&config.page_down, Action::PageDown),
+            (&config.search, Action::Search),
+            (&config.next_match, Action::NextMatch),
+            (&config.toggle_chart, Action::ToggleChart),
+            (&config.toggle_products, Action::ToggleProducts),
+            (&config.toggle_pointing, Action::TogglePointing),
+            (&config.show_warning, Action::ShowWarning),
+        ] {
+            for name in names {
+                let key = parse_key(name).ok_or_else(|| format!("Unknown key name: {:?}", name))?;
+                if let Some(other) = keys.insert(key, action) {
+                    if other != action {
+                        return Err(format!("{:?} is bound to both {:?} and {:?}", name, other, action));
+                    }
+                }
+            }
+        }
+        Ok(KeyBindings { keys })
+    }
+
+    pub fn action(&self, key: Key) -> Option<Action> {
+        self.keys.get(&key).copied()
+    }
+
+    /// The names of the keys bound to an action, for the help
+    pub fn keys_for(&self, action: Action) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .keys
+            .iter()
+            .filter(|(_, a)| **a == action)
+            .map(|(k, _)| key_name(*k))
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+/// Parses a key name, like "q", "Ctrl-c", "Alt-x", "Esc", "PageUp", or "F1"
+fn parse_key(name: &str) -> Option<Key> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(rest) = name.strip_prefix("Ctrl-") {
+        return single(rest).map(Key::Ctrl);
+    }
+    if let Some(rest) = name.strip_prefix("Alt-") {
+        return single(rest).map(Key::Alt);
+    }
+    if let Some(c) = single(name) {
+        return Some(Key::Char(c));
+    }
+    Some(match name {
+        "Esc" => Key::Esc,
+        "Enter" => Key::Char('\n'),
+        "Space" => Key::Char(' '),
+        "Tab" => Key::Char('\t'),
+        "Backspace" => Key::Backspace,
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        _ => Key::F(name.strip_prefix('F')?.parse().ok()?),
+    })
+}
+
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("Ctrl-{}", c),
+        Key::Alt(c) => format!("Alt-{}", c),
+        Key::F(n) => format!("F{}", n),
+        other => format!("{:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use termion::event::Key;
+
+    #[cfg(unix)]
+    use super::utf8_vars;
+    use super::{
+        parse_key, Action, Config, DcsSource, DecodeMode, DuplicatePolicy, HeaderPassthrough, KeyBindings, Language,
+        MetarConfig, OutputFormat, ReplayConfig, Stretch, TimeSource,
+    };
+
+    #[test]
+    fn test_key_bindings() {
+        for (name, key) in [
+            ("q", Key::Char('q')),
+            ("Ctrl-c", Key::Ctrl('c')),
+            ("PageUp", Key::PageUp),
+            ("F10", Key::F(10)),
+            ("Space", Key::Char(' ')),
+        ] {
+            assert_eq!(parse_key(name), Some(key), "{}", name);
+        }
+        assert_eq!(parse_key("Ctrl-"), None);
+        assert_eq!(parse_key("Shift"), None);
+
+        let config: Config = toml::from_str("[keys]\nquit = [\"x\"]\nhelp = [\"F1\", \"h\"]").unwrap();
+        let keys = KeyBindings::new(&config.keys).unwrap();
+        assert_eq!(keys.action(Key::Char('x')), Some(Action::Quit));
+        assert_eq!(keys.action(Key::Char('q')), None);
+        // untouched actions keep their defaults
+        assert_eq!(keys.action(Key::Char('p')), Some(Action::Pause));
+        assert_eq!(keys.action(Key::Char('P')), Some(Action::TogglePointing));
+        assert_eq!(keys.action(Key::Char('w')), Some(Action::ShowWarning));
+        assert_eq!(keys.keys_for(Action::Help), vec!["F1", "h"]);
+
+        let config: Config = toml::from_str("[keys]\nquit = [\"p\"]").unwrap();
+        assert!(KeyBindings::new(&config.keys).is_err());
+    }
+
+    #[test]
+    fn test_board_config() {
+        let config: Config =
+            toml::from_str("[board]\npath = \"board.md\"\nproducts = [{ title = \"AFD\", pattern = \"AFDPHI*\" }]")
+                .unwrap();
+        let board = config.board.unwrap();
+        assert_eq!(board.products[0].pattern, "AFDPHI*");
+        assert!(toml::from_str::<Config>("[board]\npath = \"board.md\"").is_err());
+    }
+
+    #[test]
+    fn test_metar_config() {
+        let config: Config = toml::from_str("[metar]\nformat = \"csv\"").unwrap();
+        assert!(matches!(
+            config.metar,
+            Some(MetarConfig {
+                format: Some(OutputFormat::Csv),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_space_weather_config() {
+        let config: Config = toml::from_str("[space_weather]\nalert_scales = [\"g3\"]").unwrap();
+        assert_eq!(config.space_weather.unwrap().alert_scales[0].to_string(), "G3");
+        assert!(toml::from_str::<Config>("[space_weather]\nalert_scales = [\"K5\"]").is_err());
+    }
+
+    #[test]
+    fn test_schedule_config() {
+        let config: Config = toml::from_str("[[schedule]]\nproduct = \"AFDPHI\"\ncron = \"30 */6 * * *\"").unwrap();
+        assert_eq!(config.schedule[0].expected().grace.as_secs(), 600);
+        assert!(toml::from_str::<Config>("[[schedule]]\nproduct = \"AFDPHI\"\ncron = \"30 */6\"").is_err());
+    }
+
+    #[test]
+    fn test_decoder_config() {
+        let config: Config =
+            toml::from_str("decode = \"tolerant\"\npreamble = 0\nlanguage = \"es\"\n[cache]\nproducts = 5").unwrap();
+        assert_eq!(config.decode, DecodeMode::Tolerant);
+        assert_eq!(config.preamble, Some(0));
+        assert_eq!(config.language, Some(Language::Spanish));
+        assert!(toml::from_str::<Config>("language = \"fr\"").is_err());
+        assert!(!config.cache.unwrap().ram_only);
+    }
+
+    #[test]
+    fn test_dcs_config() {
+        let config: Config = toml::from_str("[dcs]\nsources = [\"up\", \"D1\"]").unwrap();
+        assert_eq!(config.dcs.sources, [DcsSource::UP, DcsSource::D1]);
+        assert!(toml::from_str::<Config>("[dcs]\nsources = [\"UPB\"]").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env = [
+            ("GOESBOX__CACHE__PRODUCTS", "20"),
+            ("GOESBOX__DECODE", "tolerant"),
+            ("GOESBOX__DCS__SOURCES", "[\"NP\"]"),
+            ("GOESBOX_TARGETS", "tcp://localhost:5004"),
+            ("HOME", "/home/goes"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()));
+        let config = Config::parse("[cache]\nproducts = 5\nram_only = true", env).unwrap();
+        let cache = config.cache.unwrap();
+        assert_eq!((cache.products, cache.ram_only), (20, true));
+        assert_eq!(config.decode, DecodeMode::Tolerant);
+        assert_eq!(config.dcs.sources, [DcsSource::NP]);
+        let env = [("GOESBOX__CACHE__BOGUS".to_string(), "1".to_string())];
+        assert!(Config::parse("", env).is_err());
+
+        // variables that aren't UTF-8 are skipped, rather than stopping everything
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+            let env = [
+                (OsString::from("GOESBOX__DECODE"), OsString::from("tolerant")),
+                (OsString::from("GOESBOX__LANGUAGE"), OsString::from_vec(vec![0xff])),
+            ];
+            assert_eq!(
+                utf8_vars(env).collect::<Vec<_>>(),
+                [("GOESBOX__DECODE".to_string(), "tolerant".to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn test_profiles_config() {
+        let config: Config = toml::from_str(
+            "[format]\nheaders = \"sidecar\"\n\
+             [profiles.web]\nroot = \"/var/www\"\npatterns = [\"*CMIPF*\"]\nformat = { pyramid_levels = 1 }",
+        )
+        .unwrap();
+        assert_eq!(config.format.headers, HeaderPassthrough::Sidecar);
+        let web = &config.profiles["web"];
+        assert_eq!(
+            (web.format.pyramid_levels, web.format.headers),
+            (1, HeaderPassthrough::None)
+        );
+        assert_eq!(web.route().patterns, ["*CMIPF*"]);
+        assert!(web.retention().is_none());
+    }
+
+    #[test]
+    fn test_output_config() {
+        let config: Config = toml::from_str("[output]\nstorage = \"s3://goes/station1\"").unwrap();
+        assert_eq!(config.output.unwrap().storage.as_deref(), Some("s3://goes/station1"));
+    }
+
+    #[test]
+    fn test_telemetry_config() {
+        let config: Config = toml::from_str("[telemetry]").unwrap();
+        assert_eq!(config.telemetry.unwrap().interval_minutes, None);
+    }
+
+    #[test]
+    fn test_rates_config() {
+        let config: Config = toml::from_str("[rates]\nspike_factor = 5.0").unwrap();
+        assert_eq!(config.rates.unwrap().spike_factor, Some(5.0));
+    }
+
+    #[test]
+    fn test_storm_watch_config() {
+        let storm = "[[storm_watch]]\nname = \"OK\"\nsouth = 33.0\nnorth = 37.0\nwest = -100.0\neast = -94.0";
+        let config: Config = toml::from_str(storm).unwrap();
+        assert_eq!(
+            (config.storm_watch[0].name.as_str(), config.storm_watch[0].threshold),
+            ("OK", None)
+        );
+        assert!(toml::from_str::<Config>("[[storm_watch]]\nname = \"OK\"\nsouth = 33.0").is_err());
+    }
+
+    #[test]
+    fn test_notify_config() {
+        let notify = "[[notify]]\ntitle = \"Home\"\nlocation = [40.3, -75.1]";
+        assert!(Config::parse(notify, []).is_err());
+        let config = Config::parse(&format!("{}\n[zones]\nshapefiles = [\"z.shp\"]", notify), []).unwrap();
+        assert_eq!(config.notify[0].location, Some([40.3, -75.1]));
+    }
+
+    #[test]
+    fn test_emwin_feed_config() {
+        let config: Config = toml::from_str("[emwin_feed]\naddress = \"localhost:2211\"").unwrap();
+        assert!(!config.emwin_feed.unwrap().xor);
+    }
+
+    #[test]
+    fn test_test_products_config() {
+        let config: Config = toml::from_str("[test_products]\nkeep = true").unwrap();
+        let test_products = config.test_products.unwrap();
+        assert!(test_products.filter(Some("/goes")).dir().is_some());
+        assert!(test_products.filter(None).dir().is_none());
+    }
+
+    #[test]
+    fn test_replay_config() {
+        let config: Config = toml::from_str("[replay]\nmax_age_minutes = 180\nseparate = true").unwrap();
+        let replay = config.replay.unwrap();
+        assert_eq!(replay.root("/goes").as_deref(), Some("/goes/replay"));
+        assert!(ReplayConfig::default().root("/goes").is_none());
+    }
+
+    #[test]
+    fn test_quotas_config() {
+        let config = Config::parse(
+            "[quotas]
+imagery_mb_per_hour = 500
+text_files_per_hour = 2000",
+            [],
+        )
+        .unwrap();
+        let quotas = config.quotas.unwrap().quotas();
+        assert_eq!(quotas.max_imagery_bytes(), Some(500 * 1024 * 1024));
+        assert_eq!(quotas.max_text_files(), Some(2000));
+        let config = Config::parse(
+            "[quotas]
+text_files_per_hour = 10",
+            [],
+        )
+        .unwrap();
+        assert_eq!(config.quotas.unwrap().quotas().max_imagery_bytes(), None);
+        assert!(Config::parse(
+            "[quotas]
+imagery_mb = 500",
+            []
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_text_config() {
+        let config: Config = toml::from_str("[text]\nduplicates = \"version\"").unwrap();
+        assert_eq!(config.text.duplicates, DuplicatePolicy::Version);
+        assert!(config.text.collision_detector().is_none());
+    }
+
+    #[test]
+    fn test_dcs_drift_config() {
+        let config: Config = toml::from_str("[dcs_drift]\ninterval_minutes = 30").unwrap();
+        assert_eq!(config.dcs_drift.unwrap().interval_minutes, Some(30));
+    }
+
+    #[test]
+    fn test_dcs_coverage_config() {
+        let config: Config = toml::from_str("[dcs_coverage]\nplatforms = \"platforms.csv\"\nwindow_hours = 6").unwrap();
+        assert_eq!(config.dcs_coverage.unwrap().window_hours, Some(6));
+        assert!(toml::from_str::<Config>("[dcs_coverage]\nwindow_hours = 6").is_err());
+    }
+
+    #[test]
+    fn test_influx_config() {
+        let config: Config = toml::from_str("[influx]\noutput = \"points.txt\"\nstats = true").unwrap();
+        let influx = config.influx.unwrap();
+        assert_eq!((influx.interval().as_secs(), influx.stats), (10, true));
+        assert!(toml::from_str::<Config>("[influx]\ntoken = \"t\"").is_err());
+    }
+
+    #[test]
+    fn test_clock_config() {
+        let config: Config = toml::from_str("[clock]\ntrust = \"product\"\nmax_skew_minutes = 20").unwrap();
+        assert_eq!(config.clock.trust, TimeSource::Product);
+        assert_eq!(config.clock.skew().report().source, TimeSource::Product);
+        assert!(toml::from_str::<Config>("[clock]\ntrust = \"gps\"").is_err());
+    }
+
+    #[test]
+    fn test_change_config() {
+        let config: Config = toml::from_str("[change]\ndifference_images = true").unwrap();
+        let change = config.change.unwrap();
+        assert_eq!((change.threshold_percent, change.difference_images), (None, true));
+    }
+
+    #[test]
+    fn test_levels_config() {
+        let config: Config = toml::from_str(
+            "[levels]\npercentiles = false\nbands = { C02 = true, C13 = [1.0, 99.0] }\nregions = { \"Full Disk\" = false }",
+        )
+        .unwrap();
+        let levels = config.levels.unwrap().auto_levels();
+        let meta = |name| goeslib::xmp::ImageMetadata::from_product_name(name);
+        let c02 = meta("OR_ABI-L2-CMIPF-M6C02_G16_s20221241800205.lrit");
+        assert_eq!(levels.stretch(&c02), Some(Stretch::new(2.0, 98.0)));
+        let c13 = meta("OR_ABI-L2-CMIPM1-M6C13_G16_s20221241800205.lrit");
+        assert_eq!(levels.stretch(&c13), Some(Stretch::new(1.0, 99.0)));
+        assert_eq!(
+            levels.stretch(&meta("OR_ABI-L2-CMIPF-M6C07_G16_s20221241800205.lrit")),
+            None
+        );
+    }
+}
@@ -0,0 +1,354 @@
+//! The gRPC service (with the `grpc` feature), see `proto/goesbox.proto`
+//!
+//! The server runs on its own thread, with its own tokio runtime.  Events are handed to it with
+//! [`GrpcServer::publish`], and fanned out to every subscribed client; a client that falls too far
+//! behind misses events rather than holding up the decoder.
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use chrono::Utc;
+use goeslib::cache::{CachedProduct, ProductCache};
+use goeslib::events::Event;
+use goeslib::index::{IndexQuery, ProductIndex};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+mod service {
+    include!(concat!(env!("OUT_DIR"), "/goesbox.Goesbox.rs"));
+}
+
+use service::goesbox_server::{Goesbox, GoesboxServer};
+
+/// How many events are buffered for each client
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamLritsRequest {
+    #[prost(uint32, repeated, tag = "1")]
+    pub filetype_codes: Vec<u32>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LritInfo {
+    #[prost(uint32, tag = "1")]
+    pub vcid: u32,
+    #[prost(uint32, tag = "2")]
+    pub filetype_code: u32,
+    #[prost(string, tag = "3")]
+    pub product: String,
+    #[prost(uint64, tag = "4")]
+    pub bytes: u64,
+    #[prost(int64, tag = "5")]
+    pub time_unix_ms: i64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetProductRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Product {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub class: String,
+    #[prost(uint32, tag = "3")]
+    pub vcid: u32,
+    #[prost(uint32, tag = "4")]
+    pub filetype_code: u32,
+    #[prost(uint32, optional, tag = "5")]
+    pub product_id: Option<u32>,
+    #[prost(int64, tag = "6")]
+    pub received_unix_ms: i64,
+    #[prost(bytes = "vec", tag = "7")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct SubscribeEventsRequest {
+    #[prost(string, repeated, tag = "1")]
+    pub kinds: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EventMessage {
+    #[prost(string, tag = "1")]
+    pub kind: String,
+    #[prost(string, tag = "2")]
+    pub json: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryIndexRequest {
+    #[prost(string, tag = "1")]
+    pub query: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct IndexEntry {
+    #[prost(int64, tag = "1")]
+    pub received_unix_ms: i64,
+    #[prost(uint32, tag = "2")]
+    pub vcid: u32,
+    #[prost(uint32, tag = "3")]
+    pub filetype_code: u32,
+    #[prost(uint32, optional, tag = "4")]
+    pub product_id: Option<u32>,
+    #[prost(string, tag = "5")]
+    pub annotation: String,
+    #[prost(string, repeated, tag = "6")]
+    pub files: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct QueryIndexResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub records: Vec<IndexEntry>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GetCapabilitiesRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Capabilities {
+    #[prost(string, tag = "1")]
+    pub version: String,
+    #[prost(uint32, tag = "2")]
+    pub decoder_version: u32,
+    #[prost(string, repeated, tag = "3")]
+    pub features: Vec<String>,
+    #[prost(uint32, repeated, tag = "4")]
+    pub filetypes: Vec<u32>,
+    #[prost(string, repeated, tag = "5")]
+    pub handlers: Vec<String>,
+}
+
+impl From<goeslib::Capabilities> for Capabilities {
+    fn from(caps: goeslib::Capabilities) -> Capabilities {
+        Capabilities {
+            version: caps.version,
+            decoder_version: caps.decoder_version,
+            features: caps.features,
+            filetypes: caps.filetypes.into_iter().map(u32::from).collect(),
+            handlers: caps.handlers,
+        }
+    }
+}
+
+impl From<CachedProduct> for Product {
+    fn from(product: CachedProduct) -> Product {
+        let class = serde_json::to_value(product.class)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default();
+        Product {
+            name: product.name,
+            class,
+            vcid: product.vcid as u32,
+            filetype_code: product.filetype_code as u32,
+            product_id: product.product_id.map(u32::from),
+            received_unix_ms: product.received.timestamp_millis(),
+            data: product.data.to_vec(),
+        }
+    }
+}
+
+/// Runs a query against the index in `root`
+fn query_index(root: &Path, text: &str) -> Result<QueryIndexResponse, Status> {
+    let now = Utc::now();
+    let query = IndexQuery::parse(text, now).map_err(Status::invalid_argument)?;
+    let records = ProductIndex::new(root)
+        .search(&query, now)
+        .map_err(|e| Status::internal(e.to_string()))?;
+    let records = records
+        .into_iter()
+        .map(|record| IndexEntry {
+            received_unix_ms: record.received.timestamp_millis(),
+            vcid: record.vcid as u32,
+            filetype_code: record.filetype_code as u32,
+            product_id: record.product_id.map(u32::from),
+            files: record
+                .files(root)
+                .iter()
+                .filter_map(|f| Some(f.strip_prefix(root).ok()?.to_string_lossy().into_owned()))
+                .collect(),
+            annotation: record.annotation,
+        })
+        .collect();
+    Ok(QueryIndexResponse { records })
+}
+
+/// The kind of an event (like `ImageCompleted`) and the event as JSON
+fn event_message(event: &Event) -> Option<EventMessage> {
+    let value = serde_json::to_value(event).ok()?;
+    Some(EventMessage {
+        kind: value.get("event")?.as_str()?.to_string(),
+        json: value.to_string(),
+    })
+}
+
+type EventStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+struct Service {
+    events: broadcast::Sender<Event>,
+    cache: ProductCache,
+    output_root: PathBuf,
+}
+
+impl Service {
+    /// Every event from now on, skipping any that a slow client missed
+    fn subscribe(&self) -> impl Stream<Item = Event> {
+        BroadcastStream::new(self.events.subscribe()).filter_map(Result::ok)
+    }
+}
+
+#[tonic::async_trait]
+impl Goesbox for Service {
+    type StreamLritsStream = EventStream<LritInfo>;
+    type SubscribeEventsStream = EventStream<EventMessage>;
+
+    async fn stream_lrits(
+        &self,
+        request: Request<StreamLritsRequest>,
+    ) -> Result<Response<Self::StreamLritsStream>, Status> {
+        let filetypes = request.into_inner().filetype_codes;
+        let stream = self.subscribe().filter_map(move |event| match event {
+            Event::LritCompleted(lrit) if filetypes.is_empty() || filetypes.contains(&(lrit.filetype_code as u32)) => {
+                Some(Ok(LritInfo {
+                    vcid: lrit.vcid as u32,
+                    filetype_code: lrit.filetype_code as u32,
+                    product: lrit.product.unwrap_or_default(),
+                    bytes: lrit.bytes as u64,
+                    time_unix_ms: lrit.time.timestamp_millis(),
+                }))
+            }
+            _ => None,
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_product(&self, request: Request<GetProductRequest>) -> Result<Response<Product>, Status> {
+        let name = request.into_inner().name;
+        match self.cache.get(&name) {
+            Some(product) => Ok(Response::new(product.into())),
+            None => Err(Status::not_found(format!("{} is not in the product cache", name))),
+        }
+    }
+
+    async fn query_index(&self, request: Request<QueryIndexRequest>) -> Result<Response<QueryIndexResponse>, Status> {
+        let text = request.into_inner().query;
+        let root = self.output_root.clone();
+        // reading the index is blocking file IO
+        let response = tokio::task::spawn_blocking(move || query_index(&root, &text))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??;
+        Ok(Response::new(response))
+    }
+
+    async fn get_capabilities(
+        &self,
+        _request: Request<GetCapabilitiesRequest>,
+    ) -> Result<Response<Capabilities>, Status> {
+        Ok(Response::new(goeslib::capabilities().into()))
+    }
+
+    async fn subscribe_events(
+        &self,
+        request: Request<SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let kinds = request.into_inner().kinds;
+        let stream = self
+            .subscribe()
+            .filter_map(|event| event_message(&event))
+            .filter(move |message| kinds.is_empty() || kinds.contains(&message.kind))
+            .map(Ok);
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// The gRPC server, which runs until the program exits
+pub struct GrpcServer {
+    events: broadcast::Sender<Event>,
+}
+
+impl GrpcServer {
+    /// Starts serving on `address`, with products from `cache`, and the index in `output_root`
+    pub fn spawn(
+        address: SocketAddr,
+        cache: ProductCache,
+        output_root: impl Into<PathBuf>,
+    ) -> Result<GrpcServer, Box<dyn std::error::Error>> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()?;
+        // bind here, so that an address in use is reported right away
+        let listener = runtime.block_on(tokio::net::TcpListener::bind(address))?;
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let service = Service {
+            events: events.clone(),
+            cache,
+            output_root: output_root.into(),
+        };
+        std::thread::spawn(move || {
+            let server = tonic::transport::Server::builder()
+                .add_service(GoesboxServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener));
+            if let Err(e) = runtime.block_on(server) {
+                log::error!("gRPC server stopped: {}", e);
+            }
+        });
+        Ok(GrpcServer { events })
+    }
+
+    /// Sends an event to every subscribed client
+    pub fn publish(&self, event: &Event) {
+        // this only fails if there are no clients
+        let _ = self.events.send(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use goeslib::cache::CachedProduct;
+    use goeslib::events::{Event, SourceDisconnectedEvent};
+    use goeslib::quota::ProductClass;
+
+    use super::{event_message, Capabilities, Product};
+
+    #[test]
+    fn test_messages() {
+        let message = event_message(&Event::SourceDisconnected(SourceDisconnectedEvent {
+            address: "tcp://localhost:5004".to_string(),
+            error: "closed".to_string(),
+            time: Utc::now(),
+        }))
+        .unwrap();
+        assert_eq!(message.kind, "SourceDisconnected");
+        assert!(message.json.contains("\"error\":\"closed\""));
+
+        let product: Product = CachedProduct {
+            name: "text1".to_string(),
+            class: ProductClass::Text,
+            received: Utc::now(),
+            vcid: 1,
+            filetype_code: 2,
+            product_id: None,
+            data: b"hello"[..].into(),
+        }
+        .into();
+        assert_eq!(
+            (product.class.as_str(), product.data.as_slice()),
+            ("text", &b"hello"[..])
+        );
+
+        let caps: Capabilities = goeslib::capabilities().into();
+        assert!(caps.filetypes.contains(&2));
+    }
+}
@@ -1,10 +1,46 @@
 //! A text-based user interface for the goesbox.
 
-use goeslib::lrit::{VirtualChannel, VCDU};
-use goeslib::stats::{Stat, Stats};
-use goeslib::{handlers, lrit};
-use log::warn;
+mod cli;
+mod config;
+#[cfg(feature = "grpc")]
+mod grpc;
+
+use cli::{
+    Cli, Command, CompareArgs, DumpArgs, MergeArgs, QueryArgs, RegenArgs, ReparseArgs, ReportArgs, ReprocessArgs,
+    RunArgs, SelftestArgs, SimulateArgs, StreamArgs, SyncArgs, TimelapseArgs, VerifyArgs,
+};
+use config::{Action, Config, EmwinFeedConfig, KeyBindings, NotifyConfig, ProductFormat};
+use goeslib::annotation::LritFilename;
+use goeslib::archive::DailyArchiver;
+use goeslib::cache::{CacheHandler, ProductCache};
+use goeslib::capture::{merge_captures, CaptureReader};
+use goeslib::clock::TimeSource;
+use goeslib::deadletter::DeadLetter;
+use goeslib::emwin::Priority;
+use goeslib::events::{
+    Event, EventBus, EventSender, LritCompletedEvent, ProductTruncatedEvent, ShutdownEvent, SourceDisconnectedEvent,
+    TextWrittenEvent,
+};
+use goeslib::handlers::{AdminHandler, Handler, InfluxHandler};
+use goeslib::i18n::Language;
+use goeslib::index::{IndexHandler, IndexQuery, MemoryIndex, ProductIndex};
+use goeslib::influx::Point;
+use goeslib::logging::{LogLine, LoggerBuilder};
+use goeslib::lrit::{VcduDedup, VirtualChannel, VCDU};
+use goeslib::profile::ProfileHandler;
+use goeslib::regen::Regenerator;
+use goeslib::relay::RelayHandler;
+use goeslib::reparse::reparse;
+use goeslib::sim::{LossInjector, Simulator};
+use goeslib::stats::{DcsStats, Stat, Stats, StatsSink, RATE_WINDOW};
+use goeslib::storage::SharedStorage;
+use goeslib::stream::StreamHandler;
+use goeslib::timelapse::Timelapse;
+use goeslib::writer::{BatchOptions, BatchWriter, WriterHealth};
+use goeslib::{handlers, lrit, report};
 use nanomsg::{Protocol, Socket};
+use signal_hook::consts::{SIGHUP, SIGTERM};
+use signal_hook::iterator::Signals;
 use tui::text::{Span, Spans};
 
 use std::io;
@@ -12,113 +48,441 @@ use termion::event::Key;
 use termion::raw::IntoRawMode;
 use tui::backend::{Backend, TermionBackend};
 use tui::layout::{Constraint, Direction, Layout, Rect};
-use tui::widgets::{BarChart, Block, Borders, Paragraph, Wrap};
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{BarChart, Block, Borders, Clear, Paragraph, Wrap};
 use tui::{Frame, Terminal};
 
+use crossbeam_channel::select;
 use crossbeam_channel::unbounded;
-use crossbeam_channel::{select, Sender};
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
 use std::time::{Duration, Instant};
 
-const MIN_DRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the screen is redrawn, if anything on it has changed
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the screen is redrawn even if nothing has changed, so the uptime and rates keep moving
+const IDLE_DRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the signal quality in pointing mode is averaged over
+///
+/// This is short, so the display follows the antenna as it's moved.
+const POINTING_WINDOW: Duration = Duration::from_secs(2);
+
+/// Rows of the characters that [`big_text`] can draw, 3 columns wide and 5 rows tall
+const BIG_FONT: [(char, [&str; 5]); 13] = [
+    ('0', ["███", "█ █", "█ █", "█ █", "███"]),
+    ('1', [" █ ", "██ ", " █ ", " █ ", "███"]),
+    ('2', ["███", "  █", "███", "█  ", "███"]),
+    ('3', ["███", "  █", "███", "  █", "███"]),
+    ('4', ["█ █", "█ █", "███", "  █", "  █"]),
+    ('5', ["███", "█  ", "███", "  █", "███"]),
+    ('6', ["███", "█  ", "███", "█ █", "███"]),
+    ('7', ["███", "  █", "  █", "  █", "  █"]),
+    ('8', ["███", "█ █", "███", "█ █", "███"]),
+    ('9', ["███", "█ █", "███", "  █", "███"]),
+    ('.', ["   ", "   ", "   ", "   ", " █ "]),
+    ('%', ["█ █", "  █", " █ ", "█  ", "█ █"]),
+    (' ', ["   ", "   ", "   ", "   ", "   "]),
+];
+
+/// Draws text in big characters, as 5 lines
+///
+/// Only digits, `.`, `%`, and spaces can be drawn; anything else is left blank.
+fn big_text(text: &str) -> Vec<String> {
+    (0..5)
+        .map(|row| {
+            text.chars()
+                .map(|c| {
+                    BIG_FONT
+                        .iter()
+                        .find(|(ch, _)| *ch == c)
+                        .map_or("   ", |(_, rows)| rows[row])
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+/// How many messages to keep for scrollback
+const MAX_MESSAGES: usize = 5000;
+
+/// How many log messages a second each module can show, so one can't flood the message pane
+const LOG_LINES_PER_SECOND: usize = 20;
+
+/// How many of the most recent LRIT annotations to keep, for crash dumps
+const RECENT_ANNOTATIONS: usize = 10;
 
 pub struct App {
     pub stats: Stats,
-    messages: Vec<String>,
+    messages: VecDeque<LogLine>,
     last_draw: Instant,
+    /// Whether anything has changed since the last draw
+    dirty: bool,
     vcs: HashMap<u8, VirtualChannel>,
+    /// What the virtual channels do when the decoder's checks fail
+    decode_mode: lrit::DecodeMode,
+    /// How many bytes the virtual channels skip before each primary header, if they don't look
+    preamble: Option<usize>,
+    /// Where the virtual channels save LRIT files whose headers can't be parsed, if anywhere
+    dead_letter: Option<std::sync::Arc<DeadLetter>>,
+    /// The language labels (and warnings' product descriptions) are shown in
+    language: Language,
+    /// Recent products kept in memory, if there's a cache
+    cache: Option<ProductCache>,
+    recent_annotations: VecDeque<String>,
+    keys: KeyBindings,
+    show_help: bool,
+    /// Don't follow new messages
+    paused: bool,
+    /// How many messages back from the newest the message pane is scrolled
+    scroll: usize,
+    /// How many messages fit in the message pane (as of the last draw)
+    page: usize,
+    /// The search being typed, if any
+    search_input: Option<String>,
+    /// The last search, which matching messages are highlighted with
+    search: Option<String>,
+    /// Show per-APID rates instead of per-VC rates
+    apid_chart: bool,
+    /// Show products by hour instead of APID totals
+    product_mix: bool,
+    /// Show only the big signal quality display
+    pointing: bool,
+    /// The most recent high priority EMWIN product (a warning), if one has been written
+    latest_warning: Option<TextWrittenEvent>,
+    /// The warning being shown, if any
+    warning: Option<WarningPopup>,
+    /// Whether products are making it to disk
+    writer_health: WriterHealth,
 }
 
-pub struct AppLogger {
-    app_channel: Sender<String>,
+/// The text of a warning, shown in a box over the rest of the screen
+struct WarningPopup {
+    product: String,
+    text: String,
+    /// How many lines down the text is scrolled
+    scroll: u16,
+    /// How many lines fit in the box (as of the last draw)
+    page: u16,
 }
 
-impl AppLogger {
-    pub fn new(chan: Sender<String>) -> AppLogger {
-        AppLogger { app_channel: chan }
+impl App {
+    pub fn new() -> App {
+        App {
+            stats: Stats::new(),
+            messages: VecDeque::new(),
+            last_draw: Instant::now(),
+            dirty: true,
+            vcs: HashMap::new(),
+            decode_mode: lrit::DecodeMode::default(),
+            preamble: None,
+            dead_letter: None,
+            language: Language::English,
+            cache: None,
+            recent_annotations: VecDeque::new(),
+            keys: KeyBindings::new(&Default::default()).expect("default key bindings"),
+            show_help: false,
+            paused: false,
+            scroll: 0,
+            page: 10,
+            search_input: None,
+            search: None,
+            apid_chart: false,
+            product_mix: false,
+            pointing: false,
+            latest_warning: None,
+            warning: None,
+            writer_health: WriterHealth::default(),
+        }
+    }
+
+    pub fn with_keys(mut self, keys: KeyBindings) -> Self {
+        self.keys = keys;
+        self
     }
-}
 
-impl log::Log for AppLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+    pub fn with_decode_mode(mut self, mode: lrit::DecodeMode) -> Self {
+        self.decode_mode = mode;
+        self
+    }
+
+    pub fn with_preamble(mut self, preamble: Option<usize>) -> Self {
+        self.preamble = preamble;
+        self
+    }
+
+    pub fn with_dead_letter(mut self, dead_letter: DeadLetter) -> Self {
+        self.dead_letter = Some(std::sync::Arc::new(dead_letter));
+        self
+    }
+
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: ProductCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Handles a key press
+    ///
+    /// Returns false if the app should quit.
+    pub fn key(&mut self, key: Key) -> bool {
+        self.dirty = true;
+        if let Some(input) = &mut self.search_input {
+            match key {
+                Key::Char('\n') => {
+                    self.search = self.search_input.take().filter(|s| !s.is_empty());
+                    self.find_match(self.scroll);
+                }
+                Key::Esc => self.search_input = None,
+                Key::Backspace => {
+                    input.pop();
+                }
+                Key::Char(c) => input.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        // while a warning is shown, the scroll keys scroll it instead of the messages
+        if let Some(popup) = &mut self.warning {
+            let scroll = match self.keys.action(key) {
+                Some(Action::ScrollUp) => Some(popup.scroll.saturating_sub(1)),
+                Some(Action::ScrollDown) => Some(popup.scroll.saturating_add(1)),
+                Some(Action::PageUp) => Some(popup.scroll.saturating_sub(popup.page)),
+                Some(Action::PageDown) => Some(popup.scroll.saturating_add(popup.page)),
+                _ => None,
+            };
+            if let Some(scroll) = scroll {
+                let last = popup.text.lines().count().saturating_sub(1);
+                popup.scroll = scroll.min(last.min(u16::MAX as usize) as u16);
+                return true;
+            }
+        }
+
+        let last = self.messages.len().saturating_sub(1);
+        match self.keys.action(key) {
+            Some(Action::Quit) => return false,
+            Some(Action::ClearMessages) => {
+                self.clear_msg();
+            }
+            Some(Action::Help) => self.show_help = !self.show_help,
+            Some(Action::Pause) => self.paused = !self.paused,
+            Some(Action::ScrollUp) => self.scroll = (self.scroll + 1).min(last),
+            Some(Action::ScrollDown) => self.scroll = self.scroll.saturating_sub(1),
+            Some(Action::PageUp) => self.scroll = (self.scroll + self.page).min(last),
+            Some(Action::PageDown) => self.scroll = self.scroll.saturating_sub(self.page),
+            Some(Action::Search) => self.search_input = Some(String::new()),
+            Some(Action::NextMatch) => self.find_match(self.scroll + 1),
+            Some(Action::ToggleChart) => self.apid_chart = !self.apid_chart,
+            Some(Action::ToggleProducts) => self.product_mix = !self.product_mix,
+            Some(Action::TogglePointing) => self.pointing = !self.pointing,
+            Some(Action::ShowWarning) => self.toggle_warning(),
+            None => log::info!("Unbound key {:?} (see the help for key bindings)", key),
+        }
         true
     }
 
-    fn log(&self, record: &log::Record) {
-        if !record.target().starts_with("goes_dht") && record.level() >= log::Level::Debug {
+    /// Remembers a text product that was written, if it's a high priority EMWIN product
+    pub fn text_written(&mut self, event: TextWrittenEvent) {
+        if let LritFilename::Emwin(emwin) = LritFilename::parse(&event.product) {
+            if matches!(emwin.priority, Priority::Highest | Priority::High) {
+                self.latest_warning = Some(event);
+            }
+        }
+    }
+
+    /// Shows the text of the latest warning, or hides it if it's already shown
+    fn toggle_warning(&mut self) {
+        if self.warning.take().is_some() {
             return;
         }
-        let _ = self
-            .app_channel
-            .send(format!("{} {} {}", record.target(), record.level(), record.args()));
+        let latest = match &self.latest_warning {
+            Some(latest) => latest,
+            None => {
+                log::info!("No warnings have been received yet");
+                return;
+            }
+        };
+        match std::fs::read(&latest.path) {
+            Ok(data) => {
+                let product = match LritFilename::parse(&latest.product) {
+                    LritFilename::Emwin(emwin) => format!("{}: {}", emwin.description(self.language), latest.product),
+                    _ => latest.product.clone(),
+                };
+                self.warning = Some(WarningPopup {
+                    product,
+                    // EMWIN text has \r\r\n line endings
+                    text: String::from_utf8_lossy(&data).replace('\r', ""),
+                    scroll: 0,
+                    page: 10,
+                })
+            }
+            Err(e) => log::warn!("Failed to read {}: {}", latest.path.display(), e),
+        }
     }
 
-    fn flush(&self) {}
-}
+    /// Scrolls to the newest message that matches the search, starting `scroll` messages back
+    ///
+    /// Wraps around to the newest message if nothing older matches.
+    fn find_match(&mut self, scroll: usize) {
+        let search = match &self.search {
+            Some(search) => search.to_lowercase(),
+            None => return,
+        };
+        let len = self.messages.len();
+        let matches = |scroll: &usize| self.messages[len - 1 - scroll].matches(&search);
+        let found = (scroll.min(len)..len).find(matches).or_else(|| (0..len).find(matches));
+        match found {
+            Some(found) => self.scroll = found,
+            None => log::info!("No messages match {:?}", search),
+        }
+    }
 
-impl App {
-    pub fn new() -> App {
-        App {
-            stats: Stats::new(),
-            messages: Vec::new(),
-            last_draw: Instant::now(),
-            vcs: HashMap::new(),
+    /// Remembers the annotation of a completed LRIT file
+    pub fn remember(&mut self, lrit: &lrit::LRIT) {
+        if let Some(ann) = &lrit.headers.annotation {
+            if self.recent_annotations.len() == RECENT_ANNOTATIONS {
+                self.recent_annotations.pop_front();
+            }
+            self.recent_annotations.push_back(ann.text.clone());
         }
     }
 
+    /// The state of the decoding pipeline, for crash dumps
+    ///
+    /// `current` is the LRIT file that was being handled, if any.
+    pub fn crash_state(&self, current: Option<&lrit::LRIT>) -> serde_json::Value {
+        let mut vcs: Vec<_> = self.vcs.values().map(|vc| vc.state()).collect();
+        vcs.sort_by_key(|vc| vc.vcid);
+        serde_json::json!({
+            "virtual_channels": vcs,
+            "recent_annotations": self.recent_annotations,
+            "current_lrit": current.map(|l| serde_json::json!({ "vcid": l.vcid, "headers": l.headers })),
+            "stats": self.stats.snapshot(),
+        })
+    }
+
     /// Process an incoming VCDU packet, and return any completed LRIT files (if any)
     pub fn process(&mut self, vcdu: lrit::VCDU) -> Vec<lrit::LRIT> {
         let id = vcdu.vcid();
         self.record(Stat::Packet);
         self.record(Stat::VCDUPacket(id));
         if vcdu.is_fill() {
+            self.record(Stat::FillPacket);
             return Vec::new();
         }
         // Each VCDU needs to be processed by the corresponding VirtualChannel
-        let vc = self
-            .vcs
-            .entry(id)
-            .or_insert_with(|| VirtualChannel::new(id, vcdu.counter()));
+        let (mode, preamble, dead_letter) = (self.decode_mode, self.preamble, &self.dead_letter);
+        let vc = self.vcs.entry(id).or_insert_with(|| {
+            let mut vc = VirtualChannel::new(id, vcdu.counter()).with_mode(mode);
+            if let Some(dead_letter) = dead_letter {
+                vc = vc.with_dead_letter(dead_letter.clone());
+            }
+            match preamble {
+                Some(len) => vc.with_preamble(len),
+                None => vc,
+            }
+        });
         vc.process_vcdu(vcdu, &mut self.stats)
     }
 
     pub fn record(&mut self, stat: Stat) {
+        self.dirty = true;
         self.stats.record(stat);
     }
 
     pub fn info(&mut self, msg: impl ToString) {
-        self.messages.push(msg.to_string());
+        self.message(LogLine {
+            level: log::Level::Info,
+            target: "goesbox".to_string(),
+            message: msg.to_string(),
+        });
+    }
+
+    pub fn message(&mut self, line: LogLine) {
+        self.dirty = true;
+        self.messages.push_back(line);
+        // keep the same messages in view when paused or scrolled back
+        if self.paused || self.scroll > 0 {
+            self.scroll += 1;
+        }
 
         self.trim_messages();
     }
 
     pub fn clear_msg(&mut self) {
+        self.dirty = true;
         self.messages.clear();
+        self.scroll = 0;
     }
 
     fn trim_messages(&mut self) {
         // keep only the most recent messages
-        let len = self.messages.len();
-        if len > 200 {
-            self.messages = self.messages.split_off(len - 200);
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
         }
+        self.scroll = self.scroll.min(self.messages.len().saturating_sub(1));
+    }
+
+    /// Returns true if the screen should be redrawn: something on it has changed, or it hasn't
+    /// been drawn for a while
+    pub fn needs_draw(&self) -> bool {
+        self.dirty || self.last_draw.elapsed() >= IDLE_DRAW_INTERVAL
     }
 
     pub fn draw<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> std::io::Result<()> {
-        if self.last_draw.elapsed() <= MIN_DRAW_INTERVAL {
-            return Ok(());
-        }
-        terminal.draw(|mut f| {
+        terminal.draw(|f| {
+            if self.pointing {
+                self.draw_pointing(f, f.size());
+                self.draw_warning(f);
+                if self.show_help {
+                    self.draw_help(f);
+                }
+                return;
+            }
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(10), Constraint::Length(10), Constraint::Min(20)].as_ref())
+                .constraints(
+                    [
+                        Constraint::Length(3),
+                        Constraint::Percentage(10),
+                        Constraint::Length(10),
+                        Constraint::Length(3),
+                        Constraint::Min(20),
+                    ]
+                    .as_ref(),
+                )
                 .split(f.size());
 
-            self.draw_stats(&mut f, chunks[1]);
-            self.draw_messages(&mut f, chunks[2]);
+            self.draw_summary(f, chunks[0]);
+            if self.product_mix {
+                self.draw_product_mix(f, chunks[1]);
+            } else {
+                self.draw_apids(f, chunks[1]);
+            }
+            if self.apid_chart {
+                self.draw_apid_rates(f, chunks[2]);
+            } else {
+                self.draw_stats(f, chunks[2]);
+            }
+            self.draw_handler_times(f, chunks[3]);
+            self.page = chunks[4].height.saturating_sub(2).max(1) as usize;
+            self.draw_messages(f, chunks[4]);
+            self.draw_warning(f);
+            if self.show_help {
+                self.draw_help(f);
+            }
         })?;
         self.last_draw = Instant::now();
+        self.dirty = false;
 
         Ok(())
     }
@@ -127,36 +491,353 @@ impl App {
     where
         B: Backend,
     {
-        let dursec = 10;
-        let duration = Duration::from_secs(dursec);
-
-        let mut total_map = HashMap::new();
-        for (inst, map) in &self.stats.vcdu_packets {
-            if inst.elapsed() > duration {
-                continue;
-            }
-            for (id, count) in map {
-                *total_map.entry(id).or_insert(0) += count;
-            }
-        }
-
-        let mut sorted = total_map.into_iter().collect::<Vec<_>>();
-        sorted.sort_by_key(|(&k, _)| k);
-        let d: Vec<(String, u64)> = sorted
+        let d: Vec<(String, u64)> = self
+            .stats
+            .rates(RATE_WINDOW)
+            .vcs
             .into_iter()
-            .map(|(k, v)| (format!("VC{:02}", k), (v as u64 / dursec) as u64))
+            .map(|r| (r.vcid.to_string(), r.packets_per_second as u64))
             .collect();
         let d: Vec<(&str, u64)> = d.iter().map(|(a, b)| (a.as_ref(), *b)).collect();
 
+        let lang = self.language;
+        let title = match self.stats.duplicates {
+            0 => lang.tr("VCDU receive rates (pps)").to_string(),
+            dups => lang.format("VCDU receive rates (pps), {} duplicates dropped", &[&dups]),
+        };
+
         let widget = BarChart::default()
             .data(&d)
             .bar_width(4)
             .bar_gap(1)
-            .max(60)
-            .block(Block::default().borders(Borders::ALL).title("VCDU receive rates (pps)"));
+            .max(
+                self.stats
+                    .mode()
+                    .unwrap_or(lrit::DownlinkMode::Hrit)
+                    .max_vcdus_per_second(),
+            )
+            .block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(widget, area)
+    }
+
+    /// Shows the busiest APIDs of the last 10 seconds, labeled with their product names
+    fn draw_apid_rates<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let bar_width = 12;
+
+        let mut sorted: Vec<_> = self
+            .stats
+            .rates(RATE_WINDOW)
+            .apids
+            .into_iter()
+            .filter(|r| r.apid != lrit::FILL_APID)
+            .collect();
+        sorted.sort_by(|a, b| {
+            b.packets_per_second
+                .total_cmp(&a.packets_per_second)
+                .then((a.vcid, a.apid).cmp(&(b.vcid, b.apid)))
+        });
+        // as many as fit, in VC/APID order
+        sorted.truncate((area.width.saturating_sub(2) / (bar_width + 1)) as usize);
+        sorted.sort_by_key(|r| (r.vcid, r.apid));
+
+        let d: Vec<(String, u64)> = sorted
+            .into_iter()
+            .map(|r| {
+                let name = lrit::apid_product_name(r.vcid.into(), r.apid).unwrap_or("?");
+                (format!("{} {}", r.apid, name), r.packets_per_second as u64)
+            })
+            .collect();
+        let d: Vec<(&str, u64)> = d.iter().map(|(a, b)| (a.as_ref(), *b)).collect();
+
+        let widget = BarChart::default().data(&d).bar_width(bar_width).bar_gap(1).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(self.language.tr("APID receive rates (pps)")),
+        );
         f.render_widget(widget, area)
     }
 
+    /// Shows signal quality in big text, so it can be read from across the room while pointing
+    /// an antenna
+    fn draw_pointing<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let quality = self.stats.link_quality(POINTING_WINDOW);
+        // green when things are good, yellow when they're marginal, and red when they're bad
+        let color = |value: f64, ok: f64, marginal: f64| {
+            if value <= ok {
+                Color::Green
+            } else if value <= marginal {
+                Color::Yellow
+            } else {
+                Color::Red
+            }
+        };
+        let panels = [
+            (
+                "VCDUs per second",
+                format!("{:.0}", quality.vcdus_per_second),
+                Color::White,
+            ),
+            (
+                "Dropped",
+                format!("{:.1}%", quality.drop_rate * 100.0),
+                color(quality.drop_rate, 0.001, 0.05),
+            ),
+            ("Fill", format!("{:.0}%", quality.fill_ratio * 100.0), Color::White),
+            (
+                "Errors",
+                format!("{:.1}%", quality.error_rate * 100.0),
+                color(quality.error_rate, 0.001, 0.05),
+            ),
+        ];
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(area);
+        for (row, pair) in rows.iter().zip(panels.chunks(2)) {
+            let cells = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(*row);
+            for (cell, (title, value, color)) in cells.iter().zip(pair) {
+                let lines: Vec<Spans> = big_text(value)
+                    .into_iter()
+                    .map(|line| Spans::from(Span::styled(line, Style::default().fg(*color))))
+                    .collect();
+                let secs = format!("{:.0}", quality.window_secs);
+                let title = self
+                    .language
+                    .format("{} (last {}s)", &[&self.language.tr(title), &secs]);
+                let widget = Paragraph::new(lines)
+                    .alignment(tui::layout::Alignment::Center)
+                    .block(Block::default().borders(Borders::ALL).title(title));
+                f.render_widget(widget, *cell);
+            }
+        }
+    }
+
+    /// Shows uptime, how much has been received, and the state of each source
+    fn draw_summary<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let stats = &self.stats;
+        let lang = self.language;
+        let products = match stats.products.values().sum::<usize>() {
+            0 => lang.tr("no products yet").to_string(),
+            total => {
+                let by_type = stats
+                    .products
+                    .iter()
+                    .map(|(code, count)| match lrit::filetype_name(*code) {
+                        Some(name) => format!("{} {}", lang.tr(name), count),
+                        None => format!("{} {}", lang.format("type {}", &[code]), count),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                lang.format("{} products ({})", &[&total, &by_type])
+            }
+        };
+        let mut text = vec![
+            lang.format("Up {}", &[&format_uptime(stats.uptime())]),
+            match stats.mode() {
+                Some(mode) => mode.to_string(),
+                None => lang.tr("mode unknown").to_string(),
+            },
+            lang.format(
+                "{} MB received",
+                &[&format!("{:.1}", stats.bytes as f64 / (1024.0 * 1024.0))],
+            ),
+            lang.format("{}% utilized", &[&format!("{:.0}", stats.utilization() * 100.0)]),
+            products,
+        ];
+        text.extend(
+            stats
+                .sources
+                .iter()
+                .map(|s| format!("{}: {}", s.address, s.status(stats.now()))),
+        );
+        let dcs = stats.dcs.messages_by_source();
+        if !dcs.is_empty() {
+            let by_source: Vec<_> = dcs
+                .iter()
+                .map(|(source, count)| format!("{} {}", source, count))
+                .collect();
+            text.push(format!("DCS: {}", by_source.join(", ")));
+        }
+        if let Some(cache) = &self.cache {
+            let (count, bytes) = cache.usage();
+            let mb = format!("{:.1}", bytes as f64 / (1024.0 * 1024.0));
+            text.push(lang.format("{} cached ({} MB)", &[&count, &mb]));
+        }
+        let overdue = stats.overdue();
+        if !overdue.is_empty() {
+            let products: Vec<_> = overdue.iter().map(|o| o.product.as_str()).collect();
+            text.push(lang.format("OVERDUE: {}", &[&products.join(", ")]));
+        }
+        let skew = stats.clock_skew();
+        if skew.stale > 0 {
+            text.push(lang.format("{} stale products", &[&skew.stale]));
+        }
+        let now = stats.utc();
+        for outage in stats.outages() {
+            let label = lang.tr(if outage.is_active(now) { "OUTAGE" } else { "UPCOMING" });
+            text.push(format!("{}: {}", label, outage.summary()));
+        }
+
+        let mut spans = Vec::new();
+        let health = &self.writer_health;
+        if !health.is_healthy() {
+            let mut status = match health.degraded_since {
+                Some(since) => lang.format("DISK WRITES FAILING since {}", &[&since.format("%H:%M:%S")]),
+                None => lang.tr("DISK WRITES FAILED").to_string(),
+            };
+            let mb = format!("{:.1}", health.spooled_bytes as f64 / (1024.0 * 1024.0));
+            status += &lang.format(", {} files ({} MB) held in memory", &[&health.spooled_files, &mb]);
+            if health.dropped_files > 0 {
+                status += &lang.format(", {} dropped", &[&health.dropped_files]);
+            }
+            if let Some(e) = &health.last_error {
+                status += &format!(" ({})", e);
+            }
+            spans.push(Span::styled(
+                status + "  |  ",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+        if let (true, Some(offset)) = (skew.skewed, skew.offset_secs) {
+            let by = format_uptime(Duration::from_secs(offset.unsigned_abs()));
+            let mut status = match offset > 0 {
+                true => lang.format("CLOCK SKEW: local clock is {} ahead of product times", &[&by]),
+                false => lang.format("CLOCK SKEW: local clock is {} behind product times", &[&by]),
+            };
+            if skew.source == TimeSource::Product {
+                status += lang.tr(", going by product times");
+            }
+            spans.push(Span::styled(
+                status + "  |  ",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+        spans.push(Span::raw(text.join("  |  ")));
+        let widget = Paragraph::new(Spans::from(spans))
+            .wrap(Wrap { trim: true })
+            .block(Block::default().borders(Borders::ALL).title(lang.tr("Summary")));
+        f.render_widget(widget, area);
+    }
+
+    /// Shows the busiest APIDs (since startup), with their product names
+    fn draw_apids<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let mut sorted: Vec<_> = self.stats.apid.iter().collect();
+        sorted.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+
+        let text = sorted
+            .into_iter()
+            .map(|((vcid, apid), count)| {
+                format!(
+                    "{} APID {}: {}",
+                    lrit::Vcid::from(*vcid),
+                    lrit::apid_label(*vcid, *apid),
+                    count
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("  |  ");
+
+        let widget = Paragraph::new(Spans::from(vec![Span::raw(text)]))
+            .wrap(Wrap { trim: true })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(self.language.tr("APIDs (packets)")),
+            );
+        f.render_widget(widget, area);
+    }
+
+    /// Shows the products handled this hour and last hour, by file type and NOAA product ID
+    fn draw_product_mix<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let label = |code: u8, product_id: Option<u16>| {
+            let name = self.language.tr(lrit::filetype_name(code).unwrap_or("?"));
+            match product_id {
+                Some(id) => format!("{} ({}) #{}", name, code, id),
+                None => format!("{} ({})", name, code),
+            }
+        };
+        let lines: Vec<Spans> = self
+            .stats
+            .product_mix
+            .iter()
+            .take(2)
+            .map(|(hour, counts)| {
+                let mut sorted: Vec<_> = counts.iter().collect();
+                sorted.sort_by_key(|(key, count)| (Reverse(**count), **key));
+                let text = sorted
+                    .into_iter()
+                    .map(|((code, product_id), count)| format!("{}: {}", label(*code, *product_id), count))
+                    .collect::<Vec<_>>()
+                    .join("  |  ");
+                Spans::from(vec![
+                    Span::styled(format!("{} ", hour.format("%H:00")), Style::default().fg(Color::Cyan)),
+                    Span::raw(text),
+                ])
+            })
+            .collect();
+
+        let widget = Paragraph::new(lines).wrap(Wrap { trim: true }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(self.language.tr("Products by hour (UTC)")),
+        );
+        f.render_widget(widget, area);
+    }
+
+    /// Shows how long each handler takes, to help find what's slowing things down
+    fn draw_handler_times<B>(&self, f: &mut Frame<B>, area: Rect)
+    where
+        B: Backend,
+    {
+        let lang = self.language;
+        let text = self
+            .stats
+            .handler_times
+            .iter()
+            .map(|(name, times)| {
+                let crashes = match self.stats.handler_crashes.get(name) {
+                    Some(n) => lang.format(", {} crashes", &[n]),
+                    None => String::new(),
+                };
+                let ms = |ms: f64| format!("{:.0}", ms);
+                lang.format(
+                    "{}: mean {} ms, p95 {} ms, max {} ms",
+                    &[
+                        name,
+                        &ms(times.mean_ms()),
+                        &ms(times.percentile_ms(95.0)),
+                        &ms(times.max_ms),
+                    ],
+                ) + &crashes
+            })
+            .collect::<Vec<_>>()
+            .join("  |  ");
+
+        let widget = Paragraph::new(Spans::from(vec![Span::raw(text)]))
+            .wrap(Wrap { trim: true })
+            .block(Block::default().borders(Borders::ALL).title(lang.tr("Handler times")));
+        f.render_widget(widget, area);
+    }
+
     fn draw_messages<B>(&self, f: &mut Frame<B>, area: Rect)
     where
         B: Backend,
@@ -164,43 +845,126 @@ impl App {
         // 1 message, hight 5, skip max(-4, 0) skip 0
         // 6 messages, height 5, skip max(1, 0) skip 1
         let h = (area.height - 2) as usize;
-        let to_skip = if self.messages.len() > h {
-            (self.messages.len() - h) as usize
-        } else {
-            0
-        };
+        let end = self.messages.len() - self.scroll.min(self.messages.len());
+        let to_skip = end.saturating_sub(h);
 
+        let search = self.search.as_ref().map(|s| s.to_lowercase());
         let msg: Vec<Spans> = self
             .messages
-            .iter()
-            .skip(to_skip)
-            .map(|m| {
-                Spans::from(vec![Span::raw({
-                    let mut s = m.clone();
-                    s.push('\n');
-                    s
-                })])
+            .range(to_skip..end)
+            .map(|line| {
+                let mut style = match line.level {
+                    log::Level::Error => Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+                    log::Level::Warn => Style::default().fg(Color::Yellow),
+                    log::Level::Info => Style::default(),
+                    log::Level::Debug | log::Level::Trace => Style::default().fg(Color::DarkGray),
+                };
+                if matches!(&search, Some(s) if line.matches(s)) {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                // the level is shown by the color, except for errors and warnings, which should
+                // stand out even on a monochrome terminal
+                let level = match line.level {
+                    log::Level::Error | log::Level::Warn => format!("{} ", line.level),
+                    _ => String::new(),
+                };
+                Spans::from(vec![
+                    Span::styled(format!("[{}] ", line.tag()), style.fg(Color::Cyan)),
+                    Span::styled(format!("{}{}\n", level, line.message), style),
+                ])
             })
             .collect();
 
+        let lang = self.language;
+        let mut title = match (self.paused, self.scroll) {
+            (true, _) => lang.format("Messages (paused, {} newer)", &[&self.scroll]),
+            (false, 0) => lang.tr("Messages").to_string(),
+            (false, n) => lang.format("Messages ({} newer)", &[&n]),
+        };
+        if let Some(input) = &self.search_input {
+            title = format!("{}  /{}_", title, input);
+        } else if let Some(search) = &self.search {
+            title = format!("{}  /{}", title, search);
+        }
         let widget = Paragraph::new(msg)
             .wrap(Wrap { trim: true })
-            .block(Block::default().borders(Borders::ALL).title("Messages"));
+            .block(Block::default().borders(Borders::ALL).title(title));
         f.render_widget(widget, area);
     }
+
+    /// Shows the key bindings, in a box over the middle of the screen
+    fn draw_help<B>(&self, f: &mut Frame<B>)
+    where
+        B: Backend,
+    {
+        let lines: Vec<Spans> = Action::ALL
+            .iter()
+            .map(|action| {
+                Spans::from(Span::raw(format!(
+                    "{:<16} {}",
+                    self.keys.keys_for(*action).join(", "),
+                    self.language.tr(action.description())
+                )))
+            })
+            .collect();
+
+        let size = f.size();
+        let width = 60.min(size.width);
+        let height = (lines.len() as u16 + 2).min(size.height);
+        let area = Rect::new((size.width - width) / 2, (size.height - height) / 2, width, height);
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(self.language.tr("Keys"))),
+            area,
+        );
+    }
+
+    /// Shows the text of a warning, in a big box over the middle of the screen
+    fn draw_warning<B>(&mut self, f: &mut Frame<B>)
+    where
+        B: Backend,
+    {
+        let close = self.keys.keys_for(Action::ShowWarning).join(", ");
+        let popup = match &mut self.warning {
+            Some(popup) => popup,
+            None => return,
+        };
+        let size = f.size();
+        let width = 84.min(size.width);
+        let height = (size.height * 4 / 5).max(3).min(size.height);
+        let area = Rect::new((size.width - width) / 2, (size.height - height) / 2, width, height);
+        popup.page = height.saturating_sub(2).max(1);
+
+        let title = self.language.format("{} ({} to close)", &[&popup.product, &close]);
+        f.render_widget(Clear, area);
+        f.render_widget(
+            Paragraph::new(popup.text.as_str())
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .wrap(Wrap { trim: false })
+                .scroll((popup.scroll, 0)),
+            area,
+        );
+    }
 }
 
+fn open_panic_log() -> io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .append(true)
+        .create(true)
+        .truncate(false)
+        .open("panic.log")
+}
+
+/// Logs panics to `panic.log`
+///
+/// Panics in the decoding pipeline are followed by a dump of the pipeline state (see
+/// [`write_crash_state`]).
 pub fn set_panic_handler() {
     let old_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
         // log this panic to disk:
-        if let Ok(mut file) = std::fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .create(true)
-            .truncate(false)
-            .open("panic.log")
-        {
+        if let Ok(mut file) = open_panic_log() {
             let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .map(|d| d.as_secs())
@@ -225,113 +989,1518 @@ pub fn set_panic_handler() {
     }));
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    set_panic_handler();
+/// Appends the pipeline state (as JSON) to `panic.log`, after the panic message itself
+fn write_crash_state(app: &App, current: Option<&lrit::LRIT>) {
+    if let Ok(mut file) = open_panic_log() {
+        let state = app.crash_state(current);
+        let _ = writeln!(
+            file,
+            "State: {}",
+            serde_json::to_string_pretty(&state).unwrap_or_default()
+        );
+    }
+}
 
-    let mut args = std::env::args().skip(1);
-    let target: String = args.next().expect(
-        "Missing first arg: target. \
-        Example tcp://localhost:5004",
-    );
-    let output_root = args.next().expect("Missing second arg: output root");
+/// Writes a completeness report for a single day
+///
+/// The date defaults to yesterday, and the schedule defaults to `report::default_expectations()`
+fn run_report(args: ReportArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let output_root = args.output_root;
+    let date = args
+        .date
+        .unwrap_or_else(|| (chrono::Utc::now() - chrono::Duration::days(1)).naive_utc().date());
+    let expectations = match args.schedule {
+        Some(path) => serde_json::from_reader(std::fs::File::open(path)?)?,
+        None => report::default_expectations(),
+    };
 
-    let stdout = io::stdout().into_raw_mode()?;
-    let backend = TermionBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-    terminal.clear()?;
+    let records = ProductIndex::new(&output_root).read_day(date)?;
+    let report = report::CompletenessReport::generate(date, &records, &expectations);
 
-    // channels for messaging
-    let (s, log_receiver) = unbounded();
-    let logger = AppLogger::new(s);
-    log::set_boxed_logger(Box::new(logger))?;
-    log::set_max_level(log::LevelFilter::Debug);
+    let report_dir = std::path::Path::new(&output_root).join("reports");
+    std::fs::create_dir_all(&report_dir)?;
+    let report_path = report_dir.join(format!("completeness-{}.txt", date.format("%Y-%m-%d")));
+    report.write(&mut std::fs::File::create(&report_path)?)?;
+    report.write(&mut io::stdout())?;
+    println!("Wrote {}", report_path.display());
 
-    let mut app = App::new();
+    Ok(())
+}
 
-    let mut sock = Socket::new(Protocol::Sub).expect("socket::new");
-    sock.connect(&target).expect("sock.bind");
-    sock.subscribe(b"").expect("sock.subscribe");
-    log::info!("Connected and subscribed to {}", target);
+/// Builds an animated GIF out of the last 24 hours of images
+///
+/// If the satellite longitude is given, frames where the sun is down at the sub-satellite point
+/// are skipped (useful for visible band loops).
+///
+/// If the pattern is a directory under the output root (like `suvi/Fe195`), every image in it is
+/// used instead.  If it's a query (like `band=13 region=FD since=6h`, see [`IndexQuery::parse`]),
+/// the images it finds are used.
+///
+/// Mesoscale sectors move around, so if the frames are of more than one place, each place gets
+/// its own loop, named like `<output>-M1_35.2N_097.5W-202205041800.gif`.
+fn run_timelapse(args: TimelapseArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let TimelapseArgs {
+        output_root,
+        pattern,
+        output,
+        satellite_longitude,
+    } = args;
 
-    // all network receiving will happen in a new thread, and will send VCDU packets
-    // to the main thread via a channel
-    let (s, net) = unbounded();
-    std::thread::spawn(move || {
-        let mut buf = Vec::new();
-
-        loop {
-            buf.truncate(0);
-            let num_bytes_read = sock.read_to_end(&mut buf).expect("sock.read");
-            //println!("bytes read: {}", num_bytes_read);
-            if num_bytes_read != 892 {
-                eprintln!("Read a packet that wasn't 892 bytes!");
-                return;
-            }
-            s.send(buf[..num_bytes_read].to_owned()).unwrap();
-        }
-    });
+    let mut timelapse = Timelapse::new();
+    if let Some(lon) = satellite_longitude {
+        timelapse = timelapse.skip_night(lon);
+    }
 
-    // spawn a thread to handle keyboard input
-    let (s, kbd) = unbounded();
-    std::thread::spawn(move || {
-        use termion::input::TermRead;
-        let stdin = io::stdin();
-        for evt in stdin.keys() {
-            s.send(evt.unwrap()).unwrap();
-        }
-    });
+    let dir = std::path::Path::new(&output_root).join(&pattern);
+    let frames = if dir.is_dir() {
+        timelapse.frames_in_dir(&dir)?
+    } else if pattern.contains('=') {
+        let now = chrono::Utc::now();
+        let mut query = IndexQuery::parse(&pattern, now)?;
+        query.since.get_or_insert(now - chrono::Duration::days(1));
+        let records = ProductIndex::new(&output_root).search(&query, now)?;
+        timelapse.select_frames(std::path::Path::new(&output_root), &records, "")
+    } else {
+        let now = chrono::Utc::now();
+        let since = now - chrono::Duration::days(1);
+        let index = ProductIndex::new(&output_root);
+        let mut records = index.read_day(since.naive_utc().date())?;
+        records.extend(index.read_day(now.naive_utc().date())?);
+        records.retain(|r| r.received >= since);
+        timelapse.select_frames(std::path::Path::new(&output_root), &records, &pattern)
+    };
+    let loops = Timelapse::sector_loops(frames);
+    for frames in &loops {
+        let output = match (loops.len(), &frames[0].sector) {
+            (2.., Some(sector)) => format!(
+                "{}-{}-{}.gif",
+                output.trim_end_matches(".gif"),
+                sector,
+                frames[0].time.format("%Y%m%d%H%M")
+            ),
+            _ => output.clone(),
+        };
+        timelapse.write_gif(frames, &output)?;
+        println!("Wrote {} frames to {}", frames.len(), output);
+    }
 
-    let mut handlers: Vec<Box<dyn handlers::Handler>> = Vec::new();
-    handlers.push(Box::new(handlers::TextHandler::new(&output_root)));
-    handlers.push(Box::new(handlers::ImageHandler::new(&output_root)));
-    handlers.push(Box::new(handlers::DcsHandler::new(&output_root)));
-    handlers.push(Box::new(handlers::DebugHandler::new(&output_root)));
+    Ok(())
+}
 
-    loop {
-        select! {
-            recv(kbd) -> msg => {
-                let msg = msg.unwrap();
-                if msg == Key::Esc || msg == Key::Char('q')  || msg == Key::Ctrl('c') {
-                    break;
-                } else if msg == Key::Char('c') {
-                    app.clear_msg();
-                    app.draw(&mut terminal)?;
-                } else {
-                    log::info!("got kbd {:?}", msg);
-                }
+/// The set of handlers that all decoded LRIT files are passed through
+///
+/// If `events` is given, handlers will send an event for every completed image, text product,
+/// and DCS message.  DCS messages are counted in `dcs_stats`, if it's given.  Options for the
+/// default handlers come from `config`, and products are written in the given `format`.
+fn build_dispatcher(
+    output_root: &str,
+    events: Option<EventSender>,
+    dcs_stats: Option<DcsStats>,
+    writer: &BatchWriter,
+    config: &Config,
+    format: &ProductFormat,
+) -> handlers::Dispatcher {
+    let storage = product_storage(config, output_root);
+    let mut text = handlers::TextHandler::new(output_root)
+        .with_storage(storage.clone())
+        .with_writer(writer.queue())
+        .with_history(&config.text.history)
+        .with_header_passthrough(format.headers)
+        .with_duplicate_policy(config.text.duplicates)
+        .with_index(output_root);
+    if let Some(detector) = config.text.collision_detector() {
+        text = text.with_collision_detector(detector);
+    }
+    let mut image = handlers::ImageHandler::new(output_root)
+        .with_storage(storage.clone())
+        .with_pyramid_levels(format.pyramid_levels)
+        .with_index(output_root)
+        .with_header_passthrough(format.headers);
+    if format.timing_sidecar {
+        image = image.with_timing_sidecar();
+    }
+    if format.streaming_assembly {
+        image = image.with_streaming_assembly(std::path::Path::new(output_root).join(".assembly"));
+    }
+    if let Some(change) = &config.change {
+        image = image.with_change_detection(change.detector());
+    }
+    for watch in &config.storm_watch {
+        image = image.with_storm_watch(watch.watch());
+    }
+    if let Some(levels) = &config.levels {
+        image = image.with_auto_levels(levels.auto_levels());
+    }
+    let mut himawari = handlers::HimawariHandler::new(output_root)
+        .with_storage(storage.clone())
+        .with_header_passthrough(format.headers);
+    let mut suvi = handlers::SuviHandler::new(output_root).with_storage(storage.clone());
+    if let Some(product_id) = config.suvi.product_id {
+        suvi = suvi.with_product_id(product_id);
+    }
+    let mut dcs = config.dcs.handler(output_root);
+    if let Some(dcs_stats) = dcs_stats {
+        dcs = dcs.with_stats(dcs_stats);
+    }
+    // a crash in one handler shouldn't stop the others
+    let mut handlers = handlers::Dispatcher::new().with_sandbox(handlers::SandboxOptions::default());
+    if let Some(events) = events {
+        handlers = handlers.with_events(events.clone());
+        text = text.with_events(events.clone());
+        image = image.with_events(events.clone());
+        himawari = himawari.with_events(events.clone());
+        suvi = suvi.with_events(events.clone());
+        dcs = dcs.with_events(events);
+    }
 
-            },
-            recv(net) -> data => {
-                let data = data.unwrap();
-                let vcdu = VCDU::new(&data[..892]);
+    handlers.push(Box::new(text));
+    handlers.push(Box::new(
+        handlers::GtsHandler::new(output_root)
+            .with_storage(storage.clone())
+            .with_writer(writer.queue())
+            .with_duplicate_policy(config.text.duplicates)
+            .with_index(output_root),
+    ));
+    handlers.push(Box::new(image));
+    handlers.push(Box::new(himawari));
+    handlers.push(Box::new(suvi));
+    let mut glm = handlers::GlmHandler::new(output_root).with_storage(storage);
+    if config.glm.overlays {
+        glm = glm.with_overlays();
+    }
+    handlers.push(Box::new(glm));
+    handlers.push(Box::new(dcs));
+    handlers.push(Box::new(handlers::DebugHandler::new(output_root)));
+    handlers.push(Box::new(IndexHandler::new(output_root)));
+    handlers
+}
 
-                for lrit in app.process(vcdu) {
-                    for handler in &mut handlers {
-                        match handler.handle(&lrit) {
-                            Ok(()) => {},
-                            Err(handlers::HandlerError::Skipped) => {},
-                            Err(e) => {
-                                warn!("Handler failed: {:?}", e);
-                            }
-                        }
-                    }
-                    let code = lrit.headers.primary.filetype_code ;
-                    if code != 0 && code != 2 && code != 130 {
-                        log::info!("{:?}", lrit.headers);
-                    }
-                }
-                app.draw(&mut terminal)?;
-            },
-            recv(log_receiver) -> data => {
-                let data = data.unwrap();
-                app.info(data);
-                app.draw(&mut terminal)?;
-            },
-            default(Duration::from_millis(100)) => {
-                app.draw(&mut terminal)?;
-            }
+/// Where products under `output_root` are written, from the `[output]` config
+fn product_storage(config: &Config, output_root: &str) -> SharedStorage {
+    match &config.output {
+        Some(output) => output.storage(output_root),
+        None => goeslib::storage::local(),
+    }
+}
 
-        };
+/// Counts a completed LRIT file, and queues it to be handled
+fn received(app: &mut App, bus: &mut EventBus, queue: &mut handlers::DispatchQueue, lrit: lrit::LRIT) {
+    app.remember(&lrit);
+    if let Some(ann) = &lrit.headers.annotation {
+        app.record(Stat::Annotation(ann.text.clone()));
+    }
+    if let Some(time) = lrit.data_time().filter(|_| !lrit.replay) {
+        app.record(Stat::ProductTime(time));
+    }
+    let product_id = lrit.headers.noaa.as_ref().map(|n| n.product_id);
+    app.record(Stat::Product(lrit.headers.primary.filetype_code, product_id));
+    if let Some(decompression) = &lrit.decompression {
+        app.record(Stat::Decompression(product_id, decompression.clone()));
+    }
+    bus.publish(Event::LritCompleted(LritCompletedEvent::new(&lrit)));
+    if let Some(truncated) = ProductTruncatedEvent::new(&lrit) {
+        bus.publish(Event::ProductTruncated(truncated));
+    }
+    let code = lrit.headers.primary.filetype_code;
+    if code != 0 && code != 1 && code != 2 && code != 130 {
+        log::info!("{:?}", lrit.headers);
+    }
+    queue.push(lrit);
+}
+
+/// Runs an LRIT file through the handlers, saving the crash state if one panics, and returns how
+/// many handlers failed on it
+///
+/// Failures are already logged by the dispatcher.
+fn dispatch_lrit(app: &mut App, handlers: &mut handlers::Dispatcher, bus: &mut EventBus, lrit: &lrit::LRIT) -> usize {
+    let failures = match std::panic::catch_unwind(AssertUnwindSafe(|| handlers.dispatch(lrit))) {
+        Ok(failures) => failures.len(),
+        Err(panic) => {
+            write_crash_state(app, Some(lrit));
+            std::panic::resume_unwind(panic);
+        }
+    };
+    bus.dispatch();
+    app.stats.handler_times.clone_from(handlers.timings());
+    app.stats.handler_crashes.clone_from(handlers.crashes());
+    failures
+}
+
+/// Prints how well reception went, for `--no-write`
+fn print_evaluation(stats: &Stats, index: &MemoryIndex) {
+    let percent = |count: usize| 100.0 * count as f64 / stats.packets.max(1) as f64;
+    println!(
+        "{} VCDUs in {}: {} products, {} corrupt ({:.2}%), {} fill ({:.1}%, {:.1}% utilized), {} decompression errors",
+        stats.packets,
+        format_uptime(stats.uptime()),
+        index.len(),
+        stats.corrupt_packets,
+        percent(stats.corrupt_packets),
+        stats.fills,
+        percent(stats.fills),
+        stats.utilization() * 100.0,
+        stats.decompression_errors
+    );
+    for (code, count) in &stats.products {
+        match lrit::filetype_name(*code) {
+            Some(name) => println!("  {}: {}", name, count),
+            None => println!("  type {}: {}", code, count),
+        }
+    }
+}
+
+/// Prints what a `--once` run read and wrote, returning an error (for the exit status) if a
+/// target couldn't be read or anything couldn't be written
+fn finish_batch(stats: &Stats, failed: usize, failed_writes: u64) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Read {} VCDUs into {} LRIT files ({} corrupt, {} duplicates): {} failed in handlers, {} failed writes",
+        stats.packets,
+        stats.products.values().sum::<usize>(),
+        stats.corrupt_packets,
+        stats.duplicates,
+        failed,
+        failed_writes
+    );
+    for (code, count) in &stats.products {
+        match lrit::filetype_name(*code) {
+            Some(name) => println!("  {}: {}", name, count),
+            None => println!("  type {}: {}", code, count),
+        }
+    }
+    let unread: Vec<_> = stats.sources.iter().filter(|s| s.error.is_some()).collect();
+    for source in &unread {
+        println!(
+            "  {} couldn't be read: {}",
+            source.address,
+            source.error.as_deref().unwrap_or_default()
+        );
+    }
+
+    if !unread.is_empty() || failed > 0 || failed_writes > 0 {
+        return Err(format!(
+            "{} unreadable targets, {} handler failures, {} failed writes",
+            unread.len(),
+            failed,
+            failed_writes
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Publishes an event (as a line of JSON) on the events socket
+fn publish_event(sock: &mut Socket, event: &Event) {
+    let mut msg = match serde_json::to_vec(event) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::warn!("Failed to serialize event: {}", e);
+            return;
+        }
+    };
+    msg.push(b'\n');
+    if let Err(e) = sock.write_all(&msg) {
+        log::warn!("Failed to publish event: {}", e);
+    }
+}
+
+/// Finds all `.lrit` files under a directory, sorted by path
+fn find_lrit_files(dir: &std::path::Path, files: &mut Vec<std::path::PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_lrit_files(&path, files)?;
+        } else if path.extension() == Some(std::ffi::OsStr::new("lrit")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(())
+}
+
+/// What's needed from the sidecar file of a saved LRIT file (a
+/// [`DeadLetterInfo`](goeslib::deadletter::DeadLetterInfo), an
+/// [`UnparsedInfo`](goeslib::deadletter::UnparsedInfo), or a [`QuarantineInfo`](handlers::QuarantineInfo))
+#[derive(serde::Deserialize)]
+struct SavedInfo {
+    vcid: u8,
+}
+
+/// The VCID of a saved LRIT file, from its dead-letter or quarantine sidecar file if there is one
+fn saved_vcid(path: &std::path::Path, default_vcid: u8) -> u8 {
+    std::fs::File::open(path.with_extension("json"))
+        .ok()
+        .and_then(|f| serde_json::from_reader::<_, SavedInfo>(f).ok())
+        .map_or(default_vcid, |info| info.vcid)
+}
+
+/// Runs saved LRIT files (from the dead-letter directory, or any other archive) back through the
+/// handlers
+///
+/// The VCID is read from the dead-letter or quarantine sidecar file if there is one, and otherwise defaults to
+/// the given value (or 0).  Files that fail again are reported, but not written back into the
+/// dead-letter directory.
+fn run_reprocess(args: ReprocessArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let output_root = args.output_root;
+    let mut files = Vec::new();
+    find_lrit_files(&args.dir, &mut files)?;
+
+    let writer = BatchWriter::spawn(BatchOptions::default())?;
+    let config = Config::resolve(args.config.as_deref())?;
+    let mut handlers = build_dispatcher(&output_root, None, None, &writer, &config, &config.format)
+        .with_retry_policy(handlers::RetryPolicy::none());
+    let mut failed = 0;
+    for path in &files {
+        let vcid = saved_vcid(path, args.default_vcid);
+        let lrit = match lrit::LRIT::from_bytes(vcid, &std::fs::read(path)?) {
+            Ok(lrit) => lrit,
+            Err(e) => {
+                failed += 1;
+                println!("{}: failed to read headers: {}", path.display(), e);
+                continue;
+            }
+        };
+        let failures = handlers.dispatch(&lrit);
+        if !failures.is_empty() {
+            failed += 1;
+            for f in failures {
+                println!("{}: {} failed: {}", path.display(), f.handler, f.error);
+            }
+        }
+    }
+    // write out partial images, and anything else the handlers are holding on to
+    for f in handlers.flush() {
+        println!("{} failed to flush: {}", f.handler, f.error);
+    }
+    writer.flush();
+    println!(
+        "Reprocessed {} files ({} failed, {} failed writes)",
+        files.len(),
+        failed,
+        writer.failed_writes()
+    );
+
+    Ok(())
+}
+
+/// Publishes a synthetic HRIT stream, for testing and demos without an antenna
+///
+/// Like `simulate tcp://*:5004`.  The stream is paced at the real downlink rate unless another
+/// rate is given, and can be received by running goesbox against the same address.  VCDUs can be
+/// dropped, damaged, or reordered to simulate a poor signal.
+fn run_simulate(args: SimulateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let rate = args.bits_per_second.unwrap_or(goeslib::sim::HRIT_BITS_PER_SECOND);
+    let mut sock = Socket::new(Protocol::Pub)?;
+    sock.bind(&args.address)?;
+    println!("Publishing simulated downlink on {} at {} bps", args.address, rate);
+
+    let interval = goeslib::sim::vcdu_interval(rate);
+    let mut sim = Simulator::new();
+    let mut injector = LossInjector::new(args.seed)
+        .drop_rate(args.drop_rate)
+        .bit_flip_rate(args.flip_rate)
+        .reorder_rate(args.reorder_rate);
+    let mut next_send = Instant::now();
+    loop {
+        sim.queue_due(chrono::Utc::now());
+        for vcdu in injector.process(sim.next_vcdu()) {
+            sock.write_all(&vcdu)?;
+        }
+
+        next_send += interval;
+        let now = Instant::now();
+        if next_send > now {
+            std::thread::sleep(next_send - now);
+        }
+    }
+}
+
+/// Prints the headers of saved LRIT files
+fn run_dump(args: DumpArgs) -> Result<(), Box<dyn std::error::Error>> {
+    for path in &args.files {
+        let bytes = std::fs::read(path)?;
+        match lrit::LRIT::from_bytes(saved_vcid(path, args.vcid), &bytes) {
+            Ok(lrit) => {
+                println!(
+                    "{}: VCID {}, {} bytes of data",
+                    path.display(),
+                    lrit.vcid,
+                    lrit.data.len()
+                );
+                println!("{:#?}", lrit.headers);
+            }
+            Err(e) => println!("{}: failed to read headers: {}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Prints the index records that match a query, one per line
+fn run_query(args: QueryArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let now = chrono::Utc::now();
+    let mut query = IndexQuery::parse(&args.query.join(" "), now)?;
+    query.pattern = args.pattern.or(query.pattern);
+    query.vcid = args.vcid.or(query.vcid);
+    query.filetype_code = args.filetype.or(query.filetype_code);
+    query.product_id = args.product_id.or(query.product_id);
+
+    let index = ProductIndex::new(&args.output_root);
+    let records = if args.from.is_none() && args.to.is_none() {
+        index.search(&query, now)?
+    } else {
+        let from = args.from.unwrap_or_else(|| now.naive_utc().date());
+        let to = args.to.unwrap_or(from);
+        index.query(from.iter_days().take_while(|day| *day <= to), &query)?
+    };
+    if args.files {
+        // each segment of an image has its own record, but they're all in the same file
+        let mut seen = std::collections::HashSet::new();
+        for record in &records {
+            for path in record.files(std::path::Path::new(&args.output_root)) {
+                if seen.insert(path.clone()) {
+                    println!("{}", path.display());
+                }
+            }
+        }
+        return Ok(());
+    }
+    for record in records {
+        if args.json {
+            println!("{}", serde_json::to_string(&record)?);
+            continue;
+        }
+        let product_id = record.product_id.map(|id| id.to_string()).unwrap_or_default();
+        println!(
+            "{}  {}  type {:3}  {:>5}  {}{}",
+            record.received.format("%Y-%m-%d %H:%M:%S"),
+            lrit::Vcid::from(record.vcid),
+            record.filetype_code,
+            product_id,
+            record.annotation,
+            record.archive.map(|a| format!("  ({})", a)).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Checks the product index and the daily archives, and fails if anything is wrong
+fn run_verify(args: VerifyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let days = match args.date {
+        Some(date) => vec![date],
+        None => ProductIndex::new(&args.output_root).days()?,
+    };
+    let archiver = DailyArchiver::new(&args.output_root);
+    let mut problems = 0;
+    for day in days {
+        let summary = archiver.verify_day(day)?;
+        println!(
+            "{}: {} records ({} archived), {} bad index lines",
+            day, summary.records, summary.archived, summary.bad_lines
+        );
+        for (archive, error) in &summary.bad_archives {
+            println!("  {} is unreadable: {}", archive, error);
+        }
+        for (archive, annotation) in &summary.missing {
+            println!("  {} is missing from {}", annotation, archive);
+        }
+        problems += summary.bad_lines + summary.bad_archives.len() + summary.missing.len();
+    }
+    if problems > 0 {
+        return Err(format!("Found {} problems", problems).into());
+    }
+    Ok(())
+}
+
+/// Runs the built-in test vectors through the decoder and handlers (see [`goeslib::selftest`]),
+/// and fails if any check does
+fn run_selftest(args: SelftestArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = match &args.output {
+        Some(dir) => dir.clone(),
+        None => std::env::temp_dir().join(format!("goesbox-selftest-{}", std::process::id())),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let checks = goeslib::selftest::run(&dir);
+    if args.output.is_none() {
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    for check in &checks {
+        let result = if check.passed { "ok" } else { "FAILED" };
+        println!("{:<14} {:<6} {}", check.name, result, check.detail);
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        return Err(format!("{} of {} checks failed", failed, checks.len()).into());
+    }
+    println!("All {} checks passed", checks.len());
+    Ok(())
+}
+
+/// Re-parses the EMWIN products in an output root, and prints what the parsers didn't know (see
+/// [`goeslib::reparse`])
+fn run_reparse(args: ReparseArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let summary = reparse(&args.output_root)?;
+
+    println!(
+        "{} EMWIN files, {} parsed, {} with unknown T2 codes, {} with unknown areas, {} failed",
+        summary.files,
+        summary.parsed,
+        summary.unknown_t2.values().sum::<usize>(),
+        summary.unknown_areas.values().sum::<usize>(),
+        summary.failures.len()
+    );
+    for (t1t2, count) in &summary.unknown_t2 {
+        println!("  unknown T2 code {}: {}", t1t2, count);
+    }
+    for (ttaa, count) in &summary.unknown_areas {
+        println!("  unknown area designator {}: {}", ttaa, count);
+    }
+    let mut undecoded = BTreeMap::new();
+    for (_, decoder) in &summary.undecoded {
+        *undecoded.entry(*decoder).or_insert(0) += 1;
+    }
+    for (decoder, count) in &undecoded {
+        println!("  bulletins the {} decoder got nothing from: {}", decoder, count);
+    }
+    if args.list {
+        for (path, error) in &summary.failures {
+            println!("{}: {}", path.display(), error);
+        }
+        for (path, decoder) in &summary.undecoded {
+            println!("{}: nothing decoded by {}", path.display(), decoder);
+        }
+    }
+    Ok(())
+}
+
+/// Makes the derived outputs of images again, for the ones written by an older version (see
+/// [`goeslib::regen`])
+fn run_regen(args: RegenArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let days = match args.date {
+        Some(date) => vec![date],
+        None => {
+            let today = chrono::Utc::now().date_naive();
+            let mut days = ProductIndex::new(&args.output_root).days()?;
+            days.retain(|day| *day < today);
+            days
+        }
+    };
+    let format = Config::resolve(args.config.as_deref())?.format;
+    let mut regen = Regenerator::new(&args.output_root).with_pyramid_levels(format.pyramid_levels);
+    if format.timing_sidecar {
+        regen = regen.with_timing_sidecar();
+    }
+    if args.dry_run {
+        regen = regen.with_dry_run();
+    }
+    let mut failed = 0;
+    for day in days {
+        let summary = regen.regen_day(day)?;
+        println!(
+            "{}: {} {}, {} up to date, {} no longer on disk",
+            day,
+            summary.regenerated,
+            if args.dry_run { "out of date" } else { "regenerated" },
+            summary.current,
+            summary.missing
+        );
+        for (annotation, error) in &summary.failed {
+            println!("  {} failed: {}", annotation, error);
+        }
+        failed += summary.failed.len();
+    }
+    if failed > 0 {
+        return Err(format!("Failed to regenerate {} images", failed).into());
+    }
+    Ok(())
+}
+
+fn replay_capture(capture: &std::path::Path, output_root: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let writer = BatchWriter::spawn(BatchOptions::default())?;
+    let config = Config::default();
+    let mut handlers = build_dispatcher(output_root, None, None, &writer, &config, &config.format)
+        .with_retry_policy(handlers::RetryPolicy::none());
+    let mut app = App::new();
+    let mut products = 0;
+    for frame in CaptureReader::new(io::BufReader::new(std::fs::File::open(capture)?)) {
+        for lrit in app.process(VCDU::new(&frame?)) {
+            products += 1;
+            handlers.dispatch(&lrit);
+        }
+    }
+    handlers.flush();
+    writer.flush();
+
+    let stats = &app.stats;
+    println!(
+        "Replayed {} VCDUs into {} LRIT files ({} corrupt packets, {} failed writes)",
+        stats.packets,
+        products,
+        stats.corrupt_packets,
+        writer.failed_writes()
+    );
+    for (name, times) in handlers.timings() {
+        println!(
+            "  {}: {} files, mean {:.1} ms, p95 {:.0} ms, max {:.1} ms",
+            name,
+            times.count,
+            times.mean_ms(),
+            times.percentile_ms(95.0),
+            times.max_ms
+        );
+    }
+    Ok(())
+}
+
+/// What one side of a comparison did
+struct CompareSide {
+    failures: usize,
+    failed_writes: u64,
+    busy: Duration,
+}
+
+/// Runs LRIT files through the handlers `config` sets up under `output_root`, until there are no
+/// more
+fn compare_side(
+    config: &Config,
+    output_root: &str,
+    lrits: std::sync::mpsc::Receiver<std::sync::Arc<lrit::LRIT>>,
+) -> Result<CompareSide, String> {
+    let writer = BatchWriter::spawn(BatchOptions::default()).map_err(|e| e.to_string())?;
+    let mut handlers = build_dispatcher(output_root, None, None, &writer, config, &config.format)
+        .with_retry_policy(handlers::RetryPolicy::none());
+    let (mut failures, mut busy) = (0, Duration::ZERO);
+    for lrit in lrits {
+        let start = Instant::now();
+        failures += handlers.dispatch(&lrit).len();
+        busy += start.elapsed();
+    }
+    failures += handlers.flush().len();
+    writer.flush();
+    Ok(CompareSide {
+        failures,
+        failed_writes: writer.failed_writes(),
+        busy,
+    })
+}
+
+/// Runs a capture through the handlers of two configs at once, each into its own output root, and
+/// compares the files they wrote (see [`goeslib::compare`]), failing if any differ
+fn run_compare(args: CompareArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let configs = [
+        Config::resolve(Some(&args.config_a))?,
+        Config::resolve(Some(&args.config_b))?,
+    ];
+    let roots = ["a", "b"].map(|side| std::path::Path::new(&args.output_root).join(side));
+    for root in &roots {
+        std::fs::create_dir_all(root)?;
+    }
+    let roots = roots.map(|root| root.to_string_lossy().into_owned());
+    let mut app = App::new();
+    let mut products = 0;
+    let sides = std::thread::scope(|scope| -> Result<Vec<CompareSide>, Box<dyn std::error::Error>> {
+        let mut senders = Vec::new();
+        let mut workers = Vec::new();
+        for (config, root) in configs.iter().zip(&roots) {
+            let (sender, receiver) = std::sync::mpsc::sync_channel(64);
+            senders.push(sender);
+            workers.push(scope.spawn(move || compare_side(config, root, receiver)));
+        }
+        for frame in CaptureReader::new(io::BufReader::new(std::fs::File::open(&args.capture)?)) {
+            for lrit in app.process(VCDU::new(&frame?)) {
+                products += 1;
+                let lrit = std::sync::Arc::new(lrit);
+                for sender in &senders {
+                    let _ = sender.send(lrit.clone());
+                }
+            }
+        }
+        drop(senders);
+        let mut sides = Vec::new();
+        for worker in workers {
+            sides.push(worker.join().map_err(|_| "a handler thread panicked")??);
+        }
+        Ok(sides)
+    })?;
+
+    println!("Ran {} LRIT files through both configs", products);
+    for (name, side) in ["a", "b"].iter().zip(&sides) {
+        println!(
+            "  {}: {} handler failures, {} failed writes, {:.1} ms in handlers",
+            name,
+            side.failures,
+            side.failed_writes,
+            side.busy.as_secs_f64() * 1000.0
+        );
+    }
+    let ignore: Vec<String> = (goeslib::compare::VOLATILE.iter().map(|p| p.to_string()))
+        .chain(args.ignore)
+        .collect();
+    let diff = goeslib::compare::compare_roots(&roots[0], &roots[1], &ignore)?;
+    println!(
+        "{} files the same, {} only in a, {} only in b, {} different",
+        diff.same,
+        diff.only_a.len(),
+        diff.only_b.len(),
+        diff.changed.len()
+    );
+    for path in &diff.only_a {
+        println!("  only in a: {}", path.display());
+    }
+    for path in &diff.only_b {
+        println!("  only in b: {}", path.display());
+    }
+    for (path, a, b) in &diff.changed {
+        println!(
+            "  different: {} ({:08x}, {} bytes in a; {:08x}, {} bytes in b)",
+            path.display(),
+            a.crc32,
+            a.size,
+            b.crc32,
+            b.size
+        );
+    }
+    if !diff.is_empty() {
+        return Err(format!("Found {} differences", diff.differences()).into());
+    }
+    Ok(())
+}
+
+/// Merges raw captures of the same time period into a best-of capture
+///
+/// Frames are lined up by their VCDU counters.  When more than one capture has a frame, the copy
+/// from the capture listed first is kept.  With `--replay`, the merged capture is then run through
+/// the decoder, writing products into the given output root.
+fn run_merge(args: MergeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let output = args.output;
+    let inputs = args
+        .captures
+        .iter()
+        .map(|path| Ok(io::BufReader::new(std::fs::File::open(path)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let out = io::BufWriter::new(std::fs::File::create(&output)?);
+    // about 5 minutes of the busiest virtual channel
+    let summary = merge_captures(inputs, out, 16384)?;
+    println!(
+        "Wrote {} frames to {} ({} duplicates, {} late)",
+        summary.written, output, summary.duplicates, summary.late
+    );
+    for (i, count) in summary.from_each.iter().enumerate() {
+        println!("  capture {}: {} frames", i + 1, count);
+    }
+
+    if let Some(root) = args.replay {
+        replay_capture(std::path::Path::new(&output), &root)?;
+    }
+    Ok(())
+}
+
+/// Mirrors the output root to a local directory, another machine, or an S3 bucket
+///
+/// The target is parsed by [`parse_target`](goeslib::mirror::parse_target).  Only new and
+/// changed files are sent, and an interrupted sync resumes where it left off.  With `--every`, the
+/// sync repeats forever, and a lost connection is retried on the next round.
+fn run_sync(args: SyncArgs) -> Result<(), Box<dyn std::error::Error>> {
+    use goeslib::mirror::{parse_target, Mirror};
+
+    let SyncArgs {
+        output_root,
+        target,
+        every,
+    } = args;
+    let every = every.map(Duration::from_secs);
+
+    let mut dest = parse_target(&target);
+    let name: String = target
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut mirror = Mirror::new(&output_root, &name)?;
+    loop {
+        match mirror.run(dest.as_mut()) {
+            Ok(summary) => println!(
+                "Sent {} files ({:.1} MB), {} unchanged",
+                summary.sent,
+                summary.bytes as f64 / (1024.0 * 1024.0),
+                summary.unchanged
+            ),
+            Err(e) if every.is_some() => eprintln!("Sync failed, will retry: {}", e),
+            Err(e) => return Err(e.into()),
+        }
+        match every {
+            Some(every) => std::thread::sleep(every),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Formats an uptime like "3d 04:05:06"
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let hms = format!("{:02}:{:02}:{:02}", secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    match secs / 86400 {
+        0 => hms,
+        days => format!("{}d {}", days, hms),
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    set_panic_handler();
+
+    let cli = Cli::from_env();
+    // the live UI shows log messages itself
+    if !matches!(cli.command, Command::Run(_)) {
+        LoggerBuilder::new().with_stderr().install()?;
+    }
+    match cli.command {
+        Command::Run(args) => run(args),
+        Command::Replay(args) => replay_capture(&args.capture, &args.output_root),
+        Command::Dump(args) => run_dump(args),
+        Command::Query(args) => run_query(args),
+        Command::Verify(args) => run_verify(args),
+        Command::Regen(args) => run_regen(args),
+        Command::Reparse(args) => run_reparse(args),
+        Command::Simulate(args) => run_simulate(args),
+        Command::Report(args) => run_report(args),
+        Command::Timelapse(args) => run_timelapse(args),
+        Command::Reprocess(args) => run_reprocess(args),
+        Command::Merge(args) => run_merge(args),
+        Command::Sync(args) => run_sync(args),
+        Command::Stream(args) => run_stream(args),
+        Command::Selftest(args) => run_selftest(args),
+        Command::Compare(args) => run_compare(args),
+    }
+}
+
+/// Receives and decodes the live downlink, writing a record for each product to stdout
+///
+/// This has no UI, and nothing else is written to stdout, so goesbox can be one stage of a
+/// container pipeline.  It stops on SIGTERM, or once stdout is closed.
+fn run_stream(args: StreamArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let targets: Vec<String> = args.targets.split(',').map(|t| t.to_string()).collect();
+    let mut stream = StreamHandler::new(io::stdout())
+        .with_format(args.format)
+        .with_classes(&args.class);
+    if let Some(dir) = &args.save_dir {
+        std::fs::create_dir_all(dir)?;
+        stream = stream.with_save_dir(dir);
+    }
+
+    let mut app = App::new().with_decode_mode(args.decode.unwrap_or_default());
+    let net = connect(&targets, &mut app.stats);
+    let mut dedup = (targets.len() > 1).then(VcduDedup::default);
+    let mut signals = Signals::new([SIGTERM])?;
+    let (s, signal) = unbounded();
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            let _ = s.send(sig);
+        }
+    });
+
+    loop {
+        let data = select! {
+            recv(net) -> data => match data {
+                Ok((_, Ok(data))) => data,
+                Ok((source, Err(e))) => {
+                    eprintln!("Stopped receiving from {}: {}", app.stats.sources[source].address, e);
+                    continue;
+                }
+                Err(_) => break,
+            },
+            recv(signal) -> _ => break,
+        };
+        let vcdu = VCDU::new(&data[..892]);
+        if let Some(dedup) = &mut dedup {
+            if !dedup.is_new(&vcdu) {
+                continue;
+            }
+        }
+        for lrit in app.process(vcdu) {
+            match stream.handle(&lrit) {
+                Ok(()) | Err(handlers::HandlerError::Skipped) => {}
+                // the next stage is gone
+                Err(handlers::HandlerError::Io(e)) => return Err(e.into()),
+                Err(e) => eprintln!("Failed to stream a product: {}", e),
+            }
+        }
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+/// A VCDU from one of the targets (by its index in [`Stats::sources`]), or why that target
+/// stopped
+type Packet = (usize, Result<Vec<u8>, String>);
+
+/// The prefix of targets that are raw captures to read, instead of addresses to subscribe to
+const FILE_TARGET: &str = "file:";
+
+/// Subscribes to each of the targets
+///
+/// All network receiving happens in new threads (one per target), which send VCDU packets to the
+/// main thread via the returned channel.  Captures (`file:` targets) are read in threads too, and
+/// their threads stop at the end of the file.
+fn connect(targets: &[String], stats: &mut Stats) -> crossbeam_channel::Receiver<Packet> {
+    // captures are read only as fast as they're decoded, instead of all at once into memory, but
+    // nothing can hold up a receiver
+    let (s, net) = if targets.iter().all(|t| t.starts_with(FILE_TARGET)) {
+        crossbeam_channel::bounded(1024)
+    } else {
+        unbounded()
+    };
+    for target in targets {
+        if let Some(path) = target.strip_prefix(FILE_TARGET) {
+            let source = stats.add_source(target.as_str());
+            let path = std::path::PathBuf::from(path);
+            let s = s.clone();
+            log::info!("Reading VCDUs from {}", path.display());
+            std::thread::spawn(move || {
+                let file = match std::fs::File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        let _ = s.send((source, Err(e.to_string())));
+                        return;
+                    }
+                };
+                for frame in CaptureReader::new(io::BufReader::new(file)) {
+                    let packet = frame.map(|frame| frame.to_vec()).map_err(|e| e.to_string());
+                    let failed = packet.is_err();
+                    if s.send((source, packet)).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+            continue;
+        }
+        let mut sock = Socket::new(Protocol::Sub).expect("socket::new");
+        sock.connect(target).expect("sock.bind");
+        sock.subscribe(b"").expect("sock.subscribe");
+        log::info!("Connected and subscribed to {}", target);
+        let source = stats.add_source(target.as_str());
+
+        let s = s.clone();
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+
+            loop {
+                buf.truncate(0);
+                let packet = match sock.read_to_end(&mut buf) {
+                    Ok(892) => Ok(buf[..892].to_owned()),
+                    Ok(n) => Err(format!("read a packet that wasn't 892 bytes ({} bytes)", n)),
+                    Err(e) => Err(e.to_string()),
+                };
+                let failed = packet.is_err();
+                if s.send((source, packet)).is_err() || failed {
+                    return;
+                }
+            }
+        });
+    }
+    net
+}
+
+/// How long to wait before reconnecting to the EMWIN feed
+const EMWIN_FEED_RETRY: Duration = Duration::from_secs(10);
+
+/// Connects to a byte-interleaved EMWIN feed
+///
+/// Receiving happens in a new thread, which sends the products it assembles to the main thread via
+/// the returned channel, and reconnects whenever the connection drops.
+fn connect_emwin_feed(feed: &EmwinFeedConfig) -> crossbeam_channel::Receiver<lrit::LRIT> {
+    let (s, products) = unbounded();
+    let address = feed.address.clone();
+    let mut assembler = feed.assembler();
+    std::thread::spawn(move || loop {
+        match std::net::TcpStream::connect(&address) {
+            Ok(mut stream) => {
+                log::info!("Connected to the EMWIN feed at {}", address);
+                let mut buf = [0; 4096];
+                loop {
+                    let n = match stream.read(&mut buf) {
+                        Ok(0) => {
+                            log::warn!("The EMWIN feed at {} closed the connection", address);
+                            break;
+                        }
+                        Ok(n) => n,
+                        Err(e) => {
+                            log::warn!("Stopped receiving from the EMWIN feed at {}: {}", address, e);
+                            break;
+                        }
+                    };
+                    for lrit in assembler.push(&buf[..n]) {
+                        if s.send(lrit).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e) => log::warn!("Failed to connect to the EMWIN feed at {}: {}", address, e),
+        }
+        std::thread::sleep(EMWIN_FEED_RETRY);
+    });
+    products
+}
+
+/// How many products of each class are kept for gRPC clients, if the config doesn't set up a cache
+const GRPC_CACHE_PRODUCTS: usize = 10;
+
+/// Starts the gRPC server, returning the sink that passes events on to it
+#[cfg(feature = "grpc")]
+fn grpc_server(
+    address: std::net::SocketAddr,
+    cache: ProductCache,
+    output_root: &str,
+) -> Result<impl FnMut(&Event), Box<dyn std::error::Error>> {
+    let server = grpc::GrpcServer::spawn(address, cache, output_root)?;
+    Ok(move |event: &Event| server.publish(event))
+}
+
+#[cfg(not(feature = "grpc"))]
+fn grpc_server(
+    _address: std::net::SocketAddr,
+    _cache: ProductCache,
+    _output_root: &str,
+) -> Result<impl FnMut(&Event), Box<dyn std::error::Error>> {
+    Err::<fn(&Event), _>("goesbox was built without the grpc feature".into())
+}
+
+/// Receives and decodes the live downlink, with the UI
+fn run(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let targets: Vec<String> = args.targets.split(',').map(|t| t.to_string()).collect();
+    let output_root = args.output_root;
+    let events_addr = args.events;
+    let stats_sink = match (args.stats_file, args.stats_socket) {
+        (Some(path), _) => Some(StatsSink::file(path)),
+        (None, Some(path)) => Some(StatsSink::unix_socket(path)?),
+        (None, None) => None,
+    };
+    let mut stats_sink = stats_sink.map(|s| s.interval(Duration::from_secs(args.stats_interval)));
+    let handler_budget = Duration::from_millis(args.handler_budget);
+    let config = Config::resolve(args.config.as_deref())?;
+    let decode_mode = args.decode;
+    let no_write = args.no_write;
+    let once = args.once;
+    if once && !targets.iter().all(|t| t.starts_with(FILE_TARGET)) {
+        return Err("--once only reads file: targets".into());
+    }
+    // before anything is written to the output root
+    if let (Some(output), false) = (&config.output, no_write) {
+        let profile_roots = config.profiles.values().map(|p| p.root.as_str());
+        output
+            .permissions()?
+            .apply(std::iter::once(output_root.as_str()).chain(profile_roots))?;
+    }
+
+    // there's no UI in batch mode, and log messages go to stderr instead
+    let mut terminal = if once {
+        None
+    } else {
+        let stdout = io::stdout().into_raw_mode()?;
+        let backend = TermionBackend::new(stdout);
+        let mut terminal = Terminal::new(backend)?;
+        terminal.clear()?;
+        Some(terminal)
+    };
+
+    // channels for messaging
+    let (s, mut log_receiver) = unbounded();
+    let mut logger = LoggerBuilder::new().with_throttle(LOG_LINES_PER_SECOND);
+    if once {
+        logger = logger.with_stderr();
+        log_receiver = crossbeam_channel::never();
+    } else {
+        logger = logger.with_sink(move |line| {
+            let _ = s.send(line);
+        });
+    }
+    for target in args.debug.iter().flat_map(|targets| targets.split(',')) {
+        logger = logger.with_debug_target(target);
+    }
+    if let Some(path) = &args.log_file {
+        logger = logger.with_file(path)?;
+    }
+    logger.install()?;
+
+    let mut app = App::new()
+        .with_keys(KeyBindings::new(&config.keys)?)
+        .with_decode_mode(decode_mode.unwrap_or(config.decode))
+        .with_preamble(config.preamble)
+        .with_language(config.language.unwrap_or_else(Language::from_env));
+    // gRPC clients fetch products from the cache
+    let cache = (config.cache.as_ref().map(|c| c.products))
+        .or(args.grpc.map(|_| GRPC_CACHE_PRODUCTS))
+        .map(ProductCache::new);
+    if let Some(cache) = &cache {
+        app = app.with_cache(cache.clone());
+    }
+    let ram_only = matches!(&config.cache, Some(c) if c.ram_only);
+    if let Some(mode) = config.mode {
+        app.stats.set_mode(mode);
+    }
+    app.stats.set_clock_skew(config.clock.skew());
+    for schedule in &config.schedule {
+        app.stats.expect(schedule.expected());
+    }
+
+    let mut net = connect(&targets, &mut app.stats);
+    let mut dedup = (targets.len() > 1).then(VcduDedup::default);
+    let emwin_feed = match &config.emwin_feed {
+        Some(feed) => connect_emwin_feed(feed),
+        None => crossbeam_channel::never(),
+    };
+
+    // spawn a thread to handle keyboard input
+    let kbd = if once {
+        crossbeam_channel::never()
+    } else {
+        let (s, kbd) = unbounded();
+        std::thread::spawn(move || {
+            use termion::input::TermRead;
+            let stdin = io::stdin();
+            for evt in stdin.keys() {
+                s.send(evt.unwrap()).unwrap();
+            }
+        });
+        kbd
+    };
+
+    let mut bus = EventBus::new();
+    bus.subscribe(|event: &Event| match event {
+        Event::ImageCompleted(image) => {
+            log::info!("Image complete ({:.0}%): {}", image.completeness(), image.product)
+        }
+        Event::ImageChanged(change) => {
+            log::info!("Image changed ({:.1}%): {}", change.changed_percent, change.product)
+        }
+        Event::StormWatch(storm) if storm.active => {
+            log::warn!("Cold cloud tops over {} ({} pixels)", storm.name, storm.cold_pixels)
+        }
+        _ => {}
+    });
+    // text products are passed back to the app, so the latest warning can be shown
+    let (s, texts_written) = unbounded();
+    bus.subscribe(move |event: &Event| {
+        if let Event::TextWritten(text) = event {
+            let _ = s.send(text.clone());
+        }
+    });
+    // and so are notices from admin messages, so upcoming outages can be shown
+    let (s, admin_notices) = unbounded();
+    bus.subscribe(move |event: &Event| {
+        if let Event::AdminNotice(notice) = event {
+            let _ = s.send(notice.clone());
+        }
+    });
+    if let Some(addr) = &events_addr {
+        let mut sock = Socket::new(Protocol::Pub)?;
+        sock.bind(addr)?;
+        log::info!("Publishing events on {}", addr);
+        bus.subscribe(move |event: &Event| publish_event(&mut sock, event));
+    }
+    if let (Some(address), Some(cache)) = (args.grpc, &cache) {
+        bus.subscribe(grpc_server(address, cache.clone(), &output_root)?);
+        log::info!("Serving gRPC on {}", address);
+    }
+
+    // small products are written in batches, off of this thread, and packed into daily archives
+    // once their day is over
+    let writer = BatchWriter::spawn_with_storage(BatchOptions::default(), product_storage(&config, &output_root))?;
+    let memory_index = MemoryIndex::new();
+    let mut handlers = if ram_only || no_write {
+        // products are only kept in the cache, or only indexed
+        let mut handlers = handlers::Dispatcher::new()
+            .with_sandbox(handlers::SandboxOptions::default())
+            .with_events(bus.sender());
+        if no_write {
+            handlers.push(Box::new(IndexHandler::in_memory(memory_index.clone())));
+        }
+        handlers
+    } else {
+        // some handlers write straight into the root, so it has to be there before the first
+        // batch of writes makes it
+        std::fs::create_dir_all(&output_root)?;
+        app = app.with_dead_letter(DeadLetter::new(&output_root));
+        DailyArchiver::new(&output_root).spawn();
+        let handlers = build_dispatcher(
+            &output_root,
+            Some(bus.sender()),
+            Some(app.stats.dcs.clone()),
+            &writer,
+            &config,
+            &config.format,
+        )
+        .with_dead_letter(DeadLetter::new(&output_root));
+        config.quarantine.apply(handlers, &output_root)
+    }
+    .with_time_budget(handler_budget);
+    if let Some(test_products) = &config.test_products {
+        // with nowhere to write them, test products are dropped even if they'd be kept
+        let root = (!(ram_only || no_write)).then_some(output_root.as_str());
+        handlers = handlers.with_test_products(test_products.filter(root));
+    }
+    if let Some(replay) = &config.replay {
+        let mut filter = replay.filter();
+        // with nowhere to write them, replays are only marked
+        if let (Some(root), false) = (replay.root(&output_root), ram_only || no_write) {
+            std::fs::create_dir_all(&root)?;
+            filter = filter.with_handlers(build_dispatcher(&root, None, None, &writer, &config, &config.format));
+        }
+        handlers = handlers.with_replay_filter(filter);
+    }
+    if let Some(quotas) = &config.quotas {
+        handlers = handlers.with_quotas(quotas.quotas());
+    }
+    if let Some(cache) = cache {
+        handlers.push(Box::new(CacheHandler::new(cache)));
+    }
+    // outages announced on the admin channel are shown in the summary
+    let admin = AdminHandler::new().with_events(bus.sender());
+    handlers.push(Box::new(if ram_only || no_write {
+        admin
+    } else {
+        admin.with_index(&output_root)
+    }));
+    if let Some(rates) = &config.rates {
+        handlers.push(Box::new(rates.handler(bus.sender())));
+    }
+    // these all write files
+    if !no_write {
+        if let Some(board) = &config.board {
+            let board = board.handler();
+            board.write()?;
+            handlers.push(Box::new(board));
+        }
+        if let Some(metar) = &config.metar {
+            handlers.push(Box::new(metar.handler(&output_root)));
+        }
+        if let Some(soundings) = &config.soundings {
+            handlers.push(Box::new(soundings.handler(&output_root)));
+        }
+        if let Some(shef) = &config.shef {
+            handlers.push(Box::new(shef.handler(&output_root)?));
+        }
+        if let Some(drift) = &config.dcs_drift {
+            handlers.push(Box::new(drift.handler(&output_root)));
+        }
+        if let Some(coverage) = &config.dcs_coverage {
+            handlers.push(Box::new(coverage.handler(&output_root)?));
+        }
+        if let Some(swpc) = &config.space_weather {
+            handlers.push(Box::new(swpc.handler(&output_root, bus.sender())));
+        }
+        if let Some(notify) = NotifyConfig::handler(&config.notify) {
+            handlers.push(Box::new(notify.with_zone_map(config.zones.load()?)));
+        }
+        if let Some(raw) = &config.raw {
+            handlers.push(Box::new(
+                raw.handler(&output_root)
+                    .with_storage(product_storage(&config, &output_root)),
+            ));
+        }
+        if let Some(survey) = &config.survey {
+            handlers.push(Box::new(survey.handler(&output_root)));
+        }
+        if let Some(telemetry) = &config.telemetry {
+            handlers.push(Box::new(
+                telemetry.handler(&output_root, app.stats.unknown_codes.clone()),
+            ));
+        }
+        for (name, profile) in &config.profiles {
+            let dispatcher = build_dispatcher(&profile.root, None, None, &writer, &config, &profile.format);
+            handlers.push(Box::new(
+                ProfileHandler::new(name.as_str(), dispatcher).with_route(profile.route()),
+            ));
+            if let Some(retention) = profile.retention() {
+                retention.spawn();
+            }
+        }
+    }
+    // the relay thread stops when this is dropped, at the end of main
+    let relay = config.relay.as_ref().map(|r| r.spawn()).transpose()?;
+    if let Some(relay) = &relay {
+        handlers.push(Box::new(RelayHandler::new(relay.sender())));
+    }
+    // observations are exported from their own thread, which writes what's left once every sender
+    // is dropped
+    let influx = config.influx.as_ref().map(|i| i.spawn()).transpose()?;
+    if let Some((sender, _)) = &influx {
+        handlers.push(Box::new(InfluxHandler::new(sender.clone())));
+        bus.subscribe(sender.clone());
+    }
+    let mut influx_stats = Instant::now();
+    // products are queued on disk as they're written, and uploaded from their own threads
+    if !ram_only && !no_write {
+        for forward in &config.forward {
+            bus.subscribe(forward.spawn(&output_root)?);
+            log::info!("Forwarding products to {}", forward.target);
+        }
+    }
+
+    // SIGTERM (from systemd, or kill) and SIGHUP (a closed terminal) shut down the same way as
+    // pressing 'q'
+    let mut signals = Signals::new([SIGTERM, SIGHUP])?;
+    let (s, signal) = unbounded();
+    std::thread::spawn(move || {
+        for sig in signals.forever() {
+            let _ = s.send(sig);
+        }
+    });
+
+    let mut queue = handlers::DispatchQueue::new();
+    if let Some(vcids) = &config.priority_vcids {
+        queue = queue.with_priority_vcids(vcids.iter().copied());
+    }
+    // the screen is drawn on its own schedule, so a flood of packets doesn't redraw it any more
+    // often (and key presses are drawn right away)
+    let draw_ticker = crossbeam_channel::tick(DRAW_INTERVAL);
+    // LRIT files that a handler failed on, for the summary in batch mode
+    let mut failed = 0;
+    let shutdown_reason = loop {
+        select! {
+            recv(kbd) -> msg => {
+                let msg = msg.unwrap();
+                if !app.key(msg) {
+                    break "quit".to_string();
+                }
+                if let Some(terminal) = &mut terminal {
+                    app.draw(terminal)?;
+                }
+            },
+            recv(draw_ticker) -> _ => {
+                if let (Some(terminal), true) = (&mut terminal, app.needs_draw()) {
+                    app.draw(terminal)?;
+                }
+            },
+            recv(net) -> data => {
+                let data = match data {
+                    Ok((source, Ok(data))) => {
+                        app.record(Stat::SourcePacket(source));
+                        app.record(Stat::Bytes(data.len()));
+                        data
+                    }
+                    Ok((source, Err(e))) => {
+                        let address = app.stats.sources[source].address.clone();
+                        log::error!("Stopped receiving from {}: {}", address, e);
+                        bus.publish(Event::SourceDisconnected(SourceDisconnectedEvent {
+                            address,
+                            error: e.clone(),
+                            time: chrono::Utc::now(),
+                        }));
+                        app.record(Stat::SourceError(source, e));
+                        continue;
+                    }
+                    // every target has stopped, which captures do once they've been read
+                    Err(_) if once => break "end of input".to_string(),
+                    Err(_) => {
+                        net = crossbeam_channel::never();
+                        continue;
+                    }
+                };
+                let vcdu = VCDU::new(&data[..892]);
+                if let Some(dedup) = &mut dedup {
+                    if !dedup.is_new(&vcdu) {
+                        app.record(Stat::DuplicatePacket);
+                        continue;
+                    }
+                }
+
+                let lrits = match std::panic::catch_unwind(AssertUnwindSafe(|| app.process(vcdu))) {
+                    Ok(lrits) => lrits,
+                    Err(panic) => {
+                        write_crash_state(&app, None);
+                        std::panic::resume_unwind(panic);
+                    }
+                };
+                for lrit in lrits {
+                    received(&mut app, &mut bus, &mut queue, lrit);
+                }
+            },
+            recv(emwin_feed) -> lrit => {
+                if let Ok(lrit) = lrit {
+                    received(&mut app, &mut bus, &mut queue, lrit);
+                }
+            },
+            recv(signal) -> sig => {
+                let sig = sig.unwrap();
+                break if sig == SIGTERM { "SIGTERM" } else { "SIGHUP" }.to_string();
+            },
+            recv(log_receiver) -> data => {
+                let data = data.unwrap();
+                app.message(data);
+            },
+        };
+        // EMWIN files are handled right away, but other files wait while VCDUs are still coming
+        // in (with one handled per VCDU, so they don't pile up)
+        while let Some(lrit) = queue.pop_priority() {
+            failed += dispatch_lrit(&mut app, &mut handlers, &mut bus, &lrit);
+        }
+        let batch = if net.is_empty() { queue.len().1 } else { 1 };
+        for lrit in std::iter::from_fn(|| queue.pop()).take(batch) {
+            failed += dispatch_lrit(&mut app, &mut handlers, &mut bus, &lrit);
+        }
+        for text in texts_written.try_iter() {
+            app.text_written(text);
+        }
+        for notice in admin_notices.try_iter() {
+            app.record(Stat::AdminNotice(notice));
+        }
+        app.writer_health = writer.health();
+        if let Some(sink) = &mut stats_sink {
+            if let Err(e) = sink.tick(&app.stats) {
+                log::warn!("Failed to write stats: {}", e);
+            }
+        }
+        if let (Some((sender, _)), Some(config)) = (&influx, &config.influx) {
+            if config.stats && influx_stats.elapsed() >= config.interval() {
+                sender.send(Point::from_stats(&app.stats.snapshot(), chrono::Utc::now()));
+                influx_stats = Instant::now();
+            }
+        }
+    };
+
+    // write out anything that's still in flight: queued LRIT files, partially received images, and
+    // queued writes
+    log::info!("Shutting down ({})", shutdown_reason);
+    while let Some(lrit) = queue.pop() {
+        failed += dispatch_lrit(&mut app, &mut handlers, &mut bus, &lrit);
+    }
+    failed += handlers.flush().len();
+    bus.dispatch();
+    drop(handlers);
+    writer.flush();
+
+    let shutdown = ShutdownEvent {
+        time: chrono::Utc::now(),
+        reason: shutdown_reason,
+        stats: Box::new(app.stats.snapshot()),
+    };
+    if !no_write {
+        let stats_path = std::path::Path::new(&output_root).join("stats.json");
+        if let Err(e) = std::fs::write(&stats_path, serde_json::to_vec_pretty(&shutdown)?) {
+            eprintln!("Failed to save stats to {}: {}", stats_path.display(), e);
+        }
+    }
+    bus.publish(Event::Shutdown(shutdown));
+    drop(bus);
+    if let Some((sender, thread)) = influx {
+        drop(sender);
+        let _ = thread.join();
+    }
+
+    if no_write {
+        // back to a normal terminal, for the summary
+        drop(terminal);
+        print_evaluation(&app.stats, &memory_index);
+    }
+    if once {
+        return finish_batch(&app.stats, failed, writer.failed_writes());
     }
 
     //loop {
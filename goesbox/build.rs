@@ -0,0 +1,58 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::compile();
+}
+
+/// Generates the gRPC service for `proto/goesbox.proto`
+///
+/// The messages are declared by hand in `bin/grpc.rs`, so that building doesn't need protoc.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    fn method(name: &str, route: &str, input: &str, output: &str, streaming: bool) -> Method {
+        let method = Method::builder()
+            .name(name)
+            .route_name(route)
+            .input_type(format!("crate::grpc::{}", input))
+            .output_type(format!("crate::grpc::{}", output))
+            .codec_path("tonic_prost::ProstCodec");
+        if streaming {
+            method.server_streaming().build()
+        } else {
+            method.build()
+        }
+    }
+
+    pub fn compile() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let service = Service::builder()
+            .name("Goesbox")
+            .package("goesbox")
+            .method(method("stream_lrits", "StreamLrits", "StreamLritsRequest", "LritInfo", true))
+            .method(method("get_product", "GetProduct", "GetProductRequest", "Product", false))
+            .method(method(
+                "subscribe_events",
+                "SubscribeEvents",
+                "SubscribeEventsRequest",
+                "EventMessage",
+                true,
+            ))
+            .method(method(
+                "query_index",
+                "QueryIndex",
+                "QueryIndexRequest",
+                "QueryIndexResponse",
+                false,
+            ))
+            .method(method(
+                "get_capabilities",
+                "GetCapabilities",
+                "GetCapabilitiesRequest",
+                "Capabilities",
+                false,
+            ))
+            .build();
+        Builder::new().compile(&[service]);
+    }
+}
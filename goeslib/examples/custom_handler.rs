@@ -0,0 +1,85 @@
+//! Writing your own handler
+//!
+//! A [`Handler`] gets every LRIT file that's decoded, and returns [`HandlerError::Skipped`] for
+//! the ones it isn't interested in (so they don't count as handled).  This one keeps a tally of
+//! EMWIN products by AWIPS ID, and prints each DCS file's name.
+//!
+//! ```text
+//! cargo run --example custom_handler
+//! ```
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, Utc};
+use goeslib::{
+    annotation::LritFilename,
+    handlers::{Dispatcher, Handler, HandlerError},
+    lrit::{VirtualChannel, LRIT, VCDU},
+    sim::Simulator,
+    stats::Stats,
+};
+
+#[derive(Default)]
+struct Tally {
+    emwin: BTreeMap<String, usize>,
+    dcs: usize,
+}
+
+impl Handler for Tally {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let annotation = lrit
+            .headers
+            .annotation
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("annotation"))?;
+        match lrit.headers.primary.filetype_code {
+            2 => {
+                let name = annotation.text.trim_end_matches(".TXT");
+                match LritFilename::parse(name) {
+                    LritFilename::Emwin(emwin) => {
+                        *self.emwin.entry(emwin.legacy_filename).or_default() += 1;
+                        Ok(())
+                    }
+                    _ => Err(HandlerError::Skipped),
+                }
+            }
+            130 => {
+                println!("DCS file {} ({} bytes)", annotation.text, lrit.data.len());
+                self.dcs += 1;
+                Ok(())
+            }
+            _ => Err(HandlerError::Skipped),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        for (name, count) in &self.emwin {
+            println!("{}: {}", name, count);
+        }
+        println!("{} DCS files", self.dcs);
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut handlers = Dispatcher::new();
+    handlers.push(Box::new(Tally::default()));
+
+    let mut stats = Stats::new();
+    let mut channels: HashMap<u8, VirtualChannel> = HashMap::new();
+    let mut sim = Simulator::new().image_size(64, 1);
+    let start = Utc::now();
+    for second in 0..120 {
+        sim.queue_due(start + Duration::seconds(second));
+        while !sim.is_idle() {
+            let frame = sim.next_vcdu();
+            let vcdu = VCDU::new(&frame);
+            let (id, counter) = (vcdu.vcid(), vcdu.counter());
+            let channel = channels.entry(id).or_insert_with(|| VirtualChannel::new(id, counter));
+            for lrit in channel.process_vcdu(vcdu, &mut stats) {
+                handlers.dispatch(&lrit);
+            }
+        }
+    }
+    // the tally is printed when the handler is flushed
+    handlers.flush();
+}
@@ -0,0 +1,55 @@
+//! Parses LRIT product names (annotations), like EMWIN filenames
+//!
+//! Names are taken from the arguments, or one per line from stdin if there aren't any:
+//!
+//! ```text
+//! ls /srv/goes | cargo run --example emwin_names
+//! cargo run --example emwin_names A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT
+//! ```
+use std::io::BufRead;
+
+use goeslib::annotation::LritFilename;
+
+fn describe(name: &str) -> String {
+    // EMWIN names are parsed without their extension
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    match LritFilename::parse(stem) {
+        LritFilename::Emwin(emwin) => format!(
+            "EMWIN {} from {:?}, issued {}, priority {:?}, product {:?}",
+            emwin.legacy_filename,
+            emwin.location,
+            emwin.date.format("%Y-%m-%d %H:%M:%S"),
+            emwin.priority,
+            emwin.nws_product
+        ),
+        LritFilename::GoesR(goes) => format!(
+            "{} {} {} from {}, band {}, scanned {}",
+            goes.instrument,
+            goes.level,
+            goes.product,
+            goes.satellite,
+            goes.band.as_deref().unwrap_or("-"),
+            goes.scan_start.map_or("-".to_string(), |t| t.to_string())
+        ),
+        LritFilename::Himawari(tile) => format!("Himawari tile of {}", tile.scene_name()),
+        LritFilename::Other => "not a recognized name".to_string(),
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        for name in &args {
+            println!("{}: {}", name, describe(name));
+        }
+        return Ok(());
+    }
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let name = line.trim();
+        if !name.is_empty() {
+            println!("{}: {}", name, describe(name));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! A minimal receiver: VCDUs in, products on disk
+//!
+//! This is the same pipeline the goesbox binary runs, without the UI.  VCDUs are fed to a
+//! [`VirtualChannel`] for each VCID, which reassembles them into LRIT files, and every LRIT file is
+//! passed to a [`Dispatcher`] full of handlers that write products under the output root.
+//!
+//! The VCDUs come from the [`Simulator`] here, so no antenna is needed; a real receiver would read
+//! them from goesrecv instead.
+//!
+//! ```text
+//! cargo run --example pipeline /tmp/goes
+//! ```
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use goeslib::{
+    handlers::{Dispatcher, ImageHandler, TextHandler},
+    index::IndexHandler,
+    lrit::{VirtualChannel, VCDU},
+    sim::Simulator,
+    stats::Stats,
+};
+
+fn main() {
+    let output_root = std::env::args().nth(1).expect("usage: pipeline <output root>");
+    std::fs::create_dir_all(&output_root).expect("failed to create the output root");
+
+    let mut handlers = Dispatcher::new();
+    handlers.push(Box::new(TextHandler::new(&output_root)));
+    handlers.push(Box::new(ImageHandler::new(&output_root).with_index(&output_root)));
+    handlers.push(Box::new(IndexHandler::new(&output_root)));
+
+    let mut stats = Stats::new();
+    let mut channels: HashMap<u8, VirtualChannel> = HashMap::new();
+    let mut products = 0;
+
+    // two minutes of simulated downlink, with an image every minute
+    let mut sim = Simulator::new().image_size(256, 4).image_interval(Duration::minutes(1));
+    let start = Utc::now();
+    for second in 0..120 {
+        sim.queue_due(start + Duration::seconds(second));
+        while !sim.is_idle() {
+            let frame = sim.next_vcdu();
+            let vcdu = VCDU::new(&frame);
+            if vcdu.is_fill() {
+                continue;
+            }
+            let (id, counter) = (vcdu.vcid(), vcdu.counter());
+            let channel = channels.entry(id).or_insert_with(|| VirtualChannel::new(id, counter));
+            for lrit in channel.process_vcdu(vcdu, &mut stats) {
+                products += 1;
+                for failure in handlers.dispatch(&lrit) {
+                    eprintln!("{} failed: {}", failure.handler, failure.error);
+                }
+            }
+        }
+    }
+    handlers.flush();
+
+    println!("Decoded {} LRIT files into {}", products, output_root);
+}
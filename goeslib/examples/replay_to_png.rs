@@ -0,0 +1,127 @@
+//! Replays a raw capture (VCDUs back to back, like `goesbox-ui` records) and saves every image in
+//! it as a PNG
+//!
+//! Segments are put together into whole images, and an image is saved once all of its segments
+//! have arrived.  Images that are still missing segments at the end of the capture are saved
+//! anyway, with black bands where the missing segments go.
+//!
+//! ```text
+//! cargo run --example replay_to_png capture.raw /tmp/pngs
+//! ```
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use goeslib::{
+    capture::CaptureReader,
+    lrit::{VirtualChannel, LRIT, VCDU},
+    stats::Stats,
+};
+use image::GrayImage;
+
+/// An image whose segments are still arriving
+struct Partial {
+    name: String,
+    image: GrayImage,
+    remaining: u16,
+}
+
+struct Assembler {
+    out_dir: PathBuf,
+    images: HashMap<u16, Partial>,
+}
+
+impl Assembler {
+    /// Adds an LRIT file to the image it's a segment of, saving the image if it's complete
+    fn add(&mut self, lrit: &LRIT) -> image::ImageResult<()> {
+        let (structure, annotation) = match (&lrit.headers.img_strucutre, &lrit.headers.annotation) {
+            (Some(structure), Some(annotation)) if structure.bits_per_pixel == 8 => (structure, annotation),
+            _ => return Ok(()),
+        };
+        let segment = match &lrit.headers.img_segment {
+            Some(segment) => segment.clone(),
+            None => {
+                // the whole image is in this one file
+                let image = gray_image(structure.num_columns, structure.num_lines, &lrit.data);
+                return self.save(&annotation.text, &image);
+            }
+        };
+        let partial = self.images.entry(segment.image_id).or_insert_with(|| Partial {
+            name: annotation.text.clone(),
+            image: GrayImage::new(segment.max_column as u32, segment.max_row as u32),
+            remaining: segment.max_segment,
+        });
+        let piece = gray_image(structure.num_columns, structure.num_lines, &lrit.data);
+        image::imageops::replace(
+            &mut partial.image,
+            &piece,
+            segment.start_col as i64,
+            segment.start_line as i64,
+        );
+        partial.remaining = partial.remaining.saturating_sub(1);
+        if partial.remaining == 0 {
+            let partial = self.images.remove(&segment.image_id).unwrap();
+            self.save(&partial.name, &partial.image)?;
+        }
+        Ok(())
+    }
+
+    /// Saves every image that's still missing segments
+    fn finish(&mut self) -> image::ImageResult<()> {
+        for (_, partial) in std::mem::take(&mut self.images) {
+            eprintln!("{} is missing {} segments", partial.name, partial.remaining);
+            self.save(&partial.name, &partial.image)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self, name: &str, image: &GrayImage) -> image::ImageResult<()> {
+        let name = Path::new(name).file_stem().unwrap_or_default();
+        let path = self.out_dir.join(name).with_extension("png");
+        image.save(&path)?;
+        println!("Saved {}", path.display());
+        Ok(())
+    }
+}
+
+/// An 8 bit image from its pixels, padding or truncating `data` to fit
+fn gray_image(columns: u16, lines: u16, data: &[u8]) -> GrayImage {
+    let mut pixels = data.to_vec();
+    pixels.resize(columns as usize * lines as usize, 0);
+    GrayImage::from_raw(columns as u32, lines as u32, pixels).unwrap()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let (capture, out_dir) = match (args.next(), args.next()) {
+        (Some(capture), Some(out_dir)) => (capture, PathBuf::from(out_dir)),
+        _ => return Err("usage: replay_to_png <capture> <output dir>".into()),
+    };
+    std::fs::create_dir_all(&out_dir)?;
+
+    let mut assembler = Assembler {
+        out_dir,
+        images: HashMap::new(),
+    };
+    let mut stats = Stats::new();
+    let mut channels: HashMap<u8, VirtualChannel> = HashMap::new();
+    for frame in CaptureReader::new(BufReader::new(File::open(capture)?)) {
+        let frame = frame?;
+        let vcdu = VCDU::new(&frame);
+        if vcdu.is_fill() {
+            continue;
+        }
+        let (id, counter) = (vcdu.vcid(), vcdu.counter());
+        let channel = channels.entry(id).or_insert_with(|| VirtualChannel::new(id, counter));
+        for lrit in channel.process_vcdu(vcdu, &mut stats) {
+            if lrit.headers.primary.filetype_code == 0 {
+                assembler.add(&lrit)?;
+            }
+        }
+    }
+    assembler.finish()?;
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! Arbitrary LRIT files, sent as valid TP_PDUs and VCDUs
+//!
+//! Random VCDUs almost never have a TP_PDU with a good CRC, so this gets much further into the
+//! session layer (and header parsing) than the other targets.  The input is split on `0xff`
+//! into one file per APID, and a file starting with `0xfe` is sent as rice compressed TP_PDUs (one
+//! per `0xfe`-separated chunk), like an image.
+#![no_main]
+use goeslib::{
+    lrit::{VirtualChannel, VCDU},
+    sim::Transmitter,
+    stats::Stats,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut tx = Transmitter::new();
+    for (apid, file) in data.split(|b| *b == 0xff).filter(|f| !f.is_empty()).enumerate().take(8) {
+        let apid = apid as u16;
+        match file.split_first() {
+            Some((0xfe, rest)) => {
+                let mut packets = rest.split(|b| *b == 0xfe).map(|p| p.to_vec());
+                let headers = packets.next().unwrap_or_default();
+                tx.send_packets(13, apid, &headers, &packets.collect::<Vec<_>>());
+            }
+            _ => tx.send(13, apid, file),
+        }
+    }
+
+    let mut stats = Stats::new();
+    let mut vc = VirtualChannel::new(13, 0);
+    while !tx.is_idle() {
+        let frame = tx.next_vcdu();
+        let vcdu = VCDU::new(&frame);
+        if !vcdu.is_fill() {
+            vc.process_vcdu(vcdu, &mut stats);
+        }
+    }
+});
@@ -0,0 +1,16 @@
+//! A single VCDU, as if it had been received right after the one before it
+#![no_main]
+use goeslib::{
+    lrit::{VirtualChannel, VCDU},
+    stats::Stats,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: [u8; 892]| {
+    let vcdu = VCDU::new(&data);
+    if vcdu.is_fill() {
+        return;
+    }
+    let mut vc = VirtualChannel::new(vcdu.vcid(), vcdu.counter().wrapping_sub(1) & 0xff_ffff);
+    vc.process_vcdu(vcdu, &mut Stats::new());
+});
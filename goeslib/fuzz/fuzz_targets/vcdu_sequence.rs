@@ -0,0 +1,23 @@
+//! A sequence of VCDUs, each passed to the virtual channel it's for (like goesbox does)
+#![no_main]
+use std::collections::HashMap;
+
+use goeslib::{
+    lrit::{VirtualChannel, VCDU},
+    stats::Stats,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut stats = Stats::new();
+    let mut vcs = HashMap::new();
+    for frame in data.chunks_exact(892) {
+        let vcdu = VCDU::new(frame);
+        if vcdu.is_fill() {
+            continue;
+        }
+        vcs.entry(vcdu.vcid())
+            .or_insert_with(|| VirtualChannel::new(vcdu.vcid(), vcdu.counter()))
+            .process_vcdu(vcdu, &mut stats);
+    }
+});
@@ -0,0 +1,323 @@
+//! Administrative messages, and the outages they announce
+//!
+//! The admin virtual channel (VCID 0) carries text messages from the operators, which are sent
+//! over and over until they expire.  Most of them announce an outage or a schedule change, and
+//! follow the same "Key: value" layout as the NOAA satellite notices:
+//!
+//! ```text
+//! Topic: GOES-East HRIT/EMWIN Outage
+//! Date/Time Issued: May 3, 2022 1800 UTC
+//! Product(s) or Data Impacted: HRIT/EMWIN
+//! Date/Time of Initial Impact: May 10, 2022 1400 UTC
+//! Date/Time of Expected End: May 10, 2022 1600 UTC
+//! Details/Specifics of Change: The HRIT/EMWIN broadcast will be unavailable during
+//! antenna maintenance.
+//! ```
+//!
+//! An [`AdminNotice`] is parsed from a message like that, and an [`OutageSchedule`] keeps the
+//! notices whose outage hasn't ended yet.
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Notices that say when an outage starts, but not when it ends, are dropped this long after it
+/// starts
+const OPEN_ENDED: Duration = Duration::hours(24);
+
+/// The formats of the times in notices, after [`parse_time`] has cleaned them up
+const FORMATS: [&str; 6] = [
+    "%B %d %Y %H%M",
+    "%b %d %Y %H%M",
+    "%Y-%m-%d %H%M",
+    "%Y/%m/%d %H%M",
+    "%m/%d/%Y %H%M",
+    "%d %B %Y %H%M",
+];
+
+/// A parsed administrative message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminNotice {
+    /// Like "GOES-East HRIT/EMWIN Outage"
+    pub topic: Option<String>,
+    pub issued: Option<DateTime<Utc>>,
+    /// The products or services that are affected, like "HRIT/EMWIN"
+    pub impacted: Option<String>,
+    /// The satellites that are affected, like "GOES-16"
+    pub satellites: Option<String>,
+    /// When the outage (or change) starts
+    pub start: Option<DateTime<Utc>>,
+    /// When it's expected to end
+    pub end: Option<DateTime<Utc>>,
+    pub details: Option<String>,
+}
+
+/// The fields of a notice, recognized by words in their key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Topic,
+    Issued,
+    Impacted,
+    Satellites,
+    Start,
+    End,
+    Details,
+    /// Something else, like "Length of Event" or "Contact Information"
+    Other,
+}
+
+impl Key {
+    fn parse(key: &str) -> Key {
+        let key = key.to_ascii_lowercase();
+        if key.starts_with("topic") || key == "subject" {
+            Key::Topic
+        } else if key.contains("issued") {
+            Key::Issued
+        } else if key.contains("initial impact") || key.contains("start") || key.contains("begin") {
+            Key::Start
+        } else if key.contains("end") {
+            Key::End
+        } else if key.starts_with("satellite") {
+            Key::Satellites
+        } else if key.contains("impacted") || key.contains("affected") {
+            Key::Impacted
+        } else if key.starts_with("details") || key.starts_with("description") {
+            Key::Details
+        } else {
+            Key::Other
+        }
+    }
+}
+
+impl AdminNotice {
+    /// Parses a notice from the text of an admin message
+    ///
+    /// Returns `None` unless the message has a topic or a start time, since messages without
+    /// either aren't notices.
+    pub fn parse(text: &str) -> Option<AdminNotice> {
+        let mut notice = AdminNotice {
+            topic: None,
+            issued: None,
+            impacted: None,
+            satellites: None,
+            start: None,
+            end: None,
+            details: None,
+        };
+        // the field that lines without a key of their own continue
+        let mut current = Key::Other;
+        for line in text.lines().map(str::trim) {
+            let (key, value) = match line.split_once(':') {
+                // times like "1400 UTC" don't have colons, but "14:00 UTC" do
+                Some((key, value)) if !key.is_empty() && !key.bytes().all(|b| b.is_ascii_digit()) => {
+                    (Key::parse(key.trim()), value.trim())
+                }
+                _ => {
+                    if current == Key::Details && !line.is_empty() {
+                        let details = notice.details.get_or_insert_with(String::new);
+                        if !details.is_empty() {
+                            details.push(' ');
+                        }
+                        details.push_str(line);
+                    }
+                    continue;
+                }
+            };
+            current = key;
+            let text = Some(value.to_string()).filter(|v| !v.is_empty());
+            match key {
+                Key::Topic => notice.topic = text,
+                Key::Issued => notice.issued = parse_time(value),
+                Key::Impacted => notice.impacted = text,
+                Key::Satellites => notice.satellites = text,
+                Key::Start => notice.start = parse_time(value),
+                Key::End => notice.end = parse_time(value),
+                Key::Details => notice.details = text,
+                Key::Other => {}
+            }
+        }
+        if notice.topic.is_none() && notice.start.is_none() {
+            return None;
+        }
+        Some(notice)
+    }
+
+    /// When the notice stops mattering: when the outage ends, or a while after it starts if it
+    /// doesn't say when it ends
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.end.or_else(|| Some(self.start? + OPEN_ENDED))
+    }
+
+    /// Returns true if the outage has started, and hasn't ended, as of `now`
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.start, Some(start) if start <= now) && self.expires().is_none_or(|end| now < end)
+    }
+
+    /// A one line summary, like "GOES-East HRIT/EMWIN Outage, 05-10 14:00 to 16:00 UTC"
+    pub fn summary(&self) -> String {
+        let topic = self.topic.as_deref().unwrap_or("Outage");
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start.date_naive() == end.date_naive() => format!(
+                "{}, {} to {} UTC",
+                topic,
+                start.format("%m-%d %H:%M"),
+                end.format("%H:%M")
+            ),
+            (Some(start), Some(end)) => format!(
+                "{}, {} to {} UTC",
+                topic,
+                start.format("%m-%d %H:%M"),
+                end.format("%m-%d %H:%M")
+            ),
+            (Some(start), None) => format!("{}, from {} UTC", topic, start.format("%m-%d %H:%M")),
+            (None, _) => topic.to_string(),
+        }
+    }
+}
+
+/// Parses a time like "May 10, 2022 1400 UTC", "2022-05-10 14:00Z", or "05/10/2022 1400"
+///
+/// Returns `None` for anything else (including "TBD").
+fn parse_time(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.replace([',', ':'], " ");
+    let mut words: Vec<&str> = s.split_whitespace().collect();
+    if matches!(words.last(), Some(&("UTC" | "GMT" | "Z"))) {
+        words.pop();
+    }
+    // like "1400Z"
+    if let Some(last) = words.last_mut() {
+        *last = last.trim_end_matches('Z');
+    }
+    // like "14 00", from "14:00"
+    if let [date @ .., hours, minutes] = words.as_slice() {
+        let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if minutes.len() == 2 && hours.len() <= 2 && digits(hours) && digits(minutes) {
+            let time = format!("{:0>2}{}", hours, minutes);
+            let words: Vec<&str> = date.iter().copied().chain(std::iter::once(time.as_str())).collect();
+            return parse_words(&words);
+        }
+    }
+    parse_words(&words)
+}
+
+fn parse_words(words: &[&str]) -> Option<DateTime<Utc>> {
+    let s = words.join(" ");
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&s, format).ok())
+        .map(|time| Utc.from_utc_datetime(&time))
+}
+
+/// The notices whose outage hasn't ended yet, see the [module docs](self)
+///
+/// Messages are sent over and over, so a notice that's already known (with the same topic and
+/// start) replaces the one from before.
+#[derive(Debug, Clone, Default)]
+pub struct OutageSchedule {
+    notices: Vec<AdminNotice>,
+}
+
+impl OutageSchedule {
+    pub fn new() -> OutageSchedule {
+        OutageSchedule::default()
+    }
+
+    /// Adds a notice, returning false if it was already known
+    pub fn add(&mut self, notice: AdminNotice) -> bool {
+        match self
+            .notices
+            .iter_mut()
+            .find(|n| n.topic == notice.topic && n.start == notice.start)
+        {
+            Some(known) if *known == notice => false,
+            Some(known) => {
+                *known = notice;
+                true
+            }
+            None => {
+                self.notices.push(notice);
+                true
+            }
+        }
+    }
+
+    /// Forgets about outages that ended before `now`
+    pub fn prune(&mut self, now: DateTime<Utc>) {
+        self.notices.retain(|n| n.expires().is_none_or(|end| now < end));
+    }
+
+    /// The outages that are going on, or are still to come, as of `now`, soonest first
+    ///
+    /// Notices without a start time are left out.
+    pub fn upcoming(&self, now: DateTime<Utc>) -> Vec<AdminNotice> {
+        let mut upcoming: Vec<_> = self
+            .notices
+            .iter()
+            .filter(|n| n.start.is_some() && n.expires().is_none_or(|end| now < end))
+            .cloned()
+            .collect();
+        upcoming.sort_by_key(|n| n.start);
+        upcoming
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{AdminNotice, OutageSchedule};
+
+    #[test]
+    fn test_admin_notice() {
+        let text = "NOAA/NESDIS Office of Satellite and Product Operations\r\n\
+                    Topic: GOES-East HRIT/EMWIN Outage\r\n\
+                    Date/Time Issued: May 3, 2022 1800 UTC\r\n\
+                    Product(s) or Data Impacted: HRIT/EMWIN\r\n\
+                    Date/Time of Initial Impact: May 10, 2022 1400 UTC\r\n\
+                    Date/Time of Expected End: 2022-05-10 16:00Z\r\n\
+                    Length of Event: 2 hours\r\n\
+                    Satellite(s) Impacted: GOES-16\r\n\
+                    Details/Specifics of Change: The broadcast will be unavailable\r\n\
+                    during antenna maintenance.\r\n\
+                    \r\n\
+                    Contact Information: ESPC Help Desk\r\n";
+        let notice = AdminNotice::parse(text).unwrap();
+        let start = Utc.with_ymd_and_hms(2022, 5, 10, 14, 0, 0).unwrap();
+        assert_eq!(notice.topic.as_deref(), Some("GOES-East HRIT/EMWIN Outage"));
+        assert_eq!(notice.issued, Some(Utc.with_ymd_and_hms(2022, 5, 3, 18, 0, 0).unwrap()));
+        assert_eq!(notice.impacted.as_deref(), Some("HRIT/EMWIN"));
+        assert_eq!(notice.satellites.as_deref(), Some("GOES-16"));
+        assert_eq!(notice.start, Some(start));
+        assert_eq!(notice.end, Some(start + Duration::hours(2)));
+        assert_eq!(
+            notice.details.as_deref(),
+            Some("The broadcast will be unavailable during antenna maintenance.")
+        );
+        assert_eq!(
+            notice.summary(),
+            "GOES-East HRIT/EMWIN Outage, 05-10 14:00 to 16:00 UTC"
+        );
+        assert!(notice.is_active(start + Duration::minutes(30)));
+        assert!(!notice.is_active(start + Duration::hours(3)));
+        assert!(AdminNotice::parse("Welcome to the GOES HRIT broadcast").is_none());
+
+        let mut schedule = OutageSchedule::new();
+        assert!(schedule.add(notice.clone()));
+        // the same message again
+        assert!(!schedule.add(notice.clone()));
+        // an update, with a new end time
+        let extended = AdminNotice {
+            end: Some(start + Duration::hours(4)),
+            ..notice.clone()
+        };
+        assert!(schedule.add(extended.clone()));
+        let open_ended = AdminNotice {
+            topic: Some("Schedule change".to_string()),
+            start: Some(start - Duration::hours(1)),
+            end: None,
+            ..notice
+        };
+        assert!(schedule.add(open_ended.clone()));
+        assert_eq!(schedule.upcoming(start), vec![open_ended, extended.clone()]);
+        schedule.prune(start + Duration::hours(24));
+        assert!(schedule.upcoming(start).is_empty());
+    }
+}
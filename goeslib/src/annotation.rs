@@ -0,0 +1,291 @@
+//! Structured parsing of annotation records
+//!
+//! The annotation record of an LRIT file holds the name of the product it carries, and for most
+//! products that name follows a well known pattern that says what the data is, which satellite it
+//! came from, when it was made, and (for tiled imagery) which piece of the whole it is.  Handlers
+//! should route on [`LritFilename`] rather than matching on prefixes of the raw text.
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+use crate::emwin::{EmwinNameError, ParsedEmwinName};
+
+/// The parsed form of an annotation record
+#[derive(Debug)]
+pub enum LritFilename {
+    /// A GOES-R series product, like `OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e…_c….lrit`
+    GoesR(GoesRFilename),
+    /// An EMWIN text product, like `A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS`
+    Emwin(ParsedEmwinName),
+    /// One tile of a Himawari image, like `IMG_DK01B13_202205041800_003.lrit`
+    Himawari(HimawariTileName),
+    /// Anything else
+    Other,
+}
+
+impl LritFilename {
+    pub fn parse(text: &str) -> LritFilename {
+        if text.starts_with("A_") || text.starts_with("Z_") {
+            if let Ok(emwin) = ParsedEmwinName::parse(text) {
+                return LritFilename::Emwin(emwin);
+            }
+        } else if let Some(tile) = HimawariTileName::parse(text) {
+            return LritFilename::Himawari(tile);
+        } else if let Some(goes) = GoesRFilename::parse(text) {
+            return LritFilename::GoesR(goes);
+        }
+        LritFilename::Other
+    }
+
+    /// The satellite that produced this data, if known (like "G16")
+    pub fn satellite(&self) -> Option<&str> {
+        match self {
+            LritFilename::GoesR(g) => Some(&g.satellite),
+            _ => None,
+        }
+    }
+
+    /// The observation time (for imagery) or issue time (for text)
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        match self {
+            LritFilename::GoesR(g) => g.scan_start,
+            LritFilename::Emwin(e) => Some(e.date),
+            LritFilename::Himawari(h) => NaiveDate::parse_from_str(&h.time[..8], "%Y%m%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(h.time[8..10].parse().ok()?, h.time[10..12].parse().ok()?, 0))
+                .map(|t| Utc.from_utc_datetime(&t)),
+            LritFilename::Other => None,
+        }
+    }
+
+    /// The segment (or tile) number, for products that are split across several files
+    pub fn segment(&self) -> Option<u16> {
+        match self {
+            LritFilename::Himawari(h) => Some(h.tile),
+            _ => None,
+        }
+    }
+}
+
+/// The parts of a GOES-R product filename
+///
+/// Ref: GOES-R Product Definition and Users' Guide, Volume 3, Appendix A
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoesRFilename {
+    /// System environment, like "OR" (operational, real time)
+    pub environment: String,
+    /// Instrument, like "ABI"
+    pub instrument: String,
+    /// Processing level, like "L2"
+    pub level: String,
+    /// Product and scan region, like "CMIPF"
+    pub product: String,
+    /// ABI scan mode, like 6
+    pub mode: Option<u8>,
+    /// ABI band, like "C13"
+    pub band: Option<String>,
+    /// Satellite identifier, like "G16"
+    pub satellite: String,
+    pub scan_start: Option<DateTime<Utc>>,
+    pub scan_end: Option<DateTime<Utc>>,
+    pub created: Option<DateTime<Utc>>,
+}
+
+impl GoesRFilename {
+    /// Parses a name like `OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit`
+    ///
+    /// Only the environment, product description and satellite are required.
+    pub fn parse(name: &str) -> Option<GoesRFilename> {
+        let stem = name.split('.').next()?;
+        let mut fields = stem.split('_');
+
+        let environment = fields.next()?;
+        if environment.len() != 2 || !environment.bytes().all(|b| b.is_ascii_uppercase()) {
+            return None;
+        }
+
+        // e.g. ABI-L2-CMIPF-M6C13
+        let mut desc = fields.next()?.split('-');
+        let instrument = desc.next()?;
+        let level = desc.next()?;
+        let product = desc.next()?;
+        let (mode, band) = match desc.next() {
+            Some(mode_band) => parse_mode_band(mode_band),
+            None => (None, None),
+        };
+
+        let satellite = fields.next()?;
+        if satellite.len() != 3 || !satellite.starts_with('G') {
+            return None;
+        }
+
+        let mut goes = GoesRFilename {
+            environment: environment.to_string(),
+            instrument: instrument.to_string(),
+            level: level.to_string(),
+            product: product.to_string(),
+            mode,
+            band,
+            satellite: satellite.to_string(),
+            scan_start: None,
+            scan_end: None,
+            created: None,
+        };
+        for field in fields {
+            if let Some(t) = field.strip_prefix('s') {
+                goes.scan_start = parse_scan_time(t);
+            } else if let Some(t) = field.strip_prefix('e') {
+                goes.scan_end = parse_scan_time(t);
+            } else if let Some(t) = field.strip_prefix('c') {
+                goes.created = parse_scan_time(t);
+            }
+        }
+        Some(goes)
+    }
+
+    /// The scan region, from the last letters of the product name
+    pub fn region(&self) -> Option<&'static str> {
+        let p = &self.product;
+        if p.ends_with("M1") {
+            Some("Mesoscale 1")
+        } else if p.ends_with("M2") {
+            Some("Mesoscale 2")
+        } else if p.ends_with('F') {
+            Some("Full Disk")
+        } else if p.ends_with('C') {
+            Some("CONUS")
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses the mode and band part of a product description, like `M6C13`
+fn parse_mode_band(s: &str) -> (Option<u8>, Option<String>) {
+    let rest = match s.strip_prefix('M') {
+        Some(rest) => rest,
+        None => return (None, None),
+    };
+    let (mode, band) = match rest.find('C') {
+        Some(c) => (&rest[..c], Some(&rest[c..])),
+        None => (rest, None),
+    };
+    (mode.parse().ok(), band.filter(|b| b.len() == 3).map(|b| b.to_string()))
+}
+
+/// Parses a scan time like `20221241800205` (year, day of year, hour, minute, second, tenths)
+fn parse_scan_time(s: &str) -> Option<DateTime<Utc>> {
+    if s.len() < 13 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year = s[0..4].parse().ok()?;
+    let doy = s[4..7].parse().ok()?;
+    let hour = s[7..9].parse().ok()?;
+    let min = s[9..11].parse().ok()?;
+    let sec = s[11..13].parse().ok()?;
+    let tenths: u32 = s.get(13..14).and_then(|t| t.parse().ok()).unwrap_or(0);
+
+    let naive = NaiveDate::from_yo_opt(year, doy)?.and_hms_milli_opt(hour, min, sec, tenths * 100)?;
+    Some(Utc.from_utc_datetime(&naive))
+}
+
+/// The parts of a Himawari tile annotation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HimawariTileName {
+    /// The region and channel, like `DK01B13`
+    pub channel: String,
+    /// Scene time, as `YYYYMMDDhhmm`
+    pub time: String,
+    /// Tile number, starting at 1
+    pub tile: u16,
+}
+
+impl HimawariTileName {
+    /// Parses an annotation like `IMG_DK01B13_202205041800_003.lrit`
+    pub fn parse(annotation: &str) -> Option<HimawariTileName> {
+        let stem = annotation.split('.').next()?;
+        let mut parts = stem.split('_');
+        if parts.next()? != "IMG" {
+            return None;
+        }
+        let channel = parts.next()?;
+        let time = parts.next()?;
+        let tile = parts.next()?;
+        if parts.next().is_some() || !channel.starts_with("DK") {
+            return None;
+        }
+        if time.len() != 12 || !time.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(HimawariTileName {
+            channel: channel.to_string(),
+            time: time.to_string(),
+            tile: tile.parse().ok()?,
+        })
+    }
+
+    /// The name of the complete scene (without the tile number)
+    pub fn scene_name(&self) -> String {
+        format!("IMG_{}_{}", self.channel, self.time)
+    }
+}
+
+/// Returns true if this annotation belongs to a Himawari tile
+pub fn is_himawari_tile(annotation: &str) -> bool {
+    HimawariTileName::parse(annotation).is_some()
+}
+
+/// Returns true if this is named like an EMWIN product, even one whose WMO heading has codes the
+/// parser doesn't know
+pub fn is_emwin_name(name: &str) -> bool {
+    !matches!(ParsedEmwinName::parse(name), Err(EmwinNameError::NotEmwin))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Timelike};
+
+    use super::LritFilename;
+
+    #[test]
+    fn test_parse() {
+        let name =
+            LritFilename::parse("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit");
+        let goes = match &name {
+            LritFilename::GoesR(goes) => goes,
+            x => panic!("{:?}", x),
+        };
+        assert_eq!(
+            (goes.instrument.as_str(), goes.level.as_str(), goes.product.as_str()),
+            ("ABI", "L2", "CMIPF")
+        );
+        assert_eq!((goes.mode, goes.band.as_deref()), (Some(6), Some("C13")));
+        assert_eq!(goes.region(), Some("Full Disk"));
+        assert_eq!(name.satellite(), Some("G16"));
+        let end = goes.scan_end.unwrap();
+        assert_eq!((end.hour(), end.minute(), end.second()), (18, 9, 51));
+        assert!(goes.created.unwrap() > end);
+
+        let name = LritFilename::parse("A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.lrit");
+        assert!(matches!(name, LritFilename::Emwin(_)));
+        assert_eq!(
+            name.date().unwrap().naive_utc().date(),
+            NaiveDate::from_ymd_opt(2022, 5, 7).unwrap()
+        );
+
+        let name = LritFilename::parse("IMG_DK01B13_202205041800_003.lrit");
+        assert_eq!(name.segment(), Some(3));
+        assert_eq!(name.date().unwrap().hour(), 18);
+
+        // things that only look a bit like known names
+        for text in [
+            "A_short",
+            "Z_",
+            "some_other_image.lrit",
+            "OR_ABI",
+            "pH-22124180000-A.dcs",
+            "",
+        ] {
+            assert!(matches!(LritFilename::parse(text), LritFilename::Other), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Packing old small products into daily archives
+//!
+//! EMWIN and other text products are tiny, but there are tens of thousands of them a day, which
+//! adds up to a lot of inodes (and slow directory listings) on a long running receiver.  Once a day
+//! is over, its small products are packed into `<output root>/archive/YYYY-MM-DD.tar.zst`, and the
+//! [`ProductIndex`] is updated to say which archive each product went into.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use log::{info, warn};
+
+use crate::{
+    handlers::strip_compressed_ext,
+    index::{IndexRecord, ProductIndex},
+};
+
+/// What happened when packing a day
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// The archive that was written, if there was anything to pack
+    pub path: Option<PathBuf>,
+    /// How many files were packed
+    pub packed: usize,
+    /// Total size of the packed files (before compression)
+    pub bytes: u64,
+    /// Indexed products that couldn't be found on disk
+    pub missing: usize,
+}
+
+/// What [`DailyArchiver::verify_day`] found
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifySummary {
+    pub records: usize,
+    /// Index lines that couldn't be parsed
+    pub bad_lines: usize,
+    /// Records of products that were packed into an archive
+    pub archived: usize,
+    /// Archives that couldn't be read all the way through, and why
+    pub bad_archives: Vec<(String, String)>,
+    /// Archived products that aren't in their archive, as (archive, annotation)
+    pub missing: Vec<(String, String)>,
+}
+
+impl VerifySummary {
+    pub fn is_ok(&self) -> bool {
+        self.bad_lines == 0 && self.bad_archives.is_empty() && self.missing.is_empty()
+    }
+}
+
+pub struct DailyArchiver {
+    root: PathBuf,
+    index: ProductIndex,
+    /// Only products with these file type codes are packed
+    filetypes: Vec<u8>,
+    level: i32,
+}
+
+impl DailyArchiver {
+    /// Packs text (type 2) and DCS (type 130) products, with the default zstd level
+    pub fn new(root: impl AsRef<Path>) -> DailyArchiver {
+        DailyArchiver {
+            root: root.as_ref().to_path_buf(),
+            index: ProductIndex::new(root),
+            filetypes: vec![2, 130],
+            level: 0,
+        }
+    }
+
+    pub fn filetypes(mut self, filetypes: Vec<u8>) -> Self {
+        self.filetypes = filetypes;
+        self
+    }
+
+    /// The zstd compression level (0 means the zstd default)
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    fn archive_dir(&self) -> PathBuf {
+        self.root.join("archive")
+    }
+
+    /// Packs the small products received on `date`
+    ///
+    /// Products that are already in an archive are skipped, so this is safe to run more than once
+    /// for the same day.  Files that a `latest-*` symlink points at are left in place.
+    pub fn pack_day(&self, date: NaiveDate) -> io::Result<ArchiveSummary> {
+        let mut records = self.index.read_day(date)?;
+        let linked = self.latest_targets()?;
+
+        let mut summary = ArchiveSummary::default();
+        // the same product can be received (and indexed) more than once
+        let mut files: HashMap<String, Option<PathBuf>> = HashMap::new();
+        for rec in records.iter().filter(|r| self.should_pack(r)) {
+            if files.contains_key(&rec.annotation) {
+                continue;
+            }
+            let found = self.find_product(&rec.annotation);
+            if found.is_none() {
+                summary.missing += 1;
+            }
+            files.insert(rec.annotation.clone(), found.filter(|p| !linked.contains(p)));
+        }
+        let mut to_pack: Vec<_> = files.values().filter_map(|f| f.as_ref()).collect();
+        if to_pack.is_empty() {
+            return Ok(summary);
+        }
+        to_pack.sort();
+
+        std::fs::create_dir_all(self.archive_dir())?;
+        let path = self.next_archive_path(date);
+        let partial = path.with_extension("part");
+        {
+            let encoder = zstd::Encoder::new(File::create(&partial)?, self.level)?;
+            let mut tar = tar::Builder::new(encoder);
+            for file in &to_pack {
+                let name = file.file_name().expect("products are always files");
+                tar.append_path_with_name(file, name)?;
+                summary.bytes += file.metadata()?.len();
+            }
+            tar.into_inner()?.finish()?.sync_all()?;
+        }
+        std::fs::rename(&partial, &path)?;
+        summary.packed = to_pack.len();
+
+        let archive_name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        for rec in records.iter_mut() {
+            if self.should_pack(rec) && matches!(files.get(&rec.annotation), Some(Some(_))) {
+                rec.archive = archive_name.clone();
+            }
+        }
+        self.index.write_day(date, &records)?;
+
+        for file in to_pack {
+            if let Err(e) = std::fs::remove_file(file) {
+                warn!("Failed to remove archived file {}: {}", file.display(), e);
+            }
+        }
+        summary.path = Some(path);
+        Ok(summary)
+    }
+
+    /// Reads a product back out of the archive it was packed into
+    ///
+    /// Returns `None` if the record hasn't been archived, or the archive doesn't contain it.
+    pub fn read_archived(&self, record: &IndexRecord) -> io::Result<Option<Vec<u8>>> {
+        let archive = match &record.archive {
+            Some(archive) => self.archive_dir().join(archive),
+            None => return Ok(None),
+        };
+        let names = [record.annotation.as_str(), strip_compressed_ext(&record.annotation)];
+        let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(archive)?)?);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if matches!(entry.path(), Ok(p) if names.iter().any(|n| p == Path::new(n))) {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                return Ok(Some(data));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks the index of `date`, and that every product it says was archived can be found in
+    /// its archive
+    ///
+    /// Every archive that's referenced is read all the way through, so truncated or corrupt
+    /// archives are found even if the products in them are all listed.
+    pub fn verify_day(&self, date: NaiveDate) -> io::Result<VerifySummary> {
+        let (records, bad_lines) = self.index.read_day_checked(date)?;
+        let mut summary = VerifySummary {
+            records: records.len(),
+            bad_lines,
+            ..Default::default()
+        };
+
+        let mut by_archive: BTreeMap<&str, Vec<&IndexRecord>> = BTreeMap::new();
+        for rec in &records {
+            if let Some(archive) = &rec.archive {
+                by_archive.entry(archive).or_default().push(rec);
+                summary.archived += 1;
+            }
+        }
+        for (archive, recs) in by_archive {
+            let names = match self.archive_names(archive) {
+                Ok(names) => names,
+                Err(e) => {
+                    summary.bad_archives.push((archive.to_string(), e.to_string()));
+                    continue;
+                }
+            };
+            for rec in recs {
+                let stripped = strip_compressed_ext(&rec.annotation);
+                if !names.contains(&rec.annotation) && !names.contains(stripped) {
+                    summary.missing.push((archive.to_string(), rec.annotation.clone()));
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// The names of every file in an archive, reading (and decompressing) every entry
+    fn archive_names(&self, archive: &str) -> io::Result<HashSet<String>> {
+        let mut tar = tar::Archive::new(zstd::Decoder::new(File::open(self.archive_dir().join(archive))?)?);
+        let mut names = HashSet::new();
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            io::copy(&mut entry, &mut io::sink())?;
+            names.insert(entry.path()?.to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+
+    /// Packs the previous day shortly after midnight (UTC), every day
+    ///
+    /// Yesterday is also packed right away, in case the receiver wasn't running at midnight.
+    pub fn spawn(self) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            let now = Utc::now();
+            let yesterday = now.naive_utc().date() - Duration::days(1);
+            match self.pack_day(yesterday) {
+                Ok(ArchiveSummary {
+                    path: Some(path),
+                    packed,
+                    ..
+                }) => {
+                    info!("Packed {} products into {}", packed, path.display())
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to pack products from {}: {}", yesterday, e),
+            }
+
+            // a few minutes after midnight, so that late writes have finished
+            let next = Utc.from_utc_datetime(
+                &(now.naive_utc().date() + Duration::days(1))
+                    .and_hms_opt(0, 10, 0)
+                    .unwrap(),
+            );
+            std::thread::sleep((next - Utc::now()).to_std().unwrap_or_default());
+        })
+    }
+
+    fn should_pack(&self, record: &IndexRecord) -> bool {
+        record.archive.is_none() && self.filetypes.contains(&record.filetype_code)
+    }
+
+    /// Finds where a product was written, which is normally its annotation (minus any compression
+    /// extension, if it was decompressed)
+    fn find_product(&self, annotation: &str) -> Option<PathBuf> {
+        [annotation, strip_compressed_ext(annotation)]
+            .iter()
+            // don't let an odd annotation point outside of the output root
+            .filter(|name| !name.is_empty() && Path::new(name).file_name() == Some(name.as_ref()))
+            .map(|name| self.root.join(name))
+            .find(|path| matches!(path.symlink_metadata(), Ok(m) if m.is_file()))
+    }
+
+    /// All the files that `latest-*` symlinks point at
+    fn latest_targets(&self) -> io::Result<HashSet<PathBuf>> {
+        let mut targets = HashSet::new();
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with("latest-") {
+                if let Ok(target) = std::fs::read_link(entry.path()) {
+                    targets.insert(self.root.join(target));
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// `YYYY-MM-DD.tar.zst`, or `YYYY-MM-DD.N.tar.zst` if that day was already packed
+    fn next_archive_path(&self, date: NaiveDate) -> PathBuf {
+        let day = date.format("%Y-%m-%d");
+        let mut path = self.archive_dir().join(format!("{}.tar.zst", day));
+        let mut n = 1;
+        while path.exists() {
+            path = self.archive_dir().join(format!("{}.{}.tar.zst", day, n));
+            n += 1;
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::DailyArchiver;
+    use crate::index::{IndexRecord, ProductIndex};
+
+    fn record(date: NaiveDate, filetype_code: u8, annotation: &str) -> IndexRecord {
+        IndexRecord {
+            received: Utc.from_utc_datetime(&date.and_hms_opt(12, 0, 0).unwrap()),
+            vcid: 20,
+            filetype_code,
+            product_id: None,
+            annotation: annotation.to_string(),
+            archive: None,
+            segments: None,
+            sector: None,
+            decompression: None,
+            timing: None,
+            duplicate: None,
+            collision: None,
+            suspect: None,
+            decoder_version: None,
+            build: None,
+            admin: None,
+            replay: false,
+        }
+    }
+
+    #[test]
+    fn test_pack_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let date = NaiveDate::from_ymd_opt(2022, 5, 4).unwrap();
+        let index = ProductIndex::new(root);
+        for rec in [
+            record(date, 2, "A_one.TXT"),
+            record(date, 2, "A_two.TXT.gz"),
+            record(date, 2, "A_one.TXT"),
+            record(date, 2, "A_linked.TXT"),
+            record(date, 2, "A_gone.TXT"),
+            record(date, 0, "image.lrit"),
+        ] {
+            index.append(&rec).unwrap();
+        }
+        std::fs::write(root.join("A_one.TXT"), "one").unwrap();
+        std::fs::write(root.join("A_two.TXT"), "two").unwrap();
+        std::fs::write(root.join("A_linked.TXT"), "linked").unwrap();
+        std::fs::write(root.join("image.jpg"), "image").unwrap();
+        std::os::unix::fs::symlink(root.join("A_linked.TXT"), root.join("latest-LINKED")).unwrap();
+
+        let archiver = DailyArchiver::new(root);
+        let summary = archiver.pack_day(date).unwrap();
+        assert_eq!((summary.packed, summary.bytes, summary.missing), (2, 6, 1));
+        assert_eq!(summary.path.unwrap(), root.join("archive/2022-05-04.tar.zst"));
+        assert!(!root.join("A_one.TXT").exists());
+        assert!(root.join("A_linked.TXT").exists());
+        assert!(root.join("image.jpg").exists());
+
+        let records = index.read_day(date).unwrap();
+        assert_eq!(records.len(), 6);
+        let archived: Vec<_> = records.iter().filter(|r| r.archive.is_some()).collect();
+        assert_eq!(archived.len(), 3);
+        assert_eq!(archiver.read_archived(archived[0]).unwrap().unwrap(), b"one");
+        assert_eq!(archiver.read_archived(archived[1]).unwrap().unwrap(), b"two");
+        assert_eq!(archiver.read_archived(&records[5]).unwrap(), None);
+
+        // running again doesn't pack anything new
+        assert_eq!(archiver.pack_day(date).unwrap().path, None);
+
+        let summary = archiver.verify_day(date).unwrap();
+        assert!(summary.is_ok(), "{:?}", summary);
+        assert_eq!((summary.records, summary.archived), (6, 3));
+
+        // a truncated archive is caught
+        let archive = root.join("archive/2022-05-04.tar.zst");
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&archive, &bytes[..bytes.len() / 2]).unwrap();
+        let summary = archiver.verify_day(date).unwrap();
+        assert_eq!(summary.bad_archives.len(), 1);
+        assert_eq!(summary.bad_archives[0].0, "2022-05-04.tar.zst");
+    }
+}
@@ -0,0 +1,102 @@
+//! Binary coded decimal timestamps, like the carrier times in DCS blocks
+//!
+//! Reference: HRIT_DCS_File_Format_Rev1.pdf
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+/// Why a BCD timestamp couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BcdError {
+    /// A nibble wasn't a decimal digit
+    InvalidDigit(u8),
+    /// A field was out of range, like an hour of 25 or a day of the year of 367
+    OutOfRange { field: &'static str, value: u32 },
+}
+
+impl std::fmt::Display for BcdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BcdError::InvalidDigit(nibble) => write!(f, "{:#x} is not a BCD digit", nibble),
+            BcdError::OutOfRange { field, value } => write!(f, "{} of {} is out of range", field, value),
+        }
+    }
+}
+
+impl std::error::Error for BcdError {}
+
+/// The decimal digit in the high or low nibble of a byte
+fn digit(byte: u8, high: bool) -> Result<u32, BcdError> {
+    let nibble = if high { byte >> 4 } else { byte & 0xf };
+    if nibble > 9 {
+        return Err(BcdError::InvalidDigit(nibble));
+    }
+    Ok(nibble as u32)
+}
+
+fn in_range(field: &'static str, value: u32, max: u32) -> Result<u32, BcdError> {
+    if value > max {
+        return Err(BcdError::OutOfRange { field, value });
+    }
+    Ok(value)
+}
+
+/// Parses the 7 byte timestamp used for the carrier start and end of a DCS message
+///
+/// The digits are stored from least to most significant, starting with the low nibble of the first
+/// byte: milliseconds (tens, then units), then seconds, minutes, hours, the day of the year (3
+/// digits), and the last 2 digits of the year (in the 2000s).
+pub fn parse_dcs_time(bytes: &[u8; 7]) -> Result<DateTime<Utc>, BcdError> {
+    let d = |index: usize, high: bool| digit(bytes[index], high);
+
+    let millis = 100 * d(1, false)? + 10 * d(0, false)? + d(0, true)?;
+    let second = in_range("second", 10 * d(2, false)? + d(1, true)?, 59)?;
+    let minute = in_range("minute", 10 * d(3, false)? + d(2, true)?, 59)?;
+    let hour = in_range("hour", 10 * d(4, false)? + d(3, true)?, 23)?;
+    let day = 100 * d(5, true)? + 10 * d(5, false)? + d(4, true)?;
+    let year = 2000 + 10 * d(6, true)? + d(6, false)?;
+
+    let date = NaiveDate::from_yo_opt(year as i32, day).ok_or(BcdError::OutOfRange {
+        field: "day of the year",
+        value: day,
+    })?;
+    // the fields were all checked above
+    let time = date.and_hms_milli_opt(hour, minute, second, millis).unwrap();
+    Ok(Utc.from_utc_datetime(&time))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, Timelike};
+
+    use super::{parse_dcs_time, BcdError};
+
+    #[test]
+    fn test_parse_dcs_time() {
+        // 2022, day 124 (May 4th), 18:07:53.721
+        let time = parse_dcs_time(&[0x12, 0x37, 0x75, 0x80, 0x41, 0x12, 0x22]).unwrap();
+        assert_eq!((time.year(), time.ordinal()), (2022, 124));
+        assert_eq!((time.hour(), time.minute(), time.second()), (18, 7, 53));
+        assert_eq!(time.timestamp_subsec_millis(), 721);
+
+        // day 366 is only valid in a leap year
+        assert!(parse_dcs_time(&[0, 0, 0, 0, 0x60, 0x36, 0x24]).is_ok());
+        assert_eq!(
+            parse_dcs_time(&[0, 0, 0, 0, 0x60, 0x36, 0x23]),
+            Err(BcdError::OutOfRange {
+                field: "day of the year",
+                value: 366
+            })
+        );
+        assert_eq!(
+            parse_dcs_time(&[0, 0, 0, 0x50, 0x12, 0x01, 0x22]),
+            Err(BcdError::OutOfRange {
+                field: "hour",
+                value: 25
+            })
+        );
+        assert_eq!(
+            parse_dcs_time(&[0, 0, 0, 0, 0x10, 0x0a, 0x22]),
+            Err(BcdError::InvalidDigit(0xa))
+        );
+        assert!(parse_dcs_time(&[0; 7]).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Helpers for reading bit fields out of headers
+//!
+//! Most fields in the downlink are a few bits of a byte or a word.  Shifting and masking them by
+//! hand is where off-by-one masks and sign bugs creep in (like treating a 14 bit two's complement
+//! field as an `i16`), so use these instead.
+
+/// A mask of the low `bits` bits (at most 32)
+pub const fn mask(bits: u32) -> u32 {
+    if bits >= 32 {
+        u32::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+/// The `bits` bits of `value` starting at bit `shift`, counting from the least significant bit
+pub fn extract(value: impl Into<u32>, shift: u32, bits: u32) -> u32 {
+    (value.into() >> shift) & mask(bits)
+}
+
+/// Whether bit `bit` of `value` is set, counting from the least significant bit
+pub fn is_set(value: impl Into<u32>, bit: u32) -> bool {
+    extract(value, bit, 1) == 1
+}
+
+/// The low `bits` bits of `value` as an N bit two's complement number
+///
+/// Anything above the low `bits` bits is ignored.  `bits` has to be between 1 and 32.
+pub fn sign_extend(value: impl Into<u32>, bits: u32) -> i32 {
+    debug_assert!((1..=32).contains(&bits), "can't sign extend a {} bit field", bits);
+    let unused = 32 - bits;
+    ((value.into() << unused) as i32) >> unused
+}
+
+/// Reads a field of `bits` bits (at most 32) that starts `offset` bits into `bytes`
+///
+/// Bits are counted from the most significant bit of the first byte, which is how the CCSDS and
+/// LRIT specs number them.  Panics if the field runs past the end of `bytes`.
+pub fn read_bits(bytes: &[u8], offset: usize, bits: u32) -> u32 {
+    debug_assert!(bits <= 32, "can't read a {} bit field", bits);
+    let end = offset + bits as usize;
+    let value = bytes[offset / 8..end.div_ceil(8)]
+        .iter()
+        .fold(0u64, |value, b| value << 8 | *b as u64);
+    let trailing = end.div_ceil(8) * 8 - end;
+    (value >> trailing) as u32 & mask(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, is_set, mask, read_bits, sign_extend};
+
+    #[test]
+    fn test_bitfields() {
+        assert_eq!((mask(0), mask(3), mask(32)), (0, 0b111, u32::MAX));
+        assert_eq!(extract(0b1011_0110u8, 2, 3), 0b101);
+        assert!(is_set(0b1000u8, 3) && !is_set(0b1000u8, 2));
+
+        // 14 bit fields, with garbage in the 2 bits above them
+        assert_eq!(sign_extend(0x1fffu16, 14), 8191);
+        assert_eq!(sign_extend(0x2000u16, 14), -8192);
+        assert_eq!(sign_extend(0x3fffu16, 14), -1);
+        assert_eq!(sign_extend(0xc078u16, 14), 120);
+        assert_eq!(sign_extend(0x7f88u16, 14), -120);
+        assert_eq!(sign_extend(u32::MAX, 32), -1);
+
+        // a VCDU header: version 1, spacecraft 195, VC 13, counter 0x123456
+        let vcdu = [0x70, 0xcd, 0x12, 0x34, 0x56, 0x00];
+        assert_eq!(read_bits(&vcdu, 0, 2), 1);
+        assert_eq!(read_bits(&vcdu, 2, 8), 195);
+        assert_eq!(read_bits(&vcdu, 10, 6), 13);
+        assert_eq!(read_bits(&vcdu, 16, 24), 0x123456);
+        assert_eq!(read_bits(&vcdu, 8, 32), 0xcd123456);
+    }
+}
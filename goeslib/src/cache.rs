@@ -0,0 +1,170 @@
+//! An in-memory cache of recent products
+//!
+//! A [`ProductCache`] keeps the most recent products of each [`ProductClass`] in RAM, so the
+//! latest products can be served without reading them back from disk.  On a system with a
+//! read-only root filesystem, the cache can be the only output (see `ram_only` in the goesbox
+//! config).
+//!
+//! The cache is cheap to clone, and every clone shares the same products, so one clone can be
+//! given to the [`CacheHandler`] while others are read from other threads.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    handlers::{Handler, HandlerError},
+    lrit::LRIT,
+    quota::ProductClass,
+};
+
+/// A product in a [`ProductCache`]
+#[derive(Debug, Clone)]
+pub struct CachedProduct {
+    /// The annotation text (which is the original filename of the product)
+    pub name: String,
+    pub class: ProductClass,
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub filetype_code: u8,
+    pub product_id: Option<u16>,
+    /// The product data, without the LRIT headers (decompressed, if it was rice compressed)
+    pub data: Arc<[u8]>,
+}
+
+impl CachedProduct {
+    /// Returns `None` if the LRIT file is missing an annotation header
+    pub fn from_lrit(lrit: &LRIT, received: DateTime<Utc>) -> Option<CachedProduct> {
+        Some(CachedProduct {
+            name: lrit.headers.annotation.as_ref()?.text.clone(),
+            class: ProductClass::of(lrit),
+            received,
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            product_id: lrit.headers.noaa.as_ref().map(|n| n.product_id),
+            data: lrit.data.as_slice().into(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Products {
+    /// Newest first
+    by_class: HashMap<ProductClass, VecDeque<CachedProduct>>,
+}
+
+/// The most recent products of each class; see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct ProductCache {
+    per_class: usize,
+    products: Arc<Mutex<Products>>,
+}
+
+impl ProductCache {
+    /// A cache that keeps the `per_class` most recent products of each class
+    pub fn new(per_class: usize) -> ProductCache {
+        ProductCache {
+            per_class,
+            products: Arc::new(Mutex::new(Products::default())),
+        }
+    }
+
+    pub fn insert(&self, product: CachedProduct) {
+        if self.per_class == 0 {
+            return;
+        }
+        let mut products = self.products.lock().unwrap();
+        let recent = products.by_class.entry(product.class).or_default();
+        recent.push_front(product);
+        recent.truncate(self.per_class);
+    }
+
+    /// The most recent product of a class
+    pub fn latest(&self, class: ProductClass) -> Option<CachedProduct> {
+        let products = self.products.lock().unwrap();
+        products.by_class.get(&class)?.front().cloned()
+    }
+
+    /// The cached products of a class, newest first
+    pub fn recent(&self, class: ProductClass) -> Vec<CachedProduct> {
+        let products = self.products.lock().unwrap();
+        products
+            .by_class
+            .get(&class)
+            .map_or_else(Vec::new, |recent| recent.iter().cloned().collect())
+    }
+
+    /// The most recent product with this name (annotation), if it's still cached
+    pub fn get(&self, name: &str) -> Option<CachedProduct> {
+        let products = self.products.lock().unwrap();
+        products
+            .by_class
+            .values()
+            .flatten()
+            .filter(|p| p.name == name)
+            .max_by_key(|p| p.received)
+            .cloned()
+    }
+
+    /// How many products are cached, and their total size in bytes
+    pub fn usage(&self) -> (usize, usize) {
+        let products = self.products.lock().unwrap();
+        products
+            .by_class
+            .values()
+            .flatten()
+            .fold((0, 0), |(count, bytes), p| (count + 1, bytes + p.data.len()))
+    }
+}
+
+/// Adds every product to a [`ProductCache`]
+pub struct CacheHandler {
+    cache: ProductCache,
+}
+
+impl CacheHandler {
+    pub fn new(cache: ProductCache) -> CacheHandler {
+        CacheHandler { cache }
+    }
+}
+
+impl Handler for CacheHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let product = CachedProduct::from_lrit(lrit, Utc::now()).ok_or(HandlerError::MissingHeader("annotation"))?;
+        self.cache.insert(product);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheHandler, ProductCache};
+    use crate::{handlers::Handler, lrit::LRIT, quota::ProductClass, sim::LritBuilder};
+
+    #[test]
+    fn test_product_cache() {
+        let cache = ProductCache::new(2);
+        let mut handler = CacheHandler::new(cache.clone());
+        for (filetype, name) in [(0, "img1"), (2, "text1"), (0, "img2"), (0, "img3")] {
+            let bytes = LritBuilder::new(filetype).annotation(name).build(name.as_bytes());
+            handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).unwrap();
+        }
+        assert_eq!(cache.latest(ProductClass::Imagery).unwrap().name, "img3");
+        let names: Vec<_> = cache
+            .recent(ProductClass::Imagery)
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, ["img3", "img2"]);
+        assert_eq!(&*cache.get("text1").unwrap().data, b"text1");
+        // pushed out by newer images
+        assert!(cache.get("img1").is_none());
+        assert!(cache.latest(ProductClass::Other).is_none());
+        assert_eq!(cache.usage(), (3, 13));
+
+        let bytes = LritBuilder::new(2).build(b"no annotation");
+        assert!(handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).is_err());
+    }
+}
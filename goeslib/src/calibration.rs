@@ -0,0 +1,279 @@
+//! ABI band constants, and converting pixel values to physical units
+//!
+//! The 16 ABI bands come in two kinds.  The six reflective (visible and near infrared) bands
+//! measure reflected sunlight, and their radiances are converted to a reflectance factor with a
+//! single constant, `kappa0`.  The ten emissive (infrared) bands measure emitted heat, and their
+//! radiances are converted to a brightness temperature with the inverse Planck function, using
+//! the `fk1`/`fk2` coefficients and the `bc1`/`bc2` band corrections:
+//!
+//! ```text
+//! T = (fk2 / ln(fk1 / L + 1) - bc1) / bc2
+//! ```
+//!
+//! The constants here are the nominal GOES-16 values; the other satellites' differ very slightly.
+//! Products that carry their own coefficients (like the L1b NetCDF files) should use those, with
+//! [`Planck::new`].
+//!
+//! HRIT images are already scaled to 8 or 10 bit counts, and say how to get back to physical
+//! units in their image data function header, which [`DataFunction`] parses.
+//!
+//! Ref: GOES-R Product Definition and Users' Guide (PUG), Volume 3, section 5.1.3.6
+use std::collections::BTreeMap;
+
+/// The first radiation constant, 2hc², in mW/(m²·sr·cm⁻⁴)
+const C1: f64 = 1.191_042e-5;
+
+/// The second radiation constant, hc/k, in K·cm
+const C2: f64 = 1.438_775_2;
+
+/// How to turn an ABI band's radiance into something more useful
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BandKind {
+    /// Radiance times `kappa0` is the reflectance factor
+    Reflective { kappa0: f64 },
+    /// Radiance is turned into a brightness temperature with these coefficients
+    Emissive(Planck),
+}
+
+/// The constants for one ABI band
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    /// 1 to 16
+    pub number: u8,
+    /// Central wavelength, in µm
+    pub wavelength: f64,
+    /// Like "Clean Longwave IR Window"
+    pub name: &'static str,
+    pub kind: BandKind,
+}
+
+/// Coefficients of the inverse Planck function, for radiances in mW/(m²·sr·cm⁻¹)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Planck {
+    pub fk1: f64,
+    pub fk2: f64,
+    pub bc1: f64,
+    pub bc2: f64,
+}
+
+impl Planck {
+    pub const fn new(fk1: f64, fk2: f64, bc1: f64, bc2: f64) -> Planck {
+        Planck { fk1, fk2, bc1, bc2 }
+    }
+
+    /// The coefficients for a band with this effective wavenumber (in cm⁻¹) and band corrections
+    fn from_wavenumber(wavenumber: f64, bc1: f64, bc2: f64) -> Planck {
+        Planck::new(C1 * wavenumber.powi(3), C2 * wavenumber, bc1, bc2)
+    }
+
+    /// Brightness temperature, in K, of a radiance
+    ///
+    /// Returns `None` for radiances that aren't positive, which don't have a temperature.
+    pub fn brightness_temperature(&self, radiance: f64) -> Option<f64> {
+        if radiance <= 0.0 || radiance.is_nan() {
+            return None;
+        }
+        Some((self.fk2 / (self.fk1 / radiance + 1.0).ln() - self.bc1) / self.bc2)
+    }
+
+    /// The radiance with this brightness temperature (in K)
+    pub fn radiance(&self, temperature: f64) -> f64 {
+        self.fk1 / ((self.fk2 / (self.bc1 + self.bc2 * temperature)).exp() - 1.0)
+    }
+}
+
+/// Effective wavenumbers (cm⁻¹) and band corrections of the emissive bands, 7 through 16
+const EMISSIVE: [(f64, f64, f64); 10] = [
+    (2570.35, 0.43361, 0.99939),
+    (1616.8, 1.55228, 0.99700),
+    (1438.8, 0.34427, 0.99918),
+    (1362.4, 0.05651, 0.99986),
+    (1176.5, 0.18733, 0.99948),
+    (1040.6, 0.09102, 0.99971),
+    (968.0, 0.07550, 0.99975),
+    (894.0, 0.22516, 0.99920),
+    (815.3, 0.21702, 0.99916),
+    (753.78, 0.06266, 0.99974),
+];
+
+/// `kappa0` of the reflective bands, 1 through 6
+const REFLECTIVE: [f64; 6] = [0.0015839, 0.0019586, 0.0033384, 0.0074061, 0.0100927, 0.0335458];
+
+/// Central wavelengths and names of all the bands
+const BANDS: [(f64, &str); 16] = [
+    (0.47, "Blue"),
+    (0.64, "Red"),
+    (0.86, "Veggie"),
+    (1.37, "Cirrus"),
+    (1.6, "Snow/Ice"),
+    (2.2, "Cloud Particle Size"),
+    (3.9, "Shortwave Window"),
+    (6.2, "Upper-Level Water Vapor"),
+    (6.9, "Mid-Level Water Vapor"),
+    (7.3, "Lower-level Water Vapor"),
+    (8.4, "Cloud-Top Phase"),
+    (9.6, "Ozone"),
+    (10.3, "Clean Longwave IR Window"),
+    (11.2, "IR Longwave Window"),
+    (12.3, "Dirty Longwave Window"),
+    (13.3, "CO2 Longwave IR"),
+];
+
+/// The constants for a band, by number (1 to 16)
+pub fn band_number(number: u8) -> Option<Band> {
+    let (wavelength, name) = *BANDS.get(usize::from(number).checked_sub(1)?)?;
+    let kind = match number {
+        1..=6 => BandKind::Reflective {
+            kappa0: REFLECTIVE[usize::from(number) - 1],
+        },
+        _ => {
+            let (wavenumber, bc1, bc2) = EMISSIVE[usize::from(number) - 7];
+            BandKind::Emissive(Planck::from_wavenumber(wavenumber, bc1, bc2))
+        }
+    };
+    Some(Band {
+        number,
+        wavelength,
+        name,
+        kind,
+    })
+}
+
+/// The constants for a band, by name, like "C13" (as in GOES-R product names) or "13"
+pub fn band(name: &str) -> Option<Band> {
+    let number = name.strip_prefix(['C', 'c']).unwrap_or(name);
+    band_number(number.parse().ok()?)
+}
+
+impl Band {
+    /// The reflectance factor of a radiance, for reflective bands
+    pub fn reflectance(&self, radiance: f64) -> Option<f64> {
+        match self.kind {
+            BandKind::Reflective { kappa0 } => Some(radiance * kappa0),
+            BandKind::Emissive(_) => None,
+        }
+    }
+
+    /// The brightness temperature (in K) of a radiance, for emissive bands
+    pub fn brightness_temperature(&self, radiance: f64) -> Option<f64> {
+        match self.kind {
+            BandKind::Emissive(planck) => planck.brightness_temperature(radiance),
+            BandKind::Reflective { .. } => None,
+        }
+    }
+}
+
+/// The linear scaling from stored counts to radiance, like the `scale_factor` and `add_offset`
+/// attributes of a NetCDF variable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scaling {
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl Scaling {
+    pub const fn new(scale: f64, offset: f64) -> Scaling {
+        Scaling { scale, offset }
+    }
+
+    pub fn radiance(&self, count: u16) -> f64 {
+        f64::from(count) * self.scale + self.offset
+    }
+}
+
+/// An image data function, which maps the counts of an HRIT image to physical values
+///
+/// The header is text like this, with one line for every count that has a value:
+///
+/// ```text
+/// _NAME:=toa_brightness_temperature
+/// _UNIT:=K
+/// 0:=330.0000
+/// 1:=329.5000
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DataFunction {
+    /// Like "toa_brightness_temperature"
+    pub name: Option<String>,
+    /// Like "K", or "1" for reflectance factors
+    pub unit: Option<String>,
+    values: BTreeMap<u16, f64>,
+}
+
+impl DataFunction {
+    /// Parses a data function, returning `None` if it doesn't map any counts
+    pub fn parse(text: &str) -> Option<DataFunction> {
+        let mut func = DataFunction::default();
+        for line in text.split(['\r', '\n']).map(str::trim) {
+            let (key, value) = match line.split_once(":=") {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            match key {
+                "_NAME" => func.name = Some(value.to_string()),
+                "_UNIT" => func.unit = Some(value.to_string()),
+                _ => {
+                    if let (Ok(count), Ok(value)) = (key.parse(), value.parse()) {
+                        func.values.insert(count, value);
+                    }
+                }
+            }
+        }
+        if func.values.is_empty() {
+            return None;
+        }
+        Some(func)
+    }
+
+    /// The value of a count, interpolated between the nearest counts in the table
+    ///
+    /// Counts outside of the table get the value of the nearest end.
+    pub fn value(&self, count: u16) -> Option<f64> {
+        let below = self.values.range(..=count).next_back();
+        let above = self.values.range(count..).next();
+        match (below, above) {
+            (Some((&c0, &v0)), Some((&c1, &v1))) if c1 > c0 => {
+                Some(v0 + (v1 - v0) * f64::from(count - c0) / f64::from(c1 - c0))
+            }
+            (Some((_, &v)), _) | (None, Some((_, &v))) => Some(v),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{band, band_number, BandKind, DataFunction, Scaling};
+
+    #[test]
+    fn test_calibration() {
+        let c13 = band("C13").unwrap();
+        assert_eq!(c13.number, 13);
+        assert_eq!(c13.wavelength, 10.3);
+        let planck = match c13.kind {
+            BandKind::Emissive(planck) => planck,
+            other => panic!("unexpected kind {:?}", other),
+        };
+        // matches the coefficients in the GOES-16 L1b files
+        assert!((planck.fk1 - 10803.3).abs() < 1.0);
+        assert!((planck.fk2 - 1392.74).abs() < 0.1);
+        let radiance = planck.radiance(290.0);
+        assert!((c13.brightness_temperature(radiance).unwrap() - 290.0).abs() < 1e-9);
+        assert_eq!(c13.brightness_temperature(0.0), None);
+        assert_eq!(c13.reflectance(radiance), None);
+
+        let c02 = band("2").unwrap();
+        let scaling = Scaling::new(0.158, -20.29);
+        let reflectance = c02.reflectance(scaling.radiance(1000)).unwrap();
+        assert!((reflectance - 0.2697).abs() < 1e-3);
+        assert!(band_number(0).is_none() && band_number(17).is_none() && band("C1x").is_none());
+
+        let func =
+            DataFunction::parse("_NAME:=toa_brightness_temperature\r\n_UNIT:=K\r\n0:=330.0\r\n10:=320.0\r\n").unwrap();
+        assert_eq!(func.unit.as_deref(), Some("K"));
+        assert_eq!(func.value(4), Some(326.0));
+        assert_eq!(func.value(10), Some(320.0));
+        assert_eq!(func.value(200), Some(320.0));
+        assert!(DataFunction::parse("$HALFTONE:=8").is_none());
+    }
+}
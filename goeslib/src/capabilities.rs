@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::index::DECODER_VERSION;
+
+/// Every cargo feature that changes what goeslib can decode or write
+const FEATURES: [(&str, bool); 8] = [
+    ("image", cfg!(feature = "image")),
+    ("mmap", cfg!(feature = "mmap")),
+    ("rayon", cfg!(feature = "rayon")),
+    ("turbojpeg", cfg!(feature = "turbojpeg")),
+    ("zip", cfg!(feature = "zip")),
+    ("rice", cfg!(feature = "rice")),
+    ("archive", cfg!(feature = "archive")),
+    ("server", cfg!(feature = "server")),
+];
+
+/// What this build of goeslib is, and what it can do, from [`capabilities`]
+///
+/// Products written by different versions (or builds with different features) can differ, like
+/// images that couldn't be decompressed without `rice`, so this is recorded in the
+/// [product index](crate::index::IndexRecord::build) and served by `goesbox-ui --grpc`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// The version of goeslib, like `0.1.0`
+    pub version: String,
+    /// The [`DECODER_VERSION`], which goes up when products are written differently
+    pub decoder_version: u32,
+    /// The cargo features goeslib was built with
+    pub features: Vec<String>,
+    /// The LRIT file types that are decoded into products (any others can only be saved as they
+    /// are, by the [`RawLritHandler`](crate::handlers::RawLritHandler))
+    pub filetypes: Vec<u8>,
+    /// The handlers that decode products, by name
+    pub handlers: Vec<String>,
+}
+
+impl Capabilities {
+    /// The version and features in one string, like `0.1.0+image.zip.rice` (a semver version with
+    /// the features as build metadata)
+    pub fn build(&self) -> String {
+        if self.features.is_empty() {
+            self.version.clone()
+        } else {
+            format!("{}+{}", self.version, self.features.join("."))
+        }
+    }
+}
+
+/// Returns the version, features, and supported products of this build of goeslib
+pub fn capabilities() -> Capabilities {
+    let mut handlers = vec![
+        "TextHandler",
+        "GtsHandler",
+        "DcsHandler",
+        "DcsDriftHandler",
+        "DcsCoverageHandler",
+        "AdminHandler",
+        "BoardHandler",
+        "MetarHandler",
+        "SoundingHandler",
+        "ShefHandler",
+        "SpaceWeatherHandler",
+        "NotifyHandler",
+        "RawLritHandler",
+    ];
+    if cfg!(feature = "image") {
+        handlers.extend(["ImageHandler", "GlmHandler", "SuviHandler", "HimawariHandler"]);
+    }
+    // images (0) need the image feature
+    let filetypes = if cfg!(feature = "image") {
+        vec![0, 1, 2, 130]
+    } else {
+        vec![1, 2, 130]
+    };
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        decoder_version: DECODER_VERSION,
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        filetypes,
+        handlers: handlers.into_iter().map(str::to_string).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::capabilities;
+
+    #[test]
+    fn test_capabilities() {
+        let caps = capabilities();
+        assert_eq!(caps.version, env!("CARGO_PKG_VERSION"));
+        assert!(caps.filetypes.contains(&2));
+        assert!(caps.handlers.iter().any(|h| h == "TextHandler"));
+        assert_eq!(caps.features.iter().any(|f| f == "image"), cfg!(feature = "image"));
+        assert!(caps.build().starts_with(&caps.version));
+        assert_eq!(caps.build().contains('+'), !caps.features.is_empty());
+    }
+}
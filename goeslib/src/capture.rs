@@ -0,0 +1,210 @@
+//! Raw capture files, and merging several captures of the same pass
+//!
+//! A capture is every VCDU (892 bytes each) written back to back, in the order it was received.
+//! When several stations record the same time period, each of them will have missed a different
+//! set of frames, so [`merge_captures`] lines them up by their VCDU counters and writes a single
+//! capture with every frame that any of them received.
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    io::{self, Read, Write},
+};
+
+use crate::{lrit::VCDU, sim::VCDU_LEN};
+
+/// VCDU counters are 24 bits
+const COUNTER_MODULUS: i64 = 1 << 24;
+
+/// Reads the VCDUs out of a capture
+///
+/// A partial frame at the end of the capture is ignored.
+pub struct CaptureReader<R> {
+    inner: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(inner: R) -> CaptureReader<R> {
+        CaptureReader { inner }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<[u8; VCDU_LEN]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = [0; VCDU_LEN];
+        match self.inner.read_exact(&mut frame) {
+            Ok(()) => Some(Ok(frame)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// What happened when merging captures
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// Frames written to the merged capture
+    pub written: usize,
+    /// Frames that were dropped because an earlier capture had the same frame
+    pub duplicates: usize,
+    /// Frames that arrived after later frames of the same virtual channel were already written
+    ///
+    /// This happens when the captures are badly out of step with each other (or when a capture has
+    /// frames out of order).  Some of these may also have been duplicates.
+    pub late: usize,
+    /// How many of the written frames came from each capture
+    pub from_each: Vec<usize>,
+}
+
+/// Maps a 24-bit counter to a position that keeps increasing across counter wraps
+///
+/// `prev` is a nearby position on the same virtual channel.
+fn unwrap_counter(prev: i64, counter: u32) -> i64 {
+    let delta = (counter as i64 - prev).rem_euclid(COUNTER_MODULUS);
+    if delta < COUNTER_MODULUS / 2 {
+        prev + delta
+    } else {
+        prev + delta - COUNTER_MODULUS
+    }
+}
+
+/// Merges captures of the same time period into one, using the VCDU counters to line them up
+///
+/// The captures are read in step with each other, and each frame is held back until every capture
+/// that is still being read has moved past it, so that the frames of each virtual channel are
+/// written in order.  When several captures have the same frame, the copy from the capture listed
+/// first is kept.  No more than `max_buffered` frames are held back per virtual channel, which
+/// bounds memory use when one capture covers a much longer time than the others.
+///
+/// Fill frames are not copied into the merged capture.
+pub fn merge_captures<R: Read, W: Write>(
+    inputs: Vec<R>,
+    mut output: W,
+    max_buffered: usize,
+) -> io::Result<MergeSummary> {
+    let mut readers: Vec<Option<CaptureReader<R>>> = inputs.into_iter().map(|r| Some(CaptureReader::new(r))).collect();
+    let mut summary = MergeSummary {
+        from_each: vec![0; readers.len()],
+        ..Default::default()
+    };
+
+    // the last position seen on each virtual channel, by each capture
+    let mut positions: Vec<HashMap<u8, i64>> = vec![HashMap::new(); readers.len()];
+    // the last position seen on each virtual channel, by any capture
+    let mut latest: HashMap<u8, i64> = HashMap::new();
+    // frames that haven't been written yet, and the capture they came from
+    let mut pending: BTreeMap<u8, BTreeMap<i64, (usize, [u8; VCDU_LEN])>> = BTreeMap::new();
+    // everything up to this position has been written
+    let mut written_to: HashMap<u8, i64> = HashMap::new();
+
+    while readers.iter().any(|r| r.is_some()) {
+        for (source, reader) in readers.iter_mut().enumerate() {
+            let frame = match reader.as_mut().and_then(|r| r.next()) {
+                Some(frame) => frame?,
+                None => {
+                    *reader = None;
+                    continue;
+                }
+            };
+            let vcdu = VCDU::new(&frame);
+            if vcdu.is_fill() {
+                continue;
+            }
+            let vcid = vcdu.vcid();
+            let prev = positions[source].get(&vcid).or_else(|| latest.get(&vcid));
+            let pos = prev.map_or(vcdu.counter() as i64, |prev| unwrap_counter(*prev, vcdu.counter()));
+            positions[source].insert(vcid, pos);
+            let newest = latest.entry(vcid).or_insert(pos);
+            *newest = (*newest).max(pos);
+
+            if matches!(written_to.get(&vcid), Some(w) if pos <= *w) {
+                summary.late += 1;
+                continue;
+            }
+            match pending.entry(vcid).or_default().entry(pos) {
+                Entry::Occupied(_) => summary.duplicates += 1,
+                Entry::Vacant(v) => {
+                    v.insert((source, frame));
+                }
+            }
+        }
+
+        for (vcid, frames) in pending.iter_mut() {
+            // every capture that's still being read has moved past this point
+            let horizon = readers
+                .iter()
+                .zip(&positions)
+                .filter(|(reader, _)| reader.is_some())
+                .filter_map(|(_, pos)| pos.get(vcid))
+                .min()
+                .copied()
+                .unwrap_or(i64::MAX);
+            while let Some(&pos) = frames.keys().next() {
+                if pos > horizon && frames.len() <= max_buffered {
+                    break;
+                }
+                let (source, frame) = frames.remove(&pos).expect("first key");
+                output.write_all(&frame)?;
+                summary.written += 1;
+                summary.from_each[source] += 1;
+                written_to.insert(*vcid, pos);
+            }
+        }
+    }
+
+    output.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_captures, CaptureReader, MergeSummary};
+    use crate::{lrit::VCDU, sim::VCDU_LEN};
+
+    fn frame(vcid: u8, counter: u32) -> [u8; VCDU_LEN] {
+        let mut bytes = [0; VCDU_LEN];
+        bytes[0] = 0x40;
+        bytes[1] = vcid;
+        bytes[2..5].copy_from_slice(&counter.to_be_bytes()[1..]);
+        bytes
+    }
+
+    fn capture(frames: &[(u8, u32)]) -> Vec<u8> {
+        frames
+            .iter()
+            .flat_map(|(vcid, counter)| frame(*vcid, *counter))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_captures() {
+        let wrap = (1 << 24) - 2;
+        // the first station missed counter 0 on VC 13 and 9 on VC 30, the second missed 1 and 8
+        let a = capture(&[(13, wrap), (13, wrap + 1), (63, 5), (30, 7), (13, 1), (30, 8)]);
+        let b = capture(&[(13, wrap), (13, wrap + 1), (13, 0), (30, 7), (30, 9)]);
+
+        let mut merged = Vec::new();
+        let summary = merge_captures(vec![&a[..], &b[..]], &mut merged, 100).unwrap();
+        assert_eq!(
+            summary,
+            MergeSummary {
+                written: 7,
+                duplicates: 3,
+                late: 0,
+                from_each: vec![5, 2],
+            }
+        );
+
+        let frames: Vec<(u8, u32)> = CaptureReader::new(&merged[..])
+            .map(|f| {
+                let f = f.unwrap();
+                let vcdu = VCDU::new(&f);
+                (vcdu.vcid(), vcdu.counter())
+            })
+            .collect();
+        let vc13: Vec<_> = frames.iter().filter(|f| f.0 == 13).map(|f| f.1).collect();
+        assert_eq!(vc13, vec![wrap, wrap + 1, 0, 1]);
+        let vc30: Vec<_> = frames.iter().filter(|f| f.0 == 30).map(|f| f.1).collect();
+        assert_eq!(vc30, vec![7, 8, 9]);
+    }
+}
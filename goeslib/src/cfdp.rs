@@ -0,0 +1,450 @@
+//! Parsing CCSDS File Delivery Protocol (CFDP) PDUs, and assembling the files they carry
+//!
+//! The GOES downlinks send each LRIT file as a session of TP_PDUs, but some newer dissemination
+//! systems send files with CFDP instead: a Metadata PDU with the file's name and size, File Data
+//! PDUs with pieces of the file (each at an offset), and an EOF PDU with a checksum of the whole
+//! file.  A [`CfdpTransport`] can be given to a
+//! [`VirtualChannel`](crate::lrit::VirtualChannel::with_transport) for an APID that carries CFDP
+//! PDUs (one in each TP_PDU), and returns the LRIT files it receives like any other session.
+//!
+//! This only covers unacknowledged mode (there's no uplink to send ACKs or NAKs on), and ignores
+//! the directives that only matter to a sender.
+//!
+//! # References
+//!
+//! * CCSDS 727.0-B-5, CCSDS File Delivery Protocol (https://public.ccsds.org/Pubs/727x0b5.pdf)
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use log::{info, warn};
+
+use crate::{
+    bitfield::read_bits,
+    crc::calc_crc16,
+    lrit::{Transport, LRIT},
+    stats::{Stat, Stats},
+};
+
+/// How many transactions can be in progress at once; past this, the oldest is dropped
+const MAX_TRANSACTIONS: usize = 64;
+
+/// Directive codes (Table 5-4)
+const EOF: u8 = 0x04;
+const METADATA: u8 = 0x07;
+
+/// Why a PDU couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CfdpError {
+    /// The PDU ended before one of its fields did
+    Truncated,
+    /// Only version 2 (`001`, from CCSDS 727.0-B-4 on) and version 1 (`000`) PDUs are understood
+    UnsupportedVersion(u8),
+    /// The PDU's CRC doesn't match
+    BadCrc,
+}
+
+impl std::fmt::Display for CfdpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CfdpError::Truncated => write!(f, "CFDP PDU is truncated"),
+            CfdpError::UnsupportedVersion(v) => write!(f, "unsupported CFDP version {}", v),
+            CfdpError::BadCrc => write!(f, "CFDP PDU fails its CRC"),
+        }
+    }
+}
+
+impl std::error::Error for CfdpError {}
+
+/// The fixed header at the start of every PDU (section 5.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PduHeader {
+    pub version: u8,
+    /// File Data PDUs, as opposed to file directives
+    pub file_data: bool,
+    /// Sent from the receiver back to the sender (like an ACK)
+    pub toward_sender: bool,
+    pub unacknowledged: bool,
+    pub crc_present: bool,
+    /// File sizes and offsets are 64 bits instead of 32
+    pub large_file: bool,
+    /// File Data PDUs start with segment metadata
+    pub segment_metadata: bool,
+    pub source_entity: u64,
+    pub transaction: u64,
+    pub destination_entity: u64,
+}
+
+/// The part of a PDU after its header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pdu {
+    Metadata {
+        /// 0 for the modular checksum, 15 for none
+        checksum_type: u8,
+        file_size: u64,
+        source_name: String,
+        destination_name: String,
+    },
+    FileData {
+        offset: u64,
+        data: Vec<u8>,
+    },
+    Eof {
+        /// 0 if the transfer finished normally
+        condition: u8,
+        checksum: u32,
+        file_size: u64,
+    },
+    /// Another file directive, like Finished or Prompt, which a receiver can ignore
+    Directive(u8),
+}
+
+/// Reads big endian numbers and length-value fields off the front of a PDU
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CfdpError> {
+        if self.0.len() < len {
+            return Err(CfdpError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, CfdpError> {
+        Ok(self.take(len)?.iter().fold(0, |n, b| n << 8 | *b as u64))
+    }
+
+    /// A 1 byte length, followed by that many bytes of text
+    fn lv_string(&mut self) -> Result<String, CfdpError> {
+        let len = self.uint(1)? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+/// Parses a PDU, checking its CRC if it has one
+pub fn parse_pdu(bytes: &[u8]) -> Result<(PduHeader, Pdu), CfdpError> {
+    if bytes.len() < 4 {
+        return Err(CfdpError::Truncated);
+    }
+    let version = read_bits(bytes, 0, 3) as u8;
+    if version > 1 {
+        return Err(CfdpError::UnsupportedVersion(version));
+    }
+    let data_len = read_bits(bytes, 8, 16) as usize;
+    // the lengths are stored minus one
+    let entity_len = read_bits(bytes, 25, 3) as usize + 1;
+    let seq_len = read_bits(bytes, 29, 3) as usize + 1;
+
+    let mut reader = Reader(&bytes[4..]);
+    let mut header = PduHeader {
+        version,
+        file_data: read_bits(bytes, 3, 1) == 1,
+        toward_sender: read_bits(bytes, 4, 1) == 1,
+        unacknowledged: read_bits(bytes, 5, 1) == 1,
+        crc_present: read_bits(bytes, 6, 1) == 1,
+        large_file: read_bits(bytes, 7, 1) == 1,
+        segment_metadata: read_bits(bytes, 28, 1) == 1,
+        source_entity: reader.uint(entity_len)?,
+        transaction: 0,
+        destination_entity: 0,
+    };
+    header.transaction = reader.uint(seq_len)?;
+    header.destination_entity = reader.uint(entity_len)?;
+
+    let header_len = bytes.len() - reader.0.len();
+    let mut data = reader.take(data_len)?;
+    if header.crc_present {
+        if data.len() < 2 {
+            return Err(CfdpError::Truncated);
+        }
+        let (rest, crc) = data.split_at(data.len() - 2);
+        if calc_crc16(&bytes[..header_len + rest.len()]) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return Err(CfdpError::BadCrc);
+        }
+        data = rest;
+    }
+
+    let size_len = if header.large_file { 8 } else { 4 };
+    let mut reader = Reader(data);
+    let pdu = if header.file_data {
+        if header.segment_metadata {
+            let metadata_len = reader.uint(1)? as usize & 0x3f;
+            reader.take(metadata_len)?;
+        }
+        Pdu::FileData {
+            offset: reader.uint(size_len)?,
+            data: reader.0.to_vec(),
+        }
+    } else {
+        match reader.uint(1)? as u8 {
+            METADATA => {
+                let flags = reader.uint(1)? as u8;
+                Pdu::Metadata {
+                    checksum_type: flags & 0x0f,
+                    file_size: reader.uint(size_len)?,
+                    source_name: reader.lv_string()?,
+                    destination_name: reader.lv_string()?,
+                }
+            }
+            EOF => Pdu::Eof {
+                condition: reader.uint(1)? as u8 >> 4,
+                checksum: reader.uint(4)? as u32,
+                file_size: reader.uint(size_len)?,
+            },
+            directive => Pdu::Directive(directive),
+        }
+    };
+    Ok((header, pdu))
+}
+
+/// The CFDP modular checksum (section 4.2.2): the sum of the file as big endian 32 bit words,
+/// aligned to the start of the file
+pub fn modular_checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, word| {
+        let mut padded = [0; 4];
+        padded[..word.len()].copy_from_slice(word);
+        sum.wrapping_add(u32::from_be_bytes(padded))
+    })
+}
+
+/// A file received over CFDP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CfdpFile {
+    pub source_entity: u64,
+    pub transaction: u64,
+    /// The destination file name from the Metadata PDU (or the source name, if that's empty)
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// A transaction that's still being received
+#[derive(Default)]
+struct Transaction {
+    name: Option<String>,
+    checksum_type: u8,
+    /// The pieces of the file received so far, by offset
+    segments: BTreeMap<u64, Vec<u8>>,
+    /// The checksum and size from the EOF PDU
+    eof: Option<(u32, u64)>,
+}
+
+impl Transaction {
+    /// The whole file, if every byte of it has been received
+    fn assemble(&self, file_size: u64) -> Option<Vec<u8>> {
+        let mut data = Vec::with_capacity(file_size as usize);
+        for (offset, segment) in &self.segments {
+            let offset = *offset as usize;
+            if offset > data.len() {
+                return None;
+            }
+            // segments can overlap when a sender repeats data
+            let end = offset + segment.len();
+            if end > data.len() {
+                data.extend_from_slice(&segment[data.len() - offset..]);
+            }
+        }
+        (data.len() as u64 == file_size).then_some(data)
+    }
+}
+
+/// Puts files back together from their PDUs
+///
+/// Transactions are kept until their Metadata PDU, EOF PDU, and all of their data have arrived, in
+/// any order.
+#[derive(Default)]
+pub struct CfdpAssembler {
+    transactions: HashMap<(u64, u64), Transaction>,
+    /// Transactions in the order they started, so the oldest can be dropped
+    order: VecDeque<(u64, u64)>,
+}
+
+impl CfdpAssembler {
+    pub fn new() -> CfdpAssembler {
+        Default::default()
+    }
+
+    /// Adds a PDU, returning the file if this finished it
+    pub fn add(&mut self, header: &PduHeader, pdu: Pdu) -> Option<CfdpFile> {
+        if header.toward_sender {
+            return None;
+        }
+        let key = (header.source_entity, header.transaction);
+        if !self.transactions.contains_key(&key) {
+            if self.order.len() == MAX_TRANSACTIONS {
+                if let Some(oldest) = self.order.pop_front() {
+                    warn!(
+                        "Dropping unfinished CFDP transaction {} from entity {}",
+                        oldest.1, oldest.0
+                    );
+                    self.transactions.remove(&oldest);
+                }
+            }
+            self.order.push_back(key);
+        }
+        let transaction = self.transactions.entry(key).or_default();
+        match pdu {
+            Pdu::Metadata {
+                checksum_type,
+                source_name,
+                destination_name,
+                ..
+            } => {
+                transaction.checksum_type = checksum_type;
+                transaction.name = Some(match destination_name.is_empty() {
+                    true => source_name,
+                    false => destination_name,
+                });
+            }
+            Pdu::FileData { offset, data } => {
+                transaction.segments.insert(offset, data);
+            }
+            Pdu::Eof {
+                condition,
+                checksum,
+                file_size,
+            } => {
+                if condition != 0 {
+                    info!("CFDP transaction {} was cancelled (condition {})", key.1, condition);
+                    self.remove(key);
+                    return None;
+                }
+                transaction.eof = Some((checksum, file_size));
+            }
+            Pdu::Directive(_) => return None,
+        }
+
+        let ((checksum, file_size), name) = match (transaction.eof, &transaction.name) {
+            (Some(eof), Some(name)) => (eof, name.clone()),
+            _ => return None,
+        };
+        let data = transaction.assemble(file_size)?;
+        let checksum_type = transaction.checksum_type;
+        self.remove(key);
+        if checksum_type == 0 && modular_checksum(&data) != checksum {
+            warn!("Dropping CFDP file {}, its checksum doesn't match", name);
+            return None;
+        }
+        Some(CfdpFile {
+            source_entity: key.0,
+            transaction: key.1,
+            name,
+            data,
+        })
+    }
+
+    fn remove(&mut self, key: (u64, u64)) {
+        self.transactions.remove(&key);
+        self.order.retain(|k| *k != key);
+    }
+}
+
+/// A [`Transport`] for APIDs whose TP_PDUs each hold a CFDP PDU, and whose files are LRIT files
+#[derive(Default)]
+pub struct CfdpTransport {
+    assembler: CfdpAssembler,
+}
+
+impl CfdpTransport {
+    pub fn new() -> CfdpTransport {
+        Default::default()
+    }
+}
+
+impl Transport for CfdpTransport {
+    fn packet(&mut self, vcid: u8, apid: u16, _flags: u8, data: &[u8], stats: &mut Stats) -> Option<LRIT> {
+        let (header, pdu) = match parse_pdu(data) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Dropping CFDP PDU on APID {}: {}", apid, e);
+                stats.record(Stat::CorruptPacket);
+                return None;
+            }
+        };
+        let file = self.assembler.add(&header, pdu)?;
+        match LRIT::from_bytes(vcid, &file.data) {
+            Ok(mut lrit) => {
+                lrit.apid = Some(apid);
+                Some(lrit)
+            }
+            Err(e) => {
+                warn!("Dropping CFDP file {}, it isn't an LRIT file: {}", file.name, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{modular_checksum, parse_pdu, CfdpError, CfdpTransport, Pdu, EOF, METADATA};
+    use crate::{
+        crc::calc_crc16,
+        lrit::{VirtualChannel, VCDU},
+        sim::{LritBuilder, Transmitter},
+        stats::Stats,
+    };
+
+    /// A version 2, unacknowledged PDU from entity 5 to entity 1, with a CRC
+    fn pdu(file_data: bool, transaction: u8, body: &[u8]) -> Vec<u8> {
+        let mut pdu = vec![0b0010_0110 | (file_data as u8) << 4];
+        pdu.extend(((body.len() + 2) as u16).to_be_bytes());
+        pdu.extend([0, 5, transaction, 1]);
+        pdu.extend(body);
+        pdu.extend(calc_crc16(&pdu).to_be_bytes());
+        pdu
+    }
+
+    #[test]
+    fn test_cfdp() {
+        let file = LritBuilder::new(2).annotation("cfdp.txt").build(b"sent over CFDP");
+        // no checksum type (so the modular checksum), the file size, no source name, and the
+        // destination name
+        let mut metadata = vec![METADATA, 0];
+        metadata.extend((file.len() as u32).to_be_bytes());
+        metadata.extend(b"\0\x08cfdp.txt");
+        let (header, parsed) = parse_pdu(&pdu(false, 7, &metadata)).unwrap();
+        assert_eq!(
+            (header.source_entity, header.transaction, header.destination_entity),
+            (5, 7, 1)
+        );
+        assert!(matches!(parsed, Pdu::Metadata { file_size, destination_name, .. }
+            if file_size == file.len() as u64 && destination_name == "cfdp.txt"));
+
+        let mut bad = pdu(false, 7, &metadata);
+        *bad.last_mut().unwrap() ^= 1;
+        assert_eq!(parse_pdu(&bad), Err(CfdpError::BadCrc));
+        assert_eq!(parse_pdu(&bad[..6]), Err(CfdpError::Truncated));
+
+        // the data in two pieces, sent out of order, with the EOF before the metadata
+        let split = file.len() / 2;
+        let data = |offset: usize, piece: &[u8]| {
+            let mut body = (offset as u32).to_be_bytes().to_vec();
+            body.extend(piece);
+            pdu(true, 7, &body)
+        };
+        let mut eof = vec![EOF, 0];
+        eof.extend(modular_checksum(&file).to_be_bytes());
+        eof.extend((file.len() as u32).to_be_bytes());
+
+        let mut tx = Transmitter::new();
+        for p in [
+            data(split, &file[split..]),
+            pdu(false, 7, &eof),
+            data(0, &file[..split]),
+            pdu(false, 7, &metadata),
+        ] {
+            tx.send_pdu(20, 600, &p);
+        }
+        let mut vc = VirtualChannel::new(20, 0).with_transport(600, CfdpTransport::new());
+        let mut stats = Stats::new();
+        let mut lrits = Vec::new();
+        while !tx.is_idle() {
+            let vcdu = tx.next_vcdu();
+            lrits.extend(vc.process_vcdu(VCDU::new(&vcdu), &mut stats));
+        }
+        assert_eq!(lrits.len(), 1);
+        assert_eq!(lrits[0].headers.annotation.as_ref().unwrap().text, "cfdp.txt");
+        assert_eq!(lrits[0].data, b"sent over CFDP");
+        assert_eq!(lrits[0].apid, Some(600));
+    }
+}
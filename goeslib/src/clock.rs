@@ -0,0 +1,269 @@
+//! Where the current time comes from
+//!
+//! Everything that measures rates or ages (like [`Stats`](crate::stats::Stats)) asks a [`Clock`]
+//! instead of calling `Instant::now()` itself.  Normally that's the [`SystemClock`], but tests can
+//! use a [`ManualClock`] to step through time without sleeping.
+//!
+//! The local clock isn't always right, though, so [`ClockSkew`] compares it with the times in the
+//! products that are received.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub trait Clock: Send + Sync {
+    /// The current monotonic time, for measuring how long things take
+    fn now(&self) -> Instant;
+
+    /// The current wall clock time, for timestamps
+    fn utc(&self) -> DateTime<Utc>;
+}
+
+/// The real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when it's told to
+///
+/// Clones share the same time, so a test can keep one to [`advance`](ManualClock::advance) after
+/// giving another to the code under test.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    /// A clock that starts at the given wall clock time
+    pub fn new(start_utc: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            start_utc,
+            offset: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.offset.lock().unwrap() += by;
+    }
+
+    fn offset(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.offset()
+    }
+
+    fn utc(&self) -> DateTime<Utc> {
+        self.start_utc + chrono::Duration::from_std(self.offset()).unwrap_or_else(|_| chrono::Duration::zero())
+    }
+}
+
+/// Which clock to believe when the local clock and the times in products disagree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// The local clock, which products are only compared against
+    #[default]
+    Local,
+    /// The times in products, once they show that the local clock is off
+    Product,
+}
+
+/// How many of the most recent product times the local clock is compared against
+const SKEW_SAMPLES: usize = 100;
+
+/// How many product times are needed before the local clock is judged
+const MIN_SKEW_SAMPLES: usize = 5;
+
+/// Compares the times in products (when their data was taken) with the local clock
+///
+/// Products normally arrive within minutes of being taken.  A single product that's much older
+/// than the others is a stale rebroadcast, but if the typical (median) difference of the recent
+/// ones is large, it's the local clock that's wrong, and everything that goes by it (retention,
+/// schedules, loops) is off too.
+#[derive(Debug, Clone)]
+pub struct ClockSkew {
+    /// How long after being taken each recent product arrived, in seconds, oldest first
+    ages: VecDeque<i64>,
+    max_skew: chrono::Duration,
+    source: TimeSource,
+    stale: usize,
+}
+
+/// What [`ClockSkew`] found, for health status
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkewReport {
+    /// How far the local clock is ahead of the recent product times (the median of how long after
+    /// being taken they arrived), in seconds, or `None` until enough products had times
+    pub offset_secs: Option<i64>,
+    /// Set if that's more than the maximum skew, so the local clock looks wrong
+    pub skewed: bool,
+    /// Products that arrived much later than the others, like stale rebroadcasts
+    pub stale: usize,
+    /// The clock that goesbox is going by
+    pub source: TimeSource,
+}
+
+impl Default for ClockSkew {
+    fn default() -> Self {
+        ClockSkew::new()
+    }
+}
+
+impl ClockSkew {
+    /// Flags skews of more than 30 minutes, and goes by the local clock regardless
+    pub fn new() -> ClockSkew {
+        ClockSkew {
+            ages: VecDeque::new(),
+            max_skew: chrono::Duration::minutes(30),
+            source: TimeSource::Local,
+            stale: 0,
+        }
+    }
+
+    /// Flags skews of more than this
+    pub fn with_max_skew(mut self, max_skew: Duration) -> Self {
+        self.max_skew = chrono::Duration::from_std(max_skew).unwrap_or(self.max_skew);
+        self
+    }
+
+    /// Go by this clock when the local clock looks wrong
+    pub fn with_source(mut self, source: TimeSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Compares the time a product was taken with the local time it arrived
+    ///
+    /// Every product counts towards the median, whichever way it's off, so a stale rebroadcast
+    /// only moves it if most of the recent products are stale, and once the local clock is set
+    /// right the products that arrive after that bring it back.
+    pub fn record(&mut self, taken: DateTime<Utc>, local: DateTime<Utc>) {
+        let age = (local - taken).num_seconds();
+        if let Some(offset) = self.offset() {
+            if age - offset > self.max_skew.num_seconds() {
+                self.stale += 1;
+            }
+        }
+        self.ages.push_back(age);
+        if self.ages.len() > SKEW_SAMPLES {
+            self.ages.pop_front();
+        }
+    }
+
+    /// The median of how long after being taken the recent products arrived, in seconds
+    fn offset(&self) -> Option<i64> {
+        if self.ages.len() < MIN_SKEW_SAMPLES {
+            return None;
+        }
+        let mut ages: Vec<i64> = self.ages.iter().copied().collect();
+        ages.sort_unstable();
+        Some(ages[ages.len() / 2])
+    }
+
+    pub fn report(&self) -> SkewReport {
+        let offset_secs = self.offset();
+        SkewReport {
+            offset_secs,
+            skewed: offset_secs.is_some_and(|offset| offset.abs() > self.max_skew.num_seconds()),
+            stale: self.stale,
+            source: self.source,
+        }
+    }
+
+    /// The current time, given the local time: the local time itself, or if product times are
+    /// trusted and the local clock looks wrong, the local time moved back (or forward) to match
+    /// them
+    pub fn utc(&self, local: DateTime<Utc>) -> DateTime<Utc> {
+        let report = self.report();
+        match report.offset_secs {
+            Some(offset) if report.skewed && self.source == TimeSource::Product => {
+                local - chrono::Duration::seconds(offset)
+            }
+            _ => local,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{ClockSkew, TimeSource, SKEW_SAMPLES};
+
+    #[test]
+    fn test_clock_skew() {
+        let local = Utc.with_ymd_and_hms(2022, 5, 4, 18, 0, 0).unwrap();
+        let minutes = |m| chrono::Duration::minutes(m);
+        let mut skew = ClockSkew::new().with_max_skew(Duration::from_secs(3600));
+        for age in [1, 2, 3, 2, 1] {
+            skew.record(local - minutes(age), local);
+        }
+        let report = skew.report();
+        assert_eq!(report.offset_secs, Some(120));
+        assert!(!report.skewed);
+        // a rebroadcast from hours ago doesn't mean the local clock is wrong
+        skew.record(local - minutes(300), local);
+        assert_eq!(skew.report().stale, 1);
+        assert!(!skew.report().skewed);
+
+        // a local clock that's 3 hours behind
+        let mut skew = ClockSkew::new().with_source(TimeSource::Product);
+        for age in 0..10 {
+            skew.record(local + minutes(180 - age), local);
+        }
+        let report = skew.report();
+        assert!(report.skewed && report.offset_secs.unwrap() < 0);
+        assert_eq!(skew.utc(local), local + minutes(175));
+        // unless product times are trusted, the local clock is still used
+        let skew = ClockSkew {
+            source: TimeSource::Local,
+            ..skew
+        };
+        assert_eq!(skew.utc(local), local);
+    }
+
+    #[test]
+    fn test_clock_corrected() {
+        let local = Utc.with_ymd_and_hms(2022, 5, 4, 18, 0, 0).unwrap();
+        let minutes = |m| chrono::Duration::minutes(m);
+        let mut skew = ClockSkew::new().with_source(TimeSource::Product);
+        // a local clock that's 3 hours behind, for a full window of products
+        for _ in 0..SKEW_SAMPLES {
+            skew.record(local + minutes(178), local);
+        }
+        assert!(skew.report().skewed);
+        assert_eq!(skew.utc(local), local + minutes(178));
+
+        // then it's set right, and products arrive a couple of minutes after being taken again
+        let corrected = local + minutes(180);
+        for _ in 0..SKEW_SAMPLES / 2 + 1 {
+            skew.record(corrected - minutes(2), corrected);
+        }
+        let report = skew.report();
+        assert_eq!(report.offset_secs, Some(120));
+        assert!(!report.skewed);
+        assert_eq!(skew.utc(corrected), corrected);
+    }
+}
@@ -0,0 +1,144 @@
+//! Comparing what two handler configurations write
+//!
+//! Before rolling out a change to how products are written (like a new image pipeline), the same
+//! stream can be run through the old and the new configuration, each into its own output root,
+//! and the two roots compared file by file with [`compare_roots`].  Files are compared by their
+//! size and CRC-32, so only the names of the ones that differ are reported, not how.
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{crc::calc_crc32, handlers::glob_match};
+
+/// Files that are different on every run, even when nothing else is, since they have receive
+/// times in them (the product index and image timing sidecars)
+pub const VOLATILE: &[&str] = &["index/*", "*.timing.json"];
+
+/// The size and CRC-32 of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHash {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+/// How two output roots differ
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RootDiff {
+    /// Files that are the same under both roots
+    pub same: usize,
+    /// Files that are only under the first root, by their path under it
+    pub only_a: Vec<PathBuf>,
+    /// Files that are only under the second root
+    pub only_b: Vec<PathBuf>,
+    /// Files that are under both roots, but aren't the same, with their hashes under each
+    pub changed: Vec<(PathBuf, FileHash, FileHash)>,
+}
+
+impl RootDiff {
+    /// Returns true if both roots have the same files
+    pub fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty() && self.changed.is_empty()
+    }
+
+    /// How many files differ
+    pub fn differences(&self) -> usize {
+        self.only_a.len() + self.only_b.len() + self.changed.len()
+    }
+}
+
+/// Hashes every file under `root` (by its path under it), except the ones whose path matches one
+/// of the `ignore` patterns (where `*` matches anything), and symlinks
+pub fn hash_root(root: impl AsRef<Path>, ignore: &[String]) -> io::Result<BTreeMap<PathBuf, FileHash>> {
+    let root = root.as_ref();
+    let mut hashes = BTreeMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let (path, file_type) = (entry.path(), entry.file_type()?);
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let name = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            let text = name.to_string_lossy();
+            if file_type.is_symlink() || ignore.iter().any(|p| glob_match(p.as_bytes(), text.as_bytes())) {
+                continue;
+            }
+            let data = std::fs::read(&path)?;
+            let hash = FileHash {
+                size: data.len() as u64,
+                crc32: calc_crc32(&data),
+            };
+            hashes.insert(name, hash);
+        }
+    }
+    Ok(hashes)
+}
+
+/// Compares the files under two roots, leaving out the ones matching the `ignore` patterns
+pub fn compare_roots(a: impl AsRef<Path>, b: impl AsRef<Path>, ignore: &[String]) -> io::Result<RootDiff> {
+    let a = hash_root(a, ignore)?;
+    let mut b = hash_root(b, ignore)?;
+    let mut diff = RootDiff::default();
+    for (path, hash_a) in a {
+        match b.remove(&path) {
+            Some(hash_b) if hash_b == hash_a => diff.same += 1,
+            Some(hash_b) => diff.changed.push((path, hash_a, hash_b)),
+            None => diff.only_a.push(path),
+        }
+    }
+    diff.only_b = b.into_keys().collect();
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{compare_roots, VOLATILE};
+
+    #[test]
+    fn test_compare_roots() {
+        let (a, b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        for (root, files) in [
+            (
+                &a,
+                [
+                    ("emwin/A.TXT", "same"),
+                    ("image.jpg", "old"),
+                    ("index/2022-05-04.jsonl", "1"),
+                ],
+            ),
+            (
+                &b,
+                [
+                    ("emwin/A.TXT", "same"),
+                    ("image.jpg", "new"),
+                    ("index/2022-05-04.jsonl", "2"),
+                ],
+            ),
+        ] {
+            for (name, data) in files {
+                let path = root.path().join(name);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, data).unwrap();
+            }
+        }
+        std::fs::write(b.path().join("image.png"), "new").unwrap();
+
+        let ignore: Vec<String> = VOLATILE.iter().map(|p| p.to_string()).collect();
+        let diff = compare_roots(a.path(), b.path(), &ignore).unwrap();
+        assert_eq!(diff.same, 1);
+        assert!(diff.only_a.is_empty());
+        assert_eq!(diff.only_b, [PathBuf::from("image.png")]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0, PathBuf::from("image.jpg"));
+        assert_eq!(diff.differences(), 2);
+        // without leaving out the index, it differs too
+        assert_eq!(compare_roots(a.path(), b.path(), &[]).unwrap().changed.len(), 2);
+        assert!(compare_roots(a.path(), a.path(), &[]).unwrap().is_empty());
+    }
+}
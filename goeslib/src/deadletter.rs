@@ -0,0 +1,225 @@
+//! Saving LRIT files that couldn't be processed
+//!
+//! When a handler fails permanently on an LRIT file, the file is written into a `dead-letter`
+//! directory, along with a JSON sidecar describing what went wrong.  This makes it possible to
+//! report decoder bugs with the offending data, and to re-process the files once the bug is fixed.
+//! So are the files whose headers the decoder couldn't parse, as they were received.
+//!
+//! The directory is size-capped: when it gets too large, the oldest files are removed.
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    handlers::HandlerFailure,
+    lrit::{HeaderError, LRIT},
+};
+
+/// The metadata sidecar that is written next to each dead-letter LRIT file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterInfo {
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub filetype_code: u8,
+    pub annotation: Option<String>,
+    /// The handlers that failed, and why
+    pub failures: Vec<DeadLetterFailure>,
+}
+
+/// The sidecar written next to an LRIT file whose headers couldn't be parsed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnparsedInfo {
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub apid: u16,
+    /// Why the headers couldn't be parsed
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetterFailure {
+    pub handler: String,
+    pub attempts: u32,
+    pub error: String,
+}
+
+pub struct DeadLetter {
+    dir: PathBuf,
+    /// Maximum total size of the dead-letter directory, in bytes
+    max_bytes: u64,
+    /// Included in file names, so that several failures in the same millisecond don't collide
+    counter: AtomicU32,
+}
+
+impl DeadLetter {
+    /// Uses the `dead-letter` directory under `root`, capped at 100 MiB
+    pub fn new(root: impl AsRef<Path>) -> DeadLetter {
+        DeadLetter {
+            dir: root.as_ref().join("dead-letter"),
+            max_bytes: 100 * 1024 * 1024,
+            counter: AtomicU32::new(0),
+        }
+    }
+
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Saves an LRIT file and a description of the failures
+    ///
+    /// Returns the path of the saved LRIT file
+    pub fn write(&self, lrit: &LRIT, failures: &[HandlerFailure]) -> io::Result<PathBuf> {
+        let received = Utc::now();
+        let annotation = lrit.headers.annotation.as_ref().map(|a| a.text.clone());
+        let name = self.name(
+            received,
+            &annotation
+                .as_deref()
+                .map(sanitize)
+                .unwrap_or_else(|| format!("vc{}-type{}", lrit.vcid, lrit.headers.primary.filetype_code)),
+        );
+
+        let info = DeadLetterInfo {
+            received,
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            annotation,
+            failures: failures
+                .iter()
+                .map(|f| DeadLetterFailure {
+                    handler: f.handler.clone(),
+                    attempts: f.attempts,
+                    error: f.error.to_string(),
+                })
+                .collect(),
+        };
+
+        self.save(&name, &lrit.to_bytes(), &serde_json::to_vec_pretty(&info)?)
+    }
+
+    /// Saves the bytes of an LRIT file whose headers couldn't be parsed, and why
+    ///
+    /// Returns the path of the saved file, which can be re-processed once the header parser is
+    /// fixed.
+    pub fn write_unparsed(&self, vcid: u8, apid: u16, bytes: &[u8], error: &HeaderError) -> io::Result<PathBuf> {
+        let received = Utc::now();
+        let name = self.name(received, &format!("vc{}-apid{}-unparsed", vcid, apid));
+        let info = UnparsedInfo {
+            received,
+            vcid,
+            apid,
+            error: error.to_string(),
+        };
+        self.save(&name, bytes, &serde_json::to_vec_pretty(&info)?)
+    }
+
+    /// A name for an entry, which starts with the time, so that sorting them by name sorts them
+    /// by age
+    fn name(&self, received: DateTime<Utc>, label: &str) -> String {
+        format!(
+            "{}-{:04}-{}",
+            received.format("%Y%m%dT%H%M%S%.3f"),
+            self.counter.fetch_add(1, Ordering::Relaxed) % 10000,
+            label
+        )
+    }
+
+    /// Writes an entry's LRIT file and sidecar, returning the path of the LRIT file
+    fn save(&self, name: &str, bytes: &[u8], sidecar: &[u8]) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        self.make_room((bytes.len() + sidecar.len()) as u64)?;
+
+        let lrit_path = self.dir.join(format!("{}.lrit", name));
+        std::fs::write(&lrit_path, bytes)?;
+        std::fs::write(self.dir.join(format!("{}.json", name)), sidecar)?;
+        Ok(lrit_path)
+    }
+
+    /// Removes the oldest entries until there's room for `needed` more bytes
+    fn make_room(&self, needed: u64) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                total += meta.len();
+                entries.push((entry.path(), meta.len()));
+            }
+        }
+        entries.sort();
+
+        for (path, len) in entries {
+            if total + needed <= self.max_bytes {
+                break;
+            }
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove old dead-letter file {}: {}", path.display(), e);
+            }
+            total = total.saturating_sub(len);
+        }
+        Ok(())
+    }
+}
+
+/// Replaces anything that isn't safe in a filename
+pub(crate) fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeadLetter;
+    use crate::{
+        handlers::{HandlerError, HandlerFailure},
+        lrit::LRIT,
+    };
+
+    #[test]
+    fn test_dead_letter() {
+        let dir = tempfile::tempdir().unwrap();
+        let dl = DeadLetter::new(dir.path()).max_bytes(2000);
+
+        let lrit = LRIT::from_bytes(20, &[0, 0, 16, 2, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+        let failures = vec![HandlerFailure {
+            handler: "TextHandler".to_string(),
+            attempts: 1,
+            error: HandlerError::Parse("bad"),
+        }];
+
+        let path = dl.write(&lrit, &failures).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), lrit.to_bytes());
+        let sidecar = std::fs::read_to_string(path.with_extension("json")).unwrap();
+        assert!(sidecar.contains("parse error: bad"));
+
+        // keep writing until old entries need to be removed
+        for _ in 0..20 {
+            dl.write(&lrit, &failures).unwrap();
+        }
+        let total: u64 = std::fs::read_dir(dl.dir())
+            .unwrap()
+            .map(|e| e.unwrap().metadata().unwrap().len())
+            .sum();
+        assert!(total <= 2000);
+        assert!(!path.exists());
+    }
+}
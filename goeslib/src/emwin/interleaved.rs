@@ -0,0 +1,324 @@
+//! Assembling EMWIN products from an interleaved stream of blocks
+//!
+//! Terrestrial EMWIN feeds (like the ByteBlaster rebroadcasts) don't send LRIT files.  Instead,
+//! every product is cut into 1024 byte blocks, and blocks from different products are interleaved
+//! on one byte stream, so a short warning doesn't have to wait behind a long image.  Each block is
+//! sent as:
+//!
+//! * 6 NUL bytes, to sync on
+//! * an 80 byte header, like `/PFMTRBOSMA.TXT/PN 1/PT 2/CS 63412/FD5/7/2022 12:01:13 PM`, padded
+//!   with spaces: the product's filename, the block number (from 1), the number of blocks in the
+//!   product, the sum of the block's data bytes, and when the product was issued
+//! * 1024 bytes of data (the last block of a product is padded with NULs)
+//! * 6 more NUL bytes
+//!
+//! Some feeds XOR every byte with `0xFF`.
+//!
+//! An [`InterleavedAssembler`] is given the stream as it arrives, and returns each product as an
+//! LRIT file once all of its blocks are in, so it can go through the same handlers as products from
+//! the downlink.  Text products are named like the products on the downlink (`A_SAUS70KWBC...`),
+//! using the WMO heading at the top of the text, and anything else keeps the filename from its
+//! header.
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::warn;
+
+use crate::{lrit::LRIT, sim::LritBuilder};
+
+/// The NUL bytes that each block starts with
+const SYNC_LEN: usize = 6;
+const HEADER_LEN: usize = 80;
+const DATA_LEN: usize = 1024;
+/// A whole block, including the NUL bytes after it
+pub const BLOCK_LEN: usize = SYNC_LEN + HEADER_LEN + DATA_LEN + SYNC_LEN;
+
+/// How many products can be in progress at once; past this, the oldest is dropped
+const MAX_PRODUCTS: usize = 64;
+
+/// Products from the stream are handled as if they came from the first EMWIN virtual channel
+const EMWIN_VCID: u8 = 20;
+
+/// The header of one block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// The product's filename, like `MTRBOSMA.TXT`
+    pub filename: String,
+    /// The number of this block, from 1
+    pub block: u32,
+    /// How many blocks the product has
+    pub total: u32,
+    /// The sum of the block's data bytes
+    pub checksum: u32,
+    pub date: DateTime<Utc>,
+}
+
+impl BlockHeader {
+    /// Parses an 80 byte block header
+    pub fn parse(header: &[u8]) -> Option<BlockHeader> {
+        let header = std::str::from_utf8(header).ok()?.trim_end_matches([' ', '\0']);
+        let rest = header.strip_prefix("/PF")?;
+        let (filename, rest) = rest.split_once("/PN")?;
+        let (block, rest) = rest.split_once("/PT")?;
+        let (total, rest) = rest.split_once("/CS")?;
+        let (checksum, date) = rest.split_once("/FD")?;
+        let date = NaiveDateTime::parse_from_str(date.trim(), "%m/%d/%Y %I:%M:%S %p").ok()?;
+        let header = BlockHeader {
+            filename: filename.trim().to_string(),
+            block: block.trim().parse().ok()?,
+            total: total.trim().parse().ok()?,
+            checksum: checksum.trim().parse().ok()?,
+            date: Utc.from_utc_datetime(&date),
+        };
+        match header.block >= 1 && header.block <= header.total && !header.filename.is_empty() {
+            true => Some(header),
+            false => None,
+        }
+    }
+}
+
+/// The blocks received so far of one product
+struct Partial {
+    filename: String,
+    date: DateTime<Utc>,
+    total: u32,
+    blocks: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Turns an interleaved EMWIN stream back into products, see the [module docs](self)
+pub struct InterleavedAssembler {
+    xor: bool,
+    /// Bytes that haven't made a whole block yet
+    buf: Vec<u8>,
+    /// Products in progress, oldest first
+    products: VecDeque<Partial>,
+    /// Numbers the products, since the names on the downlink have a sequence number
+    sequence: u32,
+}
+
+impl Default for InterleavedAssembler {
+    fn default() -> Self {
+        InterleavedAssembler::new()
+    }
+}
+
+impl InterleavedAssembler {
+    pub fn new() -> InterleavedAssembler {
+        InterleavedAssembler {
+            xor: false,
+            buf: Vec::new(),
+            products: VecDeque::new(),
+            sequence: 0,
+        }
+    }
+
+    /// The stream has every byte XORed with `0xFF` (like the ByteBlaster protocol)
+    pub fn with_xor(mut self, xor: bool) -> Self {
+        self.xor = xor;
+        self
+    }
+
+    /// Adds the next bytes of the stream, returning the products that they finish
+    ///
+    /// Bytes that don't start a valid block are skipped, until the stream syncs up again.
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<LRIT> {
+        let xor = if self.xor { 0xff } else { 0 };
+        self.buf.extend(bytes.iter().map(|b| b ^ xor));
+        let mut lrits = Vec::new();
+        let mut start = 0;
+        loop {
+            match find_sync(&self.buf[start..]) {
+                Some(offset) => start += offset,
+                None => {
+                    // keep what might be the start of a sync
+                    start = start.max(self.buf.len().saturating_sub(SYNC_LEN));
+                    break;
+                }
+            }
+            if self.buf.len() - start < BLOCK_LEN - SYNC_LEN {
+                break;
+            }
+            let block = &self.buf[start + SYNC_LEN..start + BLOCK_LEN - SYNC_LEN];
+            let (header, data) = block.split_at(HEADER_LEN);
+            let header = match BlockHeader::parse(header) {
+                Some(header) => header,
+                None => {
+                    // a run of NULs in some data, or a corrupt header
+                    start += 1;
+                    continue;
+                }
+            };
+            start += BLOCK_LEN - SYNC_LEN;
+            let sum: u32 = data.iter().map(|&b| b as u32).sum();
+            if sum != header.checksum {
+                warn!(
+                    "Dropping block {} of {} of {}, which fails its checksum",
+                    header.block, header.total, header.filename
+                );
+                continue;
+            }
+            let data = data.to_vec();
+            if let Some(lrit) = self.add_block(header, data) {
+                lrits.push(lrit);
+            }
+        }
+        self.buf.drain(..start);
+        lrits
+    }
+
+    fn add_block(&mut self, header: BlockHeader, data: Vec<u8>) -> Option<LRIT> {
+        let position = self
+            .products
+            .iter()
+            .position(|p| p.filename == header.filename && p.date == header.date && p.total == header.total);
+        let index = match position {
+            Some(index) => index,
+            None => {
+                if self.products.len() >= MAX_PRODUCTS {
+                    if let Some(oldest) = self.products.pop_front() {
+                        warn!(
+                            "Dropping {} after receiving {} of its {} blocks",
+                            oldest.filename,
+                            oldest.blocks.len(),
+                            oldest.total
+                        );
+                    }
+                }
+                self.products.push_back(Partial {
+                    filename: header.filename.clone(),
+                    date: header.date,
+                    total: header.total,
+                    blocks: BTreeMap::new(),
+                });
+                self.products.len() - 1
+            }
+        };
+        let product = &mut self.products[index];
+        product.blocks.insert(header.block, data);
+        if product.blocks.len() < product.total as usize {
+            return None;
+        }
+        let product = self.products.remove(index)?;
+        self.sequence = (self.sequence + 1) % 1_000_000;
+        Some(product.finish(self.sequence))
+    }
+}
+
+impl Partial {
+    fn finish(self, sequence: u32) -> LRIT {
+        let mut data: Vec<u8> = self.blocks.into_values().flatten().collect();
+        let is_text = self.filename.to_ascii_uppercase().ends_with(".TXT");
+        if is_text {
+            let len = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+            data.truncate(len);
+        }
+        let name = match wmo_heading(&data) {
+            Some(heading) if is_text => format!(
+                "A_{}_C_KWIN_{}_{:06}-3-{}",
+                heading,
+                self.date.format("%Y%m%d%H%M%S"),
+                sequence,
+                self.filename
+            ),
+            _ => self.filename,
+        };
+        let file = LritBuilder::new(2).annotation(&name).build(&data);
+        LRIT::from_bytes(EMWIN_VCID, &file).expect("built LRIT files can be parsed")
+    }
+}
+
+fn find_sync(buf: &[u8]) -> Option<usize> {
+    buf.windows(SYNC_LEN + 1)
+        .position(|w| w[..SYNC_LEN].iter().all(|&b| b == 0) && w[SYNC_LEN] == b'/')
+}
+
+/// The WMO heading at the top of a text product (like `SAUS70 KWBC 071200`), run together like it
+/// is in names on the downlink (`SAUS70KWBC071200`)
+fn wmo_heading(data: &[u8]) -> Option<String> {
+    let first_line = data.split(|&b| b == b'\n').find(|l| !l.trim_ascii().is_empty())?;
+    let first_line = std::str::from_utf8(first_line).ok()?;
+    let mut parts = first_line.split_whitespace();
+    let (ttaaii, cccc, yygggg) = (parts.next()?, parts.next()?, parts.next()?);
+    let ok = ttaaii.len() == 6
+        && ttaaii.bytes().all(|b| b.is_ascii_alphanumeric())
+        && cccc.len() == 4
+        && cccc.bytes().all(|b| b.is_ascii_alphabetic())
+        && yygggg.len() == 6
+        && yygggg.bytes().all(|b| b.is_ascii_digit());
+    match ok {
+        true => Some(format!("{}{}{}", ttaaii, cccc, yygggg)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockHeader, InterleavedAssembler, BLOCK_LEN, DATA_LEN, SYNC_LEN};
+
+    /// Cuts a product into blocks, the way an interleaved feed sends it
+    fn blocks(filename: &str, date: &str, data: &[u8]) -> Vec<Vec<u8>> {
+        let chunks: Vec<_> = data.chunks(DATA_LEN).collect();
+        let mut blocks = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut chunk = chunk.to_vec();
+            chunk.resize(DATA_LEN, 0);
+            let sum: u32 = chunk.iter().map(|&b| b as u32).sum();
+            let header = format!(
+                "/PF{:<12}/PN {:<6}/PT {:<6}/CS {:<6}/FD{}",
+                filename,
+                i + 1,
+                chunks.len(),
+                sum,
+                date
+            );
+            let mut block = vec![0; SYNC_LEN];
+            block.extend_from_slice(format!("{:<80}", header).as_bytes());
+            block.extend_from_slice(&chunk);
+            block.extend_from_slice(&[0; SYNC_LEN]);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_interleaved() {
+        let header =
+            BlockHeader::parse(b"/PFMTRBOSMA.TXT/PN 1     /PT 2     /CS 63412 /FD5/7/2022 12:01:13 PM").unwrap();
+        assert_eq!(
+            (header.filename.as_str(), header.block, header.total),
+            ("MTRBOSMA.TXT", 1, 2)
+        );
+        assert_eq!(header.date.to_rfc3339(), "2022-05-07T12:01:13+00:00");
+
+        let metar = "SAUS70 KWBC 071200\r\r\nMETAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992=\r\r\n".repeat(20);
+        let image: Vec<u8> = (0..1500).map(|i| (i % 251) as u8).collect();
+        let text = blocks("MTRBOSMA.TXT", "5/7/2022 12:01:13 PM", metar.as_bytes());
+        let gif = blocks("RADUMSVY.GIF", "5/7/2022 12:02:00 PM", &image);
+        // interleaved, with some garbage and a corrupt block of the text
+        let mut bad = text[1].clone();
+        bad[200] ^= 1;
+        let mut stream = b"noise".to_vec();
+        for block in [&text[0], &gif[0], &bad, &gif[1], &text[1]] {
+            stream.extend_from_slice(block);
+        }
+        assert_eq!(text[0].len(), BLOCK_LEN);
+
+        let mut assembler = InterleavedAssembler::new().with_xor(true);
+        let xored: Vec<u8> = stream.iter().map(|b| b ^ 0xff).collect();
+        // split in awkward places
+        let mut lrits = Vec::new();
+        for chunk in xored.chunks(700) {
+            lrits.extend(assembler.push(chunk));
+        }
+        assert_eq!(lrits.len(), 2);
+        assert_eq!(lrits[0].headers.annotation.as_ref().unwrap().text, "RADUMSVY.GIF");
+        assert_eq!(lrits[0].data.len(), 2048);
+        assert_eq!(&lrits[0].data[..1500], &image[..]);
+        assert_eq!(lrits[1].vcid, 20);
+        assert_eq!(
+            lrits[1].headers.annotation.as_ref().unwrap().text,
+            "A_SAUS70KWBC071200_C_KWIN_20220507120113_000002-3-MTRBOSMA.TXT"
+        );
+        assert_eq!(lrits[1].data, metar.as_bytes());
+    }
+}
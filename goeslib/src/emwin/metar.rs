@@ -0,0 +1,685 @@
+//! Decoding METAR and TAF bulletins
+//!
+//! Surface observations (METAR and SPECI) and terminal forecasts (TAF) are sent over EMWIN as
+//! bulletins that hold reports for one or more stations, each ending with `=`.  Only the main body
+//! of each report is decoded; remarks are kept as text.
+//!
+//! # References
+//!
+//! * Federal Meteorological Handbook No. 1, chapter 12 (https://www.icams-portal.gov/resources/ofcm/fmh/FMH1/fmh1_2019.pdf)
+//! * WMO Manual on Codes, FM 15 (METAR) and FM 51 (TAF)
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReportKind {
+    Metar,
+    /// A special (unscheduled) observation, sent when conditions change quickly
+    Speci,
+}
+
+/// A day of the month and time, as used in reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DayTime {
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl DayTime {
+    /// Parses `DDHHMMZ` (or `DDHHMM`)
+    fn parse(s: &str) -> Option<DayTime> {
+        let s = s.strip_suffix('Z').unwrap_or(s);
+        if s.len() != 6 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(DayTime {
+            day: s[0..2].parse().ok()?,
+            hour: s[2..4].parse().ok()?,
+            minute: s[4..6].parse().ok()?,
+        })
+    }
+
+    /// Parses `DDHH`, as used for TAF periods
+    fn parse_day_hour(s: &str) -> Option<DayTime> {
+        if s.len() != 4 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(DayTime {
+            day: s[0..2].parse().ok()?,
+            hour: s[2..4].parse().ok()?,
+            minute: 0,
+        })
+    }
+
+    /// Works out the full date, given a time that's close to it (like when the bulletin was sent)
+    ///
+    /// Reports only give the day of the month, so this picks the closest month that has that day.
+    pub fn resolve(&self, near: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let in_month = |year: i32, month: u32| {
+            // hour 24 is used for the end of TAF periods
+            NaiveDate::from_ymd_opt(year, month, self.day as u32)
+                .and_then(|d| d.and_hms_opt(0, self.minute as u32, 0))
+                .map(|t| Utc.from_utc_datetime(&t) + Duration::hours(self.hour as i64))
+        };
+        let (year, month) = (near.year(), near.month());
+        let (prev_year, prev_month) = if month == 1 { (year - 1, 12) } else { (year, month - 1) };
+        let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+        IntoIterator::into_iter([
+            in_month(prev_year, prev_month),
+            in_month(year, month),
+            in_month(next_year, next_month),
+        ])
+        .flatten()
+        .min_by_key(|t| (*t - near).num_seconds().abs())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SpeedUnit {
+    Knots,
+    MetersPerSecond,
+    KilometersPerHour,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Wind {
+    /// Degrees true, or `None` if the direction is variable
+    pub direction: Option<u16>,
+    pub speed: u16,
+    pub gust: Option<u16>,
+    pub unit: SpeedUnit,
+}
+
+impl Wind {
+    /// Parses something like `27010KT`, `VRB03KT`, or `18015G25MPS`
+    fn parse(s: &str) -> Option<Wind> {
+        let (s, unit) = if let Some(s) = s.strip_suffix("KT") {
+            (s, SpeedUnit::Knots)
+        } else if let Some(s) = s.strip_suffix("MPS") {
+            (s, SpeedUnit::MetersPerSecond)
+        } else if let Some(s) = s.strip_suffix("KMH") {
+            (s, SpeedUnit::KilometersPerHour)
+        } else {
+            return None;
+        };
+        if s.len() < 5 {
+            return None;
+        }
+        let direction = match s.get(..3)? {
+            "VRB" => None,
+            dir => Some(dir.parse().ok()?),
+        };
+        let (speed, gust) = match s[3..].split_once('G') {
+            Some((speed, gust)) => (speed, Some(gust.parse().ok()?)),
+            None => (&s[3..], None),
+        };
+        Some(Wind {
+            direction,
+            speed: speed.parse().ok()?,
+            gust,
+            unit,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Visibility {
+    Meters(u32),
+    StatuteMiles(f32),
+    /// Less than the given number of statute miles (like `M1/4SM`)
+    LessThanMiles(f32),
+    /// More than the given number of statute miles (like `P6SM`, which is used in TAFs)
+    MoreThanMiles(f32),
+    /// Ceiling and visibility OK (visibility of 10 km or more, and no significant clouds or weather)
+    Cavok,
+}
+
+impl Visibility {
+    /// Parses a visibility in meters (`9999`, or `4000NE`) or statute miles (`10SM`, `1/2SM`)
+    fn parse(s: &str) -> Option<Visibility> {
+        if s == "CAVOK" {
+            return Some(Visibility::Cavok);
+        }
+        if let Some(miles) = s.strip_suffix("SM") {
+            return Some(if let Some(m) = miles.strip_prefix('M') {
+                Visibility::LessThanMiles(parse_fraction(m)?)
+            } else if let Some(m) = miles.strip_prefix('P') {
+                Visibility::MoreThanMiles(parse_fraction(m)?)
+            } else {
+                Visibility::StatuteMiles(parse_fraction(miles)?)
+            });
+        }
+        // a direction can follow, when visibility isn't the same in every direction
+        let meters = s.get(..4)?;
+        if !meters.bytes().all(|b| b.is_ascii_digit()) || !s[4..].bytes().all(|b| b"NSEW".contains(&b)) {
+            return None;
+        }
+        Some(Visibility::Meters(meters.parse().ok()?))
+    }
+}
+
+/// Parses a number like `3` or `3/4`
+fn parse_fraction(s: &str) -> Option<f32> {
+    match s.split_once('/') {
+        Some((n, d)) => Some(n.parse::<f32>().ok()? / d.parse::<f32>().ok()?),
+        None => s.parse().ok(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CloudLayer {
+    /// FEW, SCT, BKN, OVC, or VV (vertical visibility into an obscured sky)
+    pub cover: String,
+    /// Height of the base, in feet above ground
+    pub height_ft: Option<u32>,
+    /// CB (cumulonimbus) or TCU (towering cumulus)
+    pub cloud_type: Option<String>,
+}
+
+impl CloudLayer {
+    fn parse(s: &str) -> Option<CloudLayer> {
+        let cover = *["FEW", "SCT", "BKN", "OVC", "VV"].iter().find(|c| s.starts_with(*c))?;
+        let rest = &s[cover.len()..];
+        let height = rest.get(..3)?;
+        let cloud_type = match &rest[3..] {
+            "" => None,
+            "CB" | "TCU" => Some(rest[3..].to_string()),
+            _ => return None,
+        };
+        Some(CloudLayer {
+            cover: cover.to_string(),
+            height_ft: height.parse::<u32>().ok().map(|h| h * 100),
+            cloud_type,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Altimeter {
+    InchesOfMercury(f32),
+    Hectopascals(u16),
+}
+
+impl Altimeter {
+    fn parse(s: &str) -> Option<Altimeter> {
+        let digits = s
+            .get(1..)
+            .filter(|d| d.len() == 4 && d.bytes().all(|b| b.is_ascii_digit()))?;
+        match s.as_bytes()[0] {
+            b'A' => Some(Altimeter::InchesOfMercury(digits.parse::<f32>().ok()? / 100.0)),
+            b'Q' => Some(Altimeter::Hectopascals(digits.parse().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a temperature like `18` or `M02` (minus 2)
+fn parse_temperature(s: &str) -> Option<i8> {
+    match s.strip_prefix('M') {
+        Some(t) => t.parse::<i8>().ok().map(|t| -t),
+        None => s.parse().ok(),
+    }
+}
+
+/// Returns true if this looks like a present weather group, like `-RA`, `+TSRA`, `VCSH`, or `BR`
+fn is_weather(s: &str) -> bool {
+    const CODES: [&str; 31] = [
+        "MI", "BC", "PR", "DR", "BL", "SH", "TS", "FZ", "DZ", "RA", "SN", "SG", "IC", "PL", "GR", "GS", "UP", "BR",
+        "FG", "FU", "VA", "DU", "SA", "HZ", "PY", "PO", "SQ", "FC", "SS", "DS", "RE",
+    ];
+    let s = s
+        .strip_prefix('-')
+        .or_else(|| s.strip_prefix('+'))
+        .or_else(|| s.strip_prefix("VC"))
+        .unwrap_or(s);
+    // an odd length leaves a short chunk at the end, which doesn't match any code
+    !s.is_empty()
+        && s.as_bytes()
+            .chunks(2)
+            .all(|c| CODES.iter().any(|code| code.as_bytes() == c))
+}
+
+/// Weather conditions, which make up most of a METAR and each group of a TAF
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Conditions {
+    pub wind: Option<Wind>,
+    pub visibility: Option<Visibility>,
+    /// Present (or forecast) weather groups, like `-RA` or `+TSRA`
+    pub weather: Vec<String>,
+    /// Cloud layers, from lowest to highest; empty for a clear sky (or if not reported)
+    pub clouds: Vec<CloudLayer>,
+}
+
+impl Conditions {
+    /// Tries to use a token as part of the conditions, returning false if it isn't one
+    fn parse_token(&mut self, token: &str, next: Option<&str>) -> bool {
+        if let Some(wind) = Wind::parse(token) {
+            self.wind = Some(wind);
+        } else if let Some(vis) = Visibility::parse(token) {
+            self.visibility = Some(vis);
+        } else if let Some(layer) = CloudLayer::parse(token) {
+            self.clouds.push(layer);
+        } else if is_weather(token) {
+            self.weather.push(token.to_string());
+        } else if token.len() == 1
+            && token.bytes().all(|b| b.is_ascii_digit())
+            && matches!(next, Some(n) if n.ends_with("SM"))
+        {
+            // the whole part of a visibility like `1 1/2SM`, which is added in when the fraction is parsed
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Adds in the whole miles of a visibility like `1 1/2SM`
+    fn add_whole_miles(&mut self, prev: &str) {
+        if let (Some(Visibility::StatuteMiles(miles)), Ok(whole)) = (&mut self.visibility, prev.parse::<f32>()) {
+            *miles += whole;
+        }
+    }
+}
+
+/// A surface observation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metar {
+    pub kind: ReportKind,
+    /// The ICAO identifier of the station, like `KBOS`
+    pub station: String,
+    pub time: DayTime,
+    /// Made by an automated station, with no human observer
+    pub auto: bool,
+    #[serde(flatten)]
+    pub conditions: Conditions,
+    /// Degrees Celsius
+    pub temperature: Option<i8>,
+    /// Degrees Celsius
+    pub dew_point: Option<i8>,
+    pub altimeter: Option<Altimeter>,
+    pub remarks: Option<String>,
+    /// The whole report, as it was received
+    pub raw: String,
+}
+
+/// How a TAF group relates to the groups before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TafChange {
+    /// The first group, which holds from the start of the forecast
+    Initial,
+    /// From this time on, replacing the groups before it (`FM`)
+    From(DayTime),
+    /// Gradually changing over this period (`BECMG`)
+    Becoming(DayTime, DayTime),
+    /// Temporary fluctuations during this period (`TEMPO`)
+    Temporary(DayTime, DayTime),
+    /// A chance (in percent) of these conditions during this period (`PROB30` or `PROB40`, which
+    /// can also be followed by `TEMPO`)
+    Probability(u8, DayTime, DayTime),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TafGroup {
+    pub change: TafChange,
+    #[serde(flatten)]
+    pub conditions: Conditions,
+}
+
+/// A terminal aerodrome forecast
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Taf {
+    pub station: String,
+    pub issued: DayTime,
+    /// The period the forecast covers
+    pub valid: Option<(DayTime, DayTime)>,
+    pub amended: bool,
+    pub groups: Vec<TafGroup>,
+    /// The whole report, as it was received
+    pub raw: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Report {
+    Metar(Metar),
+    Taf(Taf),
+}
+
+impl Report {
+    pub fn station(&self) -> &str {
+        match self {
+            Report::Metar(m) => &m.station,
+            Report::Taf(t) => &t.station,
+        }
+    }
+}
+
+/// Parses a `DDHH/DDHH` period
+fn parse_period(s: &str) -> Option<(DayTime, DayTime)> {
+    let (from, to) = s.split_once('/')?;
+    Some((DayTime::parse_day_hour(from)?, DayTime::parse_day_hour(to)?))
+}
+
+fn is_station(s: &str) -> bool {
+    s.len() == 4
+        && s.as_bytes()[0].is_ascii_uppercase()
+        && s.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+}
+
+impl Metar {
+    /// Parses a single report, like `METAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992 RMK AO2`
+    ///
+    /// The `METAR` (or `SPECI`) at the start is optional; without it the report is taken to be a
+    /// METAR.
+    pub fn parse(report: &str) -> Option<Metar> {
+        let raw = report.split_whitespace().collect::<Vec<_>>().join(" ");
+        let raw = raw.trim_end_matches('=').trim_end().to_string();
+        let (body, remarks) = match raw.split_once(" RMK") {
+            Some((body, remarks)) => (body, Some(remarks.trim().to_string()).filter(|r| !r.is_empty())),
+            None => (raw.as_str(), None),
+        };
+
+        let mut tokens = body.split(' ').peekable();
+        let kind = match tokens.next_if(|t| *t == "METAR" || *t == "SPECI") {
+            Some("SPECI") => ReportKind::Speci,
+            _ => ReportKind::Metar,
+        };
+        tokens.next_if_eq(&"COR");
+        let station = tokens.next().filter(|s| is_station(s))?.to_string();
+        let time = DayTime::parse(tokens.next()?)?;
+        if tokens.peek() == Some(&"NIL") {
+            return None;
+        }
+
+        let mut metar = Metar {
+            kind,
+            station,
+            time,
+            auto: false,
+            conditions: Conditions::default(),
+            temperature: None,
+            dew_point: None,
+            altimeter: None,
+            remarks,
+            raw: raw.clone(),
+        };
+        let tokens: Vec<&str> = tokens.collect();
+        for (i, token) in tokens.iter().enumerate() {
+            match *token {
+                "AUTO" => metar.auto = true,
+                // a trend forecast follows, which isn't decoded
+                "NOSIG" | "BECMG" | "TEMPO" => break,
+                t if metar.conditions.parse_token(t, tokens.get(i + 1).copied()) => {
+                    if t.ends_with("SM") && i > 0 {
+                        metar.conditions.add_whole_miles(tokens[i - 1]);
+                    }
+                }
+                t => {
+                    if let Some(altimeter) = Altimeter::parse(t) {
+                        metar.altimeter = Some(altimeter);
+                    } else if let Some((temp, dew)) = t.split_once('/').filter(|_| !t.starts_with('R')) {
+                        if let Some(temp) = parse_temperature(temp) {
+                            metar.temperature = Some(temp);
+                            metar.dew_point = parse_temperature(dew);
+                        }
+                    }
+                    // anything else (like runway visual range or variable wind direction) is skipped
+                }
+            }
+        }
+        Some(metar)
+    }
+}
+
+impl Taf {
+    /// Parses a single forecast, like `TAF KBOS 071130Z 0712/0818 27010KT P6SM FEW050 FM071800 ...`
+    pub fn parse(report: &str) -> Option<Taf> {
+        let raw = report.split_whitespace().collect::<Vec<_>>().join(" ");
+        let raw = raw.trim_end_matches('=').trim_end().to_string();
+        let mut tokens = raw.split(' ').peekable();
+        tokens.next_if_eq(&"TAF");
+        let amended = tokens.next_if(|t| *t == "AMD" || *t == "COR").is_some();
+        let station = tokens.next().filter(|s| is_station(s))?.to_string();
+        let issued = DayTime::parse(tokens.next()?)?;
+        if tokens.peek() == Some(&"NIL") {
+            return None;
+        }
+        let valid = tokens.next_if(|t| parse_period(t).is_some()).and_then(parse_period);
+
+        let mut groups = vec![TafGroup {
+            change: TafChange::Initial,
+            conditions: Conditions::default(),
+        }];
+        let tokens: Vec<&str> = tokens.collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            let period = tokens.get(i + 1).and_then(|t| parse_period(t));
+            let change = if let Some(from) = token.strip_prefix("FM").and_then(DayTime::parse) {
+                Some(TafChange::From(from))
+            } else if let (Some(percent), Some((from, to))) = (token.strip_prefix("PROB"), period) {
+                i += 1;
+                // PROB30 TEMPO 0718/0722
+                let percent = percent.parse().ok();
+                percent.map(|p| TafChange::Probability(p, from, to))
+            } else if let ("BECMG", Some((from, to))) = (token, period) {
+                i += 1;
+                Some(TafChange::Becoming(from, to))
+            } else if let ("TEMPO", Some((from, to))) = (token, period) {
+                i += 1;
+                Some(TafChange::Temporary(from, to))
+            } else if let (Some(percent), Some("TEMPO")) = (token.strip_prefix("PROB"), tokens.get(i + 1).copied()) {
+                let period = tokens.get(i + 2).and_then(|t| parse_period(t));
+                i += 2;
+                match (percent.parse().ok(), period) {
+                    (Some(p), Some((from, to))) => Some(TafChange::Probability(p, from, to)),
+                    _ => None,
+                }
+            } else {
+                None
+            };
+
+            match change {
+                Some(change) => groups.push(TafGroup {
+                    change,
+                    conditions: Conditions::default(),
+                }),
+                None => {
+                    let conditions = &mut groups.last_mut().expect("there's always a group").conditions;
+                    if conditions.parse_token(token, tokens.get(i + 1).copied()) && token.ends_with("SM") && i > 0 {
+                        conditions.add_whole_miles(tokens[i - 1]);
+                    }
+                    // anything else (like wind shear or max/min temperatures) is skipped
+                }
+            }
+            i += 1;
+        }
+
+        Some(Taf {
+            station,
+            issued,
+            valid,
+            amended,
+            groups,
+            raw,
+        })
+    }
+}
+
+/// Parses all the reports in a bulletin
+///
+/// The bulletin can start with its WMO heading (like `SAUS70 KWBC 071200`) and AWIPS identifier
+/// (like `MTRBOS`), which are skipped.  The kind of report is taken from the `METAR`, `SPECI`, or
+/// `TAF` keyword before the first report, and applies to all the reports that follow it.  Reports
+/// that can't be parsed (or are `NIL`) are left out.
+pub fn parse_bulletin(text: &str) -> Vec<Report> {
+    let mut reports = Vec::new();
+    let mut taf = false;
+    for chunk in text.split('=') {
+        let tokens: Vec<&str> = chunk.split_whitespace().collect();
+        // the report starts at the keyword, or at the station (which is followed by the time)
+        let start = tokens.iter().enumerate().find_map(|(i, t)| match *t {
+            "METAR" | "SPECI" => {
+                taf = false;
+                Some(i)
+            }
+            "TAF" => {
+                taf = true;
+                Some(i)
+            }
+            // the WMO heading also has a station and time, but without the Z
+            t if is_station(t)
+                && matches!(tokens.get(i + 1), Some(time) if time.ends_with('Z') && DayTime::parse(time).is_some()) =>
+            {
+                Some(i)
+            }
+            _ => None,
+        });
+        let report = match start {
+            Some(start) => tokens[start..].join(" "),
+            None => continue,
+        };
+        let parsed = if taf {
+            Taf::parse(&report).map(Report::Taf)
+        } else {
+            Metar::parse(&report).map(Report::Metar)
+        };
+        reports.extend(parsed);
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> chrono::DateTime<Utc> {
+        Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(year, month, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_metar() {
+        let m = Metar::parse(
+            "METAR KBOS 071154Z AUTO 27010G18KT 1 1/2SM -RA BR FEW008 BKN050CB M02/M05 A2992 RMK AO2 SLP132=",
+        )
+        .unwrap();
+        assert_eq!(m.kind, ReportKind::Metar);
+        assert_eq!(m.station, "KBOS");
+        assert_eq!(
+            m.time,
+            DayTime {
+                day: 7,
+                hour: 11,
+                minute: 54
+            }
+        );
+        assert!(m.auto);
+        assert_eq!(
+            m.conditions.wind,
+            Some(Wind {
+                direction: Some(270),
+                speed: 10,
+                gust: Some(18),
+                unit: SpeedUnit::Knots
+            })
+        );
+        assert_eq!(m.conditions.visibility, Some(Visibility::StatuteMiles(1.5)));
+        assert_eq!(m.conditions.weather, vec!["-RA", "BR"]);
+        assert_eq!(m.conditions.clouds.len(), 2);
+        assert_eq!(m.conditions.clouds[1].height_ft, Some(5000));
+        assert_eq!(m.conditions.clouds[1].cloud_type.as_deref(), Some("CB"));
+        assert_eq!((m.temperature, m.dew_point), (Some(-2), Some(-5)));
+        assert_eq!(m.altimeter, Some(Altimeter::InchesOfMercury(29.92)));
+        assert_eq!(m.remarks.as_deref(), Some("AO2 SLP132"));
+
+        let m = Metar::parse("SPECI EGLL 071150Z VRB03MPS 9999 R27L/1500 NSC 12/ Q1013 NOSIG").unwrap();
+        assert_eq!(m.kind, ReportKind::Speci);
+        assert_eq!(m.conditions.wind.unwrap().direction, None);
+        assert_eq!(m.conditions.visibility, Some(Visibility::Meters(9999)));
+        assert_eq!((m.temperature, m.dew_point), (Some(12), None));
+        assert_eq!(m.altimeter, Some(Altimeter::Hectopascals(1013)));
+
+        assert_eq!(Metar::parse("METAR KBOS 071154Z NIL"), None);
+
+        // a garbled wind group, with a character that straddles the direction
+        let m = Metar::parse("METAR KBOS 071154Z 27°10KT 10SM A2992").unwrap();
+        assert_eq!(m.conditions.wind, None);
+        assert_eq!(m.altimeter, Some(Altimeter::InchesOfMercury(29.92)));
+    }
+
+    #[test]
+    fn test_taf() {
+        let t = Taf::parse(
+            "TAF AMD KBOS 071130Z 0712/0818 27010KT P6SM FEW050 \
+             FM071800 30015G25KT 3SM -SHRA BKN020 \
+             TEMPO 0720/0722 1SM TSRA OVC010CB \
+             PROB30 0802/0806 VRB05KT",
+        )
+        .unwrap();
+        assert_eq!(t.station, "KBOS");
+        assert!(t.amended);
+        assert_eq!(
+            t.valid,
+            Some((
+                DayTime {
+                    day: 7,
+                    hour: 12,
+                    minute: 0
+                },
+                DayTime {
+                    day: 8,
+                    hour: 18,
+                    minute: 0
+                }
+            ))
+        );
+        assert_eq!(t.groups.len(), 4);
+        assert_eq!(t.groups[0].conditions.visibility, Some(Visibility::MoreThanMiles(6.0)));
+        assert_eq!(
+            t.groups[1].change,
+            TafChange::From(DayTime {
+                day: 7,
+                hour: 18,
+                minute: 0
+            })
+        );
+        assert_eq!(t.groups[1].conditions.weather, vec!["-SHRA"]);
+        assert!(matches!(t.groups[2].change, TafChange::Temporary(..)));
+        assert_eq!(t.groups[2].conditions.clouds[0].cover, "OVC");
+        assert!(matches!(t.groups[3].change, TafChange::Probability(30, ..)));
+    }
+
+    #[test]
+    fn test_bulletin() {
+        let text = "\r\r\n000 \r\r\nSAUS70 KWBC 071200\r\r\nMTRBOS\r\r\nMETAR KBOS 071154Z 27010KT 10SM CLR 18/05 A2992=\r\r\n\
+                    KJFK 071151Z 18005KT 10SM\r\r\n     SCT250 20/10 A3001=\r\r\nKLGA 071151Z NIL=\r\r\n";
+        let reports = parse_bulletin(text);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].station(), "KBOS");
+        match &reports[1] {
+            Report::Metar(m) => {
+                assert_eq!(m.station, "KJFK");
+                assert_eq!(m.conditions.clouds[0].height_ft, Some(25000));
+                assert_eq!(m.raw, "KJFK 071151Z 18005KT 10SM SCT250 20/10 A3001");
+            }
+            other => panic!("{:?}", other),
+        }
+
+        let reports = parse_bulletin("FTUS41 KBOX 071130\nTAFBOS\nTAF\nKBOS 071130Z 0712/0818 27010KT P6SM FEW050=\n");
+        assert!(matches!(&reports[..], [Report::Taf(t)] if t.station == "KBOS"));
+
+        let time = DayTime {
+            day: 31,
+            hour: 23,
+            minute: 54,
+        };
+        assert_eq!(time.resolve(utc(2022, 6, 1, 0, 30)), Some(utc(2022, 5, 31, 23, 54)));
+        assert_eq!(time.resolve(utc(2022, 7, 31, 23, 58)), Some(utc(2022, 7, 31, 23, 54)));
+    }
+}
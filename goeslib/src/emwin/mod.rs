@@ -1,11 +1,46 @@
 //! Various ulitities for parsing EMWIN and NWS data
 //!
 //!
+pub mod interleaved;
+pub mod metar;
 pub mod nws;
+pub mod shef;
+pub mod sounding;
+pub mod swpc;
+pub mod ugc;
 pub mod wmo;
+pub mod zones;
 
 use chrono::Utc;
 
+use crate::i18n::Language;
+
+/// Why an EMWIN filename couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmwinNameError {
+    /// It isn't laid out like an EMWIN filename
+    NotEmwin,
+    /// Its WMO heading has a code that isn't in the tables
+    Wmo(wmo::WmoError),
+}
+
+impl std::fmt::Display for EmwinNameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmwinNameError::NotEmwin => write!(f, "not an EMWIN filename"),
+            EmwinNameError::Wmo(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EmwinNameError {}
+
+impl From<wmo::WmoError> for EmwinNameError {
+    fn from(e: wmo::WmoError) -> Self {
+        EmwinNameError::Wmo(e)
+    }
+}
+
 /// Data parsed from an EMWIN filename
 ///
 /// The EMWIN filename starts with 1 letter "pflag" that indicates its origin:
@@ -352,33 +387,38 @@ pub enum PFlag {
 
 impl ParsedEmwinName {
     /// Parses an EMWIN filename (without the file extension)
-    pub fn parse(filename: &str) -> Option<Self> {
-        if filename.len() < 18 {
-            return None;
+    ///
+    /// Returns [`EmwinNameError::Wmo`] if the name is laid out right, but has a WMO code that
+    /// the tables don't know.
+    pub fn parse(filename: &str) -> Result<Self, EmwinNameError> {
+        use EmwinNameError::NotEmwin;
+
+        if filename.len() < 53 || !filename.is_ascii() {
+            return Err(NotEmwin);
         }
         let mut chars = filename.chars();
         let pflag = match chars.next() {
             Some('A') => PFlag::A,
             Some('Z') => PFlag::Z,
-            _ => return None,
+            _ => return Err(NotEmwin),
         };
 
         // skip underscore
         if !matches!(chars.next(), Some('_')) {
-            return None;
+            return Err(NotEmwin);
         }
 
-        let t1 = chars.next().unwrap();
-        let t2 = chars.next().unwrap();
+        let t1 = chars.next().ok_or(NotEmwin)?;
+        let t2 = chars.next().ok_or(NotEmwin)?;
 
         let aa = &filename[4..6];
         let mut chars = chars.skip(2);
 
-        let (t1, t2, area) = wmo::parse_wmo_abbreviated_heading(t1, t2, aa);
+        let (t1, t2, area) = wmo::parse_wmo_abbreviated_heading(t1, t2, aa)?;
 
         // next 2 digits are the ii indicators
-        let i1 = chars.next().unwrap().to_digit(10).unwrap_or_default();
-        let i2 = chars.next().unwrap().to_digit(10).unwrap_or_default();
+        let i1 = chars.next().ok_or(NotEmwin)?.to_digit(10).unwrap_or_default();
+        let i2 = chars.next().ok_or(NotEmwin)?.to_digit(10).unwrap_or_default();
 
         let originator = Originator::from_ii(i1 as u8, i2 as u8);
 
@@ -394,12 +434,12 @@ impl ParsedEmwinName {
         // get a better date from other fields in the filename
 
         // then a 14-length representing the date:  yyyyMMddhhmmss (UTC i think)
-        let date = chrono::NaiveDateTime::parse_from_str(&filename[26..40], "%Y%m%d%H%M%S").ok()?;
+        let date = chrono::NaiveDateTime::parse_from_str(&filename[26..40], "%Y%m%d%H%M%S").map_err(|_| NotEmwin)?;
         let date = chrono::DateTime::<chrono::Utc>::from_utc(date, chrono::Utc);
 
         // then underscore
         // then a 6-digit sequence number
-        let sequence = (&filename[41..47]).parse::<u32>().ok()?;
+        let sequence = (&filename[41..47]).parse::<u32>().map_err(|_| NotEmwin)?;
 
         // then underscore
         // then a 1-digit priority, from 1 (highest) to 4 (lowest)
@@ -408,7 +448,7 @@ impl ParsedEmwinName {
             "2" => Priority::High,
             "3" => Priority::Medium,
             "4" => Priority::Low,
-            x => panic!("Unknown priority {}", x),
+            _ => return Err(NotEmwin),
         };
 
         // rest of the characters (6) are the old GOES-R product name
@@ -416,7 +456,7 @@ impl ParsedEmwinName {
 
         let nws_product = nws::NWSProduct::from_str(&legacy_filename[0..3]);
 
-        Some(ParsedEmwinName {
+        Ok(ParsedEmwinName {
             pflag,
             data_type_1: t1,
             data_type_2: t2,
@@ -430,13 +470,27 @@ impl ParsedEmwinName {
             legacy_filename,
         })
     }
+
+    /// What this product is, in `language`: the NWS product if it's a known one (like "Tornado
+    /// Warning"), otherwise its WMO data types (like "Forecasts: Public")
+    pub fn description(&self, language: Language) -> String {
+        match &self.nws_product {
+            Some(product) => product.description(language).to_string(),
+            None => format!(
+                "{}: {}",
+                language.tr(self.data_type_1.name()),
+                language.tr(self.data_type_2.name())
+            ),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::emwin::ParsedEmwinName;
-
-    use super::wmo::WMODataTypeT2;
+    use crate::{
+        emwin::{wmo::WmoError, EmwinNameError, ParsedEmwinName},
+        i18n::Language,
+    };
 
     #[test]
     fn test_parse() {
@@ -451,34 +505,26 @@ mod tests {
 
         let d = ParsedEmwinName::parse("A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.lrit").unwrap();
         println!("{d:?}");
-    }
-
-    #[test]
-    #[ignore]
-    fn test_unknowns() {
-        // let mut set = std::collections::HashSet::new();
-
-        for entry in std::fs::read_dir("/tank/achin/tmp/goes_out3").unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            let filename = path.file_name().unwrap().to_str().unwrap();
-            if (filename.starts_with("A_") || filename.starts_with("Z_")) && filename.ends_with(".debug") {
-                let a = ParsedEmwinName::parse(filename).unwrap();
-
-                match a.data_type_2 {
-                    WMODataTypeT2::UnknownAnalyses(_)
-                    | WMODataTypeT2::UnknownClimate(_)
-                    | WMODataTypeT2::UnknownNotice(_)
-                    | WMODataTypeT2::UnknownSatellite(_)
-                    | WMODataTypeT2::UnknownUpperAir(_)
-                    | WMODataTypeT2::UnknownWarning(_) => {
-                        println!("{} {a:?}", filename);
-                    }
-                    _ => {}
-                }
-            }
-        }
 
-        // println!("{:#?}", set);
+        assert_eq!(a.description(Language::English), "Regional Weather Roundup");
+        assert_eq!(a.description(Language::Spanish), "Resumen regional del tiempo");
+        let e = ParsedEmwinName::parse("A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-XYZWBNUS").unwrap();
+        assert_eq!(e.description(Language::English), "Forecasts: Public");
+        assert_eq!(e.description(Language::Spanish), "Pronósticos: Público");
+
+        let err = |name| ParsedEmwinName::parse(name).unwrap_err();
+        assert_eq!(
+            err("A_FYUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS"),
+            EmwinNameError::Wmo(WmoError::UnknownT2("FY".to_string()))
+        );
+        assert_eq!(
+            err("A_FPQQ20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS"),
+            EmwinNameError::Wmo(WmoError::UnknownArea("FPQQ".to_string()))
+        );
+        assert_eq!(
+            err("A_TIZZ20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS"),
+            EmwinNameError::Wmo(WmoError::UnknownArea("TIZZ".to_string()))
+        );
+        assert_eq!(err("A_bogus.TXT"), EmwinNameError::NotEmwin);
     }
 }
@@ -1,3 +1,5 @@
+use crate::i18n::Language;
+
 /// A list of NWS text products
 ///
 /// Reference: https://forecast.weather.gov/product_types.php
@@ -1481,4 +1483,9 @@ impl NWSProduct {
             NWSProduct::ZFP => "Zone Forecast Product",
         }
     }
+
+    /// What this product is, in `language`
+    pub fn description(&self, language: Language) -> &'static str {
+        language.tr(self.to_str())
+    }
 }
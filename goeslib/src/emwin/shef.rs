@@ -0,0 +1,517 @@
+//! Decoding SHEF (Standard Hydrometeorological Exchange Format) messages
+//!
+//! SHEF is how river gauges, rain gauges, and other hydrologic observations are exchanged, in the
+//! `RR1`-`RR9` and `HYD` products, and in the messages from many DCS platforms.  There are three
+//! message formats:
+//!
+//! * `.A` -- one station, one time, any number of parameters: `.A BRKM2 0507 Z DH12/HG 5.23/PPH 0.01`
+//! * `.B` -- a table of stations (one per line, until `.END`), with the parameters in the header
+//! * `.E` -- one station and parameter, with a value for each time interval:
+//!   `.E BRKM2 0507 Z DH06/HG/DIH01/5.21/5.22/5.23`
+//!
+//! Each value is decoded into a [`ShefValue`].  Local time zones without a standard/daylight
+//! suffix (like `C` instead of `CS` or `CD`) are treated as standard time.
+//!
+//! # References
+//!
+//! * https://www.weather.gov/media/mdl/SHEF_CodeManual_5July2012.pdf
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Units {
+    /// Feet, inches, degrees F, and so on (the default)
+    English,
+    Si,
+}
+
+/// One decoded value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShefValue {
+    pub location: String,
+    /// The SHEF parameter code, like `HG` (river stage) or `PPH` (hourly precipitation)
+    pub parameter: String,
+    pub time: DateTime<Utc>,
+    /// `None` for missing values
+    pub value: Option<f64>,
+    pub units: Units,
+    /// True if this came from a revised message (like `.AR`), which replaces an earlier value
+    pub revised: bool,
+}
+
+/// The time (and units) that the next value in a message is for
+///
+/// This starts out at the message's date, and is changed by date elements like `DH12`.
+#[derive(Debug, Clone)]
+struct Clock {
+    year: i32,
+    month: u32,
+    day: u32,
+    /// Can be 24, for the end of the day
+    hour: u32,
+    minute: u32,
+    second: u32,
+    /// Hours from UTC
+    offset: i64,
+    /// From a `DR` element
+    relative: Duration,
+    /// From a `DI` element, for `.E` messages
+    interval: Duration,
+    units: Units,
+}
+
+impl Clock {
+    /// Starts at a message's date (`MMDD`, `YYMMDD`, or `CCYYMMDD`) and time zone
+    fn new(date: &str, zone: Option<&str>, near: DateTime<Utc>) -> Option<Clock> {
+        let offset = match zone {
+            Some(zone) => zone_offset(zone)?,
+            None => 0,
+        };
+        let (year, month, day) = match pairs(date)?.as_slice() {
+            [month, day] => (nearest_year(*month, *day, near), *month, *day),
+            [yy, month, day] => (nearest_century(*yy, near), *month, *day),
+            [cc, yy, month, day] => ((cc * 100 + yy) as i32, *month, *day),
+            _ => return None,
+        };
+        Some(Clock {
+            year,
+            month,
+            day,
+            // without a time, values are for 12Z, or the end of the local day
+            hour: if offset == 0 { 12 } else { 24 },
+            minute: 0,
+            second: 0,
+            offset,
+            relative: Duration::zero(),
+            interval: Duration::zero(),
+            units: Units::English,
+        })
+    }
+
+    fn time(&self) -> Option<DateTime<Utc>> {
+        let date = NaiveDate::from_ymd_opt(self.year, self.month, self.day)?;
+        let time = Utc.from_utc_datetime(&date.and_hms_opt(0, self.minute, self.second)?);
+        Some(time + Duration::hours(self.hour as i64 - self.offset) + self.relative)
+    }
+
+    /// Sets the date and time fields, starting from `first` (0 for the month, up to 4 for the
+    /// second)
+    fn set_fields(&mut self, first: usize, values: &[u32]) {
+        for (i, value) in values.iter().enumerate() {
+            match first + i {
+                0 => self.month = *value,
+                1 => self.day = *value,
+                2 => self.hour = *value,
+                3 => self.minute = *value,
+                _ => self.second = *value,
+            }
+        }
+    }
+
+    /// Applies a date/data element like `DH12`, `DRH-6`, or `DIH01`
+    ///
+    /// Returns false if the element isn't one.
+    fn apply(&mut self, element: &str, near: DateTime<Utc>) -> bool {
+        if !element.starts_with('D') || element.len() < 2 || !element.is_char_boundary(2) {
+            return false;
+        }
+        let (code, rest) = element.split_at(2);
+        if !rest.is_ascii() {
+            return true;
+        }
+        let digits = pairs(rest).unwrap_or_default();
+        match code {
+            "DS" => self.set_fields(4, &digits),
+            "DN" => {
+                self.second = 0;
+                self.set_fields(3, &digits);
+            }
+            "DH" => {
+                self.minute = 0;
+                self.second = 0;
+                self.set_fields(2, &digits);
+            }
+            "DD" => self.set_fields(1, &digits),
+            "DM" => self.set_fields(0, &digits),
+            "DY" if !digits.is_empty() => {
+                self.year = nearest_century(digits[0], near);
+                self.set_fields(0, &digits[1..]);
+            }
+            "DT" if digits.len() >= 2 => {
+                self.year = (digits[0] * 100 + digits[1]) as i32;
+                self.set_fields(0, &digits[2..]);
+            }
+            "DJ" => {
+                let (year, day) = match rest.len() {
+                    3 => (Some(self.year), rest.parse().ok()),
+                    5 => (
+                        rest[..2].parse().ok().map(|yy| nearest_century(yy, near)),
+                        rest[2..].parse().ok(),
+                    ),
+                    7 => (rest[..4].parse().ok(), rest[4..].parse().ok()),
+                    _ => (None, None),
+                };
+                if let Some(date) = year.zip(day).and_then(|(y, d)| NaiveDate::from_yo_opt(y, d)) {
+                    self.year = date.year();
+                    self.month = date.month();
+                    self.day = date.day();
+                }
+            }
+            "DR" => {
+                if let Some(duration) = parse_duration(rest) {
+                    self.relative = duration;
+                }
+            }
+            "DI" => {
+                if let Some(duration) = parse_duration(rest) {
+                    self.interval = duration;
+                }
+            }
+            "DU" => match rest {
+                "E" => self.units = Units::English,
+                "S" => self.units = Units::Si,
+                _ => {}
+            },
+            // creation dates (DC) and data qualifiers (DQ) aren't kept
+            _ => {}
+        }
+        true
+    }
+}
+
+/// Splits a string of digits into 2-digit numbers
+fn pairs(s: &str) -> Option<Vec<u32>> {
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.as_bytes()
+        .chunks(2)
+        .map(|c| match c {
+            [a, b] => Some(((a - b'0') * 10 + (b - b'0')) as u32),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses a duration like `H01` or `N-15`, from a `DR` or `DI` element
+fn parse_duration(s: &str) -> Option<Duration> {
+    let amount: i64 = s.get(1..)?.parse().ok()?;
+    match s.get(..1)? {
+        "S" => Some(Duration::seconds(amount)),
+        "N" => Some(Duration::minutes(amount)),
+        "H" => Some(Duration::hours(amount)),
+        "D" => Some(Duration::days(amount)),
+        _ => None,
+    }
+}
+
+/// The year (within one of `near`) that puts a month and day closest to `near`
+fn nearest_year(month: u32, day: u32, near: DateTime<Utc>) -> i32 {
+    (near.year() - 1..=near.year() + 1)
+        .filter_map(|year| Some((year, NaiveDate::from_ymd_opt(year, month, day)?)))
+        .min_by_key(|(_, date)| (*date - near.naive_utc().date()).num_days().abs())
+        .map_or(near.year(), |(year, _)| year)
+}
+
+/// The year ending in `yy` that's closest to `near`
+fn nearest_century(yy: u32, near: DateTime<Utc>) -> i32 {
+    let century = near.year() / 100 * 100;
+    IntoIterator::into_iter([century - 100, century, century + 100])
+        .map(|c| c + yy as i32)
+        .min_by_key(|year| (year - near.year()).abs())
+        .unwrap_or(century + yy as i32)
+}
+
+/// Hours from UTC for a SHEF time zone code
+fn zone_offset(zone: &str) -> Option<i64> {
+    Some(match zone {
+        "Z" => 0,
+        "A" | "AS" => -4,
+        "AD" => -3,
+        "E" | "ES" => -5,
+        "ED" => -4,
+        "C" | "CS" => -6,
+        "CD" => -5,
+        "M" | "MS" => -7,
+        "MD" => -6,
+        "P" | "PS" => -8,
+        "PD" => -7,
+        "L" | "LS" => -9,
+        "LD" => -8,
+        "H" | "HS" => -10,
+        _ => return None,
+    })
+}
+
+/// Returns true if an element is a parameter code, like `HG` or `PPHRZZ`
+fn is_parameter(code: &str) -> bool {
+    (2..=7).contains(&code.len())
+        && code.starts_with(|c: char| c.is_ascii_uppercase() && c != 'D')
+        && code.bytes().all(|b| b.is_ascii_alphanumeric())
+        && code != "MM"
+}
+
+/// Parses a value, where `M`, `MM`, and `-9999` are missing and `T` is a trace
+///
+/// Values can have a one letter data qualifier after them (like `5.2E` for an estimate), which is
+/// ignored.  Returns `None` if this isn't a value at all.
+fn parse_value(s: &str) -> Option<Option<f64>> {
+    match s {
+        "M" | "MM" => return Some(None),
+        "T" => return Some(Some(0.001)),
+        _ => {}
+    }
+    let number = s.strip_suffix(|c: char| c.is_ascii_uppercase()).unwrap_or(s);
+    let value: f64 = number.parse().ok()?;
+    Some(if value == -9999.0 { None } else { Some(value) })
+}
+
+/// Removes comments, which go from a `:` to the next `:` (or the end of the line)
+fn strip_comments(line: &str) -> String {
+    line.split(':').step_by(2).collect()
+}
+
+/// A message, with any continuation lines added on
+struct Message {
+    /// `A`, `B`, or `E`
+    format: char,
+    revised: bool,
+    header: String,
+    /// The station lines of a `.B` message
+    body: Vec<String>,
+}
+
+impl Message {
+    /// Parses the start of a message, like `.A`, `.AR`, or `.E1` (for a continuation)
+    ///
+    /// Returns the format, whether it's revised, whether it's a continuation, and the rest of the
+    /// line.
+    fn start(line: &str) -> Option<(char, bool, bool, &str)> {
+        let line = line.strip_prefix('.')?;
+        let format = line.chars().next().filter(|c| matches!(c, 'A' | 'B' | 'E'))?;
+        let rest = &line[1..];
+        let (revised, rest) = match rest.strip_prefix('R') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let data = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        if !data.is_empty() && !data.starts_with(char::is_whitespace) {
+            return None;
+        }
+        Some((format, revised, data.len() != rest.len(), data.trim()))
+    }
+
+    fn decode(&self, near: DateTime<Utc>, values: &mut Vec<ShefValue>) {
+        let (id, rest) = take_token(&self.header);
+        let (date, rest) = take_token(rest);
+        let (zone, data) = match take_token(rest) {
+            (zone, data) if zone_offset(zone).is_some() => (Some(zone), data),
+            _ => (None, rest),
+        };
+        let mut clock = match Clock::new(date, zone, near) {
+            Some(clock) if !id.is_empty() => clock,
+            _ => return,
+        };
+        let mut push = |location: &str, parameter: &str, time: Option<DateTime<Utc>>, value, units| {
+            if let Some(time) = time {
+                values.push(ShefValue {
+                    location: location.to_string(),
+                    parameter: parameter.to_string(),
+                    time,
+                    value,
+                    units,
+                    revised: self.revised,
+                });
+            }
+        };
+        let elements = data.split('/').map(str::trim);
+
+        match self.format {
+            'A' => {
+                for element in elements {
+                    if clock.apply(element, near) {
+                        continue;
+                    }
+                    let (parameter, value) = take_token(element);
+                    if let Some(value) = parse_value(take_token(value).0).filter(|_| is_parameter(parameter)) {
+                        push(id, parameter, clock.time(), value, clock.units);
+                    }
+                }
+            }
+            'E' => {
+                let mut parameter = None;
+                let mut step = 0;
+                for element in elements {
+                    if is_parameter(element) {
+                        parameter = Some(element);
+                        step = 0;
+                    } else if clock.apply(element, near) {
+                        step = 0;
+                    } else if let Some(parameter) = parameter {
+                        // an empty element is a value that wasn't sent, but still takes up a step
+                        if let Some(value) = parse_value(element) {
+                            let time = clock.time().map(|t| t + clock.interval * step);
+                            push(id, parameter, time, value, clock.units);
+                        }
+                        step += 1;
+                    }
+                }
+            }
+            _ => {
+                // each column gets the clock as it was when its parameter was listed
+                let mut columns = Vec::new();
+                for element in elements {
+                    if clock.apply(element, near) {
+                        continue;
+                    }
+                    let parameter = take_token(element).0;
+                    if is_parameter(parameter) {
+                        columns.push((parameter, clock.clone()));
+                    }
+                }
+                for line in &self.body {
+                    let (location, data) = take_token(line);
+                    for ((parameter, clock), element) in columns.iter().zip(data.split('/')) {
+                        if let Some(value) = parse_value(take_token(element).0) {
+                            push(location, parameter, clock.time(), value, clock.units);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits off the first whitespace separated token
+fn take_token(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Decodes all of the SHEF messages in a bulletin
+///
+/// Messages that only give the month and day are placed in the year closest to `near` (like when
+/// the bulletin was issued).  Any other text in the bulletin is ignored.
+pub fn parse_bulletin(text: &str, near: DateTime<Utc>) -> Vec<ShefValue> {
+    let mut values = Vec::new();
+    let mut message: Option<Message> = None;
+    for line in text.lines() {
+        let line = strip_comments(line);
+        let line = line.trim();
+        if line.starts_with(".END") {
+            if let Some(message) = message.take() {
+                message.decode(near, &mut values);
+            }
+            continue;
+        }
+        match Message::start(line) {
+            Some((format, _, true, data)) if matches!(&message, Some(m) if m.format == format) => {
+                if let Some(message) = &mut message {
+                    message.header.push('/');
+                    message.header.push_str(data.trim_start_matches('/'));
+                }
+            }
+            Some((format, revised, _, data)) => {
+                if let Some(message) = message.take() {
+                    message.decode(near, &mut values);
+                }
+                message = Some(Message {
+                    format,
+                    revised,
+                    header: data.to_string(),
+                    body: Vec::new(),
+                });
+            }
+            None => {
+                if let Some(message) = message.as_mut().filter(|m| m.format == 'B') {
+                    if !line.is_empty() {
+                        message.body.push(line.to_string());
+                    }
+                }
+            }
+        }
+    }
+    if let Some(message) = message {
+        message.decode(near, &mut values);
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    use super::{parse_bulletin, Units};
+
+    fn time(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2022, month, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_a_and_e() {
+        let text = "SRUS53 KDMX 071230\nRR3DMX\n\
+                    .A BRKM2 0507 Z DH12/HG 5.23/PPH 0.01 : a comment / HG 9:\r\n\
+                    .A1 DH1215/TA 54E\n\
+                    .AR DESI4 0507 C DH06/HG M/DUS/TA 12.5\n\
+                    .E BRKM2 220507 Z DH06/HG/DIH01/5.21//5.23\n\
+                    .E1 5.24\n";
+        let values = parse_bulletin(text, time(5, 7, 12, 30));
+        let summary: Vec<_> = values
+            .iter()
+            .map(|v| (v.location.as_str(), v.parameter.as_str(), v.time, v.value))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("BRKM2", "HG", time(5, 7, 12, 0), Some(5.23)),
+                ("BRKM2", "PPH", time(5, 7, 12, 0), Some(0.01)),
+                ("BRKM2", "TA", time(5, 7, 12, 15), Some(54.0)),
+                // central standard time
+                ("DESI4", "HG", time(5, 7, 12, 0), None),
+                ("DESI4", "TA", time(5, 7, 12, 0), Some(12.5)),
+                ("BRKM2", "HG", time(5, 7, 6, 0), Some(5.21)),
+                ("BRKM2", "HG", time(5, 7, 8, 0), Some(5.23)),
+                ("BRKM2", "HG", time(5, 7, 9, 0), Some(5.24)),
+            ]
+        );
+        assert!(!values[0].revised && values[3].revised);
+        assert_eq!(values[3].units, Units::English);
+        assert_eq!(values[4].units, Units::Si);
+    }
+
+    #[test]
+    fn test_b() {
+        let text = ".B DMX 0506 C DH07/PPD/DRD-1/SFD\n\
+                    : station  precip  snow\n\
+                    AMSI4      0.25 /  M\n\
+                    DSMI4      T    /  1.5\n\
+                    .END\n\
+                    ANKI4      9.99 /  9.9\n";
+        let values = parse_bulletin(text, time(5, 7, 12, 0));
+        let values: Vec<_> = values
+            .iter()
+            .map(|v| (v.location.as_str(), v.parameter.as_str(), v.time, v.value))
+            .collect();
+        let day = |d| {
+            Utc.from_utc_datetime(
+                &NaiveDate::from_ymd_opt(2022, 5, d)
+                    .unwrap()
+                    .and_hms_opt(13, 0, 0)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[0], ("AMSI4", "PPD", day(6), Some(0.25)));
+        assert_eq!(values[1], ("AMSI4", "SFD", day(5), None));
+        assert_eq!(values[2], ("DSMI4", "PPD", day(6), Some(0.001)));
+        assert_eq!(values[3], ("DSMI4", "SFD", day(5), Some(1.5)));
+    }
+}
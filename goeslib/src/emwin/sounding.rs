@@ -0,0 +1,472 @@
+//! Decoding upper air soundings (TEMP bulletins)
+//!
+//! Radiosonde soundings are sent in parts: part A (`TTAA`, the EMWIN `MAN` products) has the
+//! mandatory pressure levels, and part B (`TTBB`, the EMWIN `SGL` products) has the significant
+//! levels in between.  Each part is decoded into a [`Sounding`], and the parts for a station and
+//! time can be merged into one profile.  Parts C and D (above 100 hPa) aren't decoded.
+//!
+//! # References
+//!
+//! * WMO Manual on Codes, FM 35 (TEMP)
+//! * Federal Meteorological Handbook No. 3, chapter 7
+use serde::Serialize;
+
+use super::metar::{DayTime, SpeedUnit};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SoundingPart {
+    /// Mandatory levels, up to 100 hPa (`TTAA`)
+    A,
+    /// Significant levels, up to 100 hPa (`TTBB`)
+    B,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LevelKind {
+    Surface,
+    /// A standard pressure level (1000, 925, 850, 700, 500 hPa, and so on)
+    Mandatory,
+    Tropopause,
+    MaxWind,
+    /// A level where the temperature or wind profile changes
+    Significant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SoundingWind {
+    /// Degrees true
+    pub direction: u16,
+    /// In the sounding's [`wind_unit`](Sounding::wind_unit)
+    pub speed: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Level {
+    pub kind: LevelKind,
+    pub pressure_hpa: u16,
+    /// Geopotential height, for mandatory levels
+    pub height_m: Option<i32>,
+    pub temperature_c: Option<f32>,
+    pub dew_point_c: Option<f32>,
+    pub wind: Option<SoundingWind>,
+}
+
+impl Level {
+    fn new(kind: LevelKind, pressure_hpa: u16) -> Level {
+        Level {
+            kind,
+            pressure_hpa,
+            height_m: None,
+            temperature_c: None,
+            dew_point_c: None,
+            wind: None,
+        }
+    }
+
+    /// Sets the temperature and dew point from a `TTTDD` group
+    fn set_temperature(&mut self, group: Option<&str>) {
+        if let Some((temp, dew)) = group.and_then(parse_temperature) {
+            self.temperature_c = Some(temp);
+            self.dew_point_c = dew;
+        }
+    }
+}
+
+/// One station's sounding
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Sounding {
+    /// The WMO block and station number, like `72403`
+    pub station: String,
+    /// The nominal time of the sounding (minutes are always zero)
+    pub time: DayTime,
+    pub wind_unit: SpeedUnit,
+    /// The parts that this sounding was made from
+    pub parts: Vec<SoundingPart>,
+    /// From the surface up (highest pressure first)
+    pub levels: Vec<Level>,
+}
+
+impl Sounding {
+    /// Adds the levels from another part of the same sounding
+    ///
+    /// Levels at the same pressure are combined, keeping the values that are already here.
+    pub fn merge(&mut self, other: &Sounding) {
+        for part in &other.parts {
+            if !self.parts.contains(part) {
+                self.parts.push(*part);
+            }
+        }
+        for level in &other.levels {
+            match self
+                .levels
+                .iter_mut()
+                .find(|l| l.pressure_hpa == level.pressure_hpa && l.kind == level.kind)
+            {
+                Some(existing) => {
+                    existing.height_m = existing.height_m.or(level.height_m);
+                    existing.temperature_c = existing.temperature_c.or(level.temperature_c);
+                    existing.dew_point_c = existing.dew_point_c.or(level.dew_point_c);
+                    existing.wind = existing.wind.or(level.wind);
+                }
+                None => self.levels.push(level.clone()),
+            }
+        }
+        self.sort_levels();
+    }
+
+    fn sort_levels(&mut self) {
+        self.levels.sort_by_key(|l| std::cmp::Reverse(l.pressure_hpa));
+    }
+}
+
+/// Parses a `TTTDD` group into a temperature and dew point
+///
+/// The tenths digit of the temperature is even for positive temperatures and odd for negative
+/// ones.  The dew point is given as a depression below the temperature.
+fn parse_temperature(group: &str) -> Option<(f32, Option<f32>)> {
+    let ttt: u16 = group.get(..3)?.parse().ok()?;
+    let temp = if matches!(ttt % 10, 0 | 2 | 4 | 6 | 8) {
+        ttt as f32 / 10.0
+    } else {
+        -(ttt as f32) / 10.0
+    };
+    let depression = match group.get(3..5)?.parse::<u16>() {
+        Ok(dd @ 0..=50) => Some(dd as f32 / 10.0),
+        Ok(dd @ 56..=99) => Some(dd as f32 - 50.0),
+        _ => None,
+    };
+    Some((temp, depression.map(|d| temp - d)))
+}
+
+/// Parses a `dddff` wind group
+///
+/// Directions are rounded to 5 degrees, so speeds of 100 or more add their hundreds to the last
+/// digit of the direction.
+fn parse_wind(group: &str) -> Option<SoundingWind> {
+    let ddd: u16 = group.get(..3)?.parse().ok()?;
+    let ff: u16 = group.get(3..5)?.parse().ok()?;
+    Some(SoundingWind {
+        direction: ddd - ddd % 5,
+        speed: ff + 100 * (ddd % 5),
+    })
+}
+
+/// Works out the height of a mandatory level from its `hhh` group, in meters
+fn mandatory_height(pressure: u16, hhh: &str) -> Option<i32> {
+    let h: i32 = hhh.parse().ok()?;
+    Some(match pressure {
+        // negative heights are sent as 500 plus the height
+        1000 if h >= 500 => -(h - 500),
+        1000 | 925 => h,
+        850 => 1000 + h,
+        700 if h < 500 => 3000 + h,
+        700 => 2000 + h,
+        // the rest are in decameters
+        500 | 400 => h * 10,
+        300 | 250 if h < 500 => h * 10 + 10000,
+        300 | 250 => h * 10,
+        _ => h * 10 + 10000,
+    })
+}
+
+/// Parses the day and hour group (`YYGGI`), where days over 50 mean winds are in knots
+fn parse_day_hour(group: &str) -> Option<(DayTime, SpeedUnit)> {
+    let yy: u8 = group.get(..2)?.parse().ok()?;
+    let hour = group.get(2..4)?.parse().ok()?;
+    let (day, unit) = if yy > 50 {
+        (yy - 50, SpeedUnit::Knots)
+    } else {
+        (yy, SpeedUnit::MetersPerSecond)
+    };
+    Some((DayTime { day, hour, minute: 0 }, unit))
+}
+
+fn is_missing(group: &str) -> bool {
+    group.contains('/')
+}
+
+/// Parses a part A (`TTAA`) report, starting after the `TTAA`
+fn parse_part_a(tokens: &[&str]) -> Option<Sounding> {
+    let (time, wind_unit) = parse_day_hour(tokens.first()?)?;
+    // the last standard level that has a wind group
+    let last_wind = match tokens[0].as_bytes().get(4) {
+        Some(b'0') => 1000,
+        Some(b'8') => 850,
+        Some(b'9') => 925,
+        Some(d @ b'1'..=b'7') => (d - b'0') as u16 * 100,
+        _ => u16::MAX,
+    };
+    let mut sounding = Sounding {
+        station: tokens.get(1).filter(|s| s.len() == 5)?.to_string(),
+        time,
+        wind_unit,
+        parts: vec![SoundingPart::A],
+        levels: Vec::new(),
+    };
+
+    let mut groups = tokens[2..].iter().copied().peekable();
+    while let Some(group) = groups.next() {
+        if group.len() != 5 {
+            break;
+        }
+        if !group.is_ascii() {
+            // garbled, and can't be split into an indicator and a value
+            continue;
+        }
+        let (indicator, value) = group.split_at(2);
+        let mut level = match indicator {
+            "99" => {
+                let p: u16 = match value.parse() {
+                    Ok(p) => p,
+                    Err(_) => break,
+                };
+                Level::new(LevelKind::Surface, if p < 100 { p + 1000 } else { p })
+            }
+            "00" | "92" | "85" | "70" | "50" | "40" | "30" | "25" | "20" | "15" | "10" => {
+                let pressure = match indicator {
+                    "00" => 1000,
+                    "92" => 925,
+                    p => p.parse::<u16>().expect("two digits") * 10,
+                };
+                let mut level = Level::new(LevelKind::Mandatory, pressure);
+                level.height_m = mandatory_height(pressure, value);
+                level.set_temperature(groups.next());
+                if pressure >= last_wind {
+                    level.wind = groups.next().and_then(parse_wind);
+                }
+                sounding.levels.push(level);
+                continue;
+            }
+            "88" | "77" | "66" if value == "999" => continue,
+            "88" => match value.parse() {
+                Ok(p) => Level::new(LevelKind::Tropopause, p),
+                Err(_) => break,
+            },
+            "77" | "66" => {
+                let mut level = match value.parse() {
+                    Ok(p) => Level::new(LevelKind::MaxWind, p),
+                    Err(_) => break,
+                };
+                level.wind = groups.next().and_then(parse_wind);
+                // an optional wind shear group
+                groups.next_if(|g| g.starts_with('4') && g.len() == 5);
+                sounding.levels.push(level);
+                continue;
+            }
+            // regional groups (like 51515) and anything else end the part
+            _ => break,
+        };
+        level.set_temperature(groups.next());
+        level.wind = groups.next().and_then(parse_wind);
+        sounding.levels.push(level);
+    }
+    sounding.sort_levels();
+    Some(sounding)
+}
+
+/// Parses a part B (`TTBB`) report, starting after the `TTBB`
+fn parse_part_b(tokens: &[&str]) -> Option<Sounding> {
+    let (time, wind_unit) = parse_day_hour(tokens.first()?)?;
+    let mut sounding = Sounding {
+        station: tokens.get(1).filter(|s| s.len() == 5)?.to_string(),
+        time,
+        wind_unit,
+        parts: vec![SoundingPart::B],
+        levels: Vec::new(),
+    };
+
+    // temperatures come first, and then winds after 21212
+    let mut winds = false;
+    let mut groups = tokens[2..].iter().copied();
+    while let Some(group) = groups.next() {
+        if group == "21212" {
+            winds = true;
+            continue;
+        }
+        let bytes = group.as_bytes();
+        // levels are numbered 00, 11, 22, ... 99, 11, ...
+        if group.len() != 5 || bytes[0] != bytes[1] || !bytes[0].is_ascii_digit() || group == "31313" {
+            break;
+        }
+        let data = groups.next();
+        let pressure: u16 = match group[2..].parse() {
+            Ok(p) if p < 100 => p + 1000,
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+        let mut level = Level::new(LevelKind::Significant, pressure);
+        if winds {
+            level.wind = data.and_then(parse_wind);
+        } else {
+            level.set_temperature(data);
+        }
+        sounding.levels.push(level);
+    }
+
+    // a level can have both a temperature and a wind
+    let mut merged = Sounding {
+        levels: Vec::new(),
+        ..sounding.clone()
+    };
+    merged.merge(&sounding);
+    Some(merged)
+}
+
+/// Parses all the soundings in a bulletin
+///
+/// Each report starts with `TTAA` or `TTBB`, except that a bulletin can leave it out of the
+/// reports after the first.  Reports that can't be parsed are left out.
+pub fn parse_bulletin(text: &str) -> Vec<Sounding> {
+    let mut soundings = Vec::new();
+    let mut part = None;
+    for chunk in text.split('=') {
+        let tokens: Vec<&str> = chunk.split_whitespace().collect();
+        let start = match tokens.iter().position(|t| *t == "TTAA" || *t == "TTBB") {
+            Some(i) => {
+                part = Some(if tokens[i] == "TTAA" {
+                    SoundingPart::A
+                } else {
+                    SoundingPart::B
+                });
+                i + 1
+            }
+            None => 0,
+        };
+        let tokens = &tokens[start..];
+        if !matches!(tokens.get(1), Some(station) if !is_missing(station)) || tokens.get(2) == Some(&"NIL") {
+            continue;
+        }
+        let sounding = match part {
+            Some(SoundingPart::A) => parse_part_a(tokens),
+            Some(SoundingPart::B) => parse_part_b(tokens),
+            None => None,
+        };
+        soundings.extend(sounding);
+    }
+    soundings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PART_A: &str = "USUS41 KWBC 011200\r\r\nMANIAD\r\r\nTTAA 51121 72403 99012 26856 22009 00148 ///// ///// \
+        92787 21056 24512 85514 16456 25520 70151 03056 25528 50583 13157 24536 \
+        40757 24356 24544 30960 38358 24556 25083 47958 24563 20230 55357 25051 \
+        15407 59959 24535 10669 63561 25016 88200 59559 24552 77999 51515 10164=\r\r\n";
+
+    #[test]
+    fn test_part_a() {
+        let soundings = parse_bulletin(PART_A);
+        assert_eq!(soundings.len(), 1);
+        let s = &soundings[0];
+        assert_eq!(s.station, "72403");
+        assert_eq!(
+            s.time,
+            DayTime {
+                day: 1,
+                hour: 12,
+                minute: 0
+            }
+        );
+        assert_eq!(s.wind_unit, SpeedUnit::Knots);
+
+        let surface = &s.levels[0];
+        assert_eq!((surface.kind, surface.pressure_hpa), (LevelKind::Surface, 1012));
+        assert_eq!(surface.temperature_c, Some(26.8));
+        assert_eq!(surface.dew_point_c, Some(26.8 - 6.0));
+        assert_eq!(
+            surface.wind,
+            Some(SoundingWind {
+                direction: 220,
+                speed: 9
+            })
+        );
+
+        let level = |p| {
+            s.levels
+                .iter()
+                .find(|l| l.pressure_hpa == p && l.kind == LevelKind::Mandatory)
+                .unwrap()
+        };
+        assert_eq!(level(1000).height_m, Some(148));
+        assert_eq!(level(1000).temperature_c, None);
+        assert_eq!(level(850).height_m, Some(1514));
+        assert_eq!(level(700).height_m, Some(3151));
+        assert_eq!(level(500).height_m, Some(5830));
+        assert_eq!(level(500).temperature_c, Some(-13.1));
+        assert_eq!(level(250).height_m, Some(10830));
+        assert_eq!(level(100).height_m, Some(16690));
+        // winds are only reported up to 100 hPa (the I in 51121)
+        assert_eq!(
+            level(100).wind,
+            Some(SoundingWind {
+                direction: 250,
+                speed: 16
+            })
+        );
+        let tropopause = s.levels.iter().find(|l| l.kind == LevelKind::Tropopause).unwrap();
+        assert_eq!(tropopause.pressure_hpa, 200);
+        assert!(s.levels.windows(2).all(|w| w[0].pressure_hpa >= w[1].pressure_hpa));
+
+        // a garbled group is skipped
+        let garbled = parse_bulletin(&PART_A.replace("22009 00148", "22009 9\u{e9}12 00148"));
+        assert_eq!(garbled.len(), 1);
+        assert_eq!(garbled[0].levels, s.levels);
+    }
+
+    #[test]
+    fn test_part_b_and_merge() {
+        let text = "UKUS41 KWBC 011200\nSGLIAD\nTTBB 5112/ 72403 00012 26856 11000 25061 22981 23662 \
+                    33850 16456 21212 00012 22009 11000 22511 22850 25520 31313 58708=\n";
+        let b = parse_bulletin(text);
+        assert_eq!(b.len(), 1);
+        let b = &b[0];
+        assert_eq!(b.levels.len(), 4);
+        assert_eq!(b.levels[1].pressure_hpa, 1000);
+        assert_eq!(b.levels[1].temperature_c, Some(25.0));
+        assert_eq!(
+            b.levels[1].wind,
+            Some(SoundingWind {
+                direction: 225,
+                speed: 11
+            })
+        );
+        assert_eq!(b.levels[2].pressure_hpa, 981);
+        assert_eq!(
+            b.levels[3].wind,
+            Some(SoundingWind {
+                direction: 255,
+                speed: 20
+            })
+        );
+
+        let mut a = parse_bulletin(PART_A).remove(0);
+        let mandatory = a.levels.len();
+        a.merge(b);
+        assert_eq!(a.parts, vec![SoundingPart::A, SoundingPart::B]);
+        assert_eq!(a.levels.len(), mandatory + 4);
+        assert!(a.levels.windows(2).all(|w| w[0].pressure_hpa >= w[1].pressure_hpa));
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(parse_temperature("13157"), Some((-13.1, Some(-13.1 - 7.0))));
+        assert_eq!(parse_temperature("004//"), Some((0.4, None)));
+        assert_eq!(
+            parse_wind("25515"),
+            Some(SoundingWind {
+                direction: 255,
+                speed: 15
+            })
+        );
+        assert_eq!(
+            parse_wind("27620"),
+            Some(SoundingWind {
+                direction: 275,
+                speed: 120
+            })
+        );
+        assert_eq!(mandatory_height(1000, "512"), Some(-12));
+    }
+}
@@ -0,0 +1,296 @@
+//! Decoding SWPC space weather messages
+//!
+//! The Space Weather Prediction Center issues alerts (`ALT`), warnings (`WAR`), watches (`WAT`),
+//! and summaries (`SUM`) for geomagnetic storms, solar radiation storms, and radio blackouts.
+//! They're plain text, with a `Key: Value` line for each field:
+//!
+//! ```text
+//! Space Weather Message Code: ALTK05
+//! Serial Number: 1813
+//! Issue Time: 2022 May 10 1207 UTC
+//!
+//! ALERT: Geomagnetic K-index of 5
+//! Threshold Reached: 2022 May 10 1159 UTC
+//! Synoptic Period: 0900-1200 UTC
+//!
+//! Active Warning: Yes
+//! NOAA Scale: G1 - Minor
+//! ```
+//!
+//! # References
+//!
+//! * https://www.swpc.noaa.gov/content/space-weather-alerts-warnings-and-watches
+//! * https://www.swpc.noaa.gov/noaa-scales-explanation
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageKind {
+    /// A threshold has been reached (`ALT`)
+    Alert,
+    /// A threshold is expected to be reached soon (`WAR`)
+    Warning,
+    /// Conditions are favorable for a storm in the next few days (`WAT`)
+    Watch,
+    /// An event has ended (`SUM`)
+    Summary,
+    Other,
+}
+
+/// The kinds of NOAA space weather scales
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScaleKind {
+    /// Geomagnetic storms (G1 through G5)
+    Geomagnetic,
+    /// Solar radiation storms (S1 through S5)
+    SolarRadiation,
+    /// Radio blackouts (R1 through R5)
+    RadioBlackout,
+}
+
+/// A level on one of the NOAA space weather scales, like G3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct NoaaScale {
+    pub kind: ScaleKind,
+    /// 1 (minor) through 5 (extreme)
+    pub level: u8,
+}
+
+impl NoaaScale {
+    /// Returns true if this is the same kind of scale as `other`, and at least as severe
+    pub fn reaches(&self, other: &NoaaScale) -> bool {
+        self.kind == other.kind && self.level >= other.level
+    }
+}
+
+impl FromStr for NoaaScale {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        let kind = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('G') => ScaleKind::Geomagnetic,
+            Some('S') => ScaleKind::SolarRadiation,
+            Some('R') => ScaleKind::RadioBlackout,
+            _ => return Err("scale must start with G, S, or R"),
+        };
+        match chars.as_str().parse() {
+            Ok(level) if (1..=5).contains(&level) => Ok(NoaaScale { kind, level }),
+            _ => Err("scale level must be 1 through 5"),
+        }
+    }
+}
+
+impl fmt::Display for NoaaScale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let letter = match self.kind {
+            ScaleKind::Geomagnetic => 'G',
+            ScaleKind::SolarRadiation => 'S',
+            ScaleKind::RadioBlackout => 'R',
+        };
+        write!(f, "{}{}", letter, self.level)
+    }
+}
+
+/// One SWPC space weather message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpaceWeatherMessage {
+    /// Like `ALTK05` or `WARSUD`
+    pub code: String,
+    pub kind: MessageKind,
+    pub serial: Option<u32>,
+    pub issued: Option<DateTime<Utc>>,
+    /// The first line of the message body, like `ALERT: Geomagnetic K-index of 5`
+    pub headline: String,
+    /// The highest NOAA scale level in the message, if it has one
+    pub scale: Option<NoaaScale>,
+    /// When the event began (or the threshold was reached), or when the warning starts
+    pub begin: Option<DateTime<Utc>>,
+    /// When the event peaked
+    pub maximum: Option<DateTime<Utc>>,
+    /// When the event ended, or when the warning expires
+    pub end: Option<DateTime<Utc>>,
+    /// The serial number of the message this one extends
+    pub extends: Option<u32>,
+    /// The serial number of the message this one cancels
+    pub cancels: Option<u32>,
+    /// Every `Key: Value` field in the message
+    pub fields: BTreeMap<String, String>,
+}
+
+impl SpaceWeatherMessage {
+    /// Parses a message, which has to start with a `Space Weather Message Code` line
+    ///
+    /// Anything before that line (like the WMO heading) is ignored.
+    pub fn parse(text: &str) -> Option<SpaceWeatherMessage> {
+        let start = text.find("Space Weather Message Code:")?;
+        let mut fields = BTreeMap::new();
+        let mut headline = None;
+        for line in text[start..].lines().map(str::trim) {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            // the headline is the only field with an all caps key, like "ALERT" or "CANCEL WATCH"
+            if headline.is_none()
+                && key.chars().any(|c| c.is_ascii_uppercase())
+                && !key.chars().any(|c| c.is_ascii_lowercase())
+            {
+                headline = Some(line.to_string());
+                continue;
+            }
+            if !value.is_empty() {
+                fields.entry(key.to_string()).or_insert_with(|| value.to_string());
+            }
+        }
+
+        let code = fields.get("Space Weather Message Code")?.clone();
+        let kind = match code.get(..3) {
+            Some("ALT") => MessageKind::Alert,
+            Some("WAR") => MessageKind::Warning,
+            Some("WAT") => MessageKind::Watch,
+            Some("SUM") => MessageKind::Summary,
+            _ => MessageKind::Other,
+        };
+        let time = |keys: &[&str]| keys.iter().find_map(|k| fields.get(*k).and_then(|v| parse_time(v)));
+        let serial = |key: &str| fields.get(key).and_then(|v| v.parse().ok());
+
+        let scale = match fields.get("NOAA Scale") {
+            Some(value) => value.split_whitespace().next().and_then(|s| s.parse().ok()),
+            None => highest_scale(&text[start..]),
+        };
+
+        Some(SpaceWeatherMessage {
+            kind,
+            serial: serial("Serial Number"),
+            issued: time(&["Issue Time"]),
+            headline: headline.unwrap_or_default(),
+            scale,
+            begin: time(&["Begin Time", "Valid From", "Threshold Reached"]),
+            maximum: time(&["Maximum Time"]),
+            end: time(&["End Time", "Now Valid Until", "Valid To"]),
+            extends: serial("Extension to Serial Number"),
+            cancels: serial("Cancel Serial Number"),
+            code,
+            fields,
+        })
+    }
+}
+
+/// Parses a time like `2022 May 10 1207 UTC`
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim_end_matches("UTC").trim();
+    let time = NaiveDateTime::parse_from_str(value, "%Y %b %d %H%M").ok()?;
+    Some(Utc.from_utc_datetime(&time))
+}
+
+/// Finds the highest scale level mentioned in a message without a `NOAA Scale` field
+///
+/// Watches list the predicted level for each day, like `May 11:  G1 (Minor)   May 12:  None
+/// (Below G1)`, so levels after "Below" don't count.
+fn highest_scale(text: &str) -> Option<NoaaScale> {
+    let mut highest: Option<NoaaScale> = None;
+    let mut below = false;
+    for word in text.split_whitespace() {
+        let word = word.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+        if !below {
+            if let Ok(scale) = word.parse::<NoaaScale>() {
+                if word.len() == 2
+                    && word.starts_with(char::is_uppercase)
+                    && !matches!(highest, Some(h) if h.level >= scale.level)
+                {
+                    highest = Some(scale);
+                }
+            }
+        }
+        below = word.eq_ignore_ascii_case("below");
+    }
+    highest
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::{MessageKind, NoaaScale, ScaleKind, SpaceWeatherMessage};
+
+    #[test]
+    fn test_alert() {
+        let text = "WOXX01 KWNP 101207\nALTK05\n\
+                    Space Weather Message Code: ALTK05\r\n\
+                    Serial Number: 1813\r\n\
+                    Issue Time: 2022 May 10 1207 UTC\r\n\
+                    \r\n\
+                    ALERT: Geomagnetic K-index of 5\r\n\
+                    Threshold Reached: 2022 May 10 1159 UTC\r\n\
+                    Synoptic Period: 0900-1200 UTC\r\n\
+                    \r\n\
+                    Active Warning: Yes\r\n\
+                    NOAA Scale: G1 - Minor\r\n";
+        let msg = SpaceWeatherMessage::parse(text).unwrap();
+        assert_eq!(msg.code, "ALTK05");
+        assert_eq!(msg.kind, MessageKind::Alert);
+        assert_eq!(msg.serial, Some(1813));
+        assert_eq!(msg.headline, "ALERT: Geomagnetic K-index of 5");
+        assert_eq!(
+            msg.scale,
+            Some(NoaaScale {
+                kind: ScaleKind::Geomagnetic,
+                level: 1
+            })
+        );
+        let time = |h, m| {
+            Utc.from_utc_datetime(
+                &NaiveDate::from_ymd_opt(2022, 5, 10)
+                    .unwrap()
+                    .and_hms_opt(h, m, 0)
+                    .unwrap(),
+            )
+        };
+        assert_eq!(msg.issued, Some(time(12, 7)));
+        assert_eq!(msg.begin, Some(time(11, 59)));
+        assert_eq!(msg.end, None);
+        assert_eq!(msg.fields["Synoptic Period"], "0900-1200 UTC");
+
+        let text = "Space Weather Message Code: WARK05\n\
+                    Serial Number: 2022\n\
+                    Issue Time: 2022 May 10 1530 UTC\n\n\
+                    EXTENDED WARNING: Geomagnetic K-index of 5 expected\n\
+                    Extension to Serial Number: 2021\n\
+                    Valid From: 2022 May 10 0900 UTC\n\
+                    Now Valid Until: 2022 May 10 2100 UTC\n";
+        let msg = SpaceWeatherMessage::parse(text).unwrap();
+        assert_eq!(msg.kind, MessageKind::Warning);
+        assert_eq!(msg.headline, "EXTENDED WARNING: Geomagnetic K-index of 5 expected");
+        assert_eq!(msg.extends, Some(2021));
+        assert_eq!(msg.begin, Some(time(9, 0)));
+        assert_eq!(msg.end, Some(time(21, 0)));
+        assert_eq!(msg.scale, None);
+    }
+
+    #[test]
+    fn test_watch() {
+        let text = "Space Weather Message Code: WATA50\n\
+                    Serial Number: 123\n\
+                    Issue Time: 2022 May 09 1234 UTC\n\n\
+                    WATCH: Geomagnetic Storm Category G2 Predicted\n\n\
+                    Highest Storm Level Predicted by Day:\n\
+                    May 10:  G1 (Minor)   May 11:  G2 (Moderate)   May 12:  None (Below G1)\n";
+        let msg = SpaceWeatherMessage::parse(text).unwrap();
+        assert_eq!(msg.kind, MessageKind::Watch);
+        assert_eq!(msg.scale, Some("G2".parse().unwrap()));
+
+        let quiet = text
+            .replace("G2 (Moderate)", "None (Below G1)")
+            .replace("Category G2", "Category G1");
+        assert_eq!(
+            SpaceWeatherMessage::parse(&quiet).unwrap().scale,
+            Some("G1".parse().unwrap())
+        );
+        assert!(SpaceWeatherMessage::parse("FXUS61 KPHI 071250\nAFDPHI\n").is_none());
+        assert!("G6".parse::<NoaaScale>().is_err());
+        assert!("X1".parse::<NoaaScale>().is_err());
+    }
+}
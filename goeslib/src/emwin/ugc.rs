@@ -0,0 +1,127 @@
+//! Decoding Universal Geographic Codes (UGC), the list of counties or zones a product is for
+//!
+//! Watches, warnings, and forecasts list the areas they cover on a line (or a few lines) like:
+//!
+//! ```text
+//! PAZ070-071-101>106-NJZ001-
+//! 007-041815-
+//! ```
+//!
+//! Each code is a state, `C` for a county or `Z` for a forecast zone, and a 3 digit number.  The
+//! state and type carry over to the numbers that follow, `>` is an inclusive range, and the group
+//! ends with the product's expiration time (`DDHHMM`).  `ALL` (like `PAZALL`) means every county or
+//! zone in a state, and is kept as is.
+//!
+//! # References
+//!
+//! * NWS Directive 10-1702, Universal Geographic Code
+//!   (https://www.weather.gov/media/directives/010_pdfs/pd01017002curr.pdf)
+
+/// Every UGC code (like "PAZ070") in a product, in the order they appear, without repeats
+///
+/// Segmented products have a group of codes for each segment, and they're all included.
+pub fn parse_ugc(text: &str) -> Vec<String> {
+    let mut codes = Vec::new();
+    let mut group: Option<String> = None;
+    for line in text.lines().map(str::trim) {
+        let group_text = match &mut group {
+            Some(group_text) if !line.is_empty() => group_text,
+            _ if is_ugc_start(line) => group.insert(String::new()),
+            _ => {
+                group = None;
+                continue;
+            }
+        };
+        group_text.push_str(line);
+        if line.split('-').any(is_expiration) {
+            expand(group_text, &mut codes);
+            group = None;
+        }
+    }
+    codes
+}
+
+/// Returns true if a line starts with a code like `PAZ070-`, `PAC101>`, or `PAZALL-`
+fn is_ugc_start(line: &str) -> bool {
+    match line.as_bytes() {
+        [s1, s2, kind, rest @ ..] if rest.len() >= 4 => {
+            let number = &rest[..3];
+            s1.is_ascii_uppercase()
+                && s2.is_ascii_uppercase()
+                && (*kind == b'C' || *kind == b'Z')
+                && (number.iter().all(u8::is_ascii_digit) || number == b"ALL")
+                && (rest[3] == b'-' || rest[3] == b'>')
+        }
+        _ => false,
+    }
+}
+
+/// The `DDHHMM` that ends a group
+fn is_expiration(token: &str) -> bool {
+    token.len() == 6 && token.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Adds the codes in a group (up to its expiration time) to `codes`
+fn expand(group: &str, codes: &mut Vec<String>) {
+    let mut prefix = "";
+    for token in group.split('-') {
+        if is_expiration(token) {
+            break;
+        }
+        let number = match token.get(..3) {
+            Some(p) if token.len() > 3 && p.as_bytes()[..2].iter().all(u8::is_ascii_uppercase) => {
+                prefix = p;
+                &token[3..]
+            }
+            _ => token,
+        };
+        if prefix.is_empty() {
+            continue;
+        }
+        let mut add = |code: String| {
+            if !codes.contains(&code) {
+                codes.push(code);
+            }
+        };
+        match number.split_once('>') {
+            Some((from, to)) => {
+                if let (Ok(from), Ok(to)) = (from.parse::<u16>(), to.parse::<u16>()) {
+                    for n in from..=to.min(999) {
+                        add(format!("{}{:03}", prefix, n));
+                    }
+                }
+            }
+            None if number == "ALL" || (number.len() == 3 && number.bytes().all(|b| b.is_ascii_digit())) => {
+                add(format!("{}{}", prefix, number))
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_ugc;
+
+    #[test]
+    fn test_parse_ugc() {
+        let text = "WFUS51 KPHI 041812\r\r\n\
+                    TORPHI\r\r\n\
+                    PAC017-091-041900-\r\r\n\
+                    /O.NEW.KPHI.TO.W.0012.220504T1812Z-220504T1900Z/\r\r\n\
+                    \r\r\n\
+                    BULLETIN - EAS ACTIVATION REQUESTED\r\r\n\
+                    Tornado Warning\r\r\n\
+                    $$\r\r\n\
+                    PAZ070-071-101>103-NJZ001-\r\r\n\
+                    007-PAC017-041815-\r\r\n";
+        assert_eq!(
+            parse_ugc(text),
+            ["PAC017", "PAC091", "PAZ070", "PAZ071", "PAZ101", "PAZ102", "PAZ103", "NJZ001", "NJZ007"]
+        );
+
+        assert_eq!(parse_ugc("MDZALL-041200-\n"), ["MDZALL"]);
+        // no expiration time, so it's not a UGC group
+        assert!(parse_ugc("PAZ070-071-\n\nRest of the product\n").is_empty());
+    }
+}
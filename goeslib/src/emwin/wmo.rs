@@ -1,5 +1,29 @@
 //! Data structures for parsing WMO data, in particular data from attachment II-5 of WMO manual 386
 //!
+use std::convert::TryFrom;
+
+/// A code in a WMO abbreviated heading that isn't in the tables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WmoError {
+    /// A T1 data type that isn't known (or that doesn't have a T2 table)
+    UnknownT1(char),
+    /// A T2 code that isn't known for its T1, with the T1T2 (like "FY")
+    UnknownT2(String),
+    /// An area designator that isn't known for its data type, with the T1T2A1A2 (like "FPQQ")
+    UnknownArea(String),
+}
+
+impl std::fmt::Display for WmoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WmoError::UnknownT1(t1) => write!(f, "Unknown WMO data type {}", t1),
+            WmoError::UnknownT2(t1t2) => write!(f, "Unknown t2 data type in {}", t1t2),
+            WmoError::UnknownArea(ttaa) => write!(f, "Unknown area designator in {}", ttaa),
+        }
+    }
+}
+
+impl std::error::Error for WmoError {}
 
 /// Parse a WMO abbreviated heading
 ///
@@ -11,9 +35,15 @@
 /// # References:
 ///
 /// * https://library.wmo.int/doc_num.php?explnum_id=10469
-pub fn parse_wmo_abbreviated_heading(t1: char, t2: char, aa: &str) -> (WMODataTypeT1, WMODataTypeT2, Area) {
+pub fn parse_wmo_abbreviated_heading(
+    t1: char,
+    t2: char,
+    aa: &str,
+) -> Result<(WMODataTypeT1, WMODataTypeT2, Area), WmoError> {
     // The first character (T1) indicates data type
-    let data_type = WMODataTypeT1::from(t1);
+    let data_type = WMODataTypeT1::try_from(t1)?;
+    let unknown_t2 = || WmoError::UnknownT2(format!("{}{}", t1, t2));
+    let unknown_area = || WmoError::UnknownArea(format!("{}{}{}", t1, t2, aa));
 
     // The next two characters (T2) depend on the data type
     let data_type_2 = match data_type {
@@ -26,11 +56,11 @@ pub fn parse_wmo_abbreviated_heading(t1: char, t2: char, aa: &str) -> (WMODataTy
         | WMODataTypeT1::UpperAirData
         | WMODataTypeT1::Warnings => {
             // table B1 is used to look up the next data type
-            lookup_table_b1(data_type, t2)
+            lookup_table_b1(data_type, t2).ok_or_else(unknown_t2)?
         }
-        WMODataTypeT1::Pictoral | WMODataTypeT1::PictoralRegional => lookup_table_b6(t2),
-        WMODataTypeT1::SatelliteImg => lookup_table_b5(t2),
-        x => panic!("Unknow {:?}", x),
+        WMODataTypeT1::Pictoral | WMODataTypeT1::PictoralRegional => lookup_table_b6(t2).ok_or_else(unknown_t2)?,
+        WMODataTypeT1::SatelliteImg => lookup_table_b5(t2).ok_or_else(unknown_t2)?,
+        _ => return Err(WmoError::UnknownT1(t1)),
     };
 
     // next is A1 and A2.  This is nominally an area designator, but T1 can adjust
@@ -44,50 +74,38 @@ pub fn parse_wmo_abbreviated_heading(t1: char, t2: char, aa: &str) -> (WMODataTy
         | WMODataTypeT1::Notices
         | WMODataTypeT1::Warnings => {
             // these types ues table c1 to look up area designator
-            let a = AreaDesignator::from_c1(aa).unwrap_or_else(|| {
-                panic!(
-                    "Unknown area designator: {} for {}{}: {:?} {:?}",
-                    aa, t1, t2, data_type, data_type_2
-                )
-            });
-            Area::Area(a)
+            Area::Area(AreaDesignator::from_c1(aa).ok_or_else(unknown_area)?)
         }
         WMODataTypeT1::SurfaceData | WMODataTypeT1::UpperAirData => {
             let mut c = aa.chars();
-            let a1 = c.next().unwrap();
-            let a2 = c.next().unwrap();
+            let a1 = c.next().ok_or_else(unknown_area)?;
+            let a2 = c.next().ok_or_else(unknown_area)?;
 
             if let Some((a, b)) = lookup_nature_and_area(a1, a2) {
                 Area::ReportArea(a, b)
             } else {
                 // fall back to table c1
-                let a = AreaDesignator::from_c1(aa).unwrap_or_else(|| {
-                    panic!(
-                        "Unknown area designator: {} for {}{}: {:?} {:?}",
-                        aa, t1, t2, data_type, data_type_2
-                    )
-                });
-                Area::Area(a)
+                Area::Area(AreaDesignator::from_c1(aa).ok_or_else(unknown_area)?)
             }
         }
         WMODataTypeT1::PictoralRegional | WMODataTypeT1::SatalliteData => {
             let mut c = aa.chars();
-            let a1 = c.next().unwrap();
-            let a2 = c.next().unwrap();
-            let a = GeographicalAreaDesignator::from_c3(a1).unwrap();
+            let a1 = c.next().ok_or_else(unknown_area)?;
+            let a2 = c.next().ok_or_else(unknown_area)?;
+            let a = GeographicalAreaDesignator::from_c3(a1).ok_or_else(unknown_area)?;
 
             let t = if data_type == WMODataTypeT1::SatalliteData {
-                TimeDesignator::from_c4(a2).unwrap()
+                TimeDesignator::from_c4(a2)
             } else {
-                TimeDesignator::from_c5(a2).unwrap()
+                TimeDesignator::from_c5(a2)
             };
 
-            Area::GeoArea(a, t)
+            Area::GeoArea(a, t.ok_or_else(unknown_area)?)
         }
-        _ => panic!("Unable to lookup area designator for {:?} {}", data_type, t2),
+        _ => return Err(unknown_area()),
     };
 
-    (data_type, data_type_2, area)
+    Ok((data_type, data_type_2, area))
 }
 
 fn lookup_nature_and_area(a1: char, a2: char) -> Option<(ReportAreaDesignator, ReportNature)> {
@@ -176,6 +194,28 @@ pub enum WMODataTypeT1 {
     Warnings,
 }
 
+impl WMODataTypeT1 {
+    /// What this data type is, in English (see [`Language::tr`](crate::i18n::Language::tr) to
+    /// translate it)
+    pub fn name(&self) -> &'static str {
+        match self {
+            WMODataTypeT1::Analyses => "Analyses",
+            WMODataTypeT1::AddressedMessage => "Addressed message",
+            WMODataTypeT1::ClimaticData => "Climatic data",
+            WMODataTypeT1::GridD => "Grid point information",
+            WMODataTypeT1::SatelliteImg => "Satellite imagery",
+            WMODataTypeT1::Forecasts => "Forecasts",
+            WMODataTypeT1::Notices => "Notices",
+            WMODataTypeT1::Pictoral => "Pictorial information",
+            WMODataTypeT1::PictoralRegional => "Pictorial information (regional)",
+            WMODataTypeT1::SurfaceData => "Surface data",
+            WMODataTypeT1::SatalliteData => "Satellite data",
+            WMODataTypeT1::UpperAirData => "Upper-air data",
+            WMODataTypeT1::Warnings => "Warnings",
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 /// WMODataTypeT2
 ///
@@ -820,6 +860,176 @@ pub enum WMODataTypeT2 {
     UnknownSatellite(char),
 }
 
+impl WMODataTypeT2 {
+    /// What this data type is, in English (see [`Language::tr`](crate::i18n::Language::tr) to
+    /// translate it)
+    pub fn name(&self) -> &'static str {
+        match self {
+            WMODataTypeT2::TemperaturePrecipitationTable => "Temperature and precipitation table",
+            WMODataTypeT2::CycloneAnalysis => "Cyclone",
+            WMODataTypeT2::AirQualityAlert => "Air quality alert",
+            WMODataTypeT2::HydrologicalMarineAnalysis => "Hydrological/marine",
+            WMODataTypeT2::Thickness | WMODataTypeT2::ThicknessImg => "Thickness",
+            WMODataTypeT2::Ice => "Ice",
+            WMODataTypeT2::Ozone => "Ozone layer",
+            WMODataTypeT2::Radar => "Radar",
+            WMODataTypeT2::SurfaceAnalysis | WMODataTypeT2::SurfaceForecast => "Surface",
+            WMODataTypeT2::UpperAirAnalysis | WMODataTypeT2::UpperAirForecast => "Upper air",
+            WMODataTypeT2::WeatherSummary => "Weather summary",
+            WMODataTypeT2::MiscellaneousAnalysis
+            | WMODataTypeT2::MiscellaneousForecast
+            | WMODataTypeT2::MiscellaneousSurface
+            | WMODataTypeT2::MiscellaneousSatellite
+            | WMODataTypeT2::MiscellaneousUpperAir => "Miscellaneous",
+            WMODataTypeT2::ClimateAnomalies => "Climate anomalies",
+            WMODataTypeT2::ClimatologicalReportDaily => "Climatological report (daily)",
+            WMODataTypeT2::ClimatologicalReport => "Climatological report",
+            WMODataTypeT2::MonthlyMeansUpperAir => "Monthly means (upper air)",
+            WMODataTypeT2::MonthlyMeansSurface | WMODataTypeT2::MonthlyMeansSurface2 => "Monthly means (surface)",
+            WMODataTypeT2::MonthlyMeansOceanAreas => "Monthly means (ocean areas)",
+            WMODataTypeT2::AviationAreaAdvisories => "Aviation area/GAMET/advisories",
+            WMODataTypeT2::UpperWindsAndTemperatures => "Upper winds and temperatures",
+            WMODataTypeT2::Aerodrome => "Aerodrome (VT < 12 hours)",
+            WMODataTypeT2::RadiologicalTrajectoryDose => "Radiological trajectory dose",
+            WMODataTypeT2::Extended => "Extended",
+            WMODataTypeT2::Shipping => "Shipping",
+            WMODataTypeT2::Hydrological | WMODataTypeT2::HydrologicalNotice => "Hydrological",
+            WMODataTypeT2::UpperAirThickness => "Upper air thickness",
+            WMODataTypeT2::Iceberg => "Iceberg",
+            WMODataTypeT2::RadioWarningService => "Radio warning service",
+            WMODataTypeT2::TropicalCycloneAdvisories => "Tropical cyclone advisories",
+            WMODataTypeT2::LocalArea => "Local/area",
+            WMODataTypeT2::TemperatureExtremes => "Temperature extremes",
+            WMODataTypeT2::SpaceWeatherAdvisories => "Space weather advisories",
+            WMODataTypeT2::Guidance => "Guidance",
+            WMODataTypeT2::Public => "Public",
+            WMODataTypeT2::OtherShipping => "Other shipping",
+            WMODataTypeT2::AviationRoute => "Aviation route",
+            WMODataTypeT2::Aerodrome12 => "Aerodrome (VT >= 12 hours)",
+            WMODataTypeT2::VolcanicAshAdvisories => "Volcanic ash advisories",
+            WMODataTypeT2::WinterSports => "Winter sports",
+            WMODataTypeT2::ShippingArea => "Shipping area",
+            WMODataTypeT2::MarineNotice => "Marine",
+            WMODataTypeT2::NuclearEmergencyResponse => "Nuclear emergency response",
+            WMODataTypeT2::METNOWIFMANotice => "METNO/WIFMA",
+            WMODataTypeT2::ProductGenerationDelay => "Product generation delay",
+            WMODataTypeT2::TestMsg => "Test message",
+            WMODataTypeT2::WarningRelatedCancellation => "Warning related and/or cancellation",
+            WMODataTypeT2::RegionalWeatherRoundup => "Regional weather roundup",
+            WMODataTypeT2::AviationRoutineReports => "Aviation routine reports",
+            WMODataTypeT2::RadarReportsPartA => "Radar reports (part A)",
+            WMODataTypeT2::RadarReportsPartB => "Radar reports (part B)",
+            WMODataTypeT2::RadarReportsPartsAB => "Radar reports (parts A and B)",
+            WMODataTypeT2::SeismicData => "Seismic data",
+            WMODataTypeT2::AtmosphericsReports => "Atmospherics reports",
+            WMODataTypeT2::RadiologicalDataReport => "Radiological data report",
+            WMODataTypeT2::ReportsFromDCPStations => "Reports from DCP stations",
+            WMODataTypeT2::IntermediateSynopticHour => "Intermediate synoptic hour",
+            WMODataTypeT2::NotUsed | WMODataTypeT2::NotAssignedImg => "Not assigned",
+            WMODataTypeT2::MainSynopticHour => "Main synoptic hour",
+            WMODataTypeT2::NonStandardSynopticHour => "Non-standard synoptic hour",
+            WMODataTypeT2::OceanographicData => "Oceanographic data",
+            WMODataTypeT2::SpecialAviationWeatherReports => "Special aviation weather reports",
+            WMODataTypeT2::HydrologicalRiverReports => "Hydrological (river) reports",
+            WMODataTypeT2::DriftingBouyReports => "Drifting buoy reports",
+            WMODataTypeT2::SeaIce => "Sea ice",
+            WMODataTypeT2::SnowDepth => "Snow depth",
+            WMODataTypeT2::LakeIce => "Lake ice",
+            WMODataTypeT2::WaveInformation => "Wave information",
+            WMODataTypeT2::SeismicWaveformData => "Seismic waveform data",
+            WMODataTypeT2::TsunamiData => "Sea level and deep-ocean tsunami data",
+            WMODataTypeT2::SatelliteOrbitParameters => "Satellite orbit parameters",
+            WMODataTypeT2::SatelliteCloudInterpretations => "Satellite cloud interpretations",
+            WMODataTypeT2::SatelliteRemoteUpperAirSounding => "Satellite remote upper-air soundings",
+            WMODataTypeT2::ClearRadianceObservations => "Clear radiance observations",
+            WMODataTypeT2::SeaSurfaceTemperatures => "Sea surface temperatures",
+            WMODataTypeT2::WindsAndCloudTemperatures => "Winds and cloud temperatures",
+            WMODataTypeT2::AircraftReports41 => "Aircraft reports (FM 41)",
+            WMODataTypeT2::AircraftReports42 => "Aircraft reports (FM 42)",
+            WMODataTypeT2::AircraftReport | WMODataTypeT2::AircraftReport2 => "Aircraft report",
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartA => {
+                "Upper-level pressure, temperature, humidity and wind (part A)"
+            }
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartB => {
+                "Upper-level pressure, temperature, humidity and wind (part B)"
+            }
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartC => {
+                "Upper-level pressure, temperature, humidity and wind (part C)"
+            }
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartD => {
+                "Upper-level pressure, temperature, humidity and wind (part D)"
+            }
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartsAB => {
+                "Upper-level pressure, temperature, humidity and wind (parts A and B)"
+            }
+            WMODataTypeT2::UpperLevelPressureTemperatureHumidityWindPartCD => {
+                "Upper-level pressure, temperature, humidity and wind (parts C and D)"
+            }
+            WMODataTypeT2::PTHWFromSonde => {
+                "Upper-level pressure, temperature, humidity and wind from a released sonde"
+            }
+            WMODataTypeT2::UpperWindPartA => "Upper wind (part A)",
+            WMODataTypeT2::UpperWindPartB => "Upper wind (part B)",
+            WMODataTypeT2::UpperWindPartC => "Upper wind (part C)",
+            WMODataTypeT2::UpperWindPartD => "Upper wind (part D)",
+            WMODataTypeT2::UpperWindPartsAB => "Upper wind (parts A and B)",
+            WMODataTypeT2::UpperWindPartsCD => "Upper wind (parts C and D)",
+            WMODataTypeT2::RocketsondeReports => "Rocketsonde reports",
+            WMODataTypeT2::AIRMET => "AIRMET",
+            WMODataTypeT2::TropicalCyclone => "Tropical cyclone (SIGMET)",
+            WMODataTypeT2::Tsunami => "Tsunami",
+            WMODataTypeT2::Tornado => "Tornado",
+            WMODataTypeT2::HydrologicalRiverFloor => "Hydrological/river flood",
+            WMODataTypeT2::MarineCoastalFlood => "Marine/coastal flood",
+            WMODataTypeT2::OtherWarning => "Other",
+            WMODataTypeT2::HumanitarianActivities => "Humanitarian activities",
+            WMODataTypeT2::SIGMET => "SIGMET",
+            WMODataTypeT2::TropicalCyclone2 => "Tropical cyclone (typhoon/hurricane)",
+            WMODataTypeT2::SevereThunderstorm => "Severe thunderstorm",
+            WMODataTypeT2::VolcanicAshClouds => "Volcanic ash clouds (SIGMET)",
+            WMODataTypeT2::WarningsAndWeatherSummary => "Warnings and weather summary",
+            WMODataTypeT2::RadarDataImg => "Radar data",
+            WMODataTypeT2::CloudImg => "Cloud",
+            WMODataTypeT2::ClearAirTurbulenceImg => "Clear air turbulence",
+            WMODataTypeT2::PrecipitationImg => "Precipitation",
+            WMODataTypeT2::AerologicalDiagramsImg => "Aerological diagrams (ash cloud)",
+            WMODataTypeT2::SignificantWeatherImg => "Significant weather",
+            WMODataTypeT2::HeightImg => "Height",
+            WMODataTypeT2::IceFlowImg => "Ice flow",
+            WMODataTypeT2::WaveHeightCombinationsImg => "Wave height and combinations",
+            WMODataTypeT2::SwellHeightCombinationsImg => "Swell height and combinations",
+            WMODataTypeT2::PlainLanguageImg => "Plain language",
+            WMODataTypeT2::NationalUseImg => "For national use",
+            WMODataTypeT2::RadiationImg => "Radiation",
+            WMODataTypeT2::VerticalVelocityImg => "Vertical velocity",
+            WMODataTypeT2::PressureImg => "Pressure",
+            WMODataTypeT2::WetBulbPotentialTemperatureImg => "Wet bulb potential temperature",
+            WMODataTypeT2::RelativeHumidityImg => "Relative humidity",
+            WMODataTypeT2::SnowCoverImg => "Snow cover",
+            WMODataTypeT2::TemperatureImg => "Temperature",
+            WMODataTypeT2::EastwardWindComponentImg => "Eastward wind component",
+            WMODataTypeT2::NorthwardWindComponentImg => "Northward wind component",
+            WMODataTypeT2::WindImg => "Wind",
+            WMODataTypeT2::LiftedIndexImg => "Lifted index",
+            WMODataTypeT2::ObservationalPlottedChartImg => "Observational plotted chart",
+            WMODataTypeT2::CloudTopTemperatureSatImg => "Cloud top temperature",
+            WMODataTypeT2::FogSatImg => "Fog",
+            WMODataTypeT2::InfraredSatImg => "Infrared",
+            WMODataTypeT2::SurfaceTemperatureSatImg => "Surface temperature",
+            WMODataTypeT2::VisibleSatImg => "Visible",
+            WMODataTypeT2::WaterVaporSatImg => "Water vapor",
+            WMODataTypeT2::UserSpecifiedSatImg => "User specified",
+            WMODataTypeT2::UnspecifiedSatImg => "Unspecified",
+            WMODataTypeT2::UnknownAnalyses(_)
+            | WMODataTypeT2::UnknownClimate(_)
+            | WMODataTypeT2::UnknownNotice(_)
+            | WMODataTypeT2::UnknownUpperAir(_)
+            | WMODataTypeT2::UnknownWarning(_)
+            | WMODataTypeT2::UnknownSatellite(_) => "Unknown",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum AreaDesignator {
     Albania,
@@ -1490,7 +1700,7 @@ impl AreaDesignator {
             "XX" => AreaDesignator::Unknown,
 
             x => {
-                println!("Unknown area designator: {}", x);
+                log::debug!("Unknown area designator: {}", x);
                 return None;
             }
         })
@@ -1605,7 +1815,7 @@ impl GeographicalAreaDesignator {
             'X' => Some(GeographicalAreaDesignator::GlobalArea),
             'U' => Some(GeographicalAreaDesignator::UnknownU),
             'P' => Some(GeographicalAreaDesignator::UnknownP),
-            x => panic!("unknown c3: {}", x),
+            _ => None,
         }
     }
 }
@@ -1669,7 +1879,7 @@ impl TimeDesignator {
             'T' => Some(TimeDesignator::Forecast10Days),
             'U' => Some(TimeDesignator::Forecast15Days),
             'V' => Some(TimeDesignator::Forecast30Days),
-            x => panic!("unknown C4 time designator: {}", x),
+            _ => None,
         }
     }
     pub fn from_c5(c: char) -> Option<TimeDesignator> {
@@ -1691,13 +1901,14 @@ impl TimeDesignator {
             'O' => Some(TimeDesignator::Forecast42Hours),
             'P' => Some(TimeDesignator::Forecast45Hours),
             'Q' => Some(TimeDesignator::Forecast48Hours),
-            x => panic!("unknown C5 time designator: {}", x),
+            _ => None,
         }
     }
 }
 
-pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> WMODataTypeT2 {
-    match dt {
+/// Looks up a T2 code in table B1, returning `None` if it isn't there for the data type
+pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> Option<WMODataTypeT2> {
+    Some(match dt {
         WMODataTypeT1::Analyses => match t2 {
             'B' => WMODataTypeT2::TemperaturePrecipitationTable,
             'C' => WMODataTypeT2::CycloneAnalysis,
@@ -1749,7 +1960,7 @@ pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> WMODataTypeT2 {
             'W' => WMODataTypeT2::WinterSports,
             'X' => WMODataTypeT2::MiscellaneousForecast,
             'Z' => WMODataTypeT2::ShippingArea,
-            _ => panic!("Unknown t2 data type {} for t1 {:?}", t2, dt),
+            _ => return None,
         },
         WMODataTypeT1::Notices => match t2 {
             'G' => WMODataTypeT2::Hydrological,
@@ -1761,7 +1972,6 @@ pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> WMODataTypeT2 {
             'W' => WMODataTypeT2::WarningRelatedCancellation,
             'Z' => WMODataTypeT2::RegionalWeatherRoundup,
             x => WMODataTypeT2::UnknownNotice(x),
-            // _ => panic!("Unknown t2 data type {} for t1 {:?}", t2, dt)
         },
         WMODataTypeT1::SurfaceData => match t2 {
             'A' => WMODataTypeT2::AviationRoutineReports,
@@ -1787,7 +1997,7 @@ pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> WMODataTypeT2 {
             'X' => WMODataTypeT2::MiscellaneousSurface,
             'Y' => WMODataTypeT2::SeismicWaveformData,
             'Z' => WMODataTypeT2::TsunamiData,
-            _ => panic!("Unknown t2 data type {} for t1 {:?}", t2, dt),
+            _ => return None,
         },
         WMODataTypeT1::SatalliteData => match t2 {
             'B' => WMODataTypeT2::SatelliteOrbitParameters,
@@ -1835,14 +2045,15 @@ pub fn lookup_table_b1(dt: WMODataTypeT1, t2: char) -> WMODataTypeT2 {
             'U' => WMODataTypeT2::SevereThunderstorm,
             'V' => WMODataTypeT2::VolcanicAshClouds,
             'W' => WMODataTypeT2::WarningRelatedCancellation,
-            _ => panic!("Unknown t2 data type {} for t1 {:?}", t2, dt),
+            _ => return None,
         },
-        _ => panic!("Unknown t2 data type {} for t1 {:?}", t2, dt),
-    }
+        _ => return None,
+    })
 }
 
-pub fn lookup_table_b6(t2: char) -> WMODataTypeT2 {
-    match t2 {
+/// Looks up a T2 code in table B6 (for pictorial information)
+pub fn lookup_table_b6(t2: char) -> Option<WMODataTypeT2> {
+    Some(match t2 {
         'A' => WMODataTypeT2::RadarDataImg,
         'B' => WMODataTypeT2::CloudImg,
         'C' => WMODataTypeT2::ClearAirTurbulenceImg,
@@ -1869,12 +2080,13 @@ pub fn lookup_table_b6(t2: char) -> WMODataTypeT2 {
         'X' => WMODataTypeT2::LiftedIndexImg,
         'Y' => WMODataTypeT2::ObservationalPlottedChartImg,
         'Z' => WMODataTypeT2::NotAssignedImg,
-        _ => panic!("Unknown table b5 t2 data type {}", t2),
-    }
+        _ => return None,
+    })
 }
 
-pub fn lookup_table_b5(t2: char) -> WMODataTypeT2 {
-    match t2 {
+/// Looks up a T2 code in table B5 (for satellite imagery)
+pub fn lookup_table_b5(t2: char) -> Option<WMODataTypeT2> {
+    Some(match t2 {
         'C' => WMODataTypeT2::CloudTopTemperatureSatImg,
         'F' => WMODataTypeT2::FogSatImg,
         'I' => WMODataTypeT2::InfraredSatImg,
@@ -1883,13 +2095,15 @@ pub fn lookup_table_b5(t2: char) -> WMODataTypeT2 {
         'W' => WMODataTypeT2::WaterVaporSatImg,
         'Y' => WMODataTypeT2::UserSpecifiedSatImg,
         'Z' => WMODataTypeT2::UnspecifiedSatImg,
-        _ => panic!("Unknown table b5 t2 data type {}", t2),
-    }
+        _ => return None,
+    })
 }
 
-impl From<char> for WMODataTypeT1 {
-    fn from(c: char) -> Self {
-        match c {
+impl TryFrom<char> for WMODataTypeT1 {
+    type Error = WmoError;
+
+    fn try_from(c: char) -> Result<Self, WmoError> {
+        Ok(match c {
             'A' => WMODataTypeT1::Analyses,
             'B' => WMODataTypeT1::AddressedMessage,
             'C' => WMODataTypeT1::ClimaticData,
@@ -1903,8 +2117,8 @@ impl From<char> for WMODataTypeT1 {
             'P' => WMODataTypeT1::Pictoral,
             'Q' => WMODataTypeT1::PictoralRegional,
             'E' => WMODataTypeT1::SatelliteImg,
-            x => panic!("Unknown WMO data type {}", x),
-        }
+            x => return Err(WmoError::UnknownT1(x)),
+        })
     }
 }
 
@@ -1918,3 +2132,72 @@ pub enum Area {
     /// automatic marine stations.
     ReportArea(ReportAreaDesignator, ReportNature),
 }
+
+/// The abbreviated heading line found at the top of every GTS bulletin
+///
+/// This has the form `TTAAii CCCC YYGGgg [BBB]`, where `YYGGgg` is the day of month, hour and
+/// minute of the bulletin, and the optional `BBB` indicates a delayed, corrected or amended bulletin.
+///
+/// # References:
+///
+/// * https://library.wmo.int/doc_num.php?explnum_id=10469 (Part II, section 2.3.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbbreviatedHeading {
+    pub ttaaii: String,
+    pub cccc: String,
+    pub yygggg: String,
+    pub bbb: Option<String>,
+}
+
+impl AbbreviatedHeading {
+    /// Parses a heading line, returning `None` if the line doesn't look like an abbreviated heading
+    pub fn parse(line: &str) -> Option<AbbreviatedHeading> {
+        let mut fields = line.split_whitespace();
+
+        let ttaaii = fields.next()?;
+        let cccc = fields.next()?;
+        let yygggg = fields.next()?;
+        let bbb = fields.next();
+
+        if ttaaii.len() != 6
+            || !ttaaii.is_ascii()
+            || !ttaaii[..4].chars().all(|c| c.is_ascii_uppercase())
+            || !ttaaii[4..].chars().all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        if cccc.len() != 4 || !cccc.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return None;
+        }
+        if yygggg.len() != 6 || !yygggg.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        if let Some(bbb) = bbb {
+            if bbb.len() != 3 || !bbb.chars().all(|c| c.is_ascii_uppercase()) {
+                return None;
+            }
+        }
+        if fields.next().is_some() {
+            return None;
+        }
+
+        Some(AbbreviatedHeading {
+            ttaaii: ttaaii.to_string(),
+            cccc: cccc.to_string(),
+            yygggg: yygggg.to_string(),
+            bbb: bbb.map(|s| s.to_string()),
+        })
+    }
+
+    /// A filesystem-friendly name for a bulletin with this heading
+    ///
+    /// For example, `SAUS70_KWBC_041800` or `SAUS70_KWBC_041800_RRA`
+    pub fn filename(&self) -> String {
+        let mut name = format!("{}_{}_{}", self.ttaaii, self.cccc, self.yygggg);
+        if let Some(bbb) = &self.bbb {
+            name.push('_');
+            name.push_str(bbb);
+        }
+        name
+    }
+}
@@ -0,0 +1,325 @@
+//! The areas that UGC codes stand for, from the NWS zone and county shapefiles
+//!
+//! [`parse_ugc`](super::ugc::parse_ugc) gives the counties and zones a product is for, by code.  A
+//! [`ZoneMap`] knows the boundaries of those counties and zones, so a product can be checked
+//! against a point, like "is my house in the warned area".
+//!
+//! The boundaries come from the shapefiles the NWS publishes (there's one for public forecast
+//! zones, one for counties, and a few for marine zones).  Each record's UGC code is made from its
+//! attributes: `ID` if it's a whole code (like the marine zones' `ANZ430`), `STATE_ZONE` or
+//! `STATE` and `ZONE` for forecast zones, or `STATE` and `FIPS` for counties.
+//!
+//! # References
+//!
+//! * NWS GIS data (https://www.weather.gov/gis/AWIPSShapefiles)
+//! * ESRI Shapefile Technical Description
+//!   (https://www.esri.com/content/dam/esrisites/sitecore-archive/Files/Pdfs/library/whitepapers/pdfs/shapefile.pdf)
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+/// The boundary of a county or zone
+///
+/// It's made of rings of (longitude, latitude) points.  Counties with islands have more than one,
+/// and so do zones with holes in them, so a point is inside if it's inside an odd number of rings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Polygon {
+    pub rings: Vec<Vec<(f64, f64)>>,
+}
+
+impl Polygon {
+    /// Returns true if the point is inside the polygon
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let mut inside = false;
+        for ring in &self.rings {
+            for (i, &(x1, y1)) in ring.iter().enumerate() {
+                let (x2, y2) = ring[(i + 1) % ring.len()];
+                if (y1 > lat) != (y2 > lat) && lon < x1 + (lat - y1) * (x2 - x1) / (y2 - y1) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+}
+
+/// The boundaries of counties and zones, by UGC code, see the [module docs](self)
+#[derive(Debug, Clone, Default)]
+pub struct ZoneMap {
+    polygons: HashMap<String, Polygon>,
+}
+
+impl ZoneMap {
+    pub fn new() -> ZoneMap {
+        ZoneMap::default()
+    }
+
+    /// Adds the boundary of a county or zone, like "PAZ070"
+    ///
+    /// If the code already has a boundary, this adds to it.
+    pub fn insert(&mut self, ugc: &str, polygon: Polygon) {
+        self.polygons
+            .entry(ugc.to_string())
+            .or_default()
+            .rings
+            .extend(polygon.rings);
+    }
+
+    /// Loads a shapefile, given the path of its `.shp` file (the `.dbf` file next to it has the
+    /// attributes), returning how many counties or zones it has
+    ///
+    /// Records that don't have a UGC code, or aren't polygons, are skipped.
+    pub fn load_shapefile(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let path = path.as_ref();
+        let shapes = read_shp(&std::fs::read(path)?)?;
+        let records = read_dbf(&std::fs::read(path.with_extension("dbf"))?)?;
+        if shapes.len() != records.len() {
+            return Err(invalid("the .shp and .dbf files have a different number of records"));
+        }
+        let mut codes = Vec::new();
+        for (polygon, record) in shapes.into_iter().zip(records) {
+            if let (Some(polygon), Some(ugc)) = (polygon, ugc_for(&record)) {
+                self.insert(&ugc, polygon);
+                codes.push(ugc);
+            }
+        }
+        codes.sort_unstable();
+        codes.dedup();
+        Ok(codes.len())
+    }
+
+    /// The boundary of a county or zone, like "PAZ070"
+    pub fn polygon_for(&self, ugc: &str) -> Option<&Polygon> {
+        self.polygons.get(ugc)
+    }
+
+    /// Returns true if a county or zone includes the point (and false if its boundary isn't
+    /// known)
+    pub fn contains(&self, ugc: &str, lat: f64, lon: f64) -> bool {
+        self.polygon_for(ugc).is_some_and(|p| p.contains(lat, lon))
+    }
+
+    pub fn len(&self) -> usize {
+        self.polygons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// The UGC code of a shapefile record, from its attributes
+fn ugc_for(record: &HashMap<String, String>) -> Option<String> {
+    let field = |name: &str| record.get(name).map(String::as_str).filter(|v| !v.is_empty());
+    let is_code = |code: &str| {
+        code.len() == 6
+            && code[..2].bytes().all(|b| b.is_ascii_uppercase())
+            && matches!(&code[2..3], "C" | "Z")
+            && code[3..].bytes().all(|b| b.is_ascii_digit())
+    };
+    let code = if let Some(id) = field("ID").filter(|id| is_code(id)) {
+        id.to_string()
+    } else if let Some(state_zone) = field("STATE_ZONE").filter(|sz| sz.len() == 5) {
+        format!("{}Z{}", &state_zone[..2], &state_zone[2..])
+    } else if let (Some(state), Some(zone)) = (field("STATE"), field("ZONE")) {
+        format!("{}Z{:0>3}", state, zone)
+    } else if let (Some(state), Some(fips)) = (field("STATE"), field("FIPS").filter(|f| f.len() == 5)) {
+        format!("{}C{}", state, &fips[2..])
+    } else {
+        return None;
+    };
+    match is_code(&code) {
+        true => Some(code),
+        false => None,
+    }
+}
+
+/// Reads the polygons from a `.shp` file, with `None` for records that aren't polygons
+fn read_shp(bytes: &[u8]) -> io::Result<Vec<Option<Polygon>>> {
+    if bytes.len() < 100 || BigEndian::read_i32(&bytes[0..4]) != 9994 {
+        return Err(invalid("not a shapefile"));
+    }
+    let mut shapes = Vec::new();
+    let mut offset = 100;
+    while offset + 8 <= bytes.len() {
+        // the length is in 16 bit words
+        let len = BigEndian::read_i32(&bytes[offset + 4..offset + 8]) as usize * 2;
+        let content = bytes
+            .get(offset + 8..offset + 8 + len)
+            .ok_or_else(|| invalid("truncated shapefile record"))?;
+        shapes.push(read_polygon(content)?);
+        offset += 8 + len;
+    }
+    Ok(shapes)
+}
+
+fn read_polygon(content: &[u8]) -> io::Result<Option<Polygon>> {
+    if content.len() < 4 {
+        return Err(invalid("truncated shapefile record"));
+    }
+    // Polygon, PolygonZ, and PolygonM all start the same way
+    if !matches!(LittleEndian::read_i32(&content[..4]), 5 | 15 | 25) {
+        return Ok(None);
+    }
+    if content.len() < 44 {
+        return Err(invalid("truncated polygon"));
+    }
+    let num_parts = LittleEndian::read_i32(&content[36..40]) as usize;
+    let num_points = LittleEndian::read_i32(&content[40..44]) as usize;
+    let points_start = 44 + num_parts * 4;
+    if content.len() < points_start + num_points * 16 {
+        return Err(invalid("truncated polygon"));
+    }
+    let mut starts: Vec<usize> = content[44..points_start]
+        .chunks(4)
+        .map(|c| LittleEndian::read_i32(c) as usize)
+        .collect();
+    starts.push(num_points);
+    let point = |i: usize| {
+        let p = &content[points_start + i * 16..points_start + i * 16 + 16];
+        (LittleEndian::read_f64(&p[..8]), LittleEndian::read_f64(&p[8..]))
+    };
+    let rings = starts
+        .windows(2)
+        .filter(|w| w[0] < w[1] && w[1] <= num_points)
+        .map(|w| (w[0]..w[1]).map(point).collect())
+        .collect();
+    Ok(Some(Polygon { rings }))
+}
+
+/// Reads the attributes of each record from a `.dbf` file
+fn read_dbf(bytes: &[u8]) -> io::Result<Vec<HashMap<String, String>>> {
+    if bytes.len() < 32 {
+        return Err(invalid("truncated .dbf file"));
+    }
+    let num_records = LittleEndian::read_u32(&bytes[4..8]) as usize;
+    let header_len = LittleEndian::read_u16(&bytes[8..10]) as usize;
+    let record_len = LittleEndian::read_u16(&bytes[10..12]) as usize;
+    let mut fields = Vec::new();
+    let mut offset = 32;
+    while offset + 32 <= header_len.min(bytes.len()) && bytes[offset] != 0x0d {
+        let descriptor = &bytes[offset..offset + 32];
+        let name_len = descriptor[..11].iter().position(|&b| b == 0).unwrap_or(11);
+        let name = String::from_utf8_lossy(&descriptor[..name_len]).to_ascii_uppercase();
+        fields.push((name, descriptor[16] as usize));
+        offset += 32;
+    }
+    let mut records = Vec::with_capacity(num_records);
+    for i in 0..num_records {
+        let start = header_len + i * record_len;
+        let record = bytes
+            .get(start..start + record_len)
+            .ok_or_else(|| invalid("truncated .dbf file"))?;
+        // the first byte is the deletion flag
+        let mut offset = 1;
+        let mut values = HashMap::new();
+        for (name, len) in &fields {
+            let value = record.get(offset..offset + len).unwrap_or_default();
+            values.insert(name.clone(), String::from_utf8_lossy(value).trim().to_string());
+            offset += len;
+        }
+        records.push(values);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Polygon, ZoneMap};
+
+    /// A ring, and the record's attributes
+    type Record<'a> = (Vec<(f64, f64)>, Vec<&'a str>);
+
+    /// Writes a shapefile of polygons with one ring each, and character attributes
+    fn write_shapefile(path: &Path, fields: &[&str], records: &[Record]) {
+        let mut shp = vec![0; 100];
+        shp[..4].copy_from_slice(&9994i32.to_be_bytes());
+        for (i, (ring, _)) in records.iter().enumerate() {
+            let mut content = 5i32.to_le_bytes().to_vec();
+            content.extend_from_slice(&[0; 32]);
+            content.extend_from_slice(&1i32.to_le_bytes());
+            content.extend_from_slice(&(ring.len() as i32).to_le_bytes());
+            content.extend_from_slice(&0i32.to_le_bytes());
+            for (x, y) in ring {
+                content.extend_from_slice(&x.to_le_bytes());
+                content.extend_from_slice(&y.to_le_bytes());
+            }
+            shp.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+            shp.extend_from_slice(&(content.len() as i32 / 2).to_be_bytes());
+            shp.extend_from_slice(&content);
+        }
+        std::fs::write(path, shp).unwrap();
+
+        let field_len = 10;
+        let mut dbf = vec![3, 0, 0, 0];
+        dbf.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        dbf.extend_from_slice(&(32 + 32 * fields.len() as u16 + 1).to_le_bytes());
+        dbf.extend_from_slice(&(1 + field_len * fields.len() as u16).to_le_bytes());
+        dbf.resize(32, 0);
+        for name in fields {
+            let mut descriptor = [0; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = b'C';
+            descriptor[16] = field_len as u8;
+            dbf.extend_from_slice(&descriptor);
+        }
+        dbf.push(0x0d);
+        for (_, values) in records {
+            dbf.push(b' ');
+            for value in values {
+                dbf.extend_from_slice(format!("{:<10}", value).as_bytes());
+            }
+        }
+        std::fs::write(path.with_extension("dbf"), dbf).unwrap();
+    }
+
+    #[test]
+    fn test_zone_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let square = |lon: f64, lat: f64| vec![(lon, lat), (lon + 1.0, lat), (lon + 1.0, lat + 1.0), (lon, lat + 1.0)];
+        let counties = dir.path().join("c_05mr24.shp");
+        write_shapefile(
+            &counties,
+            &["STATE", "FIPS", "COUNTYNAME"],
+            &[
+                (square(-76.0, 40.0), vec!["PA", "42017", "Bucks"]),
+                (square(-75.0, 40.0), vec!["NJ", "34005", "Burlington"]),
+                (square(-74.0, 40.0), vec!["", "", "Nowhere"]),
+            ],
+        );
+        let zones = dir.path().join("z_05mr24.shp");
+        write_shapefile(&zones, &["STATE_ZONE"], &[(square(-76.0, 40.0), vec!["PA106"])]);
+
+        let mut map = ZoneMap::new();
+        assert_eq!(map.load_shapefile(&counties).unwrap(), 2);
+        assert_eq!(map.load_shapefile(&zones).unwrap(), 1);
+        assert_eq!(map.len(), 3);
+        assert!(map.contains("PAC017", 40.5, -75.5));
+        assert!(!map.contains("PAC017", 40.5, -74.5));
+        assert!(map.contains("NJC005", 40.5, -74.5));
+        assert!(map.contains("PAZ106", 40.5, -75.5));
+        assert!(!map.contains("PAZ070", 40.5, -75.5));
+        assert_eq!(map.polygon_for("NJC005").unwrap().rings[0].len(), 4);
+
+        // a hole
+        let donut = Polygon {
+            rings: vec![
+                square(0.0, 0.0).iter().map(|(x, y)| (x * 3.0, y * 3.0)).collect(),
+                square(1.0, 1.0),
+            ],
+        };
+        assert!(donut.contains(0.5, 0.5));
+        assert!(!donut.contains(1.5, 1.5));
+        assert!(map.load_shapefile(dir.path().join("missing.shp")).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+//! Events emitted by handlers, for downstream automation
+//!
+//! Handlers that are given an [`EventSender`] will emit an event as soon as a product is finished,
+//! so that things like loop builders or uploaders don't need to poll the output directory.  The
+//! events all go through an [`EventBus`], which hands them to every subscribed [`EventSink`] (like
+//! the events socket, or the TUI's message log).
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::AdminNotice, emwin::swpc::SpaceWeatherMessage, handlers::RateState, lrit::LRIT, quota::ProductClass,
+    stats::StatsSnapshot, xmp::ImageMetadata,
+};
+
+/// Everything that can be published on the [`EventBus`]
+///
+/// As JSON, the kind of event is in the `event` field, like `{"event": "ImageCompleted", ...}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    LritCompleted(LritCompletedEvent),
+    ProductTruncated(ProductTruncatedEvent),
+    ImageCompleted(ImageCompleteEvent),
+    TextWritten(TextWrittenEvent),
+    DcsBlockDecoded(DcsBlockEvent),
+    SourceDisconnected(SourceDisconnectedEvent),
+    HandlerCrashed(HandlerCrashedEvent),
+    SpaceWeather(SpaceWeatherMessage),
+    AdminNotice(AdminNotice),
+    ImageChanged(ImageChangedEvent),
+    StormWatch(StormWatchEvent),
+    RateAnomaly(RateAnomalyEvent),
+    Shutdown(ShutdownEvent),
+}
+
+/// How handlers publish events
+pub type EventSender = Sender<Event>;
+
+/// Something that wants to see every event, like the events socket or a log
+pub trait EventSink {
+    fn publish(&mut self, event: &Event);
+}
+
+impl<F: FnMut(&Event)> EventSink for F {
+    fn publish(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Fans out events from handlers (and the rest of the app) to every subscribed sink
+///
+/// Handlers can run anywhere, so they're given an [`EventSender`].  The events they send are
+/// delivered whenever [`dispatch`](EventBus::dispatch) is called, on the thread that owns the
+/// bus, so sinks don't need to be thread safe.
+pub struct EventBus {
+    sender: EventSender,
+    receiver: Receiver<Event>,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        let (sender, receiver) = channel();
+        EventBus {
+            sender,
+            receiver,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// A sender for handlers to publish events with
+    pub fn sender(&self) -> EventSender {
+        self.sender.clone()
+    }
+
+    pub fn subscribe(&mut self, sink: impl EventSink + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    /// Delivers an event to every sink right away
+    pub fn publish(&mut self, event: Event) {
+        for sink in self.sinks.iter_mut() {
+            sink.publish(&event);
+        }
+    }
+
+    /// Delivers everything that's been sent by handlers, and returns how many events there were
+    pub fn dispatch(&mut self) -> usize {
+        let events: Vec<Event> = self.receiver.try_iter().collect();
+        let count = events.len();
+        for event in events {
+            self.publish(event);
+        }
+        count
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
+
+/// A complete LRIT file has been received, and is about to be handled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LritCompletedEvent {
+    pub vcid: u8,
+    pub filetype_code: u8,
+    /// Product name (from the annotation header)
+    pub product: Option<String>,
+    /// Size of the data, not counting the headers
+    pub bytes: usize,
+    pub time: DateTime<Utc>,
+}
+
+impl LritCompletedEvent {
+    pub fn new(lrit: &LRIT) -> LritCompletedEvent {
+        LritCompletedEvent {
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            product: lrit.headers.annotation.as_ref().map(|a| a.text.clone()),
+            bytes: lrit.data.len(),
+            time: Utc::now(),
+        }
+    }
+}
+
+/// An LRIT file arrived with less data than its primary header said it would have (see
+/// [`LRIT::truncated`])
+///
+/// It's still handled, so the product it becomes may be missing something.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductTruncatedEvent {
+    pub vcid: u8,
+    pub filetype_code: u8,
+    /// Product name (from the annotation header)
+    pub product: Option<String>,
+    pub expected_bytes: usize,
+    pub received_bytes: usize,
+    pub time: DateTime<Utc>,
+}
+
+impl ProductTruncatedEvent {
+    /// The event for an LRIT file, if it's truncated
+    pub fn new(lrit: &LRIT) -> Option<ProductTruncatedEvent> {
+        let truncated = lrit.truncated?;
+        Some(ProductTruncatedEvent {
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            product: lrit.headers.annotation.as_ref().map(|a| a.text.clone()),
+            expected_bytes: truncated.expected_bytes,
+            received_bytes: truncated.received_bytes,
+            time: Utc::now(),
+        })
+    }
+}
+
+/// A text product has been written (or queued to be written)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextWrittenEvent {
+    /// The name of the product, or of the file in a ZIP compressed product
+    pub product: String,
+    pub path: PathBuf,
+}
+
+/// A message from a DCS platform has been decoded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DcsBlockEvent {
+    /// The platform address, in hex
+    pub address: String,
+    pub channel: u16,
+    /// The uplink site's two letter code, like "UP"
+    #[serde(default)]
+    pub source: String,
+    /// Like "GOES-East"
+    #[serde(default)]
+    pub spacecraft: String,
+    pub carrier_start: DateTime<Utc>,
+    pub carrier_end: DateTime<Utc>,
+    /// dBm EIRP
+    pub signal_strength: f32,
+    /// Size of the message
+    pub bytes: usize,
+}
+
+/// A network source stopped sending data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceDisconnectedEvent {
+    pub address: String,
+    pub error: String,
+    pub time: DateTime<Utc>,
+}
+
+/// A handler panicked, or went over its time or memory budget
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandlerCrashedEvent {
+    pub handler: String,
+    pub error: String,
+    /// The annotation of the LRIT file the handler crashed on
+    pub product: Option<String>,
+    pub time: DateTime<Utc>,
+}
+
+/// A multi-segment (or multi-tile) image has been completed and written to disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageCompleteEvent {
+    /// Product name (from the annotation header)
+    pub product: String,
+    /// Where the full resolution image was written
+    pub path: PathBuf,
+    pub satellite: Option<String>,
+    pub band: Option<String>,
+    pub region: Option<String>,
+    pub scan_start: Option<DateTime<Utc>>,
+    /// When the image was completed (local clock)
+    pub completed: DateTime<Utc>,
+    pub segments_received: u16,
+    pub segments_expected: u16,
+    /// Seconds since the previous image of the same band and region was completed
+    ///
+    /// This makes it easy to spot changes in the scan schedule (like a switch between mode 3 and
+    /// mode 6), or missing images.
+    pub interval_seconds: Option<i64>,
+}
+
+impl ImageCompleteEvent {
+    /// Percent of segments that were received
+    pub fn completeness(&self) -> f32 {
+        if self.segments_expected == 0 {
+            return 100.0;
+        }
+        100.0 * self.segments_received as f32 / self.segments_expected as f32
+    }
+}
+
+/// Sends [`ImageCompleteEvent`]s, and keeps track of the per-band schedule
+pub struct ImageEvents {
+    sender: EventSender,
+    /// The last time an image was completed, keyed by (band, region)
+    last_seen: HashMap<(Option<String>, Option<String>), DateTime<Utc>>,
+}
+
+impl ImageEvents {
+    pub fn new(sender: EventSender) -> ImageEvents {
+        ImageEvents {
+            sender,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Emit an event for a newly written image
+    ///
+    /// A closed channel is not an error; the event is simply dropped.
+    pub fn image_complete(&mut self, meta: &ImageMetadata, path: PathBuf, received: u16, expected: u16) {
+        let completed = Utc::now();
+        let key = (meta.band.clone(), meta.region.clone());
+        let interval_seconds = self
+            .last_seen
+            .insert(key, completed)
+            .map(|prev| (completed - prev).num_seconds());
+
+        let _ = self.sender.send(Event::ImageCompleted(ImageCompleteEvent {
+            product: meta.product.clone(),
+            path,
+            satellite: meta.satellite.clone(),
+            band: meta.band.clone(),
+            region: meta.region.clone(),
+            scan_start: meta.scan_start,
+            completed,
+            segments_received: received,
+            segments_expected: expected,
+            interval_seconds,
+        }));
+    }
+
+    /// Emit an event for an image that's changed a lot since the one before
+    pub fn image_changed(&self, event: ImageChangedEvent) {
+        let _ = self.sender.send(Event::ImageChanged(event));
+    }
+
+    /// Emit an event for cold cloud tops showing up in (or leaving) a watched area
+    pub fn storm_watch(&self, event: StormWatchEvent) {
+        let _ = self.sender.send(Event::StormWatch(event));
+    }
+}
+
+/// Much of an image is different from the previous image of the same band and region, see
+/// [`ChangeDetector`](crate::handlers::ChangeDetector)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageChangedEvent {
+    /// Product name (from the annotation header)
+    pub product: String,
+    /// Where the new image was written
+    pub path: PathBuf,
+    /// Where the difference image was written, if it was
+    pub diff_path: Option<PathBuf>,
+    pub band: Option<String>,
+    pub region: Option<String>,
+    pub scan_start: Option<DateTime<Utc>>,
+    pub previous_scan_start: Option<DateTime<Utc>>,
+    /// Percent of the pixels that changed by more than the pixel threshold
+    pub changed_percent: f32,
+    /// The average difference of every pixel, from 0 to 255
+    pub mean_difference: f32,
+}
+
+/// Cold cloud tops showed up in an area that's being watched, or went away, see
+/// [`StormWatch`](crate::handlers::StormWatch)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StormWatchEvent {
+    /// The name of the watched area
+    pub name: String,
+    /// Product name (from the annotation header)
+    pub product: String,
+    /// Where the image was written
+    pub path: PathBuf,
+    pub band: Option<String>,
+    pub region: Option<String>,
+    pub scan_start: Option<DateTime<Utc>>,
+    /// Whether the area has cold cloud tops now (false once they've gone)
+    pub active: bool,
+    /// The brightness temperature cold pixels are below, in kelvin
+    pub threshold: f64,
+    /// How many pixels in the area are colder than the threshold
+    pub cold_pixels: usize,
+    /// How many pixels of the image are in the area
+    pub area_pixels: usize,
+    /// The coldest brightness temperature in the area, in kelvin
+    pub min_temperature: Option<f64>,
+    /// Where that is, as latitude and longitude
+    pub coldest: Option<(f64, f64)>,
+}
+
+/// A class of products stopped arriving or spiked, or got back to normal after that, from the
+/// [`RateMonitor`](crate::handlers::RateMonitor)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateAnomalyEvent {
+    pub class: ProductClass,
+    pub state: RateState,
+    /// How many products of the class arrived in the interval
+    pub count: u64,
+    /// How many usually do
+    pub expected: f64,
+    pub interval_start: DateTime<Utc>,
+    pub interval_seconds: i64,
+}
+
+/// The receiver is shutting down
+///
+/// This is sent after all handlers have been flushed, so it's the last event a subscriber will see.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownEvent {
+    pub time: DateTime<Utc>,
+    /// Why the receiver is shutting down, like "quit" or "SIGTERM"
+    pub reason: String,
+    /// The decoding statistics for the whole run (boxed, since they're much bigger than other events)
+    pub stats: Box<StatsSnapshot>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use chrono::Utc;
+
+    use super::{Event, EventBus, SourceDisconnectedEvent};
+
+    #[test]
+    fn test_event_bus() {
+        let mut bus = EventBus::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let sink = seen.clone();
+        bus.subscribe(move |event: &Event| sink.borrow_mut().push(serde_json::to_value(event).unwrap()));
+
+        let sender = bus.sender();
+        std::thread::spawn(move || {
+            let _ = sender.send(Event::SourceDisconnected(SourceDisconnectedEvent {
+                address: "tcp://127.0.0.1:5004".to_string(),
+                error: "connection reset".to_string(),
+                time: Utc::now(),
+            }));
+        })
+        .join()
+        .unwrap();
+        // nothing is delivered until the bus is dispatched
+        assert!(seen.borrow().is_empty());
+        assert_eq!(bus.dispatch(), 1);
+        assert_eq!(bus.dispatch(), 0);
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 1);
+        assert_eq!(seen[0]["event"], "SourceDisconnected");
+        assert_eq!(seen[0]["error"], "connection reset");
+    }
+}
@@ -0,0 +1,308 @@
+//! Store-and-forward uploads of new products
+//!
+//! Installations that send their products somewhere else often have a link that comes and goes.
+//! A [`ForwardSpool`] is a directory (`forward/<name>` under the output root) with a small JSON
+//! entry for each product that's waiting to be sent.  A [`Forwarder`] sends them to a
+//! [`SyncTarget`] from its own thread, oldest first, and only deletes an entry once its product has
+//! been sent.  Since the spool is on disk, nothing is lost when the link is down for a while, or when
+//! the receiver restarts in the meantime.  Failed sends are retried, waiting longer after each
+//! failure in a row.
+//!
+//! Products are added to the spool as they're written, by subscribing it to the
+//! [`EventBus`](crate::events::EventBus).
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::{Event, EventSink},
+    handlers::glob_match,
+    mirror::SyncTarget,
+};
+
+/// How often the forwarding thread looks for new entries
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Entries whose product is still missing after this long are dropped, since it was probably
+/// removed by retention (or never written)
+const MISSING_GRACE: chrono::Duration = chrono::Duration::hours(1);
+
+/// A product waiting to be forwarded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForwardEntry {
+    /// The product's path, relative to the output root (with `/` separators)
+    pub path: String,
+    pub queued: DateTime<Utc>,
+}
+
+/// Products waiting to be forwarded, see the [module docs](self)
+///
+/// This is cheap to clone: clones share the same directory.
+#[derive(Clone)]
+pub struct ForwardSpool {
+    name: String,
+    root: PathBuf,
+    dir: PathBuf,
+    /// Only products whose path matches one of these (everything, if empty)
+    patterns: Vec<String>,
+    /// Included in entry names, so that several entries in the same millisecond don't collide
+    counter: Arc<AtomicU32>,
+}
+
+impl ForwardSpool {
+    /// The spool for `name` (which should be different for each target)
+    pub fn new(root: impl AsRef<Path>, name: &str) -> ForwardSpool {
+        let root = root.as_ref().to_path_buf();
+        ForwardSpool {
+            name: name.to_string(),
+            dir: root.join("forward").join(name),
+            root,
+            patterns: Vec::new(),
+            counter: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Only forward products whose path (relative to the output root, like `emwin/2022-05-07/*TOR*`)
+    /// matches one of these
+    pub fn with_patterns(mut self, patterns: &[String]) -> Self {
+        self.patterns = patterns.to_vec();
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Adds a product (anywhere under the output root) to the spool
+    ///
+    /// Returns false if it isn't under the output root, or doesn't match the patterns.
+    pub fn push(&self, path: &Path) -> io::Result<bool> {
+        let rel = match path.strip_prefix(&self.root) {
+            Ok(rel) => rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => return Ok(false),
+        };
+        if !self.patterns.is_empty() && !self.patterns.iter().any(|p| glob_match(p.as_bytes(), rel.as_bytes())) {
+            return Ok(false);
+        }
+
+        let entry = ForwardEntry {
+            path: rel,
+            queued: Utc::now(),
+        };
+        // entry names start with the time, so that sorting them by name sorts them by age
+        let name = format!(
+            "{}-{:04}.json",
+            entry.queued.format("%Y%m%dT%H%M%S%.3f"),
+            self.counter.fetch_add(1, Ordering::Relaxed) % 10000
+        );
+        std::fs::create_dir_all(&self.dir)?;
+        // write to a temporary name first, so the forwarder never sees half an entry
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        std::fs::write(&tmp, serde_json::to_vec(&entry)?)?;
+        std::fs::rename(tmp, self.dir.join(name))?;
+        Ok(true)
+    }
+
+    /// Every entry that's waiting, oldest first, with the path of its entry file
+    pub fn pending(&self) -> io::Result<Vec<(PathBuf, ForwardEntry)>> {
+        let mut files = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir.map(|e| e.map(|e| e.path())).collect::<Result<Vec<_>, _>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        files.retain(|p| p.extension().is_some_and(|ext| ext == "json"));
+        files.sort();
+
+        let mut entries = Vec::new();
+        for file in files {
+            match std::fs::read(&file).map(|data| serde_json::from_slice(&data)) {
+                Ok(Ok(entry)) => entries.push((file, entry)),
+                Ok(Err(e)) => warn!("Ignoring bad forward entry {}: {}", file.display(), e),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl EventSink for ForwardSpool {
+    fn publish(&mut self, event: &Event) {
+        let path = match event {
+            Event::TextWritten(text) => &text.path,
+            Event::ImageCompleted(image) => &image.path,
+            _ => return,
+        };
+        if let Err(e) = self.push(path) {
+            warn!("Failed to queue {} for {}: {}", path.display(), self.name, e);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardSummary {
+    pub sent: usize,
+    /// Entries whose product never showed up
+    pub dropped: usize,
+}
+
+/// Sends the products in a [`ForwardSpool`] to a [`SyncTarget`]
+pub struct Forwarder {
+    spool: ForwardSpool,
+    target: Box<dyn SyncTarget + Send>,
+    delay: chrono::Duration,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+}
+
+impl Forwarder {
+    pub fn new(spool: ForwardSpool, target: Box<dyn SyncTarget + Send>) -> Forwarder {
+        Forwarder {
+            spool,
+            target,
+            delay: chrono::Duration::seconds(5),
+            retry_delay: Duration::from_secs(5),
+            max_retry_delay: Duration::from_secs(600),
+        }
+    }
+
+    /// Wait this long before sending a product (5 seconds by default), so that writes that were
+    /// still queued when it was added to the spool have landed
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+        self
+    }
+
+    /// How long to wait after the first failure in a row (5 seconds by default), and the most to
+    /// wait after later ones (10 minutes by default)
+    pub fn with_retry_delay(mut self, first: Duration, max: Duration) -> Self {
+        self.retry_delay = first;
+        self.max_retry_delay = max.max(first);
+        self
+    }
+
+    /// Sends every entry that's old enough, oldest first
+    ///
+    /// Stops at the first product that can't be sent, which stays in the spool.
+    pub fn run_once(&mut self) -> io::Result<ForwardSummary> {
+        let mut summary = ForwardSummary::default();
+        let now = Utc::now();
+        for (file, entry) in self.spool.pending()? {
+            if now - entry.queued < self.delay {
+                break;
+            }
+            if !self.spool.root.join(&entry.path).exists() {
+                if now - entry.queued >= MISSING_GRACE {
+                    warn!("Not forwarding {}, it was never written", entry.path);
+                    std::fs::remove_file(&file)?;
+                    summary.dropped += 1;
+                }
+                continue;
+            }
+            self.target.put_file(&self.spool.root, &entry.path)?;
+            std::fs::remove_file(&file)?;
+            summary.sent += 1;
+        }
+        Ok(summary)
+    }
+
+    /// Starts forwarding on its own thread, which runs until the program exits
+    pub fn spawn(mut self) -> io::Result<JoinHandle<()>> {
+        std::thread::Builder::new()
+            .name(format!("forward-{}", self.spool.name))
+            .spawn(move || {
+                let mut retry_delay = self.retry_delay;
+                loop {
+                    match self.run_once() {
+                        Ok(summary) => {
+                            if summary.sent > 0 {
+                                info!("Forwarded {} products to {}", summary.sent, self.spool.name);
+                            }
+                            retry_delay = self.retry_delay;
+                            std::thread::sleep(POLL_INTERVAL);
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Forwarding to {} failed, retrying in {}s: {}",
+                                self.spool.name,
+                                retry_delay.as_secs(),
+                                e
+                            );
+                            std::thread::sleep(retry_delay);
+                            retry_delay = (retry_delay * 2).min(self.max_retry_delay);
+                        }
+                    }
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use super::{ForwardSpool, ForwardSummary, Forwarder};
+    use crate::mirror::{DirectoryTarget, SyncTarget};
+
+    /// A target whose link is down
+    struct Down;
+
+    impl SyncTarget for Down {
+        fn put(&mut self, _path: &str, _data: &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "link down"))
+        }
+    }
+
+    #[test]
+    fn test_forward() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("emwin")).unwrap();
+        std::fs::write(root.path().join("emwin/tor.txt"), "tor").unwrap();
+        std::fs::write(root.path().join("emwin/afd.txt"), "afd").unwrap();
+
+        let spool = ForwardSpool::new(root.path(), "remote").with_patterns(&["emwin/*".to_string()]);
+        assert!(spool.push(&root.path().join("emwin/tor.txt")).unwrap());
+        // not written yet, like a product that's still in the write queue
+        assert!(spool.push(&root.path().join("emwin/late.txt")).unwrap());
+        assert!(spool.push(&root.path().join("emwin/afd.txt")).unwrap());
+        assert!(!spool.push(&root.path().join("images/a.png")).unwrap());
+        assert!(!spool.push(std::path::Path::new("/elsewhere/emwin/a.txt")).unwrap());
+
+        let mut forwarder = Forwarder::new(spool.clone(), Box::new(Down)).with_delay(Duration::ZERO);
+        assert!(forwarder.run_once().is_err());
+        assert_eq!(spool.pending().unwrap().len(), 3);
+
+        // after a restart, with the link back up
+        let spool = ForwardSpool::new(root.path(), "remote");
+        let mut forwarder =
+            Forwarder::new(spool.clone(), Box::new(DirectoryTarget::new(remote.path()))).with_delay(Duration::ZERO);
+        assert_eq!(forwarder.run_once().unwrap(), ForwardSummary { sent: 2, dropped: 0 });
+        assert_eq!(
+            std::fs::read_to_string(remote.path().join("emwin/afd.txt")).unwrap(),
+            "afd"
+        );
+        let pending = spool.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].1.path, "emwin/late.txt");
+
+        // once it's written, it's sent too
+        std::fs::write(root.path().join("emwin/late.txt"), "late").unwrap();
+        assert_eq!(forwarder.run_once().unwrap().sent, 1);
+        assert!(spool.pending().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,147 @@
+use chrono::Utc;
+use log::info;
+
+use crate::{
+    admin::{AdminNotice, OutageSchedule},
+    events::{Event, EventSender},
+    index::{IndexRecord, ProductIndex},
+    lrit::{Vcid, LRIT},
+};
+
+use super::{text_files, Handler, HandlerError};
+
+/// Parses the text messages on the admin virtual channel into [`AdminNotice`]s
+///
+/// Admin messages are sent over and over, so only new (or changed) notices are used.  Each one is
+/// sent as an [`Event::AdminNotice`], and recorded in the product index along with the message it
+/// came from.  Notices for outages that are already over are ignored.
+pub struct AdminHandler {
+    events: Option<EventSender>,
+    index: Option<ProductIndex>,
+    schedule: OutageSchedule,
+}
+
+impl Default for AdminHandler {
+    fn default() -> Self {
+        AdminHandler::new()
+    }
+}
+
+impl AdminHandler {
+    pub fn new() -> AdminHandler {
+        AdminHandler {
+            events: None,
+            index: None,
+            schedule: OutageSchedule::new(),
+        }
+    }
+
+    /// Send an [`Event::AdminNotice`] for every new notice
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Record new notices in the product index under `root`
+    pub fn with_index(mut self, root: impl AsRef<std::path::Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+
+    fn publish(&self, lrit: &LRIT, notice: AdminNotice) -> Result<(), HandlerError> {
+        info!("Admin notice: {}", notice.summary());
+        if let Some(index) = &self.index {
+            if let Some(mut record) = IndexRecord::from_lrit(lrit, Utc::now()) {
+                record.admin = Some(notice.clone());
+                index.append(&record)?;
+            }
+        }
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::AdminNotice(notice));
+        }
+        Ok(())
+    }
+}
+
+impl Handler for AdminHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if Vcid::from(lrit.vcid) != Vcid::Admin || lrit.headers.primary.filetype_code != 2 {
+            return Err(HandlerError::Skipped);
+        }
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+
+        let now = Utc::now();
+        self.schedule.prune(now);
+        for (_, data) in text_files(lrit, annotation)? {
+            let notice = match AdminNotice::parse(&String::from_utf8_lossy(&data)) {
+                Some(notice) => notice,
+                None => continue,
+            };
+            if notice.expires().is_some_and(|end| end <= now) || !self.schedule.add(notice.clone()) {
+                continue;
+            }
+            self.publish(lrit, notice)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::AdminHandler;
+    use crate::{
+        events::Event,
+        handlers::{Handler, HandlerError},
+        index::ProductIndex,
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    fn test_admin_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = AdminHandler::new().with_events(s).with_index(dir.path());
+
+        let start = Utc::now() + Duration::days(2);
+        let text = format!(
+            "Topic: GOES-East HRIT/EMWIN Outage\nDate/Time of Initial Impact: {}\nDate/Time of Expected End: {}\n",
+            start.format("%B %-d, %Y %H%M UTC"),
+            (start + Duration::hours(2)).format("%Y-%m-%d %H:%MZ"),
+        );
+        let lrit = |vcid, text: &str| {
+            LRIT::from_bytes(
+                vcid,
+                &LritBuilder::new(2).annotation("admin.txt").build(text.as_bytes()),
+            )
+            .unwrap()
+        };
+        // sent twice, but only announced once
+        handler.handle(&lrit(0, &text)).unwrap();
+        handler.handle(&lrit(0, &text)).unwrap();
+        // an outage that's over
+        let over = "Topic: Old outage\nDate/Time of Initial Impact: May 10, 2022 1400 UTC\n\
+                    Date/Time of Expected End: May 10, 2022 1600 UTC\n";
+        handler.handle(&lrit(0, over)).unwrap();
+        assert!(matches!(handler.handle(&lrit(20, &text)), Err(HandlerError::Skipped)));
+
+        let notices: Vec<_> = r
+            .try_iter()
+            .map(|event| match event {
+                Event::AdminNotice(notice) => notice,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect();
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].end, notices[0].start.map(|s| s + Duration::hours(2)));
+
+        let records = ProductIndex::new(dir.path()).read_day(Utc::now().date_naive()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].admin.as_ref(), Some(&notices[0]));
+    }
+}
@@ -0,0 +1,136 @@
+//! Assembling segmented images in memory-mapped files, see
+//! [`ImageHandler::with_streaming_assembly`](super::ImageHandler::with_streaming_assembly)
+//!
+//! A 16 segment full disk image is about 30 MB.  Buffering every segment until the last one
+//! arrives, then copying them all into the image, needs twice that at its peak, for every image
+//! being assembled.  Instead, each segment is copied into a file that's sized for the whole image
+//! as soon as it arrives, and the image is encoded straight from that file.  The kernel can write
+//! back and drop pages of the file whenever memory is short.
+use std::{
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use memmap2::MmapMut;
+
+use crate::lrit::LRIT;
+
+use super::{
+    image::{place_segment, MAX_IMAGE_PIXELS},
+    HandlerError,
+};
+
+/// A segmented image that's being assembled in a memory-mapped file
+///
+/// The file is deleted when this is dropped.
+pub(super) struct StreamingImage {
+    /// The first segment that arrived, without its data, for its headers
+    pub first: LRIT,
+    /// Which segments have been copied in
+    pub received: Vec<bool>,
+    width: usize,
+    rows: usize,
+    path: PathBuf,
+    map: MmapMut,
+}
+
+impl StreamingImage {
+    /// Creates the file (in `dir`) for the image that `lrit` is a segment of
+    pub fn create(dir: &Path, lrit: &LRIT) -> Result<StreamingImage, HandlerError> {
+        let ihs = lrit
+            .headers
+            .img_strucutre
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        let seg = lrit
+            .headers
+            .img_segment
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+        if ihs.bits_per_pixel != 8 {
+            warn!("Found non grayscale image: {:?}", ihs);
+            return Err(HandlerError::Parse("unsupported bits per pixel"));
+        }
+
+        // the file is sized before any data arrives, so a bogus max_row can't be checked against
+        // the data like it is for buffered images; don't go past what the segments can hold
+        let plausible_rows = ihs.num_lines as usize * seg.max_segment as usize;
+        let rows = match seg.max_row as usize {
+            0 => plausible_rows,
+            rows => rows.min(plausible_rows),
+        };
+        let width = seg.max_column as usize;
+        if width == 0 || rows == 0 {
+            return Err(HandlerError::Parse("image segment has zero max_column or max_row"));
+        }
+        let rows = rows.min(MAX_IMAGE_PIXELS / width);
+
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.raw", seg.image_id));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len((width * rows) as u64)?;
+        // Safety: nothing else writes to (or truncates) this file while it's mapped
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        let mut first = lrit.clone();
+        first.data = Vec::new();
+        first.compressed_data = None;
+        Ok(StreamingImage {
+            first,
+            received: vec![false; seg.max_segment as usize],
+            width,
+            rows,
+            path,
+            map,
+        })
+    }
+
+    /// Copies a segment into its place in the image, returning false if it was already there
+    pub fn add(&mut self, lrit: &LRIT) -> Result<bool, HandlerError> {
+        let seg = lrit
+            .headers
+            .img_segment
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+        let seq = seg.segment_seq as usize;
+        match self.received.get(seq) {
+            Some(true) => return Ok(false),
+            Some(false) => {}
+            None => {
+                warn!("segment {} of {} is out of range", seq, self.received.len());
+                return Err(HandlerError::Parse("image segment sequence out of range"));
+            }
+        }
+
+        let clipped = place_segment(&mut self.map, self.width, seg.start_line, &lrit.data)?;
+        if clipped > 0 {
+            warn!("segment {} doesn't fit in the image, dropping {} bytes", seq, clipped);
+        }
+        self.received[seq] = true;
+        Ok(true)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received.iter().all(|r| *r)
+    }
+
+    /// The image, with any missing segments left black
+    pub fn image(&self) -> image::ImageBuffer<image::Luma<u8>, &[u8]> {
+        image::ImageBuffer::from_raw(self.width as u32, self.rows as u32, &self.map[..])
+            .expect("the file is sized for the whole image")
+    }
+}
+
+impl Drop for StreamingImage {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! A board of the latest EMWIN products, for display on a kiosk
+//!
+//! The board lists a configured set of products (like the latest area forecast discussion for a
+//! forecast office, or the METARs for a station), and is rewritten every time a new copy of one of
+//! them arrives.  The board starts out empty each time goesbox starts, and fills in as products
+//! are received.
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::{annotation::LritFilename, lrit::LRIT};
+
+use super::{text_files, Handler, HandlerError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardFormat {
+    Html,
+    Markdown,
+}
+
+/// One product on the board
+#[derive(Debug, Clone)]
+struct BoardEntry {
+    title: String,
+    /// Matched against the short EMWIN product name, like `AFDPHIPA`
+    pattern: String,
+    /// When the latest copy was issued, and its text
+    latest: Option<(DateTime<Utc>, String)>,
+}
+
+/// Keeps a board of the latest copy of each configured EMWIN product
+pub struct BoardHandler {
+    path: PathBuf,
+    format: BoardFormat,
+    entries: Vec<BoardEntry>,
+}
+
+impl BoardHandler {
+    /// Writes the board to `path`, as Markdown if it ends in `.md` and as HTML otherwise
+    pub fn new(path: impl AsRef<Path>) -> BoardHandler {
+        let path = path.as_ref().to_path_buf();
+        let format = match path.extension() {
+            Some(ext) if ext == "md" || ext == "markdown" => BoardFormat::Markdown,
+            _ => BoardFormat::Html,
+        };
+        BoardHandler {
+            path,
+            format,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: BoardFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Adds a product to the board
+    ///
+    /// `pattern` is matched against the short EMWIN product name (like `AFDPHIPA` for the
+    /// Philadelphia area forecast discussion, or `MTRBOSMA` for the Boston METARs), and can use `*`
+    /// and `?` wildcards.  Products are listed in the order they're added.
+    pub fn product(mut self, title: impl Into<String>, pattern: impl Into<String>) -> Self {
+        self.entries.push(BoardEntry {
+            title: title.into(),
+            pattern: pattern.into(),
+            latest: None,
+        });
+        self
+    }
+
+    /// Writes the board, replacing any previous copy all at once
+    pub fn write(&self) -> Result<(), HandlerError> {
+        let board = match self.format {
+            BoardFormat::Html => self.render_html(),
+            BoardFormat::Markdown => self.render_markdown(),
+        };
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, board)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn render_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"60\">\n<title>Latest products</title>\n</head>\n<body>\n",
+        );
+        for entry in &self.entries {
+            html += &format!("<section>\n<h2>{}</h2>\n", escape_html(&entry.title));
+            match &entry.latest {
+                Some((issued, text)) => {
+                    html += &format!(
+                        "<p>Issued {}</p>\n<pre>{}</pre>\n",
+                        issued.format("%Y-%m-%d %H:%M UTC"),
+                        escape_html(text)
+                    )
+                }
+                None => html += "<p>Not received yet</p>\n",
+            }
+            html += "</section>\n";
+        }
+        html += "</body>\n</html>\n";
+        html
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut md = String::from("# Latest products\n");
+        for entry in &self.entries {
+            md += &format!("\n## {}\n\n", entry.title);
+            match &entry.latest {
+                Some((issued, text)) => {
+                    md += &format!(
+                        "_Issued {}_\n\n```\n{}\n```\n",
+                        issued.format("%Y-%m-%d %H:%M UTC"),
+                        text
+                    )
+                }
+                None => md += "_Not received yet_\n",
+            }
+        }
+        md
+    }
+}
+
+impl Handler for BoardHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 2 {
+            return Err(HandlerError::Skipped);
+        }
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+        if !matches!(LritFilename::parse(annotation), LritFilename::Emwin(_)) {
+            return Err(HandlerError::Skipped);
+        }
+
+        let mut changed = false;
+        for (filename, data) in text_files(lrit, annotation)? {
+            let emwin = match LritFilename::parse(&filename) {
+                LritFilename::Emwin(emwin) => emwin,
+                _ => continue,
+            };
+            let name = emwin.legacy_filename.split('.').next().unwrap_or_default();
+            for entry in self.entries.iter_mut() {
+                if !glob_match(entry.pattern.as_bytes(), name.as_bytes()) {
+                    continue;
+                }
+                // products can arrive out of order, so don't replace a newer copy with an older one
+                if matches!(&entry.latest, Some((issued, _)) if *issued > emwin.date) {
+                    continue;
+                }
+                let text = String::from_utf8_lossy(&data).replace('\r', "");
+                entry.latest = Some((emwin.date, text.trim_end().to_string()));
+                changed = true;
+            }
+        }
+        if !changed {
+            return Err(HandlerError::Skipped);
+        }
+        self.write()
+    }
+}
+
+/// Matches a name against a pattern, where `*` matches any number of characters and `?` matches
+/// exactly one
+pub(crate) fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => glob_match(rest, name) || (!name.is_empty() && glob_match(pattern, &name[1..])),
+        (Some((b'?', rest)), Some((_, name))) => glob_match(rest, name),
+        (Some((p, rest)), Some((n, name))) => p.eq_ignore_ascii_case(n) && glob_match(rest, name),
+        _ => false,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, BoardHandler};
+    use crate::{
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    fn text(name: &str, data: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"AFDPHI*", b"AFDPHIPA"));
+        assert!(glob_match(b"afd???pa", b"AFDPHIPA"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"AFDPHI", b"AFDPHIPA"));
+        assert!(!glob_match(b"AFD?", b"AFD"));
+    }
+
+    #[test]
+    fn test_board() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("board.md");
+        let mut board = BoardHandler::new(&path)
+            .product("Area Forecast Discussion", "AFDPHI*")
+            .product("Boston METARs", "MTRBOS*");
+
+        let afd = "A_FXUS61KPHI071250_C_KWIN_20220507125113_106868-3-AFDPHIPA.TXT";
+        board.handle(&text(afd, "first\r\r\n")).unwrap();
+        let md = std::fs::read_to_string(&path).unwrap();
+        assert!(md.contains("## Area Forecast Discussion\n\n_Issued 2022-05-07 12:51 UTC_\n\n```\nfirst\n```"));
+        assert!(md.contains("## Boston METARs\n\n_Not received yet_"));
+
+        // an older copy doesn't replace a newer one
+        let older = "A_FXUS61KPHI071150_C_KWIN_20220507115113_106800-3-AFDPHIPA.TXT";
+        assert!(matches!(
+            board.handle(&text(older, "older")),
+            Err(HandlerError::Skipped)
+        ));
+        let other = "A_FPUS20KWBN071250_C_KWIN_20220507125113_106869-3-SCSWBNUS.TXT";
+        assert!(matches!(
+            board.handle(&text(other, "other")),
+            Err(HandlerError::Skipped)
+        ));
+        assert!(std::fs::read_to_string(&path).unwrap().contains("first"));
+
+        let html_path = dir.path().join("board.html");
+        let mut board = BoardHandler::new(&html_path).product("<AFD>", "AFDPHI*");
+        board.handle(&text(afd, "a < b")).unwrap();
+        let html = std::fs::read_to_string(&html_path).unwrap();
+        assert!(html.contains("<h2>&lt;AFD&gt;</h2>"));
+        assert!(html.contains("<pre>a &lt; b</pre>"));
+    }
+}
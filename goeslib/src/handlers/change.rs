@@ -0,0 +1,200 @@
+use std::{collections::HashMap, ops::Deref, path::Path};
+
+use chrono::{DateTime, Utc};
+use log::{debug, info};
+
+use crate::{events::ImageChangedEvent, storage::Storage, xmp::ImageMetadata};
+
+use super::{
+    image::{box_downsample, save_jpeg},
+    HandlerError,
+};
+
+/// Compares each segmented image with the previous image of the same band and region
+///
+/// This is set up with [`ImageHandler::with_change_detection`](super::ImageHandler::with_change_detection).
+/// Images are compared at reduced resolution (no more than 1024 pixels on a side, by default),
+/// and a pixel has changed if it's brighter or darker by more than the pixel threshold.  When
+/// more than the threshold percent of the pixels have changed, an
+/// [`Event::ImageChanged`](crate::events::Event::ImageChanged) is sent.  This is handy for
+/// spotting new fires or convection.
+///
+/// Optionally, the absolute difference of the two images is written next to the new one, as
+/// `<name>.diff.jpg`.
+pub struct ChangeDetector {
+    /// The last image of each band and region, at reduced resolution
+    previous: HashMap<String, (Option<DateTime<Utc>>, image::GrayImage)>,
+    max_dimension: u32,
+    pixel_threshold: u8,
+    threshold_percent: f32,
+    difference_images: bool,
+}
+
+impl Default for ChangeDetector {
+    fn default() -> Self {
+        ChangeDetector::new()
+    }
+}
+
+impl ChangeDetector {
+    pub fn new() -> ChangeDetector {
+        ChangeDetector {
+            previous: HashMap::new(),
+            max_dimension: 1024,
+            pixel_threshold: 32,
+            threshold_percent: 5.0,
+            difference_images: false,
+        }
+    }
+
+    /// Compare images at no more than this many pixels on a side
+    pub fn with_max_dimension(mut self, pixels: u32) -> Self {
+        self.max_dimension = pixels.max(1);
+        self
+    }
+
+    /// A pixel has changed if its value differs by more than this (32 by default)
+    pub fn with_pixel_threshold(mut self, threshold: u8) -> Self {
+        self.pixel_threshold = threshold;
+        self
+    }
+
+    /// Send an event when more than this percent of the pixels have changed (5 by default)
+    pub fn with_threshold(mut self, percent: f32) -> Self {
+        self.threshold_percent = percent;
+        self
+    }
+
+    /// Write a difference image next to every image that's compared
+    pub fn with_difference_images(mut self) -> Self {
+        self.difference_images = true;
+        self
+    }
+
+    /// Compares a newly written image with the last one with the same `key` (which identifies
+    /// its band and region), and remembers it for next time
+    ///
+    /// Returns an event if enough of the image has changed.  Images are only compared with ones
+    /// from an earlier scan that are the same size; a retransmission of the same scan just
+    /// replaces the one it was merged into.
+    pub fn compare<C: Deref<Target = [u8]>>(
+        &mut self,
+        storage: &dyn Storage,
+        key: String,
+        meta: &ImageMetadata,
+        img: &image::ImageBuffer<image::Luma<u8>, C>,
+        out_name: &Path,
+    ) -> Result<Option<ImageChangedEvent>, HandlerError> {
+        let mut reduced: Option<image::GrayImage> = None;
+        loop {
+            let (width, height) = reduced.as_ref().map_or(img.dimensions(), |r| r.dimensions());
+            if width.max(height) <= self.max_dimension {
+                break;
+            }
+            reduced = Some(match &reduced {
+                Some(reduced) => box_downsample(reduced),
+                None => box_downsample(img),
+            });
+        }
+        let current = match reduced {
+            Some(reduced) => reduced,
+            None => image::GrayImage::from_raw(img.width(), img.height(), img.as_raw().to_vec())
+                .ok_or(HandlerError::Parse("image is smaller than its dimensions"))?,
+        };
+
+        let (previous_scan_start, previous) = match self.previous.insert(key.clone(), (meta.scan_start, current)) {
+            Some((scan_start, previous)) if scan_start.is_none() || scan_start != meta.scan_start => {
+                (scan_start, previous)
+            }
+            _ => return Ok(None),
+        };
+        let current = &self.previous[&key].1;
+        if previous.dimensions() != current.dimensions() {
+            debug!("{}: not comparing images of different sizes", key);
+            return Ok(None);
+        }
+
+        let mut changed = 0u64;
+        let mut total = 0u64;
+        let diff = image::GrayImage::from_fn(current.width(), current.height(), |x, y| {
+            let d = current.get_pixel(x, y).0[0].abs_diff(previous.get_pixel(x, y).0[0]);
+            if d > self.pixel_threshold {
+                changed += 1;
+            }
+            total += d as u64;
+            image::Luma([d])
+        });
+        let pixels = (current.width() as u64 * current.height() as u64).max(1);
+        let changed_percent = 100.0 * changed as f32 / pixels as f32;
+
+        let diff_path = if self.difference_images {
+            let path = out_name.with_extension("diff.jpg");
+            save_jpeg(storage, &diff, &path, meta)?;
+            Some(path)
+        } else {
+            None
+        };
+        if changed_percent <= self.threshold_percent {
+            return Ok(None);
+        }
+        info!("{}: {:.1}% of the image has changed", meta.product, changed_percent);
+        Ok(Some(ImageChangedEvent {
+            product: meta.product.clone(),
+            path: out_name.to_path_buf(),
+            diff_path,
+            band: meta.band.clone(),
+            region: meta.region.clone(),
+            scan_start: meta.scan_start,
+            previous_scan_start,
+            changed_percent,
+            mean_difference: total as f32 / pixels as f32,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::ChangeDetector;
+    use crate::{storage::LocalStorage, xmp::ImageMetadata};
+
+    #[test]
+    fn test_change_detector() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_name = dir.path().join("image.jpg");
+        let mut detector = ChangeDetector::new()
+            .with_max_dimension(50)
+            .with_threshold(10.0)
+            .with_difference_images();
+        let mut meta =
+            ImageMetadata::from_product_name("OR_ABI-L2-CMIPF-M6C07_G16_s20221241800205_e20221241809513.lrit");
+        let compare = |detector: &mut ChangeDetector, meta: &ImageMetadata, hot: u32| {
+            let img = image::GrayImage::from_fn(200, 100, |x, _| image::Luma([if x < hot { 250 } else { 100 }]));
+            detector
+                .compare(&LocalStorage, "C07".to_string(), meta, &img, &out_name)
+                .unwrap()
+        };
+
+        assert!(compare(&mut detector, &meta, 0).is_none());
+        // the same scan again (a retransmission) isn't compared
+        assert!(compare(&mut detector, &meta, 100).is_none());
+        assert!(!dir.path().join("image.diff.jpg").exists());
+
+        // a small change
+        let first_scan = meta.scan_start;
+        meta.scan_start = first_scan.map(|t| t + Duration::minutes(10));
+        assert!(compare(&mut detector, &meta, 108).is_none());
+        assert!(dir.path().join("image.diff.jpg").exists());
+
+        // a big one
+        let second_scan = meta.scan_start;
+        meta.scan_start = second_scan.map(|t| t + Duration::minutes(10));
+        let event = compare(&mut detector, &meta, 148).unwrap();
+        assert_eq!(event.band.as_deref(), Some("C07"));
+        assert_eq!(event.previous_scan_start, second_scan);
+        assert_eq!(event.changed_percent, 20.0);
+        assert_eq!(event.mean_difference, 30.0);
+        assert_eq!(event.diff_path, Some(dir.path().join("image.diff.jpg")));
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{lrit::LRIT, quota::ProductClass, storage::Storage};
+
+use super::versioned;
+
+/// Two different products that would have been written to the same file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collision {
+    /// Where both products would have been written
+    pub path: PathBuf,
+    /// Where this product was written instead
+    pub written: PathBuf,
+    /// The annotation of the product that's already at `path`
+    pub other: String,
+}
+
+/// A file written recently
+struct Written {
+    hash: u64,
+    annotation: String,
+    class: ProductClass,
+    time: DateTime<Utc>,
+}
+
+/// Notices when a product is about to be written to the same file as a different product, and
+/// picks another name for it
+///
+/// EMWIN products are often sent under legacy filenames (like `RADUMSVY.GIF` in a ZIP file), and
+/// those get reused, so one product would silently overwrite another.  This remembers what was
+/// written to each file for a while (an hour, by default, which can be set for each
+/// [`ProductClass`]), and if a product with different contents comes along for the same file in
+/// that time, it's written with a number before the extension instead (like `RADUMSVY-2.GIF`).
+/// The same product sent again isn't a collision.
+///
+/// Unlike a [`DuplicatePolicy`](super::DuplicatePolicy), this notices files that are still
+/// waiting in a [`WriteQueue`](crate::writer::WriteQueue), and doesn't confuse a repeat with a
+/// different product.
+pub struct CollisionDetector {
+    window: Duration,
+    class_windows: HashMap<ProductClass, Duration>,
+    recent: HashMap<PathBuf, Written>,
+}
+
+impl Default for CollisionDetector {
+    fn default() -> Self {
+        CollisionDetector::new()
+    }
+}
+
+impl CollisionDetector {
+    pub fn new() -> CollisionDetector {
+        CollisionDetector {
+            window: Duration::hours(1),
+            class_windows: HashMap::new(),
+            recent: HashMap::new(),
+        }
+    }
+
+    /// Remember the files written for products of a class this long (rounded down to whole
+    /// seconds)
+    pub fn with_window(mut self, class: ProductClass, window: std::time::Duration) -> Self {
+        self.class_windows
+            .insert(class, Duration::seconds(window.as_secs() as i64));
+        self
+    }
+
+    /// Works out where to write a product whose file would be at `path`, and the collision, if
+    /// there is one
+    ///
+    /// `storage` is checked too, so the new name doesn't overwrite an older file.
+    pub fn check(
+        &mut self,
+        storage: &dyn Storage,
+        lrit: &LRIT,
+        path: PathBuf,
+        data: &[u8],
+        now: DateTime<Utc>,
+    ) -> (PathBuf, Option<Collision>) {
+        let (class_windows, window) = (&self.class_windows, self.window);
+        self.recent
+            .retain(|_, w| now - w.time < class_windows.get(&w.class).copied().unwrap_or(window));
+
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let written = Written {
+            hash: hasher.finish(),
+            annotation: lrit
+                .headers
+                .annotation
+                .as_ref()
+                .map(|a| a.text.clone())
+                .unwrap_or_default(),
+            class: ProductClass::of(lrit),
+            time: now,
+        };
+        let other = match self.recent.get(&path) {
+            Some(other) if other.hash != written.hash => other.annotation.clone(),
+            _ => {
+                self.recent.insert(path.clone(), written);
+                return (path, None);
+            }
+        };
+        // the same product again, after it collided
+        let repeat = (2..)
+            .map(|n| versioned(&path, n))
+            .take_while(|p| self.recent.contains_key(p))
+            .find(|p| self.recent[p].hash == written.hash);
+        if let Some(repeat) = repeat {
+            self.recent.insert(repeat.clone(), written);
+            return (repeat, None);
+        }
+        let new_path = (2..)
+            .map(|n| versioned(&path, n))
+            .find(|p| !self.is_taken(storage, p, written.hash))
+            .expect("some version is free");
+        warn!(
+            "{} would overwrite a different product ({}), writing it to {} instead",
+            path.display(),
+            other,
+            new_path.display()
+        );
+        self.recent.insert(new_path.clone(), written);
+        let collision = Collision {
+            path,
+            written: new_path.clone(),
+            other,
+        };
+        (new_path, Some(collision))
+    }
+
+    /// Returns true if `path` holds (or is about to hold) something other than the product with
+    /// this hash
+    fn is_taken(&self, storage: &dyn Storage, path: &Path, hash: u64) -> bool {
+        match self.recent.get(path) {
+            Some(written) => written.hash != hash,
+            None => storage.exists(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::CollisionDetector;
+    use crate::{lrit::LRIT, quota::ProductClass, sim::LritBuilder, storage::MemoryStorage};
+
+    #[test]
+    fn test_collisions() {
+        let storage = MemoryStorage::new();
+        let start = Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap();
+        let text = |name: &str| LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(b"")).unwrap();
+        let mut detector =
+            CollisionDetector::new().with_window(ProductClass::Text, std::time::Duration::from_secs(600));
+        let mut check = |name: &str, data: &[u8], minutes: i64| {
+            let (path, collision) = detector.check(
+                &storage,
+                &text(name),
+                Path::new("out/RADUMSVY.GIF").to_path_buf(),
+                data,
+                start + Duration::minutes(minutes),
+            );
+            (path.to_string_lossy().into_owned(), collision.map(|c| c.other))
+        };
+        assert_eq!(check("a.zis", b"first", 0), ("out/RADUMSVY.GIF".to_string(), None));
+        // a repeat isn't a collision
+        assert_eq!(check("a.zis", b"first", 1), ("out/RADUMSVY.GIF".to_string(), None));
+        assert_eq!(
+            check("b.zis", b"second", 2),
+            ("out/RADUMSVY-2.GIF".to_string(), Some("a.zis".to_string()))
+        );
+        assert_eq!(check("b.zis", b"second", 3), ("out/RADUMSVY-2.GIF".to_string(), None));
+        assert_eq!(check("c.zis", b"third", 4).0, "out/RADUMSVY-3.GIF");
+        // once the window has passed, the file is fair game
+        assert_eq!(check("d.zis", b"fourth", 15), ("out/RADUMSVY.GIF".to_string(), None));
+    }
+}
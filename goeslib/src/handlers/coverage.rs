@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use log::{debug, info};
+use serde_json::json;
+
+use crate::lrit::LRIT;
+
+use super::{DcsBlock, Handler, HandlerError};
+
+/// The signal strengths (in dBm) at the red and green ends of the color scale
+const WEAK_DBM: f32 = 30.0;
+const STRONG_DBM: f32 = 46.0;
+
+/// How wide the PNG map is, in pixels
+#[cfg(feature = "image")]
+const MAP_WIDTH: u32 = 800;
+
+/// How many degrees of margin are left around the platforms on the PNG map
+#[cfg(feature = "image")]
+const MAP_MARGIN: f64 = 5.0;
+
+/// Where a DCS platform is
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlatformLocation {
+    pub name: String,
+    /// In degrees, south is negative
+    pub latitude: f64,
+    /// In degrees, west is negative
+    pub longitude: f64,
+}
+
+/// The locations of DCS platforms, by address
+///
+/// These are in the PDTs (Platform Description Tables), which aren't sent over the downlink, so
+/// they're loaded from a CSV file, like one exported from the PDTs.  Its first line names the
+/// columns, which have to include `address` (in hex, like `CE0A1B2C`), `latitude`, and `longitude`
+/// (in decimal degrees, with west and south negative), and can include a `name`.  Other columns
+/// are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct PlatformLocations {
+    platforms: HashMap<u32, PlatformLocation>,
+}
+
+impl PlatformLocations {
+    pub fn new() -> PlatformLocations {
+        PlatformLocations::default()
+    }
+
+    pub fn insert(&mut self, address: u32, location: PlatformLocation) {
+        self.platforms.insert(address, location);
+    }
+
+    pub fn get(&self, address: u32) -> Option<&PlatformLocation> {
+        self.platforms.get(&address)
+    }
+
+    /// Loads a CSV file of platform locations, returning how many platforms it has
+    ///
+    /// Lines without an address and a position are skipped.
+    pub fn load_csv(&mut self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let text = std::fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let header: Vec<String> = split_csv(lines.next().unwrap_or_default())
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect();
+        let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, message.to_string());
+        let address = column(&["address", "dcp_address"]).ok_or_else(|| invalid("no address column"))?;
+        let latitude = column(&["latitude", "lat"]).ok_or_else(|| invalid("no latitude column"))?;
+        let longitude = column(&["longitude", "lon"]).ok_or_else(|| invalid("no longitude column"))?;
+        let name = column(&["name", "description"]);
+
+        let mut count = 0;
+        for line in lines {
+            let fields = split_csv(line);
+            let field = |i: usize| fields.get(i).map(|f| f.trim()).unwrap_or_default();
+            let parsed = (
+                u32::from_str_radix(field(address), 16),
+                field(latitude).parse::<f64>(),
+                field(longitude).parse::<f64>(),
+            );
+            if let (Ok(address), Ok(latitude), Ok(longitude)) = parsed {
+                let name = name.map(|i| field(i).to_string()).unwrap_or_default();
+                self.insert(
+                    address,
+                    PlatformLocation {
+                        name,
+                        latitude,
+                        longitude,
+                    },
+                );
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Splits a line of a CSV file into its fields, which can be in double quotes
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// The messages heard from one platform
+#[derive(Debug, Clone, Copy)]
+struct Heard {
+    messages: usize,
+    total_dbm: f64,
+    last: DateTime<Utc>,
+}
+
+impl Heard {
+    fn mean_dbm(&self) -> f32 {
+        (self.total_dbm / self.messages as f64) as f32
+    }
+}
+
+/// The color of a signal strength, from red (weak) through yellow to green (strong)
+fn strength_color(dbm: f32) -> [u8; 3] {
+    let t = ((dbm - WEAK_DBM) / (STRONG_DBM - WEAK_DBM)).clamp(0.0, 1.0);
+    if t < 0.5 {
+        [255, (510.0 * t) as u8, 0]
+    } else {
+        [(510.0 * (1.0 - t)) as u8, 255, 0]
+    }
+}
+
+/// Writes a file all at once, so that it's never seen half written
+fn replace_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Maps the DCS platforms that have been heard, colored by their signal strength
+///
+/// Every so often (once an hour by default, going by the messages' carrier start times), the
+/// platforms heard in the last 24 hours (by default) are written to `dcs-coverage.geojson` in the
+/// output root, as points with their address, name, message count, mean signal strength, and a
+/// `marker-color` (which most GeoJSON viewers will use).  With the `image` feature, they're also
+/// drawn on a plain latitude/longitude grid in `dcs-coverage.png`.
+///
+/// Platforms are placed using [`PlatformLocations`]; ones that aren't in it are only counted, in
+/// the GeoJSON's `unlocated` member.
+pub struct DcsCoverageHandler {
+    output_root: PathBuf,
+    platforms: PlatformLocations,
+    interval: Duration,
+    window: Duration,
+    heard: HashMap<u32, Heard>,
+    /// The newest carrier start time the map was written at
+    written: Option<DateTime<Utc>>,
+    /// Whether anything has been heard since the map was written
+    dirty: bool,
+}
+
+impl DcsCoverageHandler {
+    pub fn new(root: impl AsRef<Path>, platforms: PlatformLocations) -> DcsCoverageHandler {
+        DcsCoverageHandler {
+            output_root: root.as_ref().to_path_buf(),
+            platforms,
+            interval: Duration::hours(1),
+            window: Duration::hours(24),
+            heard: HashMap::new(),
+            written: None,
+            dirty: false,
+        }
+    }
+
+    /// Write the map this often (rounded down to whole seconds)
+    pub fn with_interval(mut self, interval: std::time::Duration) -> Self {
+        self.interval = Duration::seconds(interval.as_secs() as i64);
+        self
+    }
+
+    /// Map the platforms heard this long before the newest message
+    pub fn with_window(mut self, window: std::time::Duration) -> Self {
+        self.window = Duration::seconds(window.as_secs() as i64);
+        self
+    }
+
+    fn add(&mut self, block: &DcsBlock) {
+        if block.bad_addr {
+            return;
+        }
+        let heard = self.heard.entry(block.corrected_addr).or_insert(Heard {
+            messages: 0,
+            total_dbm: 0.0,
+            last: block.carrier_start,
+        });
+        heard.messages += 1;
+        heard.total_dbm += f64::from(block.signal_strength);
+        heard.last = heard.last.max(block.carrier_start);
+        self.dirty = true;
+    }
+
+    /// Forgets the platforms that haven't been heard since `now` minus the window, and writes the
+    /// rest
+    fn write(&mut self, now: DateTime<Utc>) -> Result<(), HandlerError> {
+        let since = now - self.window;
+        self.heard.retain(|_, heard| heard.last >= since);
+
+        let mut located: Vec<(u32, &PlatformLocation, &Heard)> = self
+            .heard
+            .iter()
+            .filter_map(|(address, heard)| Some((*address, self.platforms.get(*address)?, heard)))
+            .collect();
+        located.sort_by_key(|(address, _, _)| *address);
+        let unlocated = self.heard.len() - located.len();
+
+        let features: Vec<_> = located
+            .iter()
+            .map(|(address, location, heard)| {
+                let [r, g, b] = strength_color(heard.mean_dbm());
+                json!({
+                    "type": "Feature",
+                    "geometry": {"type": "Point", "coordinates": [location.longitude, location.latitude]},
+                    "properties": {
+                        "address": format!("{:08X}", address),
+                        "name": location.name,
+                        "messages": heard.messages,
+                        "last_heard": heard.last.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        "signal_strength_dbm": (heard.mean_dbm() * 10.0).round() / 10.0,
+                        "marker-color": format!("#{:02x}{:02x}{:02x}", r, g, b),
+                    },
+                })
+            })
+            .collect();
+        let geojson = json!({
+            "type": "FeatureCollection",
+            "features": features,
+            "unlocated": unlocated,
+        });
+        let geojson = serde_json::to_vec_pretty(&geojson).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        replace_file(&self.output_root.join("dcs-coverage.geojson"), &geojson)?;
+        #[cfg(feature = "image")]
+        if !located.is_empty() {
+            let points: Vec<_> = located
+                .iter()
+                .map(|(_, location, heard)| (location.latitude, location.longitude, heard.mean_dbm()))
+                .collect();
+            let mut png = std::io::Cursor::new(Vec::new());
+            draw_map(&points).write_to(&mut png, image::ImageOutputFormat::Png)?;
+            replace_file(&self.output_root.join("dcs-coverage.png"), png.get_ref())?;
+        }
+        info!(
+            "Mapped {} DCS platforms ({} without a location)",
+            located.len(),
+            unlocated
+        );
+
+        self.written = Some(now);
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Draws platforms (as latitude, longitude, and signal strength) as dots on a latitude/longitude
+/// grid, with lines every 10 degrees
+#[cfg(feature = "image")]
+fn draw_map(points: &[(f64, f64, f32)]) -> image::RgbImage {
+    let (mut south, mut north, mut west, mut east) = (90.0f64, -90.0f64, 180.0f64, -180.0f64);
+    for &(lat, lon, _) in points {
+        south = south.min(lat);
+        north = north.max(lat);
+        west = west.min(lon);
+        east = east.max(lon);
+    }
+    let (south, north) = ((south - MAP_MARGIN).max(-90.0), (north + MAP_MARGIN).min(90.0));
+    let (west, east) = ((west - MAP_MARGIN).max(-180.0), (east + MAP_MARGIN).min(180.0));
+    let scale = f64::from(MAP_WIDTH) / (east - west);
+    let height = ((north - south) * scale).ceil().max(1.0) as u32;
+
+    let mut img = image::RgbImage::from_pixel(MAP_WIDTH, height, image::Rgb([16, 20, 32]));
+    let grid = image::Rgb([56, 64, 80]);
+    for lon in ((west / 10.0).ceil() as i32..=(east / 10.0).floor() as i32).map(|d| f64::from(d) * 10.0) {
+        let x = (((lon - west) * scale) as u32).min(MAP_WIDTH - 1);
+        (0..height).for_each(|y| img.put_pixel(x, y, grid));
+    }
+    for lat in ((south / 10.0).ceil() as i32..=(north / 10.0).floor() as i32).map(|d| f64::from(d) * 10.0) {
+        let y = (((north - lat) * scale) as u32).min(height - 1);
+        (0..MAP_WIDTH).for_each(|x| img.put_pixel(x, y, grid));
+    }
+
+    // weaker platforms are drawn last, so they aren't hidden by stronger ones nearby
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| b.2.total_cmp(&a.2));
+    for (lat, lon, dbm) in points {
+        let (cx, cy) = ((lon - west) * scale, (north - lat) * scale);
+        let color = image::Rgb(strength_color(dbm));
+        for y in (cy as i64 - 4).max(0)..=(cy as i64 + 4).min(i64::from(height) - 1) {
+            for x in (cx as i64 - 4).max(0)..=(cx as i64 + 4).min(i64::from(MAP_WIDTH) - 1) {
+                if (x as f64 - cx).powi(2) + (y as f64 - cy).powi(2) <= 16.0 {
+                    img.put_pixel(x as u32, y as u32, color);
+                }
+            }
+        }
+    }
+    img
+}
+
+impl Handler for DcsCoverageHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 130 || lrit.data.len() <= 64 {
+            return Err(HandlerError::Skipped);
+        }
+        let blocks = DcsBlock::parse(&lrit.data[64..])?;
+        let newest = match blocks.iter().map(|b| b.carrier_start).max() {
+            Some(newest) => newest,
+            None => return Err(HandlerError::Skipped),
+        };
+        for block in &blocks {
+            self.add(block);
+        }
+        match self.written {
+            Some(written) if newest - written < self.interval => {
+                debug!("Heard {} DCS platforms so far", self.heard.len());
+                Ok(())
+            }
+            _ => self.write(newest),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        match self.heard.values().map(|heard| heard.last).max() {
+            Some(newest) if self.dirty => self.write(newest),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use super::{strength_color, DcsCoverageHandler, PlatformLocation, PlatformLocations};
+    use crate::{handlers::Handler, lrit::LRIT, sim};
+
+    fn dcs(sequence: u32, time: DateTime<Utc>) -> LRIT {
+        let data = sim::dcs_file("test.dcs", sequence, time, b"NP", b"HELLO");
+        let file = sim::LritBuilder::new(130)
+            .annotation("test.dcs")
+            .noaa(8, 0, 0, 0)
+            .build(&data);
+        LRIT::from_bytes(31, &file).unwrap()
+    }
+
+    #[test]
+    fn test_coverage_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("platforms.csv");
+        std::fs::write(
+            &csv,
+            "Address,Name,Latitude,Longitude\nCE000001,\"Lehigh River, Walnutport\",40.75,-75.6\nCE000002,Nowhere,,\n",
+        )
+        .unwrap();
+        let mut platforms = PlatformLocations::new();
+        assert_eq!(platforms.load_csv(&csv).unwrap(), 1);
+        assert_eq!(platforms.get(0xCE000001).unwrap().name, "Lehigh River, Walnutport");
+        platforms.insert(
+            0xCE000003,
+            PlatformLocation {
+                name: "Lake Okeechobee".to_string(),
+                latitude: 26.95,
+                longitude: -80.8,
+            },
+        );
+
+        let mut handler = DcsCoverageHandler::new(dir.path(), platforms);
+        let start = Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap();
+        // the first message is mapped right away, and then once an hour
+        handler.handle(&dcs(1, start)).unwrap();
+        handler.handle(&dcs(2, start + Duration::minutes(10))).unwrap();
+        let read = || -> serde_json::Value {
+            serde_json::from_slice(&std::fs::read(dir.path().join("dcs-coverage.geojson")).unwrap()).unwrap()
+        };
+        let map = read();
+        assert_eq!(map["features"].as_array().unwrap().len(), 1);
+        assert_eq!(map["unlocated"], 0);
+        handler.handle(&dcs(3, start + Duration::minutes(20))).unwrap();
+        handler.flush().unwrap();
+
+        let map = read();
+        let features = map["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[0]["geometry"]["coordinates"][1], 40.75);
+        assert_eq!(features[0]["properties"]["address"], "CE000001");
+        assert_eq!(features[0]["properties"]["signal_strength_dbm"], 44.5);
+        assert_eq!(features[1]["properties"]["name"], "Lake Okeechobee");
+        // CE000002 has no location
+        assert_eq!(map["unlocated"], 1);
+        assert_eq!(dir.path().join("dcs-coverage.png").exists(), cfg!(feature = "image"));
+
+        // platforms that haven't been heard in a day are left off
+        handler.handle(&dcs(1, start + Duration::hours(25))).unwrap();
+        let map = read();
+        assert_eq!(map["features"].as_array().unwrap().len(), 1);
+        assert_eq!(map["unlocated"], 0);
+
+        assert_eq!(strength_color(20.0), [255, 0, 0]);
+        assert_eq!(strength_color(38.0), [255, 255, 0]);
+        assert_eq!(strength_color(50.0), [0, 255, 0]);
+    }
+}
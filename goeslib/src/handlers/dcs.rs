@@ -2,29 +2,67 @@
 //!
 //! Reference: HRIT_DCS_File_Format_Rev1.pdf
 use std::{
+    convert::TryInto,
+    fmt,
     fs::File,
     io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use chrono::Utc;
 use log::{debug, info, warn};
 
-use crate::{crc, handlers::HandlerError};
+use crate::{
+    bcd_time::parse_dcs_time,
+    bitfield::{extract, is_set, sign_extend},
+    crc,
+    events::{DcsBlockEvent, Event, EventSender},
+    handlers::HandlerError,
+    stats::DcsStats,
+};
 
 use super::Handler;
 
 pub struct DcsHandler {
     output_root: PathBuf,
+    events: Option<EventSender>,
+    /// Only these sources are passed on, or all of them if this is empty
+    sources: Vec<DcsSource>,
+    stats: Option<DcsStats>,
 }
 
 impl DcsHandler {
     pub fn new(root: impl AsRef<Path>) -> Self {
         Self {
             output_root: root.as_ref().to_path_buf(),
+            events: None,
+            sources: Vec::new(),
+            stats: None,
         }
     }
+
+    /// Only send events for messages from these sources (uplink sites)
+    pub fn with_sources(mut self, sources: impl IntoIterator<Item = DcsSource>) -> Self {
+        self.sources = sources.into_iter().collect();
+        self
+    }
+
+    /// Count every message in `stats`, by source and spacecraft
+    ///
+    /// Messages from every source are counted, even the ones filtered out by
+    /// [`with_sources`](DcsHandler::with_sources), so that uplink sites can be compared.
+    pub fn with_stats(mut self, stats: DcsStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Send a [`DcsBlockEvent`] for every decoded message
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
 }
 
 impl Handler for DcsHandler {
@@ -63,37 +101,25 @@ impl Handler for DcsHandler {
         let blocks = DcsBlock::parse(&lrit.data[64..])?;
         debug!("Found {} blocks", blocks.len());
 
-        for (_idx, block) in blocks.into_iter().enumerate() {
-            let _pseudo_binary: Vec<_> = block.data.into_iter().skip(1).map(|x| x & 0x7f).collect();
-
-            // let mut f = std::fs::File::create(self.output_root.join(format!(
-            //     "{base_name}-{:0>8X}-{idx:03}.dcs",
-            //     block.corrected_addr
-            // )))?;
-            // writeln!(f, "{:#?}\n\n", header)?;
-            // writeln!(f, "Baud: {:?}", block.baud_rate)?;
-            // writeln!(f, "platform: {:?}", block.platform)?;
-            // writeln!(f, "Parity errors: {}", block.parity_errors)?;
-            // writeln!(f, "Missing EOT: {}", block.missing_eot)?;
-            // writeln!(f, "Addr corrected: {}", block.addr_corrected)?;
-            // writeln!(f, "Bad addr: {}", block.bad_addr)?;
-            // writeln!(f, "Invalid addr: {}", block.invalid_addr)?;
-            // writeln!(f, "Incomplete PDT: {}", block.incomplete_pdt)?;
-            // writeln!(f, "Timing error: {}", block.timing_error)?;
-            // writeln!(f, "Unexpected message: {}", block.unexpected_message)?;
-            // writeln!(f, "Wrong channel: {}", block.wrong_channel)?;
-            // writeln!(f, "Corrected addr: {:0>8X}", block.corrected_addr)?;
-            // writeln!(f, "Carrier Start: {:?}", block.carrier_start)?;
-            // writeln!(f, "Carrier End: {:?}", block.carrier_end)?;
-            // writeln!(f, "Signal strength: {} dBm EIRP", block.signal_strength)?;
-            // writeln!(f, "Freq offset: {}Hz", block.freq_offset)?;
-            // writeln!(f, "Phase noise: {}° RMS", block.phase_noise)?;
-            // writeln!(f, "Good phase: {}", block.good_phase)?;
-            // writeln!(f, "Space platform: {:?}", block.space_platform)?;
-            // writeln!(f, "Channel: {}", block.channel_number)?;
-            // writeln!(f, "Source platform: {:?}", block.source_platform)?;
-
-            // f.write_all(&pseudo_binary)?;
+        for block in blocks {
+            if let Some(stats) = &self.stats {
+                stats.record(&block);
+            }
+            if !self.sources.is_empty() && !self.sources.contains(&block.source_platform) {
+                continue;
+            }
+            if let Some(events) = &self.events {
+                let _ = events.send(Event::DcsBlockDecoded(DcsBlockEvent {
+                    address: format!("{:08X}", block.corrected_addr),
+                    channel: block.channel_number,
+                    source: block.source_platform.to_string(),
+                    spacecraft: block.space_platform.to_string(),
+                    carrier_start: block.carrier_start,
+                    carrier_end: block.carrier_end,
+                    signal_strength: block.signal_strength,
+                    bytes: block.data.len(),
+                }));
+            }
         }
 
         Ok(())
@@ -184,7 +210,7 @@ pub enum DcsPlatform {
     CS2 = 1,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DcsSpacescraft {
     Unknown = 0,
     GoesEast,
@@ -194,7 +220,24 @@ pub enum DcsSpacescraft {
     Reserved,
 }
 
-#[derive(Debug)]
+impl fmt::Display for DcsSpacescraft {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DcsSpacescraft::Unknown => "unknown",
+            DcsSpacescraft::GoesEast => "GOES-East",
+            DcsSpacescraft::GoesWest => "GOES-West",
+            DcsSpacescraft::GoesCentral => "GOES-Central",
+            DcsSpacescraft::GoesTest => "GOES-Test",
+            DcsSpacescraft::Reserved => "reserved",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The DCS uplink site that a message was relayed through
+///
+/// As a string, this is the two letter source code, like "UP".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum DcsSource {
     /// NOAA WCDA E/W Prime -- Wallops Island, VA
     UP,
@@ -225,6 +268,75 @@ pub enum DcsSource {
     Unknown([u8; 2]),
 }
 
+impl DcsSource {
+    const KNOWN: [DcsSource; 13] = [
+        DcsSource::UP,
+        DcsSource::UB,
+        DcsSource::NP,
+        DcsSource::NB,
+        DcsSource::XE,
+        DcsSource::XW,
+        DcsSource::RE,
+        DcsSource::RW,
+        DcsSource::D1,
+        DcsSource::D2,
+        DcsSource::LE,
+        DcsSource::SF,
+        DcsSource::OW,
+    ];
+
+    pub fn from_code(code: [u8; 2]) -> DcsSource {
+        DcsSource::KNOWN
+            .iter()
+            .copied()
+            .find(|source| source.code() == code)
+            .unwrap_or(DcsSource::Unknown(code))
+    }
+
+    /// The source code, as it appears in a DCS block
+    pub fn code(&self) -> [u8; 2] {
+        match self {
+            DcsSource::UP => *b"UP",
+            DcsSource::UB => *b"UB",
+            DcsSource::NP => *b"NP",
+            DcsSource::NB => *b"NB",
+            DcsSource::XE => *b"XE",
+            DcsSource::XW => *b"XW",
+            DcsSource::RE => *b"RE",
+            DcsSource::RW => *b"RW",
+            DcsSource::D1 => *b"d1",
+            DcsSource::D2 => *b"d2",
+            DcsSource::LE => *b"LE",
+            DcsSource::SF => *b"SF",
+            DcsSource::OW => *b"OW",
+            DcsSource::Unknown(code) => *code,
+        }
+    }
+}
+
+impl fmt::Display for DcsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.code()))
+    }
+}
+
+impl FromStr for DcsSource {
+    type Err = &'static str;
+
+    /// Known source codes are matched without regard to case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 2] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| "source codes are two characters, like UP")?;
+        Ok(DcsSource::KNOWN
+            .iter()
+            .copied()
+            .find(|source| source.code().eq_ignore_ascii_case(&code))
+            .unwrap_or(DcsSource::Unknown(code)))
+    }
+}
+
 /// The main payload of a DCS file
 ///
 /// After the 64 byte header, there will be a variable number of DcsBlock structs
@@ -330,103 +442,63 @@ impl DcsBlock {
             let sequence = cur.read_u24::<LittleEndian>()?;
 
             let tmp = cur.read_u8()?;
-            let baud_rate = match tmp & 0b111 {
+            let baud_rate = match extract(tmp, 0, 3) {
                 1 => 100,
                 2 => 300,
                 3 => 1200,
-                _ => {
-                    warn!("Unexpected baud rate: {}", tmp & 0b111);
-                    continue;
-                }
-            };
-            let platform = match (tmp & 0b1000) >> 3 {
-                0 => DcsPlatform::CS1,
-                1 => DcsPlatform::CS2,
                 x => {
-                    warn!("Unexpected platform: {}", x);
+                    warn!("Unexpected baud rate: {}", x);
                     continue;
                 }
             };
+            let platform = if is_set(tmp, 3) {
+                DcsPlatform::CS2
+            } else {
+                DcsPlatform::CS1
+            };
 
-            let parity_errors = (tmp & 0b10000) >> 4 == 1;
-            let missing_eot = (tmp & 0b100000) >> 5 == 1;
+            let parity_errors = is_set(tmp, 4);
+            let missing_eot = is_set(tmp, 5);
 
             // ARM flags (Abnormal Received Message)
             let tmp = cur.read_u8()?;
-            let addr_corrected = (tmp & 0b1) == 1;
-            let bad_addr = (tmp & 0b10) >> 1 == 1;
-            let invalid_addr = (tmp & 0b100) >> 2 == 1;
-            let incomplete_pdt = (tmp & 0b1000) >> 3 == 1;
-            let timing_error = (tmp & 0b10000) >> 4 == 1;
-            let unexpected_message = (tmp & 0b100000) >> 5 == 1;
-            let wrong_channel = (tmp & 0b1000000) >> 6 == 1;
+            let addr_corrected = is_set(tmp, 0);
+            let bad_addr = is_set(tmp, 1);
+            let invalid_addr = is_set(tmp, 2);
+            let incomplete_pdt = is_set(tmp, 3);
+            let timing_error = is_set(tmp, 4);
+            let unexpected_message = is_set(tmp, 5);
+            let wrong_channel = is_set(tmp, 6);
 
             // corrected address
             let corrected_addr = cur.read_u32::<LittleEndian>()?;
 
-            // carrier start
+            // carrier start and end, as BCD timestamps
             let mut carrier_start_buf = [0; 7];
             cur.read_exact(&mut carrier_start_buf)?;
-
-            // a date stored in BCD format
-            // HRIT_DCS_File_Format_Rev1.pdf
-
-            // last 2 digits of the year (like 22 for 2022)
-            let year = 10 * (carrier_start_buf[6] >> 4) + (carrier_start_buf[6] & 0xF);
-            // day of the year
-            let day = 100 * (carrier_start_buf[5] as u32 >> 4)
-                + 10 * (carrier_start_buf[5] as u32 & 0xF)
-                + (carrier_start_buf[4] as u32 >> 4);
-
-            let hour = 10 * (carrier_start_buf[4] & 0xF) + (carrier_start_buf[3] >> 4);
-            let minute = 10 * (carrier_start_buf[3] & 0xF) + (carrier_start_buf[2] >> 4);
-            let second = 10 * (carrier_start_buf[2] & 0xF) + (carrier_start_buf[1] >> 4);
-            let millis = 100 * (carrier_start_buf[1] as u32 & 0xF)
-                + 10 * (carrier_start_buf[0] as u32 & 0xF)
-                + (carrier_start_buf[0] as u32 >> 4);
-
-            let start_date = chrono::NaiveDate::from_yo(2000 + year as i32, day);
-            let start = start_date.and_hms_milli(hour as u32, minute as u32, second as u32, millis as u32);
-            let start = chrono::DateTime::<Utc>::from_utc(start, chrono::Utc);
-
-            // carrier end
             let mut carrier_end_buf = [0; 7];
             cur.read_exact(&mut carrier_end_buf)?;
-
-            let year = 10 * (carrier_end_buf[6] >> 4) + (carrier_end_buf[6] & 0xF);
-            // day of the year
-            let day = 100 * (carrier_end_buf[5] as u32 >> 4)
-                + 10 * (carrier_end_buf[5] as u32 & 0xF)
-                + (carrier_end_buf[4] as u32 >> 4);
-
-            let hour = 10 * (carrier_end_buf[4] & 0xF) + (carrier_end_buf[3] >> 4);
-            let minute = 10 * (carrier_end_buf[3] & 0xF) + (carrier_end_buf[2] >> 4);
-            let second = 10 * (carrier_end_buf[2] & 0xF) + (carrier_end_buf[1] >> 4);
-            let millis = 100 * (carrier_end_buf[1] as u32 & 0xF)
-                + 10 * (carrier_end_buf[0] as u32 & 0xF)
-                + (carrier_end_buf[0] as u32 >> 4);
-
-            let end_date = chrono::NaiveDate::from_yo(2000 + year as i32, day);
-            let end = end_date.and_hms_milli(hour as u32, minute as u32, second as u32, millis as u32);
-            let end = chrono::DateTime::<Utc>::from_utc(end, chrono::Utc);
+            let (start, end) = match (parse_dcs_time(&carrier_start_buf), parse_dcs_time(&carrier_end_buf)) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Invalid DCS carrier time: {}", e);
+                    cur.set_position((block_start_idx + block_len as usize) as u64);
+                    continue;
+                }
+            };
 
             // signal strength (10 bits)
             let signal_strength_10x = cur.read_u16::<LittleEndian>()?;
-            // mask off the top 6 bytes and then divide by 10
-            let signal_strength = (signal_strength_10x & 0x3ff) as f32 / 10.0;
-
-            // freq offset (14 bite)
-            let freq_offset_10x = cur.read_i16::<LittleEndian>()?;
-            let freq_offset = if freq_offset_10x > 0x1fff {
-                // two's complement
-                (freq_offset_10x - 0x4000) as f32 / 10.0
-            } else {
-                freq_offset_10x as f32 / 10.0
-            };
+            // mask off the top 6 bits and then divide by 10
+            let signal_strength = extract(signal_strength_10x, 0, 10) as f32 / 10.0;
+
+            // freq offset (14 bit two's complement, the top 2 bits are reserved)
+            let freq_offset_10x = cur.read_u16::<LittleEndian>()?;
+            let freq_offset = sign_extend(freq_offset_10x, 14) as f32 / 10.0;
 
             // phase noise (12 bits)
             let phase_noise_100x = cur.read_u16::<LittleEndian>()?;
-            let phase_noise = (phase_noise_100x & 0xfff) as f32 / 100.0;
+            let phase_noise = extract(phase_noise_100x, 0, 12) as f32 / 100.0;
 
             // phase mod quality
             let good_phase_2x = cur.read_u8()?;
@@ -434,8 +506,8 @@ impl DcsBlock {
 
             // channel/spacecraft
             let tmp = cur.read_u16::<LittleEndian>()?;
-            let channel_number = tmp & 0x3ff;
-            let space_platform = match (tmp & 0xf000) >> 12 {
+            let channel_number = extract(tmp, 0, 10) as u16;
+            let space_platform = match extract(tmp, 12, 4) {
                 0 => DcsSpacescraft::Unknown,
                 1 => DcsSpacescraft::GoesEast,
                 2 => DcsSpacescraft::GoesWest,
@@ -450,22 +522,7 @@ impl DcsBlock {
             // source code (2bytes)
             let mut source_code_buf = [0; 2];
             cur.read_exact(&mut source_code_buf)?;
-            let source_platform = match source_code_buf {
-                [b'U', b'P'] => DcsSource::UP,
-                [b'U', b'B'] => DcsSource::UB,
-                [b'N', b'P'] => DcsSource::NP,
-                [b'N', b'B'] => DcsSource::NB,
-                [b'X', b'E'] => DcsSource::XE,
-                [b'X', b'W'] => DcsSource::XW,
-                [b'R', b'E'] => DcsSource::RE,
-                [b'R', b'W'] => DcsSource::RW,
-                [b'd', b'1'] => DcsSource::D1,
-                [b'd', b'2'] => DcsSource::D2,
-                [b'L', b'E'] => DcsSource::LE,
-                [b'S', b'F'] => DcsSource::SF,
-                [b'O', b'W'] => DcsSource::OW,
-                x => DcsSource::Unknown(x),
-            };
+            let source_platform = DcsSource::from_code(source_code_buf);
 
             // Not currently used
             let _secondary_source = cur.read_u16::<LittleEndian>()?;
@@ -525,3 +582,70 @@ impl DcsBlock {
         Ok(blocks)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{DcsBlock, DcsHandler, DcsSource};
+    use crate::{events::Event, handlers::Handler, lrit::LRIT, sim, stats::DcsStats};
+
+    #[test]
+    fn test_freq_offset() {
+        let file = sim::dcs_file("test.dcs", 1, Utc::now(), b"NP", b"HELLO");
+        // the block, without the file header (but with the file CRC)
+        let mut data = file[64..].to_vec();
+        // +12.0 Hz and -12.0 Hz as 14 bits, with reserved bits set above them
+        for (raw, offset) in [(0xc078u16, 12.0), (0x7f88, -12.0)] {
+            data[28..30].copy_from_slice(&raw.to_le_bytes());
+            let block_end = data.len() - 6;
+            let crc = crate::crc::calc_crc16(&data[..block_end]);
+            data[block_end..block_end + 2].copy_from_slice(&crc.to_le_bytes());
+            assert_eq!(DcsBlock::parse(&data).unwrap()[0].freq_offset, offset);
+        }
+    }
+
+    #[test]
+    fn test_source_filter_and_stats() {
+        assert_eq!("up".parse::<DcsSource>(), Ok(DcsSource::UP));
+        assert_eq!("D2".parse::<DcsSource>(), Ok(DcsSource::D2));
+        assert_eq!(DcsSource::from_code(*b"ZZ").to_string(), "ZZ");
+        assert!("UPB".parse::<DcsSource>().is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, receiver) = channel();
+        let stats = DcsStats::default();
+        let mut handler = DcsHandler::new(dir.path())
+            .with_events(sender)
+            .with_stats(stats.clone())
+            .with_sources([DcsSource::UP]);
+        let now = Utc.timestamp_opt(1_650_000_000, 0).unwrap();
+        for (sequence, source) in [(1, b"UP"), (2, b"NP"), (3, b"UP")] {
+            let data = sim::dcs_file("test.dcs", sequence, now, source, b"HELLO");
+            let file = sim::LritBuilder::new(130)
+                .annotation("test.dcs")
+                .noaa(8, 0, 0, 0)
+                .build(&data);
+            handler.handle(&LRIT::from_bytes(31, &file).unwrap()).unwrap();
+        }
+
+        // only messages from UP are passed on
+        let events: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        assert!(events
+            .iter()
+            .all(|e| matches!(e, Event::DcsBlockDecoded(b) if b.source == "UP" && b.spacecraft == "GOES-East")));
+
+        // but everything is counted
+        let totals = stats.totals();
+        assert_eq!(totals.len(), 2);
+        assert_eq!((totals[0].source.as_str(), totals[0].messages), ("UP", 2));
+        assert_eq!((totals[1].source.as_str(), totals[1].messages), ("NP", 1));
+        assert_eq!(totals[0].bytes, 10);
+        assert!((totals[0].signal_strength - 44.5).abs() < 0.01);
+        assert_eq!(totals[0].last, Some(now));
+        assert_eq!(stats.messages_by_source()[&DcsSource::NP], 1);
+    }
+}
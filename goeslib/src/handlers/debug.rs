@@ -2,7 +2,7 @@ use std::path::{Path, PathBuf};
 
 use log::warn;
 
-use crate::{emwin, lrit::LRIT};
+use crate::{annotation::LritFilename, lrit::LRIT};
 
 use super::{Handler, HandlerError};
 use std::io::Write;
@@ -31,10 +31,8 @@ impl Handler for DebugHandler {
 
                 // Is this a EMWIN text product?
                 if lrit.vcid == 20 || lrit.vcid == 21 || lrit.vcid == 22 {
-                    if annotation.text.starts_with("A_") || annotation.text.starts_with("Z_") {
-                        if let Some(parsed_emwin) = emwin::ParsedEmwinName::parse(&annotation.text) {
-                            writeln!(&mut output_file, "{:#?}", parsed_emwin)?;
-                        }
+                    if let LritFilename::Emwin(parsed_emwin) = annotation.parsed() {
+                        writeln!(&mut output_file, "{:#?}", parsed_emwin)?;
                     }
                 }
             }
@@ -0,0 +1,533 @@
+//! Running LRIT files through a list of handlers
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    deadletter::DeadLetter,
+    events::{Event, EventSender, HandlerCrashedEvent},
+    lrit::LRIT,
+    quota::Quotas,
+    replay::ReplayFilter,
+    stats::TimeHistogram,
+    testproducts::TestProductFilter,
+};
+
+use super::{Handler, HandlerError, Quarantine, SandboxOptions, Sandboxed, Suspect, Verifier};
+
+/// How to retry a handler that fails with a transient error
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of times to call a handler (including the first attempt)
+    pub max_attempts: u32,
+    /// How long to wait before the first retry
+    pub initial_backoff: Duration,
+    /// Each following retry waits this many times longer than the previous one
+    pub backoff_multiplier: u32,
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::ZERO,
+            backoff_multiplier: 1,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// 3 attempts, waiting 100ms and then 400ms between them
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            backoff_multiplier: 4,
+        }
+    }
+}
+
+/// A handler that failed to process an LRIT file
+#[derive(Debug)]
+pub struct HandlerFailure {
+    /// The name of the handler (from [`Handler::name`])
+    pub handler: String,
+    /// How many times the handler was called
+    pub attempts: u32,
+    /// The error from the last attempt
+    pub error: HandlerError,
+}
+
+/// Passes each LRIT file to all handlers, retrying transient failures
+///
+/// Handlers that return a transient error (see [`HandlerError::is_transient`]) are called again
+/// with the same LRIT file after a short delay.  Note that this delay blocks the caller.
+///
+/// If a [`DeadLetter`] is configured, LRIT files that any handler failed on are saved there.  If
+/// [`Quotas`] are configured, LRIT files that are over quota aren't passed to any handler.
+///
+/// LRIT files that a [`Verifier`] finds suspect aren't passed to any handler either, and are saved
+/// to the [`Quarantine`] if there is one.  Replays go to the handlers of the [`ReplayFilter`]
+/// instead, if it has any.
+///
+/// The time each handler takes (including retries) is recorded, and a warning is logged if it
+/// takes longer than the time budget.
+///
+/// With a sandbox, each handler runs in its own worker (see [`Sandboxed`]).  Crashes are counted,
+/// and sent as an [`Event::HandlerCrashed`] if events are configured.
+pub struct Dispatcher {
+    handlers: Vec<Box<dyn Handler>>,
+    retry: RetryPolicy,
+    dead_letter: Option<DeadLetter>,
+    quotas: Option<Quotas>,
+    test_products: Option<TestProductFilter>,
+    replay: Option<ReplayFilter>,
+    verifiers: Vec<Box<dyn Verifier>>,
+    quarantine: Option<Quarantine>,
+    budget: Option<Duration>,
+    sandbox: Option<SandboxOptions>,
+    events: Option<EventSender>,
+    timings: BTreeMap<String, TimeHistogram>,
+    crashes: BTreeMap<String, usize>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Dispatcher {
+        Dispatcher {
+            handlers: Vec::new(),
+            retry: RetryPolicy::default(),
+            dead_letter: None,
+            quotas: None,
+            test_products: None,
+            replay: None,
+            verifiers: Vec::new(),
+            quarantine: None,
+            budget: None,
+            sandbox: None,
+            events: None,
+            timings: BTreeMap::new(),
+            crashes: BTreeMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_dead_letter(mut self, dead_letter: DeadLetter) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    pub fn quotas(&self) -> Option<&Quotas> {
+        self.quotas.as_ref()
+    }
+
+    /// Keep test products away from the handlers, see [`TestProductFilter`]
+    pub fn with_test_products(mut self, filter: TestProductFilter) -> Self {
+        self.test_products = Some(filter);
+        self
+    }
+
+    /// Mark replayed LRIT files, and handle them separately if the filter has its own handlers, see
+    /// [`ReplayFilter`]
+    pub fn with_replay_filter(mut self, filter: ReplayFilter) -> Self {
+        self.replay = Some(filter);
+        self
+    }
+
+    /// Check every LRIT file with `verifier` before it's handled
+    pub fn with_verifier(mut self, verifier: impl Verifier + 'static) -> Self {
+        self.verifiers.push(Box::new(verifier));
+        self
+    }
+
+    /// Save LRIT files that a verifier finds suspect here
+    pub fn with_quarantine(mut self, quarantine: Quarantine) -> Self {
+        self.quarantine = Some(quarantine);
+        self
+    }
+
+    /// Warn when a handler takes longer than this to handle one LRIT file
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Run each handler that's pushed after this in its own worker, with these limits
+    pub fn with_sandbox(mut self, options: SandboxOptions) -> Self {
+        self.sandbox = Some(options);
+        self
+    }
+
+    /// Send an [`Event::HandlerCrashed`] every time a handler crashes
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Get the time for quotas, replays, and events from `clock`, instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// How long each handler has taken, keyed by handler name
+    pub fn timings(&self) -> &BTreeMap<String, TimeHistogram> {
+        &self.timings
+    }
+
+    /// How many times each handler has crashed, keyed by handler name
+    pub fn crashes(&self) -> &BTreeMap<String, usize> {
+        &self.crashes
+    }
+
+    pub fn push(&mut self, handler: Box<dyn Handler>) {
+        match &self.sandbox {
+            Some(options) => self.handlers.push(Box::new(Sandboxed::new(handler, options.clone()))),
+            None => self.handlers.push(handler),
+        }
+    }
+
+    /// Runs an LRIT file through all handlers
+    ///
+    /// Returns the handlers that failed (after any retries).  Skipped handlers are not failures.
+    pub fn dispatch(&mut self, lrit: &LRIT) -> Vec<HandlerFailure> {
+        if let Some(filter) = self.test_products.as_ref().filter(|f| f.is_test(lrit)) {
+            let annotation = lrit.headers.annotation.as_ref().map(|a| &a.text);
+            match filter.divert(lrit) {
+                Ok(paths) if paths.is_empty() => debug!("Dropping test product {:?}", annotation),
+                Ok(_) => debug!("Saved test product {:?} in {:?}", annotation, filter.dir()),
+                Err(e) => warn!("Failed to save test product {:?}: {}", annotation, e),
+            }
+            return Vec::new();
+        }
+        let now = self.clock.utc();
+        let marked = self.replay.as_ref().and_then(|filter| filter.mark(lrit, now));
+        let lrit = marked.as_ref().unwrap_or(lrit);
+        if lrit.replay {
+            if let Some(failures) = self.replay.as_mut().and_then(|filter| filter.dispatch(lrit)) {
+                debug!(
+                    "Handling replayed {:?} separately",
+                    lrit.headers.annotation.as_ref().map(|a| &a.text)
+                );
+                return failures;
+            }
+        }
+        if let Some(quotas) = &mut self.quotas {
+            if !quotas.admit(lrit, now) {
+                debug!(
+                    "Dropping {:?}, over quota",
+                    lrit.headers.annotation.as_ref().map(|a| &a.text)
+                );
+                return Vec::new();
+            }
+        }
+        if let Some(mut suspect) = self.verify(lrit) {
+            let annotation = lrit.headers.annotation.as_ref().map(|a| &a.text);
+            warn!(
+                "{} found {:?} suspect: {}",
+                suspect.verifier, annotation, suspect.reason
+            );
+            if let Some(quarantine) = &self.quarantine {
+                match quarantine.write(lrit, &mut suspect) {
+                    Ok(path) => warn!("Quarantined it in {}", path.display()),
+                    Err(e) => warn!("Failed to quarantine it: {}", e),
+                }
+            }
+            return Vec::new();
+        }
+
+        let mut failures = Vec::new();
+        for handler in &mut self.handlers {
+            let mut attempts = 0;
+            let mut backoff = self.retry.initial_backoff;
+            let start = Instant::now();
+            let mut skipped = false;
+            loop {
+                attempts += 1;
+                match handler.handle(lrit) {
+                    Ok(()) => break,
+                    Err(HandlerError::Skipped) => {
+                        skipped = true;
+                        break;
+                    }
+                    Err(e) if e.is_transient() && attempts < self.retry.max_attempts => {
+                        warn!("{} failed (attempt {}), retrying: {}", handler.name(), attempts, e);
+                        std::thread::sleep(backoff);
+                        backoff *= self.retry.backoff_multiplier;
+                    }
+                    Err(error) => {
+                        warn!("{} failed after {} attempt(s): {}", handler.name(), attempts, error);
+                        if let HandlerError::Crashed(msg) = &error {
+                            *self.crashes.entry(handler.name().to_string()).or_insert(0) += 1;
+                            if let Some(events) = &self.events {
+                                // a closed channel is not an error; the event is simply dropped
+                                let _ = events.send(Event::HandlerCrashed(HandlerCrashedEvent {
+                                    handler: handler.name().to_string(),
+                                    error: msg.clone(),
+                                    product: lrit.headers.annotation.as_ref().map(|a| a.text.clone()),
+                                    time: now,
+                                }));
+                            }
+                        }
+                        failures.push(HandlerFailure {
+                            handler: handler.name().to_string(),
+                            attempts,
+                            error,
+                        });
+                        break;
+                    }
+                }
+            }
+
+            // skipped files say nothing about how long a handler takes
+            if !skipped {
+                let elapsed = start.elapsed();
+                self.timings
+                    .entry(handler.name().to_string())
+                    .or_default()
+                    .record(elapsed);
+                if matches!(self.budget, Some(budget) if elapsed > budget) {
+                    warn!(
+                        "{} took {} ms for {:?}",
+                        handler.name(),
+                        elapsed.as_millis(),
+                        lrit.headers.annotation.as_ref().map(|a| &a.text)
+                    );
+                }
+            }
+        }
+
+        if let (Some(dl), false) = (&self.dead_letter, failures.is_empty()) {
+            match dl.write(lrit, &failures) {
+                Ok(path) => warn!("Saved failed LRIT file to {}", path.display()),
+                Err(e) => warn!("Failed to write dead-letter file: {}", e),
+            }
+        }
+
+        failures
+    }
+
+    /// Runs an LRIT file past the verifiers, stopping at the first one that finds it suspect
+    fn verify(&mut self, lrit: &LRIT) -> Option<Suspect> {
+        self.verifiers.iter_mut().find_map(|verifier| {
+            verifier.verify(lrit).map(|reason| Suspect {
+                verifier: verifier.name().to_string(),
+                reason,
+                path: None,
+            })
+        })
+    }
+
+    /// Flushes all handlers (see [`Handler::flush`])
+    ///
+    /// Returns the handlers that failed to flush.  Flushing isn't retried.
+    pub fn flush(&mut self) -> Vec<HandlerFailure> {
+        let mut failures = self.replay.as_mut().map(ReplayFilter::flush).unwrap_or_default();
+        for handler in &mut self.handlers {
+            if let Err(error) = handler.flush() {
+                warn!("{} failed to flush: {}", handler.name(), error);
+                failures.push(HandlerFailure {
+                    handler: handler.name().to_string(),
+                    attempts: 1,
+                    error,
+                });
+            }
+        }
+        failures
+    }
+}
+
+impl Default for Dispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{Dispatcher, RetryPolicy};
+    use crate::{
+        clock::ManualClock,
+        events::Event,
+        handlers::{Handler, HandlerError, Quarantine, SandboxOptions, Verifier},
+        index::ProductIndex,
+        lrit::LRIT,
+        quota::{ProductClass, Quotas},
+        sim::LritBuilder,
+        testproducts::TestProductFilter,
+    };
+
+    /// Fails the first `failures` times it's called
+    struct Flaky {
+        failures: u32,
+        transient: bool,
+        calls: u32,
+    }
+
+    impl Handler for Flaky {
+        fn handle(&mut self, _lrit: &LRIT) -> Result<(), HandlerError> {
+            self.calls += 1;
+            if self.calls > self.failures {
+                Ok(())
+            } else if self.transient {
+                Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+            } else {
+                Err(HandlerError::Parse("bad data"))
+            }
+        }
+    }
+
+    struct Panicky;
+
+    impl Handler for Panicky {
+        fn handle(&mut self, _lrit: &LRIT) -> Result<(), HandlerError> {
+            panic!("oops")
+        }
+    }
+
+    /// Finds every file with data in it suspect
+    struct Picky;
+
+    impl Verifier for Picky {
+        fn verify(&mut self, lrit: &LRIT) -> Option<String> {
+            (!lrit.data.is_empty()).then(|| "has data".to_string())
+        }
+    }
+
+    fn lrit() -> LRIT {
+        LRIT::from_bytes(20, &[0, 0, 16, 2, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap()
+    }
+
+    fn dispatcher(failures: u32, transient: bool) -> Dispatcher {
+        let mut d = Dispatcher::new().with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            backoff_multiplier: 2,
+        });
+        d.push(Box::new(Flaky {
+            failures,
+            transient,
+            calls: 0,
+        }));
+        d
+    }
+
+    #[test]
+    fn test_transient_retry() {
+        assert!(dispatcher(2, true).dispatch(&lrit()).is_empty());
+
+        let failures = dispatcher(3, true).dispatch(&lrit());
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].attempts, 3);
+        assert_eq!(failures[0].handler, "Flaky");
+    }
+
+    #[test]
+    fn test_permanent_not_retried() {
+        let failures = dispatcher(1, false).dispatch(&lrit());
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].attempts, 1);
+        assert!(matches!(failures[0].error, HandlerError::Parse(_)));
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let (s, events) = std::sync::mpsc::channel();
+        let mut d = Dispatcher::new()
+            .with_retry_policy(RetryPolicy::none())
+            .with_sandbox(SandboxOptions::default())
+            .with_events(s);
+        d.push(Box::new(Panicky));
+        let failures = d.dispatch(&lrit());
+        assert!(matches!(&failures[0].error, HandlerError::Crashed(_)));
+        assert_eq!(failures[0].handler, "Panicky");
+        assert_eq!(d.crashes()["Panicky"], 1);
+        assert!(matches!(events.try_recv(), Ok(Event::HandlerCrashed(e)) if e.handler == "Panicky"));
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut d = dispatcher(1, false)
+            .with_verifier(Picky)
+            .with_quarantine(Quarantine::new(dir.path()).with_index(dir.path()));
+        let suspect = LRIT::from_bytes(20, &LritBuilder::new(2).annotation("suspect.txt").build(b"text")).unwrap();
+
+        // the handler never sees the suspect file, so it doesn't fail
+        assert!(d.dispatch(&suspect).is_empty());
+        assert_eq!(d.dispatch(&lrit()).len(), 1);
+
+        let records = ProductIndex::new(dir.path()).read_day(Utc::now().date_naive()).unwrap();
+        let flagged = records[0].suspect.as_ref().unwrap();
+        assert_eq!(
+            (flagged.verifier.as_str(), flagged.reason.as_str()),
+            ("Picky", "has data")
+        );
+        let path = flagged.path.as_ref().unwrap();
+        assert!(path.starts_with(dir.path().join("quarantine")));
+        assert_eq!(std::fs::read(path).unwrap(), suspect.to_bytes());
+    }
+
+    #[test]
+    fn test_test_products() {
+        let mut d = dispatcher(1, false).with_test_products(TestProductFilter::new());
+        let rwt = LRIT::from_bytes(
+            20,
+            &LritBuilder::new(2)
+                .annotation("A_NPUS51KPHI071000_C_KWIN_20220507100012_000001-3-RWTPHIPA.TXT")
+                .build(b"test"),
+        )
+        .unwrap();
+
+        // the handler never sees the test product, so it doesn't fail
+        assert!(d.dispatch(&rwt).is_empty());
+        assert_eq!(d.dispatch(&lrit()).len(), 1);
+    }
+
+    #[test]
+    fn test_quotas() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap());
+        let mut d = dispatcher(0, false)
+            .with_quotas(Quotas::new().max_text_files_per_hour(1))
+            .with_clock(Arc::new(clock.clone()));
+        let text = LRIT::from_bytes(20, &LritBuilder::new(2).annotation("bulletin.txt").build(b"text")).unwrap();
+        d.dispatch(&text);
+        clock.advance(Duration::from_secs(59 * 60));
+        d.dispatch(&text);
+        assert_eq!(d.quotas().unwrap().dropped(ProductClass::Text), 1);
+
+        // an hour after the first one, there's room again
+        clock.advance(Duration::from_secs(60));
+        d.dispatch(&text);
+        assert_eq!(d.quotas().unwrap().dropped(ProductClass::Text), 1);
+        assert_eq!(d.timings()["Flaky"].count, 2);
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut d = dispatcher(0, false);
+        d.dispatch(&lrit());
+        d.dispatch(&lrit());
+        assert_eq!(d.timings()["Flaky"].count, 2);
+    }
+}
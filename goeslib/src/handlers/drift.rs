@@ -0,0 +1,246 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::debug;
+
+use crate::lrit::LRIT;
+
+use super::{DcsBlock, DcsSpacescraft, Handler, HandlerError};
+
+const CSV_HEADER: &str = "interval_start,spacecraft,channel,messages,mean_offset_hz,min_offset_hz,max_offset_hz";
+
+/// The frequency offsets of the messages on one channel in one interval
+#[derive(Debug, Clone, Copy)]
+struct Drift {
+    messages: usize,
+    total: f64,
+    min: f32,
+    max: f32,
+}
+
+impl Drift {
+    fn new(offset: f32) -> Drift {
+        Drift {
+            messages: 1,
+            total: offset as f64,
+            min: offset,
+            max: offset,
+        }
+    }
+
+    fn add(&mut self, offset: f32) {
+        self.messages += 1;
+        self.total += offset as f64;
+        self.min = self.min.min(offset);
+        self.max = self.max.max(offset);
+    }
+
+    fn mean(&self) -> f64 {
+        self.total / self.messages as f64
+    }
+}
+
+/// Tracks the mean frequency offset of DCS messages on each channel, to show transponder or LO
+/// drift over time
+///
+/// Messages are grouped by spacecraft and channel, into intervals (10 minutes by default) of their
+/// carrier start time.  Once an interval is over, a line per channel is appended to
+/// `dcs-drift.csv` in the output root, which is meant to be plotted.
+///
+/// With [`with_prometheus`](DcsDriftHandler::with_prometheus), the mean of each channel in its
+/// latest interval is also written as a gauge, in the format of node_exporter's textfile collector.
+pub struct DcsDriftHandler {
+    output_root: PathBuf,
+    interval: Duration,
+    prometheus: Option<PathBuf>,
+    /// Intervals that haven't been written yet, keyed by their start
+    open: BTreeMap<DateTime<Utc>, BTreeMap<(DcsSpacescraft, u16), Drift>>,
+    /// The end of the last interval that was written; later messages from before this are dropped
+    written_until: Option<DateTime<Utc>>,
+    /// The mean of each channel in the last interval it was written for
+    latest: BTreeMap<(DcsSpacescraft, u16), f64>,
+}
+
+impl DcsDriftHandler {
+    pub fn new(root: impl AsRef<Path>) -> DcsDriftHandler {
+        DcsDriftHandler {
+            output_root: root.as_ref().to_path_buf(),
+            interval: Duration::minutes(10),
+            prometheus: None,
+            open: BTreeMap::new(),
+            written_until: None,
+            latest: BTreeMap::new(),
+        }
+    }
+
+    /// Average over intervals of this length (rounded down to whole seconds, and at least one second)
+    pub fn with_interval(mut self, interval: std::time::Duration) -> Self {
+        self.interval = Duration::seconds(interval.as_secs().max(1) as i64);
+        self
+    }
+
+    /// Also write gauges to this file (replacing it all at once) every time an interval is written
+    pub fn with_prometheus(mut self, path: impl AsRef<Path>) -> Self {
+        self.prometheus = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn interval_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = self.interval.num_seconds();
+        Utc.timestamp_opt(time.timestamp().div_euclid(secs) * secs, 0).unwrap()
+    }
+
+    fn add(&mut self, block: &DcsBlock) {
+        let start = self.interval_start(block.carrier_start);
+        if matches!(self.written_until, Some(until) if start < until) {
+            debug!("Dropping a late DCS message from {}", block.carrier_start);
+            return;
+        }
+        self.open
+            .entry(start)
+            .or_default()
+            .entry((block.space_platform, block.channel_number))
+            .and_modify(|d| d.add(block.freq_offset))
+            .or_insert_with(|| Drift::new(block.freq_offset));
+    }
+
+    /// Writes every interval that started before `before` (or every interval, if it's `None`)
+    fn write_intervals(&mut self, before: Option<DateTime<Utc>>) -> Result<(), HandlerError> {
+        let remaining = match before {
+            Some(before) => self.open.split_off(&before),
+            None => BTreeMap::new(),
+        };
+        let done = std::mem::replace(&mut self.open, remaining);
+        if done.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.output_root.join("dcs-drift.csv");
+        let new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if new {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        for (start, channels) in &done {
+            for ((spacecraft, channel), drift) in channels {
+                writeln!(
+                    file,
+                    "{},{},{},{},{:.2},{:.1},{:.1}",
+                    start.format("%Y-%m-%dT%H:%M:%SZ"),
+                    spacecraft,
+                    channel,
+                    drift.messages,
+                    drift.mean(),
+                    drift.min,
+                    drift.max
+                )?;
+                self.latest.insert((*spacecraft, *channel), drift.mean());
+            }
+            self.written_until = Some(*start + self.interval);
+        }
+        self.write_prometheus()
+    }
+
+    fn write_prometheus(&self) -> Result<(), HandlerError> {
+        let path = match &self.prometheus {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut text = String::from(
+            "# HELP goesbox_dcs_frequency_offset_hz Mean frequency offset of DCS messages on a channel\n\
+             # TYPE goesbox_dcs_frequency_offset_hz gauge\n",
+        );
+        for ((spacecraft, channel), mean) in &self.latest {
+            let _ = writeln!(
+                text,
+                "goesbox_dcs_frequency_offset_hz{{spacecraft=\"{}\",channel=\"{}\"}} {:.2}",
+                spacecraft, channel, mean
+            );
+        }
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+impl Handler for DcsDriftHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 130 || lrit.data.len() <= 64 {
+            return Err(HandlerError::Skipped);
+        }
+        let blocks = DcsBlock::parse(&lrit.data[64..])?;
+        let newest = match blocks.iter().map(|b| b.carrier_start).max() {
+            Some(newest) => newest,
+            None => return Err(HandlerError::Skipped),
+        };
+        for block in &blocks {
+            self.add(block);
+        }
+        // the interval before the newest one is kept open, for messages that arrive late
+        let before = self.interval_start(newest) - self.interval;
+        self.write_intervals(Some(before))
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        self.write_intervals(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use super::DcsDriftHandler;
+    use crate::{handlers::Handler, lrit::LRIT, sim};
+
+    fn dcs(sequence: u32, time: DateTime<Utc>) -> LRIT {
+        let data = sim::dcs_file("test.dcs", sequence, time, b"NP", b"HELLO");
+        let file = sim::LritBuilder::new(130)
+            .annotation("test.dcs")
+            .noaa(8, 0, 0, 0)
+            .build(&data);
+        LRIT::from_bytes(31, &file).unwrap()
+    }
+
+    #[test]
+    fn test_drift_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let prom = dir.path().join("goesbox.prom");
+        let mut handler = DcsDriftHandler::new(dir.path())
+            .with_interval(std::time::Duration::from_secs(600))
+            .with_prometheus(&prom);
+        let start = Utc.timestamp_opt(1_650_000_000 / 600 * 600, 0).unwrap();
+
+        handler.handle(&dcs(1, start + Duration::seconds(30))).unwrap();
+        handler.handle(&dcs(2, start + Duration::seconds(90))).unwrap();
+        // the first interval is kept open for late messages
+        handler.handle(&dcs(3, start + Duration::minutes(11))).unwrap();
+        assert!(!dir.path().join("dcs-drift.csv").exists());
+
+        handler.handle(&dcs(4, start + Duration::minutes(21))).unwrap();
+        let csv = std::fs::read_to_string(dir.path().join("dcs-drift.csv")).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            format!("{},GOES-East,42,2,12.00,12.0,12.0", start.format("%Y-%m-%dT%H:%M:%SZ"))
+        );
+        let gauges = std::fs::read_to_string(&prom).unwrap();
+        assert!(gauges.contains("goesbox_dcs_frequency_offset_hz{spacecraft=\"GOES-East\",channel=\"42\"} 12.00"));
+
+        // too late, its interval has already been written
+        handler.handle(&dcs(5, start + Duration::seconds(45))).unwrap();
+        handler.flush().unwrap();
+        let csv = std::fs::read_to_string(dir.path().join("dcs-drift.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().skip(2).all(|l| l.contains(",1,12.00,")));
+    }
+}
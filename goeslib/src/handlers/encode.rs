@@ -0,0 +1,182 @@
+//! Encoding grayscale images as JPEG
+//!
+//! A 5424x5424 full disk image takes hundreds of milliseconds to encode on the ARM boards that
+//! receivers usually run on, and all of that used to happen on the handler thread.  With the
+//! `rayon` feature, big images are cut into bands of rows that are encoded on every core at once,
+//! and then stitched back into a single JPEG.  The bands are separated by restart markers, which
+//! reset the decoder the same way that starting a new image does, so any decoder can read the
+//! result.  With the `turbojpeg` feature, libjpeg-turbo does the encoding instead.
+//!
+//! Ref: ITU T.81 (JPEG), B.2.4.4 (restart interval) and F.1.2.1.2 (DC prediction)
+use super::HandlerError;
+
+/// The JPEG quality (the default of the `image` crate's encoder)
+const QUALITY: u8 = 75;
+
+/// Images with fewer pixels than this are encoded in one piece
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+const PARALLEL_PIXELS: usize = 1024 * 1024;
+
+/// Encodes an 8-bit grayscale image, whose rows are `width` pixels long
+#[cfg(not(feature = "turbojpeg"))]
+pub(crate) fn encode_jpeg(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, HandlerError> {
+    #[cfg(feature = "rayon")]
+    if pixels.len() >= PARALLEL_PIXELS {
+        return encode_bands(pixels, width, height, rayon::current_num_threads());
+    }
+    encode_whole(pixels, width, height)
+}
+
+/// Encodes an 8-bit grayscale image, whose rows are `width` pixels long
+#[cfg(feature = "turbojpeg")]
+pub(crate) fn encode_jpeg(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, HandlerError> {
+    let image = turbojpeg::Image {
+        pixels,
+        width: width as usize,
+        pitch: width as usize,
+        height: height as usize,
+        format: turbojpeg::PixelFormat::GRAY,
+    };
+    let jpeg = turbojpeg::compress(image, QUALITY as i32, turbojpeg::Subsamp::Gray)
+        .map_err(|e| HandlerError::Other(Box::new(e)))?;
+    Ok(jpeg.to_vec())
+}
+
+#[cfg(not(feature = "turbojpeg"))]
+fn encode_whole(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, HandlerError> {
+    let mut buf = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, QUALITY).encode(
+        &pixels[..width as usize * height as usize],
+        width,
+        height,
+        image::ColorType::L8,
+    )?;
+    Ok(buf)
+}
+
+/// Encodes an image as (up to) `bands` bands of rows in parallel
+///
+/// If the encoded bands can't be stitched together, the image is encoded in one piece instead.
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+fn encode_bands(pixels: &[u8], width: u32, height: u32, bands: usize) -> Result<Vec<u8>, HandlerError> {
+    use rayon::prelude::*;
+
+    let (width, height) = (width as usize, height as usize);
+    // each band has to be a whole number of 8x8 blocks tall, and the restart interval (which is the
+    // number of blocks in a band) has to fit in 16 bits
+    let blocks_per_row = width.div_ceil(8);
+    let max_rows = u16::MAX as usize / blocks_per_row * 8;
+    let rows = (height.div_ceil(bands.max(1)).div_ceil(8) * 8).min(max_rows);
+    if rows == 0 || rows >= height {
+        return encode_whole(pixels, width as u32, height as u32);
+    }
+
+    let encoded = pixels[..width * height]
+        .par_chunks(rows * width)
+        .map(|band| encode_whole(band, width as u32, (band.len() / width) as u32))
+        .collect::<Result<Vec<_>, _>>()?;
+    let interval = (rows / 8 * blocks_per_row) as u16;
+    match stitch(&encoded, height as u16, interval) {
+        Some(jpeg) => Ok(jpeg),
+        None => encode_whole(pixels, width as u32, height as u32),
+    }
+}
+
+/// A JPEG's marker segments up to (and including) the start of scan, and its entropy coded data
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+struct Parts<'a> {
+    segments: Vec<(u8, &'a [u8])>,
+    data: &'a [u8],
+}
+
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+fn split(jpeg: &[u8]) -> Option<Parts<'_>> {
+    let mut rest = jpeg.strip_prefix(&[0xff, 0xd8])?.strip_suffix(&[0xff, 0xd9])?;
+    let mut segments = Vec::new();
+    loop {
+        let (marker, len) = match rest {
+            [0xff, marker, hi, lo, ..] => (*marker, u16::from_be_bytes([*hi, *lo]) as usize),
+            _ => return None,
+        };
+        let payload = rest.get(4..2 + len)?;
+        rest = &rest[2 + len..];
+        segments.push((marker, payload));
+        // start of scan
+        if marker == 0xda {
+            return Some(Parts { segments, data: rest });
+        }
+    }
+}
+
+/// Joins separately encoded bands of an image into one JPEG, with a restart marker between each
+/// band
+///
+/// Every band has to use the same tables, which is the case when they come from the same encoder
+/// with the same quality.  Returns `None` if they don't, or if they can't be parsed.
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+fn stitch(bands: &[Vec<u8>], height: u16, interval: u16) -> Option<Vec<u8>> {
+    let parts = bands.iter().map(|band| split(band)).collect::<Option<Vec<_>>>()?;
+    let first = parts.first()?;
+    // only the image height (in the frame header) differs between bands
+    let not_frame = |(marker, _): &&(u8, &[u8])| *marker != 0xc0;
+    let tables = first.segments.iter().filter(not_frame);
+    if !parts
+        .iter()
+        .all(|p| p.segments.iter().filter(not_frame).eq(tables.clone()))
+    {
+        return None;
+    }
+
+    let mut out = Vec::with_capacity(bands.iter().map(Vec::len).sum::<usize>() + 4 * bands.len());
+    out.extend_from_slice(&[0xff, 0xd8]);
+    for (marker, payload) in &first.segments {
+        if *marker == 0xda {
+            // define restart interval
+            out.extend_from_slice(&[0xff, 0xdd, 0x00, 0x04]);
+            out.extend_from_slice(&interval.to_be_bytes());
+        }
+        out.extend_from_slice(&[0xff, *marker]);
+        out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        if *marker == 0xc0 {
+            // baseline frame header: precision, then the number of lines
+            let mut frame = payload.to_vec();
+            frame.get_mut(1..3)?.copy_from_slice(&height.to_be_bytes());
+            out.extend_from_slice(&frame);
+        } else {
+            out.extend_from_slice(payload);
+        }
+    }
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            out.extend_from_slice(&[0xff, 0xd0 + ((i - 1) % 8) as u8]);
+        }
+        out.extend_from_slice(part.data);
+    }
+    out.extend_from_slice(&[0xff, 0xd9]);
+    Some(out)
+}
+
+#[cfg(test)]
+#[cfg(all(feature = "rayon", not(feature = "turbojpeg")))]
+mod tests {
+    use super::{encode_bands, encode_whole};
+
+    #[test]
+    fn test_encode_bands() {
+        // 300 rows don't split evenly into 8 row blocks, or into 4 bands
+        let (width, height) = (203, 300);
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| ((i % width) * 3 + (i / width) * 5 % 256) as u8)
+            .collect();
+        let whole = image::load_from_memory(&encode_whole(&pixels, width as u32, height as u32).unwrap())
+            .unwrap()
+            .to_luma8();
+
+        let jpeg = encode_bands(&pixels, width as u32, height as u32, 4).unwrap();
+        // one restart marker between each of the 4 bands
+        assert_eq!(jpeg.windows(2).filter(|w| matches!(w, [0xff, 0xd0..=0xd7])).count(), 3);
+        let banded = image::load_from_memory(&jpeg).unwrap().to_luma8();
+        assert_eq!(banded.dimensions(), (width as u32, height as u32));
+        assert_eq!(banded, whole);
+    }
+}
@@ -0,0 +1,247 @@
+//! Handler for GLM (lightning mapper) products
+//!
+//! GLM products are recognized by their GOES-R product name, like `OR_GLM-L2-GLMF-M6_G16_s…`, and
+//! are stored in `glm/<product>/`.  Images (like flash extent density) are written as JPEGs, and
+//! anything else is written as-is.
+//!
+//! GLM images can also be resampled onto the grid of the latest ABI full disk image, as a
+//! transparent yellow overlay (`<name>.overlay.png`) that lines up with the ABI imagery.  Both
+//! instruments are on the same satellite, so this only needs the image navigation headers.
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::{
+    annotation::LritFilename,
+    lrit::LRIT,
+    storage::{self, SharedStorage},
+    xmp::ImageMetadata,
+};
+
+use super::{
+    image::{save_jpeg, save_png},
+    Handler, HandlerError,
+};
+
+/// The color of lightning in overlays
+const OVERLAY_COLOR: [u8; 3] = [255, 220, 0];
+
+/// The size and navigation of an image, for converting between pixels and scan angles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub columns: u32,
+    pub lines: u32,
+    pub column_scaling_factor: f64,
+    pub line_scaling_factor: f64,
+    pub column_offset: f64,
+    pub line_offset: f64,
+}
+
+impl Grid {
+    /// The grid of an image (or one segment of it), from its navigation header
+    ///
+    /// Like the XMP metadata, the navigation header is taken to describe the whole image.
+    pub fn from_lrit(lrit: &LRIT) -> Option<Grid> {
+        let nav = lrit.headers.img_navigation.as_ref()?;
+        if nav.column_scaling_factor == 0 || nav.line_scaling_factor == 0 {
+            return None;
+        }
+        let (columns, lines) = match (&lrit.headers.img_segment, &lrit.headers.img_strucutre) {
+            (Some(seg), _) => (seg.max_column, seg.max_row),
+            (None, Some(ihs)) => (ihs.num_columns, ihs.num_lines),
+            (None, None) => return None,
+        };
+        Some(Grid {
+            columns: columns as u32,
+            lines: lines as u32,
+            column_scaling_factor: nav.column_scaling_factor as f64,
+            line_scaling_factor: nav.line_scaling_factor as f64,
+            column_offset: nav.column_offset as f64,
+            line_offset: nav.line_offset as f64,
+        })
+    }
+
+    /// The pixel of `other` that the center of pixel (`column`, `line`) of this grid is in
+    fn pixel_in(&self, column: u32, line: u32, other: &Grid) -> Option<(u32, u32)> {
+        // scan angles, in units of 2^-16 degrees (CGMS LRIT/HRIT Global Specification, 4.4.4)
+        let x = (column as f64 + 0.5 - self.column_offset) / self.column_scaling_factor;
+        let y = (line as f64 + 0.5 - self.line_offset) / self.line_scaling_factor;
+        let c = (other.column_offset + x * other.column_scaling_factor).floor();
+        let l = (other.line_offset + y * other.line_scaling_factor).floor();
+        if c < 0.0 || l < 0.0 || c >= other.columns as f64 || l >= other.lines as f64 {
+            return None;
+        }
+        Some((c as u32, l as u32))
+    }
+}
+
+/// Resamples an image on the `from` grid onto the `to` grid, as a transparent overlay
+///
+/// Brighter pixels (like more flashes) are more opaque.
+pub fn overlay(img: &image::GrayImage, from: &Grid, to: &Grid) -> image::RgbaImage {
+    let [r, g, b] = OVERLAY_COLOR;
+    image::RgbaImage::from_fn(to.columns, to.lines, |column, line| {
+        let alpha = to
+            .pixel_in(column, line, from)
+            .and_then(|(c, l)| img.get_pixel_checked(c, l))
+            .map_or(0, |p| p.0[0]);
+        image::Rgba([r, g, b, alpha])
+    })
+}
+
+/// Returns true if this annotation is a GLM product name
+pub(crate) fn is_glm(annotation: &str) -> bool {
+    matches!(LritFilename::parse(annotation), LritFilename::GoesR(goes) if goes.instrument == "GLM")
+}
+
+pub struct GlmHandler {
+    output_root: PathBuf,
+    /// Write overlays on the ABI grid
+    overlays: bool,
+    /// The grid of the latest ABI full disk image
+    abi_grid: Option<Grid>,
+    storage: SharedStorage,
+}
+
+impl GlmHandler {
+    pub fn new(root: impl AsRef<Path>) -> GlmHandler {
+        GlmHandler {
+            output_root: root.as_ref().to_path_buf(),
+            overlays: false,
+            abi_grid: None,
+            storage: storage::local(),
+        }
+    }
+
+    /// Also write GLM images as overlays on the grid of the latest ABI full disk image
+    pub fn with_overlays(mut self) -> Self {
+        self.overlays = true;
+        self
+    }
+
+    /// Write products to `storage` instead of the local filesystem
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    fn write_image(&self, lrit: &LRIT, dir: &Path, stem: &str) -> Result<(), HandlerError> {
+        let ihs = lrit
+            .headers
+            .img_strucutre
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        if ihs.bits_per_pixel != 8 {
+            return Err(HandlerError::Parse("unsupported bits per pixel"));
+        }
+        let mut data = lrit.data.clone();
+        data.resize(ihs.num_columns as usize * ihs.num_lines as usize, 0);
+        let img = image::GrayImage::from_raw(ihs.num_columns as u32, ihs.num_lines as u32, data)
+            .ok_or(HandlerError::Parse("failed to create GLM image"))?;
+        let out_name = dir.join(stem).with_extension("jpg");
+        save_jpeg(&*self.storage, &img, &out_name, &ImageMetadata::from_lrit(lrit))?;
+        info!("glm, {}", out_name.display());
+
+        if let (true, Some(abi), Some(glm)) = (self.overlays, &self.abi_grid, Grid::from_lrit(lrit)) {
+            let overlay = image::DynamicImage::ImageRgba8(overlay(&img, &glm, abi));
+            save_png(&*self.storage, &overlay, &dir.join(stem).with_extension("overlay.png"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Handler for GlmHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let annotation = lrit.headers.annotation.as_ref().ok_or(HandlerError::Skipped)?;
+        let goes = match annotation.parsed() {
+            LritFilename::GoesR(goes) => goes,
+            _ => return Err(HandlerError::Skipped),
+        };
+        if goes.instrument == "ABI" && goes.region() == Some("Full Disk") {
+            if let Some(grid) = Grid::from_lrit(lrit) {
+                self.abi_grid = Some(grid);
+            }
+            return Err(HandlerError::Skipped);
+        }
+        // segmented images are left to the ImageHandler
+        if goes.instrument != "GLM" || lrit.headers.img_segment.is_some() {
+            return Err(HandlerError::Skipped);
+        }
+
+        let dir = self.output_root.join("glm").join(&goes.product);
+        std::fs::create_dir_all(&dir)?;
+        let stem = annotation.text.split('.').next().unwrap_or_default();
+        if lrit.headers.primary.filetype_code == 0 {
+            self.write_image(lrit, &dir, stem)
+        } else {
+            self.storage.write(&dir.join(&annotation.text), &lrit.data)?;
+            info!("glm, {}", annotation.text);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GlmHandler, Grid};
+    use crate::{
+        handlers::Handler,
+        lrit::{ImageNavigationRecord, LRIT},
+        sim::LritBuilder,
+    };
+
+    fn image(annotation: &str, size: u16, scaling_factor: i32, data: &[u8]) -> LRIT {
+        let bytes = LritBuilder::new(0)
+            .image_structure(8, size, size)
+            .annotation(annotation)
+            .build(data);
+        let mut lrit = LRIT::from_bytes(1, &bytes).unwrap();
+        lrit.headers.img_navigation = Some(ImageNavigationRecord {
+            header_type: 2,
+            header_record_lenth: 51,
+            projection_name: "GEOS(-75.0)".to_string(),
+            column_scaling_factor: scaling_factor,
+            line_scaling_factor: scaling_factor,
+            column_offset: size as i32 / 2,
+            line_offset: size as i32 / 2,
+        });
+        lrit
+    }
+
+    #[test]
+    fn test_glm() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = GlmHandler::new(dir.path()).with_overlays();
+        // an ABI image with twice the resolution of the GLM image
+        let abi = image("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit", 8, 2000, &[0; 64]);
+        assert!(handler.handle(&abi).is_err());
+        assert_eq!(handler.abi_grid, Grid::from_lrit(&abi));
+
+        // flashes in column 3 of line 1
+        let mut density = vec![0; 16];
+        density[7] = 200;
+        let glm = image("OR_GLM-L2-GLMF-M6_G16_s20221241800000.lrit", 4, 1000, &density);
+        handler.handle(&glm).unwrap();
+        let out = dir.path().join("glm/GLMF");
+        assert!(out.join("OR_GLM-L2-GLMF-M6_G16_s20221241800000.jpg").exists());
+        let overlay = image::open(out.join("OR_GLM-L2-GLMF-M6_G16_s20221241800000.overlay.png"))
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(overlay.dimensions(), (8, 8));
+        // the GLM pixel covers 2x2 ABI pixels
+        for (c, l) in [(6, 2), (7, 2), (6, 3), (7, 3)] {
+            assert_eq!(overlay.get_pixel(c, l).0, [255, 220, 0, 200], "{} {}", c, l);
+        }
+        assert_eq!(overlay.get_pixel(5, 2).0[3], 0);
+
+        // other GLM products are kept as they are
+        let bytes = LritBuilder::new(2)
+            .annotation("OR_GLM-L2-LCFA_G16_s20221241800000.nc")
+            .build(b"CDF");
+        handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("glm/LCFA/OR_GLM-L2-LCFA_G16_s20221241800000.nc")).unwrap(),
+            b"CDF"
+        );
+    }
+}
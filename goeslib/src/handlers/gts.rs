@@ -0,0 +1,236 @@
+//! Handler for GTS ("Global Telecommunication System") messages
+//!
+//! GTS message files are identified by having a filetype_code of 1 in the primary header.
+//! (Source: 4_LRIT_Transmitter-specs.pdf Table 3: LRIT File Types)
+//!
+//! A single GTS file may contain several WMO bulletins, each one wrapped in the standard
+//! `SOH`/`ETX` control sequences.  This handler splits them apart and writes each bulletin
+//! separately, named after its abbreviated heading.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::{
+    emwin::{nws, wmo::AbbreviatedHeading},
+    index::ProductIndex,
+    lrit::LRIT,
+    storage::{self, SharedStorage},
+    writer::{write_file, WriteQueue},
+};
+
+use super::{text::update_latest_symlink, DuplicatePolicy, Handler, HandlerError};
+
+/// Start of heading
+const SOH: u8 = 0x01;
+/// End of text
+const ETX: u8 = 0x03;
+
+/// A single bulletin extracted from a GTS message
+#[derive(Debug)]
+pub struct GtsBulletin<'a> {
+    pub heading: AbbreviatedHeading,
+    /// The AWIPS product identifier (like `METPHL`), if the line after the heading contains one
+    pub awips_id: Option<String>,
+    /// The full text of the bulletin, without the SOH/ETX control characters
+    pub text: &'a [u8],
+}
+
+/// Splits a GTS payload into individual bulletins
+///
+/// Any chunk of data that doesn't contain a recognizable abbreviated heading is skipped
+pub fn split_bulletins(data: &[u8]) -> Vec<GtsBulletin<'_>> {
+    let mut bulletins = Vec::new();
+
+    for chunk in data.split(|b| *b == SOH) {
+        // everything after an ETX is padding (or garbage)
+        let chunk = match chunk.iter().position(|b| *b == ETX) {
+            Some(idx) => &chunk[..idx],
+            None => chunk,
+        };
+        let text = trim_ascii(chunk);
+        if text.is_empty() {
+            continue;
+        }
+
+        // The heading is usually the 2nd line (the first is a channel sequence number), but
+        // some bulletins omit the sequence number, so look at the first few lines
+        let s = String::from_utf8_lossy(text);
+        let mut lines = s.lines().map(|l| l.trim()).filter(|l| !l.is_empty());
+        let heading = lines.by_ref().take(3).find_map(AbbreviatedHeading::parse);
+
+        if let Some(heading) = heading {
+            let awips_id = lines
+                .next()
+                .filter(|l| l.len() >= 4 && l.len() <= 6 && l.chars().all(|c| c.is_ascii_alphanumeric()))
+                .filter(|l| nws::NWSProduct::from_str(&l[..3]).is_some())
+                .map(|l| l.to_string());
+            bulletins.push(GtsBulletin {
+                heading,
+                awips_id,
+                text,
+            });
+        } else {
+            warn!(
+                "Skipping GTS bulletin without an abbreviated heading ({} bytes)",
+                text.len()
+            );
+        }
+    }
+
+    bulletins
+}
+
+fn trim_ascii(mut data: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = data {
+        if first.is_ascii_whitespace() {
+            data = rest;
+        } else {
+            break;
+        }
+    }
+    while let [rest @ .., last] = data {
+        if last.is_ascii_whitespace() {
+            data = rest;
+        } else {
+            break;
+        }
+    }
+    data
+}
+
+pub struct GtsHandler {
+    output_root: PathBuf,
+    queue: Option<WriteQueue>,
+    storage: SharedStorage,
+    duplicates: DuplicatePolicy,
+    index: Option<ProductIndex>,
+}
+
+impl GtsHandler {
+    pub fn new(root: impl AsRef<Path>) -> GtsHandler {
+        GtsHandler {
+            output_root: root.as_ref().to_path_buf(),
+            queue: None,
+            storage: storage::local(),
+            duplicates: DuplicatePolicy::Overwrite,
+            index: None,
+        }
+    }
+
+    /// What to do when a bulletin's file already exists, like for a bulletin that's sent again
+    /// (it's overwritten by default)
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Record bulletins whose file already existed in the product index under `root`
+    pub fn with_index(mut self, root: impl AsRef<Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+
+    /// Write bulletins through a [`BatchWriter`](crate::writer::BatchWriter) instead of right away
+    pub fn with_writer(mut self, queue: WriteQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Write bulletins to `storage` instead of the local filesystem
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+}
+
+impl Handler for GtsHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 1 {
+            return Err(HandlerError::Skipped);
+        }
+
+        let bulletins = split_bulletins(&lrit.data);
+        if bulletins.is_empty() {
+            return Err(HandlerError::Parse("No bulletins found in GTS message"));
+        }
+
+        // a single message can contain several bulletins with the same heading, so keep track
+        // of names we've already used
+        let mut seen: HashMap<String, usize> = HashMap::new();
+
+        for bulletin in bulletins {
+            let mut name = bulletin.heading.filename();
+            let count = seen.entry(name.clone()).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                name = format!("{}-{}", name, count);
+            }
+
+            let output_path = self.output_root.join(&name).with_extension("txt");
+            let placed = self
+                .duplicates
+                .place(&*self.storage, self.index.as_ref(), lrit, output_path)?;
+            let output_path = match placed {
+                Some(path) => path,
+                None => continue,
+            };
+            write_file(self.queue.as_ref(), &*self.storage, &output_path, bulletin.text)?;
+
+            // Route through the same "latest" machinery as EMWIN text, preferring the AWIPS ID
+            // since that's what EMWIN legacy filenames are built from
+            let latest_name = match &bulletin.awips_id {
+                Some(id) => id.clone(),
+                None => format!("{}{}", bulletin.heading.ttaaii, bulletin.heading.cccc),
+            };
+            update_latest_symlink(&self.output_root, &latest_name, &output_path)?;
+
+            info!("Wrote {}", name);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_bulletins;
+
+    #[test]
+    fn test_split() {
+        let data = b"\x01\r\r\n123\r\r\nSAUS70 KWBC 041800\r\r\nMTRPHL\r\r\nKPHL 041754Z 27010KT 10SM CLR 20/05 A3001=\r\r\n\x03\
+                     \x01\r\r\n124\r\r\nFTUS41 KPHI 041720 AAA\r\r\nTAFPHL\r\r\nTAF AMD KPHL 041720Z\r\r\n\x03";
+        let bulletins = split_bulletins(data);
+        assert_eq!(bulletins.len(), 2);
+
+        assert_eq!(bulletins[0].heading.filename(), "SAUS70_KWBC_041800");
+        assert_eq!(bulletins[0].awips_id.as_deref(), Some("MTRPHL"));
+        assert!(bulletins[0].text.starts_with(b"123"));
+        assert!(bulletins[0].text.ends_with(b"A3001="));
+
+        assert_eq!(bulletins[1].heading.filename(), "FTUS41_KPHI_041720_AAA");
+        assert_eq!(bulletins[1].awips_id.as_deref(), Some("TAFPHL"));
+    }
+
+    #[test]
+    fn test_split_no_control_chars() {
+        let data = b"SXUS40 KWBC 041800\nRRSWBC\n:data\n";
+        let bulletins = split_bulletins(data);
+        assert_eq!(bulletins.len(), 1);
+        assert_eq!(bulletins[0].heading.ttaaii, "SXUS40");
+        assert_eq!(bulletins[0].awips_id.as_deref(), Some("RRSWBC"));
+    }
+
+    #[test]
+    fn test_skip_garbage() {
+        let data = b"\x01not a bulletin\x03\x01\r\r\nNOUS41 KWBC 041800\r\r\nPNSWSH\r\r\ntext\x03";
+        let bulletins = split_bulletins(data);
+        assert_eq!(bulletins.len(), 1);
+        assert_eq!(bulletins[0].heading.cccc, "KWBC");
+
+        // a corrupted heading, where the replacement character straddles the end of TTAA
+        assert!(split_bulletins(b"\x01\r\r\nSAU\xff KWBC 041800\r\r\ntext\x03").is_empty());
+    }
+}
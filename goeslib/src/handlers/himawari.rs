@@ -0,0 +1,278 @@
+//! Handler for Himawari imagery relayed over GOES HRIT
+//!
+//! Unlike GOES imagery, Himawari full disk images arrive as a series of separate (unsegmented)
+//! image files, one per horizontal tile.  The tile number is only found in the annotation, which
+//! looks like `IMG_DK01B13_202205041800_003.lrit` (channel B13, scene time 2022-05-04 18:00,
+//! tile 3).  This handler collects the tiles for each scene and stitches them together.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::{
+    annotation::{HimawariTileName, LritFilename},
+    events::{EventSender, ImageEvents},
+    lrit::LRIT,
+    storage::{self, SharedStorage},
+    xmp::ImageMetadata,
+};
+
+use super::{image::save_jpeg, Handler, HandlerError, HeaderPassthrough};
+
+struct Scene {
+    name: HimawariTileName,
+    tiles: BTreeMap<u16, LRIT>,
+}
+
+pub struct HimawariHandler {
+    output_root: PathBuf,
+    /// How many tiles make up a complete scene
+    tiles_per_scene: u16,
+    /// Scenes that are still being received, keyed by channel
+    scenes: BTreeMap<String, Scene>,
+    events: Option<ImageEvents>,
+    headers: HeaderPassthrough,
+    storage: SharedStorage,
+}
+
+impl HimawariHandler {
+    pub fn new(root: impl AsRef<Path>) -> HimawariHandler {
+        HimawariHandler {
+            output_root: root.as_ref().to_path_buf(),
+            tiles_per_scene: 10,
+            scenes: BTreeMap::new(),
+            events: None,
+            headers: HeaderPassthrough::None,
+            storage: storage::local(),
+        }
+    }
+
+    /// Send an [`ImageCompleteEvent`](crate::events::ImageCompleteEvent) every time a scene is written
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(ImageEvents::new(sender));
+        self
+    }
+
+    pub fn tiles_per_scene(mut self, tiles: u16) -> Self {
+        self.tiles_per_scene = tiles;
+        self
+    }
+
+    /// Keep the original LRIT headers (of the first received tile) with each scene
+    pub fn with_header_passthrough(mut self, mode: HeaderPassthrough) -> Self {
+        self.headers = mode;
+        self
+    }
+
+    /// Write scenes to `storage` instead of the local filesystem
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Stitches together all received tiles of a scene
+    ///
+    /// Missing tiles are left black.  All tiles are assumed to be the same size as the first one,
+    /// which can't be bigger than its data (so a bogus header can't make a huge image).
+    fn write_scene(&mut self, scene: Scene) -> Result<(), HandlerError> {
+        let first = match scene.tiles.values().next() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        let ihs = first
+            .headers
+            .img_strucutre
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        if ihs.bits_per_pixel != 8 {
+            return Err(HandlerError::Parse("unsupported bits per pixel"));
+        }
+
+        let width = ihs.num_columns as usize;
+        if width == 0 || width > first.data.len() {
+            warn!(
+                "{}: bogus tile size {}x{}",
+                scene.name.scene_name(),
+                ihs.num_columns,
+                ihs.num_lines
+            );
+            return Err(HandlerError::Parse("bogus image size"));
+        }
+        let tile_rows = (ihs.num_lines as usize).min(first.data.len().div_ceil(width));
+        let num_tiles = self.tiles_per_scene as usize;
+        let mut pixels = vec![0u8; width * tile_rows * num_tiles];
+
+        for (num, tile) in &scene.tiles {
+            let start = (*num as usize - 1) * width * tile_rows;
+            let len = tile.data.len().min(width * tile_rows);
+            pixels[start..start + len].copy_from_slice(&tile.data[..len]);
+        }
+
+        let img = image::GrayImage::from_raw(width as u32, (tile_rows * num_tiles) as u32, pixels)
+            .ok_or(HandlerError::Parse("failed to create image from tiles"))?;
+
+        let scene_name = scene.name.scene_name();
+        let mut meta = ImageMetadata::from_lrit(first);
+        meta.product = scene_name.clone();
+        let out_name = self.output_root.join(scene_name).with_extension("jpg");
+        info!(
+            "himawari ({} of {} tiles), {}",
+            scene.tiles.len(),
+            self.tiles_per_scene,
+            out_name.display()
+        );
+        save_jpeg(&*self.storage, &img, &out_name, &meta)?;
+        self.headers.write_sidecar(None, &*self.storage, first, &out_name)?;
+        if let Some(events) = &mut self.events {
+            events.image_complete(&meta, out_name, scene.tiles.len() as u16, self.tiles_per_scene);
+        }
+        Ok(())
+    }
+}
+
+impl Handler for HimawariHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 0 {
+            return Err(HandlerError::Skipped);
+        }
+        let name = match lrit.headers.annotation.as_ref().map(|a| a.parsed()) {
+            Some(LritFilename::Himawari(name)) => name,
+            _ => return Err(HandlerError::Skipped),
+        };
+        if name.tile == 0 || name.tile > self.tiles_per_scene {
+            warn!(
+                "Unexpected Himawari tile number {} for {}",
+                name.tile,
+                name.scene_name()
+            );
+            return Err(HandlerError::Parse("himawari tile number out of range"));
+        }
+
+        // a tile from a newer scene means the previous scene on this channel is done, even if some
+        // tiles never arrived.  This tile is kept even if writing that scene fails.
+        let mut result = Ok(());
+        if let Some(scene) = self.scenes.get(&name.channel) {
+            if scene.name.time != name.time {
+                let scene = self.scenes.remove(&name.channel).unwrap();
+                result = self.write_scene(scene);
+            }
+        }
+
+        let scene = self.scenes.entry(name.channel.clone()).or_insert_with(|| Scene {
+            name: name.clone(),
+            tiles: BTreeMap::new(),
+        });
+        scene.tiles.insert(name.tile, lrit.clone());
+
+        if scene.tiles.len() == self.tiles_per_scene as usize {
+            let scene = self.scenes.remove(&name.channel).unwrap();
+            let written = self.write_scene(scene);
+            if result.is_ok() {
+                result = written;
+            }
+        }
+
+        result
+    }
+
+    /// Writes every scene that is still missing some tiles
+    ///
+    /// If several scenes fail to be written, only the first error is returned.
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        let mut result = Ok(());
+        for scene in std::mem::take(&mut self.scenes).into_values() {
+            let written = self.write_scene(scene);
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::HimawariHandler;
+    use crate::{
+        annotation::HimawariTileName,
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+    };
+
+    #[test]
+    fn test_parse_name() {
+        let name = HimawariTileName::parse("IMG_DK01B13_202205041800_003.lrit").unwrap();
+        assert_eq!(name.channel, "DK01B13");
+        assert_eq!(name.time, "202205041800");
+        assert_eq!(name.tile, 3);
+        assert_eq!(name.scene_name(), "IMG_DK01B13_202205041800");
+
+        assert!(HimawariTileName::parse("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit").is_none());
+        assert!(HimawariTileName::parse("IMG_DK01B13_2022050418_003.lrit").is_none());
+        assert!(HimawariTileName::parse("IMG_DK01B13_202205041800.lrit").is_none());
+    }
+
+    /// Makes a Himawari tile out of the first segment of the test image
+    fn tile(time: &str, num: u16) -> LRIT {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/segmented/0.lrit");
+        let mut lrit = LRIT::from_bytes(13, &std::fs::read(path).unwrap()).unwrap();
+        lrit.headers.img_segment = None;
+        lrit.headers.text = None;
+        lrit.headers.annotation.as_mut().unwrap().text = format!("IMG_DK01B13_{}_{:03}.lrit", time, num);
+        lrit
+    }
+
+    #[test]
+    fn test_mosaic() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = HimawariHandler::new(dir.path()).tiles_per_scene(3);
+        let out = dir.path().join("IMG_DK01B13_202205041800.jpg");
+
+        handler.handle(&tile("202205041800", 2)).unwrap();
+        handler.handle(&tile("202205041800", 1)).unwrap();
+        assert!(!out.exists());
+        handler.handle(&tile("202205041800", 3)).unwrap();
+
+        let img = image::open(&out).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 36));
+
+        // an incomplete scene is written once the next scene starts
+        handler.handle(&tile("202205041810", 1)).unwrap();
+        handler.handle(&tile("202205041820", 1)).unwrap();
+        let img = image::open(dir.path().join("IMG_DK01B13_202205041810.jpg")).unwrap();
+        assert_eq!((img.width(), img.height()), (64, 36));
+    }
+
+    #[test]
+    fn test_bogus_tiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = HimawariHandler::new(dir.path()).tiles_per_scene(3);
+
+        // a tile claiming far more lines than its data has only makes an image as big as the data
+        let mut tall = tile("202205041800", 1);
+        let data_len = tall.data.len();
+        tall.headers.img_strucutre.as_mut().unwrap().num_lines = u16::MAX;
+        handler.handle(&tall).unwrap();
+        handler.flush().unwrap();
+        let img = image::open(dir.path().join("IMG_DK01B13_202205041800.jpg")).unwrap();
+        assert_eq!(img.width(), 64);
+        assert_eq!(img.height() as usize, data_len.div_ceil(64) * 3);
+
+        let mut wide = tile("202205041810", 1);
+        wide.headers.img_strucutre.as_mut().unwrap().num_columns = u16::MAX;
+        handler.handle(&wide).unwrap();
+
+        // the scene that can't be written is reported, but the tile that ended it is still kept
+        assert!(matches!(
+            handler.handle(&tile("202205041820", 1)),
+            Err(HandlerError::Parse("bogus image size"))
+        ));
+        assert!(!dir.path().join("IMG_DK01B13_202205041810.jpg").exists());
+        handler.flush().unwrap();
+        assert!(dir.path().join("IMG_DK01B13_202205041820.jpg").exists());
+    }
+}
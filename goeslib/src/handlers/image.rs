@@ -3,16 +3,49 @@
 //! Image products are identified by having a filetype_code of 0 in the primary header.
 //! (Source: 4_LRIT_Transmitter-specs.pdf Table 3: LRIT File Types)
 use std::{
-    collections::HashMap,
-    io::Write,
+    borrow::Cow,
+    ops::Deref,
     path::{Path, PathBuf},
 };
 
-use log::info;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
 
-use crate::lrit::LRIT;
+use crate::{
+    annotation::LritFilename,
+    events::{EventSender, ImageEvents},
+    index::{ImageTiming, IndexRecord, ProductIndex},
+    levels::{apply_levels, AutoLevels},
+    lrit::LRIT,
+    sector::Sector,
+    storage::{self, SharedStorage, Storage},
+    xmp::{embed_in_jpeg, ImageMetadata},
+};
+
+#[cfg(feature = "mmap")]
+use super::assembly::StreamingImage;
+use super::{
+    change::ChangeDetector, encode::encode_jpeg, glm::is_glm, storm::StormWatch, suvi::is_suvi, Handler, HandlerError,
+    HeaderPassthrough,
+};
 
-use super::{Handler, HandlerError};
+/// How many written images to remember, to recognize retransmissions
+const WRITTEN_IMAGES: usize = 32;
+
+/// The unstretched pixels of an image that was written with some of its segments missing
+struct PartialImage {
+    pixels: image::GrayImage,
+    /// The levels it was stretched to, if it was
+    levels: Option<(u8, u8)>,
+}
+
+/// Which levels to stretch an image to when it's written
+enum Levels {
+    /// Work them out from the image (if it's to be stretched at all)
+    Auto,
+    /// The ones it was written with before, when merging segments into it
+    Same(Option<(u8, u8)>),
+}
 
 pub struct ImageHandler {
     output_root: PathBuf,
@@ -24,6 +57,52 @@ pub struct ImageHandler {
     /// and so this cache will keep track of segments for the 3 most recent images (indexed by a
     /// u16 image identifier)
     segments: lru_cache::LruCache<u16, Vec<LRIT>>, //files: Vec<_>
+
+    /// Which segments were in each recently written segmented image, keyed by image id and
+    /// annotation (which has the scan time in it)
+    ///
+    /// Full disk images are sometimes retransmitted.  Segments that were already written are
+    /// skipped, and the rest are merged into the image that was written before.
+    written: lru_cache::LruCache<(u16, String), Vec<bool>>,
+
+    /// The most recently written images that are missing segments, keyed like `written`
+    ///
+    /// Retransmitted segments are merged into the original pixels, so the rest of the image isn't
+    /// stretched (or compressed) a second time.
+    partial: lru_cache::LruCache<(u16, String), PartialImage>,
+
+    /// When the first and last segments of each image being assembled arrived, keyed like
+    /// `written`
+    arrivals: lru_cache::LruCache<(u16, String), (DateTime<Utc>, DateTime<Utc>)>,
+
+    /// Where to record (and look up) which segments each written image has, so retransmissions
+    /// can still be merged after a restart, or after the image has fallen out of `written`
+    index: Option<ProductIndex>,
+
+    /// How many reduced-resolution copies to write for each segmented (full disk) image
+    pyramid_levels: u8,
+
+    events: Option<ImageEvents>,
+
+    headers: HeaderPassthrough,
+
+    timing_sidecar: bool,
+
+    change: Option<ChangeDetector>,
+
+    storm_watches: Vec<StormWatch>,
+
+    levels: Option<AutoLevels>,
+
+    storage: SharedStorage,
+
+    /// Where segmented images are assembled, with streaming assembly
+    #[cfg(feature = "mmap")]
+    assembly_dir: Option<PathBuf>,
+
+    /// Segmented images being assembled in memory-mapped files, keyed like `segments`
+    #[cfg(feature = "mmap")]
+    streaming: lru_cache::LruCache<u16, StreamingImage>,
 }
 
 impl ImageHandler {
@@ -31,8 +110,158 @@ impl ImageHandler {
         ImageHandler {
             output_root: root.as_ref().to_path_buf(),
             segments: lru_cache::LruCache::new(3),
+            written: lru_cache::LruCache::new(WRITTEN_IMAGES),
+            partial: lru_cache::LruCache::new(3),
+            arrivals: lru_cache::LruCache::new(WRITTEN_IMAGES),
+            index: None,
+            pyramid_levels: 0,
+            events: None,
+            headers: HeaderPassthrough::None,
+            timing_sidecar: false,
+            change: None,
+            storm_watches: Vec::new(),
+            levels: None,
+            storage: storage::local(),
+            #[cfg(feature = "mmap")]
+            assembly_dir: None,
+            #[cfg(feature = "mmap")]
+            streaming: lru_cache::LruCache::new(3),
         }
     }
+
+    /// Send an [`ImageCompleteEvent`](crate::events::ImageCompleteEvent) every time a segmented image is written
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(ImageEvents::new(sender));
+        self
+    }
+
+    /// Also write reduced resolution copies of each segmented (full disk) image
+    ///
+    /// Each level is half the size of the previous one, so 3 levels will write 1/2, 1/4, and 1/8
+    /// resolution images next to the full resolution image, named like `<name>.1-2.jpg`,
+    /// `<name>.1-4.jpg`, and `<name>.1-8.jpg`.  These are much cheaper to load for thumbnails and
+    /// dashboards than the full 5424x5424 image.
+    pub fn with_pyramid_levels(mut self, levels: u8) -> Self {
+        self.pyramid_levels = levels;
+        self
+    }
+
+    /// Record which segments each segmented image has in the product index under `root`
+    ///
+    /// When an image was written with missing segments, a later retransmission is merged into it
+    /// even if goesbox was restarted in between.
+    pub fn with_index(mut self, root: impl AsRef<Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+
+    /// Keep the original LRIT headers with each image
+    ///
+    /// Segmented images keep the headers of their first segment.
+    pub fn with_header_passthrough(mut self, mode: HeaderPassthrough) -> Self {
+        self.headers = mode;
+        self
+    }
+
+    /// Write the [`ImageTiming`] of each segmented image next to it, as `<name>.jpg.timing.json`
+    ///
+    /// The timing is always recorded in the product index (if there is one).
+    pub fn with_timing_sidecar(mut self) -> Self {
+        self.timing_sidecar = true;
+        self
+    }
+
+    /// Compare each segmented image with the one before it of the same band and region, see
+    /// [`ChangeDetector`]
+    ///
+    /// Big changes are sent as events, if there's an event sender.
+    pub fn with_change_detection(mut self, detector: ChangeDetector) -> Self {
+        self.change = Some(detector);
+        self
+    }
+
+    /// Watch an area for cold cloud tops in each segmented image, see [`StormWatch`]
+    ///
+    /// This can be called more than once, to watch several areas.  Cold cloud tops showing up
+    /// (and going away) are sent as events, if there's an event sender.
+    pub fn with_storm_watch(mut self, watch: StormWatch) -> Self {
+        self.storm_watches.push(watch);
+        self
+    }
+
+    /// Stretch the contrast of each image before it's written, see [`AutoLevels`]
+    ///
+    /// Change detection still sees the images as they were sent.
+    pub fn with_auto_levels(mut self, levels: AutoLevels) -> Self {
+        self.levels = Some(levels);
+        self
+    }
+
+    /// Write images to `storage` instead of the local filesystem
+    ///
+    /// Segmented images are still assembled in memory (or in local files, with streaming
+    /// assembly), and only written to the storage once they're done.
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Assemble segmented images in memory-mapped files in `dir`, instead of in memory
+    ///
+    /// Each segment is copied into place as soon as it arrives, rather than being held until the
+    /// whole image is there, which keeps peak memory down on boards with little RAM.  `dir` should
+    /// be on a real disk, not a tmpfs.  Retransmissions of images that were already written are
+    /// still merged in memory.
+    #[cfg(feature = "mmap")]
+    pub fn with_streaming_assembly(mut self, dir: impl AsRef<Path>) -> Self {
+        self.assembly_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// The most pixels a segmented image can have (a full disk image has about 29 million), so bogus
+/// headers can't have us allocating huge images
+pub(super) const MAX_IMAGE_PIXELS: usize = 64 << 20;
+
+/// Copies the pixels of a segment that starts at `start_line` into an image that's `width` pixels
+/// wide, returning how many bytes didn't fit (and were dropped)
+///
+/// Returns an error if the segment starts past the end of the image.
+pub(super) fn place_segment(
+    pixels: &mut [u8],
+    width: usize,
+    start_line: u16,
+    data: &[u8],
+) -> Result<usize, HandlerError> {
+    let start = (start_line as usize)
+        .checked_mul(width)
+        .filter(|start| *start < pixels.len())
+        .ok_or(HandlerError::Parse("image segment starts past the end of the image"))?;
+    let len = data.len().min(pixels.len() - start);
+    pixels[start..start + len].copy_from_slice(&data[..len]);
+    Ok(data.len() - len)
+}
+
+/// Halves the size of an image by averaging each 2x2 block of pixels
+///
+/// If the image has an odd width or height, the last row/column is averaged with only the pixels
+/// that exist.
+pub(crate) fn box_downsample<C: Deref<Target = [u8]>>(
+    img: &image::ImageBuffer<image::Luma<u8>, C>,
+) -> image::GrayImage {
+    let (width, height) = img.dimensions();
+    let (out_width, out_height) = (width.div_ceil(2), height.div_ceil(2));
+    image::GrayImage::from_fn(out_width, out_height, |x, y| {
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for sy in (y * 2)..(y * 2 + 2).min(height) {
+            for sx in (x * 2)..(x * 2 + 2).min(width) {
+                sum += img.get_pixel(sx, sy).0[0] as u32;
+                count += 1;
+            }
+        }
+        image::Luma([((sum + count / 2) / count) as u8])
+    })
 }
 
 impl Handler for ImageHandler {
@@ -42,179 +271,955 @@ impl Handler for ImageHandler {
         }
 
         // these headers are mandatory for image data:
-        let ihs = lrit.headers.img_strucutre.as_ref().expect("image structure header");
-        let annotation = lrit.headers.annotation.as_ref().expect("Annotation header");
+        let ihs = lrit
+            .headers
+            .img_strucutre
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        let annotation = lrit
+            .headers
+            .annotation
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("annotation"))?;
+
+        if let LritFilename::Himawari(_) = annotation.parsed() {
+            // these are stitched together by the HimawariHandler
+            return Err(HandlerError::Skipped);
+        }
 
         // images
         //info!("image Headers: {:?}", headers);
 
-        let segmented = if let Some(text) = &lrit.headers.text {
-            let mut map = HashMap::new();
-            for pair in text.text.split(';') {
-                let mut s = pair.splitn(2, '=');
-                let key = s.next().expect("splitn").trim().to_owned();
-                let val = s.next().expect("splitn").trim().to_owned();
-                map.insert(key, val);
-            }
-            match map.get("Segmented") {
-                Some(s) if s == "yes" => true,
-                _ => false,
-            }
-        } else {
-            false
-        };
+        let segmented = matches!(
+            lrit.headers.text.as_ref().and_then(|text| text.params().remove("Segmented")),
+            Some(s) if s == "yes"
+        );
 
         //info!("segmented: {}", segmented);
         if !segmented {
+            if is_suvi(&annotation.text) || is_glm(&annotation.text) {
+                // these are written by the SuviHandler and GlmHandler
+                return Err(HandlerError::Skipped);
+            }
             // write out image immeditally
             //info!("headers: {:?}", lrit.headers);
-            assert_eq!(ihs.bits_per_pixel, 8, "Found non grayscale image: {:?}", ihs);
+            if ihs.bits_per_pixel != 8 {
+                warn!("Found non grayscale image: {:?}", ihs);
+                return Err(HandlerError::Parse("unsupported bits per pixel"));
+            }
 
             if let Some(noaa) = &lrit.headers.noaa {
                 if noaa.noaa_compression == 5 {
                     // gif image can be written directly to disk
-                    let mut file =
-                        std::fs::File::create(self.output_root.join(&annotation.text).with_extension("gif"))?;
-                    file.write_all(&lrit.data)?;
+                    let out_name = self.output_root.join(&annotation.text).with_extension("gif");
+                    self.headers
+                        .write_raw(None, &*self.storage, lrit, &out_name, &lrit.data)?;
                     return Ok(());
                 }
             }
 
             // sometimes the data seems to be not quite long enough to contain the entire image, so
             // extend it if necessary
+            let pixels = ihs.num_columns as usize * ihs.num_lines as usize;
+            if pixels == 0 || pixels > MAX_IMAGE_PIXELS {
+                warn!(
+                    "{}: bogus image size {}x{}",
+                    annotation.text, ihs.num_columns, ihs.num_lines
+                );
+                return Err(HandlerError::Parse("bogus image size"));
+            }
             let mut data = lrit.data.clone();
-            data.resize(ihs.num_columns as usize * ihs.num_lines as usize, 0);
+            data.resize(pixels, 0);
             // save raw pixel data
+            let meta = ImageMetadata::from_lrit(lrit);
+            if let Some(stretch) = self.levels.as_ref().and_then(|l| l.stretch(&meta)) {
+                stretch.apply(&mut data);
+            }
             let img: image::GrayImage = image::GrayImage::from_raw(ihs.num_columns as u32, ihs.num_lines as u32, data)
-                .unwrap_or_else(|| {
-                    panic!("Failed to create img for {}:\n{:?}", &annotation.text, lrit.headers);
-                });
+                .ok_or(HandlerError::Parse("failed to create image"))?;
             let out_name = self.output_root.join(&annotation.text).with_extension("jpg");
             info!("{}", out_name.display());
 
-            img.save(out_name)?;
+            save_jpeg(&*self.storage, &img, &out_name, &meta)?;
+            self.headers.write_sidecar(None, &*self.storage, lrit, &out_name)?;
+            self.link_sector(lrit, &out_name)?;
 
             return Ok(());
         }
 
-        let seg = lrit.headers.img_segment.as_ref().expect("image segment header");
+        let seg = lrit
+            .headers
+            .img_segment
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+
+        // has this segment already been written, as part of an earlier transmission of the image?
+        let key = (seg.image_id, annotation.text.clone());
+        if !self.written.contains_key(&key) && !self.segments.contains_key(&seg.image_id) {
+            if let Some(received) = self.written_segments(&annotation.text) {
+                self.written.insert(key, received);
+            }
+        }
+        let (already_written, written_before) = match self.written.get_mut(&(seg.image_id, annotation.text.clone())) {
+            Some(received) => (
+                received.get(seg.segment_seq as usize).copied().unwrap_or(false),
+                received.iter().filter(|r| **r).count(),
+            ),
+            None => (false, 0),
+        };
+        if already_written {
+            debug!(
+                "{}: skipping retransmitted segment {}",
+                annotation.text, seg.segment_seq
+            );
+            return Ok(());
+        }
+        if written_before == 0 && self.stream_segment(lrit)? {
+            return Ok(());
+        }
 
         // have we seen segments with this image id before?
         if let Some(mut seg_vec) = self.segments.remove(&seg.image_id) {
+            let duplicate = seg_vec
+                .iter()
+                .any(|l| matches!(&l.headers.img_segment, Some(s) if s.segment_seq == seg.segment_seq));
+            if duplicate {
+                debug!("{}: skipping duplicate segment {}", annotation.text, seg.segment_seq);
+                self.segments.insert(seg.image_id, seg_vec);
+                return Ok(());
+            }
+            self.segment_arrived(seg.image_id, &annotation.text);
             seg_vec.push(lrit.clone());
 
-            if seg_vec.len() == seg.max_segment as usize {
+            if seg_vec.len() + written_before == seg.max_segment as usize {
                 self.write_image_from_segments(seg_vec)?;
             } else {
                 // put the list back in the LRU cache
                 self.segments.insert(seg.image_id, seg_vec);
             }
+        } else if written_before + 1 == seg.max_segment as usize {
+            // the last missing segment of an image that was written before
+            self.segment_arrived(seg.image_id, &annotation.text);
+            self.write_image_from_segments(vec![lrit.clone()])?;
         } else {
             // if adding this entry would evict an old entry... we don't really care
+            self.segment_arrived(seg.image_id, &annotation.text);
             self.segments.insert(seg.image_id, vec![lrit.clone()]);
         }
 
         Ok(())
     }
+
+    /// Writes every partially received image, with its missing segments left black
+    ///
+    /// If several images fail to be written, only the first error is returned.
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        let mut result = Ok(());
+        while let Some((_, segments)) = self.segments.remove_lru() {
+            let written = self.write_image_from_segments(segments);
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        #[cfg(feature = "mmap")]
+        while let Some((_, image)) = self.streaming.remove_lru() {
+            let written = self.write_assembled(&image.first, &image.image(), image.received.clone(), Levels::Auto);
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
 }
 
 impl ImageHandler {
-    fn write_image_from_segments(&self, mut segments: Vec<LRIT>) -> Result<(), HandlerError> {
-        if segments.len() == 0 {
-            return Ok(());
+    /// Notes when a segment of an image arrived
+    fn segment_arrived(&mut self, image_id: u16, annotation: &str) {
+        let now = Utc::now();
+        let key = (image_id, annotation.to_string());
+        match self.arrivals.get_mut(&key) {
+            Some((_, last)) => *last = now,
+            None => {
+                self.arrivals.insert(key, (now, now));
+            }
         }
+    }
+
+    /// Copies a segment into its image with streaming assembly, writing the image once it's
+    /// complete
+    ///
+    /// Returns false if streaming assembly is off.
+    #[cfg(feature = "mmap")]
+    fn stream_segment(&mut self, lrit: &LRIT) -> Result<bool, HandlerError> {
+        let dir = match &self.assembly_dir {
+            Some(dir) => dir,
+            None => return Ok(false),
+        };
+        let seg = lrit
+            .headers
+            .img_segment
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+        let annotation = lrit
+            .headers
+            .annotation
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("annotation"))?;
+        let mut image = match self.streaming.remove(&seg.image_id) {
+            Some(image) => image,
+            None => StreamingImage::create(dir, lrit)?,
+        };
+        if image.add(lrit)? {
+            self.segment_arrived(seg.image_id, &annotation.text);
+        } else {
+            debug!("{}: skipping duplicate segment {}", annotation.text, seg.segment_seq);
+        }
+        if image.is_complete() {
+            self.write_assembled(&image.first, &image.image(), image.received.clone(), Levels::Auto)?;
+        } else {
+            // if this evicts an old image, its file is deleted
+            self.streaming.insert(seg.image_id, image);
+        }
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn stream_segment(&mut self, _lrit: &LRIT) -> Result<bool, HandlerError> {
+        Ok(false)
+    }
+
+    /// Looks up which segments of an image were written before, if the image is still on disk
+    fn written_segments(&self, annotation: &str) -> Option<Vec<bool>> {
+        let index = self.index.as_ref()?;
+        if !self
+            .storage
+            .exists(&self.output_root.join(annotation).with_extension("jpg"))
+        {
+            return None;
+        }
+        match index.written_segments(annotation, Utc::now().date_naive()) {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("{}: failed to read the product index: {}", annotation, e);
+                None
+            }
+        }
+    }
+
+    /// Links a mesoscale image into `mesoscale/<sector>/`, so images of each place the sector has
+    /// been are kept together
+    fn link_sector(&self, lrit: &LRIT, out_name: &Path) -> Result<(), HandlerError> {
+        match Sector::from_lrit(lrit) {
+            Some(sector) => link_sector(&*self.storage, &self.output_root, &sector.key(), out_name),
+            None => Ok(()),
+        }
+    }
+
+    fn write_image_from_segments(&mut self, segments: Vec<LRIT>) -> Result<(), HandlerError> {
+        let first = match segments.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
 
-        // these 3 headers are required for image data, but might be missing nonetheless
         // general structure info will be the same in all LRIT files, so just take the first
-        let ihs = segments
-            .first()
-            .unwrap()
+        let ihs = first
             .headers
             .img_strucutre
             .as_ref()
-            .expect("img_structure header")
-            .clone();
-        assert_eq!(ihs.bits_per_pixel, 8, "Found non grayscale image: {:?}", ihs);
-        let seg = segments
-            .first()
-            .unwrap()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        if ihs.bits_per_pixel != 8 {
+            warn!("Found non grayscale image: {:?}", ihs);
+            return Err(HandlerError::Parse("unsupported bits per pixel"));
+        }
+        let seg = first
             .headers
             .img_segment
             .as_ref()
-            .expect("img_segment header")
-            .clone();
-        let ann = segments
-            .first()
-            .unwrap()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+        let ann = first
             .headers
             .annotation
             .as_ref()
-            .expect("annotation header")
-            .clone();
+            .ok_or(HandlerError::MissingHeader("annotation"))?;
 
-        let num_segments = segments.len();
+        let width = seg.max_column as usize;
+        if width == 0 {
+            return Err(HandlerError::Parse("image segment has zero max_column"));
+        }
+        if ihs.num_columns != seg.max_column {
+            warn!(
+                "{}: image structure has {} columns, but segment header says {}",
+                ann.text, ihs.num_columns, seg.max_column
+            );
+        }
 
-        //assert_eq!(ihs.num_lines * seg.max_segment, seg.max_column, "segment max_col doesn't match num_lines*max_segment");
-        assert!(
-            self.segments.len() <= seg.max_segment as usize,
-            "too many segments: {} <= {}",
-            self.segments.len(),
-            seg.max_segment
-        );
+        // Work out where each segment goes, and how big the image really is.  The headers aren't
+        // always consistent (especially for mesoscale images), so rather than trusting max_row,
+        // look at the data we actually have.
+        let out_name = self.output_root.join(&ann.text).with_extension("jpg");
+        let key = (seg.image_id, ann.text.clone());
+        let mut received = self
+            .written
+            .remove(&key)
+            .unwrap_or_else(|| vec![false; seg.max_segment as usize]);
+        // if some of this image was written before, start from that: its original pixels if they're
+        // still around, otherwise the saved image (which was already stretched, so the new segments
+        // are stretched on their own)
+        let mut levels = Levels::Auto;
+        let mut stretch_segments = None;
+        let partial = self.partial.remove(&key);
+        let base = if !received.iter().any(|r| *r) {
+            None
+        } else if let Some(partial) = partial.filter(|p| p.pixels.width() as usize == width) {
+            levels = Levels::Same(partial.levels);
+            Some(partial.pixels)
+        } else {
+            match self.storage.read(&out_name).map(|data| image::load_from_memory(&data)) {
+                Ok(Ok(img)) if img.width() as usize == width => {
+                    levels = Levels::Same(None);
+                    let meta = ImageMetadata::from_lrit(first);
+                    stretch_segments = self.levels.as_ref().and_then(|l| l.stretch(&meta));
+                    Some(img.to_luma8())
+                }
+                _ => {
+                    warn!("{}: can't merge with the image written before", ann.text);
+                    received.iter_mut().for_each(|r| *r = false);
+                    None
+                }
+            }
+        };
 
-        // list of segments, in order (with possible gaps)
-        let mut new_segments = Vec::with_capacity(seg.max_segment as usize);
-        new_segments.resize(seg.max_segment as usize, None);
+        // no real segment starts past both the claimed image height and what the segments could
+        // hold
+        let claimed_rows = seg.max_row as usize;
+        let plausible_rows = ihs.num_lines as usize * seg.max_segment as usize;
+        let max_rows = match plausible_rows.max(claimed_rows) {
+            0 => MAX_IMAGE_PIXELS / width,
+            rows => rows.min(MAX_IMAGE_PIXELS / width),
+        };
 
-        for lrit in segments.drain(..) {
-            let seg = lrit.headers.img_segment.as_ref().unwrap();
-            let id = seg.segment_seq;
-            //info!("{:?}", seg);
-            new_segments[id as usize] = Some(lrit);
+        let mut placements = Vec::with_capacity(segments.len());
+        let mut data_end = base.as_ref().map_or(0, |b| b.len());
+        for lrit in &segments {
+            let s = lrit
+                .headers
+                .img_segment
+                .as_ref()
+                .ok_or(HandlerError::MissingHeader("image segment"))?;
+            if s.segment_seq >= seg.max_segment {
+                warn!(
+                    "{}: segment {} of {} is out of range",
+                    ann.text, s.segment_seq, seg.max_segment
+                );
+                return Err(HandlerError::Parse("image segment sequence out of range"));
+            }
+            if s.start_line as usize >= max_rows {
+                warn!(
+                    "{}: segment {} starts at line {}, past the end of the image",
+                    ann.text, s.segment_seq, s.start_line
+                );
+                continue;
+            }
+            data_end = data_end.max(s.start_line as usize * width + lrit.data.len());
+            placements.push((s, lrit));
+        }
+        let data_rows = data_end.div_ceil(width);
+
+        // only use the claimed image height if all the data fits in it, and if it agrees with the
+        // claimed segment size (otherwise a bogus header could have us allocating a huge image)
+        let rows = if claimed_rows >= data_rows && claimed_rows <= plausible_rows.max(data_rows) {
+            claimed_rows
+        } else {
+            warn!(
+                "{}: segment header claims {} rows, but the data has {}",
+                ann.text, claimed_rows, data_rows
+            );
+            data_rows
+        };
+        let rows = rows.min(MAX_IMAGE_PIXELS / width);
+
+        let mut pixels = vec![0u8; rows * width];
+        if let Some(base) = base {
+            let base = base.into_raw();
+            let len = base.len().min(pixels.len());
+            pixels[..len].copy_from_slice(&base[..len]);
+        }
+        for (s, lrit) in placements {
+            let mut data = Cow::Borrowed(&lrit.data[..]);
+            if let Some(stretch) = stretch_segments {
+                stretch.apply(data.to_mut());
+            }
+            match place_segment(&mut pixels, width, s.start_line, &data) {
+                Ok(0) => {}
+                Ok(clipped) => warn!(
+                    "{}: segment {} doesn't fit in the image, dropping {} bytes",
+                    ann.text, s.segment_seq, clipped
+                ),
+                Err(e) => {
+                    warn!("{}: dropping segment {}: {}", ann.text, s.segment_seq, e);
+                    continue;
+                }
+            }
+            if let Some(r) = received.get_mut(s.segment_seq as usize) {
+                *r = true;
+            }
         }
 
-        let segments = new_segments;
+        let img = image::GrayImage::from_raw(width as u32, rows as u32, pixels)
+            .ok_or(HandlerError::Parse("failed to create image from segments"))?;
+        self.write_assembled(first, &img, received, levels)
+    }
 
-        let mut pixels: Vec<u8> = Vec::with_capacity(ihs.num_columns as usize * seg.max_row as usize);
-        pixels.resize(seg.max_row as usize * seg.max_column as usize, 0u8);
+    /// Writes a segmented image, once its segments have been put together
+    ///
+    /// `first` is the first segment that arrived, and `received` says which segments the image
+    /// has.
+    fn write_assembled<C: Deref<Target = [u8]>>(
+        &mut self,
+        first: &LRIT,
+        img: &image::ImageBuffer<image::Luma<u8>, C>,
+        received: Vec<bool>,
+        levels: Levels,
+    ) -> Result<(), HandlerError> {
+        let seg = first
+            .headers
+            .img_segment
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image segment"))?;
+        let ann = first
+            .headers
+            .annotation
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("annotation"))?;
+        let meta = ImageMetadata::from_lrit(first);
+        let out_name = self.output_root.join(&ann.text).with_extension("jpg");
 
-        for lrit in segments {
-            if let Some(lrit) = lrit {
-                let seg = lrit.headers.img_segment.as_ref().expect("img_segment header");
-                // let ihs = lrit.headers.img_strucutre.as_ref().expect("img_structure header");
+        let received_count = received.iter().filter(|r| **r).count();
+        info!(
+            "segmented ({} of {}), {}",
+            received_count,
+            seg.max_segment,
+            out_name.display()
+        );
+        // the stretched copy is what's written, but the change detector gets the original
+        let levels = match levels {
+            Levels::Auto => self
+                .levels
+                .as_ref()
+                .and_then(|l| l.stretch(&meta))
+                .and_then(|stretch| stretch.levels(img.as_raw())),
+            Levels::Same(levels) => levels,
+        };
+        let stretched = levels.map(|levels| {
+            let mut pixels = img.as_raw().to_vec();
+            apply_levels(levels, &mut pixels);
+            pixels
+        });
+        let written = image::ImageBuffer::<image::Luma<u8>, &[u8]>::from_raw(
+            img.width(),
+            img.height(),
+            stretched.as_deref().unwrap_or(img.as_raw()),
+        )
+        .ok_or(HandlerError::Parse("image is smaller than its dimensions"))?;
+        save_jpeg(&*self.storage, &written, &out_name, &meta)?;
+        self.headers.write_sidecar(None, &*self.storage, first, &out_name)?;
+        self.link_sector(first, &out_name)?;
 
-                let start = seg.max_column as usize * seg.start_line as usize;
-                //let end = start + (ihs.num_lines  as usize * seg.max_column as usize);
-                let end = start + lrit.data.len();
-                pixels[start..end].copy_from_slice(&lrit.data);
-                //pixels.extend(lrit.data);
-                //
-                //} else {
-                //pixels.extend(std::iter::repeat(0u8).take(ihs.num_columns as usize * ihs.num_lines as usize));
+        let completed = Utc::now();
+        let (first_segment, last_segment) = self
+            .arrivals
+            .remove(&(seg.image_id, ann.text.clone()))
+            .unwrap_or((completed, completed));
+        let timing = ImageTiming::from_lrit(first, first_segment, last_segment, completed);
+        if self.timing_sidecar {
+            write_timing_sidecar(&*self.storage, &out_name, &timing)?;
+        }
+        if let Some(events) = &mut self.events {
+            events.image_complete(&meta, out_name.clone(), received_count as u16, seg.max_segment);
+        }
+        if let Some(change) = &mut self.change {
+            // mesoscale sectors move around, so only compare images of the same place
+            let key = format!(
+                "{}/{}/{}",
+                meta.band.as_deref().unwrap_or_default(),
+                meta.region.as_deref().unwrap_or_default(),
+                Sector::from_lrit(first).map(|s| s.key()).unwrap_or_default()
+            );
+            if let Some(event) = change.compare(&*self.storage, key, &meta, img, &out_name)? {
+                if let Some(events) = &self.events {
+                    events.image_changed(event);
+                }
+            }
+        }
+        for watch in &mut self.storm_watches {
+            if let Some(event) = watch.check(first, &meta, img, &out_name)? {
+                if let Some(events) = &self.events {
+                    events.storm_watch(event);
+                }
             }
         }
+        if let Some(index) = &self.index {
+            if let Some(mut record) = IndexRecord::from_lrit(first, completed) {
+                record.segments = Some(received.iter().map(|r| if *r { '1' } else { '0' }).collect());
+                record.timing = Some(timing);
+                index.append(&record)?;
+            }
+        }
+        let key = (seg.image_id, ann.text.clone());
+        if received.iter().all(|r| *r) {
+            self.partial.remove(&key);
+        } else if let Some(pixels) = image::GrayImage::from_raw(img.width(), img.height(), img.as_raw().to_vec()) {
+            self.partial.insert(key.clone(), PartialImage { pixels, levels });
+        }
+        self.written.insert(key, received);
 
-        let pixlen = pixels.len();
-        match image::GrayImage::from_raw(ihs.num_columns as u32, seg.max_row as u32, pixels) {
-            Some(img) => {
-                let out_name = self.output_root.join(&ann.text).with_extension("jpg");
+        write_pyramid(&*self.storage, &written, &out_name, self.pyramid_levels, &meta)
+    }
+}
 
-                info!(
-                    "segmented ({} of {}), {}",
-                    num_segments,
-                    seg.max_segment,
-                    out_name.display()
-                );
-                img.save(out_name)?;
-            }
-            None => {
-                /*
-                ImageStructureRecord { header_type: 1, header_record_lenth: 9, bits_per_pixel: 8, num_columns: 5424, num_lines: 339, compression: 1 }
-                ImageSegmentIdentificationRecord { header_type: 128, header_record_lenth: 17, image_id: 58004, segment_seq: 1, start_col: 0, start_line: 339, max_segment: 16, max_column: 5424, max_row: 5424 }
-                                   */
-                info!("failed to create image, pixlen={} {:?} {:?}", pixlen, ihs, seg);
+/// Writes `levels` reduced resolution copies of the image at `out_name` (see
+/// [`ImageHandler::with_pyramid_levels`])
+pub(crate) fn write_pyramid<C: Deref<Target = [u8]>>(
+    storage: &dyn Storage,
+    img: &image::ImageBuffer<image::Luma<u8>, C>,
+    out_name: &Path,
+    levels: u8,
+    meta: &ImageMetadata,
+) -> Result<(), HandlerError> {
+    let mut factor = 1;
+    let mut reduced: Option<image::GrayImage> = None;
+    for _ in 0..levels {
+        factor *= 2;
+        let next = match &reduced {
+            Some(reduced) => box_downsample(reduced),
+            None => box_downsample(img),
+        };
+        save_jpeg(
+            storage,
+            &next,
+            &out_name.with_extension(format!("1-{}.jpg", factor)),
+            meta,
+        )?;
+        reduced = Some(next);
+    }
+    Ok(())
+}
+
+/// Links an image of a mesoscale sector (see [`Sector::key`]) into `mesoscale/<sector>/` under
+/// `root`
+pub(crate) fn link_sector(
+    storage: &dyn Storage,
+    root: &Path,
+    sector: &str,
+    out_name: &Path,
+) -> Result<(), HandlerError> {
+    let file_name = match out_name.file_name() {
+        Some(file_name) => file_name,
+        None => return Ok(()),
+    };
+    let dir = root.join("mesoscale").join(sector);
+    std::fs::create_dir_all(&dir)?;
+    // a merged retransmission is a new file, so this replaces the old link
+    storage.link(out_name, &dir.join(file_name))?;
+    Ok(())
+}
+
+/// Writes the timing of an image next to it, as `<name>.jpg.timing.json`
+pub(crate) fn write_timing_sidecar(
+    storage: &dyn Storage,
+    out_name: &Path,
+    timing: &ImageTiming,
+) -> Result<(), HandlerError> {
+    let mut sidecar = out_name.to_path_buf().into_os_string();
+    sidecar.push(".timing.json");
+    let json = serde_json::to_vec_pretty(timing).map_err(|e| HandlerError::Other(Box::new(e)))?;
+    storage.write(Path::new(&sidecar), &json)?;
+    Ok(())
+}
+
+/// Writes a JPEG with the product metadata embedded as XMP
+pub(super) fn save_jpeg<C: Deref<Target = [u8]>>(
+    storage: &dyn Storage,
+    img: &image::ImageBuffer<image::Luma<u8>, C>,
+    path: &Path,
+    meta: &ImageMetadata,
+) -> Result<(), HandlerError> {
+    let buf = encode_jpeg(img.as_raw().deref(), img.width(), img.height())?;
+    let buf = embed_in_jpeg(&buf, &meta.to_xmp()).unwrap_or(buf);
+    storage.write(path, &buf)?;
+    Ok(())
+}
+
+/// Writes a PNG
+pub(super) fn save_png(storage: &dyn Storage, img: &image::DynamicImage, path: &Path) -> Result<(), HandlerError> {
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageOutputFormat::Png)?;
+    storage.write(path, buf.get_ref())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::{box_downsample, place_segment, ImageHandler};
+    use crate::{
+        events::Event,
+        handlers::{Handler, HandlerError},
+        index::{ImageTiming, ProductIndex},
+        levels::{AutoLevels, Stretch},
+        lrit::{TimeStampRecord, LRIT},
+    };
+
+    const ANNOTATION: &str = "OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580";
+
+    fn testdata() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/segmented")
+    }
+
+    /// Loads one of the recorded LRIT segments (a 64x48 image, in 4 segments)
+    fn load_segment(seq: usize) -> LRIT {
+        let bytes = std::fs::read(testdata().join(format!("{}.lrit", seq))).unwrap();
+        LRIT::from_bytes(13, &bytes).unwrap()
+    }
+
+    /// Compares an output image against a golden image, allowing for some JPEG noise
+    fn assert_matches_golden(output: &Path, golden: &Path) {
+        let out = image::open(output).unwrap().to_luma8();
+        let golden = image::open(golden).unwrap().to_luma8();
+        assert_eq!(out.dimensions(), golden.dimensions());
+
+        let diffs: Vec<u32> = out
+            .pixels()
+            .zip(golden.pixels())
+            .map(|(a, b)| (a.0[0] as i32 - b.0[0] as i32).unsigned_abs())
+            .collect();
+        let max = *diffs.iter().max().unwrap();
+        let mean = diffs.iter().sum::<u32>() as f64 / diffs.len() as f64;
+        assert!(max <= 24 && mean <= 3.0, "max diff {}, mean diff {}", max, mean);
+    }
+
+    #[test]
+    fn test_segments_out_of_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path());
+        let out = dir.path().join(ANNOTATION).with_extension("jpg");
+
+        for seq in [2, 0, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+            assert!(!out.exists(), "image written before all segments arrived");
+        }
+        handler.handle(&load_segment(1)).unwrap();
+
+        assert_matches_golden(&out, &testdata().join("golden.png"));
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_streaming_assembly() {
+        let dir = tempfile::tempdir().unwrap();
+        let assembly = dir.path().join("assembly");
+        let mut handler = ImageHandler::new(dir.path())
+            .with_streaming_assembly(&assembly)
+            .with_pyramid_levels(1);
+        let out = dir.path().join(ANNOTATION).with_extension("jpg");
+
+        for seq in [2, 0, 2, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+            assert_eq!(std::fs::read_dir(&assembly).unwrap().count(), 1);
+        }
+        assert!(!out.exists(), "image written before all segments arrived");
+        handler.handle(&load_segment(1)).unwrap();
+
+        assert_matches_golden(&out, &testdata().join("golden.png"));
+        assert!(dir.path().join(ANNOTATION).with_extension("1-2.jpg").exists());
+        assert_eq!(std::fs::read_dir(&assembly).unwrap().count(), 0);
+
+        // a partial image is written (and its file removed) on flush
+        let mut handler = ImageHandler::new(dir.path()).with_streaming_assembly(&assembly);
+        let mut segment = load_segment(0);
+        segment.headers.img_segment.as_mut().unwrap().image_id = 4321;
+        segment.headers.annotation.as_mut().unwrap().text = "partial.lrit".to_string();
+        handler.handle(&segment).unwrap();
+        handler.flush().unwrap();
+        let partial = image::open(dir.path().join("partial.jpg")).unwrap();
+        assert_eq!((partial.width(), partial.height()), (64, 48));
+        assert_eq!(std::fs::read_dir(&assembly).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_interleaved_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path());
+
+        // a second image (with a different image id) arriving in the middle of the first
+        let mut other = load_segment(0);
+        other.headers.img_segment.as_mut().unwrap().image_id = 4321;
+        other.headers.annotation.as_mut().unwrap().text = "other.lrit".to_string();
+
+        handler.handle(&load_segment(0)).unwrap();
+        handler.handle(&load_segment(1)).unwrap();
+        handler.handle(&other).unwrap();
+        handler.handle(&load_segment(2)).unwrap();
+        handler.handle(&load_segment(3)).unwrap();
+
+        let out = dir.path().join(ANNOTATION).with_extension("jpg");
+        assert_matches_golden(&out, &testdata().join("golden.png"));
+        assert!(!dir.path().join("other.jpg").exists());
+    }
+
+    #[test]
+    fn test_image_complete_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = ImageHandler::new(dir.path()).with_events(s);
+
+        // an incomplete image doesn't produce an event
+        handler.handle(&load_segment(0)).unwrap();
+        assert!(r.try_recv().is_err());
+        for seq in 1..4 {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+
+        let event = match r.try_recv().unwrap() {
+            Event::ImageCompleted(event) => event,
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert_eq!(event.band.as_deref(), Some("C13"));
+        assert_eq!(event.region.as_deref(), Some("Full Disk"));
+        assert_eq!(event.path, dir.path().join(ANNOTATION).with_extension("jpg"));
+        assert_eq!(event.completeness(), 100.0);
+        assert_eq!(event.interval_seconds, None);
+    }
+
+    #[test]
+    fn test_bogus_max_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path());
+        for seq in 0..4 {
+            let mut lrit = load_segment(seq);
+            lrit.headers.img_segment.as_mut().unwrap().max_row = 60000;
+            handler.handle(&lrit).unwrap();
+        }
+
+        // the image should be sized based on the data, not the header
+        let out = dir.path().join(ANNOTATION).with_extension("jpg");
+        assert_matches_golden(&out, &testdata().join("golden.png"));
+    }
+
+    #[test]
+    fn test_unsegmented_bogus_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path());
+        let unsegmented = || {
+            let mut lrit = load_segment(0);
+            lrit.headers.text = None;
+            lrit
+        };
+
+        let mut lrit = unsegmented();
+        lrit.headers.img_strucutre.as_mut().unwrap().bits_per_pixel = 16;
+        assert!(matches!(handler.handle(&lrit), Err(HandlerError::Parse(_))));
+        let mut lrit = unsegmented();
+        lrit.headers.img_strucutre.as_mut().unwrap().num_lines = 0;
+        assert!(matches!(handler.handle(&lrit), Err(HandlerError::Parse(_))));
+        let mut lrit = unsegmented();
+        lrit.headers.annotation = None;
+        assert!(matches!(handler.handle(&lrit), Err(HandlerError::MissingHeader(_))));
+
+        handler.handle(&unsegmented()).unwrap();
+        assert!(dir.path().join(ANNOTATION).with_extension("jpg").exists());
+    }
+
+    #[test]
+    fn test_bad_segment_seq() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path());
+        for seq in 0..3 {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        let mut lrit = load_segment(3);
+        lrit.headers.img_segment.as_mut().unwrap().segment_seq = 7;
+        assert!(matches!(handler.handle(&lrit), Err(HandlerError::Parse(_))));
+    }
+
+    #[test]
+    fn test_adversarial_start_line() {
+        let mut pixels = vec![0u8; 4 * 3];
+        assert_eq!(place_segment(&mut pixels, 4, 1, &[1; 4]).unwrap(), 0);
+        // runs off the end of the image
+        assert_eq!(place_segment(&mut pixels, 4, 2, &[2; 10]).unwrap(), 6);
+        assert_eq!(pixels, [0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2]);
+        assert!(place_segment(&mut pixels, 4, 3, &[3; 4]).is_err());
+        assert!(place_segment(&mut pixels, usize::MAX, u16::MAX, &[3; 4]).is_err());
+
+        // a segment that claims to start far past the end of the image is dropped
+        let dir = tempfile::tempdir().unwrap();
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = ImageHandler::new(dir.path()).with_events(s);
+        for seq in 0..4 {
+            let mut lrit = load_segment(seq);
+            if seq == 3 {
+                lrit.headers.img_segment.as_mut().unwrap().start_line = 60000;
             }
+            handler.handle(&lrit).unwrap();
         }
-        Ok(())
+        handler.flush().unwrap();
+
+        let out = image::open(dir.path().join(ANNOTATION).with_extension("jpg")).unwrap();
+        assert_eq!((out.width(), out.height()), (64, 48));
+        assert!(matches!(r.try_recv().unwrap(), Event::ImageCompleted(e) if e.completeness() == 75.0));
+    }
+
+    #[test]
+    fn test_pyramid() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path()).with_pyramid_levels(2);
+        for seq in 0..4 {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+
+        let half = image::open(dir.path().join(ANNOTATION).with_extension("1-2.jpg")).unwrap();
+        assert_eq!((half.width(), half.height()), (32, 24));
+        let quarter = image::open(dir.path().join(ANNOTATION).with_extension("1-4.jpg")).unwrap();
+        assert_eq!((quarter.width(), quarter.height()), (16, 12));
+        assert!(!dir.path().join(ANNOTATION).with_extension("1-8.jpg").exists());
+    }
+
+    #[test]
+    fn test_flush_partial_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = ImageHandler::new(dir.path()).with_events(s);
+        for seq in [0, 1, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        handler.flush().unwrap();
+
+        let out = image::open(dir.path().join(ANNOTATION).with_extension("jpg")).unwrap();
+        assert_eq!((out.width(), out.height()), (64, 48));
+        assert!(matches!(r.try_recv().unwrap(), Event::ImageCompleted(e) if e.completeness() == 75.0));
+
+        // nothing is left to flush
+        handler.flush().unwrap();
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let dir = tempfile::tempdir().unwrap();
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = ImageHandler::new(dir.path()).with_events(s);
+        for seq in [0, 1, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        handler.flush().unwrap();
+        assert!(matches!(r.try_recv().unwrap(), Event::ImageCompleted(e) if e.completeness() == 75.0));
+
+        // segments that were already written are skipped, and the missing one fills the gap
+        handler.handle(&load_segment(0)).unwrap();
+        assert!(r.try_recv().is_err());
+        handler.handle(&load_segment(2)).unwrap();
+        assert!(matches!(r.try_recv().unwrap(), Event::ImageCompleted(e) if e.completeness() == 100.0));
+        // the segment was merged into the original pixels, not the saved JPEG
+        assert_matches_golden(
+            &dir.path().join(ANNOTATION).with_extension("jpg"),
+            &testdata().join("golden.png"),
+        );
+
+        // and a retransmission of the whole image is skipped
+        for seq in 0..4 {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        handler.flush().unwrap();
+        assert!(r.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_retransmission_keeps_written_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler =
+            ImageHandler::new(dir.path()).with_auto_levels(AutoLevels::new(Some(Stretch::new(2.0, 98.0))));
+        for seq in [0, 1, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        handler.flush().unwrap();
+        let out_name = dir.path().join(ANNOTATION).with_extension("jpg");
+        let before = image::open(&out_name).unwrap().to_luma8();
+
+        let missing = load_segment(2);
+        handler.handle(&missing).unwrap();
+        let after = image::open(&out_name).unwrap().to_luma8();
+
+        // rows in JPEG blocks that the new segment doesn't touch come out exactly the same
+        let seg = missing.headers.img_segment.as_ref().unwrap();
+        let start = seg.start_line as u32 / 8 * 8;
+        let end = (seg.start_line as u32 + missing.data.len() as u32 / 64).div_ceil(8) * 8;
+        assert!(start > 0 && end < 48);
+        for y in (0..start).chain(end..48) {
+            let row = |img: &image::GrayImage| (0..64).map(|x| img.get_pixel(x, y).0[0]).collect::<Vec<_>>();
+            assert_eq!(row(&before), row(&after), "row {}", y);
+        }
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn test_retransmission_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path()).with_index(dir.path());
+        for seq in [0, 1, 3] {
+            handler.handle(&load_segment(seq)).unwrap();
+        }
+        handler.flush().unwrap();
+
+        // a new handler finds out from the index which segments the saved image is missing
+        let (s, r) = std::sync::mpsc::channel();
+        let mut handler = ImageHandler::new(dir.path()).with_index(dir.path()).with_events(s);
+        handler.handle(&load_segment(1)).unwrap();
+        assert!(r.try_recv().is_err());
+        handler.handle(&load_segment(2)).unwrap();
+        assert!(matches!(r.try_recv().unwrap(), Event::ImageCompleted(e) if e.completeness() == 100.0));
+
+        let index = ProductIndex::new(dir.path());
+        let records = index.read_day(chrono::Utc::now().date_naive()).unwrap();
+        let masks: Vec<_> = records.iter().filter_map(|r| r.segments.as_deref()).collect();
+        assert_eq!(masks, ["1101", "1111"]);
+    }
+
+    #[test]
+    fn test_timing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ImageHandler::new(dir.path())
+            .with_index(dir.path())
+            .with_timing_sidecar();
+        for seq in [1, 0, 3, 2] {
+            let mut lrit = load_segment(seq);
+            // 2022-05-04 18:09:58
+            lrit.headers.timestamp = Some(TimeStampRecord {
+                header_type: 5,
+                header_record_lenth: 10,
+                time: [0x40, 91, 203, 3, 229, 228, 240],
+            });
+            handler.handle(&lrit).unwrap();
+        }
+
+        let sidecar = dir.path().join(ANNOTATION).with_extension("jpg.timing.json");
+        let timing: ImageTiming = serde_json::from_slice(&std::fs::read(sidecar).unwrap()).unwrap();
+        assert_eq!(timing.scan_start.unwrap().to_rfc3339(), "2022-05-04T18:00:20.500+00:00");
+        assert_eq!(timing.header_time.unwrap().to_rfc3339(), "2022-05-04T18:09:58+00:00");
+        assert!(timing.first_segment <= timing.last_segment && timing.last_segment <= timing.completed);
+        assert!(timing.latency().unwrap() > chrono::Duration::days(1));
+
+        let index = ProductIndex::new(dir.path());
+        let records = index.read_day(chrono::Utc::now().date_naive()).unwrap();
+        assert_eq!(records[0].timing.as_ref(), Some(&timing));
+    }
+
+    #[test]
+    fn test_box_downsample() {
+        let img = image::GrayImage::from_raw(3, 3, vec![0, 10, 100, 20, 30, 200, 7, 9, 50]).unwrap();
+        let half = box_downsample(&img);
+        assert_eq!(half.dimensions(), (2, 2));
+        assert_eq!(half.into_raw(), vec![15, 150, 8, 50]);
     }
 }
@@ -0,0 +1,101 @@
+use chrono::Utc;
+
+use crate::{
+    emwin::metar::Report,
+    influx::{InfluxSender, Point},
+    lrit::LRIT,
+};
+
+use super::{metar::decode_reports, shef::decode_values, Handler, HandlerError};
+
+/// Sends decoded observations to an [`InfluxWriter`](crate::influx::InfluxWriter)
+///
+/// Every METAR becomes a `metar` point, and every SHEF value (from `RR`/`HYD` products and DCS
+/// messages) becomes a `shef` point, see [`Point`].  TAFs are forecasts, so they're left out.
+pub struct InfluxHandler {
+    sender: InfluxSender,
+}
+
+impl InfluxHandler {
+    pub fn new(sender: InfluxSender) -> InfluxHandler {
+        InfluxHandler { sender }
+    }
+}
+
+impl Handler for InfluxHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let mut points = Vec::new();
+        match decode_reports(lrit) {
+            Ok(reports) => {
+                for (report, time) in reports {
+                    if let Report::Metar(metar) = report {
+                        points.push(Point::from_metar(&metar, time.unwrap_or_else(Utc::now)));
+                    }
+                }
+            }
+            Err(HandlerError::Skipped) => {}
+            Err(e) => return Err(e),
+        }
+        match decode_values(lrit) {
+            Ok(values) => points.extend(values.iter().filter_map(Point::from_shef)),
+            Err(HandlerError::Skipped) => {}
+            Err(e) => return Err(e),
+        }
+        if points.is_empty() {
+            return Err(HandlerError::Skipped);
+        }
+        points.into_iter().for_each(|point| self.sender.send(point));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::InfluxHandler;
+    use crate::{
+        handlers::Handler,
+        influx::{InfluxOutput, InfluxWriter},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    fn test_influx_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.txt");
+        let (sender, thread) = InfluxWriter::new(InfluxOutput::File(path.clone()))
+            .spawn(Duration::from_secs(3600))
+            .unwrap();
+        let mut handler = InfluxHandler::new(sender);
+
+        let text = |name: &str, data: &str| {
+            LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+        };
+        handler
+            .handle(&text(
+                "A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT",
+                "SAUS70 KWBC 071200\nMETAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992=\n",
+            ))
+            .unwrap();
+        handler
+            .handle(&text(
+                "A_SRUS53KDMX071230_C_KWIN_20220507123013_106868-3-RR3DMXIA.TXT",
+                "SRUS53 KDMX 071230\nRR3DMX\n.A DESI4 0507 Z DH12/HG 5.23/PPH M\n",
+            ))
+            .unwrap();
+        drop(handler);
+        thread.join().unwrap();
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("metar,station=KBOS temperature=18,dew_point=5,wind_direction=270i,"));
+        // the missing value is left out
+        assert_eq!(
+            lines[1],
+            "shef,location=DESI4,parameter=HG,units=english value=5.23,revised=false 1651924800000000000"
+        );
+    }
+}
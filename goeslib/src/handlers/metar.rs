@@ -0,0 +1,326 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    annotation::LritFilename,
+    emwin::metar::{parse_bulletin, Altimeter, Metar, Report, Visibility},
+    lrit::LRIT,
+};
+
+use super::{text_files, update_latest_symlink, Handler, HandlerError};
+
+/// How decoded observations are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObservationFormat {
+    Csv,
+    Json,
+}
+
+/// A line of JSON output
+#[derive(Serialize)]
+struct ReportLine<'a> {
+    /// The full observation (or issue) time, if it could be worked out
+    time: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    report: &'a Report,
+}
+
+/// Decodes METAR and TAF bulletins, and appends the reports to files in the output root
+///
+/// As CSV, there's a line per METAR in `metar.csv` (TAFs don't fit in a table, so they're left
+/// out).  As JSON, there's an object per line in `metar.jsonl` and `taf.jsonl`.
+///
+/// With [`with_station_files`](MetarHandler::with_station_files), the reports for each station are
+/// also kept in `stations/<station>/`, in a file per day (like `metar-2022-05-07.txt`) and a file
+/// with just the latest report (like `metar-latest.txt`), which a `latest-<station>-METAR` (or
+/// `-TAF`) symlink in the output root points at.
+pub struct MetarHandler {
+    output_root: PathBuf,
+    format: ObservationFormat,
+    /// Only keep reports from these stations (all stations if `None`)
+    stations: Option<HashSet<String>>,
+    station_files: bool,
+    /// The time of the latest report in each station's latest file, by station and kind
+    latest: HashMap<(String, &'static str), DateTime<Utc>>,
+}
+
+impl MetarHandler {
+    pub fn new(root: impl AsRef<Path>) -> MetarHandler {
+        MetarHandler {
+            output_root: root.as_ref().to_path_buf(),
+            format: ObservationFormat::Json,
+            stations: None,
+            station_files: false,
+            latest: HashMap::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: ObservationFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Only keep reports from these stations, like `KBOS`
+    pub fn with_stations(mut self, stations: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.stations = Some(stations.into_iter().map(|s| s.into().to_ascii_uppercase()).collect());
+        self
+    }
+
+    /// Also keep the reports of each station in their own files
+    ///
+    /// This is meant to be used along with [`with_stations`](MetarHandler::with_stations), since
+    /// otherwise every station gets a directory.
+    pub fn with_station_files(mut self) -> Self {
+        self.station_files = true;
+        self
+    }
+
+    fn write_report(&self, report: &Report, time: Option<DateTime<Utc>>) -> Result<(), HandlerError> {
+        let root = &self.output_root;
+        match (self.format, report) {
+            (ObservationFormat::Csv, Report::Metar(metar)) => {
+                append_line(&root.join("metar.csv"), Some(CSV_HEADER), &csv_line(metar, time))
+            }
+            (ObservationFormat::Csv, Report::Taf(_)) => Ok(()),
+            (ObservationFormat::Json, report) => {
+                let name = match report {
+                    Report::Metar(_) => "metar.jsonl",
+                    Report::Taf(_) => "taf.jsonl",
+                };
+                let line = serde_json::to_string(&ReportLine { time, report })
+                    .map_err(|e| HandlerError::Other(Box::new(e)))?;
+                append_line(&root.join(name), None, &line)
+            }
+        }
+    }
+
+    fn write_station_files(&mut self, report: &Report, time: Option<DateTime<Utc>>) -> Result<(), HandlerError> {
+        let (kind, raw) = match report {
+            Report::Metar(m) => ("metar", &m.raw),
+            Report::Taf(t) => ("taf", &t.raw),
+        };
+        // station names are checked by the parser, so they're safe to use in paths
+        let station = report.station();
+        let dir = self.output_root.join("stations").join(station);
+        std::fs::create_dir_all(&dir)?;
+        let time = time.unwrap_or_else(Utc::now);
+        append_line(
+            &dir.join(format!("{}-{}.txt", kind, time.format("%Y-%m-%d"))),
+            None,
+            raw,
+        )?;
+
+        // reports can arrive out of order, so don't replace a newer report with an older one
+        let latest = self.latest.entry((station.to_string(), kind)).or_insert(time);
+        if *latest > time {
+            return Ok(());
+        }
+        *latest = time;
+        let latest_path = dir.join(format!("{}-latest.txt", kind));
+        std::fs::write(&latest_path, format!("{}\n", raw))?;
+        let link = format!("{}-{}", station, kind.to_ascii_uppercase());
+        if !self.output_root.join(format!("latest-{}", link)).exists() {
+            update_latest_symlink(&self.output_root, &link, &latest_path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Appends a line to a file, starting the file with `header` if it's new
+fn append_line(path: &Path, header: Option<&str>, line: &str) -> Result<(), HandlerError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if let Some(header) = header {
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", header)?;
+        }
+    }
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+/// Returns true if the WMO heading of an EMWIN product (like `A_SAUS70KWBC...`) is for METARs,
+/// SPECIs, or TAFs
+pub(crate) fn is_surface_bulletin(annotation: &str) -> bool {
+    matches!(annotation.get(2..4), Some("SA" | "SP" | "FT" | "FC"))
+}
+
+/// A decoded report, with its full time (if it could be worked out)
+pub(super) type TimedReport = (Report, Option<DateTime<Utc>>);
+
+/// Decodes the reports in a METAR or TAF bulletin
+///
+/// Returns [`HandlerError::Skipped`] for anything else.
+pub(super) fn decode_reports(lrit: &LRIT) -> Result<Vec<TimedReport>, HandlerError> {
+    if lrit.headers.primary.filetype_code != 2 {
+        return Err(HandlerError::Skipped);
+    }
+    let annotation = match &lrit.headers.annotation {
+        Some(ann) => &ann.text,
+        None => return Err(HandlerError::MissingHeader("annotation")),
+    };
+    if !is_surface_bulletin(annotation) {
+        return Err(HandlerError::Skipped);
+    }
+
+    let mut decoded = Vec::new();
+    for (filename, data) in text_files(lrit, annotation)? {
+        let issued = match LritFilename::parse(&filename) {
+            LritFilename::Emwin(emwin) => Some(emwin.date),
+            _ => None,
+        };
+        let reports = parse_bulletin(&String::from_utf8_lossy(&data));
+        debug!("{} reports in {}", reports.len(), filename);
+        for report in reports {
+            let time = match &report {
+                Report::Metar(m) => issued.and_then(|issued| m.time.resolve(issued)),
+                Report::Taf(t) => issued.and_then(|issued| t.issued.resolve(issued)),
+            };
+            decoded.push((report, time));
+        }
+    }
+    Ok(decoded)
+}
+
+impl Handler for MetarHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        for (report, time) in decode_reports(lrit)? {
+            if matches!(&self.stations, Some(stations) if !stations.contains(report.station())) {
+                continue;
+            }
+            self.write_report(&report, time)?;
+            if self.station_files {
+                self.write_station_files(&report, time)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const CSV_HEADER: &str = "time,station,kind,wind_direction,wind_speed,wind_gust,wind_unit,visibility,\
+                          weather,clouds,temperature_c,dew_point_c,altimeter,raw";
+
+fn csv_line(metar: &Metar, time: Option<DateTime<Utc>>) -> String {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    let wind = metar.conditions.wind.as_ref();
+    let visibility = metar.conditions.visibility.map(|v| match v {
+        Visibility::Meters(m) => format!("{} m", m),
+        Visibility::StatuteMiles(sm) => format!("{} SM", sm),
+        Visibility::LessThanMiles(sm) => format!("<{} SM", sm),
+        Visibility::MoreThanMiles(sm) => format!(">{} SM", sm),
+        Visibility::Cavok => "CAVOK".to_string(),
+    });
+    let clouds = metar
+        .conditions
+        .clouds
+        .iter()
+        .map(|c| format!("{}{}", c.cover, c.height_ft.map(|h| h.to_string()).unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let altimeter = metar.altimeter.map(|a| match a {
+        Altimeter::InchesOfMercury(inhg) => format!("{:.2} inHg", inhg),
+        Altimeter::Hectopascals(hpa) => format!("{} hPa", hpa),
+    });
+    [
+        opt(time.map(|t| t.to_rfc3339())),
+        metar.station.clone(),
+        format!("{:?}", metar.kind),
+        opt(wind.and_then(|w| w.direction).map(|d| d.to_string())),
+        opt(wind.map(|w| w.speed.to_string())),
+        opt(wind.and_then(|w| w.gust).map(|g| g.to_string())),
+        opt(wind.map(|w| format!("{:?}", w.unit))),
+        opt(visibility),
+        metar.conditions.weather.join(" "),
+        clouds,
+        opt(metar.temperature.map(|t| t.to_string())),
+        opt(metar.dew_point.map(|t| t.to_string())),
+        opt(altimeter),
+        format!("\"{}\"", metar.raw.replace('"', "\"\"")),
+    ]
+    .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MetarHandler, ObservationFormat};
+    use crate::{
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    fn test_metar_handler() {
+        let bulletin = "SAUS70 KWBC 071200\r\r\nMETAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992=\r\r\n\
+                        KJFK 071151Z 18005KT 10SM SCT250 20/10 A3001=\r\r\n";
+        let name = "A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT";
+        let lrit = LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(bulletin.as_bytes())).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut handler = MetarHandler::new(dir.path())
+            .with_format(ObservationFormat::Csv)
+            .with_stations(["kbos"]);
+        handler.handle(&lrit).unwrap();
+        handler.handle(&lrit).unwrap();
+        let csv = std::fs::read_to_string(dir.path().join("metar.csv")).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("time,station"));
+        assert_eq!(
+            lines[1],
+            "2022-05-07T11:54:00+00:00,KBOS,Metar,270,10,,Knots,10 SM,,FEW5000,18,5,29.92 inHg,\
+             \"METAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992\""
+        );
+
+        let mut handler = MetarHandler::new(dir.path());
+        handler.handle(&lrit).unwrap();
+        let json = std::fs::read_to_string(dir.path().join("metar.jsonl")).unwrap();
+        let first: serde_json::Value = serde_json::from_str(json.lines().next().unwrap()).unwrap();
+        assert_eq!(first["Metar"]["station"], "KBOS");
+        assert_eq!(first["Metar"]["temperature"], 18);
+        assert_eq!(json.lines().count(), 2);
+
+        let other = "A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT";
+        let lrit = LRIT::from_bytes(20, &LritBuilder::new(2).annotation(other).build(b"text")).unwrap();
+        assert!(matches!(handler.handle(&lrit), Err(HandlerError::Skipped)));
+    }
+
+    #[test]
+    fn test_station_files() {
+        let bulletin = |time: &str| {
+            format!(
+                "SAUS70 KWBC 071200\nMETAR KBOS {}Z 27010KT 10SM FEW050 18/05 A2992=\nKJFK 071151Z 18005KT 10SM=\n",
+                time
+            )
+        };
+        let name = "A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT";
+        let lrit = |time: &str| {
+            LRIT::from_bytes(
+                20,
+                &LritBuilder::new(2).annotation(name).build(bulletin(time).as_bytes()),
+            )
+            .unwrap()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = MetarHandler::new(dir.path())
+            .with_stations(["KBOS"])
+            .with_station_files();
+        handler.handle(&lrit("071154")).unwrap();
+        // an older report goes in the daily file, but isn't the latest
+        handler.handle(&lrit("071054")).unwrap();
+
+        let station = dir.path().join("stations/KBOS");
+        let daily = std::fs::read_to_string(station.join("metar-2022-05-07.txt")).unwrap();
+        assert_eq!(daily.lines().count(), 2);
+        let latest = std::fs::read_to_string(dir.path().join("latest-KBOS-METAR")).unwrap();
+        assert!(latest.starts_with("METAR KBOS 071154Z"));
+        assert!(!dir.path().join("stations/KJFK").exists());
+    }
+}
@@ -1,16 +1,90 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
 
-use crate::lrit::LRIT;
+use chrono::Utc;
+use log::info;
+use serde::{Deserialize, Serialize};
 
+use crate::{
+    index::{IndexRecord, ProductIndex},
+    lrit::LRIT,
+    storage::Storage,
+    writer::{write_file, WriteQueue},
+};
+
+mod admin;
+#[cfg(feature = "mmap")]
+mod assembly;
+mod board;
+#[cfg(feature = "image")]
+mod change;
+mod collision;
+mod coverage;
 mod dcs;
 mod debug;
+mod dispatch;
+mod drift;
+#[cfg(feature = "image")]
+mod encode;
+#[cfg(feature = "image")]
+mod glm;
+mod gts;
+#[cfg(feature = "image")]
+mod himawari;
+#[cfg(feature = "image")]
 mod image;
+mod influx;
+mod metar;
+mod notify;
+mod queue;
+mod rate;
+mod raw;
+mod sandbox;
+mod shef;
+mod sounding;
+#[cfg(feature = "image")]
+mod storm;
+#[cfg(feature = "image")]
+mod suvi;
+mod swpc;
 mod text;
+mod verify;
 
+pub use self::admin::*;
+pub use self::board::*;
+#[cfg(feature = "image")]
+pub use self::change::*;
+pub use self::collision::*;
+pub use self::coverage::*;
 pub use self::dcs::*;
 pub use self::debug::*;
+pub use self::dispatch::*;
+pub use self::drift::*;
+#[cfg(feature = "image")]
+pub use self::glm::*;
+pub use self::gts::*;
+#[cfg(feature = "image")]
+pub use self::himawari::*;
+#[cfg(feature = "image")]
 pub use self::image::*;
+pub use self::influx::*;
+pub use self::metar::*;
+pub use self::notify::*;
+pub use self::queue::*;
+pub use self::rate::*;
+pub use self::raw::*;
+pub use self::sandbox::*;
+pub use self::shef::*;
+pub use self::sounding::*;
+#[cfg(feature = "image")]
+pub use self::storm::*;
+#[cfg(feature = "image")]
+pub use self::suvi::*;
+pub use self::swpc::*;
 pub use self::text::*;
+pub use self::verify::*;
 
 #[derive(Debug)]
 pub enum HandlerError {
@@ -21,6 +95,7 @@ pub enum HandlerError {
     /// Some IO error (generally from writing data to disk)
     Io(std::io::Error),
     /// A ZIP error
+    #[cfg(feature = "zip")]
     Zip(zip::result::ZipError),
     /// A handler is missing a header
     ///
@@ -30,21 +105,75 @@ pub enum HandlerError {
     /// Some parsing error
     Parse(&'static str),
 
-    Other(Box<dyn Error>),
+    /// The handler panicked, or went over its time or memory budget (see [`Sandboxed`])
+    Crashed(String),
+
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl HandlerError {
+    /// Returns true if this error might go away if the handler is retried
+    ///
+    /// This is mostly for IO errors that can happen when writing to flaky storage (like NFS).
+    /// Parse errors and missing headers are permanent -- retrying won't change the data.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            HandlerError::Io(e) => is_transient_io(e),
+            #[cfg(feature = "zip")]
+            HandlerError::Zip(zip::result::ZipError::Io(e)) => is_transient_io(e),
+            _ => false,
+        }
+    }
+}
+
+fn is_transient_io(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    /// "Stale file handle", which NFS clients return after a server hiccup
+    const ESTALE: i32 = 116;
+
+    matches!(
+        e.kind(),
+        ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+    ) || e.raw_os_error() == Some(ESTALE)
+}
+
+impl std::fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandlerError::Skipped => write!(f, "skipped"),
+            HandlerError::Io(e) => write!(f, "IO error: {}", e),
+            #[cfg(feature = "zip")]
+            HandlerError::Zip(e) => write!(f, "ZIP error: {}", e),
+            HandlerError::MissingHeader(h) => write!(f, "missing {} header", h),
+            HandlerError::Parse(msg) => write!(f, "parse error: {}", msg),
+            HandlerError::Crashed(msg) => write!(f, "crashed: {}", msg),
+            HandlerError::Other(e) => write!(f, "{}", e),
+        }
+    }
 }
 
+impl Error for HandlerError {}
+
 impl From<std::io::Error> for HandlerError {
     fn from(io: std::io::Error) -> Self {
         Self::Io(io)
     }
 }
 
+#[cfg(feature = "zip")]
 impl From<zip::result::ZipError> for HandlerError {
     fn from(zip: zip::result::ZipError) -> Self {
         Self::Zip(zip)
     }
 }
 
+#[cfg(feature = "image")]
 impl From<::image::ImageError> for HandlerError {
     fn from(e: ::image::ImageError) -> Self {
         match e {
@@ -54,6 +183,220 @@ impl From<::image::ImageError> for HandlerError {
     }
 }
 
-pub trait Handler {
+/// How the original LRIT headers are kept with a stored product
+///
+/// goesbox only keeps the headers it needs, which loses metadata that other LRIT tools might want.
+/// Any mode other than `None` keeps all of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderPassthrough {
+    #[default]
+    None,
+    /// Put the raw header block in front of products that are stored byte-for-byte (like text
+    /// products).  Products that are converted (like images) get a `.hdr` file instead.
+    Prepend,
+    /// Write the raw header block to a `.hdr` file next to the product
+    Sidecar,
+    /// Write the parsed headers to a `.hdr.json` file next to the product
+    Json,
+}
+
+impl HeaderPassthrough {
+    /// Writes a product that is stored byte-for-byte, along with its headers
+    ///
+    /// Writes go through `queue` if there is one, or straight to `storage` if not.
+    pub(crate) fn write_raw(
+        self,
+        queue: Option<&WriteQueue>,
+        storage: &dyn Storage,
+        lrit: &LRIT,
+        path: &Path,
+        data: &[u8],
+    ) -> Result<(), HandlerError> {
+        if self == HeaderPassthrough::Prepend {
+            let mut bytes = Vec::with_capacity(lrit.raw_headers.len() + data.len());
+            bytes.extend_from_slice(&lrit.raw_headers);
+            bytes.extend_from_slice(data);
+            write_file(queue, storage, path, &bytes)?;
+            return Ok(());
+        }
+        write_file(queue, storage, path, data)?;
+        self.write_sidecar(queue, storage, lrit, path)
+    }
+
+    /// Writes the headers next to a product that has already been written to `path`
+    ///
+    /// The headers go in `<path>.hdr` (or `<path>.hdr.json`), so `foo.jpg` gets `foo.jpg.hdr`.
+    pub(crate) fn write_sidecar(
+        self,
+        queue: Option<&WriteQueue>,
+        storage: &dyn Storage,
+        lrit: &LRIT,
+        path: &Path,
+    ) -> Result<(), HandlerError> {
+        let mut sidecar = path.as_os_str().to_owned();
+        match self {
+            HeaderPassthrough::None => return Ok(()),
+            HeaderPassthrough::Prepend | HeaderPassthrough::Sidecar => {
+                sidecar.push(".hdr");
+                write_file(queue, storage, Path::new(&sidecar), &lrit.raw_headers)?;
+            }
+            HeaderPassthrough::Json => {
+                sidecar.push(".hdr.json");
+                let json = serde_json::json!({ "vcid": lrit.vcid, "headers": lrit.headers });
+                let json = serde_json::to_vec_pretty(&json).map_err(|e| HandlerError::Other(Box::new(e)))?;
+                write_file(queue, storage, Path::new(&sidecar), &json)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a handler does when the file it's about to write already exists
+///
+/// Some products legitimately reuse annotation names, so overwriting (the default) can lose
+/// products.  Whenever a file already exists, the handler adds a record with a [`Duplicate`] to
+/// the product index (if it has one).
+///
+/// Only files that are already on disk are noticed, not ones still waiting in a
+/// [`WriteQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    #[default]
+    Overwrite,
+    /// Keep the existing file, and drop the new one
+    Skip,
+    /// Keep both, writing the new one with a number before the extension, like `foo-2.txt`
+    Version,
+}
+
+/// What was done about a product whose file already existed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub policy: DuplicatePolicy,
+    /// Where the product was written, or `None` if it was skipped
+    pub path: Option<PathBuf>,
+}
+
+impl DuplicatePolicy {
+    /// Checks whether `path` already exists, and if it does, works out what to do about it
+    ///
+    /// Returns `None` if there's nothing there yet.
+    pub fn check(self, storage: &dyn Storage, path: &Path) -> Option<Duplicate> {
+        if !storage.exists(path) {
+            return None;
+        }
+        let path = match self {
+            DuplicatePolicy::Overwrite => Some(path.to_path_buf()),
+            DuplicatePolicy::Skip => None,
+            DuplicatePolicy::Version => (2..).map(|n| versioned(path, n)).find(|p| !storage.exists(p)),
+        };
+        Some(Duplicate { policy: self, path })
+    }
+
+    /// Works out where to write a file that would go at `path`, recording any duplicate in
+    /// `index`
+    ///
+    /// Returns `None` if the file shouldn't be written.
+    pub(crate) fn place(
+        self,
+        storage: &dyn Storage,
+        index: Option<&ProductIndex>,
+        lrit: &LRIT,
+        path: PathBuf,
+    ) -> Result<Option<PathBuf>, HandlerError> {
+        let duplicate = match self.check(storage, &path) {
+            Some(duplicate) => duplicate,
+            None => return Ok(Some(path)),
+        };
+        if duplicate.path.is_none() {
+            info!("Skipping {}, which already exists", path.display());
+        }
+        let placed = duplicate.path.clone();
+        if let Some(index) = index {
+            if let Some(mut record) = IndexRecord::from_lrit(lrit, Utc::now()) {
+                record.duplicate = Some(duplicate);
+                index.append(&record)?;
+            }
+        }
+        Ok(placed)
+    }
+}
+
+/// `path` with `-<n>` added before the extension
+fn versioned(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+/// Runs a shell command in the background (like an alert hook), with `input` on its stdin and
+/// `env` added to its environment
+///
+/// Nothing waits for the command, so if it fails, that's only logged.
+pub(crate) fn spawn_command(command: &str, env: &[(&str, String)], input: Vec<u8>) -> std::io::Result<()> {
+    let child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(std::process::Stdio::piped())
+        .spawn()?;
+    wait_in_background(child, input, format!("Alert command `{}`", command));
+    Ok(())
+}
+
+/// Writes `input` to a child's stdin (if it was piped), and waits for it on another thread,
+/// logging a failure as `what` failing
+pub(crate) fn wait_in_background(mut child: std::process::Child, input: Vec<u8>, what: String) {
+    use std::io::Write;
+
+    let mut stdin = child.stdin.take();
+    std::thread::spawn(move || {
+        if let Some(stdin) = &mut stdin {
+            let _ = stdin.write_all(&input);
+        }
+        drop(stdin);
+        match child.wait() {
+            Ok(status) if !status.success() => log::warn!("{} failed: {}", what, status),
+            Err(e) => log::warn!("{} failed: {}", what, e),
+            Ok(_) => {}
+        }
+    });
+}
+
+/// Handlers must be `Send`, so they can be moved into their own worker thread (see [`Sandboxed`])
+pub trait Handler: Send {
     fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError>;
+
+    /// Writes out anything the handler is still holding on to
+    ///
+    /// This is called when shutting down, so handlers that collect several LRIT files into one
+    /// product should write whatever they have, even if it's incomplete.
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        Ok(())
+    }
+
+    /// A short name for this handler, used in log messages
+    fn name(&self) -> &str {
+        let full = std::any::type_name::<Self>();
+        full.rsplit("::").next().unwrap_or(full)
+    }
+}
+
+impl<H: Handler + ?Sized> Handler for Box<H> {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        (**self).handle(lrit)
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        (**self).flush()
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
 }
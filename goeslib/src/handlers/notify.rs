@@ -0,0 +1,302 @@
+use std::{
+    collections::VecDeque,
+    process::{Command, Stdio},
+};
+
+use log::info;
+
+use crate::{
+    emwin::{ugc::parse_ugc, zones::ZoneMap},
+    lrit::LRIT,
+    profile::Route,
+};
+
+use super::{glob_match, spawn_command, text_files, wait_in_background, Handler, HandlerError};
+
+/// How many product names to remember, so repeats don't notify again
+const RECENT_PRODUCTS: usize = 100;
+
+/// Which products to pop up a notification for, like tornado warnings for a few zones
+#[derive(Debug, Clone, Default)]
+pub struct NotifyRule {
+    /// The title of the notification, like "Tornado Warning"
+    pub title: String,
+    pub route: Route,
+    /// Only text products for one of these UGC codes, like "PAZ070" (or "PAZ*" for all of the
+    /// zones in a state), see [`parse_ugc`]
+    pub zones: Vec<String>,
+    /// Only text products for a county or zone that includes this point (latitude, longitude),
+    /// which needs the boundaries from [`NotifyHandler::with_zone_map`]
+    pub location: Option<(f64, f64)>,
+    /// A shell command to run instead of showing a desktop notification
+    pub command: Option<String>,
+}
+
+/// Shows a desktop notification for products that match a [`NotifyRule`]
+///
+/// Notifications are shown with `notify-send` on Linux, and `osascript` on macOS.  A rule with a
+/// command runs it instead, with the notification in the `NOTIFY_TITLE`, `NOTIFY_BODY`,
+/// `NOTIFY_PRODUCT`, and `NOTIFY_ZONES` environment variables, and the product's text on stdin.
+pub struct NotifyHandler {
+    rules: Vec<NotifyRule>,
+    /// The names of the most recent products, since products are often sent more than once
+    recent: VecDeque<String>,
+    zone_map: ZoneMap,
+}
+
+impl NotifyHandler {
+    pub fn new(rules: Vec<NotifyRule>) -> NotifyHandler {
+        NotifyHandler {
+            rules,
+            recent: VecDeque::new(),
+            zone_map: ZoneMap::new(),
+        }
+    }
+
+    /// The boundaries of counties and zones, for rules with a [`location`](NotifyRule::location)
+    pub fn with_zone_map(mut self, zone_map: ZoneMap) -> Self {
+        self.zone_map = zone_map;
+        self
+    }
+
+    /// Returns false if a product has been seen recently
+    fn is_new(&mut self, name: &str) -> bool {
+        if self.recent.iter().any(|n| n == name) {
+            return false;
+        }
+        if self.recent.len() == RECENT_PRODUCTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(name.to_string());
+        true
+    }
+}
+
+/// A notification for one product
+struct Notification<'a> {
+    title: &'a str,
+    body: String,
+    product: &'a str,
+    /// The zones the product is for, that the rule asked for
+    zones: Vec<String>,
+}
+
+impl Notification<'_> {
+    /// The command that shows a desktop notification
+    fn desktop(&self) -> Command {
+        if cfg!(target_os = "macos") {
+            let mut command = Command::new("osascript");
+            command.arg("-e").arg(format!(
+                "display notification {} with title {}",
+                applescript_string(&self.body),
+                applescript_string(self.title)
+            ));
+            command
+        } else {
+            let mut command = Command::new("notify-send");
+            // the title and body come from the product, so they can't be taken as options
+            command.args(["--app-name", "goesbox", "--", self.title, &self.body]);
+            command
+        }
+    }
+
+    /// Shows the notification (or runs `command`) in the background
+    fn show(&self, command: Option<&str>, text: &[u8]) -> Result<(), HandlerError> {
+        match command {
+            Some(command) => {
+                let env = [
+                    ("NOTIFY_TITLE", self.title.to_string()),
+                    ("NOTIFY_BODY", self.body.clone()),
+                    ("NOTIFY_PRODUCT", self.product.to_string()),
+                    ("NOTIFY_ZONES", self.zones.join(",")),
+                ];
+                spawn_command(command, &env, text.to_vec())?;
+            }
+            None => {
+                let child = self.desktop().stdin(Stdio::null()).spawn()?;
+                wait_in_background(child, Vec::new(), format!("Notification for {}", self.title));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Quotes a string for AppleScript
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The first headline in a product (like `...TORNADO WARNING IN EFFECT UNTIL 300 PM...`)
+fn headline(text: &str) -> Option<&str> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("...") && line.len() > 6)
+        .map(|line| line.trim_matches('.'))
+}
+
+impl Handler for NotifyHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+        if !self.rules.iter().any(|rule| rule.route.matches(lrit)) {
+            return Err(HandlerError::Skipped);
+        }
+
+        let files = match lrit.headers.primary.filetype_code {
+            2 => text_files(lrit, annotation)?,
+            _ => vec![(annotation.clone(), Vec::new())],
+        };
+        let mut shown = false;
+        for (name, data) in files {
+            if !self.is_new(&name) {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&data);
+            let codes = match self
+                .rules
+                .iter()
+                .any(|rule| !rule.zones.is_empty() || rule.location.is_some())
+            {
+                true => parse_ugc(&text),
+                false => Vec::new(),
+            };
+            for rule in self.rules.iter().filter(|rule| rule.route.matches(lrit)) {
+                let by_zone = !rule.zones.is_empty() || rule.location.is_some();
+                let zones: Vec<String> = codes
+                    .iter()
+                    .filter(|_| by_zone)
+                    .filter(|code| {
+                        rule.zones.is_empty() || rule.zones.iter().any(|z| glob_match(z.as_bytes(), code.as_bytes()))
+                    })
+                    .filter(|code| match rule.location {
+                        Some((lat, lon)) => self.zone_map.contains(code, lat, lon),
+                        None => true,
+                    })
+                    .cloned()
+                    .collect();
+                if by_zone && zones.is_empty() {
+                    continue;
+                }
+                let notification = Notification {
+                    title: &rule.title,
+                    body: headline(&text).unwrap_or(&name).to_string(),
+                    product: &name,
+                    zones,
+                };
+                info!("{}: {}", notification.title, notification.body);
+                notification.show(rule.command.as_deref(), &data)?;
+                shown = true;
+            }
+        }
+        match shown {
+            true => Ok(()),
+            false => Err(HandlerError::Skipped),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notification, NotifyHandler, NotifyRule};
+    use crate::{
+        emwin::zones::{Polygon, ZoneMap},
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+        profile::Route,
+        sim::LritBuilder,
+    };
+
+    fn text(name: &str, data: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_notify_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("notifications.txt");
+        let mut handler = NotifyHandler::new(vec![NotifyRule {
+            title: "Tornado Warning".to_string(),
+            route: Route {
+                patterns: vec!["*-TOR*".to_string()],
+                ..Default::default()
+            },
+            zones: vec!["PAC017".to_string(), "NJZ*".to_string()],
+            location: None,
+            command: Some(format!("echo \"$NOTIFY_ZONES $NOTIFY_BODY\" >> {}", out.display())),
+        }]);
+
+        let warning = |seq: u32, ugc: &str| {
+            text(
+                &format!("A_WFUS51KPHI041812_C_KWIN_20220504181303_{}-1-TORPHIPA.TXT", seq),
+                &format!(
+                    "WFUS51 KPHI 041812\nTORPHI\n{}\n\nBULLETIN - EAS ACTIVATION REQUESTED\n\
+                     Tornado Warning\n\n...TORNADO WARNING IN EFFECT UNTIL 300 PM EDT...\n",
+                    ugc
+                ),
+            )
+        };
+        handler.handle(&warning(1, "PAC017-091-NJZ001-041900-")).unwrap();
+        // a repeat doesn't notify again
+        assert!(matches!(
+            handler.handle(&warning(1, "PAC017-091-NJZ001-041900-")),
+            Err(HandlerError::Skipped)
+        ));
+        // nor does a warning for somewhere else, or a product the route doesn't match
+        assert!(matches!(
+            handler.handle(&warning(2, "PAC101-041900-")),
+            Err(HandlerError::Skipped)
+        ));
+        let afd = text(
+            "A_FXUS61KPHI041812_C_KWIN_20220504181303_3-3-AFDPHIPA.TXT",
+            "FXUS61 KPHI 041812\nAFDPHI\nPAC017-041900-\n",
+        );
+        assert!(matches!(handler.handle(&afd), Err(HandlerError::Skipped)));
+
+        // the command runs in the background
+        for _ in 0..50 {
+            if out.exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "PAC017,NJZ001 TORNADO WARNING IN EFFECT UNTIL 300 PM EDT\n"
+        );
+
+        // only warnings for a county or zone that includes the house
+        let mut zone_map = ZoneMap::new();
+        let square = vec![(-76.0, 40.0), (-75.0, 40.0), (-75.0, 41.0), (-76.0, 41.0)];
+        zone_map.insert("PAC017", Polygon { rings: vec![square] });
+        let mut handler = NotifyHandler::new(vec![NotifyRule {
+            title: "Tornado Warning".to_string(),
+            location: Some((40.3, -75.1)),
+            command: Some("true".to_string()),
+            ..Default::default()
+        }])
+        .with_zone_map(zone_map);
+        assert!(matches!(
+            handler.handle(&warning(3, "PAC101-NJZ001-041900-")),
+            Err(HandlerError::Skipped)
+        ));
+        handler.handle(&warning(4, "PAC017-041900-")).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_desktop_command() {
+        let notification = Notification {
+            title: "-Tornado Warning",
+            body: "--help".to_string(),
+            product: "A_WFUS51KPHI041812",
+            zones: Vec::new(),
+        };
+        let command = notification.desktop();
+        assert_eq!(command.get_program(), "notify-send");
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(args, ["--app-name", "goesbox", "--", "-Tornado Warning", "--help"]);
+    }
+}
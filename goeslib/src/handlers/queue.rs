@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+use crate::lrit::{Vcid, LRIT};
+
+/// The virtual channels that carry EMWIN (including warnings)
+pub const EMWIN_VCIDS: [Vcid; 3] = [Vcid::Emwin(20), Vcid::Emwin(21), Vcid::Emwin(22)];
+
+/// LRIT files waiting to be handled, with some virtual channels ahead of the rest
+///
+/// Handling a big image can take a while, and during a flood of images, text products (and
+/// warnings) from the EMWIN channels would otherwise wait behind them.  Files from priority
+/// channels ([`EMWIN_VCIDS`] by default) always come out first.  Files from the same channel
+/// always come out in the order they went in, so image segments stay in order.
+#[derive(Default)]
+pub struct DispatchQueue {
+    priority_vcids: Vec<Vcid>,
+    priority: VecDeque<LRIT>,
+    rest: VecDeque<LRIT>,
+}
+
+impl DispatchQueue {
+    pub fn new() -> DispatchQueue {
+        DispatchQueue {
+            priority_vcids: EMWIN_VCIDS.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_priority_vcids<V: Into<Vcid>>(mut self, vcids: impl IntoIterator<Item = V>) -> Self {
+        self.priority_vcids = vcids.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn push(&mut self, lrit: LRIT) {
+        if self.priority_vcids.contains(&Vcid::from(lrit.vcid)) {
+            self.priority.push_back(lrit);
+        } else {
+            self.rest.push_back(lrit);
+        }
+    }
+
+    /// The next file, from a priority channel if there are any
+    pub fn pop(&mut self) -> Option<LRIT> {
+        self.pop_priority().or_else(|| self.rest.pop_front())
+    }
+
+    /// The next file from a priority channel
+    pub fn pop_priority(&mut self) -> Option<LRIT> {
+        self.priority.pop_front()
+    }
+
+    /// How many files are waiting, as (from priority channels, from the rest)
+    pub fn len(&self) -> (usize, usize) {
+        (self.priority.len(), self.rest.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priority.is_empty() && self.rest.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DispatchQueue;
+    use crate::{lrit::LRIT, sim::LritBuilder};
+
+    fn lrit(vcid: u8, name: &str) -> LRIT {
+        LRIT::from_bytes(vcid, &LritBuilder::new(0).annotation(name).build(b"")).unwrap()
+    }
+
+    #[test]
+    fn test_dispatch_queue() {
+        let mut queue = DispatchQueue::new();
+        for (vcid, name) in [(0, "seg1"), (0, "seg2"), (20, "warning"), (0, "seg3"), (21, "text")] {
+            queue.push(lrit(vcid, name));
+        }
+        assert_eq!(queue.len(), (2, 3));
+        assert_eq!(queue.pop_priority().unwrap().vcid, 20);
+        let names: Vec<_> = std::iter::from_fn(|| queue.pop())
+            .map(|l| l.headers.annotation.unwrap().text)
+            .collect();
+        assert_eq!(names, ["text", "seg1", "seg2", "seg3"]);
+        assert!(queue.is_empty());
+
+        let mut queue = DispatchQueue::new().with_priority_vcids([2]);
+        queue.push(lrit(20, "text"));
+        assert!(queue.pop_priority().is_none());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    events::{Event, EventSender, RateAnomalyEvent},
+    lrit::LRIT,
+    quota::ProductClass,
+};
+
+use super::{Handler, HandlerError};
+
+/// Every class of product, in the order anomalies are reported
+const CLASSES: [ProductClass; 3] = [ProductClass::Imagery, ProductClass::Text, ProductClass::Other];
+
+/// How much each interval counts towards the average rate
+const SMOOTHING: f64 = 0.2;
+
+/// How many intervals to learn a class's usual rate over before flagging anything
+const WARMUP_INTERVALS: u32 = 12;
+
+/// A class that averages fewer products than this per interval is too bursty to flag
+const MIN_AVERAGE: f64 = 1.0;
+
+/// Whether a class of products is arriving at its usual rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateState {
+    Normal,
+    /// Nothing arrived in an interval, when several products usually do
+    Stopped,
+    /// Many more products than usual arrived in an interval
+    Spike,
+}
+
+/// The usual rate of one class of products
+#[derive(Debug)]
+struct ClassRate {
+    /// Products per interval, as an exponentially weighted moving average
+    average: f64,
+    intervals: u32,
+    state: RateState,
+}
+
+/// Learns how many products of each [`ProductClass`] usually arrive, and flags a class that stops
+/// arriving or spikes
+///
+/// Products are counted in intervals (5 minutes by default).  At the end of each interval, the
+/// count is compared to the class's moving average: no products at all is
+/// [`Stopped`](RateState::Stopped), and more than the spike factor (3 by default) times the
+/// average is a [`Spike`](RateState::Spike).  Either one (and the class getting back to normal) is
+/// logged and sent as an [`Event::RateAnomaly`].  Nothing is flagged until the first 12 intervals
+/// (an hour, by default) have been seen.
+///
+/// The average isn't updated while a class is stopped, so that it's still known when products
+/// come back, but it is while a class spikes, so a lasting change in the broadcast is learned.
+pub struct RateMonitor {
+    interval: Duration,
+    spike_factor: f64,
+    events: Option<EventSender>,
+    /// The start of the interval that's being counted
+    current: Option<DateTime<Utc>>,
+    counts: HashMap<ProductClass, u64>,
+    rates: HashMap<ProductClass, ClassRate>,
+}
+
+impl Default for RateMonitor {
+    fn default() -> Self {
+        RateMonitor::new()
+    }
+}
+
+impl RateMonitor {
+    pub fn new() -> RateMonitor {
+        RateMonitor {
+            interval: Duration::minutes(5),
+            spike_factor: 3.0,
+            events: None,
+            current: None,
+            counts: HashMap::new(),
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Count products in intervals of this length (rounded down to whole seconds, and at least one
+    /// second)
+    pub fn with_interval(mut self, interval: std::time::Duration) -> Self {
+        self.interval = Duration::seconds(interval.as_secs().max(1) as i64);
+        self
+    }
+
+    /// Flag an interval with more than this many times the usual number of products
+    pub fn with_spike_factor(mut self, factor: f64) -> Self {
+        self.spike_factor = factor;
+        self
+    }
+
+    /// Send an [`Event::RateAnomaly`] every time a class stops, spikes, or gets back to normal
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    fn interval_start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let secs = self.interval.num_seconds();
+        Utc.timestamp_opt(time.timestamp().div_euclid(secs) * secs, 0).unwrap()
+    }
+
+    /// Counts a product that arrived at `now`, returning the changes from any intervals that ended
+    /// before it
+    fn record(&mut self, class: ProductClass, now: DateTime<Utc>) -> Vec<RateAnomalyEvent> {
+        let start = self.interval_start(now);
+        let mut changes = Vec::new();
+        match self.current {
+            Some(mut current) if current < start => {
+                // intervals with nothing at all in them are still counted
+                while current < start {
+                    changes.extend(self.close(current));
+                    current += self.interval;
+                }
+                self.current = Some(start);
+            }
+            Some(_) => {}
+            None => self.current = Some(start),
+        }
+        *self.counts.entry(class).or_insert(0) += 1;
+        changes
+    }
+
+    /// Compares the counts of the interval starting at `start` to the averages, and updates them
+    fn close(&mut self, start: DateTime<Utc>) -> Vec<RateAnomalyEvent> {
+        let counts = std::mem::take(&mut self.counts);
+        let mut changes = Vec::new();
+        for class in CLASSES {
+            let count = counts.get(&class).copied().unwrap_or(0);
+            let rate = self.rates.entry(class).or_insert(ClassRate {
+                average: count as f64,
+                intervals: 0,
+                state: RateState::Normal,
+            });
+            let state = if rate.intervals < WARMUP_INTERVALS {
+                RateState::Normal
+            } else if count == 0 && rate.average >= MIN_AVERAGE {
+                RateState::Stopped
+            } else if count as f64 > self.spike_factor * rate.average.max(MIN_AVERAGE) {
+                RateState::Spike
+            } else {
+                RateState::Normal
+            };
+            if state != rate.state {
+                changes.push(RateAnomalyEvent {
+                    class,
+                    state,
+                    count,
+                    expected: rate.average,
+                    interval_start: start,
+                    interval_seconds: self.interval.num_seconds(),
+                });
+            }
+            if state != RateState::Stopped {
+                rate.average += SMOOTHING * (count as f64 - rate.average);
+            }
+            rate.intervals += 1;
+            rate.state = state;
+        }
+        changes
+    }
+}
+
+impl Handler for RateMonitor {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        for change in self.record(ProductClass::of(lrit), Utc::now()) {
+            match change.state {
+                RateState::Normal => info!("{:?} products are arriving at their usual rate again", change.class),
+                state => warn!(
+                    "{:?} products {:?}: {} in {} seconds, usually {:.1}",
+                    change.class, state, change.count, change.interval_seconds, change.expected
+                ),
+            }
+            if let Some(events) = &self.events {
+                // a closed channel is not an error; the event is simply dropped
+                let _ = events.send(Event::RateAnomaly(change));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{RateMonitor, RateState};
+    use crate::quota::ProductClass;
+
+    #[test]
+    fn test_rate_anomalies() {
+        let mut monitor = RateMonitor::new().with_interval(std::time::Duration::from_secs(60));
+        let start = Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap();
+        // sends `texts` text products and one image in minute `minute`, returning the changes
+        let mut minute = |minute: i64, texts: i64| {
+            let time = start + Duration::minutes(minute);
+            let mut changes = monitor.record(ProductClass::Imagery, time);
+            for n in 0..texts {
+                changes.extend(monitor.record(ProductClass::Text, time + Duration::seconds(n)));
+            }
+            changes
+                .into_iter()
+                .map(|c| (c.class, c.state, c.count))
+                .collect::<Vec<_>>()
+        };
+
+        // nothing is flagged while the rates are being learned
+        assert!(minute(0, 0).is_empty());
+        for m in 1..20 {
+            assert!(minute(m, 5).is_empty(), "minute {}", m);
+        }
+        // the changes for an interval are seen once the next one starts
+        assert!(minute(20, 0).is_empty());
+        assert_eq!(minute(21, 5), [(ProductClass::Text, RateState::Stopped, 0)]);
+        assert_eq!(minute(22, 40), [(ProductClass::Text, RateState::Normal, 5)]);
+        assert_eq!(minute(23, 5), [(ProductClass::Text, RateState::Spike, 40)]);
+        assert_eq!(minute(24, 5), [(ProductClass::Text, RateState::Normal, 5)]);
+
+        // minutes with nothing at all are counted too
+        assert_eq!(
+            minute(30, 5),
+            [
+                (ProductClass::Imagery, RateState::Stopped, 0),
+                (ProductClass::Text, RateState::Stopped, 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use log::debug;
+
+use crate::{
+    deadletter::sanitize,
+    index::ProductIndex,
+    lrit::{Vcid, LRIT},
+    storage::{self, SharedStorage},
+};
+
+use super::{DuplicatePolicy, Handler, HandlerError};
+
+/// Archives complete LRIT files (headers and data), for products selected by file type code,
+/// APID, or virtual channel
+///
+/// Files are written exactly as they were transmitted (see [`LRIT::to_raw_bytes`]), so they can
+/// be read by other LRIT tools.
+/// Files go in `raw/<YYYYMMDD>/`, named after their annotation (or their virtual channel and
+/// file type, if they don't have one).  With no filters, every LRIT file is archived.
+pub struct RawLritHandler {
+    output_root: PathBuf,
+    filetypes: HashSet<u8>,
+    apids: HashSet<u16>,
+    vcids: HashSet<Vcid>,
+    duplicates: DuplicatePolicy,
+    index: Option<ProductIndex>,
+    storage: SharedStorage,
+}
+
+impl RawLritHandler {
+    pub fn new(root: impl AsRef<Path>) -> RawLritHandler {
+        RawLritHandler {
+            output_root: root.as_ref().to_path_buf(),
+            filetypes: HashSet::new(),
+            apids: HashSet::new(),
+            vcids: HashSet::new(),
+            duplicates: DuplicatePolicy::Overwrite,
+            index: None,
+            storage: storage::local(),
+        }
+    }
+
+    /// Only archive files with one of these file type codes
+    pub fn with_filetypes(mut self, codes: impl IntoIterator<Item = u8>) -> Self {
+        self.filetypes.extend(codes);
+        self
+    }
+
+    /// Only archive files that came in on one of these APIDs
+    pub fn with_apids(mut self, apids: impl IntoIterator<Item = u16>) -> Self {
+        self.apids.extend(apids);
+        self
+    }
+
+    /// Only archive files that came in on one of these virtual channels
+    pub fn with_vcids<V: Into<Vcid>>(mut self, vcids: impl IntoIterator<Item = V>) -> Self {
+        self.vcids.extend(vcids.into_iter().map(Into::into));
+        self
+    }
+
+    /// What to do when a file has already been archived under the same name (it's overwritten
+    /// by default)
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Record files that had already been archived under the same name in the product index
+    /// under `root`
+    pub fn with_index(mut self, root: impl AsRef<Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+
+    /// Archive files to `storage` instead of the local filesystem
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Returns true if the file passes every filter that's been set
+    fn wants(&self, lrit: &LRIT) -> bool {
+        (self.filetypes.is_empty() || self.filetypes.contains(&lrit.headers.primary.filetype_code))
+            && (self.apids.is_empty() || matches!(lrit.apid, Some(apid) if self.apids.contains(&apid)))
+            && (self.vcids.is_empty() || self.vcids.contains(&Vcid::from(lrit.vcid)))
+    }
+}
+
+impl Handler for RawLritHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if !self.wants(lrit) {
+            return Err(HandlerError::Skipped);
+        }
+
+        let now = Utc::now();
+        let dir = self.output_root.join("raw").join(now.format("%Y%m%d").to_string());
+        std::fs::create_dir_all(&dir)?;
+        let name = match &lrit.headers.annotation {
+            Some(ann) => sanitize(&ann.text),
+            None => format!(
+                "{}-vc{}-type{}",
+                now.format("%H%M%S%.3f"),
+                lrit.vcid,
+                lrit.headers.primary.filetype_code
+            ),
+        };
+        let path = match self.duplicates.place(
+            &*self.storage,
+            self.index.as_ref(),
+            lrit,
+            dir.join(format!("{}.lrit", name)),
+        )? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.storage.write(&path, &lrit.to_raw_bytes())?;
+        debug!("Archived raw LRIT file {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawLritHandler;
+    use crate::{
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    fn test_raw_lrit_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = RawLritHandler::new(dir.path())
+            .with_filetypes([2, 130])
+            .with_vcids([20]);
+
+        let bytes = LritBuilder::new(2)
+            .annotation("A_FXUS61KPHI071250_C_KWIN.TXT")
+            .build(b"text");
+        let text = LRIT::from_bytes(20, &bytes).unwrap();
+        handler.handle(&text).unwrap();
+        let image = LRIT::from_bytes(20, &LritBuilder::new(0).annotation("img").build(&[0; 16])).unwrap();
+        assert!(matches!(handler.handle(&image), Err(HandlerError::Skipped)));
+        let other_vc = LRIT::from_bytes(21, &bytes).unwrap();
+        assert!(matches!(handler.handle(&other_vc), Err(HandlerError::Skipped)));
+
+        let day = std::fs::read_dir(dir.path().join("raw"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let files: Vec<_> = std::fs::read_dir(&day).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files, vec![day.join("A_FXUS61KPHI071250_C_KWIN.TXT.lrit")]);
+        assert_eq!(std::fs::read(&files[0]).unwrap(), bytes);
+
+        // LRIT files read back from disk don't have an APID, so they never match an APID filter
+        let mut handler = RawLritHandler::new(dir.path()).with_apids([5]);
+        assert!(matches!(handler.handle(&text), Err(HandlerError::Skipped)));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    any::Any,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::lrit::LRIT;
+
+use super::{Handler, HandlerError};
+
+/// Limits for a [`Sandboxed`] handler
+#[derive(Debug, Clone)]
+pub struct SandboxOptions {
+    /// How long to wait for the handler to finish with one LRIT file
+    pub time_budget: Duration,
+    /// How many bytes of LRIT data can be waiting for a handler that's fallen behind
+    pub memory_budget: usize,
+}
+
+impl Default for SandboxOptions {
+    /// 30 seconds, and 64 MiB
+    fn default() -> Self {
+        SandboxOptions {
+            time_budget: Duration::from_secs(30),
+            memory_budget: 64 * 1024 * 1024,
+        }
+    }
+}
+
+enum Job {
+    Handle(u64, Box<LRIT>),
+    Flush(u64),
+}
+
+/// Runs a handler in its own worker thread, so it can't take down the rest of goesbox
+///
+/// A panic in the handler is caught, and returned as [`HandlerError::Crashed`].  The handler keeps
+/// running afterwards (with whatever state the panic left it in), since most panics are caused by
+/// one bad LRIT file.
+///
+/// If the handler takes longer than the time budget, that file is reported as a crash, and the
+/// handler is left to finish in the background.  Until it does, LRIT files are queued for it
+/// without waiting, up to the memory budget; files that don't fit are dropped (and reported as
+/// crashes too).  Failures of files that were queued like this are only logged.
+///
+/// Rust can't limit how much memory one thread allocates, so the memory budget only covers LRIT
+/// data waiting for the handler, not what the handler allocates itself.
+pub struct Sandboxed {
+    name: String,
+    options: SandboxOptions,
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(u64, Result<(), HandlerError>)>,
+    worker: Option<JoinHandle<()>>,
+    /// Bytes of LRIT data sent to the worker, that it hasn't finished with yet
+    queued: Arc<AtomicUsize>,
+    next_id: u64,
+    /// The newest job that went over the time budget (or was queued behind one), if the worker
+    /// hasn't finished it yet
+    behind: Option<u64>,
+}
+
+impl Sandboxed {
+    pub fn new(mut handler: impl Handler + 'static, options: SandboxOptions) -> Sandboxed {
+        let name = handler.name().to_string();
+        let (jobs, job_receiver) = channel();
+        let (result_sender, results) = channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let worker_queued = Arc::clone(&queued);
+        let worker = std::thread::Builder::new()
+            .name(format!("handler {}", name))
+            .spawn(move || {
+                for job in job_receiver {
+                    let (id, result) = match job {
+                        Job::Handle(id, lrit) => {
+                            let result = catch_unwind(AssertUnwindSafe(|| handler.handle(&lrit)));
+                            worker_queued.fetch_sub(lrit.data.len(), Ordering::SeqCst);
+                            (id, result)
+                        }
+                        Job::Flush(id) => (id, catch_unwind(AssertUnwindSafe(|| handler.flush()))),
+                    };
+                    let result = result.unwrap_or_else(|panic| Err(HandlerError::Crashed(panic_message(&panic))));
+                    if result_sender.send((id, result)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn handler thread");
+        Sandboxed {
+            name,
+            options,
+            jobs: Some(jobs),
+            results,
+            worker: Some(worker),
+            queued,
+            next_id: 0,
+            behind: None,
+        }
+    }
+
+    /// Sends a job to the worker, and waits for its result
+    ///
+    /// Results of earlier jobs that weren't waited for are logged.  The wait is restarted every
+    /// time one of them finishes, since that means the worker is still making progress.
+    fn run(&mut self, job: impl FnOnce(u64) -> Job, wait: bool) -> Result<(), HandlerError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let sent = self.jobs.as_ref().map(|jobs| jobs.send(job(id)));
+        if !matches!(sent, Some(Ok(()))) {
+            return Err(HandlerError::Crashed("the handler thread has stopped".to_string()));
+        }
+        if !wait {
+            self.behind = Some(id);
+            self.collect(Duration::ZERO);
+            return Ok(());
+        }
+
+        loop {
+            match self.results.recv_timeout(self.options.time_budget) {
+                Ok((done, result)) if done == id => {
+                    self.behind = None;
+                    return result;
+                }
+                Ok((done, result)) => self.finished_late(done, result),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.behind = Some(id);
+                    return Err(HandlerError::Crashed(format!(
+                        "took longer than {} s",
+                        self.options.time_budget.as_secs_f64()
+                    )));
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(HandlerError::Crashed("the handler thread has stopped".to_string()))
+                }
+            }
+        }
+    }
+
+    /// Logs the results of jobs that weren't waited for, that finish within `timeout`
+    fn collect(&mut self, timeout: Duration) {
+        while let Ok((done, result)) = self.results.recv_timeout(timeout) {
+            self.finished_late(done, result);
+        }
+    }
+
+    fn finished_late(&mut self, id: u64, result: Result<(), HandlerError>) {
+        match result {
+            Ok(()) | Err(HandlerError::Skipped) => {}
+            Err(e) => warn!("{} failed on a file it had fallen behind on: {}", self.name, e),
+        }
+        if self.behind == Some(id) {
+            self.behind = None;
+        }
+    }
+}
+
+impl Handler for Sandboxed {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        self.collect(Duration::ZERO);
+        let size = lrit.data.len();
+        let behind = self.behind.is_some();
+        if behind && self.queued.load(Ordering::SeqCst) + size > self.options.memory_budget {
+            return Err(HandlerError::Crashed(format!(
+                "fell behind by more than {} bytes",
+                self.options.memory_budget
+            )));
+        }
+        self.queued.fetch_add(size, Ordering::SeqCst);
+        self.run(|id| Job::Handle(id, Box::new(lrit.clone())), !behind)
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        self.run(Job::Flush, true)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for Sandboxed {
+    fn drop(&mut self) {
+        // stops the worker once it's done with everything queued
+        self.jobs = None;
+        // but a worker that's stuck would never be joined
+        if self.behind.is_none() {
+            if let Some(worker) = self.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> String {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        format!("panicked: {}", s)
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        format!("panicked: {}", s)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{SandboxOptions, Sandboxed};
+    use crate::{
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+    };
+
+    /// Panics on files with 1 byte of data, and sleeps for as many milliseconds as there are
+    /// bytes of data
+    struct Fragile;
+
+    impl Handler for Fragile {
+        fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+            if lrit.data.len() == 1 {
+                panic!("bad file");
+            }
+            std::thread::sleep(Duration::from_millis(lrit.data.len() as u64));
+            Ok(())
+        }
+    }
+
+    fn lrit(data_len: usize) -> LRIT {
+        let mut bytes = vec![0, 0, 16, 2, 0, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.resize(16 + data_len, 0);
+        LRIT::from_bytes(20, &bytes).unwrap()
+    }
+
+    #[test]
+    fn test_sandboxed() {
+        let mut handler = Sandboxed::new(
+            Fragile,
+            SandboxOptions {
+                time_budget: Duration::from_millis(100),
+                memory_budget: 200,
+            },
+        );
+        assert_eq!(handler.name(), "Fragile");
+        handler.handle(&lrit(0)).unwrap();
+        assert!(matches!(handler.handle(&lrit(1)), Err(HandlerError::Crashed(msg)) if msg == "panicked: bad file"));
+        // the handler still works after a panic
+        handler.handle(&lrit(2)).unwrap();
+
+        // a slow file is a crash, and the following files are queued, up to the memory budget
+        assert!(matches!(handler.handle(&lrit(150)), Err(HandlerError::Crashed(_))));
+        handler.handle(&lrit(50)).unwrap();
+        assert!(matches!(handler.handle(&lrit(50)), Err(HandlerError::Crashed(_))));
+
+        // once it's caught up, files are waited for again
+        handler.flush().unwrap();
+        handler.handle(&lrit(1)).unwrap_err();
+    }
+}
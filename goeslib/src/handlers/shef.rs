@@ -0,0 +1,224 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::{
+    annotation::LritFilename,
+    emwin::shef::{parse_bulletin, ShefValue, Units},
+    lrit::LRIT,
+};
+
+use super::{text_files, DcsBlock, Handler, HandlerError};
+
+const CSV_HEADER: &str = "location,parameter,time,value,units,revised";
+
+/// Decodes SHEF hydrologic data, from `RR1`-`RR9` and `HYD` products and from DCS messages
+///
+/// Every value is appended to `shef.csv` in the output root.  With the `sqlite` feature, values
+/// can also be kept in an SQLite database, see [`with_database`](ShefHandler::with_database).
+pub struct ShefHandler {
+    output_root: PathBuf,
+    #[cfg(feature = "sqlite")]
+    db: Option<rusqlite::Connection>,
+}
+
+impl ShefHandler {
+    pub fn new(root: impl AsRef<Path>) -> ShefHandler {
+        ShefHandler {
+            output_root: root.as_ref().to_path_buf(),
+            #[cfg(feature = "sqlite")]
+            db: None,
+        }
+    }
+
+    /// Also keep values in the `shef` table of an SQLite database, which is created if needed
+    ///
+    /// There's one row per location, parameter, and time, so revised values replace the originals.
+    #[cfg(feature = "sqlite")]
+    pub fn with_database(mut self, path: impl AsRef<Path>) -> Result<Self, HandlerError> {
+        let db = rusqlite::Connection::open(path).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS shef (
+                location TEXT NOT NULL,
+                parameter TEXT NOT NULL,
+                time TEXT NOT NULL,
+                value REAL,
+                units TEXT NOT NULL,
+                revised INTEGER NOT NULL,
+                PRIMARY KEY (location, parameter, time)
+            )",
+        )
+        .map_err(|e| HandlerError::Other(Box::new(e)))?;
+        self.db = Some(db);
+        Ok(self)
+    }
+
+    fn write_values(&mut self, values: &[ShefValue]) -> Result<(), HandlerError> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        let path = self.output_root.join("shef.csv");
+        let new = !path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if new {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        for v in values {
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                v.location,
+                v.parameter,
+                v.time.format("%Y-%m-%dT%H:%M:%SZ"),
+                v.value.map(|x| x.to_string()).unwrap_or_default(),
+                units_name(v.units),
+                v.revised
+            )?;
+        }
+
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &mut self.db {
+            insert_values(db, values).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn insert_values(db: &mut rusqlite::Connection, values: &[ShefValue]) -> rusqlite::Result<()> {
+    let tx = db.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT OR REPLACE INTO shef (location, parameter, time, value, units, revised)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for v in values {
+            stmt.execute(rusqlite::params![
+                v.location,
+                v.parameter,
+                v.time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                v.value,
+                units_name(v.units),
+                v.revised
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+fn units_name(units: Units) -> &'static str {
+    match units {
+        Units::English => "english",
+        Units::Si => "si",
+    }
+}
+
+/// Returns true if the short EMWIN product name (like `RR3DMXIA`) is for SHEF data
+pub(crate) fn is_shef_product(name: &str) -> bool {
+    name.starts_with("RR") || name.starts_with("HYD")
+}
+
+/// Decodes the SHEF values in an `RR`/`HYD` product or a DCS file
+///
+/// Returns [`HandlerError::Skipped`] if there aren't any.
+pub(super) fn decode_values(lrit: &LRIT) -> Result<Vec<ShefValue>, HandlerError> {
+    let mut values = Vec::new();
+    match lrit.headers.primary.filetype_code {
+        2 => {
+            let annotation = match &lrit.headers.annotation {
+                Some(ann) => &ann.text,
+                None => return Err(HandlerError::MissingHeader("annotation")),
+            };
+            for (filename, data) in text_files(lrit, annotation)? {
+                let emwin = match LritFilename::parse(&filename) {
+                    LritFilename::Emwin(emwin) if is_shef_product(&emwin.legacy_filename) => emwin,
+                    _ => continue,
+                };
+                values.extend(parse_bulletin(&String::from_utf8_lossy(&data), emwin.date));
+            }
+        }
+        130 if lrit.data.len() > 64 => {
+            // only ASCII messages can have SHEF in them; pseudo-binary ones won't decode to anything
+            for block in DcsBlock::parse(&lrit.data[64..])? {
+                let text: String = block.data.iter().skip(1).map(|b| (b & 0x7f) as char).collect();
+                values.extend(parse_bulletin(&text, block.carrier_start));
+            }
+        }
+        _ => return Err(HandlerError::Skipped),
+    }
+    if values.is_empty() {
+        return Err(HandlerError::Skipped);
+    }
+    debug!("Decoded {} SHEF values", values.len());
+    Ok(values)
+}
+
+impl Handler for ShefHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let values = decode_values(lrit)?;
+        self.write_values(&values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShefHandler;
+    use crate::{
+        handlers::{Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    fn text(name: &str, data: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_shef_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = ShefHandler::new(dir.path());
+
+        let rr3 = "A_SRUS53KDMX071230_C_KWIN_20220507123013_106868-3-RR3DMXIA.TXT";
+        handler
+            .handle(&text(
+                rr3,
+                "SRUS53 KDMX 071230\nRR3DMX\n.A DESI4 0507 Z DH12/HG 5.23/PPH M\n",
+            ))
+            .unwrap();
+        let afd = "A_FXUS63KDMX071230_C_KWIN_20220507123013_106869-3-AFDDMXIA.TXT";
+        assert!(matches!(
+            handler.handle(&text(afd, ".A DESI4 0507 Z DH12/HG 9.99\n")),
+            Err(HandlerError::Skipped)
+        ));
+
+        let csv = std::fs::read_to_string(dir.path().join("shef.csv")).unwrap();
+        assert_eq!(
+            csv,
+            "location,parameter,time,value,units,revised\n\
+             DESI4,HG,2022-05-07T12:00:00Z,5.23,english,false\n\
+             DESI4,PPH,2022-05-07T12:00:00Z,,english,false\n"
+        );
+
+        #[cfg(feature = "sqlite")]
+        {
+            let db_path = dir.path().join("hydro.db");
+            let mut handler = ShefHandler::new(dir.path()).with_database(&db_path).unwrap();
+            let revised = ".AR DESI4 0507 Z DH12/HG 5.25\n";
+            handler.handle(&text(rr3, revised)).unwrap();
+            handler.handle(&text(rr3, revised)).unwrap();
+            let db = rusqlite::Connection::open(&db_path).unwrap();
+            let value: f64 = db
+                .query_row(
+                    "SELECT value FROM shef WHERE location = 'DESI4' AND parameter = 'HG'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(value, 5.25);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+
+use crate::{
+    annotation::LritFilename,
+    emwin::sounding::{parse_bulletin, Sounding},
+    lrit::LRIT,
+};
+
+use super::{text_files, Handler, HandlerError, ObservationFormat};
+
+/// Decodes upper air soundings, and writes a profile per station and time
+///
+/// Profiles go in `soundings/<station>-<YYYYMMDDHH>.csv` (or `.json`), with the levels from the
+/// surface up.  Part A and part B of a sounding arrive separately, so the profile is rewritten
+/// with both once the second part arrives.
+pub struct SoundingHandler {
+    output_root: PathBuf,
+    format: ObservationFormat,
+    /// Soundings that might still get another part, by station and time
+    pending: HashMap<(String, DateTime<Utc>), Sounding>,
+}
+
+/// How long to wait for the other part of a sounding
+const PART_WINDOW: i64 = 12;
+
+impl SoundingHandler {
+    pub fn new(root: impl AsRef<Path>) -> SoundingHandler {
+        SoundingHandler {
+            output_root: root.as_ref().to_path_buf(),
+            format: ObservationFormat::Csv,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: ObservationFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    fn write_profile(&self, sounding: &Sounding, time: DateTime<Utc>) -> Result<(), HandlerError> {
+        let dir = self.output_root.join("soundings");
+        std::fs::create_dir_all(&dir)?;
+        let name = format!("{}-{}", sounding.station, time.format("%Y%m%d%H"));
+        let (path, data) = match self.format {
+            ObservationFormat::Csv => (dir.join(name + ".csv"), profile_csv(sounding)),
+            ObservationFormat::Json => (
+                dir.join(name + ".json"),
+                serde_json::to_string_pretty(sounding).map_err(|e| HandlerError::Other(Box::new(e)))?,
+            ),
+        };
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// Returns true if the WMO heading of an EMWIN product (like `A_USUS41KWBC...`) is for part A or
+/// part B of a sounding
+pub(crate) fn is_sounding_bulletin(annotation: &str) -> bool {
+    matches!(annotation.get(2..4), Some("US" | "UK"))
+}
+
+impl Handler for SoundingHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 2 {
+            return Err(HandlerError::Skipped);
+        }
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+        if !is_sounding_bulletin(annotation) {
+            return Err(HandlerError::Skipped);
+        }
+
+        for (filename, data) in text_files(lrit, annotation)? {
+            let issued = match LritFilename::parse(&filename) {
+                LritFilename::Emwin(emwin) => emwin.date,
+                _ => Utc::now(),
+            };
+            self.pending
+                .retain(|(_, time), _| issued - *time < Duration::hours(PART_WINDOW));
+
+            for sounding in parse_bulletin(&String::from_utf8_lossy(&data)) {
+                let time = match sounding.time.resolve(issued) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                let merged = match self.pending.get_mut(&(sounding.station.clone(), time)) {
+                    Some(pending) => {
+                        pending.merge(&sounding);
+                        pending.clone()
+                    }
+                    None => {
+                        self.pending.insert((sounding.station.clone(), time), sounding.clone());
+                        sounding
+                    }
+                };
+                self.write_profile(&merged, time)?;
+                info!("Wrote sounding for {} at {}", merged.station, time);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn profile_csv(sounding: &Sounding) -> String {
+    let mut csv =
+        String::from("kind,pressure_hpa,height_m,temperature_c,dew_point_c,wind_direction,wind_speed,wind_unit\n");
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    for level in &sounding.levels {
+        let _ = writeln!(
+            csv,
+            "{:?},{},{},{},{},{},{},{:?}",
+            level.kind,
+            level.pressure_hpa,
+            opt(level.height_m.map(|h| h.to_string())),
+            opt(level.temperature_c.map(|t| format!("{:.1}", t))),
+            opt(level.dew_point_c.map(|t| format!("{:.1}", t))),
+            opt(level.wind.map(|w| w.direction.to_string())),
+            opt(level.wind.map(|w| w.speed.to_string())),
+            sounding.wind_unit,
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SoundingHandler;
+    use crate::{handlers::Handler, lrit::LRIT, sim::LritBuilder};
+
+    fn text(name: &str, data: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_sounding_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = SoundingHandler::new(dir.path());
+
+        let part_a = text(
+            "A_USUS41KWBC011200_C_KWIN_20220501121513_106868-3-MANIADUS.TXT",
+            "USUS41 KWBC 011200\nTTAA 51121 72403 99012 26856 22009 00148 ///// ///// 85514 16456 25520 88999 77999=\n",
+        );
+        handler.handle(&part_a).unwrap();
+        let path = dir.path().join("soundings/72403-2022050112.csv");
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert_eq!(csv.lines().nth(1).unwrap(), "Surface,1012,,26.8,20.8,220,9,Knots");
+
+        let part_b = text(
+            "A_UKUS41KWBC011200_C_KWIN_20220501122013_106869-3-SGLIADUS.TXT",
+            "UKUS41 KWBC 011200\nTTBB 5112/ 72403 00012 26856 11981 23662=\n",
+        );
+        handler.handle(&part_b).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().count(), 6);
+        assert_eq!(csv.lines().nth(4).unwrap(), "Significant,981,,23.6,11.6,,,Knots");
+    }
+}
@@ -0,0 +1,330 @@
+use std::{collections::HashMap, ops::Deref, path::Path};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{events::StormWatchEvent, lrit::LRIT, sector::Sector, xmp::ImageMetadata};
+
+use super::{spawn_command, HandlerError};
+
+/// How many points along each side of an area are projected, to find the pixels it covers
+const AREA_SAMPLES: u32 = 16;
+
+/// An area between two latitudes and two longitudes, in degrees
+///
+/// The area crosses the antimeridian if `west` is greater than `east`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatLonBox {
+    pub south: f64,
+    pub north: f64,
+    pub west: f64,
+    pub east: f64,
+}
+
+impl LatLonBox {
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let lon = (lon + 180.0).rem_euclid(360.0) - 180.0;
+        let in_lon = if self.west <= self.east {
+            (self.west..=self.east).contains(&lon)
+        } else {
+            lon >= self.west || lon <= self.east
+        };
+        in_lon && (self.south..=self.north).contains(&lat)
+    }
+
+    /// The columns and lines of the image pixels that can be in the area, as `(x0, y0, x1, y1)`
+    /// (exclusive of `x1` and `y1`), or `None` if none of it is in the image
+    fn pixel_bounds(&self, nav: &crate::lrit::ImageNavigationRecord, width: u32, height: u32) -> Option<[u32; 4]> {
+        let span = if self.west <= self.east {
+            self.east - self.west
+        } else {
+            self.east + 360.0 - self.west
+        };
+        let mut bounds: Option<[f64; 4]> = None;
+        for i in 0..=AREA_SAMPLES {
+            for j in 0..=AREA_SAMPLES {
+                let lat = self.south + (self.north - self.south) * f64::from(i) / f64::from(AREA_SAMPLES);
+                let lon = self.west + span * f64::from(j) / f64::from(AREA_SAMPLES);
+                if let Some((x, y)) = nav.latlon_to_pixel(lat, lon) {
+                    let b = bounds.get_or_insert([x, y, x, y]);
+                    *b = [b[0].min(x), b[1].min(y), b[2].max(x), b[3].max(y)];
+                }
+            }
+        }
+        let [x0, y0, x1, y1] = bounds?;
+        // a pixel or so of slack, since only the samples were projected
+        let x0 = (x0.floor() - 1.0).max(0.0) as u32;
+        let y0 = (y0.floor() - 1.0).max(0.0) as u32;
+        let x1 = (x1.ceil() + 2.0).clamp(0.0, f64::from(width)) as u32;
+        let y1 = (y1.ceil() + 2.0).clamp(0.0, f64::from(height)) as u32;
+        (x0 < x1 && y0 < y1).then_some([x0, y0, x1, y1])
+    }
+}
+
+/// Watches an area for cloud tops colder than a threshold, like the tops of thunderstorms
+///
+/// This is set up with [`ImageHandler::with_storm_watch`](super::ImageHandler::with_storm_watch).
+/// Each segmented image of a watched band (C13, the clean infrared window, by default) is
+/// converted to brightness temperatures with its data function header, and the pixels in the
+/// area are found with its navigation header.  When at least 4 of them (by default) are colder
+/// than the threshold (220 K, about -53 °C, by default), the area has cold cloud tops.  An
+/// [`Event::StormWatch`](crate::events::Event::StormWatch) is sent when they first show up, and
+/// again when they're gone.
+///
+/// Images without a navigation header, or whose data function isn't a temperature, are skipped.
+pub struct StormWatch {
+    name: String,
+    area: LatLonBox,
+    threshold: f64,
+    min_pixels: usize,
+    bands: Vec<String>,
+    command: Option<String>,
+    /// Whether each band and region (of the images that cover the area) has cold cloud tops
+    active: HashMap<String, bool>,
+}
+
+impl StormWatch {
+    pub fn new(name: impl Into<String>, area: LatLonBox) -> StormWatch {
+        StormWatch {
+            name: name.into(),
+            area,
+            threshold: 220.0,
+            min_pixels: 4,
+            bands: vec!["C13".to_string()],
+            command: None,
+            active: HashMap::new(),
+        }
+    }
+
+    /// Count pixels colder than this many kelvin
+    pub fn with_threshold(mut self, kelvin: f64) -> Self {
+        self.threshold = kelvin;
+        self
+    }
+
+    /// Need at least this many cold pixels in the area (4 by default)
+    pub fn with_min_pixels(mut self, pixels: usize) -> Self {
+        self.min_pixels = pixels.max(1);
+        self
+    }
+
+    /// Watch these bands (like `C14`) instead of C13
+    pub fn with_bands(mut self, bands: Vec<String>) -> Self {
+        self.bands = bands;
+        self
+    }
+
+    /// Run a shell command when cold cloud tops show up in the area, or go away
+    ///
+    /// The event is given to the command as JSON on stdin, and in the `STORM_WATCH`,
+    /// `STORM_ACTIVE` (`1` or `0`), `STORM_COLD_PIXELS`, and `STORM_MIN_TEMPERATURE` environment
+    /// variables.
+    pub fn with_command(mut self, command: impl Into<String>) -> Self {
+        self.command = Some(command.into());
+        self
+    }
+
+    /// Looks for cold cloud tops in the area in a newly written image
+    ///
+    /// `first` is the first segment of the image, for its headers.  Returns an event if cold cloud
+    /// tops have shown up or gone away since the last image of the same band and region.
+    pub fn check<C: Deref<Target = [u8]>>(
+        &mut self,
+        first: &LRIT,
+        meta: &ImageMetadata,
+        img: &image::ImageBuffer<image::Luma<u8>, C>,
+        out_name: &Path,
+    ) -> Result<Option<StormWatchEvent>, HandlerError> {
+        match &meta.band {
+            Some(band) if self.bands.contains(band) => {}
+            _ => return Ok(None),
+        }
+        let nav = match &first.headers.img_navigation {
+            Some(nav) => nav,
+            None => return Ok(None),
+        };
+        let function = match first.headers.img_data.as_ref().and_then(|d| d.data_function()) {
+            Some(function) if function.unit.as_deref() == Some("K") => function,
+            _ => {
+                debug!("{}: no brightness temperatures to watch for storms", meta.product);
+                return Ok(None);
+            }
+        };
+        let [x0, y0, x1, y1] = match self.area.pixel_bounds(nav, img.width(), img.height()) {
+            Some(bounds) => bounds,
+            None => return Ok(None),
+        };
+
+        let temperatures: Vec<Option<f64>> = (0..=255).map(|count| function.value(count)).collect();
+        let mut area_pixels = 0;
+        let mut cold_pixels = 0;
+        let mut coldest: Option<(f64, f64, f64)> = None;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (lat, lon) = match nav.pixel_to_latlon(f64::from(x), f64::from(y)) {
+                    Some((lat, lon)) if self.area.contains(lat, lon) => (lat, lon),
+                    _ => continue,
+                };
+                area_pixels += 1;
+                let temperature = match temperatures[img.get_pixel(x, y).0[0] as usize] {
+                    Some(temperature) => temperature,
+                    None => continue,
+                };
+                if temperature < self.threshold {
+                    cold_pixels += 1;
+                }
+                if !matches!(coldest, Some((t, _, _)) if t <= temperature) {
+                    coldest = Some((temperature, lat, lon));
+                }
+            }
+        }
+        if area_pixels == 0 {
+            return Ok(None);
+        }
+
+        // mesoscale sectors move around, so they only count while they're over the same place
+        let key = format!(
+            "{}/{}/{}",
+            meta.band.as_deref().unwrap_or_default(),
+            meta.region.as_deref().unwrap_or_default(),
+            Sector::from_lrit(first).map(|s| s.key()).unwrap_or_default()
+        );
+        let active = cold_pixels >= self.min_pixels;
+        if self.active.insert(key, active).unwrap_or(false) == active {
+            return Ok(None);
+        }
+        let event = StormWatchEvent {
+            name: self.name.clone(),
+            product: meta.product.clone(),
+            path: out_name.to_path_buf(),
+            band: meta.band.clone(),
+            region: meta.region.clone(),
+            scan_start: meta.scan_start,
+            active,
+            threshold: self.threshold,
+            cold_pixels,
+            area_pixels,
+            min_temperature: coldest.map(|(t, _, _)| t),
+            coldest: coldest.map(|(_, lat, lon)| (lat, lon)),
+        };
+        if active {
+            info!(
+                "{}: {} pixels colder than {:.0} K over {}",
+                meta.product, cold_pixels, self.threshold, self.name
+            );
+        } else {
+            info!("{}: no more cold cloud tops over {}", meta.product, self.name);
+        }
+        if let Some(command) = &self.command {
+            let json = serde_json::to_vec(&event).map_err(|e| HandlerError::Other(Box::new(e)))?;
+            let env = [
+                ("STORM_WATCH", event.name.clone()),
+                ("STORM_ACTIVE", if active { "1" } else { "0" }.to_string()),
+                ("STORM_COLD_PIXELS", cold_pixels.to_string()),
+                (
+                    "STORM_MIN_TEMPERATURE",
+                    event.min_temperature.map(|t| format!("{:.1}", t)).unwrap_or_default(),
+                ),
+            ];
+            spawn_command(command, &env, json)?;
+        }
+        Ok(Some(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{LatLonBox, StormWatch};
+    use crate::{
+        lrit::{ImageDataFunctionRecord, ImageNavigationRecord, LRIT},
+        sim::LritBuilder,
+        xmp::ImageMetadata,
+    };
+
+    #[test]
+    fn test_storm_watch() {
+        // a 400 pixel full disk, where counts go from 330 K (0) to 180 K (255)
+        let name = "OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809590.lrit";
+        let bytes = LritBuilder::new(0)
+            .image_structure(8, 400, 400)
+            .annotation(name)
+            .build(&[0; 16]);
+        let mut lrit = LRIT::from_bytes(1, &bytes).unwrap();
+        let nav = ImageNavigationRecord {
+            header_type: 2,
+            header_record_lenth: 51,
+            projection_name: "GEOS(-75.0)".to_string(),
+            column_scaling_factor: 1509312,
+            line_scaling_factor: 1509312,
+            column_offset: 200,
+            line_offset: 200,
+        };
+        lrit.headers.img_navigation = Some(nav.clone());
+        let function = b"_NAME:=toa_brightness_temperature\r\n_UNIT:=K\r\n0:=330.0\r\n255:=180.0\r\n";
+        let mut record = vec![3, 0, 3 + function.len() as u8];
+        record.extend_from_slice(function);
+        lrit.headers.img_data = Some(ImageDataFunctionRecord::from_bytes(&record).unwrap());
+        let meta = ImageMetadata::from_lrit(&lrit);
+
+        let oklahoma = LatLonBox {
+            south: 33.0,
+            north: 37.0,
+            west: -100.0,
+            east: -94.0,
+        };
+        let (storm_x, storm_y) = nav.latlon_to_pixel(35.0, -97.0).unwrap();
+        // a 3x3 patch of cold pixels at `(x, y)`
+        let image = |cold: &[(f64, f64)]| {
+            image::GrayImage::from_fn(400, 400, |x, y| {
+                let near = cold.iter().any(|&(cx, cy)| {
+                    (f64::from(x) - cx.round()).abs() <= 1.0 && (f64::from(y) - cy.round()).abs() <= 1.0
+                });
+                image::Luma([if near { 255 } else { 0 }])
+            })
+        };
+        let path = Path::new("image.jpg");
+        let mut watch = StormWatch::new("Oklahoma", oklahoma).with_min_pixels(4);
+
+        // cold cloud tops somewhere else don't count
+        assert!(watch
+            .check(&lrit, &meta, &image(&[(200.0, 200.0)]), path)
+            .unwrap()
+            .is_none());
+        let event = watch
+            .check(&lrit, &meta, &image(&[(storm_x, storm_y)]), path)
+            .unwrap()
+            .unwrap();
+        assert!(event.active);
+        assert_eq!((event.name.as_str(), event.cold_pixels), ("Oklahoma", 9));
+        assert!(event.area_pixels > 9);
+        assert_eq!(event.min_temperature, Some(180.0));
+        let (lat, lon) = event.coldest.unwrap();
+        assert!(oklahoma.contains(lat, lon));
+        // they're only reported when they show up, and when they're gone
+        assert!(watch
+            .check(&lrit, &meta, &image(&[(storm_x, storm_y)]), path)
+            .unwrap()
+            .is_none());
+        let event = watch.check(&lrit, &meta, &image(&[]), path).unwrap().unwrap();
+        assert!(!event.active);
+        assert_eq!(event.cold_pixels, 0);
+        assert_eq!(event.min_temperature, Some(330.0));
+
+        // other bands aren't watched
+        let mut watch = StormWatch::new("Oklahoma", oklahoma).with_bands(vec!["C14".into()]);
+        assert!(watch
+            .check(&lrit, &meta, &image(&[(storm_x, storm_y)]), path)
+            .unwrap()
+            .is_none());
+
+        assert!(LatLonBox {
+            south: -10.0,
+            north: 10.0,
+            west: 170.0,
+            east: -170.0
+        }
+        .contains(0.0, 180.0));
+    }
+}
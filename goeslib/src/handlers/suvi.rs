@@ -0,0 +1,228 @@
+//! Handler for SUVI solar imagery
+//!
+//! The Solar Ultraviolet Imager takes pictures of the sun in six extreme ultraviolet bands (like
+//! `Fe195`, for iron at 195 Å).  The raw images are dim and gray, so they're stretched, and then
+//! colored like the usual false color solar images (a different color for each band), and written
+//! to `suvi/<band>/`.  A directory can be made into a loop with the `timelapse` command.
+use std::path::{Path, PathBuf};
+
+use log::info;
+
+use crate::{
+    annotation::LritFilename,
+    events::{EventSender, ImageEvents},
+    lrit::LRIT,
+    storage::{self, SharedStorage},
+    xmp::ImageMetadata,
+};
+
+use super::{image::save_png, text::update_latest_symlink, Handler, HandlerError};
+
+/// The color each band is shaded towards, at half brightness
+const BAND_COLORS: &[(&str, [u8; 3])] = &[
+    ("Fe093", [40, 200, 90]),
+    ("Fe131", [0, 170, 200]),
+    ("Fe171", [230, 180, 40]),
+    ("Fe195", [200, 120, 60]),
+    ("Fe284", [230, 220, 60]),
+    ("He303", [230, 70, 40]),
+];
+
+/// The fraction of the darkest and brightest pixels that are clipped when stretching an image
+const CLIP_FRACTION: f64 = 0.005;
+
+pub struct SuviHandler {
+    output_root: PathBuf,
+    /// Also treat images with this NOAA product ID as SUVI images
+    product_id: Option<u16>,
+    events: Option<ImageEvents>,
+    storage: SharedStorage,
+}
+
+impl SuviHandler {
+    pub fn new(root: impl AsRef<Path>) -> SuviHandler {
+        SuviHandler {
+            output_root: root.as_ref().to_path_buf(),
+            product_id: None,
+            events: None,
+            storage: storage::local(),
+        }
+    }
+
+    /// Recognize SUVI images by their NOAA product ID, as well as by their GOES-R product name
+    /// (like `OR_SUVI-L1b-Fe195_G16_s…`)
+    ///
+    /// The band of images that don't have a GOES-R name is taken from the `Channel` in their
+    /// ancillary text.
+    pub fn with_product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    /// Send an [`ImageCompleteEvent`](crate::events::ImageCompleteEvent) for every image
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(ImageEvents::new(sender));
+        self
+    }
+
+    /// Write images to `storage` instead of the local filesystem (the "latest" symlinks are still
+    /// local)
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// The band of a SUVI image, or `None` if this isn't one
+    fn band(&self, lrit: &LRIT, meta: &ImageMetadata) -> Option<String> {
+        if let LritFilename::GoesR(goes) = lrit.headers.annotation.as_ref()?.parsed() {
+            if goes.instrument == "SUVI" {
+                return Some(goes.product);
+            }
+        }
+        let product_id = lrit.headers.noaa.as_ref().map(|n| n.product_id);
+        if self.product_id.is_some() && product_id == self.product_id {
+            return Some(meta.band.clone().unwrap_or_else(|| "unknown".to_string()));
+        }
+        None
+    }
+}
+
+/// Returns true if this annotation is a SUVI product name
+pub(crate) fn is_suvi(annotation: &str) -> bool {
+    matches!(LritFilename::parse(annotation), LritFilename::GoesR(goes) if goes.instrument == "SUVI")
+}
+
+/// Stretches an image so the darkest and brightest pixels (ignoring a few outliers) are black and
+/// white, and colors it for its band
+pub fn colorize(img: &image::GrayImage, band: &str) -> image::RgbImage {
+    let mut histogram = [0usize; 256];
+    for p in img.pixels() {
+        histogram[p.0[0] as usize] += 1;
+    }
+    let clip = (img.len() as f64 * CLIP_FRACTION) as usize;
+    let percentile = |from_top: bool| {
+        let mut seen = 0;
+        let mut levels: Box<dyn Iterator<Item = usize>> = if from_top {
+            Box::new((0..256).rev())
+        } else {
+            Box::new(0..256)
+        };
+        levels
+            .find(|level| {
+                seen += histogram[*level];
+                seen > clip
+            })
+            .unwrap_or(0) as f64
+    };
+    let (low, high) = (percentile(false), percentile(true));
+    let range = (high - low).max(1.0);
+
+    let color = BAND_COLORS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(band))
+        .map_or([128, 128, 128], |(_, color)| *color);
+    image::RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let t = ((img.get_pixel(x, y).0[0] as f64 - low) / range).clamp(0.0, 1.0);
+        // black to the band color, then the band color to white
+        image::Rgb(color.map(|c| {
+            let c = c as f64;
+            let v = if t < 0.5 {
+                c * t * 2.0
+            } else {
+                c + (255.0 - c) * (t - 0.5) * 2.0
+            };
+            v.round() as u8
+        }))
+    })
+}
+
+impl Handler for SuviHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 0 {
+            return Err(HandlerError::Skipped);
+        }
+        let meta = ImageMetadata::from_lrit(lrit);
+        let band = self.band(lrit, &meta).ok_or(HandlerError::Skipped)?;
+        // segmented images are left to the ImageHandler
+        if lrit.headers.img_segment.is_some() {
+            return Err(HandlerError::Skipped);
+        }
+        let ihs = lrit
+            .headers
+            .img_strucutre
+            .as_ref()
+            .ok_or(HandlerError::MissingHeader("image structure"))?;
+        if ihs.bits_per_pixel != 8 {
+            return Err(HandlerError::Parse("unsupported bits per pixel"));
+        }
+        let mut data = lrit.data.clone();
+        data.resize(ihs.num_columns as usize * ihs.num_lines as usize, 0);
+        let img = image::GrayImage::from_raw(ihs.num_columns as u32, ihs.num_lines as u32, data)
+            .ok_or(HandlerError::Parse("failed to create SUVI image"))?;
+
+        let dir = self.output_root.join("suvi").join(&band);
+        std::fs::create_dir_all(&dir)?;
+        let stem = meta.product.split('.').next().unwrap_or_default();
+        let out_name = dir.join(stem).with_extension("png");
+        let colorized = image::DynamicImage::ImageRgb8(colorize(&img, &band));
+        save_png(&*self.storage, &colorized, &out_name)?;
+        update_latest_symlink(&self.output_root.join("suvi"), &format!("{}.png", band), &out_name)?;
+        info!("suvi, {}", out_name.display());
+        if let Some(events) = &mut self.events {
+            events.image_complete(&meta, out_name, 1, 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{colorize, SuviHandler};
+    use crate::{handlers::Handler, lrit::LRIT, sim::LritBuilder, timelapse::Timelapse};
+
+    #[test]
+    fn test_suvi() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = SuviHandler::new(dir.path()).with_product_id(99);
+        let pixels: Vec<u8> = (0..64).map(|i| 60 + i).collect();
+        for annotation in [
+            "OR_SUVI-L1b-Fe195_G16_s20221241800205_e20221241800215_c20221241800230.lrit",
+            "OR_SUVI-L1b-Fe195_G16_s20221241804205_e20221241804215_c20221241804230.lrit",
+        ] {
+            let bytes = LritBuilder::new(0)
+                .image_structure(8, 8, 8)
+                .annotation(annotation)
+                .build(&pixels);
+            handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).unwrap();
+        }
+        // recognized by product ID
+        let bytes = LritBuilder::new(0)
+            .image_structure(8, 8, 8)
+            .annotation("suvi_he303.lrit")
+            .ancillary_text("Channel=He303")
+            .noaa(99, 0, 0, 0)
+            .build(&pixels);
+        handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).unwrap();
+        assert!(dir.path().join("suvi/He303/suvi_he303.png").exists());
+        // but not other images
+        let bytes = LritBuilder::new(0)
+            .image_structure(8, 8, 8)
+            .annotation("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit")
+            .build(&pixels);
+        assert!(handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).is_err());
+
+        let img = image::open(dir.path().join("suvi/latest-Fe195.png")).unwrap().to_rgb8();
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0]);
+        assert_eq!(img.get_pixel(7, 7).0, [255, 255, 255]);
+        let mid = img.get_pixel(0, 4).0;
+        assert!(mid[0] > mid[2], "{:?}", mid);
+
+        let frames = Timelapse::new().frames_in_dir(&dir.path().join("suvi/Fe195")).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].time < frames[1].time);
+
+        // an image that's all one level doesn't divide by zero
+        let flat = colorize(&image::GrayImage::new(2, 2), "Fe171");
+        assert_eq!(flat.get_pixel(0, 0).0, [0, 0, 0]);
+    }
+}
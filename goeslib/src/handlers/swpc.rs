@@ -0,0 +1,199 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+
+use crate::{
+    emwin::swpc::{NoaaScale, SpaceWeatherMessage},
+    events::{Event, EventSender},
+    lrit::LRIT,
+};
+
+use super::{spawn_command, text_files, Handler, HandlerError};
+
+/// Decodes SWPC space weather alerts, warnings, watches, and summaries
+///
+/// Every message is appended as a line of JSON to `space-weather.jsonl` in the output root.
+/// Messages are often sent more than once, so repeats (with the same code and serial number) are
+/// dropped.
+pub struct SpaceWeatherHandler {
+    output_root: PathBuf,
+    events: Option<EventSender>,
+    hook: Option<AlertHook>,
+    /// The code and serial number of every message seen so far
+    seen: HashSet<(String, Option<u32>)>,
+}
+
+/// A shell command to run for new messages
+struct AlertHook {
+    command: String,
+    /// Only run for messages that reach one of these levels (or for all messages, if empty)
+    scales: Vec<NoaaScale>,
+}
+
+impl AlertHook {
+    fn wants(&self, msg: &SpaceWeatherMessage) -> bool {
+        self.scales.is_empty() || matches!(msg.scale, Some(s) if self.scales.iter().any(|min| s.reaches(min)))
+    }
+
+    /// Runs the command in the background, with the message as JSON on stdin
+    fn run(&self, msg: &SpaceWeatherMessage) -> Result<(), HandlerError> {
+        let json = serde_json::to_vec(msg).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        let env = [
+            ("SWPC_CODE", msg.code.clone()),
+            ("SWPC_KIND", format!("{:?}", msg.kind)),
+            ("SWPC_SERIAL", msg.serial.map(|s| s.to_string()).unwrap_or_default()),
+            ("SWPC_SCALE", msg.scale.map(|s| s.to_string()).unwrap_or_default()),
+            ("SWPC_HEADLINE", msg.headline.clone()),
+        ];
+        spawn_command(&self.command, &env, json)?;
+        Ok(())
+    }
+}
+
+impl SpaceWeatherHandler {
+    pub fn new(root: impl AsRef<Path>) -> SpaceWeatherHandler {
+        SpaceWeatherHandler {
+            output_root: root.as_ref().to_path_buf(),
+            events: None,
+            hook: None,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Send an [`Event::SpaceWeather`] for every new message
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Run a shell command for new messages, like a script that pages a radio operator
+    ///
+    /// The command only runs for messages that reach one of `scales` (like G3, for a strong
+    /// geomagnetic storm), or for every message if `scales` is empty.  The message is given to the
+    /// command as JSON on stdin, and in the `SWPC_CODE`, `SWPC_KIND`, `SWPC_SERIAL`, `SWPC_SCALE`,
+    /// and `SWPC_HEADLINE` environment variables.
+    pub fn with_alert_command(
+        mut self,
+        command: impl Into<String>,
+        scales: impl IntoIterator<Item = NoaaScale>,
+    ) -> Self {
+        self.hook = Some(AlertHook {
+            command: command.into(),
+            scales: scales.into_iter().collect(),
+        });
+        self
+    }
+}
+
+/// Returns true if the WMO heading of an EMWIN product (like `A_WOXX01KWNP...`) is from SWPC
+pub(crate) fn is_swpc_bulletin(annotation: &str) -> bool {
+    annotation.get(8..12) == Some("KWNP")
+}
+
+impl Handler for SpaceWeatherHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if lrit.headers.primary.filetype_code != 2 {
+            return Err(HandlerError::Skipped);
+        }
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+        if !is_swpc_bulletin(annotation) {
+            return Err(HandlerError::Skipped);
+        }
+
+        for (_, data) in text_files(lrit, annotation)? {
+            let msg = match SpaceWeatherMessage::parse(&String::from_utf8_lossy(&data)) {
+                Some(msg) => msg,
+                None => continue,
+            };
+            if !self.seen.insert((msg.code.clone(), msg.serial)) {
+                continue;
+            }
+            info!("Space weather: {}", msg.headline);
+
+            let line = serde_json::to_string(&msg).map_err(|e| HandlerError::Other(Box::new(e)))?;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.output_root.join("space-weather.jsonl"))?;
+            writeln!(file, "{}", line)?;
+
+            if let Some(hook) = self.hook.as_ref().filter(|hook| hook.wants(&msg)) {
+                hook.run(&msg)?;
+            }
+            if let Some(events) = &self.events {
+                // a closed channel is not an error; the event is simply dropped
+                let _ = events.send(Event::SpaceWeather(msg));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use super::SpaceWeatherHandler;
+    use crate::{events::Event, handlers::Handler, lrit::LRIT, sim::LritBuilder};
+
+    fn text(name: &str, data: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data.as_bytes())).unwrap()
+    }
+
+    #[test]
+    fn test_space_weather_handler() {
+        let dir = tempfile::tempdir().unwrap();
+        let hook_out = dir.path().join("hook.txt");
+        let (s, events) = channel();
+        let mut handler = SpaceWeatherHandler::new(dir.path()).with_events(s).with_alert_command(
+            format!("echo $SWPC_SCALE $SWPC_CODE >> {}", hook_out.display()),
+            vec!["G2".parse().unwrap()],
+        );
+
+        let alert = |serial: u32, scale: &str| {
+            text(
+                "A_WOXX01KWNP101207_C_KWIN_20220510120713_106868-3-ALTK05XX.TXT",
+                &format!(
+                    "WOXX01 KWNP 101207\nSpace Weather Message Code: ALTK06\nSerial Number: {}\n\
+                     Issue Time: 2022 May 10 1207 UTC\n\nALERT: Geomagnetic K-index of 6\n\
+                     Threshold Reached: 2022 May 10 1159 UTC\nNOAA Scale: {} - Moderate\n",
+                    serial, scale
+                ),
+            )
+        };
+        handler.handle(&alert(1, "G2")).unwrap();
+        // a repeat is dropped
+        handler.handle(&alert(1, "G2")).unwrap();
+        // and a lower level doesn't run the hook
+        handler.handle(&alert(2, "G1")).unwrap();
+
+        let received: Vec<_> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::SpaceWeather(msg) => Some(msg),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].headline, "ALERT: Geomagnetic K-index of 6");
+        let jsonl = std::fs::read_to_string(dir.path().join("space-weather.jsonl")).unwrap();
+        assert_eq!(jsonl.lines().count(), 2);
+
+        // the hook runs in the background
+        for _ in 0..50 {
+            if hook_out.exists() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert_eq!(std::fs::read_to_string(&hook_out).unwrap(), "G2 ALTK06\n");
+    }
+}
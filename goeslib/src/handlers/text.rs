@@ -1,24 +1,242 @@
+#[cfg(feature = "zip")]
+use std::io::Read;
 use std::{
+    fs::OpenOptions,
     io::Write,
     path::{Path, PathBuf},
 };
 
-use log::info;
+use chrono::Utc;
+use log::{info, warn};
 
-use crate::{emwin, lrit::LRIT};
+use crate::{
+    annotation::LritFilename,
+    emwin::ParsedEmwinName,
+    events::{Event, EventSender, TextWrittenEvent},
+    index::{IndexRecord, ProductIndex},
+    lrit::LRIT,
+    storage::{self, SharedStorage},
+    writer::WriteQueue,
+};
+
+use super::{board::glob_match, CollisionDetector, DuplicatePolicy, Handler, HandlerError, HeaderPassthrough};
 
-use super::{Handler, HandlerError};
+/// Points `latest-<name>` (in `root`) at the most recently written copy of a product
+///
+/// This is how EMWIN products (and anything else routed like them) are made easy to find without
+/// knowing their full timestamped filename.
+pub(crate) fn update_latest_symlink(root: &Path, name: &str, target: &Path) -> Result<(), HandlerError> {
+    let latest_symlink = root.join(format!("latest-{}", name));
+    // a link to a file that isn't on local disk doesn't "exist", but still has to be replaced
+    if latest_symlink.symlink_metadata().is_ok() {
+        std::fs::remove_file(&latest_symlink)?;
+    }
+    std::os::unix::fs::symlink(target, latest_symlink)?;
+    Ok(())
+}
 
 pub struct TextHandler {
     output_root: PathBuf,
+    headers: HeaderPassthrough,
+    queue: Option<WriteQueue>,
+    storage: SharedStorage,
+    events: Option<EventSender>,
+    /// Patterns of EMWIN products to keep a history of
+    history: Vec<String>,
+    duplicates: DuplicatePolicy,
+    collisions: Option<CollisionDetector>,
+    index: Option<ProductIndex>,
 }
 
 impl TextHandler {
     pub fn new(root: impl AsRef<Path>) -> TextHandler {
         TextHandler {
             output_root: root.as_ref().to_path_buf(),
+            headers: HeaderPassthrough::None,
+            queue: None,
+            storage: storage::local(),
+            events: None,
+            history: Vec::new(),
+            duplicates: DuplicatePolicy::Overwrite,
+            collisions: None,
+            index: None,
         }
     }
+
+    /// Also append every issuance of these EMWIN products to `history/<name>.log`
+    ///
+    /// This is for products that are reissued through the day (like zone forecasts), to be able
+    /// to look back at earlier issuances.  Patterns are matched against the short EMWIN product
+    /// name (like `ZFPPHIPA`), and can use `*` and `?` wildcards.
+    pub fn with_history(mut self, patterns: &[String]) -> Self {
+        self.history = patterns.to_vec();
+        self
+    }
+
+    /// Send a [`TextWrittenEvent`] for every file that's written
+    pub fn with_events(mut self, sender: EventSender) -> Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Write products through a [`BatchWriter`](crate::writer::BatchWriter) instead of right away
+    ///
+    /// The "latest" symlinks are still updated right away, so they can briefly point at files
+    /// that haven't been written yet.
+    pub fn with_writer(mut self, queue: WriteQueue) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Write products to `storage` instead of the local filesystem
+    ///
+    /// The "latest" symlinks and history logs are still on the local filesystem.
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Keep the original LRIT headers with each product
+    ///
+    /// Decompressed products are written with the headers of the compressed file.
+    pub fn with_header_passthrough(mut self, mode: HeaderPassthrough) -> Self {
+        self.headers = mode;
+        self
+    }
+
+    /// What to do when a product's file already exists (it's overwritten by default)
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Write a product under another name if a different product was written to its file
+    /// recently, see [`CollisionDetector`]
+    pub fn with_collision_detector(mut self, detector: CollisionDetector) -> Self {
+        self.collisions = Some(detector);
+        self
+    }
+
+    /// Record products whose file already existed (or collided with another product's) in the
+    /// product index under `root`
+    pub fn with_index(mut self, root: impl AsRef<Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+}
+
+/// How the data of a text product is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextCompression {
+    None,
+    Zip,
+    Zlib,
+    Gzip,
+    /// A compression flag we don't know how to handle
+    Unknown(u8),
+}
+
+impl TextCompression {
+    /// Works out how a text product is compressed
+    ///
+    /// The NOAA header only says that the data is compressed (and is supposed to say ZIP, which is
+    /// code 10), but some products actually use zlib or gzip, so look at the data to be sure.
+    ///
+    /// Ref: 5_LRIT_Mission-data.pdf Table 4 (NOAA compression codes)
+    pub fn detect(noaa_compression: u8, data: &[u8]) -> TextCompression {
+        if noaa_compression == 0 {
+            return TextCompression::None;
+        }
+        match data {
+            [b'P', b'K', 3, 4, ..] => TextCompression::Zip,
+            [0x1f, 0x8b, ..] => TextCompression::Gzip,
+            // zlib: compression method 8 (deflate), and a header checksum that's a multiple of 31
+            [cmf, flg, ..] if cmf & 0x0f == 8 && u16::from_be_bytes([*cmf, *flg]).rem_euclid(31) == 0 => {
+                TextCompression::Zlib
+            }
+            _ if noaa_compression == 10 => TextCompression::Zip,
+            _ => TextCompression::Unknown(noaa_compression),
+        }
+    }
+}
+
+impl TextHandler {
+    /// Writes one text product, and updates the "latest" symlink if it's an EMWIN product
+    fn write_product(&mut self, lrit: &LRIT, filename: &str, data: &[u8]) -> Result<(), HandlerError> {
+        let output_path = match self.place(lrit, filename, data)? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        self.headers
+            .write_raw(self.queue.as_ref(), &*self.storage, lrit, &output_path, data)?;
+        self.written(lrit.vcid, filename, &output_path, data)
+    }
+
+    /// Where to write a product named `filename`, or `None` if it's a duplicate to skip
+    fn place(&mut self, lrit: &LRIT, filename: &str, data: &[u8]) -> Result<Option<PathBuf>, HandlerError> {
+        let mut path = self.output_root.join(filename);
+        if let Some(collisions) = &mut self.collisions {
+            let (placed, collision) = collisions.check(&*self.storage, lrit, path, data, Utc::now());
+            path = placed;
+            if let (Some(collision), Some(index)) = (collision, &self.index) {
+                if let Some(mut record) = IndexRecord::from_lrit(lrit, Utc::now()) {
+                    record.collision = Some(collision);
+                    index.append(&record)?;
+                }
+            }
+        }
+        self.duplicates.place(&*self.storage, self.index.as_ref(), lrit, path)
+    }
+
+    /// Links a written file if it's an EMWIN product, and sends an event for it
+    fn written(&self, vcid: u8, filename: &str, output_path: &Path, data: &[u8]) -> Result<(), HandlerError> {
+        self.link_emwin(vcid, filename, output_path, data)?;
+        if let Some(events) = &self.events {
+            let _ = events.send(Event::TextWritten(TextWrittenEvent {
+                product: filename.to_string(),
+                path: output_path.to_path_buf(),
+            }));
+        }
+        Ok(())
+    }
+
+    fn link_emwin(&self, vcid: u8, filename: &str, output_path: &Path, data: &[u8]) -> Result<(), HandlerError> {
+        // Is this a EMWIN product?
+        if vcid == 20 || vcid == 21 || vcid == 22 {
+            if let LritFilename::Emwin(parsed_emwin) = LritFilename::parse(filename) {
+                update_latest_symlink(&self.output_root, &parsed_emwin.legacy_filename, output_path)?;
+                self.append_history(&parsed_emwin, filename, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends an issuance of a product to its history log, if it's one to keep a history of
+    fn append_history(&self, emwin: &ParsedEmwinName, filename: &str, data: &[u8]) -> Result<(), HandlerError> {
+        let name = emwin.legacy_filename.split('.').next().unwrap_or_default();
+        if !self.history.iter().any(|p| glob_match(p.as_bytes(), name.as_bytes())) {
+            return Ok(());
+        }
+        let dir = self.output_root.join("history");
+        std::fs::create_dir_all(&dir)?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(format!("{}.log", name)))?;
+        let mut entry = format!(
+            "==== Issued {} ({}) ====\n",
+            emwin.date.format("%Y-%m-%d %H:%M:%S UTC"),
+            filename
+        )
+        .into_bytes();
+        entry.extend_from_slice(data);
+        if !data.ends_with(b"\n") {
+            entry.push(b'\n');
+        }
+        entry.push(b'\n');
+        log.write_all(&entry)?;
+        Ok(())
+    }
 }
 
 impl Handler for TextHandler {
@@ -26,73 +244,301 @@ impl Handler for TextHandler {
         if lrit.headers.primary.filetype_code != 2 {
             return Err(HandlerError::Skipped);
         }
+        let annotation = match &lrit.headers.annotation {
+            Some(ann) => &ann.text,
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+
         // before trying to print this message, see if it's compressed by looking
+        let noaa_compression = lrit.headers.noaa.as_ref().map_or(0, |noaa| noaa.noaa_compression);
 
-        let compressed = if let Some(noaa) = &lrit.headers.noaa {
-            noaa.noaa_compression != 0
-        } else {
-            false
-        };
+        match TextCompression::detect(noaa_compression, &lrit.data) {
+            TextCompression::None => self.write_product(lrit, annotation, &lrit.data)?,
+            #[cfg(feature = "zip")]
+            TextCompression::Zip => {
+                let mut cur = std::io::Cursor::new(&lrit.data);
+                let mut archive = zip::read::ZipArchive::new(&mut cur)?;
 
-        if compressed {
-            let mut cur = std::io::Cursor::new(&lrit.data);
-            let mut archive = zip::read::ZipArchive::new(&mut cur)?;
-
-            //info!("zip read: Ok(archive) {}", archive.len());
-            for idx in 0..archive.len() {
-                if let Ok(mut file) = archive.by_index(idx) {
-                    //info!("Zip archive file {}", file.name());
-                    let output_path = self.output_root.join(file.mangled_name());
-                    let filename = file.mangled_name();
-                    let filename = filename.to_string_lossy();
-                    let mut output_file = std::fs::File::create(&output_path)?;
-                    std::io::copy(&mut file, &mut output_file)?;
-
-                    if lrit.vcid == 20 || lrit.vcid == 21 || lrit.vcid == 22 {
-                        if filename.starts_with("A_") || filename.starts_with("Z_") {
-                            if let Some(parsed_emwin) = emwin::ParsedEmwinName::parse(&filename) {
-                                let latest_symlink = self
-                                    .output_root
-                                    .join(format!("latest-{}", parsed_emwin.legacy_filename));
-                                if latest_symlink.exists() {
-                                    std::fs::remove_file(&latest_symlink)?;
-                                }
-                                std::os::unix::fs::symlink(&output_path, latest_symlink)?;
-                            }
-                        }
-                    }
-                }
-            }
-        } else {
-            // try to print data
-            //let s = String::from_utf8_lossy(&self.bytes[offset as usize..]);
-            if let Some(annotation) = &lrit.headers.annotation {
-                let output_path = self.output_root.join(&annotation.text);
-                if let Ok(mut output_file) = std::fs::File::create(&output_path) {
-                    output_file.write_all(&lrit.data)?;
-                }
+                for idx in 0..archive.len() {
+                    if let Ok(mut file) = archive.by_index(idx) {
+                        let filename = file.mangled_name();
+                        let filename = filename.to_string_lossy();
+                        let mut data = Vec::new();
+                        file.read_to_end(&mut data)?;
+                        let output_path = match self.place(lrit, &filename, &data)? {
+                            Some(path) => path,
+                            None => continue,
+                        };
+                        self.storage.write(&output_path, &data)?;
+                        self.headers
+                            .write_sidecar(self.queue.as_ref(), &*self.storage, lrit, &output_path)?;
 
-                // Is this a EMWIN product?
-                if lrit.vcid == 20 || lrit.vcid == 21 || lrit.vcid == 22 {
-                    if annotation.text.starts_with("A_") || annotation.text.starts_with("Z_") {
-                        if let Some(parsed_emwin) = emwin::ParsedEmwinName::parse(&annotation.text) {
-                            let latest_symlink = self
-                                .output_root
-                                .join(format!("latest-{}", parsed_emwin.legacy_filename));
-                            if latest_symlink.exists() {
-                                std::fs::remove_file(&latest_symlink)?;
-                            }
-                            std::os::unix::fs::symlink(&output_path, latest_symlink)?;
-                        }
+                        self.written(lrit.vcid, &filename, &output_path, &data)?;
                     }
                 }
             }
-            //info!("uncompressed string data: {}", s);
+            #[cfg(not(feature = "zip"))]
+            TextCompression::Zip => return Err(NEEDS_ZIP),
+            compression @ (TextCompression::Zlib | TextCompression::Gzip) => {
+                let data = inflate(compression, &lrit.data)?;
+                self.write_product(lrit, strip_compressed_ext(annotation), &data)?;
+            }
+            TextCompression::Unknown(code) => {
+                warn!(
+                    "Unknown compression {} for text product {}, writing it as-is",
+                    code, annotation
+                );
+                self.write_product(lrit, annotation, &lrit.data)?;
+            }
         }
 
-        if let Some(ann) = &lrit.headers.annotation {
-            info!("Wrote {}", ann.text);
-        }
+        info!("Wrote {}", annotation);
         Ok(())
     }
 }
+
+/// What compressed text products fail with, when goeslib is built without the `zip` feature
+#[cfg(not(feature = "zip"))]
+const NEEDS_ZIP: HandlerError = HandlerError::Parse("compressed text products need the zip feature");
+
+/// Decompresses a text product, returning the name and contents of each file in it
+///
+/// Most products hold a single file, named after the annotation (minus any compression extension),
+/// but ZIP compressed products can hold several.  Products with an unknown compression are
+/// returned as-is.
+pub(crate) fn text_files(lrit: &LRIT, annotation: &str) -> Result<Vec<(String, Vec<u8>)>, HandlerError> {
+    let noaa_compression = lrit.headers.noaa.as_ref().map_or(0, |noaa| noaa.noaa_compression);
+    let data = match TextCompression::detect(noaa_compression, &lrit.data) {
+        TextCompression::None | TextCompression::Unknown(_) => {
+            return Ok(vec![(annotation.to_string(), lrit.data.clone())]);
+        }
+        TextCompression::Zip => return unzip(&lrit.data),
+        compression => inflate(compression, &lrit.data)?,
+    };
+    Ok(vec![(strip_compressed_ext(annotation).to_string(), data)])
+}
+
+/// The name and contents of each file in a ZIP compressed product
+#[cfg(feature = "zip")]
+fn unzip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, HandlerError> {
+    let mut archive = zip::read::ZipArchive::new(std::io::Cursor::new(data))?;
+    let mut files = Vec::new();
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        files.push((file.mangled_name().to_string_lossy().into_owned(), data));
+    }
+    Ok(files)
+}
+
+#[cfg(not(feature = "zip"))]
+fn unzip(_data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, HandlerError> {
+    Err(NEEDS_ZIP)
+}
+
+/// Decompresses a zlib or gzip compressed product
+#[cfg(feature = "zip")]
+fn inflate(compression: TextCompression, data: &[u8]) -> Result<Vec<u8>, HandlerError> {
+    let mut out = Vec::new();
+    if compression == TextCompression::Gzip {
+        flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+    } else {
+        flate2::read::ZlibDecoder::new(data).read_to_end(&mut out)?;
+    }
+    Ok(out)
+}
+
+#[cfg(not(feature = "zip"))]
+fn inflate(_compression: TextCompression, _data: &[u8]) -> Result<Vec<u8>, HandlerError> {
+    Err(NEEDS_ZIP)
+}
+
+/// Removes a `.gz` or `.z` extension (if there is one)
+pub(crate) fn strip_compressed_ext(name: &str) -> &str {
+    let lower = name.to_ascii_lowercase();
+    if lower.ends_with(".gz") {
+        &name[..name.len() - 3]
+    } else if lower.ends_with(".z") {
+        &name[..name.len() - 2]
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_compressed_ext, TextHandler};
+    use crate::{
+        handlers::{CollisionDetector, DuplicatePolicy, Handler, HeaderPassthrough},
+        index::ProductIndex,
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    #[cfg(feature = "zip")]
+    fn test_detect_compression() {
+        use super::TextCompression;
+        use std::io::Write;
+
+        let text = b"hello, world";
+        assert_eq!(TextCompression::detect(0, text), TextCompression::None);
+        assert_eq!(TextCompression::detect(10, text), TextCompression::Zip);
+        assert_eq!(TextCompression::detect(3, text), TextCompression::Unknown(3));
+        assert_eq!(TextCompression::detect(10, b"PK\x03\x04rest"), TextCompression::Zip);
+
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(text).unwrap();
+        assert_eq!(
+            TextCompression::detect(10, &zlib.finish().unwrap()),
+            TextCompression::Zlib
+        );
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(text).unwrap();
+        assert_eq!(
+            TextCompression::detect(10, &gzip.finish().unwrap()),
+            TextCompression::Gzip
+        );
+    }
+
+    #[test]
+    fn test_strip_compressed_ext() {
+        assert_eq!(strip_compressed_ext("A_FOO.TXT.gz"), "A_FOO.TXT");
+        assert_eq!(strip_compressed_ext("A_FOO.TXT.Z"), "A_FOO.TXT");
+        assert_eq!(strip_compressed_ext("A_FOO.TXT"), "A_FOO.TXT");
+    }
+
+    #[test]
+    fn test_header_passthrough() {
+        let lrit = LRIT::from_bytes(20, &LritBuilder::new(2).annotation("test.txt").build(b"hello")).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("test.txt");
+
+        TextHandler::new(dir.path()).handle(&lrit).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), b"hello");
+        assert!(!dir.path().join("test.txt.hdr").exists());
+
+        let mut handler = TextHandler::new(dir.path()).with_header_passthrough(HeaderPassthrough::Prepend);
+        handler.handle(&lrit).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), lrit.to_bytes());
+
+        let mut handler = TextHandler::new(dir.path()).with_header_passthrough(HeaderPassthrough::Sidecar);
+        handler.handle(&lrit).unwrap();
+        assert_eq!(std::fs::read(&out).unwrap(), b"hello");
+        assert_eq!(
+            std::fs::read(dir.path().join("test.txt.hdr")).unwrap(),
+            lrit.raw_headers
+        );
+
+        let mut handler = TextHandler::new(dir.path()).with_header_passthrough(HeaderPassthrough::Json);
+        handler.handle(&lrit).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(dir.path().join("test.txt.hdr.json")).unwrap()).unwrap();
+        assert_eq!(json["vcid"], 20);
+        assert_eq!(json["headers"]["annotation"]["text"], "test.txt");
+        assert_eq!(json["headers"]["primary"]["filetype_code"], 2);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let lrit = |text: &[u8]| LRIT::from_bytes(2, &LritBuilder::new(2).annotation("dup.txt").build(text)).unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let mut handler = TextHandler::new(dir.path())
+            .with_duplicate_policy(DuplicatePolicy::Version)
+            .with_index(dir.path());
+        for text in ["first", "second", "third"] {
+            handler.handle(&lrit(text.as_bytes())).unwrap();
+        }
+        assert_eq!(
+            (read("dup.txt"), read("dup-2.txt"), read("dup-3.txt")),
+            ("first".into(), "second".into(), "third".into())
+        );
+
+        let mut handler = TextHandler::new(dir.path())
+            .with_duplicate_policy(DuplicatePolicy::Skip)
+            .with_index(dir.path());
+        handler.handle(&lrit(b"fourth")).unwrap();
+        assert_eq!(read("dup.txt"), "first");
+        TextHandler::new(dir.path()).handle(&lrit(b"fifth")).unwrap();
+        assert_eq!(read("dup.txt"), "fifth");
+
+        let records = ProductIndex::new(dir.path())
+            .read_day(chrono::Utc::now().date_naive())
+            .unwrap();
+        let duplicates: Vec<_> = records
+            .iter()
+            .map(|r| {
+                let duplicate = r.duplicate.as_ref().unwrap();
+                (
+                    duplicate.policy,
+                    duplicate.path.as_ref().map(|p| p.file_name().unwrap().to_owned()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            duplicates,
+            [
+                (DuplicatePolicy::Version, Some("dup-2.txt".into())),
+                (DuplicatePolicy::Version, Some("dup-3.txt".into())),
+                (DuplicatePolicy::Skip, None),
+            ]
+        );
+
+        // a different product that would overwrite one that was just written is written under
+        // another name, but the same one sent again isn't
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = TextHandler::new(dir.path())
+            .with_collision_detector(CollisionDetector::new())
+            .with_index(dir.path());
+        for text in ["first", "first", "second"] {
+            handler.handle(&lrit(text.as_bytes())).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!((read("dup.txt"), read("dup-2.txt")), ("first".into(), "second".into()));
+        let collisions: Vec<_> = ProductIndex::new(dir.path())
+            .read_day(chrono::Utc::now().date_naive())
+            .unwrap()
+            .into_iter()
+            .filter_map(|record| record.collision)
+            .collect();
+        assert_eq!(collisions.len(), 1);
+        let collision = &collisions[0];
+        assert_eq!(collision.written, dir.path().join("dup-2.txt"));
+        assert_eq!(collision.other, "dup.txt");
+    }
+
+    #[test]
+    fn test_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = TextHandler::new(dir.path()).with_history(&["ZFP*".to_string()]);
+        for (name, text) in [
+            (
+                "A_FPUS51KPHI071000_C_KWIN_20220507100012_000001-3-ZFPPHIPA.TXT",
+                "first",
+            ),
+            (
+                "A_FXUS61KPHI071005_C_KWIN_20220507100512_000002-3-AFDPHIPA.TXT",
+                "not kept",
+            ),
+            (
+                "A_FPUS51KPHI071600_C_KWIN_20220507160012_000003-3-ZFPPHIPA.TXT",
+                "second\n",
+            ),
+        ] {
+            let lrit = LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(text.as_bytes())).unwrap();
+            handler.handle(&lrit).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("history/ZFPPHIPA.log")).unwrap(),
+            "==== Issued 2022-05-07 10:00:12 UTC (A_FPUS51KPHI071000_C_KWIN_20220507100012_000001-3-ZFPPHIPA.TXT) ====\n\
+             first\n\n\
+             ==== Issued 2022-05-07 16:00:12 UTC (A_FPUS51KPHI071600_C_KWIN_20220507160012_000003-3-ZFPPHIPA.TXT) ====\n\
+             second\n\n"
+        );
+        assert!(!dir.path().join("history/AFDPHIPA.log").exists());
+    }
+}
@@ -0,0 +1,139 @@
+//! Checking LRIT files before they're handled, and quarantining the suspect ones
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    deadletter::sanitize,
+    index::{IndexRecord, ProductIndex},
+    lrit::LRIT,
+};
+
+/// Checks LRIT files before any handler sees them, see [`Dispatcher::with_verifier`](super::Dispatcher::with_verifier)
+///
+/// Files that a verifier finds suspect (like ones whose internal checksums don't match) aren't
+/// handled, and are saved to the [`Quarantine`] instead.
+pub trait Verifier: Send {
+    /// Returns why a file is suspect, or `None` if it looks fine
+    fn verify(&mut self, lrit: &LRIT) -> Option<String>;
+
+    /// A short name for this verifier, used in log messages and the index
+    fn name(&self) -> &str {
+        let full = std::any::type_name::<Self>();
+        full.rsplit("::").next().unwrap_or(full)
+    }
+}
+
+/// Why a product was quarantined
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suspect {
+    /// The name of the verifier (from [`Verifier::name`])
+    pub verifier: String,
+    pub reason: String,
+    /// Where the LRIT file was saved, if there's a quarantine
+    pub path: Option<PathBuf>,
+}
+
+/// The sidecar that is written next to each quarantined LRIT file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineInfo {
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub filetype_code: u8,
+    pub annotation: Option<String>,
+    pub verifier: String,
+    pub reason: String,
+}
+
+/// Checks the CRCs of compressed text products
+///
+/// ZIP and gzip compressed products have a CRC-32 of each file, and zlib compressed ones have an
+/// Adler-32, so a product that was corrupted on the way (but still passed the transport checks)
+/// fails to decompress.
+#[cfg(feature = "zip")]
+#[derive(Debug, Default)]
+pub struct ChecksumVerifier;
+
+#[cfg(feature = "zip")]
+impl Verifier for ChecksumVerifier {
+    fn verify(&mut self, lrit: &LRIT) -> Option<String> {
+        let annotation = lrit.headers.annotation.as_ref()?;
+        if lrit.headers.primary.filetype_code != 2 {
+            return None;
+        }
+        super::text_files(lrit, &annotation.text).err().map(|e| e.to_string())
+    }
+}
+
+/// Where suspect LRIT files are saved, in a `quarantine` directory under the output root
+///
+/// Each LRIT file is written with a JSON sidecar (a [`QuarantineInfo`]), like the
+/// [`DeadLetter`](crate::deadletter::DeadLetter) directory, so it can be looked at (or run through
+/// the handlers with `goesbox reprocess`) later.  With an index, a record with `suspect` set is
+/// added for each one.
+pub struct Quarantine {
+    dir: PathBuf,
+    index: Option<ProductIndex>,
+    /// Included in file names, so that several files in the same millisecond don't collide
+    counter: AtomicU32,
+}
+
+impl Quarantine {
+    pub fn new(root: impl AsRef<Path>) -> Quarantine {
+        Quarantine {
+            dir: root.as_ref().join("quarantine"),
+            index: None,
+            counter: AtomicU32::new(0),
+        }
+    }
+
+    /// Flag quarantined products in the index under `root`
+    pub fn with_index(mut self, root: impl AsRef<Path>) -> Self {
+        self.index = Some(ProductIndex::new(root));
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Saves a suspect LRIT file, returning the path it was saved to
+    pub fn write(&self, lrit: &LRIT, suspect: &mut Suspect) -> io::Result<PathBuf> {
+        let info = QuarantineInfo {
+            received: Utc::now(),
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            annotation: lrit.headers.annotation.as_ref().map(|a| a.text.clone()),
+            verifier: suspect.verifier.clone(),
+            reason: suspect.reason.clone(),
+        };
+        // file names start with the time, so that sorting them by name sorts them by age
+        let name = format!(
+            "{}-{:04}-{}",
+            info.received.format("%Y%m%dT%H%M%S%.3f"),
+            self.counter.fetch_add(1, Ordering::Relaxed) % 10000,
+            info.annotation
+                .as_deref()
+                .map(sanitize)
+                .unwrap_or_else(|| format!("vc{}-type{}", info.vcid, info.filetype_code))
+        );
+        let path = self.dir.join(format!("{}.lrit", name));
+        suspect.path = Some(path.clone());
+
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&path, lrit.to_bytes())?;
+        std::fs::write(path.with_extension("json"), serde_json::to_vec_pretty(&info)?)?;
+        if let Some(index) = &self.index {
+            if let Some(mut record) = IndexRecord::from_lrit(lrit, info.received) {
+                record.suspect = Some(suspect.clone());
+                index.append(&record)?;
+            }
+        }
+        Ok(path)
+    }
+}
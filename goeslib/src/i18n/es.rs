@@ -0,0 +1,969 @@
+//! Spanish (español)
+//!
+//! Warnings are "advertencias", watches are "vigilancias", and advisories are "avisos".  Names
+//! and codes that are used as they are in Spanish too (like SIGMET, or "AHPS XML") aren't listed.
+
+/// Spanish text, by the English text it translates
+pub(super) const SPANISH: &[(&str, &str)] = &[
+    // goesbox-ui
+    ("VCDU receive rates (pps)", "Recepción de VCDU (pps)"),
+    (
+        "VCDU receive rates (pps), {} duplicates dropped",
+        "Recepción de VCDU (pps), {} duplicados descartados",
+    ),
+    ("APID receive rates (pps)", "Recepción por APID (pps)"),
+    ("VCDUs per second", "VCDU por segundo"),
+    ("Dropped", "Perdidos"),
+    ("Fill", "Relleno"),
+    ("Errors", "Errores"),
+    ("{} (last {}s)", "{} (últimos {} s)"),
+    ("Summary", "Resumen"),
+    ("no products yet", "aún no hay productos"),
+    ("{} products ({})", "{} productos ({})"),
+    ("type {}", "tipo {}"),
+    ("Up {}", "Activo hace {}"),
+    ("mode unknown", "modo desconocido"),
+    ("{} MB received", "{} MB recibidos"),
+    ("{}% utilized", "{}% de uso"),
+    ("{} cached ({} MB)", "{} en caché ({} MB)"),
+    ("OVERDUE: {}", "ATRASADOS: {}"),
+    ("OUTAGE", "INTERRUPCIÓN"),
+    ("UPCOMING", "PRÓXIMA"),
+    (
+        "DISK WRITES FAILING since {}",
+        "FALLAN LAS ESCRITURAS EN DISCO desde las {}",
+    ),
+    ("DISK WRITES FAILED", "FALLARON LAS ESCRITURAS EN DISCO"),
+    (
+        ", {} files ({} MB) held in memory",
+        ", {} archivos ({} MB) retenidos en memoria",
+    ),
+    (", {} dropped", ", {} descartados"),
+    ("{} stale products", "{} productos atrasados"),
+    (
+        "CLOCK SKEW: local clock is {} ahead of product times",
+        "DESFASE DE RELOJ: el reloj local va {} adelantado respecto a los productos",
+    ),
+    (
+        "CLOCK SKEW: local clock is {} behind product times",
+        "DESFASE DE RELOJ: el reloj local va {} atrasado respecto a los productos",
+    ),
+    (", going by product times", ", usando la hora de los productos"),
+    ("APIDs (packets)", "APID (paquetes)"),
+    ("Products by hour (UTC)", "Productos por hora (UTC)"),
+    ("Handler times", "Tiempos de los manejadores"),
+    (
+        "{}: mean {} ms, p95 {} ms, max {} ms",
+        "{}: media {} ms, p95 {} ms, máx. {} ms",
+    ),
+    (", {} crashes", ", {} fallos"),
+    ("Messages", "Mensajes"),
+    ("Messages ({} newer)", "Mensajes ({} más recientes)"),
+    ("Messages (paused, {} newer)", "Mensajes (en pausa, {} más recientes)"),
+    ("Keys", "Teclas"),
+    ("{} ({} to close)", "{} ({} para cerrar)"),
+    ("Quit", "Salir"),
+    ("Clear messages", "Borrar los mensajes"),
+    ("Show or hide this help", "Mostrar u ocultar esta ayuda"),
+    (
+        "Pause or resume the message log",
+        "Pausar o reanudar el registro de mensajes",
+    ),
+    ("Scroll messages up", "Desplazar los mensajes hacia arriba"),
+    ("Scroll messages down", "Desplazar los mensajes hacia abajo"),
+    ("Scroll messages up a page", "Subir una página de mensajes"),
+    ("Scroll messages down a page", "Bajar una página de mensajes"),
+    (
+        "Search messages (Enter to find, Esc to cancel)",
+        "Buscar en los mensajes (Enter para buscar, Esc para cancelar)",
+    ),
+    (
+        "Find the next older match",
+        "Buscar la siguiente coincidencia más antigua",
+    ),
+    (
+        "Show VC or APID receive rates",
+        "Mostrar la recepción por VC o por APID",
+    ),
+    (
+        "Show APID totals or products by hour",
+        "Mostrar totales por APID o productos por hora",
+    ),
+    (
+        "Show or hide signal quality for pointing",
+        "Mostrar u ocultar la calidad de la señal para orientar la antena",
+    ),
+    (
+        "Show or hide the latest warning's text",
+        "Mostrar u ocultar el texto de la última advertencia",
+    ),
+    ("Image", "Imagen"),
+    ("Text", "Texto"),
+    ("Key", "Clave"),
+    // WMO data types (T1)
+    ("Analyses", "Análisis"),
+    ("Addressed message", "Mensaje dirigido"),
+    ("Climatic data", "Datos climáticos"),
+    ("Grid point information", "Información en puntos de rejilla"),
+    ("Satellite imagery", "Imágenes satelitales"),
+    ("Forecasts", "Pronósticos"),
+    ("Notices", "Notificaciones"),
+    ("Pictorial information", "Información gráfica"),
+    ("Pictorial information (regional)", "Información gráfica (regional)"),
+    ("Surface data", "Datos de superficie"),
+    ("Satellite data", "Datos satelitales"),
+    ("Upper-air data", "Datos de altura"),
+    ("Warnings", "Advertencias"),
+    // WMO data types (T2)
+    (
+        "Temperature and precipitation table",
+        "Tabla de temperatura y precipitación",
+    ),
+    ("Cyclone", "Ciclón"),
+    ("Air quality alert", "Alerta de calidad del aire"),
+    ("Hydrological/marine", "Hidrológico/marino"),
+    ("Thickness", "Espesor"),
+    ("Ice", "Hielo"),
+    ("Ozone layer", "Capa de ozono"),
+    ("Surface", "Superficie"),
+    ("Upper air", "Altura"),
+    ("Weather summary", "Resumen del tiempo"),
+    ("Miscellaneous", "Misceláneo"),
+    ("Climate anomalies", "Anomalías climáticas"),
+    ("Climatological report (daily)", "Informe climatológico (diario)"),
+    ("Climatological report", "Informe climatológico"),
+    ("Monthly means (upper air)", "Medias mensuales (altura)"),
+    ("Monthly means (surface)", "Medias mensuales (superficie)"),
+    ("Monthly means (ocean areas)", "Medias mensuales (áreas oceánicas)"),
+    ("Aviation area/GAMET/advisories", "Área de aviación/GAMET/avisos"),
+    ("Upper winds and temperatures", "Vientos y temperaturas en altura"),
+    ("Aerodrome (VT < 12 hours)", "Aeródromo (VT < 12 horas)"),
+    ("Radiological trajectory dose", "Dosis de trayectoria radiológica"),
+    ("Extended", "Extendido"),
+    ("Shipping", "Navegación marítima"),
+    ("Hydrological", "Hidrológico"),
+    ("Upper air thickness", "Espesor en altura"),
+    ("Iceberg", "Icebergs"),
+    ("Radio warning service", "Servicio de avisos por radio"),
+    ("Tropical cyclone advisories", "Avisos de ciclón tropical"),
+    ("Local/area", "Local/área"),
+    ("Temperature extremes", "Temperaturas extremas"),
+    ("Space weather advisories", "Avisos de meteorología espacial"),
+    ("Guidance", "Guía"),
+    ("Public", "Público"),
+    ("Other shipping", "Otra navegación marítima"),
+    ("Aviation route", "Ruta de aviación"),
+    ("Aerodrome (VT >= 12 hours)", "Aeródromo (VT >= 12 horas)"),
+    ("Volcanic ash advisories", "Avisos de ceniza volcánica"),
+    ("Winter sports", "Deportes de invierno"),
+    ("Shipping area", "Área de navegación marítima"),
+    ("Marine", "Marino"),
+    ("Nuclear emergency response", "Respuesta a emergencias nucleares"),
+    ("Product generation delay", "Retraso en la generación de productos"),
+    ("Test message", "Mensaje de prueba"),
+    (
+        "Warning related and/or cancellation",
+        "Relacionado con advertencias y/o cancelación",
+    ),
+    ("Regional weather roundup", "Resumen regional del tiempo"),
+    ("Aviation routine reports", "Informes rutinarios de aviación"),
+    ("Radar reports (part A)", "Informes de radar (parte A)"),
+    ("Radar reports (part B)", "Informes de radar (parte B)"),
+    ("Radar reports (parts A and B)", "Informes de radar (partes A y B)"),
+    ("Seismic data", "Datos sísmicos"),
+    ("Atmospherics reports", "Informes de parásitos atmosféricos"),
+    ("Radiological data report", "Informe de datos radiológicos"),
+    ("Reports from DCP stations", "Informes de estaciones DCP"),
+    ("Intermediate synoptic hour", "Hora sinóptica intermedia"),
+    ("Not assigned", "Sin asignar"),
+    ("Main synoptic hour", "Hora sinóptica principal"),
+    ("Non-standard synoptic hour", "Hora sinóptica no estándar"),
+    ("Oceanographic data", "Datos oceanográficos"),
+    (
+        "Special aviation weather reports",
+        "Informes meteorológicos especiales de aviación",
+    ),
+    ("Hydrological (river) reports", "Informes hidrológicos (ríos)"),
+    ("Drifting buoy reports", "Informes de boyas a la deriva"),
+    ("Sea ice", "Hielo marino"),
+    ("Snow depth", "Espesor de la nieve"),
+    ("Lake ice", "Hielo en lagos"),
+    ("Wave information", "Información de olas"),
+    ("Seismic waveform data", "Datos de formas de onda sísmicas"),
+    (
+        "Sea level and deep-ocean tsunami data",
+        "Datos del nivel del mar y de tsunamis en aguas profundas",
+    ),
+    ("Satellite orbit parameters", "Parámetros orbitales del satélite"),
+    (
+        "Satellite cloud interpretations",
+        "Interpretaciones satelitales de nubes",
+    ),
+    (
+        "Satellite remote upper-air soundings",
+        "Sondeos remotos de altura por satélite",
+    ),
+    (
+        "Clear radiance observations",
+        "Observaciones de radiancia en cielo despejado",
+    ),
+    ("Sea surface temperatures", "Temperaturas de la superficie del mar"),
+    ("Winds and cloud temperatures", "Vientos y temperaturas de las nubes"),
+    ("Aircraft reports (FM 41)", "Informes de aeronaves (FM 41)"),
+    ("Aircraft reports (FM 42)", "Informes de aeronaves (FM 42)"),
+    ("Aircraft report", "Informe de aeronave"),
+    (
+        "Upper-level pressure, temperature, humidity and wind (part A)",
+        "Presión, temperatura, humedad y viento en altura (parte A)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind (part B)",
+        "Presión, temperatura, humedad y viento en altura (parte B)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind (part C)",
+        "Presión, temperatura, humedad y viento en altura (parte C)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind (part D)",
+        "Presión, temperatura, humedad y viento en altura (parte D)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind (parts A and B)",
+        "Presión, temperatura, humedad y viento en altura (partes A y B)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind (parts C and D)",
+        "Presión, temperatura, humedad y viento en altura (partes C y D)",
+    ),
+    (
+        "Upper-level pressure, temperature, humidity and wind from a released sonde",
+        "Presión, temperatura, humedad y viento en altura desde una sonda lanzada",
+    ),
+    ("Upper wind (part A)", "Viento en altura (parte A)"),
+    ("Upper wind (part B)", "Viento en altura (parte B)"),
+    ("Upper wind (part C)", "Viento en altura (parte C)"),
+    ("Upper wind (part D)", "Viento en altura (parte D)"),
+    ("Upper wind (parts A and B)", "Viento en altura (partes A y B)"),
+    ("Upper wind (parts C and D)", "Viento en altura (partes C y D)"),
+    ("Rocketsonde reports", "Informes de cohetesonda"),
+    ("Tropical cyclone (SIGMET)", "Ciclón tropical (SIGMET)"),
+    ("Hydrological/river flood", "Inundación hidrológica/fluvial"),
+    ("Marine/coastal flood", "Inundación marina/costera"),
+    ("Other", "Otros"),
+    ("Humanitarian activities", "Actividades humanitarias"),
+    (
+        "Tropical cyclone (typhoon/hurricane)",
+        "Ciclón tropical (tifón/huracán)",
+    ),
+    ("Severe thunderstorm", "Tormenta eléctrica severa"),
+    ("Volcanic ash clouds (SIGMET)", "Nubes de ceniza volcánica (SIGMET)"),
+    ("Warnings and weather summary", "Advertencias y resumen del tiempo"),
+    ("Radar data", "Datos de radar"),
+    ("Cloud", "Nubes"),
+    ("Clear air turbulence", "Turbulencia en aire claro"),
+    ("Precipitation", "Precipitación"),
+    (
+        "Aerological diagrams (ash cloud)",
+        "Diagramas aerológicos (nube de ceniza)",
+    ),
+    ("Significant weather", "Tiempo significativo"),
+    ("Height", "Altura geopotencial"),
+    ("Ice flow", "Flujo de hielo"),
+    ("Wave height and combinations", "Altura de las olas y combinaciones"),
+    (
+        "Swell height and combinations",
+        "Altura del mar de fondo y combinaciones",
+    ),
+    ("Plain language", "Lenguaje claro"),
+    ("For national use", "Para uso nacional"),
+    ("Radiation", "Radiación"),
+    ("Vertical velocity", "Velocidad vertical"),
+    ("Pressure", "Presión"),
+    (
+        "Wet bulb potential temperature",
+        "Temperatura potencial del termómetro húmedo",
+    ),
+    ("Relative humidity", "Humedad relativa"),
+    ("Snow cover", "Cubierta de nieve"),
+    ("Temperature", "Temperatura"),
+    ("Eastward wind component", "Componente este del viento"),
+    ("Northward wind component", "Componente norte del viento"),
+    ("Wind", "Viento"),
+    ("Lifted index", "Índice de elevación"),
+    ("Observational plotted chart", "Mapa de observaciones trazadas"),
+    ("Cloud top temperature", "Temperatura de la cima de las nubes"),
+    ("Fog", "Niebla"),
+    ("Infrared", "Infrarrojo"),
+    ("Surface temperature", "Temperatura de superficie"),
+    ("Water vapor", "Vapor de agua"),
+    ("User specified", "Especificado por el usuario"),
+    ("Unspecified", "Sin especificar"),
+    ("Unknown", "Desconocido"),
+    // NWS products
+    (
+        "Rawinsonde Data Above 100 Millibars",
+        "Datos de radiosonda por encima de 100 milibares",
+    ),
+    (
+        "Alarm/Alert Administrative Msg",
+        "Mensaje administrativo de alarma/alerta",
+    ),
+    ("Alert Administrative Message", "Mensaje administrativo de alerta"),
+    ("NWS Administrative Message", "Mensaje administrativo del NWS"),
+    (
+        "Generic Space Environment Advisory",
+        "Aviso genérico del entorno espacial",
+    ),
+    ("Area Forecast Discussion", "Discusión del pronóstico de área"),
+    ("Area Forecast Matrices", "Matrices del pronóstico de área"),
+    ("Area Forecast Product", "Producto de pronóstico de área"),
+    ("Fire Weather Matrix", "Matriz del tiempo para incendios"),
+    ("Agricultural Forecast", "Pronóstico agrícola"),
+    ("Agricultural Observations", "Observaciones agrícolas"),
+    ("Space Environment Alert", "Alerta del entorno espacial"),
+    ("Air Quality Alert", "Alerta de calidad del aire"),
+    (
+        "Air Quality Index Statement",
+        "Comunicado del índice de calidad del aire",
+    ),
+    ("Air Stagnation Advisory", "Aviso de estancamiento del aire"),
+    ("Avalanche Watch", "Vigilancia de avalanchas"),
+    ("Avalanche Weather Guidance", "Guía meteorológica de avalanchas"),
+    ("Avalanche Warning", "Advertencia de avalanchas"),
+    ("Area Weather Outlook", "Perspectiva del tiempo del área"),
+    ("Area Weather Summary", "Resumen del tiempo del área"),
+    ("Area Weather Update", "Actualización del tiempo del área"),
+    ("Airport Weather Warning", "Advertencia meteorológica de aeropuerto"),
+    ("Blue Alert", "Alerta azul"),
+    ("Buoy Report", "Informe de boyas"),
+    ("Coast Guard Observations", "Observaciones de la Guardia Costera"),
+    (
+        "Hourly Roundup for Weather Radio",
+        "Resumen horario para la radio meteorológica",
+    ),
+    ("Child Abduction Emergency", "Emergencia por secuestro de menores"),
+    ("Coded City Forecast", "Pronóstico codificado por ciudades"),
+    ("Civil Danger Warning", "Advertencia de peligro civil"),
+    ("Civil Emergency Message", "Mensaje de emergencia civil"),
+    (
+        "WFO Monthly/Daily Climate Data",
+        "Datos climáticos mensuales/diarios de la WFO",
+    ),
+    ("Convective Forecast Product", "Producto de pronóstico convectivo"),
+    (
+        "Coastal Flood Warnings/Watches/Statements",
+        "Advertencias/vigilancias/comunicados de inundación costera",
+    ),
+    (
+        "Coast Guard Surface Report",
+        "Informe de superficie de la Guardia Costera",
+    ),
+    ("Computer Hurricane Guidance", "Guía computarizada de huracanes"),
+    ("Climatological Report (Annual)", "Informe climatológico (anual)"),
+    ("Climatological Report (Daily)", "Informe climatológico (diario)"),
+    ("Climatological Report (Monthly)", "Informe climatológico (mensual)"),
+    (
+        "Climatological Report (Quarterly)",
+        "Informe climatológico (trimestral)",
+    ),
+    ("Climatological Report (Seasonal)", "Informe climatológico (estacional)"),
+    ("Climate Report", "Informe climático"),
+    (
+        "Coded Climatological Monthly Means",
+        "Medias mensuales climatológicas codificadas",
+    ),
+    ("Coded Analysis and Forecasts", "Análisis y pronósticos codificados"),
+    (
+        "Great Lakes Port Forecast",
+        "Pronóstico de puertos de los Grandes Lagos",
+    ),
+    (
+        "Routine Space Environment Products",
+        "Productos rutinarios del entorno espacial",
+    ),
+    (
+        "Center (CWSU) Weather Advisory",
+        "Aviso meteorológico del centro (CWSU)",
+    ),
+    ("Coastal Waters Forecast", "Pronóstico de aguas costeras"),
+    (
+        "Center (CWSU) Weather Statement",
+        "Comunicado meteorológico del centro (CWSU)",
+    ),
+    (
+        "Routine Space Environment Product (Daily)",
+        "Producto rutinario del entorno espacial (diario)",
+    ),
+    ("Daily Dispersion Outlook", "Perspectiva diaria de dispersión"),
+    (
+        "Drought Information Statement",
+        "Comunicado de información sobre sequía",
+    ),
+    ("Practice/Demo Warning", "Advertencia de práctica/demostración"),
+    (
+        "Unnumbered Depression / Suspicious Area Advisory",
+        "Aviso de depresión sin numerar / área sospechosa",
+    ),
+    ("ASOS Daily Summary", "Resumen diario ASOS"),
+    (
+        "Dust Storm Warning and Dust Advisory",
+        "Advertencia de tormenta de polvo y aviso de polvo",
+    ),
+    ("3 To 5 Day Extended Forecast", "Pronóstico extendido de 3 a 5 días"),
+    (
+        "Average 6 To 10 Day Weather Outlook (Local)",
+        "Perspectiva media del tiempo de 6 a 10 días (local)",
+    ),
+    ("Tsunami Bulletin", "Boletín de tsunami"),
+    ("Earthquake Report", "Informe de terremoto"),
+    ("Earthquake Warning", "Advertencia de terremoto"),
+    ("Flood Potential Outlook", "Perspectiva de potencial de inundaciones"),
+    ("Extended Streamflow Guidance", "Guía extendida de caudales"),
+    ("Extended Streamflow Prediction", "Predicción extendida de caudales"),
+    ("Water Supply Outlook", "Perspectiva de abastecimiento de agua"),
+    ("Evacuation Immediate", "Evacuación inmediata"),
+    ("Extreme Wind Warning", "Advertencia de vientos extremos"),
+    (
+        "Aviation Area Forecasts (Pacific)",
+        "Pronósticos de área para la aviación (Pacífico)",
+    ),
+    (
+        "Aviation Area Forecasts (Northeast)",
+        "Pronósticos de área para la aviación (Noreste)",
+    ),
+    (
+        "Aviation Area Forecasts (Southeast)",
+        "Pronósticos de área para la aviación (Sureste)",
+    ),
+    (
+        "Aviation Area Forecasts (North Central)",
+        "Pronósticos de área para la aviación (Centro-norte)",
+    ),
+    (
+        "Aviation Area Forecasts (South Central)",
+        "Pronósticos de área para la aviación (Centro-sur)",
+    ),
+    (
+        "Aviation Area Forecasts (Rocky Mountains)",
+        "Pronósticos de área para la aviación (Montañas Rocosas)",
+    ),
+    (
+        "Aviation Area Forecasts (West Coast)",
+        "Pronósticos de área para la aviación (Costa Oeste)",
+    ),
+    (
+        "Aviation Area Forecasts (Juneau, AK)",
+        "Pronósticos de área para la aviación (Juneau, AK)",
+    ),
+    (
+        "Aviation Area Forecasts (Anchorage, AK)",
+        "Pronósticos de área para la aviación (Anchorage, AK)",
+    ),
+    (
+        "Aviation Area Forecasts (Fairbanks, AK)",
+        "Pronósticos de área para la aviación (Fairbanks, AK)",
+    ),
+    (
+        "24 Hr Fd Winds Aloft Fcst (45,000 and 53,000 Ft)",
+        "Pronóstico FD de vientos en altura a 24 h (45.000 y 53.000 pies)",
+    ),
+    (
+        "6 Hour Winds Aloft Forecast",
+        "Pronóstico de vientos en altura a 6 horas",
+    ),
+    (
+        "12 Hour Winds Aloft Forecast",
+        "Pronóstico de vientos en altura a 12 horas",
+    ),
+    (
+        "24 Hour Winds Aloft Forecast",
+        "Pronóstico de vientos en altura a 24 horas",
+    ),
+    ("Winds Aloft Forecast", "Pronóstico de vientos en altura"),
+    (
+        "6 Hour Fd Winds Aloft Fcst (45,000 and 53,000 Ft)",
+        "Pronóstico FD de vientos en altura a 6 h (45.000 y 53.000 pies)",
+    ),
+    (
+        "12 Hr Fd Winds Aloft Fcst (45,000 and 53,000 Ft)",
+        "Pronóstico FD de vientos en altura a 12 h (45.000 y 53.000 pies)",
+    ),
+    ("Fire Danger Indices", "Índices de peligro de incendio"),
+    ("Flash Flood Watch", "Vigilancia de inundaciones repentinas"),
+    ("Flash Flood Guidance", "Guía de inundaciones repentinas"),
+    ("Headwater Guidance", "Guía de cabeceras"),
+    ("Flash Flood Statement", "Comunicado de inundaciones repentinas"),
+    ("Flash Flood Warning", "Advertencia de inundaciones repentinas"),
+    ("National Flood Summary", "Resumen nacional de inundaciones"),
+    ("Flood Statement", "Comunicado de inundación"),
+    ("Flood Warning", "Advertencia de inundación"),
+    (
+        "Upper Wind Fallout Forecast",
+        "Pronóstico de precipitación radiactiva por vientos en altura",
+    ),
+    ("Fire Warning", "Advertencia de incendio"),
+    (
+        "Natl Marine Fisheries Administrative Service Message",
+        "Mensaje administrativo del Servicio Nacional de Pesca Marina",
+    ),
+    (
+        "WSR-88D Radar Outage Notification / Free Text Message",
+        "Aviso de radar WSR-88D fuera de servicio / mensaje de texto libre",
+    ),
+    (
+        "FOUS Prog Max/Min Temp/Pop Guidance",
+        "Guía FOUS de temperatura máx./mín. y probabilidad de precipitación",
+    ),
+    (
+        "Fire Weather Administrative Message",
+        "Mensaje administrativo del tiempo para incendios",
+    ),
+    (
+        "Fire Weather Outlook Discussion",
+        "Discusión de la perspectiva del tiempo para incendios",
+    ),
+    (
+        "Routine Fire Wx Fcst (With/Without 6-10 Day Outlook)",
+        "Pronóstico rutinario del tiempo para incendios (con/sin perspectiva de 6 a 10 días)",
+    ),
+    ("Land Management Forecasts", "Pronósticos para la gestión de tierras"),
+    (
+        "Miscellaneous Fire Weather Product",
+        "Producto misceláneo del tiempo para incendios",
+    ),
+    ("Fire Weather Notification", "Notificación del tiempo para incendios"),
+    ("Fire Weather Observation", "Observación del tiempo para incendios"),
+    ("Suppression Forecast", "Pronóstico para la extinción de incendios"),
+    ("Freezing Level Data (RADAT)", "Datos del nivel de congelación (RADAT)"),
+    ("Great Lakes Forecast", "Pronóstico de los Grandes Lagos"),
+    ("Great Lakes Storm Summary", "Resumen de tormentas de los Grandes Lagos"),
+    (
+        "RFC Derived QPF Data Product",
+        "Producto de datos de QPF derivados del RFC",
+    ),
+    ("Hurricane Local Statement", "Comunicado local de huracán"),
+    ("Hydrometeorological Discussion", "Discusión hidrometeorológica"),
+    ("Hazardous Materials Warning", "Advertencia de materiales peligrosos"),
+    (
+        "RFC QPF Verification Product",
+        "Producto de verificación de QPF del RFC",
+    ),
+    ("Weather Roundup", "Resumen del tiempo"),
+    ("High Seas Forecast", "Pronóstico de alta mar"),
+    ("Hazardous Weather Outlook", "Perspectiva de tiempo peligroso"),
+    ("Hourly Weather Roundup", "Resumen horario del tiempo"),
+    (
+        "Daily Hydrometeorological Products",
+        "Productos hidrometeorológicos diarios",
+    ),
+    (
+        "Monthly Hydrometeorological Plain Language Product",
+        "Producto hidrometeorológico mensual en lenguaje claro",
+    ),
+    ("Ice Forecast", "Pronóstico de hielo"),
+    ("Ice Drift Vectors", "Vectores de deriva del hielo"),
+    ("Ice Observation", "Observación de hielo"),
+    ("Keep Alive Message", "Mensaje de mantenimiento de conexión"),
+    ("Local Area Emergency", "Emergencia en el área local"),
+    (
+        "Preliminary Local Climatological Data",
+        "Datos climatológicos locales preliminares",
+    ),
+    ("Local Cooperative Observation", "Observación cooperativa local"),
+    ("Law Enforcement Warning", "Advertencia de las fuerzas del orden"),
+    ("Local Forecast", "Pronóstico local"),
+    ("Lake Stages", "Niveles de los lagos"),
+    ("Low-Level Sounding", "Sondeo de niveles bajos"),
+    ("Low Temperatures", "Temperaturas mínimas"),
+    ("Local Storm Report", "Informe local de tormentas"),
+    ("Lightning Data", "Datos de rayos"),
+    (
+        "Rawinsonde Observation Mandatory Levels",
+        "Observación de radiosonda, niveles obligatorios",
+    ),
+    ("Mean Areal Precipitation", "Precipitación media areal"),
+    ("Amended Marine Forecast", "Pronóstico marino enmendado"),
+    ("Marine Forecast Matrix", "Matriz del pronóstico marino"),
+    ("Marine Interpretation Message", "Mensaje de interpretación marina"),
+    ("Miscellaneous Local Product", "Producto local misceláneo"),
+    ("MOB Observations", "Observaciones MOB"),
+    (
+        "Routine Space Environment Product Issued Monthly",
+        "Producto rutinario mensual del entorno espacial",
+    ),
+    (
+        "Techniques Development Laboratory Marine Product",
+        "Producto marino del Laboratorio de Desarrollo de Técnicas",
+    ),
+    ("ASOS Monthly Summary Message", "Mensaje de resumen mensual ASOS"),
+    (
+        "METAR Formatted Surface Weather Observation",
+        "Observación meteorológica de superficie en formato METAR",
+    ),
+    ("METAR Test Message", "Mensaje de prueba METAR"),
+    (
+        "Marine Verification Coded Message",
+        "Mensaje codificado de verificación marina",
+    ),
+    ("Marine Weather Statement", "Comunicado meteorológico marino"),
+    ("Marine Weather Message", "Mensaje meteorológico marino"),
+    (
+        "Weather Reconnaisance Flights",
+        "Vuelos de reconocimiento meteorológico",
+    ),
+    ("Short Term Forecast", "Pronóstico a corto plazo"),
+    ("Data Mgt Message", "Mensaje de gestión de datos"),
+    (
+        "Non-Precipitation Warnings / Watches / Advisories",
+        "Advertencias / vigilancias / avisos sin precipitación",
+    ),
+    ("Nearshore Marine Forecast", "Pronóstico marino cerca de la costa"),
+    ("Nuclear Power Plant Warning", "Advertencia de central nuclear"),
+    (
+        "NOAA Weather Radio Forecast",
+        "Pronóstico de la radio meteorológica de la NOAA",
+    ),
+    ("Other Aviation Products", "Otros productos de aviación"),
+    ("Observations", "Observaciones"),
+    (
+        "Offshore Aviation Area Forecast",
+        "Pronóstico de área para la aviación en alta mar",
+    ),
+    ("Offshore Forecast", "Pronóstico de alta mar"),
+    ("Other Marine Products", "Otros productos marinos"),
+    ("Other Public Products", "Otros productos públicos"),
+    ("Other Surface Observations", "Otras observaciones de superficie"),
+    ("Ocean Surface Winds", "Vientos en la superficie del océano"),
+    ("Other Upper Air Data", "Otros datos de altura"),
+    ("Zone Forecast", "Pronóstico por zonas"),
+    ("Point Forecast Matrices", "Matrices de pronóstico puntual"),
+    (
+        "Fire Weather Point Forecast Matrices",
+        "Matrices de pronóstico puntual del tiempo para incendios",
+    ),
+    ("Plain Language Ship Report", "Informe de buque en lenguaje claro"),
+    (
+        "Prognostic Meteorological Discussion",
+        "Discusión meteorológica pronóstica",
+    ),
+    ("Public Information Statement", "Comunicado de información pública"),
+    ("Probability of Exceed", "Probabilidad de excedencia"),
+    ("Heat Index Forecast Tables", "Tablas de pronóstico del índice de calor"),
+    (
+        "State Pilot Report Collective",
+        "Colectivo estatal de informes de pilotos",
+    ),
+    ("Preliminary Forecasts", "Pronósticos preliminares"),
+    (
+        "Post Storm Hurricane Report",
+        "Informe de huracán posterior a la tormenta",
+    ),
+    ("Probabilistic Outlook Points", "Puntos de perspectiva probabilística"),
+    ("Public Severe Weather Outlook", "Perspectiva pública de tiempo severo"),
+    ("Tropical Cyclone Probabilities", "Probabilidades de ciclón tropical"),
+    (
+        "Quantitative Precipitation Forecast",
+        "Pronóstico cuantitativo de precipitación",
+    ),
+    (
+        "Quantitative Precipitation Statement",
+        "Comunicado cuantitativo de precipitación",
+    ),
+    ("Revised Digital Forecast", "Pronóstico digital revisado"),
+    ("Recreational Report", "Informe recreativo"),
+    ("Record Report", "Informe de récords"),
+    ("EAS Activation Request", "Solicitud de activación del EAS"),
+    (
+        "Rangeland Fire Danger Forecast",
+        "Pronóstico de peligro de incendio en pastizales",
+    ),
+    ("RFI Observation", "Observación RFI"),
+    ("Route Forecast", "Pronóstico de ruta"),
+    ("Red Flag Warning", "Advertencia de bandera roja"),
+    ("Radiological Hazard Warning", "Advertencia de peligro radiológico"),
+    ("Required Monthly Test", "Prueba mensual obligatoria"),
+    ("Rain Information Statement", "Comunicado de información sobre lluvias"),
+    (
+        "Hydro-Met Data Report Part 1",
+        "Informe de datos hidrometeorológicos, parte 1",
+    ),
+    (
+        "Hydro-Met Data Report Part 2",
+        "Informe de datos hidrometeorológicos, parte 2",
+    ),
+    (
+        "Hydro-Met Data Report Part 3",
+        "Informe de datos hidrometeorológicos, parte 3",
+    ),
+    (
+        "Hydro-Met Data Report Part 4",
+        "Informe de datos hidrometeorológicos, parte 4",
+    ),
+    (
+        "Hydro-Met Data Report Part 5",
+        "Informe de datos hidrometeorológicos, parte 5",
+    ),
+    (
+        "Hydro-Met Data Report Part 6",
+        "Informe de datos hidrometeorológicos, parte 6",
+    ),
+    (
+        "Hydro-Met Data Report Part 7",
+        "Informe de datos hidrometeorológicos, parte 7",
+    ),
+    (
+        "Hydro-Met Data Report Part 8",
+        "Informe de datos hidrometeorológicos, parte 8",
+    ),
+    (
+        "Hydro-Met Data Report Part 9",
+        "Informe de datos hidrometeorológicos, parte 9",
+    ),
+    (
+        "Automated Hydrologic Observation Sta Report (AHOS)",
+        "Informe de estación de observación hidrológica automatizada (AHOS)",
+    ),
+    ("Miscellaneous Hydrologic Data", "Datos hidrológicos misceláneos"),
+    ("HADS Data", "Datos HADS"),
+    (
+        "ASOS SHEF Hourly Routine Test Message",
+        "Mensaje de prueba horario rutinario ASOS SHEF",
+    ),
+    ("Daily Snotel Data", "Datos diarios de Snotel"),
+    ("Monthly Snotel Data", "Datos mensuales de Snotel"),
+    (
+        "Regional Max/Min Temp and Precipitation Table",
+        "Tabla regional de temperatura máx./mín. y precipitación",
+    ),
+    ("River Summary", "Resumen de ríos"),
+    ("Daily River Forecasts", "Pronósticos diarios de ríos"),
+    ("River Forecast", "Pronóstico de ríos"),
+    ("River Ice Statement", "Comunicado de hielo en ríos"),
+    ("Miscellaneous River Product", "Producto misceláneo de ríos"),
+    ("River Recreation Statement", "Comunicado recreativo de ríos"),
+    ("River Statement", "Comunicado de ríos"),
+    ("Regional Weather Roundup", "Resumen regional del tiempo"),
+    ("Regional Weather Summary", "Sumario regional del tiempo"),
+    ("Required Weekly Test", "Prueba semanal obligatoria"),
+    ("Special Avalanche Bulletin", "Boletín especial de avalanchas"),
+    (
+        "Speci Agri Wx Fcst / Advisory / Flying Farmer Fcst Outlook",
+        "Pronóstico agrícola especial / aviso / perspectiva para agricultores pilotos",
+    ),
+    ("Snow Avalanche Guidance", "Guía de avalanchas de nieve"),
+    ("APT Prediction", "Predicción APT"),
+    (
+        "Prelim Notice of Watch & Cancellation Msg (Aviation)",
+        "Aviso preliminar de vigilancia y mensaje de cancelación (aviación)",
+    ),
+    ("Storm Summary", "Resumen de tormenta"),
+    (
+        "Supplementary Climatological Data (ASOS)",
+        "Datos climatológicos suplementarios (ASOS)",
+    ),
+    (
+        "Soil Climate Analysis Network Data",
+        "Datos de la red de análisis climático del suelo",
+    ),
+    ("Satellite Cloud Product", "Producto satelital de nubes"),
+    ("Selected Cities Summary", "Resumen de ciudades seleccionadas"),
+    (
+        "Supplementary Data Observation (ASOS)",
+        "Observación de datos suplementarios (ASOS)",
+    ),
+    ("Special Dispersion Statement", "Comunicado especial de dispersión"),
+    (
+        "Severe Local Storm Watch and Watch Cancellation Msg",
+        "Vigilancia de tormentas locales severas y mensaje de cancelación",
+    ),
+    (
+        "SPC Watch Point Information Message",
+        "Mensaje de información de puntos de vigilancia del SPC",
+    ),
+    ("State Forecast", "Pronóstico estatal"),
+    ("Tabular State Forecast", "Pronóstico estatal tabulado"),
+    (
+        "Rawinsonde Observation Significant Levels",
+        "Observación de radiosonda, niveles significativos",
+    ),
+    (
+        "Surface Ship Report at Synoptic Time",
+        "Informe de superficie de buque a la hora sinóptica",
+    ),
+    (
+        "International Sigmet / Convective Sigmet",
+        "SIGMET internacional / SIGMET convectivo",
+    ),
+    (
+        "Satellite Interpretation Message",
+        "Mensaje de interpretación satelital",
+    ),
+    (
+        "Severe Local Storm Watch and Areal Outline",
+        "Vigilancia de tormentas locales severas y contorno del área",
+    ),
+    (
+        "Smoke Management Weather Forecast",
+        "Pronóstico del tiempo para la gestión del humo",
+    ),
+    ("Special Marine Warning", "Advertencia marina especial"),
+    ("SOO Product", "Producto del SOO"),
+    (
+        "Satellite Precipitation Estimates (TXUS20 KWBC)",
+        "Estimaciones satelitales de precipitación (TXUS20 KWBC)",
+    ),
+    (
+        "Storm Strike Probability Bulletin (TPC)",
+        "Boletín de probabilidad de impacto de tormenta (TPC)",
+    ),
+    ("Special Weather Statement", "Comunicado meteorológico especial"),
+    ("Shelter in Place Warning", "Advertencia de refugio en el lugar"),
+    ("Snow Squall Warning", "Advertencia de turbonada de nieve"),
+    ("Surf Discussion", "Discusión del oleaje"),
+    ("Surf Forecast", "Pronóstico del oleaje"),
+    ("Soaring Guidance", "Guía para vuelo a vela"),
+    (
+        "Main Synoptic Hour Surface Observation",
+        "Observación de superficie a la hora sinóptica principal",
+    ),
+    (
+        "Network and Severe Weather Statistical Summaries",
+        "Resúmenes estadísticos de la red y del tiempo severo",
+    ),
+    (
+        "Satellite Tropical Disturbance Summary",
+        "Resumen satelital de perturbaciones tropicales",
+    ),
+    (
+        "Road Condition Reports (State Agencies)",
+        "Informes del estado de las carreteras (agencias estatales)",
+    ),
+    (
+        "State Max/Min Temperature and Precipitation Table",
+        "Tabla estatal de temperatura máx./mín. y precipitación",
+    ),
+    ("Spot Forecast Request", "Solicitud de pronóstico puntual"),
+    ("Space Weather Message", "Mensaje de meteorología espacial"),
+    (
+        "Severe Thunderstorm Warning",
+        "Advertencia de tormenta eléctrica severa",
+    ),
+    ("Severe Weather Statement", "Comunicado de tiempo severo"),
+    (
+        "Severe Storm Outlook Narrative (AC)",
+        "Narrativa de la perspectiva de tormentas severas (AC)",
+    ),
+    ("State Weather Summary", "Resumen estatal del tiempo"),
+    ("Regional Weather Synopsis", "Sinopsis regional del tiempo"),
+    ("Terminal Aerodrome Forecast", "Pronóstico de aeródromo (TAF)"),
+    ("Terminal Alerting Products", "Productos de alerta de terminal"),
+    ("Travelers Forecast Table", "Tabla de pronóstico para viajeros"),
+    (
+        "Aviation Tropical Cyclone Advisory",
+        "Aviso de ciclón tropical para la aviación",
+    ),
+    ("Tropical Cyclone Discussion", "Discusión de ciclón tropical"),
+    (
+        "Tropical Cyclone Position Estimate",
+        "Estimación de la posición del ciclón tropical",
+    ),
+    (
+        "Marine/Aviation Tropical Cyclone Advisory",
+        "Aviso marino/de aviación de ciclón tropical",
+    ),
+    ("Public Tropical Cyclone Advisory", "Aviso público de ciclón tropical"),
+    (
+        "Satellite Tropical Cyclone Summary",
+        "Resumen satelital de ciclón tropical",
+    ),
+    ("Tropical Cyclone Update", "Actualización de ciclón tropical"),
+    (
+        "Tropical Cyclone Watch/Warning Break Points",
+        "Puntos de corte de vigilancia/advertencia de ciclón tropical",
+    ),
+    ("Tide Report", "Informe de mareas"),
+    (
+        "Tsunami Tide/Seismic Message Acknowledgement",
+        "Acuse de recibo de mensaje mareográfico/sísmico de tsunami",
+    ),
+    (
+        "911 Telephone Outage Emergency",
+        "Emergencia por interrupción del teléfono 911",
+    ),
+    ("Tornado Warning", "Advertencia de tornado"),
+    (
+        "Temperature Precipitation Table (Natl and Intnl)",
+        "Tabla de temperatura y precipitación (nacional e internacional)",
+    ),
+    ("Tsunami Watch/Warning", "Vigilancia/advertencia de tsunami"),
+    ("Weather Bulletin", "Boletín meteorológico"),
+    ("Travelers Forecast", "Pronóstico para viajeros"),
+    ("Transcribed Weather Broadcast", "Emisión meteorológica transcrita"),
+    ("Tropical Weather Discussion", "Discusión del tiempo tropical"),
+    (
+        "Tropical Weather Outlook and Summary",
+        "Perspectiva y resumen del tiempo tropical",
+    ),
+    ("Tropical Weather Summary", "Resumen del tiempo tropical"),
+    ("Aircraft Reconnaissance", "Reconocimiento aéreo"),
+    ("Ultraviolet Index", "Índice ultravioleta"),
+    ("Volcanic Activity Advisory", "Aviso de actividad volcánica"),
+    (
+        "Forecast Verification Statistics",
+        "Estadísticas de verificación de pronósticos",
+    ),
+    (
+        "Terminal Aerodrome Forecast (TAF) Verification",
+        "Verificación del pronóstico de aeródromo (TAF)",
+    ),
+    ("Volcano Warning", "Advertencia de volcán"),
+    ("Airmet (Pacific)", "AIRMET (Pacífico)"),
+    ("Airmet (Northeast)", "AIRMET (Noreste)"),
+    ("Airmet (Southeast)", "AIRMET (Sureste)"),
+    ("Airmet (North Central)", "AIRMET (Centro-norte)"),
+    ("Airmet (South Central)", "AIRMET (Centro-sur)"),
+    ("Airmet (Rocky Mountains)", "AIRMET (Montañas Rocosas)"),
+    ("Airmet (West Coast)", "AIRMET (Costa Oeste)"),
+    ("Airmet (Juneau, AK)", "AIRMET (Juneau, AK)"),
+    ("Airmet (Anchorage, AK)", "AIRMET (Anchorage, AK)"),
+    ("Airmet (Fairbanks, AK)", "AIRMET (Fairbanks, AK)"),
+    ("Space Environment Warning", "Advertencia del entorno espacial"),
+    ("Space Environment Watch", "Vigilancia del entorno espacial"),
+    (
+        "Weather Watch Clearance Notification",
+        "Notificación de levantamiento de vigilancia meteorológica",
+    ),
+    (
+        "Weekly Weather and Crop Report",
+        "Informe semanal del tiempo y los cultivos",
+    ),
+    ("Weekly Data for Agriculture", "Datos semanales para la agricultura"),
+    ("Warning Decision Update", "Actualización de decisiones de advertencia"),
+    (
+        "Routine Space Environment Product Issued Weekly",
+        "Producto rutinario semanal del entorno espacial",
+    ),
+    (
+        "Tornado/Severe Thunderstorm Watch",
+        "Vigilancia de tornado/tormenta eléctrica severa",
+    ),
+    ("Sigmet (Northeast)", "SIGMET (Noreste)"),
+    ("Sigmet (Southeast)", "SIGMET (Sureste)"),
+    ("Sigmet (North Central)", "SIGMET (Centro-norte)"),
+    ("Sigmet (South Central)", "SIGMET (Centro-sur)"),
+    ("Sigmet (Rocky Mountains)", "SIGMET (Montañas Rocosas)"),
+    ("Sigmet (West Coast)", "SIGMET (Costa Oeste)"),
+    ("Tropical Cyclone Sigmet", "SIGMET de ciclón tropical"),
+    ("Volcanic Activity Sigmet", "SIGMET de actividad volcánica"),
+    (
+        "Winter Weather Warnings / Watches / Advisories",
+        "Advertencias / vigilancias / avisos de tiempo invernal",
+    ),
+    ("Watch Status Report", "Informe del estado de las vigilancias"),
+    (
+        "Severe Thunderstorm / Tornado Watch Probabilities",
+        "Probabilidades de vigilancia de tormenta eléctrica severa / tornado",
+    ),
+    ("Zone Forecast Product", "Producto de pronóstico por zonas"),
+];
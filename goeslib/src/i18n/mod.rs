@@ -0,0 +1,124 @@
+//! Translations of product descriptions and UI labels
+//!
+//! Text is written in English everywhere, and translated by looking it up in a table for the
+//! [`Language`] (English text that isn't in the table is left as it is).  Labels with values in
+//! them are templates, like `"{} products ({})"`, that are translated first and then filled in
+//! with [`Language::format`], since the words don't always go in the same order.
+//!
+//! To add a language, add a table like the one in `es.rs`, and a variant for it.
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+mod es;
+
+/// A language that product descriptions and labels can be shown in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en", alias = "english")]
+    English,
+    #[serde(rename = "es", alias = "spanish")]
+    Spanish,
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Language::from_locale(s).ok_or_else(|| format!("unknown language '{}' (expected en or es)", s))
+    }
+}
+
+impl Language {
+    /// The language of a locale name like `es_MX.UTF-8`, `es`, or `spanish`, if it's a known one
+    /// (`C` and `POSIX` are English)
+    pub fn from_locale(locale: &str) -> Option<Language> {
+        let code = locale.split(['_', '-', '.', '@']).next().unwrap_or_default();
+        match code.to_lowercase().as_str() {
+            "en" | "english" | "c" | "posix" => Some(Language::English),
+            "es" | "spanish" => Some(Language::Spanish),
+            _ => None,
+        }
+    }
+
+    /// The language of the user's locale (from `LC_ALL`, `LC_MESSAGES`, or `LANG`), or English
+    pub fn from_env() -> Language {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Language::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    fn table(self) -> Option<&'static HashMap<&'static str, &'static str>> {
+        static SPANISH: OnceLock<HashMap<&str, &str>> = OnceLock::new();
+        match self {
+            Language::English => None,
+            Language::Spanish => Some(SPANISH.get_or_init(|| es::SPANISH.iter().copied().collect())),
+        }
+    }
+
+    /// Translates English text into this language, or returns it as it is if there's no
+    /// translation for it
+    pub fn tr(self, english: &str) -> &str {
+        match self.table().and_then(|table| table.get(english)) {
+            Some(translated) => translated,
+            None => english,
+        }
+    }
+
+    /// Translates a template like `"{} products ({})"`, and fills in each `{}` with the next of
+    /// `args`
+    pub fn format(self, template: &str, args: &[&dyn Display]) -> String {
+        let mut parts = self.tr(template).split("{}");
+        let mut text = parts.next().unwrap_or_default().to_string();
+        let mut args = args.iter();
+        for part in parts {
+            if let Some(arg) = args.next() {
+                text += &arg.to_string();
+            }
+            text += part;
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{es, Language};
+
+    #[test]
+    fn test_language() {
+        assert_eq!(Language::Spanish.tr("Messages"), "Mensajes");
+        assert_eq!(Language::English.tr("Messages"), "Messages");
+        // anything without a translation is left in English
+        assert_eq!(Language::Spanish.tr("KWIN"), "KWIN");
+        assert_eq!(
+            Language::Spanish.format("{} products ({})", &[&3, &"Texto 3"]),
+            "3 productos (Texto 3)"
+        );
+        assert_eq!(Language::English.format("Up {}", &[&"1d 02:03:04"]), "Up 1d 02:03:04");
+
+        assert_eq!(Language::from_locale("es_MX.UTF-8"), Some(Language::Spanish));
+        assert_eq!(Language::from_locale("C"), Some(Language::English));
+        assert_eq!(Language::from_locale("pt_BR"), None);
+        assert_eq!("es".parse::<Language>(), Ok(Language::Spanish));
+        assert!("klingon".parse::<Language>().is_err());
+
+        // every English text is translated once, with the same number of values to fill in
+        let mut seen = HashSet::new();
+        for (english, spanish) in es::SPANISH {
+            assert!(seen.insert(english), "{} is translated twice", english);
+            assert_eq!(
+                english.matches("{}").count(),
+                spanish.matches("{}").count(),
+                "{}",
+                english
+            );
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! A simple on-disk index of received products
+//!
+//! Every LRIT file that makes it through the pipeline gets one line in a per-day JSON file
+//! (`<output root>/index/YYYY-MM-DD.jsonl`).  This is intentionally very simple -- it's append-only
+//! and meant to be read back by reporting tools, not queried at high speed.
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::AdminNotice,
+    annotation::GoesRFilename,
+    capabilities,
+    handlers::{glob_match, Collision, Duplicate, Handler, HandlerError, Suspect},
+    lrit::{Decompression, TimeStampRecord, LRIT},
+    sector::Sector,
+};
+
+/// The version of the code that makes derived outputs (like the reduced resolution copies of
+/// images), which is recorded with each product
+///
+/// This goes up whenever those outputs change, so that products written before can be brought up
+/// to date with a [`Regenerator`](crate::regen::Regenerator).
+pub const DECODER_VERSION: u32 = 1;
+
+/// A single entry in the product index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexRecord {
+    /// When this product was received (local clock)
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub filetype_code: u8,
+    /// The NOAA product ID, if the LRIT file had a NOAA header
+    pub product_id: Option<u16>,
+    /// The annotation text (which is the original filename of the product)
+    pub annotation: String,
+    /// The daily archive this product has been packed into, if any (like `2022-05-04.tar.zst`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    /// For a segmented image that was written, which segments it has, like `1101` when the third
+    /// of four segments is missing
+    ///
+    /// The image handler adds a record like this every time it writes (or rewrites) an image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segments: Option<String>,
+    /// For a mesoscale image, which sector it's of, see [`Sector::key`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector: Option<String>,
+    /// Compressed and decompressed sizes, rice parameters and decompression time, if the product
+    /// was rice compressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decompression: Option<Decompression>,
+    /// For a segmented image that was written, when it was scanned and when its segments arrived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<ImageTiming>,
+    /// If the product's file already existed, what was done about it (see [`DuplicatePolicy`](crate::handlers::DuplicatePolicy))
+    ///
+    /// The handler adds a record like this whenever it finds an existing file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<Duplicate>,
+    /// If a different product was recently written to the file this product would have been
+    /// written to, where it was written instead (see
+    /// [`CollisionDetector`](crate::handlers::CollisionDetector))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collision: Option<Collision>,
+    /// If a verifier found the product suspect, why, and where it was quarantined (see
+    /// [`Quarantine`](crate::handlers::Quarantine))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspect: Option<Suspect>,
+    /// The [`DECODER_VERSION`] the product (and whatever was derived from it) was written with
+    ///
+    /// Products indexed before versions were recorded don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoder_version: Option<u32>,
+    /// The version and features of goeslib the record was written by, like `0.1.0+image.zip` (see
+    /// [`Capabilities::build`](crate::Capabilities::build))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+    /// For an admin message, the notice that was parsed from it
+    ///
+    /// The admin handler adds a record like this for every new notice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<AdminNotice>,
+    /// Set if the product was replayed, instead of received live (see [`crate::replay`])
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replay: bool,
+}
+
+/// When an image was scanned, and how long it took to get here
+///
+/// The scan start comes from the annotation (the GOES-R filename), and the header time from the
+/// time stamp header of the first segment, when they're there.  The rest are from the local
+/// clock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageTiming {
+    pub scan_start: Option<DateTime<Utc>>,
+    pub header_time: Option<DateTime<Utc>>,
+    pub first_segment: DateTime<Utc>,
+    pub last_segment: DateTime<Utc>,
+    /// When the image was written
+    pub completed: DateTime<Utc>,
+}
+
+impl ImageTiming {
+    /// The timing of an image, given (any) one of its segments and when its segments arrived
+    pub fn from_lrit(
+        lrit: &LRIT,
+        first_segment: DateTime<Utc>,
+        last_segment: DateTime<Utc>,
+        completed: DateTime<Utc>,
+    ) -> ImageTiming {
+        ImageTiming {
+            scan_start: lrit.headers.annotation.as_ref().and_then(|a| a.parsed().date()),
+            header_time: lrit.headers.timestamp.as_ref().and_then(TimeStampRecord::utc),
+            first_segment,
+            last_segment,
+            completed,
+        }
+    }
+
+    /// From the start of the scan until the image was written
+    pub fn latency(&self) -> Option<Duration> {
+        Some(self.completed - self.scan_start?)
+    }
+}
+
+impl IndexRecord {
+    /// Build an index record for a newly received LRIT file
+    ///
+    /// Returns `None` if the LRIT file is missing an annotation header
+    pub fn from_lrit(lrit: &LRIT, received: DateTime<Utc>) -> Option<IndexRecord> {
+        let annotation = lrit.headers.annotation.as_ref()?;
+        Some(IndexRecord {
+            received,
+            vcid: lrit.vcid,
+            filetype_code: lrit.headers.primary.filetype_code,
+            product_id: lrit.headers.noaa.as_ref().map(|n| n.product_id),
+            annotation: annotation.text.clone(),
+            archive: None,
+            segments: None,
+            sector: Sector::from_lrit(lrit).map(|s| s.key()),
+            decompression: lrit.decompression.clone(),
+            timing: None,
+            duplicate: None,
+            collision: None,
+            suspect: None,
+            decoder_version: Some(DECODER_VERSION),
+            build: Some(capabilities().build()),
+            admin: None,
+            replay: lrit.replay,
+        })
+    }
+
+    /// The files in `root` that were written for this product, and are still there
+    ///
+    /// Images are written as `<annotation>.jpg` (or `.gif`), and most other products under their
+    /// annotation.
+    pub fn files(&self, root: &Path) -> Vec<PathBuf> {
+        let path = root.join(&self.annotation);
+        vec![path.with_extension("jpg"), path.with_extension("gif"), path]
+            .into_iter()
+            .filter(|p| p.is_file())
+            .collect()
+    }
+}
+
+/// Which records to return from [`ProductIndex::query`]; every field that's set has to match
+///
+/// Queries can also be written as text, see [`IndexQuery::parse`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexQuery {
+    /// A pattern for the annotation, where `*` matches any number of characters and `?` matches
+    /// exactly one (case insensitive)
+    pub pattern: Option<String>,
+    pub vcid: Option<u8>,
+    pub filetype_code: Option<u8>,
+    pub product_id: Option<u16>,
+    /// The ABI band of a GOES-R image, like 13
+    pub band: Option<u8>,
+    /// The scan region of a GOES-R image: "FD", "CONUS", "M1", or "M2"
+    pub region: Option<String>,
+    /// Only records received at or after this
+    pub since: Option<DateTime<Utc>>,
+    /// Only records received before this
+    pub until: Option<DateTime<Utc>>,
+    /// Only replayed products (or only live ones, if false)
+    pub replay: Option<bool>,
+}
+
+impl IndexQuery {
+    /// Parses a query like `band=13 region=FD since=6h`
+    ///
+    /// Each term is `key=value`, and a term without a `=` is an annotation pattern.  The keys are
+    /// `pattern`, `vcid`, `type` (the file type), `product` (the NOAA product ID), `band`, `region`
+    /// (`FD`, `CONUS`, `M1`, or `M2`), `since`, `until`, and `replay` (`yes` or `no`).  Times are either a date or time (like
+    /// `2022-05-04` or `2022-05-04T18:00`, in UTC), or how long before `now` (like `30m`, `6h`, or
+    /// `2d`).
+    pub fn parse(text: &str, now: DateTime<Utc>) -> Result<IndexQuery, String> {
+        fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{}={} isn't a valid number", key, value))
+        }
+
+        let mut query = IndexQuery::default();
+        for term in text.split_whitespace() {
+            let (key, value) = match term.split_once('=') {
+                Some((key, value)) => (key.to_ascii_lowercase(), value),
+                None => ("pattern".to_string(), term),
+            };
+            match key.as_str() {
+                "pattern" => query.pattern = Some(value.to_string()),
+                "vcid" => query.vcid = Some(number(&key, value)?),
+                "type" => query.filetype_code = Some(number(&key, value)?),
+                "product" => query.product_id = Some(number(&key, value)?),
+                "band" => query.band = Some(number(&key, value.trim_start_matches(['C', 'c']))?),
+                "region" => {
+                    let region = match value.to_ascii_uppercase().as_str() {
+                        "FD" | "F" | "FULL" => "FD",
+                        "CONUS" | "C" => "CONUS",
+                        "M1" => "M1",
+                        "M2" => "M2",
+                        _ => return Err(format!("region={} should be FD, CONUS, M1, or M2", value)),
+                    };
+                    query.region = Some(region.to_string());
+                }
+                "since" => query.since = Some(parse_time(value, now)?),
+                "until" => query.until = Some(parse_time(value, now)?),
+                "replay" => {
+                    query.replay = match value.to_ascii_lowercase().as_str() {
+                        "yes" | "true" => Some(true),
+                        "no" | "false" => Some(false),
+                        _ => return Err(format!("replay={} should be yes or no", value)),
+                    }
+                }
+                _ => return Err(format!("{} isn't something that can be searched for", key)),
+            }
+        }
+        Ok(query)
+    }
+
+    pub fn matches(&self, record: &IndexRecord) -> bool {
+        let pattern_matches = match &self.pattern {
+            Some(pattern) => glob_match(pattern.as_bytes(), record.annotation.as_bytes()),
+            None => true,
+        };
+        let goes = match self.band.is_some() || self.region.is_some() {
+            true => GoesRFilename::parse(&record.annotation),
+            false => None,
+        };
+        let band = goes.as_ref().and_then(|g| g.band.as_ref()?.get(1..)?.parse().ok());
+        let region = goes.as_ref().and_then(|g| region_code(&g.product));
+        pattern_matches
+            && (self.vcid.is_none() || self.vcid == Some(record.vcid))
+            && (self.filetype_code.is_none() || self.filetype_code == Some(record.filetype_code))
+            && (self.product_id.is_none() || self.product_id == record.product_id)
+            && (self.band.is_none() || self.band == band)
+            && (self.region.is_none() || self.region.as_deref() == region)
+            && self.since.is_none_or(|since| record.received >= since)
+            && self.until.is_none_or(|until| record.received < until)
+            && self.replay.is_none_or(|replay| record.replay == replay)
+    }
+
+    /// The days of the index to search: from `since` (or the day of `until`, or today) to `until`
+    /// (or today)
+    pub fn days(&self, now: DateTime<Utc>) -> impl Iterator<Item = NaiveDate> {
+        let last = self.until.unwrap_or(now).date_naive();
+        let first = self.since.map_or(last, |since| since.date_naive());
+        first.iter_days().take_while(move |day| *day <= last)
+    }
+}
+
+/// A time in a query, like `2022-05-04`, `2022-05-04T18:00`, or `6h` (ago)
+fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    for format in ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(time.and_utc());
+        }
+    }
+    let (n, unit) = value.split_at(value.len() - value.chars().last().map_or(0, char::len_utf8));
+    let ago = match (n.parse::<i64>(), unit) {
+        (Ok(n), "m") => Duration::try_minutes(n),
+        (Ok(n), "h") => Duration::try_hours(n),
+        (Ok(n), "d") => Duration::try_days(n),
+        _ => None,
+    };
+    match ago {
+        Some(ago) => Ok(now - ago),
+        None => Err(format!("{} should be a date, a time, or like 30m, 6h, or 2d", value)),
+    }
+}
+
+/// The scan region of a GOES-R product (like `CMIPF`), as it's written in queries
+fn region_code(product: &str) -> Option<&'static str> {
+    if product.ends_with("M1") {
+        Some("M1")
+    } else if product.ends_with("M2") {
+        Some("M2")
+    } else if product.ends_with('F') {
+        Some("FD")
+    } else if product.ends_with('C') {
+        Some("CONUS")
+    } else {
+        None
+    }
+}
+
+pub struct ProductIndex {
+    dir: PathBuf,
+}
+
+impl ProductIndex {
+    /// Opens (or creates) the index found in the `index` directory under `root`
+    pub fn new(root: impl AsRef<Path>) -> ProductIndex {
+        ProductIndex {
+            dir: root.as_ref().join("index"),
+        }
+    }
+
+    fn path_for(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", date.format("%Y-%m-%d")))
+    }
+
+    /// Append a record to the index file for the day it was received
+    pub fn append(&self, record: &IndexRecord) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path_for(record.received.naive_utc().date()))?;
+        let line = serde_json::to_string(record)?;
+        writeln!(file, "{}", line)
+    }
+
+    /// Reads all records received on the given day
+    ///
+    /// A missing index file is not an error, and yields no records.  Lines that fail to parse
+    /// are skipped with a warning.
+    pub fn read_day(&self, date: NaiveDate) -> std::io::Result<Vec<IndexRecord>> {
+        Ok(self.read_day_checked(date)?.0)
+    }
+
+    /// Like [`read_day`](ProductIndex::read_day), but also returns how many lines were skipped
+    pub fn read_day_checked(&self, date: NaiveDate) -> std::io::Result<(Vec<IndexRecord>, usize)> {
+        let file = match std::fs::File::open(self.path_for(date)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(e),
+        };
+
+        let mut records = Vec::new();
+        let mut bad_lines = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(rec) => records.push(rec),
+                Err(e) => {
+                    warn!("Skipping bad index line: {}", e);
+                    bad_lines += 1;
+                }
+            }
+        }
+        Ok((records, bad_lines))
+    }
+
+    /// Every day that has an index file, oldest first
+    pub fn days(&self) -> std::io::Result<Vec<NaiveDate>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut days = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let day = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(".jsonl"))
+                .and_then(|n| NaiveDate::parse_from_str(n, "%Y-%m-%d").ok());
+            days.extend(day);
+        }
+        days.sort_unstable();
+        Ok(days)
+    }
+
+    /// The records from the given days that match `query`, in the order they were received
+    pub fn query(
+        &self,
+        days: impl IntoIterator<Item = NaiveDate>,
+        query: &IndexQuery,
+    ) -> std::io::Result<Vec<IndexRecord>> {
+        let mut records = Vec::new();
+        for day in days {
+            records.extend(self.read_day(day)?.into_iter().filter(|r| query.matches(r)));
+        }
+        records.sort_by_key(|r| r.received);
+        Ok(records)
+    }
+
+    /// The records that match `query` (on the days it covers, see [`IndexQuery::days`]), in the
+    /// order they were received
+    pub fn search(&self, query: &IndexQuery, now: DateTime<Utc>) -> std::io::Result<Vec<IndexRecord>> {
+        self.query(query.days(now), query)
+    }
+
+    /// Finds which segments were in the last written copy of a segmented image, from the records
+    /// of the given day and the day before
+    pub fn written_segments(&self, annotation: &str, date: NaiveDate) -> std::io::Result<Option<Vec<bool>>> {
+        for day in [Some(date), date.pred_opt()].iter().flatten() {
+            let found = self
+                .read_day(*day)?
+                .into_iter()
+                .rev()
+                .filter(|r| r.annotation == annotation)
+                .find_map(|r| r.segments);
+            if let Some(mask) = found {
+                return Ok(Some(mask.chars().map(|c| c == '1').collect()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Replaces all records for the given day
+    ///
+    /// The new file is written next to the old one and then renamed over it, so readers never see
+    /// a partial index.
+    pub fn write_day(&self, date: NaiveDate, records: &[IndexRecord]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.path_for(date);
+        let tmp = path.with_extension("jsonl.tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        for record in records {
+            writeln!(file, "{}", serde_json::to_string(record)?)?;
+        }
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// Index records kept in memory instead of on disk, for when nothing should be written
+///
+/// Clones share the same records.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIndex {
+    records: Arc<Mutex<Vec<IndexRecord>>>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> MemoryIndex {
+        MemoryIndex::default()
+    }
+
+    pub fn append(&self, record: IndexRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// All records received on the given day
+    pub fn read_day(&self, date: NaiveDate) -> Vec<IndexRecord> {
+        let records = self.records.lock().unwrap();
+        records
+            .iter()
+            .filter(|r| r.received.naive_utc().date() == date)
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+enum IndexTarget {
+    Disk(ProductIndex),
+    Memory(MemoryIndex),
+}
+
+/// A handler that records every LRIT file into a [`ProductIndex`] (or a [`MemoryIndex`])
+pub struct IndexHandler {
+    index: IndexTarget,
+}
+
+impl IndexHandler {
+    pub fn new(root: impl AsRef<Path>) -> IndexHandler {
+        IndexHandler {
+            index: IndexTarget::Disk(ProductIndex::new(root)),
+        }
+    }
+
+    /// Records every LRIT file into `index`, without writing anything
+    pub fn in_memory(index: MemoryIndex) -> IndexHandler {
+        IndexHandler {
+            index: IndexTarget::Memory(index),
+        }
+    }
+}
+
+impl Handler for IndexHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let record = IndexRecord::from_lrit(lrit, Utc::now()).ok_or(HandlerError::MissingHeader("annotation"))?;
+        match &self.index {
+            IndexTarget::Disk(index) => index.append(&record)?,
+            IndexTarget::Memory(index) => index.append(record),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+    use super::{IndexHandler, IndexQuery, IndexRecord, MemoryIndex, ProductIndex};
+    use crate::{handlers::Handler, lrit::LRIT, sim::LritBuilder};
+
+    #[test]
+    fn test_memory_index() {
+        let index = MemoryIndex::new();
+        let mut handler = IndexHandler::in_memory(index.clone());
+        let bytes = LritBuilder::new(2).annotation("A_NOUS42KWNO.TXT").build(b"text");
+        handler.handle(&LRIT::from_bytes(1, &bytes).unwrap()).unwrap();
+        assert!(handler
+            .handle(&LRIT::from_bytes(1, &LritBuilder::new(2).build(b"")).unwrap())
+            .is_err());
+
+        let records = index.read_day(Utc::now().naive_utc().date());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].annotation, "A_NOUS42KWNO.TXT");
+    }
+
+    #[test]
+    fn test_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = IndexHandler::new(dir.path());
+        for (filetype, name) in [(2, "A_FXUS61KPHI.TXT"), (2, "A_NOUS42KWNO.TXT"), (0, "OR_ABI-L2-CMIPF")] {
+            let bytes = LritBuilder::new(filetype).annotation(name).build(b"data");
+            handler.handle(&LRIT::from_bytes(20, &bytes).unwrap()).unwrap();
+        }
+        let index = ProductIndex::new(dir.path());
+        std::fs::write(dir.path().join("index/2022-05-04.jsonl"), "not json\n").unwrap();
+        std::fs::write(dir.path().join("index/notes.txt"), "").unwrap();
+        let old = NaiveDate::from_ymd_opt(2022, 5, 4).unwrap();
+        let today = Utc::now().naive_utc().date();
+        assert_eq!(index.days().unwrap(), [old, today]);
+        assert_eq!(index.read_day_checked(old).unwrap().1, 1);
+
+        let query = IndexQuery {
+            pattern: Some("a_*kphi*".to_string()),
+            ..Default::default()
+        };
+        let found = index.query(index.days().unwrap(), &query).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].annotation, "A_FXUS61KPHI.TXT");
+        let query = IndexQuery {
+            filetype_code: Some(2),
+            vcid: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(index.query([today], &query).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_query_text() {
+        let now = Utc.with_ymd_and_hms(2022, 5, 4, 18, 0, 0).unwrap();
+        let query = IndexQuery::parse("band=13 region=fd since=6h", now).unwrap();
+        assert_eq!(query.band, Some(13));
+        assert_eq!(query.region.as_deref(), Some("FD"));
+        assert_eq!(query.since, Some(now - Duration::hours(6)));
+        assert_eq!(
+            IndexQuery::parse("*TOR* type=2 until=2022-05-03T12:00", now).unwrap(),
+            IndexQuery {
+                pattern: Some("*TOR*".to_string()),
+                filetype_code: Some(2),
+                until: Some(Utc.with_ymd_and_hms(2022, 5, 3, 12, 0, 0).unwrap()),
+                ..Default::default()
+            }
+        );
+        assert!(IndexQuery::parse("band=blue", now).is_err());
+        assert!(IndexQuery::parse("since=6y", now).is_err());
+        assert!(IndexQuery::parse("color=red", now).is_err());
+        assert_eq!(IndexQuery::parse("replay=no", now).unwrap().replay, Some(false));
+
+        let record = |name: &str, received| IndexRecord {
+            received,
+            ..IndexRecord::from_lrit(
+                &LRIT::from_bytes(13, &LritBuilder::new(0).annotation(name).build(b"")).unwrap(),
+                now,
+            )
+            .unwrap()
+        };
+        let fd = "OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit";
+        assert!(query.matches(&record(fd, now)));
+        assert!(!query.matches(&record(fd, now - Duration::hours(7))));
+        assert!(!query.matches(&record(&fd.replace("C13", "C02"), now)));
+        assert!(!query.matches(&record(&fd.replace("CMIPF", "CMIPC"), now)));
+        let replayed = IndexRecord {
+            replay: true,
+            ..record(fd, now)
+        };
+        assert!(query.matches(&replayed));
+        assert!(!IndexQuery::parse("replay=no", now).unwrap().matches(&replayed));
+        assert_eq!(query.days(now).count(), 1);
+        assert_eq!(IndexQuery::parse("since=1d", now).unwrap().days(now).count(), 2);
+    }
+}
@@ -0,0 +1,451 @@
+//! Exporting observations in InfluxDB line protocol
+//!
+//! Decoded observations (METARs, SHEF values from river gauges and DCS platforms) and signal
+//! metrics (DCS signal strength, and the receiver's own packet counts) are turned into [`Point`]s,
+//! which are written as [line protocol] to a file or to an InfluxDB server.  That's enough for
+//! Grafana dashboards of weather and river data that came purely from the satellite feed.
+//!
+//! Points are sent through an [`InfluxSender`] to an [`InfluxWriter`] on its own thread, which
+//! writes them in batches.  If a write fails, the points are kept (up to a limit) and written
+//! with the next batch, so an InfluxDB server that's down for a while doesn't lose data.
+//!
+//! [line protocol]: https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::mpsc::{channel, RecvTimeoutError, Sender},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+
+use crate::{
+    emwin::{
+        metar::{Altimeter, Metar, SpeedUnit, Visibility},
+        shef::{ShefValue, Units},
+    },
+    events::{DcsBlockEvent, Event, EventSink},
+    stats::StatsSnapshot,
+};
+
+/// The most lines to keep while the output is failing; older ones are dropped
+const MAX_PENDING: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+}
+
+impl From<f64> for FieldValue {
+    fn from(v: f64) -> Self {
+        FieldValue::Float(v)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(v: i64) -> Self {
+        FieldValue::Integer(v)
+    }
+}
+
+impl From<bool> for FieldValue {
+    fn from(v: bool) -> Self {
+        FieldValue::Boolean(v)
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(v: String) -> Self {
+        FieldValue::String(v)
+    }
+}
+
+/// One line of line protocol: a measurement, with its tags and fields at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, FieldValue)>,
+    pub time: DateTime<Utc>,
+}
+
+impl Point {
+    pub fn new(measurement: &str, time: DateTime<Utc>) -> Point {
+        Point {
+            measurement: measurement.to_string(),
+            tags: Vec::new(),
+            fields: Vec::new(),
+            time,
+        }
+    }
+
+    pub fn tag(mut self, key: &str, value: impl Into<String>) -> Self {
+        let value = value.into();
+        // empty tag values aren't allowed
+        if !value.is_empty() {
+            self.tags.push((key.to_string(), value));
+        }
+        self
+    }
+
+    pub fn field(mut self, key: &str, value: impl Into<FieldValue>) -> Self {
+        self.fields.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Adds a field if there's a value
+    pub fn field_opt(self, key: &str, value: Option<impl Into<FieldValue>>) -> Self {
+        match value {
+            Some(value) => self.field(key, value),
+            None => self,
+        }
+    }
+
+    /// The point as a line of line protocol (without a newline), with a timestamp in nanoseconds
+    ///
+    /// Returns `None` if the point has no fields, since InfluxDB rejects those.
+    pub fn to_line(&self) -> Option<String> {
+        if self.fields.is_empty() {
+            return None;
+        }
+        let mut line = escape(&self.measurement, ", ");
+        for (key, value) in &self.tags {
+            line.push_str(&format!(",{}={}", escape(key, ",= "), escape(value, ",= ")));
+        }
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            line.push(if i == 0 { ' ' } else { ',' });
+            line.push_str(&escape(key, ",= "));
+            line.push('=');
+            match value {
+                FieldValue::Float(v) => line.push_str(&v.to_string()),
+                FieldValue::Integer(v) => line.push_str(&format!("{}i", v)),
+                FieldValue::Boolean(v) => line.push_str(&v.to_string()),
+                FieldValue::String(v) => line.push_str(&format!("\"{}\"", escape(v, "\""))),
+            }
+        }
+        let nanos = self.time.timestamp() as i128 * 1_000_000_000 + self.time.timestamp_subsec_nanos() as i128;
+        line.push_str(&format!(" {}", nanos));
+        Some(line)
+    }
+
+    /// A `metar` point, tagged with the station, with temperatures in degrees C, wind in knots,
+    /// visibility in statute miles, and pressure in hPa
+    pub fn from_metar(metar: &Metar, time: DateTime<Utc>) -> Point {
+        let wind = metar.conditions.wind.as_ref();
+        let knots = |speed: u16| {
+            let speed = speed as f64;
+            match wind.map(|w| w.unit) {
+                Some(SpeedUnit::MetersPerSecond) => speed * 1.943_844,
+                Some(SpeedUnit::KilometersPerHour) => speed / 1.852,
+                _ => speed,
+            }
+        };
+        let visibility = metar.conditions.visibility.and_then(|v| match v {
+            Visibility::Meters(m) => Some(m as f64 / 1609.344),
+            Visibility::StatuteMiles(sm) | Visibility::LessThanMiles(sm) | Visibility::MoreThanMiles(sm) => {
+                Some(sm as f64)
+            }
+            Visibility::Cavok => None,
+        });
+        let pressure = metar.altimeter.map(|a| match a {
+            Altimeter::InchesOfMercury(inhg) => inhg as f64 * 33.863_886,
+            Altimeter::Hectopascals(hpa) => hpa as f64,
+        });
+        Point::new("metar", time)
+            .tag("station", metar.station.as_str())
+            .field_opt("temperature", metar.temperature.map(|t| t as f64))
+            .field_opt("dew_point", metar.dew_point.map(|t| t as f64))
+            .field_opt("wind_direction", wind.and_then(|w| w.direction).map(|d| d as i64))
+            .field_opt("wind_speed", wind.map(|w| knots(w.speed)))
+            .field_opt("wind_gust", wind.and_then(|w| w.gust).map(knots))
+            .field_opt("visibility", visibility)
+            .field_opt("altimeter", pressure)
+    }
+
+    /// A `shef` point, tagged with the location, parameter code, and units
+    ///
+    /// Returns `None` for missing values.
+    pub fn from_shef(value: &ShefValue) -> Option<Point> {
+        let units = match value.units {
+            Units::English => "english",
+            Units::Si => "si",
+        };
+        Some(
+            Point::new("shef", value.time)
+                .tag("location", value.location.as_str())
+                .tag("parameter", value.parameter.as_str())
+                .tag("units", units)
+                .field("value", value.value?)
+                .field("revised", value.revised),
+        )
+    }
+
+    /// A `dcs` point, tagged with the platform and channel
+    pub fn from_dcs(block: &DcsBlockEvent) -> Point {
+        Point::new("dcs", block.carrier_start)
+            .tag("address", block.address.as_str())
+            .tag("channel", block.channel.to_string())
+            .tag("source", block.source.as_str())
+            .tag("spacecraft", block.spacecraft.as_str())
+            .field("signal_strength", block.signal_strength as f64)
+            .field("bytes", block.bytes as i64)
+    }
+
+    /// A `receiver` point, with the totals from the receiver's statistics
+    pub fn from_stats(stats: &StatsSnapshot, time: DateTime<Utc>) -> Point {
+        Point::new("receiver", time)
+            .field("packets", stats.packets as i64)
+            .field("bytes", stats.bytes as i64)
+            .field("fills", stats.fills as i64)
+            .field("discards", stats.discards as i64)
+            .field("corrupt_packets", stats.corrupt_packets as i64)
+            .field("decompression_errors", stats.decompression_errors as i64)
+            .field("duplicates", stats.duplicates as i64)
+            .field("dropped_packets", stats.dropped_packets as i64)
+            .field("products", stats.products.values().sum::<usize>() as i64)
+    }
+}
+
+/// Escapes each of `chars` (and backslashes) with a backslash
+fn escape(s: &str, chars: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || chars.contains(c) {
+            escaped.push('\\');
+        }
+        // line protocol can't have newlines, even escaped
+        escaped.push(if c == '\n' { ' ' } else { c });
+    }
+    escaped
+}
+
+/// Where line protocol is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InfluxOutput {
+    /// Appended to a file
+    File(PathBuf),
+    /// `POST`ed with `curl` to a write URL, like `http://localhost:8086/api/v2/write?org=home&bucket=goes`
+    /// (InfluxDB 2) or `http://localhost:8086/write?db=goes` (InfluxDB 1)
+    ///
+    /// The token is sent in an `Authorization: Token` header.  Otherwise, credentials can go in
+    /// `~/.netrc`, like with [`HttpTarget`](crate::mirror::HttpTarget).
+    Http { url: String, token: Option<String> },
+}
+
+impl InfluxOutput {
+    /// An `http://` or `https://` URL, or else a file
+    pub fn parse(output: &str, token: Option<String>) -> InfluxOutput {
+        if output.starts_with("http://") || output.starts_with("https://") {
+            InfluxOutput::Http {
+                url: output.to_string(),
+                token,
+            }
+        } else {
+            InfluxOutput::File(PathBuf::from(output))
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        match self {
+            InfluxOutput::File(path) => OpenOptions::new().create(true).append(true).open(path)?.write_all(data),
+            InfluxOutput::Http { url, token } => {
+                let mut command = Command::new("curl");
+                command.args([
+                    "--fail",
+                    "--silent",
+                    "--show-error",
+                    "--netrc-optional",
+                    "--header",
+                    "Content-Type: text/plain; charset=utf-8",
+                    "--data-binary",
+                    "@-",
+                ]);
+                if let Some(token) = token {
+                    command.arg("--header").arg(format!("Authorization: Token {}", token));
+                }
+                let mut child = command.arg(url).stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(data)?;
+                }
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(io::Error::other(format!("curl to {} failed: {}", url, status)));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Writes points to an [`InfluxOutput`] in batches, see the [module docs](self)
+pub struct InfluxWriter {
+    output: InfluxOutput,
+    /// Lines that haven't been written yet, oldest first
+    pending: VecDeque<String>,
+    max_pending: usize,
+}
+
+impl InfluxWriter {
+    pub fn new(output: InfluxOutput) -> InfluxWriter {
+        InfluxWriter {
+            output,
+            pending: VecDeque::new(),
+            max_pending: MAX_PENDING,
+        }
+    }
+
+    /// Keep at most this many lines while the output is failing (100,000 by default)
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
+    /// Queues a point to be written with the next batch
+    pub fn push(&mut self, point: &Point) {
+        if let Some(line) = point.to_line() {
+            if self.pending.len() >= self.max_pending {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(line);
+        }
+    }
+
+    /// How many lines are waiting to be written
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Writes everything that's queued, returning how many lines were written
+    ///
+    /// If the write fails, the lines stay queued.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let mut data = Vec::new();
+        for line in &self.pending {
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+        }
+        self.output.write(&data)?;
+        let written = self.pending.len();
+        self.pending.clear();
+        Ok(written)
+    }
+
+    /// Starts writing on its own thread, a batch every `interval`
+    ///
+    /// The thread writes what's left and stops once every [`InfluxSender`] has been dropped.
+    pub fn spawn(mut self, interval: Duration) -> io::Result<(InfluxSender, JoinHandle<()>)> {
+        let (sender, receiver) = channel::<Point>();
+        let handle = std::thread::Builder::new().name("influx".to_string()).spawn(move || {
+            let mut next = Instant::now() + interval;
+            loop {
+                let done = match receiver.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                    Ok(point) => {
+                        self.push(&point);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Err(RecvTimeoutError::Disconnected) => true,
+                };
+                if let Err(e) = self.flush() {
+                    warn!("Failed to write {} points to InfluxDB: {}", self.pending(), e);
+                }
+                if done {
+                    break;
+                }
+                next = Instant::now() + interval;
+            }
+        })?;
+        Ok((InfluxSender { sender }, handle))
+    }
+}
+
+/// Sends points to an [`InfluxWriter`]'s thread
+///
+/// As an [`EventSink`], this sends a `dcs` point for every decoded DCS message.
+#[derive(Clone)]
+pub struct InfluxSender {
+    sender: Sender<Point>,
+}
+
+impl InfluxSender {
+    pub fn send(&self, point: Point) {
+        // the writer only stops once every sender is gone
+        let _ = self.sender.send(point);
+    }
+}
+
+impl EventSink for InfluxSender {
+    fn publish(&mut self, event: &Event) {
+        if let Event::DcsBlockDecoded(block) = event {
+            self.send(Point::from_dcs(block));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{FieldValue, InfluxOutput, InfluxWriter, Point};
+    use crate::emwin::metar::Metar;
+
+    #[test]
+    fn test_line_protocol() {
+        let time = Utc.with_ymd_and_hms(2022, 5, 7, 11, 54, 0).unwrap();
+        let metar = Metar::parse("METAR KBOS 071154Z 18005MPS 9999 FEW050 M02/M05 Q1013").unwrap();
+        assert_eq!(
+            Point::from_metar(&metar, time).to_line().unwrap(),
+            "metar,station=KBOS temperature=-2,dew_point=-5,wind_direction=180i,wind_speed=9.71922,\
+             visibility=6.213090551181102,altimeter=1013 1651924440000000000"
+        );
+
+        let point = Point::new("odd name", time)
+            .tag("a=b", "c,d")
+            .tag("empty", "")
+            .field("text", FieldValue::String("say \"hi\"\n".to_string()))
+            .field("ok", true);
+        assert_eq!(
+            point.to_line().unwrap(),
+            "odd\\ name,a\\=b=c\\,d text=\"say \\\"hi\\\" \",ok=true 1651924440000000000"
+        );
+        assert_eq!(Point::new("none", time).to_line(), None);
+
+        // points are kept while the output is failing, and written once it's back
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing/points.txt");
+        let mut writer = InfluxWriter::new(InfluxOutput::File(path.clone())).with_max_pending(2);
+        for i in 0..3 {
+            writer.push(&Point::new("n", time).field("i", i as i64));
+        }
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.pending(), 2);
+        std::fs::create_dir(dir.path().join("missing")).unwrap();
+        assert_eq!(writer.flush().unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "n i=1i 1651924440000000000\nn i=2i 1651924440000000000\n"
+        );
+
+        // the thread writes what's left when it's stopped
+        let (sender, handle) = InfluxWriter::new(InfluxOutput::File(path.clone()))
+            .spawn(Duration::from_secs(3600))
+            .unwrap();
+        sender.send(Point::new("n", time).field("i", 3i64));
+        drop(sender);
+        handle.join().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 3);
+    }
+}
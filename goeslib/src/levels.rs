@@ -0,0 +1,150 @@
+//! Automatic contrast stretching of images
+//!
+//! Images are sent with a fixed mapping from radiance to pixel value, so a visible band image
+//! taken near the terminator (or in winter) comes out mostly dark.  A percentile [`Stretch`]
+//! spreads the pixel values out: the value below which (say) 2% of the pixels fall becomes black,
+//! the value below which 98% fall becomes white, and everything in between is scaled linearly.
+//! [`AutoLevels`] picks the stretch for each image, with overrides for particular bands and
+//! regions.
+use std::collections::HashMap;
+
+use crate::xmp::ImageMetadata;
+
+/// A contrast stretch between two percentiles of an image's histogram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stretch {
+    low: f32,
+    high: f32,
+}
+
+impl Stretch {
+    /// Stretch from the `low` percentile to the `high` one, like 2 and 98
+    pub fn new(low: f32, high: f32) -> Stretch {
+        let low = low.clamp(0.0, 100.0);
+        Stretch {
+            low,
+            high: high.clamp(low, 100.0),
+        }
+    }
+
+    /// The pixel values at the low and high percentiles
+    ///
+    /// Pixels that are 0 (space, and missing segments) aren't counted.  Returns `None` if there
+    /// aren't any other pixels, or if they're all about the same.
+    pub fn levels(&self, pixels: &[u8]) -> Option<(u8, u8)> {
+        let mut histogram = [0u64; 256];
+        pixels.iter().for_each(|p| histogram[*p as usize] += 1);
+        let total: u64 = histogram[1..].iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let percentile = |percent: f32| {
+            let target = (total as f64 * f64::from(percent) / 100.0).ceil().max(1.0) as u64;
+            let mut cdf = 0;
+            for (value, count) in histogram.iter().enumerate().skip(1) {
+                cdf += count;
+                if cdf >= target {
+                    return value as u8;
+                }
+            }
+            255
+        };
+        let (low, high) = (percentile(self.low), percentile(self.high));
+        if high <= low {
+            return None;
+        }
+        Some((low, high))
+    }
+
+    /// Stretches the pixels of an image in place, returning false if they were left alone (see
+    /// [`levels`](Stretch::levels))
+    pub fn apply(&self, pixels: &mut [u8]) -> bool {
+        match self.levels(pixels) {
+            Some(levels) => {
+                apply_levels(levels, pixels);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Stretches pixels so `low` becomes black and `high` white, like [`Stretch::apply`] but with
+/// levels that were worked out before (0 is left alone)
+pub fn apply_levels((low, high): (u8, u8), pixels: &mut [u8]) {
+    let range = u32::from(high.saturating_sub(low)).max(1);
+    let mut table = [0u8; 256];
+    for (value, out) in table.iter_mut().enumerate().skip(1) {
+        let value = (value as u32).clamp(u32::from(low), u32::from(high.max(low))) - u32::from(low);
+        *out = ((value * 255 + range / 2) / range).min(255) as u8;
+    }
+    pixels.iter_mut().for_each(|p| *p = table[*p as usize]);
+}
+
+/// Which [`Stretch`] (if any) to use for each image
+///
+/// A band's setting takes precedence over a region's, which takes precedence over the default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AutoLevels {
+    default: Option<Stretch>,
+    /// Keyed by band, like "C02"
+    bands: HashMap<String, Option<Stretch>>,
+    /// Keyed by region, like "Full Disk" or "Mesoscale 1"
+    regions: HashMap<String, Option<Stretch>>,
+}
+
+impl AutoLevels {
+    /// Use `default` for images without a setting of their own (`None` leaves them alone)
+    pub fn new(default: Option<Stretch>) -> AutoLevels {
+        AutoLevels {
+            default,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_band(mut self, band: impl Into<String>, stretch: Option<Stretch>) -> Self {
+        self.bands.insert(band.into(), stretch);
+        self
+    }
+
+    pub fn with_region(mut self, region: impl Into<String>, stretch: Option<Stretch>) -> Self {
+        self.regions.insert(region.into(), stretch);
+        self
+    }
+
+    /// The stretch for an image
+    pub fn stretch(&self, meta: &ImageMetadata) -> Option<Stretch> {
+        let band = meta.band.as_ref().and_then(|band| self.bands.get(band));
+        let region = meta.region.as_ref().and_then(|region| self.regions.get(region));
+        *band.or(region).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoLevels, Stretch};
+    use crate::xmp::ImageMetadata;
+
+    #[test]
+    fn test_stretch() {
+        // a dark image, with some space around it
+        let mut pixels: Vec<u8> = (0..100).map(|i| 10 + (i % 50) as u8).collect();
+        pixels.extend([0; 50]);
+        let stretch = Stretch::new(2.0, 98.0);
+        assert_eq!(stretch.levels(&pixels), Some((10, 58)));
+        assert!(stretch.apply(&mut pixels));
+        assert_eq!((pixels[0], pixels[25], pixels[49], pixels[120]), (0, 133, 255, 0));
+        assert!(!stretch.apply(&mut [0, 0, 7, 7]));
+
+        let levels = AutoLevels::new(Some(stretch))
+            .with_region("Full Disk", Some(Stretch::new(1.0, 99.0)))
+            .with_band("C13", None);
+        let meta = |name| ImageMetadata::from_product_name(name);
+        let c02 = meta("OR_ABI-L2-CMIPF-M6C02_G16_s20221241800205.lrit");
+        assert_eq!(levels.stretch(&c02), Some(Stretch::new(1.0, 99.0)));
+        let c13 = meta("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit");
+        assert_eq!(levels.stretch(&c13), None);
+        let meso = meta("OR_ABI-L2-CMIPM1-M6C02_G16_s20221241800205.lrit");
+        assert_eq!(levels.stretch(&meso), Some(stretch));
+    }
+}
@@ -1,10 +1,105 @@
 //! GOESBOX is a library and application to parsing a GOES-R HRIT data stream
+//!
+//! The heavier parts are behind cargo features (all on by default): `image`, `zip`, `rice`,
+//! `archive`, and `server`.  See `Cargo.toml` for what each one brings in.
 pub mod handlers;
 
+mod capabilities;
+pub use self::capabilities::{capabilities, Capabilities};
+
 pub mod lrit;
 
+pub mod annotation;
+
 pub mod crc;
 
 pub mod stats;
 
 pub mod emwin;
+
+pub mod events;
+
+pub mod index;
+
+pub mod report;
+
+pub mod schedule;
+
+pub mod sector;
+
+#[cfg(feature = "image")]
+pub mod timelapse;
+
+pub mod xmp;
+
+pub mod deadletter;
+
+pub mod sim;
+
+pub mod writer;
+
+#[cfg(feature = "archive")]
+pub mod archive;
+
+pub mod quota;
+
+pub mod testproducts;
+
+pub mod capture;
+
+#[cfg(feature = "server")]
+pub mod relay;
+
+#[cfg(feature = "server")]
+pub mod mirror;
+
+#[cfg(feature = "server")]
+pub mod forward;
+
+#[cfg(feature = "server")]
+pub mod permissions;
+
+pub mod clock;
+
+pub mod cache;
+
+pub mod bcd_time;
+
+pub mod bitfield;
+
+pub mod cfdp;
+
+pub mod stream;
+
+pub mod profile;
+
+pub mod survey;
+
+#[cfg(feature = "image")]
+pub mod regen;
+
+pub mod pool;
+
+pub mod influx;
+
+pub mod admin;
+
+pub mod calibration;
+
+pub mod levels;
+
+pub mod storage;
+
+pub mod reparse;
+
+pub mod telemetry;
+
+pub mod logging;
+
+pub mod replay;
+
+pub mod i18n;
+
+pub mod selftest;
+
+pub mod compare;
@@ -0,0 +1,311 @@
+//! Where log messages go
+//!
+//! goeslib logs with the `log` crate, and a [`Logger`] sends those messages to any combination of
+//! sinks: a function (like the one that feeds the UI's message pane), a log file, and stderr.  It's
+//! set up with a [`LoggerBuilder`].
+//!
+//! Debug messages are only kept from the targets that ask for them (like `goeslib::lrit`), since
+//! the decoder is chatty at that level.  A target can also be throttled to a number of lines per
+//! second, so a handler that's failing on every file can't flood the UI; the lines that are
+//! dropped are counted, and the count is logged before the target's next line gets through.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// A log message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub level: Level,
+    /// The module that logged this, like `goeslib::lrit`
+    pub target: String,
+    pub message: String,
+}
+
+impl LogLine {
+    /// The last part of the target, like `lrit` for `goeslib::lrit`
+    pub fn tag(&self) -> &str {
+        self.target.rsplit("::").next().unwrap_or(&self.target)
+    }
+
+    /// Returns true if the message or the target contains `search` (which should be lowercase)
+    pub fn matches(&self, search: &str) -> bool {
+        self.message.to_lowercase().contains(search) || self.target.to_lowercase().contains(search)
+    }
+}
+
+enum Sink {
+    Function(Box<dyn Fn(LogLine) + Send + Sync>),
+    File(Mutex<File>),
+    Stderr,
+}
+
+/// Sets up a [`Logger`]
+pub struct LoggerBuilder {
+    level: LevelFilter,
+    debug_targets: Vec<String>,
+    sinks: Vec<Sink>,
+    lines_per_second: Option<usize>,
+}
+
+impl Default for LoggerBuilder {
+    fn default() -> Self {
+        LoggerBuilder::new()
+    }
+}
+
+impl LoggerBuilder {
+    /// A logger that keeps info messages and above, and doesn't send them anywhere yet
+    pub fn new() -> LoggerBuilder {
+        LoggerBuilder {
+            level: LevelFilter::Info,
+            debug_targets: Vec::new(),
+            sinks: Vec::new(),
+            lines_per_second: None,
+        }
+    }
+
+    /// Keep messages at this level and above, from every target
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Also keep debug (and trace) messages from targets that start with this, like
+    /// `goeslib::lrit`
+    pub fn with_debug_target(mut self, target: impl Into<String>) -> Self {
+        self.debug_targets.push(target.into());
+        self
+    }
+
+    /// Give every line to a function, like one that sends it to the UI
+    pub fn with_sink(mut self, sink: impl Fn(LogLine) + Send + Sync + 'static) -> Self {
+        self.sinks.push(Sink::Function(Box::new(sink)));
+        self
+    }
+
+    /// Append every line to a file
+    pub fn with_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.sinks.push(Sink::File(Mutex::new(file)));
+        Ok(self)
+    }
+
+    /// Write every line to stderr
+    pub fn with_stderr(mut self) -> Self {
+        self.sinks.push(Sink::Stderr);
+        self
+    }
+
+    /// Keep no more than this many lines a second from each target
+    pub fn with_throttle(mut self, lines_per_second: usize) -> Self {
+        self.lines_per_second = Some(lines_per_second.max(1));
+        self
+    }
+
+    pub fn build(self) -> Logger {
+        Logger {
+            level: self.level,
+            debug_targets: self.debug_targets,
+            sinks: self.sinks,
+            lines_per_second: self.lines_per_second,
+            throttles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Builds the logger, and makes it the one that the `log` macros use
+    pub fn install(self) -> Result<(), SetLoggerError> {
+        let logger = self.build();
+        let max_level = logger.max_level();
+        log::set_boxed_logger(Box::new(logger))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+/// Lines from one target in the current second
+struct Throttle {
+    start: Instant,
+    lines: usize,
+    dropped: usize,
+}
+
+/// Sends log messages to its sinks, see the [module docs](self)
+pub struct Logger {
+    level: LevelFilter,
+    debug_targets: Vec<String>,
+    sinks: Vec<Sink>,
+    lines_per_second: Option<usize>,
+    throttles: Mutex<HashMap<String, Throttle>>,
+}
+
+impl Logger {
+    /// The most verbose level that any target is kept at
+    pub fn max_level(&self) -> LevelFilter {
+        match self.debug_targets.is_empty() {
+            true => self.level,
+            false => self.level.max(LevelFilter::Trace),
+        }
+    }
+
+    fn wanted(&self, level: Level, target: &str) -> bool {
+        level <= self.level
+            || (level >= Level::Debug && self.debug_targets.iter().any(|t| target.starts_with(t.as_str())))
+    }
+
+    /// Sends a line to the sinks, unless its target has been throttled
+    fn line(&self, line: LogLine, now: Instant) {
+        if let Some(limit) = self.lines_per_second {
+            let mut throttles = self.throttles.lock().unwrap();
+            let throttle = throttles.entry(line.target.clone()).or_insert(Throttle {
+                start: now,
+                lines: 0,
+                dropped: 0,
+            });
+            if now.duration_since(throttle.start) >= Duration::from_secs(1) {
+                if throttle.dropped > 0 {
+                    self.send(LogLine {
+                        level: Level::Warn,
+                        target: line.target.clone(),
+                        message: format!("Dropped {} log messages", throttle.dropped),
+                    });
+                }
+                *throttle = Throttle {
+                    start: now,
+                    lines: 0,
+                    dropped: 0,
+                };
+            }
+            if throttle.lines >= limit {
+                throttle.dropped += 1;
+                return;
+            }
+            throttle.lines += 1;
+        }
+        self.send(line);
+    }
+
+    fn send(&self, line: LogLine) {
+        for sink in &self.sinks {
+            match sink {
+                Sink::Function(f) => f(line.clone()),
+                Sink::File(file) => {
+                    let mut file = file.lock().unwrap();
+                    let _ = writeln!(
+                        file,
+                        "{} {}",
+                        Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                        format_line(&line)
+                    );
+                }
+                Sink::Stderr => eprintln!("{}", format_line(&line)),
+            }
+        }
+    }
+}
+
+fn format_line(line: &LogLine) -> String {
+    format!("{} {}: {}", line.level, line.target, line.message)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.wanted(metadata.level(), metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.wanted(record.level(), record.target()) {
+            return;
+        }
+        let line = LogLine {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        self.line(line, Instant::now());
+    }
+
+    fn flush(&self) {
+        for sink in &self.sinks {
+            if let Sink::File(file) = sink {
+                let _ = file.lock().unwrap().flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use log::{Level, LevelFilter, Log};
+
+    use super::{LogLine, LoggerBuilder};
+
+    #[test]
+    fn test_logger() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sent = lines.clone();
+        let logger = LoggerBuilder::new()
+            .with_debug_target("goeslib::lrit")
+            .with_sink(move |line| sent.lock().unwrap().push(line))
+            .with_file(dir.path().join("goesbox.log"))
+            .unwrap()
+            .with_throttle(2)
+            .build();
+        assert_eq!(logger.max_level(), LevelFilter::Trace);
+        assert!(logger.wanted(Level::Debug, "goeslib::lrit"));
+        assert!(!logger.wanted(Level::Debug, "goeslib::handlers::text"));
+        assert!(logger.wanted(Level::Info, "goeslib::handlers::text"));
+
+        let line = |target: &str, message: &str| LogLine {
+            level: Level::Info,
+            target: target.to_string(),
+            message: message.to_string(),
+        };
+        let start = Instant::now();
+        for i in 0..5 {
+            logger.line(line("goeslib::handlers::image", &format!("failed {}", i)), start);
+        }
+        logger.line(line("goeslib::lrit", "a different target"), start);
+        logger.line(
+            line("goeslib::handlers::image", "later"),
+            start + Duration::from_secs(1),
+        );
+        logger.flush();
+
+        let lines = lines.lock().unwrap();
+        let messages: Vec<_> = lines.iter().map(|l| l.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "failed 0",
+                "failed 1",
+                "a different target",
+                "Dropped 3 log messages",
+                "later"
+            ]
+        );
+        assert_eq!(lines[3].level, Level::Warn);
+        assert_eq!(lines[0].tag(), "image");
+        assert!(lines[0].matches("image"));
+        let file = std::fs::read_to_string(dir.path().join("goesbox.log")).unwrap();
+        assert_eq!(file.lines().count(), 5);
+        assert!(file
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" INFO goeslib::handlers::image: failed 0"));
+    }
+}
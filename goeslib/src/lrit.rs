@@ -1,16 +1,317 @@
 use byteorder::{NetworkEndian, ReadBytesExt};
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
 use log::{info, warn};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::bitfield::read_bits;
+use crate::calibration::DataFunction;
+use crate::clock::{Clock, SystemClock};
 use crate::crc;
+use crate::deadletter::DeadLetter;
+use crate::pool::BufferPool;
 
 // M_SDU -- Multiplexing Service Data Unit
 // VCLC -- Virtual Channel Link Control
 // VCA -- Virtual Channel Access
 // M_PDU -- Multiplexing Protocol Data Unit
 
+/// The APID used for fill (idle) TP_PDUs
+pub const FILL_APID: u16 = 2047;
+
+/// Radius of the geostationary orbit, in km
+const ORBIT_RADIUS: f64 = 42164.0;
+/// Equatorial radius of the earth, in km
+const EQUATOR_RADIUS: f64 = 6378.137;
+/// Polar radius of the earth, in km
+const POLAR_RADIUS: f64 = 6356.7523;
+
+/// A GOES-R HRIT virtual channel, with what's known to be carried on it
+///
+/// Every APID on a virtual channel carries the same kind of product, so this is also how APIDs
+/// get their names.  This serializes as the bare number.
+///
+/// Ref: GOES-R HRIT/EMWIN Specification (HRIT virtual channel assignments)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum Vcid {
+    /// Administrative text messages (0)
+    Admin,
+    /// ABI mesoscale imagery, from both sectors (1)
+    Mesoscale,
+    /// ABI full disk imagery of one band, which is also the channel number (2, 7, 8, 9, 13, 14,
+    /// and 15)
+    FullDisk(u8),
+    /// EMWIN text and graphics (20 to 22)
+    Emwin(u8),
+    /// NWS text products (23)
+    NwsText,
+    /// NHC graphics (24)
+    NhcGraphics,
+    /// International graphics (26)
+    IntlGraphics,
+    /// DCS messages (30 and 31)
+    Dcs(u8),
+    /// Fill VCDUs (63)
+    Fill,
+    /// A channel without a known assignment
+    Other(u8),
+}
+
+impl Vcid {
+    /// What's carried on this channel, like "ABI B13 FD"
+    pub fn description(self) -> &'static str {
+        match self {
+            Vcid::Admin => "Admin text",
+            Vcid::Mesoscale => "ABI Meso",
+            Vcid::FullDisk(2) => "ABI B02 FD",
+            Vcid::FullDisk(7) => "ABI B07 FD",
+            Vcid::FullDisk(8) => "ABI B08 FD",
+            Vcid::FullDisk(9) => "ABI B09 FD",
+            Vcid::FullDisk(13) => "ABI B13 FD",
+            Vcid::FullDisk(14) => "ABI B14 FD",
+            Vcid::FullDisk(15) => "ABI B15 FD",
+            Vcid::FullDisk(_) => "ABI FD",
+            Vcid::Emwin(_) => "EMWIN",
+            Vcid::NwsText => "NWS text",
+            Vcid::NhcGraphics => "NHC graphics",
+            Vcid::IntlGraphics => "Intl graphics",
+            Vcid::Dcs(_) => "DCS",
+            Vcid::Fill => "Fill",
+            Vcid::Other(_) => "Unknown",
+        }
+    }
+
+    /// Whether this channel only carries ABI full disk imagery, which is only sent over HRIT
+    pub fn is_full_disk(self) -> bool {
+        matches!(self, Vcid::FullDisk(_))
+    }
+
+    pub fn is_emwin(self) -> bool {
+        matches!(self, Vcid::Emwin(_))
+    }
+}
+
+impl From<u8> for Vcid {
+    fn from(vcid: u8) -> Vcid {
+        match vcid {
+            0 => Vcid::Admin,
+            1 => Vcid::Mesoscale,
+            2 | 7 | 8 | 9 | 13 | 14 | 15 => Vcid::FullDisk(vcid),
+            20..=22 => Vcid::Emwin(vcid),
+            23 => Vcid::NwsText,
+            24 => Vcid::NhcGraphics,
+            26 => Vcid::IntlGraphics,
+            30 | 31 => Vcid::Dcs(vcid),
+            63 => Vcid::Fill,
+            _ => Vcid::Other(vcid),
+        }
+    }
+}
+
+impl From<Vcid> for u8 {
+    fn from(vcid: Vcid) -> u8 {
+        match vcid {
+            Vcid::Admin => 0,
+            Vcid::Mesoscale => 1,
+            Vcid::NwsText => 23,
+            Vcid::NhcGraphics => 24,
+            Vcid::IntlGraphics => 26,
+            Vcid::Fill => 63,
+            Vcid::FullDisk(vcid) | Vcid::Emwin(vcid) | Vcid::Dcs(vcid) | Vcid::Other(vcid) => vcid,
+        }
+    }
+}
+
+/// Channels sort by number
+impl Ord for Vcid {
+    fn cmp(&self, other: &Vcid) -> std::cmp::Ordering {
+        u8::from(*self).cmp(&u8::from(*other))
+    }
+}
+
+impl PartialOrd for Vcid {
+    fn partial_cmp(&self, other: &Vcid) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Like `VC13`
+impl std::fmt::Display for Vcid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VC{:02}", u8::from(*self))
+    }
+}
+
+/// The biggest gap in VCDU counters that's counted as dropped VCDUs (a few minutes of HRIT)
+const MAX_COUNTED_DROP: u32 = 10_000;
+
+/// Whether the downlink is GOES-R HRIT, or the slower legacy LRIT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownlinkMode {
+    Hrit,
+    Lrit,
+}
+
+impl DownlinkMode {
+    pub const fn bits_per_second(self) -> u32 {
+        match self {
+            DownlinkMode::Hrit => 400_000,
+            DownlinkMode::Lrit => 128_000,
+        }
+    }
+
+    /// The most VCDUs (including fill) that can arrive in a second
+    pub fn max_vcdus_per_second(self) -> u64 {
+        (self.bits_per_second() as u64 + 8191) / (1024 * 8)
+    }
+
+    /// Guesses the mode from the VCDU rate (including fill) and the virtual channels seen so far
+    ///
+    /// Full disk imagery means HRIT.  Otherwise the rate decides, since the downlink is padded
+    /// with fill to a constant rate.  Returns `None` if nothing has been received yet.
+    pub fn detect(vcdus_per_second: f64, vcids: impl IntoIterator<Item = u8>) -> Option<DownlinkMode> {
+        if vcids.into_iter().any(|vc| Vcid::from(vc).is_full_disk()) {
+            return Some(DownlinkMode::Hrit);
+        }
+        if vcdus_per_second <= 0.0 {
+            return None;
+        }
+        // halfway between the two rates, so some dropped VCDUs don't change the answer
+        let lrit_max = DownlinkMode::Lrit.bits_per_second() as f64 / (1024.0 * 8.0);
+        let hrit_max = DownlinkMode::Hrit.bits_per_second() as f64 / (1024.0 * 8.0);
+        if vcdus_per_second > (lrit_max + hrit_max) / 2.0 {
+            Some(DownlinkMode::Hrit)
+        } else {
+            Some(DownlinkMode::Lrit)
+        }
+    }
+}
+
+impl std::fmt::Display for DownlinkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownlinkMode::Hrit => write!(f, "HRIT"),
+            DownlinkMode::Lrit => write!(f, "LRIT"),
+        }
+    }
+}
+
+/// What the decoder does when its own checks fail
+///
+/// The decoder checks a few things that should always be true, no matter how corrupt the data
+/// is.  If one isn't, it's a bug, or data corrupted in a way that hasn't been seen before.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeMode {
+    /// Panic, which is most useful when developing against clean captures
+    #[default]
+    Strict,
+    /// Drop the data in question, log a warning, and count it as a [decoder
+    /// error](crate::stats::Stat::DecoderError)
+    Tolerant,
+}
+
+impl std::str::FromStr for DecodeMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(DecodeMode::Strict),
+            "tolerant" => Ok(DecodeMode::Tolerant),
+            _ => Err("decode mode must be strict or tolerant"),
+        }
+    }
+}
+
+/// How many bytes usually come before the primary header, in the first TP_PDU of an LRIT file
+///
+/// According to a comment in goestools, these bytes are garbage.  The decoder doesn't rely on
+/// there always being this many, see [`VirtualChannel::with_preamble`].
+pub const PREAMBLE_LEN: usize = 10;
+
+/// How far into the first TP_PDU of an LRIT file to look for its primary header
+const MAX_PREAMBLE_LEN: usize = 32;
+
+/// Finds the primary header in the first TP_PDU of an LRIT file (without its CRC), returning how
+/// many bytes come before it
+///
+/// The usual [`PREAMBLE_LEN`] is tried first, and then every offset from the start.  A header only
+/// counts if the rest of the headers can be read after it, or if they run past the end of the
+/// TP_PDU.
+fn find_primary_header(data: &[u8]) -> Option<usize> {
+    let plausible = |offset: usize| {
+        let bytes = match data.get(offset..) {
+            Some(bytes) => bytes,
+            None => return false,
+        };
+        match read_headers(bytes) {
+            Ok(headers) => headers.primary.header_record_lenth == 16,
+            Err(HeaderError::Truncated { header_type, .. }) => {
+                // only a primary header that says its headers are longer than this TP_PDU
+                header_type == PrimaryHeader::TYPE
+                    && matches!(PrimaryHeader::from_bytes(bytes), Ok(prim) if prim.header_record_lenth == 16)
+            }
+            Err(_) => false,
+        }
+    };
+    std::iter::once(PREAMBLE_LEN)
+        .chain((0..=MAX_PREAMBLE_LEN).filter(|&offset| offset != PREAMBLE_LEN))
+        .find(|&offset| plausible(offset))
+}
+
+/// Checks something the decoder expects to always be true, returning whether it is
+///
+/// In [`DecodeMode::Tolerant`], the caller is expected to recover when this returns false.
+fn check(mode: DecodeMode, ok: bool, what: std::fmt::Arguments, stats: &mut crate::stats::Stats) -> bool {
+    if !ok {
+        match mode {
+            DecodeMode::Strict => panic!("decoder check failed: {}", what),
+            DecodeMode::Tolerant => {
+                warn!("Decoder check failed: {}", what);
+                stats.record(crate::stats::Stat::DecoderError);
+            }
+        }
+    }
+    ok
+}
+
+/// Returns the name of the product carried by an APID on the given virtual channel, if known
+pub fn apid_product_name(vcid: u8, apid: u16) -> Option<&'static str> {
+    if apid == FILL_APID {
+        return Some("Fill");
+    }
+    match Vcid::from(vcid) {
+        Vcid::Other(_) => None,
+        vc => Some(vc.description()),
+    }
+}
+
+/// Formats an APID for log messages, like "123 (ABI B13 FD)"
+pub fn apid_label(vcid: u8, apid: u16) -> String {
+    match apid_product_name(vcid, apid) {
+        Some(name) => format!("{} ({})", apid, name),
+        None => apid.to_string(),
+    }
+}
+
+/// Returns the name of an LRIT file type code, if known
+pub fn filetype_name(code: u8) -> Option<&'static str> {
+    match code {
+        0 => Some("Image"),
+        1 => Some("GTS"),
+        2 => Some("Text"),
+        3 => Some("Key"),
+        130 => Some("DCS"),
+        _ => None,
+    }
+}
+
 fn diff_with_wrap(low: u32, high: u32, max: u32) -> u32 {
     //let max = 1 << 24;
     if low <= high {
@@ -24,8 +325,117 @@ fn diff_with_wrap(low: u32, high: u32, max: u32) -> u32 {
 pub struct LRIT {
     /// The vcid (virtual channel id) that this LRIT file came in on
     pub vcid: u8,
+    /// The APID that this LRIT file came in on, if it was reassembled from a virtual channel
+    pub apid: Option<u16>,
     pub headers: Headers,
+    /// The raw bytes of all headers, as they were received
+    pub raw_headers: Vec<u8>,
     pub data: Vec<u8>,
+    /// The data as it was received, if it was rice compressed (`data` is always decompressed)
+    pub compressed_data: Option<Vec<u8>>,
+    /// How the data was decompressed, if it was rice compressed
+    pub decompression: Option<Decompression>,
+    /// Set if less data arrived than the primary header says there is (what did arrive is kept)
+    pub truncated: Option<Truncation>,
+    /// Set if any of it arrived in VCDUs with the replay flag set (see [`VCDU::is_replay`]), or a
+    /// [`ReplayFilter`](crate::replay::ReplayFilter) found it to be old data sent again
+    pub replay: bool,
+}
+
+/// An LRIT file whose data is shorter than its primary header says
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Truncation {
+    /// The length of the data, from the primary header
+    pub expected_bytes: usize,
+    /// The length of the data that arrived (before decompression, if it was rice compressed)
+    pub received_bytes: usize,
+}
+
+impl Truncation {
+    /// Compares the length of the data that arrived to the length in the primary header
+    pub fn check(primary: &PrimaryHeader, received_bytes: usize) -> Option<Truncation> {
+        let expected_bytes = (primary.data_field_bits / 8) as usize;
+        (received_bytes < expected_bytes).then_some(Truncation {
+            expected_bytes,
+            received_bytes,
+        })
+    }
+}
+
+/// Diagnostics from rice decompressing an LRIT file
+///
+/// An image with zeroed scanlines but no failed scanlines here lost data on the way (TP_PDU
+/// drops), instead of in the decompressor.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Decompression {
+    pub compressed_bytes: usize,
+    pub decompressed_bytes: usize,
+    /// The rice compression parameters, from the headers
+    pub flags: u16,
+    pub pixels_per_block: u8,
+    pub bits_per_pixel: u8,
+    pub scanlines: usize,
+    /// Scanlines that failed to decompress, and were replaced with zeros
+    pub failed_scanlines: usize,
+    /// The scanline where the decompressor returned an error, if it did
+    ///
+    /// The decompressor isn't used again for the rest of the image, so every scanline from here on
+    /// is zeros (and counted in `failed_scanlines`).
+    #[serde(default)]
+    pub error_scanline: Option<usize>,
+    /// Time spent in the decompressor, in milliseconds
+    pub time_ms: f64,
+}
+
+impl LRIT {
+    /// Parses a complete LRIT file (headers followed by data)
+    pub fn from_bytes(vcid: u8, bytes: &[u8]) -> Result<LRIT, HeaderError> {
+        let headers = read_headers(bytes)?;
+        let header_len = headers.primary.total_header_length as usize;
+        let truncated = Truncation::check(&headers.primary, bytes.len() - header_len);
+        Ok(LRIT {
+            vcid,
+            apid: None,
+            headers,
+            raw_headers: bytes[..header_len].to_vec(),
+            data: bytes[header_len..].to_vec(),
+            compressed_data: None,
+            decompression: None,
+            truncated,
+            replay: false,
+        })
+    }
+
+    /// Reconstructs the LRIT file (headers followed by data)
+    ///
+    /// Note that if the data was rice compressed, the data here will have already been
+    /// decompressed, even though the headers will still mention compression.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.raw_headers.len() + self.data.len());
+        bytes.extend_from_slice(&self.raw_headers);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Reconstructs the LRIT file exactly as it was transmitted, with rice compressed data left
+    /// compressed
+    ///
+    /// This is what other LRIT tools expect to read, but [`LRIT::from_bytes`] doesn't decompress,
+    /// so use [`LRIT::to_bytes`] for files that will be read back by goesbox.
+    pub fn to_raw_bytes(&self) -> Vec<u8> {
+        let data = self.compressed_data.as_ref().unwrap_or(&self.data);
+        let mut bytes = Vec::with_capacity(self.raw_headers.len() + data.len());
+        bytes.extend_from_slice(&self.raw_headers);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    /// When the data in this file was taken, as far as its headers say (the time stamp header, or
+    /// the annotation if there isn't one)
+    pub fn data_time(&self) -> Option<DateTime<Utc>> {
+        (self.headers.timestamp.as_ref().and_then(TimeStampRecord::utc))
+            .or_else(|| self.headers.annotation.as_ref()?.parsed().date())
+    }
 }
 
 impl Debug for LRIT {
@@ -66,21 +476,21 @@ impl<'a> VCDU<'a> {
     ///
     /// This should always be 1
     pub fn version(&self) -> u8 {
-        (self.bytes[0] & 0b11 << 6) >> 6
+        read_bits(self.bytes, 0, 2) as u8
     }
 
     /// Spacecraft ID
     ///
     /// This represents the spacecraft which sent this message
     pub fn scid(&self) -> u8 {
-        (self.bytes[0] & 0x3f) << 2 | (self.bytes[1] & 0xc0) >> 6
+        read_bits(self.bytes, 2, 8) as u8
     }
 
     /// Virtual Channel ID
     ///
     /// This is a 6-bit field, so the max ID is 63 (which represents a fill packet)
     pub fn vcid(&self) -> u8 {
-        self.bytes[1] & 0x3f
+        read_bits(self.bytes, 10, 6) as u8
     }
 
     /// A sequential counter of VCDUs on each virtual channel
@@ -89,7 +499,7 @@ impl<'a> VCDU<'a> {
     ///
     /// This is a 24-bit field, so this counter is modulo 1<<24
     pub fn counter(&self) -> u32 {
-        ((self.bytes[2] as u32) << 16) | ((self.bytes[3] as u32) << 8) | self.bytes[4] as u32
+        read_bits(self.bytes, 16, 24)
     }
 
     //const uint8_t* data() const {
@@ -109,8 +519,79 @@ impl<'a> VCDU<'a> {
     pub fn is_fill(&self) -> bool {
         self.vcid() == 63
     }
+
+    /// The replay flag, which is set when the data is being sent again (like stored mission data),
+    /// instead of live
+    pub fn is_replay(&self) -> bool {
+        read_bits(self.bytes, 40, 1) == 1
+    }
+}
+
+/// Drops VCDUs that have already been received
+///
+/// When several receivers (like two dishes, or two SDRs on one dish) feed the same pipeline, each
+/// VCDU will usually arrive more than once.  The first copy of each (VCID, counter) pair is kept,
+/// and later copies are dropped.  Frames that only one receiver got are kept too, so the merged
+/// stream is at least as complete as the best single receiver.
+///
+/// Only the most recent counters of each virtual channel are remembered, so a receiver that
+/// falls more than `window` frames behind the other will have its frames treated as new.
+pub struct VcduDedup {
+    window: usize,
+    /// Recently seen counters for each VCID, and the order they were seen in
+    seen: HashMap<u8, (HashSet<u32>, VecDeque<u32>)>,
+    duplicates: u64,
 }
 
+impl VcduDedup {
+    pub fn new(window: usize) -> VcduDedup {
+        VcduDedup {
+            window,
+            seen: HashMap::new(),
+            duplicates: 0,
+        }
+    }
+
+    /// Returns true if this is the first copy of this VCDU
+    pub fn is_new(&mut self, vcdu: &VCDU) -> bool {
+        let (set, order) = self.seen.entry(vcdu.vcid()).or_default();
+        if !set.insert(vcdu.counter()) {
+            self.duplicates += 1;
+            return false;
+        }
+        order.push_back(vcdu.counter());
+        if order.len() > self.window {
+            if let Some(old) = order.pop_front() {
+                set.remove(&old);
+            }
+        }
+        true
+    }
+
+    /// How many VCDUs have been dropped as duplicates
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+impl Default for VcduDedup {
+    /// Remembers the last 4096 frames of each virtual channel (over a minute of imagery)
+    fn default() -> Self {
+        VcduDedup::new(4096)
+    }
+}
+
+/// The most data a TP_PDU can have: 8190 bytes, plus a 2 byte CRC
+const TP_PDU_CAPACITY: usize = 8192;
+
+/// Session buffers are reserved up front for the size the primary header says the LRIT file will
+/// be, up to this much (the biggest full disk image segments are smaller)
+const MAX_SESSION_RESERVE: usize = 64 << 20;
+
+/// Sessions that haven't had a TP_PDU for this long are dropped, since their last TP_PDU must
+/// have been lost
+const SESSION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
 /// Ths Transport Service Protocol Data Unit
 ///
 /// This unit stores up to 8190 bytes for a specific APID (application process identifier)
@@ -122,17 +603,43 @@ pub struct TpPdu {
     /// The data field is max 8190 bytes, plus 2 additional bytes for CRC
     data: Vec<u8>,
     vcid: u8,
+    /// Whether any of it arrived in a VCDU with the replay flag set
+    replay: bool,
 }
 
 impl TpPdu {
     pub fn new(vcid: u8) -> TpPdu {
+        TpPdu::with_buffer(vcid, Vec::with_capacity(TP_PDU_CAPACITY))
+    }
+
+    /// A TP_PDU that reads its data into `data`, which should be empty
+    fn with_buffer(vcid: u8, data: Vec<u8>) -> TpPdu {
         TpPdu {
             header: Vec::with_capacity(6),
-            data: Vec::with_capacity(8192),
+            data,
             vcid,
+            replay: false,
         }
     }
 
+    /// The virtual channel this TP_PDU was received on
+    pub fn vcid(&self) -> u8 {
+        self.vcid
+    }
+
+    /// Whether any of this TP_PDU arrived in a VCDU with the replay flag set (see
+    /// [`VCDU::is_replay`])
+    pub fn is_replay(&self) -> bool {
+        self.replay
+    }
+
+    /// The user data field, without the CRC at the end
+    ///
+    /// This is only all of the user data once the TP_PDU is complete.
+    pub fn user_data(&self) -> &[u8] {
+        &self.data[..self.data.len().saturating_sub(2)]
+    }
+
     pub fn header_complete(&self) -> bool {
         assert!(self.header.len() <= 6);
         self.header.len() == 6
@@ -148,7 +655,7 @@ impl TpPdu {
     }
 
     pub fn is_crc_ok(&self) -> bool {
-        if self.data_complete() {
+        if self.data_complete() && self.data.len() >= 2 {
             let len = self.data.len();
             // the CRC is over the application data file, and is stored in the last 2 bytes
             let computed = crc::calc_crc16(&self.data[..len - 2]);
@@ -170,9 +677,7 @@ impl TpPdu {
     ///
     pub fn version(&self) -> Option<u8> {
         if self.header.len() > 0 {
-            let ver = (self.header[0] >> 5) & 0x7;
-            assert_eq!(ver, 0);
-            Some(ver)
+            Some(read_bits(&self.header, 0, 3) as u8)
         } else {
             None
         }
@@ -183,7 +688,7 @@ impl TpPdu {
     /// This should always be 0
     pub fn packet_type(&self) -> Option<bool> {
         if self.header.len() > 0 {
-            Some((self.header[0] & 0b00010000) > 0)
+            Some(read_bits(&self.header, 3, 1) == 1)
         } else {
             None
         }
@@ -191,7 +696,7 @@ impl TpPdu {
 
     pub fn secondary_flag(&self) -> Option<bool> {
         if self.header.len() > 0 {
-            Some((self.header[0] & 0b00001000) > 0)
+            Some(read_bits(&self.header, 4, 1) == 1)
         } else {
             None
         }
@@ -203,7 +708,7 @@ impl TpPdu {
     /// APID 2047 is a fill packet which contains no context
     pub fn apid(&self) -> Option<u16> {
         if self.header.len() >= 2 {
-            Some(((self.header[0] & 0b111) as u16) << 8 | self.header[1] as u16)
+            Some(read_bits(&self.header, 5, 11) as u16)
         } else {
             None
         }
@@ -219,7 +724,7 @@ impl TpPdu {
     /// * 2: The user data contains the last segment of one user data file beginning in an earlier packet
     pub fn flags(&self) -> Option<u8> {
         if self.header.len() >= 4 {
-            Some((self.header[2] & 0xc0) >> 6)
+            Some(read_bits(&self.header, 16, 2) as u8)
         } else {
             None
         }
@@ -227,7 +732,7 @@ impl TpPdu {
 
     pub fn sequence_count(&self) -> Option<u16> {
         if self.header.len() >= 4 {
-            Some(((self.header[2] & 0x3f) as u16) << 8 | self.header[3] as u16)
+            Some(read_bits(&self.header, 18, 14) as u16)
         } else {
             None
         }
@@ -235,27 +740,29 @@ impl TpPdu {
 
     /// Length of the user data field (including CRC)
     ///
-    /// Returns `None` if the full header hasn't been received yet
+    /// Returns `None` if the full header hasn't been received yet, or if the length is too long
+    /// for a TP_PDU (which means the header is corrupt)
     pub fn packet_length(&self) -> Option<u16> {
         if self.header_complete() {
             // This header field is documented as "the length of the remainder of the source packet
             // following this field minus 1".  There will always be a 2byte CRC field, so when
             // there is no application data, the packet_length field will be 1.  We'll return "2"
             // in this case.
-            let len = ((self.header[4] as u16) << 8 | self.header[5] as u16) + 1;
-            assert!(
-                len <= 8192,
-                "len {} is too long (apid {:?} vcid {})",
-                len,
-                self.apid(),
-                self.vcid
-            );
-            Some(len)
+            let len = read_bits(&self.header, 32, 16) + 1;
+            if len > 8192 {
+                return None;
+            }
+            Some(len as u16)
         } else {
             None
         }
     }
 
+    /// True if the header is complete, but its length field is invalid
+    pub fn has_invalid_length(&self) -> bool {
+        self.header_complete() && self.packet_length().is_none()
+    }
+
     /// Consume as many bytes as possible to fill the user data section of this PDU
     ///
     /// Returns the total number of bytes read
@@ -288,7 +795,47 @@ impl TpPdu {
 
 enum DecompInfo {
     NoneNeeded,
-    Needed(acres::sz::Sz),
+    Needed(Sz),
+}
+
+#[cfg(feature = "rice")]
+use acres::sz::Sz;
+
+/// Stands in for the rice decompressor when goeslib is built without the `rice` feature
+///
+/// Every scanline fails to decompress, so compressed images come out blank, but the compressed
+/// data is still kept in [`LRIT::compressed_data`].
+#[cfg(not(feature = "rice"))]
+struct Sz {
+    pixels_per_scanline: usize,
+}
+
+#[cfg(not(feature = "rice"))]
+impl Sz {
+    fn pixels_per_scanline(&self) -> usize {
+        self.pixels_per_scanline
+    }
+
+    fn decompress<'a>(&mut self, _data: &[u8], _out: &'a mut Vec<u8>) -> Result<&'a [u8], &'static str> {
+        Err("goeslib was built without the rice feature")
+    }
+}
+
+#[cfg(feature = "rice")]
+fn rice_decompressor(flags: u16, bits_per_pixel: u8, pixels_per_block: u8, columns: u16) -> Sz {
+    Sz::new(
+        acres::sz::Options::from_bits_truncate(flags as u32),
+        bits_per_pixel as usize,
+        pixels_per_block as usize,
+        columns as usize,
+    )
+}
+
+#[cfg(not(feature = "rice"))]
+fn rice_decompressor(_flags: u16, _bits_per_pixel: u8, _pixels_per_block: u8, columns: u16) -> Sz {
+    Sz {
+        pixels_per_scanline: columns as usize,
+    }
 }
 
 /// A utility struct used to build up session layer data (an LRIT file)
@@ -302,42 +849,122 @@ struct Session {
     last_seq: u16,
     apid: u16,
     needs_decomp: DecompInfo,
+    /// The data before decompression, if it needs decompressing
+    compressed: Option<Vec<u8>>,
+    /// Decompression diagnostics, if it needs decompressing
+    decompression: Option<Decompression>,
     /// The vcid (virtual channel id) of the session
     vcid: u8,
+    /// Whether any of its TP_PDUs were replayed
+    replay: bool,
+    mode: DecodeMode,
+    /// The error the decompressor returned, if it did
+    ///
+    /// Its state can't be trusted after an error, so the rest of this image is skipped: its
+    /// scanlines are zeroed without being decompressed.  The next image on this APID starts a new
+    /// session (with a new decompressor) as usual.
+    decompressor_error: Option<String>,
+    /// When its most recent TP_PDU arrived
+    last_seen: Instant,
 }
 
 /// Returns true if we need to decompress
-fn check_headers_for_rice_compression(bytes: &[u8]) -> DecompInfo {
-    let headers = read_headers(bytes);
+///
+/// Returns `None` if the data is rice compressed, but with parameters that can't be right (which
+/// means the headers are corrupt).
+fn check_headers_for_rice_compression(bytes: &[u8]) -> Option<DecompInfo> {
+    let headers = match read_headers(bytes) {
+        Ok(headers) => headers,
+        Err(e) => {
+            warn!("Failed to read headers from first TP_PDU: {}", e);
+            return Some(DecompInfo::NoneNeeded);
+        }
+    };
     if let (Some(ref ish), Some(ref rice)) = (headers.img_strucutre, headers.rice_compression) {
-        return DecompInfo::Needed(acres::sz::Sz::new(
-            acres::sz::Options::from_bits_truncate(rice.flags as u32),
-            ish.bits_per_pixel as usize,
-            rice.pixels_per_block as usize,
-            ish.num_columns as usize,
-        ));
+        // the decompressor only supports these block sizes, and up to 32 bits per pixel
+        if !matches!(rice.pixels_per_block, 8 | 16 | 32 | 64)
+            || !(1..=32).contains(&ish.bits_per_pixel)
+            || ish.num_columns == 0
+        {
+            warn!(
+                "Bad rice compression parameters: {} bits per pixel, {} pixels per block, {} columns",
+                ish.bits_per_pixel, rice.pixels_per_block, ish.num_columns
+            );
+            return None;
+        }
+        return Some(DecompInfo::Needed(rice_decompressor(
+            rice.flags,
+            ish.bits_per_pixel,
+            rice.pixels_per_block,
+            ish.num_columns,
+        )));
     }
-    DecompInfo::NoneNeeded
+    Some(DecompInfo::NoneNeeded)
 }
 
 impl Session {
     /// Create a new session from the first TP_PDU of some session layer data
-    pub fn new_from_pdu(pdu: TpPdu) -> Session {
-        assert!(pdu.header_complete());
-        assert!(pdu.data_complete());
-        assert!(pdu.is_crc_ok());
-        let seq = pdu.sequence_count().expect("pdu sequence should never be None");
-        let apid = pdu.apid().expect("APID should never be None");
+    ///
+    /// Returns `None` if the TP_PDU fails its CRC, or doesn't start with a primary header (which
+    /// happens when the sequence flags of some other TP_PDU were corrupted).
+    ///
+    /// The session's buffer comes from `pool`, and the TP_PDU's buffer is returned to it.  The
+    /// primary header is looked for with [`find_primary_header`], unless `preamble` says how many
+    /// bytes to skip.
+    pub fn new_from_pdu(
+        pdu: TpPdu,
+        pool: &mut BufferPool,
+        mode: DecodeMode,
+        preamble: Option<usize>,
+        now: Instant,
+        stats: &mut crate::stats::Stats,
+    ) -> Option<Session> {
+        if !check(
+            mode,
+            pdu.header_complete() && pdu.data_complete(),
+            format_args!("new session from an incomplete TP_PDU"),
+            stats,
+        ) {
+            return None;
+        }
+        if !pdu.is_crc_ok() {
+            stats.record(crate::stats::Stat::CorruptPacket);
+            return None;
+        }
+        // both are there once the header is complete
+        let seq = pdu.sequence_count()?;
+        let apid = pdu.apid()?;
 
         let _ver = pdu.version();
 
-        // According to a comment in goestools, the first 10 bytes of this data is garbage
-        // so ignore the first 10 bytes from this first TP_PDU
-
-        // last 2 bytes of pdu's data will be a CRC that we have already validated
-        let mut bytes = pdu.data;
-        bytes.truncate(bytes.len() - 2);
-        bytes = bytes.split_off(10);
+        // the primary header usually comes after PREAMBLE_LEN bytes of garbage, but not always
+        let data = pdu.user_data();
+        let found = find_primary_header(data);
+        let skip = match (preamble, found) {
+            (Some(skip), Some(found)) if found != skip => {
+                warn!(
+                    "First TP_PDU for APID {} seems to have its primary header after {} bytes, but {} are skipped",
+                    apid_label(pdu.vcid, apid),
+                    found,
+                    skip
+                );
+                skip
+            }
+            (Some(skip), _) => skip,
+            (None, Some(found)) => {
+                if found != PREAMBLE_LEN {
+                    warn!(
+                        "First TP_PDU for APID {} has its primary header after {} bytes, instead of {}",
+                        apid_label(pdu.vcid, apid),
+                        found,
+                        PREAMBLE_LEN
+                    );
+                }
+                found
+            }
+            (None, None) => PREAMBLE_LEN,
+        };
+        let bytes = &data[data.len().min(skip)..];
 
         // we need to check a few things here:
         // 1. is this an image file type (filetype_code == 0)
@@ -348,62 +975,119 @@ impl Session {
         // all other tp_pdus in this session) before adding it to self.bytes
 
         // see if we have enough data to extract a primary header
-        let needs_decomp = if let Some(prim) = PrimaryHeader::from_bytes(&bytes) {
-            if bytes.len() >= prim.total_header_length as usize {
-                // we have enough data to extract all the headers
-
-                check_headers_for_rice_compression(&bytes)
-            } else {
-                warn!("Not enough data in first TP_PDU to extract all the headers (need {} bytes, but only have {} bytes)", prim.total_header_length, bytes.len());
-                DecompInfo::NoneNeeded
+        let (needs_decomp, expected_len) = match PrimaryHeader::from_bytes(bytes) {
+            Ok(prim) if prim.header_record_lenth == 16 => {
+                let expected_len = prim.total_header_length as usize + (prim.data_field_bits / 8) as usize;
+                let needs_decomp = if bytes.len() >= prim.total_header_length as usize {
+                    // we have enough data to extract all the headers
+
+                    match check_headers_for_rice_compression(bytes) {
+                        Some(info) => info,
+                        None => {
+                            stats.record(crate::stats::Stat::CorruptPacket);
+                            return None;
+                        }
+                    }
+                } else {
+                    warn!("Not enough data in first TP_PDU to extract all the headers (need {} bytes, but only have {} bytes)", prim.total_header_length, bytes.len());
+                    DecompInfo::NoneNeeded
+                };
+                (needs_decomp, expected_len)
+            }
+            Ok(_) | Err(HeaderError::WrongType { .. }) | Err(HeaderError::BadLength { .. }) => {
+                warn!(
+                    "First TP_PDU for APID {} doesn't start with a primary header",
+                    apid_label(pdu.vcid, apid)
+                );
+                stats.record(crate::stats::Stat::CorruptPacket);
+                return None;
+            }
+            Err(_) => {
+                warn!(
+                    "First TP_PDU didn't have enough data for a primary header (only {} bytes)",
+                    bytes.len()
+                );
+                stats.record(crate::stats::Stat::CorruptPacket);
+                return None;
             }
-        } else {
-            warn!(
-                "First TP_PDU didn't have enough data for a primary header (only {} bytes)",
-                bytes.len()
-            );
-            DecompInfo::NoneNeeded
         };
 
-        if let DecompInfo::Needed(_params) = &needs_decomp {
+        // The first TP_PDU of a compressed image should only have headers, but if it has data too,
+        // that's compressed like the rest
+        let mut headers_len = bytes.len();
+        let mut decompression = None;
+        let mut expected_len = expected_len;
+        if let (DecompInfo::Needed(_params), Ok(headers)) = (&needs_decomp, read_headers(bytes)) {
+            if let (Some(ish), Some(rice)) = (&headers.img_strucutre, &headers.rice_compression) {
+                decompression = Some(Decompression {
+                    flags: rice.flags,
+                    pixels_per_block: rice.pixels_per_block,
+                    bits_per_pixel: ish.bits_per_pixel,
+                    ..Default::default()
+                });
+            }
             //info!("tp_pdu's in session {} need rice decompression", apid);
-            let headers = read_headers(&bytes);
-
-            let data = &bytes[headers.primary.total_header_length as usize..];
-            assert_eq!(
-                data.len(),
-                0,
-                "Expected data len to be zero, but was actually {}",
-                data.len()
-            );
-            //info!("{} bytes to decompress, pixels per scanline {}", data.len(), params.pixels_per_scanline);
+            headers_len = headers.primary.total_header_length as usize;
+            if headers_len < bytes.len() {
+                warn!(
+                    "First TP_PDU for APID {} has {} bytes of compressed data after the headers",
+                    apid_label(pdu.vcid, apid),
+                    bytes.len() - headers_len
+                );
+            }
+            // the data field length is of the compressed data
+            if let Some(ish) = &headers.img_strucutre {
+                expected_len = headers_len
+                    + ish.num_columns as usize * ish.num_lines as usize * ish.bits_per_pixel.div_ceil(8) as usize;
+            }
         }
 
-        // TODO
-        // read enough data to extract the full set of LRIT headers
-        // check for rice and image strucuture headers
-        // set up
-
-        Session {
+        let mut session_bytes = pool.take(expected_len.clamp(bytes.len(), MAX_SESSION_RESERVE));
+        session_bytes.extend_from_slice(&bytes[..headers_len]);
+        let mut session = Session {
             last_seq: seq,
-            bytes,
+            bytes: session_bytes,
             apid,
+            compressed: matches!(needs_decomp, DecompInfo::Needed(_)).then(Vec::new),
+            decompression,
             needs_decomp,
             vcid: pdu.vcid,
+            replay: pdu.replay,
+            mode,
+            decompressor_error: None,
+            last_seen: now,
+        };
+        if headers_len < bytes.len() {
+            session.push_data(&bytes[headers_len..], stats);
         }
+        pool.give(pdu.data);
+        Some(session)
     }
 
-    pub fn append(&mut self, mut pdu: TpPdu, _stats: &crate::stats::Stats) {
-        assert!(pdu.header_complete());
-        assert!(pdu.data_complete());
+    /// Adds the data of the next TP_PDU, returning its buffer to `pool`
+    pub fn append(&mut self, mut pdu: TpPdu, pool: &mut BufferPool, stats: &mut crate::stats::Stats) {
+        let complete = pdu.header_complete() && pdu.data_complete();
+        if !check(
+            self.mode,
+            complete,
+            format_args!("appending an incomplete TP_PDU"),
+            stats,
+        ) {
+            return;
+        }
         if !pdu.is_crc_ok() {
-            warn!("Refusing to append data that failed CRC (apid {})", pdu.apid().unwrap());
+            warn!("Refusing to append data that failed CRC (apid {})", self.apid);
+            stats.record(crate::stats::Stat::CorruptPacket);
             return;
         }
         // remove the 2 CRC bytes (which we've just verified)
         pdu.data.truncate(pdu.data.len() - 2);
+        self.replay |= pdu.replay;
 
-        let new_seq = pdu.sequence_count().expect("pdu sequence should never be None");
+        let new_seq = match pdu.sequence_count() {
+            Some(seq) => seq,
+            None => return,
+        };
 
         // Note: 4_LRIT_Transmitter-specs.pdf section 6.2.1 says that this sequence number is 14 bit modulo 16394
         //       but that is almost certainly a typo
@@ -411,58 +1095,142 @@ impl Session {
             //if new_seq != self.last_seq + 1 {
             let skipped = new_seq as isize - self.last_seq as isize;
             warn!(
-                "VC XXX: Detected TP_PDU drop (skipped {} packet(s) on APID {}; prev: {}, packet: {})",
+                "{}: Detected TP_PDU drop (skipped {} packet(s) on APID {}; prev: {}, packet: {})",
+                Vcid::from(self.vcid),
                 skipped - 1,
-                self.apid,
+                apid_label(self.vcid, self.apid),
                 self.last_seq,
                 new_seq
             );
         }
         self.last_seq = new_seq;
+        self.push_data(&pdu.data, stats);
+        pool.give(pdu.data);
+    }
+
+    /// Adds the data from a TP_PDU, decompressing it if needed
+    fn push_data(&mut self, data: &[u8], stats: &mut crate::stats::Stats) {
         if let DecompInfo::Needed(ref mut params) = self.needs_decomp {
+            if let Some(compressed) = &mut self.compressed {
+                compressed.extend_from_slice(data);
+            }
             let num_columns = params.pixels_per_scanline() as usize;
-            assert!(
-                pdu.data.len() <= num_columns,
-                "session needs rice decomp, but bytes to decomp ({}) is greater than image cols ({})",
-                pdu.data.len() - 2,
-                num_columns
-            );
 
-            let mut out_buf = Vec::with_capacity(num_columns as usize);
-            // match acres::decompress(&pdu.data, &mut out_buf, params) {
-            match params.decompress(&pdu.data, &mut out_buf) {
-                Ok(buf) => {
-                    assert_eq!(buf.len(), num_columns, "Successfully decompressed TP_PDU, but bytes out of decompressor ({}) doesn't match num columns ({})", buf.len(), num_columns);
-                    self.bytes.extend_from_slice(buf);
+            // A corrupt scanline shouldn't take down the whole image (or the receiver), so if
+            // anything goes wrong, fill this scanline with zeros and carry on
+            let skipped = self.decompressor_error.is_some();
+            let decompressed = if skipped {
+                false
+            } else if data.len() > num_columns {
+                warn!(
+                    "session needs rice decomp, but bytes to decomp ({}) is greater than image cols ({}) (apid {})",
+                    data.len(),
+                    num_columns,
+                    apid_label(self.vcid, self.apid)
+                );
+                false
+            } else {
+                let mut out_buf = Vec::with_capacity(num_columns as usize);
+                let start = std::time::Instant::now();
+                // match acres::decompress(&data, &mut out_buf, params) {
+                let result = params.decompress(data, &mut out_buf);
+                if let Some(decompression) = &mut self.decompression {
+                    decompression.time_ms += start.elapsed().as_secs_f64() * 1000.0;
+                }
+                match result {
+                    Ok(buf) if buf.len() == num_columns => {
+                        self.bytes.extend_from_slice(buf);
+                        true
+                    }
+                    Ok(buf) => {
+                        warn!(
+                            "Decompressed TP_PDU, but bytes out of decompressor ({}) doesn't match num columns ({}) (apid {})",
+                            buf.len(),
+                            num_columns,
+                            apid_label(self.vcid, self.apid)
+                        );
+                        false
+                    }
+                    Err(rc) => {
+                        warn!(
+                            "Failed to decompress scanline with rc {}, skipping the rest of the image (apid {})",
+                            rc,
+                            apid_label(self.vcid, self.apid)
+                        );
+                        self.decompressor_error = Some(rc.to_string());
+                        if let Some(decompression) = &mut self.decompression {
+                            decompression.error_scanline = Some(decompression.scanlines);
+                        }
+                        false
+                    }
                 }
-                Err(rc) => panic!("Failed to decompress with rc {}", rc),
+            };
+
+            if !decompressed {
+                if !skipped {
+                    stats.record(crate::stats::Stat::DecompressionError);
+                }
+                self.bytes.resize(self.bytes.len() + num_columns, 0);
+            }
+            if let Some(decompression) = &mut self.decompression {
+                decompression.compressed_bytes += data.len();
+                decompression.decompressed_bytes += num_columns;
+                decompression.scanlines += 1;
+                decompression.failed_scanlines += !decompressed as usize;
             }
         } else {
             // sanity check:
-            assert!(
-                pdu.data.len() < 1_000_000,
-                "tp_pdu data length is suspicious {}",
-                pdu.data.len()
-            );
-            self.bytes.extend(pdu.data);
+            let len = data.len();
+            if check(
+                self.mode,
+                len < 1_000_000,
+                format_args!("tp_pdu data length is suspicious {}", len),
+                stats,
+            ) {
+                self.bytes.extend_from_slice(data);
+            }
         }
     }
 
-    pub fn finish(mut self) -> LRIT {
+    /// Parses the headers of the completed LRIT file, returning the session's buffer to `pool`
+    ///
+    /// The LRIT file gets its own copy of the data, sized to fit.  If the headers can't be parsed,
+    /// the buffer is returned with the error instead, for the caller to give back.
+    pub fn finish(self, pool: &mut BufferPool) -> Result<LRIT, (HeaderError, Vec<u8>)> {
         //let header = crate::lrit::PrimaryHeader::from_data(&self.bytes[10..]);
         //info!("primary header: {:?}", header);
-        let headers = read_headers(&self.bytes);
-        let data = self.bytes.split_off(headers.primary.total_header_length as usize);
-        if let Some(_rice) = &headers.rice_compression {
-            //let ish = headers.img_strucutre.as_ref().unwrap();
-            //info!("{:?}", headers);
-            //info!("ish.cols={}, datalen={}", ish.num_columns, data.len());
-        }
-        return LRIT {
+        let headers = match read_headers(&self.bytes) {
+            Ok(headers) => headers,
+            Err(e) => return Err((e, self.bytes)),
+        };
+        // read_headers has already checked that the headers fit, but a broadcast that claims more
+        // header bytes than were received mustn't take down the channel if that ever changes
+        let header_len = (headers.primary.total_header_length as usize).min(self.bytes.len());
+        let (raw_headers, data) = self.bytes.split_at(header_len);
+        let (raw_headers, data) = (raw_headers.to_vec(), data.to_vec());
+        pool.give(self.bytes);
+        let received = self.compressed.as_ref().map_or(data.len(), Vec::len);
+        let truncated = Truncation::check(&headers.primary, received);
+        if let Some(truncated) = &truncated {
+            warn!(
+                "LRIT file {:?} for APID {} is truncated (expected {} bytes of data, but only {} arrived)",
+                headers.annotation.as_ref().map(|a| &a.text),
+                apid_label(self.vcid, self.apid),
+                truncated.expected_bytes,
+                truncated.received_bytes
+            );
+        }
+        return Ok(LRIT {
             vcid: self.vcid,
+            apid: Some(self.apid),
             headers,
+            raw_headers,
             data,
-        };
+            compressed_data: self.compressed,
+            decompression: self.decompression,
+            truncated,
+            replay: self.replay,
+        });
         //info!("Headers: {:?}", headers);
 
         //let root = std::path::Path::new("/nas/achin/devel/goes-dht/out_new");
@@ -484,6 +1252,64 @@ impl Session {
     }
 }
 
+/// Finishes a session, counting it as a corrupt packet if its headers can't be parsed (and saving
+/// it into the dead-letter directory, if there is one)
+fn finish_session(
+    session: Session,
+    pool: &mut BufferPool,
+    dead_letter: Option<&DeadLetter>,
+    vcid: u8,
+    apid: u16,
+    stats: &mut crate::stats::Stats,
+) -> Option<LRIT> {
+    match session.finish(pool) {
+        Ok(lrit) => Some(lrit),
+        Err((e, bytes)) => {
+            warn!("Dropping LRIT file for APID {}: {}", apid_label(vcid, apid), e);
+            if let Some(dead_letter) = dead_letter {
+                match dead_letter.write_unparsed(vcid, apid, &bytes, &e) {
+                    Ok(path) => info!("Saved the dropped LRIT file to {}", path.display()),
+                    Err(e) => warn!("Failed to write dead-letter file: {}", e),
+                }
+            }
+            pool.give(bytes);
+            if let HeaderError::UnexpectedType(header_type) = e {
+                stats.record(crate::stats::Stat::UnknownHeaderType(header_type));
+            }
+            stats.record(crate::stats::Stat::CorruptPacket);
+            None
+        }
+    }
+}
+
+/// Handles the TP_PDUs of an APID that carries something other than LRIT sessions, like CFDP
+/// (see [`crate::cfdp`])
+///
+/// A transport is given each complete TP_PDU that passes its CRC, and returns LRIT files as they're
+/// finished, so the rest of the pipeline doesn't need to know how they were sent.  See
+/// [`VirtualChannel::with_transport`].
+pub trait Transport: Send {
+    /// Handles the user data of a TP_PDU (without its CRC), with the TP_PDU's sequence flags
+    fn packet(&mut self, vcid: u8, apid: u16, flags: u8, data: &[u8], stats: &mut crate::stats::Stats) -> Option<LRIT>;
+}
+
+/// Sees every complete TP_PDU of a virtual channel, before it's assembled into an LRIT session (or
+/// handed to a [`Transport`])
+///
+/// This is for looking at the raw packet flow, like for research or for a protocol on an unusual
+/// APID, without changing how anything is decoded.  Fill TP_PDUs aren't given to subscribers, but
+/// ones that fail their CRC are (see [`TpPdu::is_crc_ok`]).  See
+/// [`VirtualChannel::with_subscriber`].
+pub trait TpPduSubscriber: Send {
+    fn tp_pdu(&mut self, pdu: &TpPdu);
+}
+
+impl<F: FnMut(&TpPdu) + Send> TpPduSubscriber for F {
+    fn tp_pdu(&mut self, pdu: &TpPdu) {
+        self(pdu)
+    }
+}
+
 /// A structure that parses LRIT data out of one specific virtual channel
 ///
 /// This structure doesn't have a direct mapping to any of the offical LRIT structures.
@@ -500,6 +1326,46 @@ pub struct VirtualChannel {
     apid_map: HashMap<u16, Session>,
 
     last_counter: u32,
+
+    /// The APID of the most recent (non-fill) TP_PDU
+    last_apid: Option<u16>,
+
+    mode: DecodeMode,
+
+    /// How many bytes come before the primary header of each LRIT file, if they aren't looked for
+    preamble: Option<usize>,
+
+    /// APIDs that aren't LRIT sessions, and what handles them instead
+    transports: HashMap<u16, Box<dyn Transport>>,
+
+    /// What's given every complete TP_PDU, before it's assembled
+    subscribers: Vec<Box<dyn TpPduSubscriber>>,
+
+    /// Where TP_PDU and session buffers come from, and go back to
+    pool: BufferPool,
+
+    /// Where LRIT files whose headers can't be parsed are saved, if anywhere
+    dead_letter: Option<Arc<DeadLetter>>,
+
+    /// Where the time for session timeouts comes from
+    clock: Arc<dyn Clock>,
+
+    /// How long a session can go without a TP_PDU before it's dropped
+    session_timeout: std::time::Duration,
+}
+
+/// A snapshot of a [`VirtualChannel`], for debugging
+#[derive(Debug, Clone, Serialize)]
+pub struct VirtualChannelState {
+    pub vcid: u8,
+    pub last_counter: u32,
+    pub last_apid: Option<u16>,
+    /// Bytes received of the TP_PDU that's currently being assembled
+    pub pending_tp_pdu_bytes: Option<usize>,
+    /// Sessions that have started but not finished, as (APID, bytes received so far)
+    pub sessions: Vec<(u16, usize)>,
+    /// The capacity of the free buffers kept for reuse
+    pub pooled_bytes: usize,
 }
 
 impl VirtualChannel {
@@ -509,131 +1375,338 @@ impl VirtualChannel {
             current_tp_pdu: None,
             apid_map: HashMap::new(),
             last_counter: initial_counter,
+            last_apid: None,
+            mode: DecodeMode::default(),
+            preamble: None,
+            transports: HashMap::new(),
+            subscribers: Vec::new(),
+            pool: BufferPool::new(),
+            dead_letter: None,
+            clock: Arc::new(SystemClock),
+            session_timeout: SESSION_TIMEOUT,
+        }
+    }
+
+    /// What to do when the decoder's checks fail, see [`DecodeMode`]
+    pub fn with_mode(mut self, mode: DecodeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Always skip this many bytes before the primary header of an LRIT file, instead of looking
+    /// for it (a warning is still logged when it seems to be somewhere else)
+    ///
+    /// Without this, the header is expected after [`PREAMBLE_LEN`] bytes, but found wherever it
+    /// is.
+    pub fn with_preamble(mut self, len: usize) -> Self {
+        self.preamble = Some(len);
+        self
+    }
+
+    /// Hands the TP_PDUs of `apid` to a [`Transport`], instead of assembling them into an LRIT
+    /// session
+    pub fn with_transport(mut self, apid: u16, transport: impl Transport + 'static) -> Self {
+        self.transports.insert(apid, Box::new(transport));
+        self
+    }
+
+    /// Gives every complete (non-fill) TP_PDU to a [`TpPduSubscriber`], like a closure, before
+    /// it's assembled
+    pub fn with_subscriber(mut self, subscriber: impl TpPduSubscriber + 'static) -> Self {
+        self.subscribers.push(Box::new(subscriber));
+        self
+    }
+
+    /// Save LRIT files whose headers can't be parsed here, rather than only dropping them
+    pub fn with_dead_letter(mut self, dead_letter: Arc<DeadLetter>) -> Self {
+        self.dead_letter = Some(dead_letter);
+        self
+    }
+
+    /// Get the time for session timeouts from `clock`, instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Drop sessions that go this long without a TP_PDU (10 minutes by default)
+    pub fn with_session_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    pub fn state(&self) -> VirtualChannelState {
+        let mut sessions: Vec<_> = self.apid_map.iter().map(|(apid, s)| (*apid, s.bytes.len())).collect();
+        sessions.sort_unstable();
+        VirtualChannelState {
+            vcid: self.id,
+            last_counter: self.last_counter,
+            last_apid: self.last_apid,
+            pending_tp_pdu_bytes: self.current_tp_pdu.as_ref().map(|pdu| pdu.data.len()),
+            sessions,
+            pooled_bytes: self.pool.pooled_bytes(),
         }
     }
 
     /// Extract TP_PUDs from a VCDU, returning any completed LRIT files
+    ///
+    /// Corrupt data (like a bad M_PDU header, or a TP_PDU that doesn't end where the next one is
+    /// supposed to start) causes the affected TP_PDUs to be dropped.  Parsing picks up again at the
+    /// next first header pointer.  Sessions that have gone longer than the session timeout without
+    /// a TP_PDU are dropped first.
     pub fn process_vcdu(&mut self, vcdu: VCDU, stats: &mut crate::stats::Stats) -> Vec<LRIT> {
         let data = vcdu.data();
-        assert_eq!(data.len(), 886);
-        assert_eq!(vcdu.vcid(), self.id);
+        let mut lrits: Vec<LRIT> = Vec::new();
+        if !check(
+            self.mode,
+            data.len() == 886 && vcdu.vcid() == self.id,
+            format_args!(
+                "{} got a VCDU for {} with {} bytes of data",
+                Vcid::from(self.id),
+                Vcid::from(vcdu.vcid()),
+                data.len()
+            ),
+            stats,
+        ) {
+            return lrits;
+        }
+        self.expire_sessions();
 
         // check this vcdu counter against the last one received
-        if diff_with_wrap(self.last_counter, vcdu.counter(), 1 << 24) > 1 {
+        let diff = diff_with_wrap(self.last_counter, vcdu.counter(), 1 << 24);
+        if diff > 1 {
+            // a huge jump is more likely a restarted (or out of order) counter than lost VCDUs
+            if diff <= MAX_COUNTED_DROP {
+                stats.record(crate::stats::Stat::DroppedPackets(diff as usize - 1));
+            }
             // we're missing some packets -- if we've got an incomplete TP_PDU,
             // we need to drop it (because we can't know if the missing packet(s)
             // started a new one or finished the current one.
             self.current_tp_pdu.take();
-            info!("VC {} Dropping incomplete TP_PDU", self.id);
+            info!("{} Dropping incomplete TP_PDU", Vcid::from(self.id));
         }
 
         self.last_counter = vcdu.counter();
 
-        let first_header = {
-            // read off the first 2 bytes and extract a first header pointer
-
-            // Ref: 3_LRIT_Receiver-specs.pdf Figure 5 M_PDU Structure
-            // Ref: 5_LRIT_Mission-data.pdf Page 3
-            let spare = (data[0] & 0b11111000) >> 3;
-            assert_eq!(spare, 0);
+        // read off the first 2 bytes and extract a first header pointer
+        //
+        // Ref: 3_LRIT_Receiver-specs.pdf Figure 5 M_PDU Structure
+        // Ref: 5_LRIT_Mission-data.pdf Page 3
+        let spare = read_bits(data, 0, 5);
+        let first_header = read_bits(data, 5, 11) as usize;
 
-            ((data[0] & 0b111) as usize) << 8 | data[1] as usize
-        };
+        if spare != 0 || (first_header != 2047 && first_header >= data.len() - 2) {
+            warn!(
+                "{}: Dropping VCDU with a corrupt M_PDU header (spare bits {}, first header {})",
+                Vcid::from(self.id),
+                spare,
+                first_header
+            );
+            stats.record(crate::stats::Stat::CorruptPacket);
+            self.current_tp_pdu.take();
+            return lrits;
+        }
 
-        let mut offset = 2; // + if first_header == 2047 { 0 } else { first_header };
-        let mut lrits: Vec<LRIT> = Vec::new();
+        let mut offset = 2;
 
         // if first_header is non-zero, and we still have an open incomplete TP_PDU, read data
         // up-to first_header to complete it
-        if let Some(mut tp_pdu) = self.current_tp_pdu.take() {
-            assert!(!tp_pdu.data_complete());
-
-            if let Some(total_len) = tp_pdu.packet_length() {
-                let bytes_needed = total_len as usize - tp_pdu.data.len();
-                if first_header != 2047 && first_header < bytes_needed {
-                    // if first_header is not 2047, then it represents how many bytes to read
-                    // before the header
-                    // TODO debug 'needed 661 bytes to finish this TP_PDU, but first_header is only 0'
-                    panic!(
-                        "needed {} bytes to finish this TP_PDU, but first_header is only {}",
-                        bytes_needed, first_header
-                    );
-                }
-            }
-
-            // we have an unfinished tp_pdu, which we may or may not be able to complete with this new data
-            // (however, we do expect to always be able to complete the 6 byte header)
-            offset += tp_pdu.process_bytes(&data[offset..]);
-            assert!(tp_pdu.header_complete());
-
-            if tp_pdu.data_complete() {
-                lrits.extend(self.process(tp_pdu, stats));
-
-                // at this point, if we have another packet, we should expect it to start at our current offset.
-                // remember "first_header" is relative to the start of the packet zone, but "offset" is relative to the start of
-                // entire data (which includes a 2 byte header).
-                if first_header != 2047 {
-                    assert_eq!(
-                        offset - 2,
-                        first_header,
-                        "offset={} first_header={}",
-                        offset,
+        let mode = self.mode;
+        let pending = self.current_tp_pdu.take().filter(|pdu| {
+            check(
+                mode,
+                !pdu.data_complete(),
+                format_args!("{}: pending TP_PDU is already complete", Vcid::from(self.id)),
+                stats,
+            )
+        });
+        if let Some(mut tp_pdu) = pending {
+            let bytes_needed = tp_pdu.packet_length().map(|len| len as usize - tp_pdu.data.len());
+            if first_header != 2047 && matches!(bytes_needed, Some(needed) if first_header < needed) {
+                // if first_header is not 2047, then it represents how many bytes to read before
+                // the next header, so this TP_PDU can't be completed
+                warn!(
+                    "{}: needed {:?} bytes to finish this TP_PDU, but first_header is only {}",
+                    Vcid::from(self.id),
+                    bytes_needed,
+                    first_header
+                );
+                stats.record(crate::stats::Stat::CorruptPacket);
+            } else {
+                // we have an unfinished tp_pdu, which we may or may not be able to complete with this new data
+                // (however, we do expect to always be able to complete the 6 byte header)
+                offset += tp_pdu.process_bytes(&data[offset..]);
+                tp_pdu.replay |= vcdu.is_replay();
+
+                if !check(
+                    self.mode,
+                    tp_pdu.header_complete(),
+                    format_args!("{}: pending TP_PDU header is still incomplete", Vcid::from(self.id)),
+                    stats,
+                ) {
+                    // the TP_PDU is dropped
+                } else if tp_pdu.has_invalid_length() {
+                    warn!("{}: Dropping TP_PDU with an invalid length", Vcid::from(self.id));
+                    stats.record(crate::stats::Stat::CorruptPacket);
+                } else if tp_pdu.data_complete() {
+                    // at this point, if we have another packet, we should expect it to start at our current offset.
+                    // remember "first_header" is relative to the start of the packet zone, but "offset" is relative to the start of
+                    // entire data (which includes a 2 byte header).
+                    if first_header != 2047 && offset - 2 != first_header {
+                        warn!(
+                            "{}: TP_PDU ended at {}, but first_header is {}",
+                            Vcid::from(self.id),
+                            offset - 2,
+                            first_header
+                        );
+                        stats.record(crate::stats::Stat::CorruptPacket);
+                    } else {
+                        lrits.extend(self.process(tp_pdu, stats));
+                    }
+                } else if first_header == 2047 {
+                    // if not complete, then we should have no more bytes to read
+                    if check(
+                        self.mode,
+                        offset == data.len(),
+                        format_args!(
+                            "{}: incomplete TP_PDU ended at {} of {} bytes",
+                            Vcid::from(self.id),
+                            offset,
+                            data.len()
+                        ),
+                        stats,
+                    ) {
+                        self.current_tp_pdu = Some(tp_pdu); // store it for later
+                    }
+                    return lrits;
+                } else {
+                    warn!(
+                        "{}: TP_PDU is still incomplete, but first_header is {}",
+                        Vcid::from(self.id),
                         first_header
                     );
+                    stats.record(crate::stats::Stat::CorruptPacket);
                 }
-                // assert!(offset - 2 <= first_header, "offset {} is past first_header {}", offset - 2, first_header);
-            } else {
-                // if not complete, then we should have no more bytes to read
-                if first_header != 2047 {
-                    info!("XXX TP_PDU is still completed, first_header was {first_header}");
-                }
-                assert_eq!(offset, data.len());
-                self.current_tp_pdu = Some(tp_pdu); // store it for later
-                return lrits;
             }
-        } else {
-            // the "first_header" is the offset to the first TP_PDU that contains a header.  Any data before this
-            // is going to be from some previously started TP_PDU
-            offset = 2 + first_header;
         }
 
         // at this point we should not have any pending tp_pdus
-        assert!(self.current_tp_pdu.is_none());
+        if !check(
+            self.mode,
+            self.current_tp_pdu.is_none(),
+            format_args!("{}: unexpected pending TP_PDU", Vcid::from(self.id)),
+            stats,
+        ) {
+            self.current_tp_pdu.take();
+        }
 
         if first_header == 2047 {
             return lrits; // fill packet
         }
 
+        // the "first_header" is the offset to the first TP_PDU that contains a header.  Any data before this
+        // is from some previously started TP_PDU
+        offset = 2 + first_header;
+
         while offset < data.len() {
-            let mut tp_pdu = TpPdu::new(vcdu.vcid());
+            let mut tp_pdu = TpPdu::with_buffer(vcdu.vcid(), self.pool.take(TP_PDU_CAPACITY));
+            tp_pdu.replay = vcdu.is_replay();
             offset += tp_pdu.process_bytes(&data[offset..]);
             // note that while "first_header" is documented to point to the first TP_PDU with a header, it doesn't
             // mean that the TP_PDU will have a complete header!
 
+            if tp_pdu.has_invalid_length() {
+                // there's no way to know where the next TP_PDU starts, so skip the rest of this VCDU
+                warn!("{}: Dropping TP_PDU with an invalid length", Vcid::from(self.id));
+                stats.record(crate::stats::Stat::CorruptPacket);
+                break;
+            }
+
             if tp_pdu.header_complete() && tp_pdu.data_complete() {
                 lrits.extend(self.process(tp_pdu, stats));
             } else {
                 // not complete, keep it around!
-                self.current_tp_pdu = Some(tp_pdu);
-                assert_eq!(offset, data.len());
+                if check(
+                    self.mode,
+                    offset == data.len(),
+                    format_args!(
+                        "{}: incomplete TP_PDU ended at {} of {} bytes",
+                        Vcid::from(self.id),
+                        offset,
+                        data.len()
+                    ),
+                    stats,
+                ) {
+                    self.current_tp_pdu = Some(tp_pdu);
+                }
             }
         }
 
         lrits
     }
 
+    /// Drops the sessions that haven't had a TP_PDU for longer than the session timeout
+    fn expire_sessions(&mut self) {
+        if self.apid_map.is_empty() {
+            return;
+        }
+        let now = self.clock.now();
+        let (id, timeout, pool) = (self.id, self.session_timeout, &mut self.pool);
+        self.apid_map.retain(|apid, session| {
+            if now.saturating_duration_since(session.last_seen) <= timeout {
+                return true;
+            }
+            warn!("Dropping stale data for APID {}", apid_label(id, *apid));
+            pool.give(std::mem::take(&mut session.bytes));
+            false
+        });
+    }
+
     /// Process a completed TP_PDU
     ///
     /// If this was the last TP_PDU in an LRIT file, a new LRIT file can be returned.
     /// Else, this TP_PDU is added
     fn process(&mut self, tp_pdu: TpPdu, stats: &mut crate::stats::Stats) -> Option<LRIT> {
-        let apid = tp_pdu.apid().unwrap();
-        if apid == 2047 {
+        let (apid, flags) = match (tp_pdu.apid(), tp_pdu.flags()) {
+            (Some(apid), Some(flags)) => (apid, flags),
+            _ => {
+                check(
+                    self.mode,
+                    false,
+                    format_args!("{}: processing an incomplete TP_PDU", Vcid::from(self.id)),
+                    stats,
+                );
+                return None;
+            }
+        };
+        if apid == FILL_APID {
+            stats.record(crate::stats::Stat::FillTpPdu(
+                self.id,
+                tp_pdu.header.len() + tp_pdu.data.len(),
+            ));
+            self.pool.give(tp_pdu.data);
             return None;
         }
-        stats.record(crate::stats::Stat::APID(apid));
-        let flags = tp_pdu.flags().unwrap();
-        assert!(flags <= 3);
+        stats.record(crate::stats::Stat::APID(
+            self.id,
+            apid,
+            tp_pdu.header.len() + tp_pdu.data.len(),
+        ));
+        self.last_apid = Some(apid);
+        for subscriber in &mut self.subscribers {
+            subscriber.tp_pdu(&tp_pdu);
+        }
+
+        if let Some(transport) = self.transports.get_mut(&apid) {
+            if !tp_pdu.is_crc_ok() {
+                stats.record(crate::stats::Stat::CorruptPacket);
+                return None;
+            }
+            let data = &tp_pdu.data[..tp_pdu.data.len() - 2];
+            let lrit = transport.packet(self.id, apid, flags, data, stats);
+            self.pool.give(tp_pdu.data);
+            return lrit;
+        }
 
         if flags == 1 || flags == 3 {
             // x == 1 means this is the first segment of a new data file, and there will be
@@ -642,25 +1715,34 @@ impl VirtualChannel {
             // (Ref: 4_LRIT_Transmitter-specs.pdf page 20)
 
             // see if there's a previous record of this apid in our map.  If so, it won't be valid.
-            if let Some(_pdu) = self.apid_map.remove(&apid) {
-                warn!("XXX Dropping old apid data {}", apid);
+            if let Some(old) = self.apid_map.remove(&apid) {
+                warn!("Dropping old data for APID {}", apid_label(self.id, apid));
+                self.pool.give(old.bytes);
             }
 
-            let session = Session::new_from_pdu(tp_pdu);
+            let now = self.clock.now();
+            let session = Session::new_from_pdu(tp_pdu, &mut self.pool, self.mode, self.preamble, now, stats)?;
             if flags == 1 {
                 // we'll expect to receive more data with this same APID
                 self.apid_map.insert(apid, session);
             } else {
                 //info!("Starting (and finishing) apid={} (total data len {})", apid, session.bytes.len());
-                let lrit = session.finish();
                 //info!("{:?}", lrit);
-                return Some(lrit);
+                return finish_session(
+                    session,
+                    &mut self.pool,
+                    self.dead_letter.as_deref(),
+                    self.id,
+                    apid,
+                    stats,
+                );
             }
         } else if flags == 0 {
             // we should expect that the starting packets were already received, and that we'll
             // receive some more.
             if let Some(ref mut sess) = self.apid_map.get_mut(&apid) {
-                sess.append(tp_pdu, stats);
+                sess.last_seen = self.clock.now();
+                sess.append(tp_pdu, &mut self.pool, stats);
             } else {
                 // ignore this
                 //println!("Dropping data for unknow apid {}", apid);
@@ -669,11 +1751,10 @@ impl VirtualChannel {
         } else if flags == 2 {
             // this is the final packet
             if let Some(mut sess) = self.apid_map.remove(&apid) {
-                sess.append(tp_pdu, stats);
+                sess.append(tp_pdu, &mut self.pool, stats);
                 //info!("got final TP_PDU packet for APID {} !", apid);
                 //info!("this session frame has {} bytes", sess.bytes.len());
-                let lrit = sess.finish();
-                return Some(lrit);
+                return finish_session(sess, &mut self.pool, self.dead_letter.as_deref(), self.id, apid, stats);
             } else {
                 info!(
                     "Got a final TP_PDU packet for APID {}, but we weren't tracking this one yet",
@@ -685,7 +1766,7 @@ impl VirtualChannel {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Headers {
     pub primary: PrimaryHeader,
     pub img_strucutre: Option<ImageStructureRecord>,
@@ -722,26 +1803,122 @@ pub trait LRITHeader: std::fmt::Debug {
     const TYPE: u8;
 }
 
+/// Why some LRIT headers couldn't be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The data ended before the header did
+    Truncated {
+        header_type: u8,
+        needed: usize,
+        available: usize,
+    },
+    /// A header was parsed as the wrong type
+    WrongType { expected: u8, found: u8 },
+    /// The record length is too short for this type of header
+    BadLength { header_type: u8, length: u16 },
+    /// The total header length in the primary header is shorter than the primary header itself
+    BadTotalLength(u32),
+    /// A header type that isn't known, or a second primary header
+    UnexpectedType(u8),
+}
+
+impl std::fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::Truncated {
+                header_type,
+                needed,
+                available,
+            } => write!(
+                f,
+                "header type {} is truncated (needed {} bytes, but only {} are available)",
+                header_type, needed, available
+            ),
+            HeaderError::WrongType { expected, found } => {
+                write!(f, "expected header type {}, but found {}", expected, found)
+            }
+            HeaderError::BadLength { header_type, length } => {
+                write!(f, "header type {} has an invalid length of {}", header_type, length)
+            }
+            HeaderError::BadTotalLength(len) => write!(f, "total header length {} is invalid", len),
+            HeaderError::UnexpectedType(t) => write!(f, "found unexpected header type {}", t),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+/// Checks the type and length of a header record
+///
+/// Returns the record length, and a cursor over the rest of the record (everything after the type
+/// and length fields).  `min_len` is the smallest valid record length for this type of header, so
+/// callers can read that many bytes from the cursor without checking.
+fn split_record<H: LRITHeader>(data: &[u8], min_len: u16) -> Result<(u16, std::io::Cursor<&[u8]>), HeaderError> {
+    if data.len() < 3 {
+        return Err(HeaderError::Truncated {
+            header_type: H::TYPE,
+            needed: 3,
+            available: data.len(),
+        });
+    }
+    if data[0] != H::TYPE {
+        return Err(HeaderError::WrongType {
+            expected: H::TYPE,
+            found: data[0],
+        });
+    }
+    let len = u16::from_be_bytes([data[1], data[2]]);
+    if len < min_len {
+        return Err(HeaderError::BadLength {
+            header_type: H::TYPE,
+            length: len,
+        });
+    }
+    if len as usize > data.len() {
+        return Err(HeaderError::Truncated {
+            header_type: H::TYPE,
+            needed: len as usize,
+            available: data.len(),
+        });
+    }
+    Ok((len, std::io::Cursor::new(&data[3..len as usize])))
+}
+
 /// Attempts to read LRIT headers
 ///
+/// Every header must fit within the total header length given by the primary header, and that
+/// many bytes must be available in `data`.
+///
 /// Ref: 3_LRIT_Receiver-specs.pdf
 ///
 /// Ref: 5_LRIT_Mission-data.pdf
-pub fn read_headers(data: &[u8]) -> Headers {
+pub fn read_headers(data: &[u8]) -> Result<Headers, HeaderError> {
     // the general approach is to read 1 byte, which indicates what type of header we have, and
     // then read the full header once we know what it is and how long it is.
     //
     // There always must be a primary header at the first header, so we read that first
-    let prim_header = PrimaryHeader::from_bytes(&data).expect("Missing primary header");
-    assert_eq!(prim_header.header_type, 0);
-    assert_eq!(prim_header.header_record_lenth, 16);
+    let prim_header = PrimaryHeader::from_bytes(data)?;
+    let total_header_length = prim_header.total_header_length as usize;
+    if total_header_length < prim_header.header_record_lenth as usize {
+        return Err(HeaderError::BadTotalLength(prim_header.total_header_length));
+    }
+    if total_header_length > data.len() {
+        return Err(HeaderError::Truncated {
+            header_type: PrimaryHeader::TYPE,
+            needed: total_header_length,
+            available: data.len(),
+        });
+    }
     let mut headers = Headers::new(prim_header);
 
     if headers.primary.total_header_length == 16 {
         // there are no more headers, so we're done
-        return headers;
+        return Ok(headers);
     }
 
+    // don't let any header run past the end of the header area, into the data
+    let data = &data[..total_header_length];
+
     let prim_header = &headers.primary;
 
     let mut offset = prim_header.header_record_lenth as usize;
@@ -749,79 +1926,78 @@ pub fn read_headers(data: &[u8]) -> Headers {
     while offset < prim_header.total_header_length as usize {
         // peek at next byte
         match &data[offset] {
-            0 => panic!("Found unexpected header type 0, after already reading a primary header"),
+            // a second primary header
+            0 => return Err(HeaderError::UnexpectedType(0)),
             1 => {
                 // Mandatory for image data
-                let h = ImageStructureRecord::from_bytes(&data[offset..]).unwrap();
+                let h = ImageStructureRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.img_strucutre = Some(h);
             }
             2 => {
                 // Optional for image data
-                let h = ImageNavigationRecord::from_bytes(&data[offset..]).unwrap();
+                let h = ImageNavigationRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.img_navigation = Some(h);
             }
             3 => {
                 // Optional for image data
-                let h = ImageDataFunctionRecord::from_bytes(&data[offset..]).unwrap();
+                let h = ImageDataFunctionRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.img_data = Some(h);
             }
             4 => {
                 // Mandatory for Image Data, Text, Meteorologic Data, and GTS Messages
-                let h = AnnotationRecord::from_bytes(&data[offset..]).unwrap();
+                let h = AnnotationRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.annotation = Some(h);
             }
             5 => {
                 // Mandatory for GTS Messages, optional for image/text/meteorological data
-                let h = TimeStampRecord::from_bytes(&data[offset..]).unwrap();
+                let h = TimeStampRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.timestamp = Some(h);
             }
             6 => {
                 // Optional for image/service messages/text/meteorological data
-                let h = AncillaryTextRecord::from_bytes(&data[offset..]).unwrap();
+                let h = AncillaryTextRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.text = Some(h);
             }
             // 7 -- encrytpion header
             // Optional for image/text/meteorological/GTS
             128 => {
-                let h = ImageSegmentIdentificationRecord::from_bytes(&data[offset..]).unwrap();
+                let h = ImageSegmentIdentificationRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.img_segment = Some(h);
             }
             129 => {
-                let h = NOAALRITHeader::from_bytes(&data[offset..]).unwrap();
+                let h = NOAALRITHeader::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.noaa = Some(h);
             }
             130 => {
-                let h = HeaderStructureRecord::from_bytes(&data[offset..]).unwrap();
+                let h = HeaderStructureRecord::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.header = Some(h);
             }
             131 => {
                 // Optional for all file types
-                let h = RiceCompressionSecondaryHeader::from_bytes(&data[offset..]).unwrap();
+                let h = RiceCompressionSecondaryHeader::from_bytes(&data[offset..])?;
                 offset += h.header_record_lenth as usize;
                 headers.rice_compression = Some(h);
             }
-            x => {
-                panic!("Found unexpected header type {}", x);
-            }
+            x => return Err(HeaderError::UnexpectedType(*x)),
         }
     }
 
-    headers
+    Ok(headers)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct PrimaryHeader {
     /// Header type, should always be 0 (zero)
-    header_type: u8,
+    pub header_type: u8,
 
     /// Length of this header record, should always be 16
     pub header_record_lenth: u16,
@@ -852,32 +2028,26 @@ impl PrimaryHeader {
     pub const fn header_type() -> u8 {
         0
     }
-    pub fn from_bytes(data: &[u8]) -> Option<PrimaryHeader> {
-        if data.len() < 16 {
-            return None;
-        }
-
-        let mut cur = std::io::Cursor::new(data);
+    pub fn from_bytes(data: &[u8]) -> Result<PrimaryHeader, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 16)?;
 
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
         let code = cur.read_u8().unwrap();
         let total_header_len = cur.read_u32::<NetworkEndian>().unwrap();
         let data_len = cur.read_u64::<NetworkEndian>().unwrap();
 
         let header = PrimaryHeader {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             filetype_code: code,
             total_header_length: total_header_len,
             data_field_bits: data_len,
         };
 
-        Some(header)
+        Ok(header)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ImageStructureRecord {
     /// Header type, must always be 1
     pub header_type: u8,
@@ -901,21 +2071,16 @@ impl ImageStructureRecord {
     pub const fn header_type() -> u8 {
         1
     }
-    pub fn from_bytes(data: &[u8]) -> Option<ImageStructureRecord> {
-        if data.len() < 16 {
-            return None;
-        }
+    pub fn from_bytes(data: &[u8]) -> Result<ImageStructureRecord, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 9)?;
 
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
         let bpp = cur.read_u8().unwrap();
         let cols = cur.read_u16::<NetworkEndian>().unwrap();
         let rows = cur.read_u16::<NetworkEndian>().unwrap();
         let cflg = cur.read_u8().unwrap();
 
         let header = ImageStructureRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             bits_per_pixel: bpp,
             num_columns: cols,
@@ -923,11 +2088,11 @@ impl ImageStructureRecord {
             compression: cflg,
         };
 
-        Some(header)
+        Ok(header)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ImageNavigationRecord {
     /// Header type, must always be 2
     pub header_type: u8,
@@ -951,18 +2116,12 @@ impl ImageNavigationRecord {
     pub const fn header_type() -> u8 {
         2
     }
-    pub fn from_bytes(data: &[u8]) -> Option<ImageNavigationRecord> {
-        if data.len() < 51 {
-            return None;
-        }
+    pub fn from_bytes(data: &[u8]) -> Result<ImageNavigationRecord, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 51)?;
 
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut name_buf = [' ' as u8; 32];
-        cur.read_exact(&mut name_buf).ok()?;
-        let name = String::from_utf8_lossy(&name_buf).to_owned().trim().to_owned();
+        let mut name_buf = [b' '; 32];
+        cur.read_exact(&mut name_buf).unwrap();
+        let name = String::from_utf8_lossy(&name_buf).trim().to_owned();
 
         let col_scaling_factor = cur.read_i32::<NetworkEndian>().unwrap();
         let line_scaling_factor = cur.read_i32::<NetworkEndian>().unwrap();
@@ -970,17 +2129,103 @@ impl ImageNavigationRecord {
         let line_offset = cur.read_i32::<NetworkEndian>().unwrap();
 
         let header = ImageNavigationRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             projection_name: name,
             column_scaling_factor: col_scaling_factor,
-            line_scaling_factor: line_scaling_factor,
+            line_scaling_factor,
             column_offset: col_offset,
-            line_offset: line_offset,
+            line_offset,
         };
 
-        Some(header)
+        Ok(header)
+    }
+
+    /// The longitude of the sub-satellite point, for the geostationary projection (like
+    /// `GEOS(-75.0)`)
+    pub fn sub_lon(&self) -> Option<f64> {
+        self.projection_name
+            .strip_prefix("GEOS(")?
+            .strip_suffix(')')?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Converts a column and line of the whole image (not just one segment) to a latitude and
+    /// longitude, in degrees
+    ///
+    /// Returns `None` if the image doesn't use the geostationary projection, or if the pixel is
+    /// off the edge of the earth.
+    pub fn pixel_to_latlon(&self, column: f64, line: f64) -> Option<(f64, f64)> {
+        let sub_lon = self.sub_lon()?;
+        if self.column_scaling_factor == 0 || self.line_scaling_factor == 0 {
+            return None;
+        }
+        // scan angles, in degrees (CGMS LRIT/HRIT Global Specification, 4.4.4)
+        let x = (column - self.column_offset as f64) * 65536.0 / self.column_scaling_factor as f64;
+        let y = (line - self.line_offset as f64) * 65536.0 / self.line_scaling_factor as f64;
+        geos_to_lat_lon(x.to_radians(), y.to_radians(), sub_lon)
     }
+
+    /// Converts a latitude and longitude (in degrees) to a column and line of the whole image
+    ///
+    /// Returns `None` if the image doesn't use the geostationary projection, or if the point is on
+    /// the far side of the earth.  The pixel can still be outside of the image.
+    pub fn latlon_to_pixel(&self, lat: f64, lon: f64) -> Option<(f64, f64)> {
+        let sub_lon = self.sub_lon()?;
+        let (x, y) = lat_lon_to_geos(lat, lon, sub_lon)?;
+        let column = self.column_offset as f64 + x.to_degrees() * self.column_scaling_factor as f64 / 65536.0;
+        let line = self.line_offset as f64 + y.to_degrees() * self.line_scaling_factor as f64 / 65536.0;
+        Some((column, line))
+    }
+}
+
+/// Converts scan angles (in radians) to a latitude and longitude (in degrees)
+///
+/// Positive scan angles are east and south.  ABI sweeps east-west (unlike the SEVIRI-style
+/// projection in the CGMS specification), so this follows the GOES-R PUG (volume 3, 4.2.8).
+/// Returns `None` if that direction doesn't point at the earth.
+pub(crate) fn geos_to_lat_lon(x: f64, y: f64, sub_lon: f64) -> Option<(f64, f64)> {
+    let ratio = (EQUATOR_RADIUS / POLAR_RADIUS).powi(2);
+    // the PUG has north positive
+    let y = -y;
+    let a = x.sin().powi(2) + x.cos().powi(2) * (y.cos().powi(2) + ratio * y.sin().powi(2));
+    let b = -2.0 * ORBIT_RADIUS * x.cos() * y.cos();
+    let c = ORBIT_RADIUS * ORBIT_RADIUS - EQUATOR_RADIUS * EQUATOR_RADIUS;
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // distance from the satellite to the point
+    let rs = (-b - discriminant.sqrt()) / (2.0 * a);
+    let sx = rs * x.cos() * y.cos();
+    let sy = -rs * x.sin();
+    let sz = rs * x.cos() * y.sin();
+    let lat = (ratio * sz / (ORBIT_RADIUS - sx).hypot(sy)).atan().to_degrees();
+    let lon = sub_lon - (sy / (ORBIT_RADIUS - sx)).atan().to_degrees();
+    Some((lat, lon))
+}
+
+/// Converts a latitude and longitude (in degrees) to scan angles (in radians), the inverse of
+/// [`geos_to_lat_lon`]
+///
+/// Returns `None` if the point can't be seen from the satellite.
+pub(crate) fn lat_lon_to_geos(lat: f64, lon: f64, sub_lon: f64) -> Option<(f64, f64)> {
+    let ratio = (POLAR_RADIUS / EQUATOR_RADIUS).powi(2);
+    let c_lat = (ratio * lat.to_radians().tan()).atan();
+    let d_lon = (lon - sub_lon).to_radians();
+    let rc = POLAR_RADIUS / (1.0 - (1.0 - ratio) * c_lat.cos().powi(2)).sqrt();
+    let sx = ORBIT_RADIUS - rc * c_lat.cos() * d_lon.cos();
+    let sy = -rc * c_lat.cos() * d_lon.sin();
+    let sz = rc * c_lat.sin();
+    // the line of sight has to reach the point from above the surface
+    if sx * (ORBIT_RADIUS - sx) - sy * sy - sz * sz / ratio <= 0.0 {
+        return None;
+    }
+    let x = (-sy / (sx * sx + sy * sy + sz * sz).sqrt()).asin();
+    let y = -(sz / sx).atan();
+    Some((x, y))
 }
 
 /// This header specifies an alphanumeric annotation for the fil
@@ -988,7 +2233,7 @@ impl ImageNavigationRecord {
 /// Mandatory for Image Data, Text, Meteorologic Data, and GTS Messages (4_LRIT_Transmitter-specs.pdf Table 16)
 ///
 /// Source: 4_LRIT_Transmitter-specs.pdf Table 10 (page 13)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AnnotationRecord {
     /// Header type, must always be 4
     pub header_type: u8,
@@ -1007,28 +2252,25 @@ impl AnnotationRecord {
     pub const fn header_type() -> u8 {
         4
     }
-    pub fn from_bytes(data: &[u8]) -> Option<AnnotationRecord> {
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut buf = Vec::with_capacity(len as usize - 3);
-        buf.resize(len as usize - 3, ' ' as u8);
-
-        cur.read_exact(&mut buf).ok()?;
-        let text = String::from_utf8_lossy(&buf).to_owned().trim().to_owned();
+    pub fn from_bytes(data: &[u8]) -> Result<AnnotationRecord, HeaderError> {
+        let (len, cur) = split_record::<Self>(data, 3)?;
 
         let header = AnnotationRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
-            text,
+            text: String::from_utf8_lossy(cur.get_ref()).trim().to_owned(),
         };
 
-        Some(header)
+        Ok(header)
+    }
+
+    /// Parses the product name in this annotation, for routing
+    pub fn parsed(&self) -> crate::annotation::LritFilename {
+        crate::annotation::LritFilename::parse(&self.text)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct NOAALRITHeader {
     /// Header type, must always be 129
     pub header_type: u8,
@@ -1050,18 +2292,11 @@ impl NOAALRITHeader {
     pub const fn header_type() -> u8 {
         129
     }
-    pub fn from_bytes(data: &[u8]) -> Option<NOAALRITHeader> {
-        if data.len() < 14 {
-            return None;
-        }
+    pub fn from_bytes(data: &[u8]) -> Result<NOAALRITHeader, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 14)?;
 
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut buf = [' ' as u8; 4];
-        cur.read_exact(&mut buf).ok()?;
-        // let agency_sig = String::from_utf8_lossy(&buf).to_owned().trim().to_owned();
+        // 4 byte agency signature (always "NOAA")
+        cur.set_position(4);
 
         let product_id = cur.read_u16::<NetworkEndian>().unwrap();
         let product_subid = cur.read_u16::<NetworkEndian>().unwrap();
@@ -1069,7 +2304,7 @@ impl NOAALRITHeader {
         let noaa_compression = cur.read_u8().unwrap();
 
         let header = NOAALRITHeader {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             product_id,
             product_subid,
@@ -1077,11 +2312,11 @@ impl NOAALRITHeader {
             noaa_compression,
         };
 
-        Some(header)
+        Ok(header)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct HeaderStructureRecord {
     /// Header type, must always be 130
     pub header_type: u8,
@@ -1100,28 +2335,20 @@ impl HeaderStructureRecord {
     pub const fn header_type() -> u8 {
         130
     }
-    pub fn from_bytes(data: &[u8]) -> Option<HeaderStructureRecord> {
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut buf = Vec::with_capacity(len as usize - 3);
-        buf.resize(len as usize - 3, ' ' as u8);
-
-        cur.read_exact(&mut buf).ok()?;
-        let text = String::from_utf8_lossy(&buf).to_owned().trim().to_owned();
+    pub fn from_bytes(data: &[u8]) -> Result<HeaderStructureRecord, HeaderError> {
+        let (len, cur) = split_record::<Self>(data, 3)?;
 
         let header = HeaderStructureRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
-            text,
+            text: String::from_utf8_lossy(cur.get_ref()).trim().to_owned(),
         };
 
-        Some(header)
+        Ok(header)
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct ImageDataFunctionRecord {
     /// Header type, must always be 3
     header_type: u8,
@@ -1153,27 +2380,25 @@ impl ImageDataFunctionRecord {
     pub const fn header_type() -> u8 {
         3
     }
-    pub fn from_bytes(data: &[u8]) -> Option<ImageDataFunctionRecord> {
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut buf = Vec::with_capacity(len as usize - 3);
-        buf.resize(len as usize - 3, 0u8);
-
-        cur.read_exact(&mut buf).ok()?;
+    pub fn from_bytes(data: &[u8]) -> Result<ImageDataFunctionRecord, HeaderError> {
+        let (len, cur) = split_record::<Self>(data, 3)?;
 
         let header = ImageDataFunctionRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
-            data: buf,
+            data: cur.get_ref().to_vec(),
         };
 
-        Some(header)
+        Ok(header)
+    }
+
+    /// Parses the data function, which says what the image's pixel values mean
+    pub fn data_function(&self) -> Option<DataFunction> {
+        DataFunction::parse(&String::from_utf8_lossy(&self.data))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TimeStampRecord {
     /// Header type, must always be 5
     pub header_type: u8,
@@ -1193,29 +2418,32 @@ impl LRITHeader for TimeStampRecord {
 }
 
 impl TimeStampRecord {
-    pub fn from_bytes(data: &[u8]) -> Option<TimeStampRecord> {
-        if data.len() < 14 {
-            return None;
-        }
-
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
+    pub fn from_bytes(data: &[u8]) -> Result<TimeStampRecord, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 10)?;
 
         let mut time = [0u8; 7];
-        cur.read_exact(&mut time).ok()?;
+        cur.read_exact(&mut time).unwrap();
 
         let header = TimeStampRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             time,
         };
 
-        Some(header)
+        Ok(header)
+    }
+
+    /// The time, skipping the first byte (the CCSDS P-field)
+    pub fn utc(&self) -> Option<DateTime<Utc>> {
+        let days = u16::from_be_bytes([self.time[1], self.time[2]]);
+        let millis = u32::from_be_bytes([self.time[3], self.time[4], self.time[5], self.time[6]]);
+        let epoch = NaiveDate::from_ymd_opt(1958, 1, 1)?.and_hms_opt(0, 0, 0)?;
+        let time = epoch + Duration::days(days as i64) + Duration::milliseconds(millis as i64);
+        Some(Utc.from_utc_datetime(&time))
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AncillaryTextRecord {
     /// Header type, must always be 6
     pub header_type: u8,
@@ -1234,28 +2462,40 @@ impl AncillaryTextRecord {
     pub const fn header_type() -> u8 {
         6
     }
-    pub fn from_bytes(data: &[u8]) -> Option<AncillaryTextRecord> {
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
-
-        let mut buf = Vec::with_capacity(len as usize - 3);
-        buf.resize(len as usize - 3, ' ' as u8);
-
-        cur.read_exact(&mut buf).ok()?;
-        let text = String::from_utf8_lossy(&buf).to_owned().trim().to_owned();
+    pub fn from_bytes(data: &[u8]) -> Result<AncillaryTextRecord, HeaderError> {
+        let (len, cur) = split_record::<Self>(data, 3)?;
 
         let header = AncillaryTextRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
-            text,
+            text: String::from_utf8_lossy(cur.get_ref()).trim().to_owned(),
         };
 
-        Some(header)
+        Ok(header)
+    }
+
+    /// The `key=value` pairs in the text, like `Segmented=yes;Region=Full Disk`
+    ///
+    /// Pairs are separated by `;`.  Whitespace around keys and values is ignored, values can be
+    /// in double quotes, and anything that isn't a pair is skipped.
+    pub fn params(&self) -> HashMap<String, String> {
+        self.text
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (key.trim().to_owned(), value.to_owned())
+            })
+            .filter(|(key, _)| !key.is_empty())
+            .collect()
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RiceCompressionSecondaryHeader {
     /// Header type, must always be 131
     pub header_type: u8,
@@ -1278,32 +2518,26 @@ impl RiceCompressionSecondaryHeader {
     pub const fn header_type() -> u8 {
         131
     }
-    pub fn from_bytes(data: &[u8]) -> Option<RiceCompressionSecondaryHeader> {
-        if data.len() < 7 {
-            return None;
-        }
-
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
+    pub fn from_bytes(data: &[u8]) -> Result<RiceCompressionSecondaryHeader, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 7)?;
 
         let flags = cur.read_u16::<NetworkEndian>().unwrap();
         let pixels_per_block = cur.read_u8().unwrap();
         let scanlines_per_packet = cur.read_u8().unwrap();
 
         let header = RiceCompressionSecondaryHeader {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             flags,
             pixels_per_block,
             scanlines_per_packet,
         };
 
-        Some(header)
+        Ok(header)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct ImageSegmentIdentificationRecord {
     /// Header type, must always be 128
     pub header_type: u8,
@@ -1331,14 +2565,8 @@ impl ImageSegmentIdentificationRecord {
     pub const fn header_type() -> u8 {
         128
     }
-    pub fn from_bytes(data: &[u8]) -> Option<ImageSegmentIdentificationRecord> {
-        if data.len() < 17 {
-            return None;
-        }
-
-        let mut cur = std::io::Cursor::new(data);
-        let typ = cur.read_u8().unwrap();
-        let len = cur.read_u16::<NetworkEndian>().unwrap();
+    pub fn from_bytes(data: &[u8]) -> Result<ImageSegmentIdentificationRecord, HeaderError> {
+        let (len, mut cur) = split_record::<Self>(data, 17)?;
 
         let image_id = cur.read_u16::<NetworkEndian>().unwrap();
         let segment_seq = cur.read_u16::<NetworkEndian>().unwrap();
@@ -1349,7 +2577,7 @@ impl ImageSegmentIdentificationRecord {
         let max_row = cur.read_u16::<NetworkEndian>().unwrap();
 
         let header = ImageSegmentIdentificationRecord {
-            header_type: typ,
+            header_type: Self::TYPE,
             header_record_lenth: len,
             image_id,
             segment_seq,
@@ -1360,6 +2588,690 @@ impl ImageSegmentIdentificationRecord {
             max_row,
         };
 
-        Some(header)
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Prepends a header type and record length to a record body
+    fn record(header_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![header_type];
+        bytes.extend_from_slice(&(body.len() as u16 + 3).to_be_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    proptest! {
+        #[test]
+        fn parsers_never_panic(data in proptest::collection::vec(any::<u8>(), 0..96)) {
+            let _ = PrimaryHeader::from_bytes(&data);
+            let _ = ImageStructureRecord::from_bytes(&data);
+            let _ = ImageNavigationRecord::from_bytes(&data);
+            let _ = ImageDataFunctionRecord::from_bytes(&data);
+            let _ = TimeStampRecord::from_bytes(&data);
+            let _ = NOAALRITHeader::from_bytes(&data);
+            let _ = RiceCompressionSecondaryHeader::from_bytes(&data);
+            let _ = ImageSegmentIdentificationRecord::from_bytes(&data);
+
+            // variable length records must never claim more bytes than they were given
+            if let Ok(h) = AnnotationRecord::from_bytes(&data) {
+                prop_assert!(h.header_record_lenth as usize <= data.len());
+            }
+            if let Ok(h) = AncillaryTextRecord::from_bytes(&data) {
+                prop_assert!(h.header_record_lenth as usize <= data.len());
+            }
+            if let Ok(h) = HeaderStructureRecord::from_bytes(&data) {
+                prop_assert!(h.header_record_lenth as usize <= data.len());
+            }
+            if let Ok(h) = ImageDataFunctionRecord::from_bytes(&data) {
+                prop_assert_eq!(h.data.len() + 3, h.header_record_lenth as usize);
+            }
+        }
+
+        #[test]
+        fn primary_header_round_trip(
+            filetype in any::<u8>(),
+            total in any::<u32>(),
+            bits in any::<u64>(),
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut bytes = vec![0, 0, 16, filetype];
+            bytes.extend_from_slice(&total.to_be_bytes());
+            bytes.extend_from_slice(&bits.to_be_bytes());
+            prop_assert!(PrimaryHeader::from_bytes(&bytes[..15]).is_err());
+            bytes.extend_from_slice(&trailing);
+
+            let h = PrimaryHeader::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_type, 0);
+            prop_assert_eq!(h.header_record_lenth, 16);
+            prop_assert_eq!(h.filetype_code, filetype);
+            prop_assert_eq!(h.total_header_length, total);
+            prop_assert_eq!(h.data_field_bits, bits);
+        }
+
+        #[test]
+        fn image_structure_round_trip(
+            bpp in any::<u8>(),
+            cols in any::<u16>(),
+            lines in any::<u16>(),
+            compression in any::<u8>(),
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut body = vec![bpp];
+            body.extend_from_slice(&cols.to_be_bytes());
+            body.extend_from_slice(&lines.to_be_bytes());
+            body.push(compression);
+            let mut bytes = record(1, &body);
+            prop_assert!(ImageStructureRecord::from_bytes(&bytes[..8]).is_err());
+            bytes.extend_from_slice(&trailing);
+
+            let h = ImageStructureRecord::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_record_lenth, 9);
+            prop_assert_eq!(h.bits_per_pixel, bpp);
+            prop_assert_eq!(h.num_columns, cols);
+            prop_assert_eq!(h.num_lines, lines);
+            prop_assert_eq!(h.compression, compression);
+        }
+
+        #[test]
+        fn segment_round_trip(
+            fields in any::<[u16; 7]>(),
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let body: Vec<u8> = fields.iter().flat_map(|f| f.to_be_bytes()).collect();
+            let mut bytes = record(128, &body);
+            prop_assert!(ImageSegmentIdentificationRecord::from_bytes(&bytes[..16]).is_err());
+            bytes.extend_from_slice(&trailing);
+
+            let h = ImageSegmentIdentificationRecord::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_record_lenth, 17);
+            prop_assert_eq!(
+                [h.image_id, h.segment_seq, h.start_col, h.start_line, h.max_segment, h.max_column, h.max_row],
+                fields
+            );
+        }
+
+        #[test]
+        fn noaa_round_trip(
+            product_id in any::<u16>(),
+            subid in any::<u16>(),
+            parameter in any::<u16>(),
+            compression in any::<u8>(),
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut body = b"NOAA".to_vec();
+            body.extend_from_slice(&product_id.to_be_bytes());
+            body.extend_from_slice(&subid.to_be_bytes());
+            body.extend_from_slice(&parameter.to_be_bytes());
+            body.push(compression);
+            let mut bytes = record(129, &body);
+            prop_assert!(NOAALRITHeader::from_bytes(&bytes[..13]).is_err());
+            bytes.extend_from_slice(&trailing);
+
+            let h = NOAALRITHeader::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_record_lenth, 14);
+            prop_assert_eq!(h.product_id, product_id);
+            prop_assert_eq!(h.product_subid, subid);
+            prop_assert_eq!(h.parameter, parameter);
+            prop_assert_eq!(h.noaa_compression, compression);
+        }
+
+        #[test]
+        fn timestamp_and_rice_round_trip(
+            time in any::<[u8; 7]>(),
+            flags in any::<u16>(),
+            pixels_per_block in any::<u8>(),
+            scanlines in any::<u8>(),
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let mut bytes = record(5, &time);
+            prop_assert!(TimeStampRecord::from_bytes(&bytes[..9]).is_err());
+            bytes.extend_from_slice(&trailing);
+            let h = TimeStampRecord::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_record_lenth, 10);
+            prop_assert_eq!(h.time, time);
+
+            let mut body = flags.to_be_bytes().to_vec();
+            body.extend_from_slice(&[pixels_per_block, scanlines]);
+            let mut bytes = record(131, &body);
+            prop_assert!(RiceCompressionSecondaryHeader::from_bytes(&bytes[..6]).is_err());
+            bytes.extend_from_slice(&trailing);
+            let h = RiceCompressionSecondaryHeader::from_bytes(&bytes).unwrap();
+            prop_assert_eq!(h.header_record_lenth, 7);
+            prop_assert_eq!(h.flags, flags);
+            prop_assert_eq!(h.pixels_per_block, pixels_per_block);
+            prop_assert_eq!(h.scanlines_per_packet, scanlines);
+        }
+
+        #[test]
+        fn text_records_round_trip(
+            text in "[!-~][ -~]{0,62}[!-~]",
+            trailing in proptest::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let len = text.len() as u16 + 3;
+            let with_trailing = |header_type| {
+                let mut bytes = record(header_type, text.as_bytes());
+                bytes.extend_from_slice(&trailing);
+                bytes
+            };
+
+            let bytes = record(4, text.as_bytes());
+            prop_assert!(AnnotationRecord::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            let h = AnnotationRecord::from_bytes(&with_trailing(4)).unwrap();
+            prop_assert_eq!((h.header_record_lenth, &h.text), (len, &text));
+
+            let bytes = record(6, text.as_bytes());
+            prop_assert!(AncillaryTextRecord::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            let h = AncillaryTextRecord::from_bytes(&with_trailing(6)).unwrap();
+            prop_assert_eq!((h.header_record_lenth, &h.text), (len, &text));
+
+            let bytes = record(130, text.as_bytes());
+            prop_assert!(HeaderStructureRecord::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            let h = HeaderStructureRecord::from_bytes(&with_trailing(130)).unwrap();
+            prop_assert_eq!((h.header_record_lenth, &h.text), (len, &text));
+
+            // a record of one type is never parsed as another
+            prop_assert_eq!(
+                AnnotationRecord::from_bytes(&with_trailing(6)).unwrap_err(),
+                HeaderError::WrongType { expected: 4, found: 6 }
+            );
+        }
+
+        /// Like the `vcdu_sequence` fuzz target
+        #[test]
+        fn decoder_never_panics(frames in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 892), 1..6)) {
+            let mut stats = crate::stats::Stats::new();
+            let mut vcs = HashMap::new();
+            for frame in &frames {
+                let vcdu = VCDU::new(frame);
+                vcs.entry(vcdu.vcid())
+                    .or_insert_with(|| VirtualChannel::new(vcdu.vcid(), vcdu.counter()))
+                    .process_vcdu(vcdu, &mut stats);
+            }
+        }
+
+        /// Like the `session` fuzz target: valid TP_PDUs (which get past the CRC check) carrying
+        /// garbage LRIT files
+        #[test]
+        fn sessions_never_panic(
+            files in proptest::collection::vec((0..4u16, proptest::collection::vec(any::<u8>(), 0..3000)), 1..4),
+            header_len in 0..64u8,
+        ) {
+            let mut tx = crate::sim::Transmitter::new();
+            for (apid, file) in &files {
+                let mut file = file.clone();
+                // usually start with something like a primary header, so more of them get past it
+                if file.len() >= 16 && header_len > 0 {
+                    let filetype = file[3] % 4;
+                    file[..8].copy_from_slice(&[0, 0, 16, filetype, 0, 0, 0, header_len]);
+                }
+                tx.send(13, *apid, &file);
+            }
+            let mut stats = crate::stats::Stats::new();
+            let mut vc = None;
+            while !tx.is_idle() {
+                let frame = tx.next_vcdu();
+                let vcdu = VCDU::new(&frame);
+                if !vcdu.is_fill() {
+                    vc.get_or_insert_with(|| VirtualChannel::new(vcdu.vcid(), vcdu.counter()))
+                        .process_vcdu(vcdu, &mut stats);
+                }
+            }
+        }
+
+        /// Rice compressed images with garbage compression parameters and data
+        #[test]
+        fn rice_sessions_never_panic(
+            bits_per_pixel in 0..40u8,
+            columns in 0..2000u16,
+            flags in any::<u16>(),
+            pixels_per_block in proptest::sample::select(vec![0, 8, 16, 32, 64, 200]),
+            // the first scanline is normally sent separately from the headers
+            first in proptest::collection::vec(any::<u8>(), 0..64),
+            scanlines in proptest::collection::vec(proptest::collection::vec(any::<u8>(), 0..2100), 0..4),
+        ) {
+            let headers = crate::sim::LritBuilder::new(0)
+                .image_structure(bits_per_pixel, columns, scanlines.len() as u16)
+                .rice_compression(flags, pixels_per_block, 1)
+                .build(&first);
+            let mut tx = crate::sim::Transmitter::new();
+            tx.send_packets(13, 1, &headers, &scanlines);
+            let mut stats = crate::stats::Stats::new();
+            let mut vc = VirtualChannel::new(13, 0);
+            while !tx.is_idle() {
+                let frame = tx.next_vcdu();
+                vc.process_vcdu(VCDU::new(&frame), &mut stats);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decompression_diagnostics() {
+        let headers = crate::sim::LritBuilder::new(0)
+            .image_structure(8, 8, 2)
+            .rice_compression(1, 16, 1)
+            .build(&[]);
+        // scanlines that are longer than the image is wide can't be right
+        let mut tx = crate::sim::Transmitter::new();
+        tx.send_packets(13, 1, &headers, &[vec![0xff; 20], vec![0xff; 20]]);
+        let mut stats = crate::stats::Stats::new();
+        let mut vc = VirtualChannel::new(13, 0);
+        let mut lrits = Vec::new();
+        while !tx.is_idle() {
+            lrits.extend(vc.process_vcdu(VCDU::new(&tx.next_vcdu()), &mut stats));
+        }
+        assert_eq!(lrits.len(), 1);
+        let decompression = lrits[0].decompression.as_ref().unwrap();
+        assert_eq!((decompression.flags, decompression.pixels_per_block), (1, 16));
+        assert_eq!(decompression.compressed_bytes, 40);
+        assert_eq!(decompression.decompressed_bytes, 16);
+        assert_eq!((decompression.scanlines, decompression.failed_scanlines), (2, 2));
+        assert_eq!(stats.decompression_errors, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "rice")]
+    fn test_decompressor_error() {
+        let headers = crate::sim::LritBuilder::new(0)
+            .image_structure(8, 1000, 2)
+            .rice_compression(1, 16, 1)
+            .build(&[]);
+        // long enough that each scanline ends in a different VCDU
+        let scanlines = [vec![0x55; 880], vec![0x55; 880]];
+        let mut tx = crate::sim::Transmitter::new();
+        tx.send_packets(13, 1, &headers, &scanlines);
+        tx.send_packets(13, 1, &headers, &scanlines);
+        let mut stats = crate::stats::Stats::new();
+        let mut vc = VirtualChannel::new(13, 0);
+        let mut lrits = Vec::new();
+        while !tx.is_idle() {
+            lrits.extend(vc.process_vcdu(VCDU::new(&tx.next_vcdu()), &mut stats));
+            // fail the first image before any of its scanlines are decompressed
+            if lrits.is_empty() {
+                if let Some(session) = vc.apid_map.get_mut(&1) {
+                    if session.decompressor_error.is_none() {
+                        session.decompressor_error = Some("test".to_string());
+                        session.decompression.as_mut().unwrap().error_scanline = Some(0);
+                    }
+                }
+            }
+        }
+        assert_eq!(lrits.len(), 2);
+        let skipped = lrits[0].decompression.as_ref().unwrap();
+        assert_eq!(skipped.error_scanline, Some(0));
+        assert_eq!((skipped.scanlines, skipped.failed_scanlines), (2, 2));
+        assert_eq!(lrits[0].data, vec![0; 2000]);
+        assert_eq!(lrits[0].compressed_data.as_ref().unwrap().len(), 1760);
+        // skipped scanlines aren't decompressor errors
+        assert_eq!(
+            stats.decompression_errors,
+            lrits[1].decompression.as_ref().unwrap().failed_scanlines
+        );
+        // the next image on the APID gets a new decompressor
+        assert_eq!(lrits[1].decompression.as_ref().unwrap().scanlines, 2);
+        assert_eq!(lrits[1].data.len(), 2000);
+    }
+
+    #[test]
+    fn test_session_timeout() {
+        let file = crate::sim::LritBuilder::new(2)
+            .annotation("slow.txt")
+            .build(&[b'x'; 20000]);
+        let clock = crate::clock::ManualClock::new(Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap());
+        let mut stats = crate::stats::Stats::new();
+        let mut vc = VirtualChannel::new(20, 0).with_clock(Arc::new(clock.clone()));
+        // pauses the transmission once a session has started
+        let mut receive = |pause: std::time::Duration| {
+            let mut tx = crate::sim::Transmitter::new();
+            tx.send(20, 1, &file);
+            let mut lrits = Vec::new();
+            let mut paused = false;
+            while !tx.is_idle() {
+                lrits.extend(vc.process_vcdu(VCDU::new(&tx.next_vcdu()), &mut stats));
+                if !paused && !vc.state().sessions.is_empty() {
+                    clock.advance(pause);
+                    paused = true;
+                }
+            }
+            assert!(vc.state().sessions.is_empty());
+            lrits
+        };
+
+        assert_eq!(receive(std::time::Duration::from_secs(5 * 60)).len(), 1);
+        // the session is dropped, and the rest of its TP_PDUs are ignored
+        assert!(receive(SESSION_TIMEOUT + std::time::Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut full = crate::sim::LritBuilder::new(2)
+            .annotation("short.txt")
+            .build(&[b'x'; 100]);
+        // the primary header says there's twice as much data as there is
+        full[8..16].copy_from_slice(&1600u64.to_be_bytes());
+        let mut bad_headers = crate::sim::LritBuilder::new(2).build(b"data");
+        // and this one says its headers run past the end of the file
+        bad_headers[4..8].copy_from_slice(&1000u32.to_be_bytes());
+
+        let mut tx = crate::sim::Transmitter::new();
+        tx.send(20, 1, &bad_headers);
+        tx.send(20, 1, &full);
+        let mut stats = crate::stats::Stats::new();
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter = Arc::new(DeadLetter::new(dir.path()));
+        let mut vc = VirtualChannel::new(20, 0).with_dead_letter(dead_letter.clone());
+        let mut lrits = Vec::new();
+        while !tx.is_idle() {
+            lrits.extend(vc.process_vcdu(VCDU::new(&tx.next_vcdu()), &mut stats));
+        }
+        // the file with bad headers is dropped (but saved as it was received), and the truncated
+        // one is kept
+        let saved: Vec<_> = std::fs::read_dir(dead_letter.dir())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "lrit"))
+            .collect();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(std::fs::read(&saved[0]).unwrap(), bad_headers);
+        let sidecar = std::fs::read_to_string(saved[0].with_extension("json")).unwrap();
+        assert!(sidecar.contains("\"apid\": 1"), "{}", sidecar);
+        assert_eq!(lrits.len(), 1);
+        assert_eq!(lrits[0].data.len(), 100);
+        assert_eq!(
+            lrits[0].truncated,
+            Some(Truncation {
+                expected_bytes: 200,
+                received_bytes: 100
+            })
+        );
+        assert_eq!(LRIT::from_bytes(20, &full).unwrap().truncated, lrits[0].truncated);
+    }
+
+    #[test]
+    fn test_decode_mode() {
+        let mut frame = vec![0; 892];
+        frame[0] = 0x40;
+        frame[1] = 20;
+        let mut stats = crate::stats::Stats::new();
+        let mut vc = VirtualChannel::new(13, 0).with_mode(DecodeMode::Tolerant);
+        assert!(vc.process_vcdu(VCDU::new(&frame), &mut stats).is_empty());
+        assert_eq!(stats.decoder_errors, 1);
+
+        let mut vc = VirtualChannel::new(13, 0);
+        let strict = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            vc.process_vcdu(VCDU::new(&frame), &mut stats)
+        }));
+        assert!(strict.is_err());
+        assert_eq!("tolerant".parse(), Ok(DecodeMode::Tolerant));
+    }
+
+    #[test]
+    fn test_replay_flag() {
+        // a file that takes 4 VCDUs, with the replay flag set from the VCDU with this counter on
+        let receive = |replay_from: u32| {
+            let file = crate::sim::LritBuilder::new(2).annotation("text").build(&[1; 3000]);
+            let mut tx = crate::sim::Transmitter::new();
+            tx.send(20, 1, &file);
+            let mut stats = crate::stats::Stats::new();
+            let mut vc = VirtualChannel::new(20, 0);
+            let mut lrits = Vec::new();
+            while !tx.is_idle() {
+                let mut vcdu = tx.next_vcdu();
+                let replay = VCDU::new(&vcdu).counter() >= replay_from;
+                if replay {
+                    vcdu[5] |= 0x80;
+                }
+                assert_eq!(VCDU::new(&vcdu).is_replay(), replay);
+                lrits.extend(vc.process_vcdu(VCDU::new(&vcdu), &mut stats));
+            }
+            lrits.iter().map(|lrit| lrit.replay).collect::<Vec<_>>()
+        };
+        assert_eq!(receive(u32::MAX), [false]);
+        // any of it being replayed is enough
+        assert_eq!(receive(3), [true]);
+        assert_eq!(receive(0), [true]);
+    }
+
+    #[test]
+    fn test_preamble() {
+        let file = crate::sim::LritBuilder::new(2).annotation("text").build(b"some text");
+        let receive = |mut vc: VirtualChannel, preamble: usize| {
+            let mut pdu = vec![0xff; preamble];
+            pdu.extend_from_slice(&file);
+            let mut tx = crate::sim::Transmitter::new();
+            tx.send_pdu(20, 1, &pdu);
+            let mut stats = crate::stats::Stats::new();
+            let mut lrits = Vec::new();
+            while !tx.is_idle() {
+                lrits.extend(vc.process_vcdu(VCDU::new(&tx.next_vcdu()), &mut stats));
+            }
+            lrits.into_iter().map(|lrit| lrit.data).collect::<Vec<_>>()
+        };
+
+        // the primary header is found wherever it is
+        for preamble in [PREAMBLE_LEN, 0, 4] {
+            assert_eq!(
+                receive(VirtualChannel::new(20, 0), preamble),
+                [b"some text"],
+                "{}",
+                preamble
+            );
+        }
+        assert_eq!(find_primary_header(&[0; 40]), None);
+        // unless it's set
+        assert_eq!(receive(VirtualChannel::new(20, 0).with_preamble(4), 4), [b"some text"]);
+        assert!(receive(VirtualChannel::new(20, 0).with_preamble(PREAMBLE_LEN), 0).is_empty());
+    }
+
+    #[test]
+    fn test_subscriber() {
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscriber = {
+            let seen = seen.clone();
+            move |pdu: &TpPdu| {
+                let mut seen = seen.lock().unwrap();
+                seen.push((
+                    pdu.vcid(),
+                    pdu.apid().unwrap(),
+                    pdu.flags().unwrap(),
+                    pdu.user_data().to_vec(),
+                ));
+            }
+        };
+        let mut vc = VirtualChannel::new(20, 0).with_subscriber(subscriber);
+
+        let file = crate::sim::LritBuilder::new(2).annotation("text").build(&[7; 20000]);
+        let mut transmitter = crate::sim::Transmitter::new();
+        transmitter.send(20, 300, &file);
+        transmitter.send_pdu(20, 500, b"something else");
+        let mut stats = crate::stats::Stats::new();
+        let mut lrits = Vec::new();
+        while !transmitter.is_idle() {
+            let vcdu = transmitter.next_vcdu();
+            let vcdu = VCDU::new(&vcdu);
+            if !vcdu.is_fill() {
+                lrits.extend(vc.process_vcdu(vcdu, &mut stats));
+            }
+        }
+
+        // the LRIT file is still assembled as usual
+        assert_eq!(lrits.len(), 1);
+        assert_eq!(lrits[0].data, [7; 20000]);
+        let seen = seen.lock().unwrap();
+        let flags: Vec<_> = seen.iter().filter(|s| s.1 == 300).map(|s| s.2).collect();
+        assert!(flags.len() > 2, "{:?}", flags);
+        assert_eq!((flags[0], flags[flags.len() - 1]), (1, 2));
+        // and the unknown APID is seen, even though nothing decodes it
+        assert_eq!(seen.last().unwrap(), &(20, 500, 3, b"something else".to_vec()));
+    }
+
+    #[test]
+    fn test_vcdu_dedup() {
+        let frame = |vcid: u8, counter: u32| {
+            let mut bytes = vec![0; 892];
+            bytes[0] = 0x40;
+            bytes[1] = vcid;
+            bytes[2..5].copy_from_slice(&counter.to_be_bytes()[1..]);
+            bytes
+        };
+        let mut dedup = VcduDedup::new(3);
+
+        // two receivers, where the second one missed counter 2
+        let a = [frame(13, 1), frame(13, 2), frame(13, 3)];
+        let b = [frame(13, 1), frame(13, 3), frame(20, 1)];
+        let kept: Vec<_> = a
+            .iter()
+            .zip(&b)
+            .flat_map(|(a, b)| [a, b])
+            .filter(|f| dedup.is_new(&VCDU::new(f)))
+            .map(|f| (VCDU::new(f).vcid(), VCDU::new(f).counter()))
+            .collect();
+        assert_eq!(kept, vec![(13, 1), (13, 2), (13, 3), (20, 1)]);
+        assert_eq!(dedup.duplicates(), 2);
+
+        // counter 1 has fallen out of the window for VC 13
+        assert!(dedup.is_new(&VCDU::new(&frame(13, 4))));
+        assert!(dedup.is_new(&VCDU::new(&frame(13, 1))));
+        assert!(!dedup.is_new(&VCDU::new(&frame(13, 4))));
+    }
+
+    #[test]
+    fn test_to_raw_bytes() {
+        let bytes = crate::sim::LritBuilder::new(0).annotation("img").build(&[7; 16]);
+        let mut lrit = LRIT::from_bytes(13, &bytes).unwrap();
+        assert_eq!(lrit.to_raw_bytes(), bytes);
+
+        // as if the 16 bytes of data had been decompressed from 4
+        lrit.compressed_data = Some(vec![1, 2, 3, 4]);
+        assert_eq!(lrit.to_bytes(), bytes);
+        let raw = lrit.to_raw_bytes();
+        assert_eq!(raw.len(), lrit.raw_headers.len() + 4);
+        assert_eq!(&raw[lrit.raw_headers.len()..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_ancillary_params() {
+        let text = |text: &str| AncillaryTextRecord {
+            header_type: 6,
+            header_record_lenth: text.len() as u16 + 3,
+            text: text.to_owned(),
+        };
+        let params = text("Segmented=yes;Channel=13").params();
+        assert_eq!(params["Segmented"], "yes");
+        assert_eq!(params["Channel"], "13");
+
+        let params =
+            text("Time of frame start = 2022-05-07T12:00:20.5Z ; Region = \"Full Disk\";junk;;=x;Empty=").params();
+        assert_eq!(params["Time of frame start"], "2022-05-07T12:00:20.5Z");
+        assert_eq!(params["Region"], "Full Disk");
+        assert_eq!(params["Empty"], "");
+        assert_eq!(params.len(), 3);
+    }
+
+    #[test]
+    fn test_read_headers_errors() {
+        let mut bytes = record(0, &[0, 0, 0, 0, 32, 0, 0, 0, 0, 0, 0, 0, 0]);
+        bytes.extend(record(4, b"test.lrit"));
+        bytes.extend(record(6, b"x"));
+
+        // the total header length covers the annotation and ancillary text
+        let headers = read_headers(&bytes).unwrap();
+        assert_eq!(headers.annotation.unwrap().text, "test.lrit");
+        assert_eq!(headers.text.unwrap().text, "x");
+
+        assert_eq!(
+            read_headers(&bytes[..29]).unwrap_err(),
+            HeaderError::Truncated {
+                header_type: 0,
+                needed: 32,
+                available: 29
+            }
+        );
+
+        // the ancillary text record runs past the total header length
+        bytes[7] = 31;
+        assert_eq!(
+            read_headers(&bytes).unwrap_err(),
+            HeaderError::Truncated {
+                header_type: 6,
+                needed: 4,
+                available: 3
+            }
+        );
+
+        bytes[7] = 8;
+        assert_eq!(read_headers(&bytes).unwrap_err(), HeaderError::BadTotalLength(8));
+
+        bytes[7] = 32;
+        bytes[28] = 99;
+        assert_eq!(read_headers(&bytes).unwrap_err(), HeaderError::UnexpectedType(99));
+    }
+
+    #[test]
+    fn test_vcid() {
+        for vcid in 0..=63u8 {
+            assert_eq!(u8::from(Vcid::from(vcid)), vcid);
+        }
+        assert_eq!(Vcid::from(13), Vcid::FullDisk(13));
+        assert_eq!(Vcid::from(13).description(), "ABI B13 FD");
+        assert_eq!(Vcid::from(15).description(), "ABI B15 FD");
+        assert_eq!(Vcid::from(40), Vcid::Other(40));
+        assert!(Vcid::from(21).is_emwin() && !Vcid::from(23).is_emwin());
+        assert_eq!(Vcid::Dcs(30).to_string(), "VC30");
+        assert!(Vcid::Fill > Vcid::Other(40) && Vcid::Other(3) < Vcid::FullDisk(7));
+        assert_eq!(serde_json::to_string(&Vcid::Emwin(20)).unwrap(), "20");
+        assert_eq!(serde_json::from_str::<Vcid>("63").unwrap(), Vcid::Fill);
+        assert_eq!(apid_product_name(40, 1), None);
+    }
+
+    #[test]
+    fn test_navigation() {
+        // a GOES-16 full disk image
+        let nav = ImageNavigationRecord {
+            header_type: 2,
+            header_record_lenth: 51,
+            projection_name: "GEOS(-75.0)".to_string(),
+            column_scaling_factor: 20466275,
+            line_scaling_factor: 20466275,
+            column_offset: 2712,
+            line_offset: 2712,
+        };
+        assert_eq!(nav.sub_lon(), Some(-75.0));
+        let (lat, lon) = nav.pixel_to_latlon(2712.0, 2712.0).unwrap();
+        assert!(lat.abs() < 1e-9 && (lon + 75.0).abs() < 1e-9);
+
+        // the worked example in the GOES-R PUG (volume 3, 4.2.8.1), where north is positive
+        let column = 2712.0 + (-0.024052f64).to_degrees() * 20466275.0 / 65536.0;
+        let line = 2712.0 - 0.095340f64.to_degrees() * 20466275.0 / 65536.0;
+        let (lat, lon) = nav.pixel_to_latlon(column, line).unwrap();
+        assert!(
+            (lat - 33.846162).abs() < 1e-3 && (lon + 84.690932).abs() < 1e-3,
+            "{} {}",
+            lat,
+            lon
+        );
+        let (c, l) = nav.latlon_to_pixel(33.846162, -84.690932).unwrap();
+        assert!((c - column).abs() < 0.1 && (l - line).abs() < 0.1, "{} {}", c, l);
+
+        for (lat, lon) in [(-40.0, -120.0), (60.0, -30.0), (0.0, -75.0)] {
+            let (c, l) = nav.latlon_to_pixel(lat, lon).unwrap();
+            let back = nav.pixel_to_latlon(c, l).unwrap();
+            assert!((back.0 - lat).abs() < 1e-6 && (back.1 - lon).abs() < 1e-6, "{:?}", back);
+        }
+        // the far side of the earth, and off the edge of the disk
+        assert!(nav.latlon_to_pixel(0.0, 105.0).is_none());
+        assert!(nav.latlon_to_pixel(0.0, 10.0).is_none());
+        assert!(nav.pixel_to_latlon(0.0, 0.0).is_none());
+        assert!(ImageNavigationRecord {
+            projection_name: "POLAR".to_string(),
+            ..nav
+        }
+        .pixel_to_latlon(2712.0, 2712.0)
+        .is_none());
     }
 }
@@ -0,0 +1,409 @@
+//! Mirroring the output directory to another machine
+//!
+//! Receivers at remote antenna sites usually have a slow or flaky link back to wherever the
+//! products are wanted.  A [`Mirror`] copies new and changed files (by size and modification time,
+//! like rsync's quick check) from the output root to a [`SyncTarget`], and remembers what it has
+//! sent in `sync/<name>.json` under the output root.  Progress is saved as it goes, so a run that's
+//! cut short by a lost connection picks up where it left off next time.
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::UNIX_EPOCH,
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{check_status, pipe_to};
+
+/// Save progress after this many bytes have been sent
+const SAVE_EVERY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Somewhere files can be copied to
+pub trait SyncTarget {
+    /// Writes a file, given its path relative to the output root (with `/` separators)
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Copies `path` (relative to `root`) from disk, for targets that work with files rather than
+    /// bytes
+    fn put_file(&mut self, root: &Path, path: &str) -> io::Result<()> {
+        let data = std::fs::read(root.join(path))?;
+        self.put(path, &data)
+    }
+}
+
+/// Parses a target, like the ones `goesbox sync` and forwarding take
+///
+/// * `https://host/dir` (or `http://`) is a [`HttpTarget`]
+/// * `s3://bucket/prefix` is a [`S3Target`]
+/// * `rsync:host:dir` or `rsync://host/module` is a [`RsyncTarget`]
+/// * `host:dir` is a [`SshTarget`], unless it's really a local path (like `./a:b`)
+/// * anything else is a [`DirectoryTarget`]
+pub fn parse_target(target: &str) -> Box<dyn SyncTarget + Send> {
+    if target.starts_with("http://") || target.starts_with("https://") {
+        return Box::new(HttpTarget::new(target));
+    }
+    if target.starts_with("s3://") {
+        return Box::new(S3Target::new(target));
+    }
+    if target.starts_with("rsync://") {
+        return Box::new(RsyncTarget::new(target));
+    }
+    if let Some(dest) = target.strip_prefix("rsync:") {
+        return Box::new(RsyncTarget::new(dest));
+    }
+    match target.split_once(':') {
+        Some((host, dir)) if !host.contains('/') => Box::new(SshTarget::new(host, dir)),
+        _ => Box::new(DirectoryTarget::new(target)),
+    }
+}
+
+/// A local directory, which can be a mounted network share
+pub struct DirectoryTarget {
+    dir: PathBuf,
+}
+
+impl DirectoryTarget {
+    pub fn new(dir: impl AsRef<Path>) -> DirectoryTarget {
+        DirectoryTarget {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl SyncTarget for DirectoryTarget {
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let dest = self.dir.join(path);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // write to a temporary name first, so a half copied file never shows up
+        let tmp = dest.with_extension("partial");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(tmp, dest)
+    }
+}
+
+/// A directory on another machine, written with `ssh`
+///
+/// This uses the `ssh` command, so keys and host settings come from the usual ssh config.
+pub struct SshTarget {
+    destination: String,
+    dir: String,
+}
+
+impl SshTarget {
+    /// `destination` is anything `ssh` accepts, like `user@host`
+    pub fn new(destination: impl Into<String>, dir: impl Into<String>) -> SshTarget {
+        SshTarget {
+            destination: destination.into(),
+            dir: dir.into(),
+        }
+    }
+}
+
+impl SyncTarget for SshTarget {
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let dest = format!("{}/{}", self.dir.trim_end_matches('/'), path);
+        let parent = dest.rsplit_once('/').map_or(".", |(parent, _)| parent);
+        let script = format!(
+            "mkdir -p {parent} && cat > {tmp} && mv {tmp} {dest}",
+            parent = shell_quote(parent),
+            tmp = shell_quote(&format!("{}.partial", dest)),
+            dest = shell_quote(&dest)
+        );
+        let mut command = Command::new("ssh");
+        command.arg(&self.destination).arg(script);
+        pipe_to(&mut command, "ssh", &self.destination, data)
+    }
+}
+
+/// An HTTP server that accepts `PUT`s (like a WebDAV share), written with `curl`
+///
+/// Credentials can go in `~/.netrc`, since `curl` is run with `--netrc-optional`.
+pub struct HttpTarget {
+    url: String,
+}
+
+impl HttpTarget {
+    /// Files are `PUT` to `url` followed by their path
+    pub fn new(url: impl Into<String>) -> HttpTarget {
+        HttpTarget { url: url.into() }
+    }
+}
+
+impl SyncTarget for HttpTarget {
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let url = format!("{}/{}", self.url.trim_end_matches('/'), path);
+        let mut command = Command::new("curl");
+        command
+            .args([
+                "--fail",
+                "--silent",
+                "--show-error",
+                "--netrc-optional",
+                "--upload-file",
+                "-",
+            ])
+            .arg(&url)
+            .stdout(Stdio::null());
+        pipe_to(&mut command, "curl", &url, data)
+    }
+}
+
+/// An S3 bucket (or anything S3 compatible), written with the `aws` command
+///
+/// Credentials, the region, and the endpoint come from the usual AWS config and environment
+/// variables.
+pub struct S3Target {
+    url: String,
+}
+
+impl S3Target {
+    /// `url` is like `s3://bucket/prefix`
+    pub fn new(url: impl Into<String>) -> S3Target {
+        S3Target { url: url.into() }
+    }
+
+    fn key_url(&self, path: &str) -> String {
+        format!("{}/{}", self.url.trim_end_matches('/'), path)
+    }
+}
+
+impl SyncTarget for S3Target {
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let url = self.key_url(path);
+        let mut command = Command::new("aws");
+        command.args(["s3", "cp", "--only-show-errors", "-"]).arg(&url);
+        pipe_to(&mut command, "aws", &url, data)
+    }
+
+    fn put_file(&mut self, root: &Path, path: &str) -> io::Result<()> {
+        let url = self.key_url(path);
+        let status = Command::new("aws")
+            .args(["s3", "cp", "--only-show-errors"])
+            .arg(root.join(path))
+            .arg(&url)
+            .status()?;
+        check_status("aws", &url, status)
+    }
+}
+
+/// A directory on another machine (or an rsync daemon module), written with `rsync`
+pub struct RsyncTarget {
+    dest: String,
+}
+
+impl RsyncTarget {
+    /// `dest` is anything `rsync` accepts as a destination, like `user@host:dir` or
+    /// `rsync://host/module`
+    pub fn new(dest: impl Into<String>) -> RsyncTarget {
+        RsyncTarget { dest: dest.into() }
+    }
+}
+
+impl SyncTarget for RsyncTarget {
+    fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        // rsync only sends files, so stage the data in a temporary directory first
+        let staging = std::env::temp_dir().join(format!("goesbox-rsync-{}", std::process::id()));
+        let file = staging.join(path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&file, data)?;
+        let result = self.put_file(&staging, path);
+        let _ = std::fs::remove_dir_all(&staging);
+        result
+    }
+
+    fn put_file(&mut self, root: &Path, path: &str) -> io::Result<()> {
+        // with --relative, everything after the "/./" is created on the other end
+        let dest = format!("{}/", self.dest.trim_end_matches('/'));
+        let status = Command::new("rsync")
+            .args(["--relative", "--times", "--partial"])
+            .arg(root.join(".").join(path))
+            .arg(&dest)
+            .status()?;
+        check_status("rsync", &dest, status)
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The size and modification time of a file, as of when it was sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Sent {
+    size: u64,
+    mtime: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SyncSummary {
+    /// Files that were new or changed, and were sent
+    pub sent: usize,
+    /// Files that were already up to date
+    pub unchanged: usize,
+    pub bytes: u64,
+}
+
+/// Copies new and changed files to a [`SyncTarget`], see the [module docs](self)
+pub struct Mirror {
+    root: PathBuf,
+    state_path: PathBuf,
+    sent: BTreeMap<String, Sent>,
+}
+
+impl Mirror {
+    /// Opens the mirror state for `name` (which should be different for each target)
+    pub fn new(root: impl AsRef<Path>, name: &str) -> io::Result<Mirror> {
+        let root = root.as_ref().to_path_buf();
+        let state_path = root.join("sync").join(format!("{}.json", name));
+        let sent = match std::fs::read(&state_path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Mirror { root, state_path, sent })
+    }
+
+    /// Sends every file that's new or has changed since it was last sent
+    ///
+    /// Stops at the first file that can't be sent, after saving progress so far.
+    pub fn run(&mut self, target: &mut dyn SyncTarget) -> io::Result<SyncSummary> {
+        let mut files = Vec::new();
+        find_files(&self.root, &self.root, &mut files)?;
+
+        let mut summary = SyncSummary::default();
+        let mut unsaved = 0;
+        for (path, rel) in files {
+            let meta = std::fs::metadata(&path)?;
+            let sent = Sent {
+                size: meta.len(),
+                mtime: meta.modified()?.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            };
+            if self.sent.get(&rel) == Some(&sent) {
+                summary.unchanged += 1;
+                continue;
+            }
+            let data = std::fs::read(&path)?;
+            if let Err(e) = target.put(&rel, &data) {
+                warn!("Failed to sync {}: {}", rel, e);
+                self.save()?;
+                return Err(e);
+            }
+            self.sent.insert(rel, sent);
+            summary.sent += 1;
+            summary.bytes += sent.size;
+            unsaved += sent.size;
+            if unsaved >= SAVE_EVERY_BYTES {
+                self.save()?;
+                unsaved = 0;
+            }
+        }
+        self.save()?;
+        info!(
+            "Synced {} files ({} bytes), {} unchanged",
+            summary.sent, summary.bytes, summary.unchanged
+        );
+        Ok(summary)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = self.state_path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&self.sent)?)?;
+        std::fs::rename(tmp, &self.state_path)
+    }
+}
+
+/// Finds every file under `dir` (except the mirror state itself, and forwarding spools), sorted by
+/// path
+fn find_files(root: &Path, dir: &Path, files: &mut Vec<(PathBuf, String)>) -> io::Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|e| e.path());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if dir == root && (entry.file_name() == "sync" || entry.file_name() == "forward") {
+                continue;
+            }
+            find_files(root, &path, files)?;
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            let rel = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((path, rel));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{DirectoryTarget, Mirror, SyncSummary, SyncTarget};
+
+    /// Fails after a number of files, like a dropped connection
+    struct Flaky {
+        inner: DirectoryTarget,
+        remaining: usize,
+    }
+
+    impl SyncTarget for Flaky {
+        fn put(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+            if self.remaining == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection lost"));
+            }
+            self.remaining -= 1;
+            self.inner.put(path, data)
+        }
+    }
+
+    #[test]
+    fn test_mirror() {
+        let root = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("emwin/2022-05-07")).unwrap();
+        std::fs::write(root.path().join("emwin/2022-05-07/a.txt"), "a").unwrap();
+        std::fs::write(root.path().join("emwin/2022-05-07/b.txt"), "bb").unwrap();
+        std::fs::write(root.path().join("c.png"), "ccc").unwrap();
+
+        let mut mirror = Mirror::new(root.path(), "remote").unwrap();
+        let mut flaky = Flaky {
+            inner: DirectoryTarget::new(remote.path()),
+            remaining: 1,
+        };
+        assert!(mirror.run(&mut flaky).is_err());
+        assert!(remote.path().join("c.png").exists());
+
+        // a new mirror (like the next run of the sync command) resumes where the last one stopped
+        let mut mirror = Mirror::new(root.path(), "remote").unwrap();
+        let mut target = DirectoryTarget::new(remote.path());
+        assert_eq!(
+            mirror.run(&mut target).unwrap(),
+            SyncSummary {
+                sent: 2,
+                unchanged: 1,
+                bytes: 3
+            }
+        );
+        assert_eq!(
+            std::fs::read_to_string(remote.path().join("emwin/2022-05-07/b.txt")).unwrap(),
+            "bb"
+        );
+        assert!(!remote.path().join("sync").exists());
+
+        std::fs::write(root.path().join("c.png"), "cccc").unwrap();
+        assert_eq!(mirror.run(&mut target).unwrap().sent, 1);
+        assert_eq!(mirror.run(&mut target).unwrap().sent, 0);
+    }
+}
@@ -0,0 +1,137 @@
+//! Ownership and permissions of output files
+//!
+//! Handlers create files with the process's umask, and as the user the process runs as.  So
+//! rather than fixing up every file as it's written, [`OutputPermissions::apply`] sets the umask
+//! and (when started as root, like from a systemd unit) switches to another user and group before
+//! any handlers run.  For example, a umask of `027` with the group `www-data` lets a web server
+//! read products, but not anyone else.
+use std::{ffi::CString, io, os::unix::fs::MetadataExt, path::Path};
+
+use log::info;
+
+#[derive(Debug, Clone, Default)]
+pub struct OutputPermissions {
+    /// Like `0o022`
+    pub umask: Option<u32>,
+    /// A user name or numeric ID
+    pub user: Option<String>,
+    /// A group name or numeric ID; defaults to the user's primary group if a user is given
+    pub group: Option<String>,
+}
+
+impl OutputPermissions {
+    /// Applies these settings to the whole process
+    ///
+    /// When switching users, everything under each of the `output_roots` is first given to the new
+    /// user (and group), so files written by earlier runs as root can still be updated.
+    pub fn apply<P: AsRef<Path>>(&self, output_roots: impl IntoIterator<Item = P>) -> io::Result<()> {
+        if let Some(umask) = self.umask {
+            // safety: umask can't fail, and only changes process state
+            unsafe { libc::umask(umask as libc::mode_t) };
+        }
+
+        let user = self.user.as_deref().map(lookup_user).transpose()?;
+        let gid = match (&self.group, user) {
+            (Some(group), _) => Some(lookup_group(group)?),
+            (None, Some((_, gid))) => Some(gid),
+            (None, None) => None,
+        };
+        if user.is_none() && gid.is_none() {
+            return Ok(());
+        }
+
+        let uid = user.map(|(uid, _)| uid);
+        for root in output_roots {
+            chown_tree(root.as_ref(), uid, gid)?;
+        }
+        if let Some(gid) = gid {
+            // safety: these only change process credentials, and errors are checked
+            if unsafe { libc::setgroups(1, &gid) } != 0 || unsafe { libc::setgid(gid) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(uid) = uid {
+            if unsafe { libc::setuid(uid) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        info!("Now running as uid {:?}, gid {:?}", uid, gid);
+        Ok(())
+    }
+}
+
+/// Parses an octal mode, like `022` or `0o022`
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("{:?} isn't an octal mode like \"022\"", s)),
+    }
+}
+
+/// Returns the user ID and primary group ID of a user name (or numeric user ID)
+fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // safety: getpwnam returns null or a pointer to a static struct, which is read right away
+    let pw = unsafe { libc::getpwnam(c_name.as_ptr()) };
+    if !pw.is_null() {
+        return Ok(unsafe { ((*pw).pw_uid, (*pw).pw_gid) });
+    }
+    if let Ok(uid) = name.parse::<libc::uid_t>() {
+        let pw = unsafe { libc::getpwuid(uid) };
+        let gid = if pw.is_null() { uid } else { unsafe { (*pw).pw_gid } };
+        return Ok((uid, gid));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no such user: {}", name),
+    ))
+}
+
+/// Returns the group ID of a group name (or numeric group ID)
+fn lookup_group(name: &str) -> io::Result<libc::gid_t> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // safety: as in lookup_user
+    let gr = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if !gr.is_null() {
+        return Ok(unsafe { (*gr).gr_gid });
+    }
+    name.parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, format!("no such group: {}", name)))
+}
+
+/// Changes the owner of everything under `dir` that isn't already owned by `uid` and `gid`
+fn chown_tree(dir: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    let meta = match std::fs::symlink_metadata(dir) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if matches!(uid, Some(uid) if meta.uid() != uid) || matches!(gid, Some(gid) if meta.gid() != gid) {
+        std::os::unix::fs::lchown(dir, uid, gid)?;
+    }
+    if meta.is_dir() {
+        for entry in std::fs::read_dir(dir)? {
+            chown_tree(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lookup_group, lookup_user, parse_mode};
+
+    #[test]
+    fn test_permissions() {
+        assert_eq!(parse_mode("022"), Ok(0o022));
+        assert_eq!(parse_mode("0o027"), Ok(0o027));
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("1777").is_err());
+
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert_eq!(lookup_user("0").unwrap().0, 0);
+        assert_eq!(lookup_group("0").unwrap(), 0);
+        assert!(lookup_user("no-such-goesbox-user").is_err());
+    }
+}
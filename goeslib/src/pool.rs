@@ -0,0 +1,142 @@
+//! Reusing byte buffers
+//!
+//! Every TP_PDU is read into its own buffer, and every session grows a buffer until its LRIT file
+//! is finished, which is tens of megabytes for a full disk image segment.  Allocating and freeing
+//! those over and over fragments the heap, which eventually matters on long running 32-bit
+//! boards.  A [`BufferPool`] keeps buffers that are done with, grouped by size (each group holds
+//! buffers with at least a power of two capacity), and hands them out again.
+use log::debug;
+
+/// The smallest group is for buffers of at least 4 KiB; smaller ones aren't kept
+const MIN_CLASS: u32 = 12;
+
+/// The largest group is for buffers of at least 64 MiB; bigger ones aren't kept
+const MAX_CLASS: u32 = 26;
+
+/// Byte buffers that can be reused, see the [module docs](self)
+///
+/// A pool isn't shared between threads: each [`VirtualChannel`](crate::lrit::VirtualChannel) has
+/// its own.
+#[derive(Debug)]
+pub struct BufferPool {
+    /// Free buffers, by size class (starting with `MIN_CLASS`)
+    classes: Vec<Vec<Vec<u8>>>,
+    /// The capacity of all the free buffers
+    pooled_bytes: usize,
+    max_bytes: usize,
+    reused: u64,
+    allocated: u64,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new()
+    }
+}
+
+impl BufferPool {
+    /// A pool that keeps up to 64 MiB of free buffers
+    pub fn new() -> BufferPool {
+        BufferPool {
+            classes: (MIN_CLASS..=MAX_CLASS).map(|_| Vec::new()).collect(),
+            pooled_bytes: 0,
+            max_bytes: 64 << 20,
+            reused: 0,
+            allocated: 0,
+        }
+    }
+
+    /// Keep at most this many bytes of free buffers (0 turns the pool off)
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// An empty buffer that can hold at least `capacity` bytes
+    pub fn take(&mut self, capacity: usize) -> Vec<u8> {
+        let class = capacity.max(1).next_power_of_two().trailing_zeros().max(MIN_CLASS);
+        if class > MAX_CLASS {
+            self.allocated += 1;
+            return Vec::with_capacity(capacity);
+        }
+        // a buffer in a bigger class will do too, so long as it isn't much bigger
+        let found = (class..=MAX_CLASS.min(class + 1)).find_map(|c| self.classes[(c - MIN_CLASS) as usize].pop());
+        match found {
+            Some(buf) => {
+                self.pooled_bytes -= buf.capacity();
+                self.reused += 1;
+                buf
+            }
+            None => {
+                self.allocated += 1;
+                Vec::with_capacity(1 << class)
+            }
+        }
+    }
+
+    /// Returns a buffer to the pool
+    ///
+    /// Buffers that are too small or too big to keep, or that don't fit in the pool, are freed.
+    pub fn give(&mut self, mut buf: Vec<u8>) {
+        let capacity = buf.capacity();
+        // rounded down, so every buffer in a class has at least that class's capacity
+        let class = match capacity.checked_ilog2() {
+            Some(class) if (MIN_CLASS..=MAX_CLASS).contains(&class) => class,
+            _ => return,
+        };
+        if self.pooled_bytes + capacity > self.max_bytes {
+            debug!("Buffer pool is full, freeing a {} byte buffer", capacity);
+            return;
+        }
+        buf.clear();
+        self.pooled_bytes += capacity;
+        self.classes[(class - MIN_CLASS) as usize].push(buf);
+    }
+
+    /// The capacity of the free buffers in the pool
+    pub fn pooled_bytes(&self) -> usize {
+        self.pooled_bytes
+    }
+
+    /// How many buffers [`take`](BufferPool::take) has handed out again, and how many it had to
+    /// allocate
+    pub fn counts(&self) -> (u64, u64) {
+        (self.reused, self.allocated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferPool;
+
+    #[test]
+    fn test_pool() {
+        let mut pool = BufferPool::new();
+        let mut buf = pool.take(8000);
+        assert_eq!(buf.capacity(), 8192);
+        buf.extend_from_slice(&[1; 100]);
+        pool.give(buf);
+        assert_eq!(pool.pooled_bytes(), 8192);
+
+        // it's reused (and empty) for the same size class
+        let buf = pool.take(5000);
+        assert!(buf.is_empty());
+        assert_eq!(buf.capacity(), 8192);
+        pool.give(buf);
+        // but not for a bigger one
+        let big = pool.take(20_000);
+        assert_eq!(big.capacity(), 32768);
+        // a buffer of up to twice the size will do, though
+        let small = pool.take(1000);
+        assert_eq!(small.capacity(), 8192);
+        assert_eq!(pool.counts(), (2, 2));
+        assert_eq!(pool.pooled_bytes(), 0);
+
+        // tiny buffers, and ones that don't fit, aren't kept
+        pool.give(Vec::with_capacity(100));
+        let mut pool = pool.with_max_bytes(16 << 10);
+        pool.give(small);
+        pool.give(big);
+        assert_eq!(pool.pooled_bytes(), 8192);
+    }
+}
@@ -0,0 +1,235 @@
+//! Named output profiles, so one receiver can feed several output trees
+//!
+//! Each profile has its own output root and its own handlers, and a [`Route`] that picks which LRIT
+//! files it gets.  For example, an "archive" profile can keep everything for a year, while a "web"
+//! profile only gets full disk images, and only keeps them for a couple of days.
+//!
+//! A [`ProfileHandler`] is an ordinary [`Handler`], so profiles all run off of the same decoder.
+//! Old files are deleted from a profile's output root by [`Retention`].
+use std::{
+    io,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+
+use crate::{
+    handlers::{glob_match, Dispatcher, Handler, HandlerError, HandlerFailure},
+    lrit::{Vcid, LRIT},
+};
+
+/// Which LRIT files a profile gets
+///
+/// An empty list matches anything, so the default route matches every LRIT file.
+#[derive(Debug, Clone, Default)]
+pub struct Route {
+    pub filetypes: Vec<u8>,
+    pub vcids: Vec<Vcid>,
+    /// Annotation patterns, where `*` matches anything and `?` matches one character
+    pub patterns: Vec<String>,
+}
+
+impl Route {
+    pub fn matches(&self, lrit: &LRIT) -> bool {
+        let pattern_matches = match &lrit.headers.annotation {
+            Some(annotation) => {
+                self.patterns.is_empty()
+                    || self
+                        .patterns
+                        .iter()
+                        .any(|p| glob_match(p.as_bytes(), annotation.text.as_bytes()))
+            }
+            None => self.patterns.is_empty(),
+        };
+        pattern_matches
+            && (self.filetypes.is_empty() || self.filetypes.contains(&lrit.headers.primary.filetype_code))
+            && (self.vcids.is_empty() || self.vcids.contains(&Vcid::from(lrit.vcid)))
+    }
+}
+
+/// Passes the LRIT files that match a [`Route`] through a profile's own handlers
+pub struct ProfileHandler {
+    name: String,
+    route: Route,
+    handlers: Dispatcher,
+}
+
+impl ProfileHandler {
+    pub fn new(name: impl Into<String>, handlers: Dispatcher) -> ProfileHandler {
+        ProfileHandler {
+            name: name.into(),
+            route: Route::default(),
+            handlers,
+        }
+    }
+
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.route = route;
+        self
+    }
+}
+
+/// Failed handlers have already been retried (and logged) by the profile's dispatcher, so the
+/// first failure is reported as a permanent error
+fn first_failure(failures: Vec<HandlerFailure>) -> Result<(), HandlerError> {
+    match failures.into_iter().next() {
+        Some(f) => Err(HandlerError::Other(format!("{} failed: {}", f.handler, f.error).into())),
+        None => Ok(()),
+    }
+}
+
+impl Handler for ProfileHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        if !self.route.matches(lrit) {
+            return Err(HandlerError::Skipped);
+        }
+        first_failure(self.handlers.dispatch(lrit))
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        first_failure(self.handlers.flush())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What [`Retention::prune`] deleted
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PruneSummary {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Deletes files from an output root once they're older than a maximum age
+pub struct Retention {
+    root: PathBuf,
+    max_age: Duration,
+}
+
+impl Retention {
+    pub fn new(root: impl AsRef<Path>, max_age: Duration) -> Retention {
+        Retention {
+            root: root.as_ref().to_path_buf(),
+            max_age,
+        }
+    }
+
+    /// Deletes every file that was last modified more than the maximum age before `now`, and
+    /// any directories that are left empty (except the output root itself)
+    pub fn prune(&self, now: DateTime<Utc>) -> io::Result<PruneSummary> {
+        let cutoff: SystemTime = (now - self.max_age).into();
+        let mut summary = PruneSummary::default();
+        if self.root.exists() {
+            prune_dir(&self.root, cutoff, &mut summary)?;
+        }
+        Ok(summary)
+    }
+
+    /// Prunes the output root every hour
+    pub fn spawn(self) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            match self.prune(Utc::now()) {
+                Ok(PruneSummary { files: 0, .. }) => {}
+                Ok(summary) => info!(
+                    "Deleted {} old files ({} bytes) from {}",
+                    summary.files,
+                    summary.bytes,
+                    self.root.display()
+                ),
+                Err(e) => warn!("Failed to delete old files from {}: {}", self.root.display(), e),
+            }
+            std::thread::sleep(std::time::Duration::from_secs(60 * 60));
+        })
+    }
+}
+
+/// Returns whether the directory is now empty
+fn prune_dir(dir: &Path, cutoff: SystemTime, summary: &mut PruneSummary) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            if prune_dir(&entry.path(), cutoff, summary)? {
+                std::fs::remove_dir(entry.path())?;
+            } else {
+                empty = false;
+            }
+        } else if metadata.modified()? < cutoff {
+            std::fs::remove_file(entry.path())?;
+            summary.files += 1;
+            summary.bytes += metadata.len();
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{Duration, Utc};
+
+    use super::{ProfileHandler, PruneSummary, Retention, Route};
+    use crate::{
+        handlers::{Dispatcher, Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    struct Names(Arc<Mutex<Vec<String>>>);
+
+    impl Handler for Names {
+        fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+            let name = lrit.headers.annotation.as_ref().unwrap().text.clone();
+            self.0.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_profiles() {
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let mut handlers = Dispatcher::new();
+        handlers.push(Box::new(Names(names.clone())));
+        let mut web = ProfileHandler::new("web", handlers).with_route(Route {
+            filetypes: vec![0],
+            patterns: vec!["*CMIPF*".to_string()],
+            ..Route::default()
+        });
+        assert_eq!(web.name(), "web");
+        for (filetype, name) in [
+            (0, "OR_ABI-L2-CMIPF-M6C13"),
+            (0, "OR_ABI-L2-CMIPM1-M6C13"),
+            (2, "CMIPF.txt"),
+        ] {
+            let lrit = LRIT::from_bytes(1, &LritBuilder::new(filetype).annotation(name).build(b"data")).unwrap();
+            let _ = web.handle(&lrit);
+        }
+        assert_eq!(*names.lock().unwrap(), ["OR_ABI-L2-CMIPF-M6C13"]);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("old")).unwrap();
+        std::fs::create_dir_all(dir.path().join("new")).unwrap();
+        std::fs::write(dir.path().join("old/a.jpg"), b"12345").unwrap();
+        std::fs::write(dir.path().join("new/b.jpg"), b"12345").unwrap();
+        let retention = Retention::new(dir.path(), Duration::days(2));
+        let later = Utc::now() + Duration::days(1);
+        assert_eq!(retention.prune(later).unwrap(), PruneSummary::default());
+        let file = std::fs::File::options()
+            .write(true)
+            .open(dir.path().join("old/a.jpg"))
+            .unwrap();
+        file.set_modified((Utc::now() - Duration::days(3)).into()).unwrap();
+        assert_eq!(retention.prune(later).unwrap(), PruneSummary { files: 1, bytes: 5 });
+        assert!(!dir.path().join("old").exists());
+        assert!(dir.path().join("new/b.jpg").exists());
+    }
+}
@@ -0,0 +1,198 @@
+//! Limits on how much data is written per hour
+//!
+//! Now and then the broadcast carries an unusual burst of products (like a flood of text products
+//! during a big weather event, or a switch to a faster imaging mode).  On a receiver with a small
+//! disk, quotas keep these bursts from filling the disk: once a product class has used up its
+//! quota for the last hour, low priority products of that class are dropped until usage falls back
+//! under the limit.  High priority EMWIN products (priority 1 and 2, which includes warnings) are
+//! never dropped, but do count towards the quota.
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{annotation::LritFilename, emwin::Priority, lrit::LRIT};
+
+/// The kinds of products that quotas apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProductClass {
+    Imagery,
+    /// Text products and GTS messages
+    Text,
+    Other,
+}
+
+impl ProductClass {
+    pub fn of(lrit: &LRIT) -> ProductClass {
+        match lrit.headers.primary.filetype_code {
+            0 => ProductClass::Imagery,
+            1 | 2 => ProductClass::Text,
+            _ => ProductClass::Other,
+        }
+    }
+}
+
+impl std::str::FromStr for ProductClass {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "imagery" => Ok(ProductClass::Imagery),
+            "text" => Ok(ProductClass::Text),
+            "other" => Ok(ProductClass::Other),
+            _ => Err("product class must be imagery, text, or other"),
+        }
+    }
+}
+
+/// Usage of one product class over the last hour
+#[derive(Debug, Default)]
+struct Usage {
+    /// When each product was admitted, and its size
+    recent: VecDeque<(DateTime<Utc>, u64)>,
+    bytes: u64,
+    dropped: u64,
+}
+
+impl Usage {
+    fn expire(&mut self, now: DateTime<Utc>) {
+        while let Some((t, size)) = self.recent.front() {
+            if now - *t < Duration::hours(1) {
+                break;
+            }
+            self.bytes -= size;
+            self.recent.pop_front();
+        }
+    }
+
+    fn add(&mut self, now: DateTime<Utc>, size: u64) {
+        self.recent.push_back((now, size));
+        self.bytes += size;
+    }
+}
+
+/// Per class quotas, over a rolling one hour window
+///
+/// With no limits set, everything is admitted.
+#[derive(Debug, Default)]
+pub struct Quotas {
+    max_imagery_bytes: Option<u64>,
+    max_text_files: Option<usize>,
+    imagery: Usage,
+    text: Usage,
+}
+
+impl Quotas {
+    pub fn new() -> Quotas {
+        Quotas::default()
+    }
+
+    pub fn max_imagery_mb_per_hour(mut self, mb: u64) -> Self {
+        self.max_imagery_bytes = Some(mb * 1024 * 1024);
+        self
+    }
+
+    pub fn max_text_files_per_hour(mut self, files: usize) -> Self {
+        self.max_text_files = Some(files);
+        self
+    }
+
+    /// The most imagery that's written in an hour, in bytes, if there's a limit
+    pub fn max_imagery_bytes(&self) -> Option<u64> {
+        self.max_imagery_bytes
+    }
+
+    /// The most text files that are written in an hour, if there's a limit
+    pub fn max_text_files(&self) -> Option<usize> {
+        self.max_text_files
+    }
+
+    /// Decides whether a product should be written, and counts it if so
+    pub fn admit(&mut self, lrit: &LRIT, now: DateTime<Utc>) -> bool {
+        let high_priority = is_high_priority(lrit);
+        let size = lrit.data.len() as u64;
+        match ProductClass::of(lrit) {
+            ProductClass::Imagery => {
+                let usage = &mut self.imagery;
+                usage.expire(now);
+                let over = matches!(self.max_imagery_bytes, Some(max) if usage.bytes + size > max);
+                admit(usage, now, size, over && !high_priority)
+            }
+            ProductClass::Text => {
+                let usage = &mut self.text;
+                usage.expire(now);
+                let over = matches!(self.max_text_files, Some(max) if usage.recent.len() >= max);
+                admit(usage, now, size, over && !high_priority)
+            }
+            ProductClass::Other => true,
+        }
+    }
+
+    /// How many products of this class have been dropped
+    pub fn dropped(&self, class: ProductClass) -> u64 {
+        match class {
+            ProductClass::Imagery => self.imagery.dropped,
+            ProductClass::Text => self.text.dropped,
+            ProductClass::Other => 0,
+        }
+    }
+}
+
+fn admit(usage: &mut Usage, now: DateTime<Utc>, size: u64, drop: bool) -> bool {
+    if drop {
+        usage.dropped += 1;
+        return false;
+    }
+    usage.add(now, size);
+    true
+}
+
+/// Returns true for EMWIN products with priority 1 or 2 (which includes warnings)
+pub(crate) fn is_high_priority(lrit: &LRIT) -> bool {
+    match lrit.headers.annotation.as_ref().map(|a| a.parsed()) {
+        Some(LritFilename::Emwin(emwin)) => matches!(emwin.priority, Priority::Highest | Priority::High),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+    use super::{ProductClass, Quotas};
+    use crate::{lrit::LRIT, sim::LritBuilder};
+
+    fn text(name: &str) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(b"text")).unwrap()
+    }
+
+    #[test]
+    fn test_quotas() {
+        let start = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2022, 5, 4)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        );
+        let mut quotas = Quotas::new().max_text_files_per_hour(2).max_imagery_mb_per_hour(1);
+
+        let low = text("A_FPUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT");
+        let warning = text("A_WWUS81KPHI071250_C_KWIN_20220507125113_106869-1-SPSPHIPA.TXT");
+        assert!(quotas.admit(&low, start));
+        assert!(quotas.admit(&low, start + Duration::minutes(10)));
+        assert!(!quotas.admit(&low, start + Duration::minutes(20)));
+        assert!(quotas.admit(&warning, start + Duration::minutes(20)));
+        assert_eq!(quotas.dropped(ProductClass::Text), 1);
+
+        // the first product has aged out of the window, but the warning still counts
+        assert!(!quotas.admit(&low, start + Duration::minutes(61)));
+        assert!(quotas.admit(&low, start + Duration::minutes(71)));
+
+        let image = LRIT::from_bytes(13, &LritBuilder::new(0).annotation("img").build(&[0; 600 * 1024])).unwrap();
+        assert!(quotas.admit(&image, start));
+        assert!(!quotas.admit(&image, start));
+        assert!(quotas.admit(&image, start + Duration::hours(1)));
+        assert_eq!(quotas.dropped(ProductClass::Imagery), 1);
+    }
+}
@@ -0,0 +1,223 @@
+//! Re-generating derived outputs after the code that makes them changes
+//!
+//! Some of what's written for an image is derived from it: the reduced resolution copies of
+//! segmented images, the links into `mesoscale/<sector>/`, and timing sidecars.  Each product in
+//! the [`ProductIndex`] records the [`DECODER_VERSION`] it was written with, and when that goes up,
+//! a [`Regenerator`] makes the derived outputs again for the images written with an older version.
+//! They're made from the full resolution image that was saved, so the original LRIT files aren't
+//! needed, and then the index is updated with the new version.
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDate;
+use log::warn;
+
+use crate::{
+    handlers::{link_sector, write_pyramid, write_timing_sidecar, HandlerError},
+    index::{ImageTiming, ProductIndex, DECODER_VERSION},
+    storage::LocalStorage,
+    xmp::ImageMetadata,
+};
+
+/// What happened when regenerating a day
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RegenSummary {
+    /// Images whose derived outputs were made again (or would be, with a dry run)
+    pub regenerated: usize,
+    /// Images that were already up to date
+    pub current: usize,
+    /// Out of date images that are no longer on disk
+    pub missing: usize,
+    /// Out of date images that couldn't be read or written, and why
+    pub failed: Vec<(String, String)>,
+}
+
+/// What the index says about an image, from all of its records
+#[derive(Default)]
+struct ImageRecords {
+    stale: bool,
+    segmented: bool,
+    sector: Option<String>,
+    timing: Option<ImageTiming>,
+}
+
+pub struct Regenerator {
+    root: PathBuf,
+    index: ProductIndex,
+    pyramid_levels: u8,
+    timing_sidecar: bool,
+    dry_run: bool,
+}
+
+impl Regenerator {
+    pub fn new(root: impl AsRef<Path>) -> Regenerator {
+        Regenerator {
+            root: root.as_ref().to_path_buf(),
+            index: ProductIndex::new(root),
+            pyramid_levels: 0,
+            timing_sidecar: false,
+            dry_run: false,
+        }
+    }
+
+    /// How many reduced resolution copies to write for each segmented image, like
+    /// [`ImageHandler::with_pyramid_levels`](crate::handlers::ImageHandler::with_pyramid_levels)
+    pub fn with_pyramid_levels(mut self, levels: u8) -> Self {
+        self.pyramid_levels = levels;
+        self
+    }
+
+    /// Also write timing sidecars, like
+    /// [`ImageHandler::with_timing_sidecar`](crate::handlers::ImageHandler::with_timing_sidecar)
+    pub fn with_timing_sidecar(mut self) -> Self {
+        self.timing_sidecar = true;
+        self
+    }
+
+    /// Only count the images that are out of date, without writing anything
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Regenerates the out of date images received on `date`
+    ///
+    /// The day's index file is rewritten afterwards, so records that goesbox appends to it in the
+    /// meantime are lost.  Either regenerate days that are over, or stop goesbox first.
+    pub fn regen_day(&self, date: NaiveDate) -> Result<RegenSummary, HandlerError> {
+        let mut records = self.index.read_day(date)?;
+
+        // the same image can be indexed several times (once for each segment, and again whenever
+        // it's written)
+        let mut images: BTreeMap<&str, ImageRecords> = BTreeMap::new();
+        for record in records.iter().filter(|r| r.filetype_code == 0) {
+            let image = images.entry(&record.annotation).or_default();
+            image.stale |= record.decoder_version.unwrap_or(0) < DECODER_VERSION;
+            image.segmented |= record.segments.is_some();
+            if record.sector.is_some() {
+                image.sector = record.sector.clone();
+            }
+            if record.timing.is_some() {
+                image.timing = record.timing.clone();
+            }
+        }
+
+        let mut summary = RegenSummary::default();
+        let mut regenerated = HashSet::new();
+        for (annotation, image) in &images {
+            if !image.stale {
+                summary.current += 1;
+                continue;
+            }
+            let out_name = self.root.join(annotation).with_extension("jpg");
+            if !out_name.is_file() {
+                summary.missing += 1;
+                continue;
+            }
+            if !self.dry_run {
+                if let Err(e) = self.regen_image(annotation, image, &out_name) {
+                    warn!("{}: failed to regenerate: {}", annotation, e);
+                    summary.failed.push((annotation.to_string(), e.to_string()));
+                    continue;
+                }
+            }
+            summary.regenerated += 1;
+            regenerated.insert(annotation.to_string());
+        }
+
+        if !self.dry_run && !regenerated.is_empty() {
+            for record in records.iter_mut() {
+                if regenerated.contains(&record.annotation) {
+                    record.decoder_version = Some(DECODER_VERSION);
+                }
+            }
+            self.index.write_day(date, &records)?;
+        }
+        Ok(summary)
+    }
+
+    fn regen_image(&self, annotation: &str, image: &ImageRecords, out_name: &Path) -> Result<(), HandlerError> {
+        if image.segmented && self.pyramid_levels > 0 {
+            let img = image::open(out_name)?.to_luma8();
+            let meta = ImageMetadata::from_product_name(annotation);
+            write_pyramid(&LocalStorage, &img, out_name, self.pyramid_levels, &meta)?;
+        }
+        if let Some(sector) = &image.sector {
+            link_sector(&LocalStorage, &self.root, sector, out_name)?;
+        }
+        if let (true, Some(timing)) = (self.timing_sidecar, &image.timing) {
+            write_timing_sidecar(&LocalStorage, out_name, timing)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::{RegenSummary, Regenerator};
+    use crate::{
+        index::{IndexRecord, ProductIndex, DECODER_VERSION},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    #[test]
+    fn test_regen() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let date = NaiveDate::from_ymd_opt(2022, 5, 4).unwrap();
+        let received = Utc.from_utc_datetime(&date.and_hms_opt(18, 10, 0).unwrap());
+        let record = |annotation: &str, segments: Option<&str>, version| IndexRecord {
+            segments: segments.map(str::to_string),
+            decoder_version: version,
+            ..IndexRecord::from_lrit(
+                &LRIT::from_bytes(13, &LritBuilder::new(0).annotation(annotation).build(b"")).unwrap(),
+                received,
+            )
+            .unwrap()
+        };
+        let index = ProductIndex::new(root);
+        // a segment, then the image it was written into, before versions were recorded
+        index.append(&record("old.lrit", None, None)).unwrap();
+        index.append(&record("old.lrit", Some("1111"), None)).unwrap();
+        index
+            .append(&record("new.lrit", Some("1111"), Some(DECODER_VERSION)))
+            .unwrap();
+        index.append(&record("gone.lrit", Some("1111"), None)).unwrap();
+        for name in ["old.jpg", "new.jpg"] {
+            image::GrayImage::new(64, 48).save(root.join(name)).unwrap();
+        }
+
+        let regen = Regenerator::new(root).with_pyramid_levels(2);
+        let dry_run = Regenerator::new(root).with_dry_run().regen_day(date).unwrap();
+        assert_eq!(dry_run.regenerated, 1);
+        assert!(!root.join("old.1-2.jpg").exists());
+
+        assert_eq!(
+            regen.regen_day(date).unwrap(),
+            RegenSummary {
+                regenerated: 1,
+                current: 1,
+                missing: 1,
+                failed: Vec::new(),
+            }
+        );
+        let quarter = image::open(root.join("old.1-4.jpg")).unwrap();
+        assert_eq!((quarter.width(), quarter.height()), (16, 12));
+        assert!(!root.join("new.1-2.jpg").exists());
+        let records = index.read_day(date).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records
+            .iter()
+            .filter(|r| r.annotation == "old.lrit")
+            .all(|r| r.decoder_version == Some(DECODER_VERSION)));
+
+        // only the missing image is still out of date
+        let summary = regen.regen_day(date).unwrap();
+        assert_eq!((summary.regenerated, summary.current, summary.missing), (0, 2, 1));
+    }
+}
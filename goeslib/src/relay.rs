@@ -0,0 +1,397 @@
+//! Re-sending products to a downstream receiver, with bandwidth shaping
+//!
+//! A receiver at a remote antenna site often has a much slower uplink than the downlink it's
+//! receiving.  A [`Relay`] sends LRIT files over TCP from its own thread, no faster than a
+//! configured rate, and always sends the most important queued product next: high priority EMWIN
+//! products (like warnings) first, then other text and small images, and bulk imagery only when
+//! nothing else is waiting.  If the imagery backlog grows past a limit, the oldest imagery is
+//! dropped.
+//!
+//! Each product is sent as a 2 byte name length, the name, a 4 byte data length, and the data
+//! (lengths are big endian).  [`read_product`] reads one back.
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use byteorder::{NetworkEndian, ReadBytesExt, WriteBytesExt};
+use log::{info, warn};
+
+use crate::{
+    handlers::{Handler, HandlerError},
+    lrit::LRIT,
+    quota::{is_high_priority, ProductClass},
+};
+
+/// Images smaller than this (like NHC graphics) aren't treated as bulk imagery
+const SMALL_IMAGE_BYTES: usize = 64 * 1024;
+
+/// How much is written between checks of the rate limit
+const CHUNK_BYTES: usize = 16 * 1024;
+
+/// The order products are sent in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RelayPriority {
+    /// High priority EMWIN products, like warnings
+    Urgent,
+    /// Text products, GTS messages, DCS, and small images
+    Normal,
+    /// Imagery, sent when nothing else is waiting
+    Bulk,
+}
+
+impl RelayPriority {
+    pub fn of(lrit: &LRIT) -> RelayPriority {
+        if is_high_priority(lrit) {
+            return RelayPriority::Urgent;
+        }
+        match ProductClass::of(lrit) {
+            ProductClass::Imagery if lrit.data.len() >= SMALL_IMAGE_BYTES => RelayPriority::Bulk,
+            _ => RelayPriority::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayOptions {
+    /// Don't send faster than this (no limit if `None`)
+    pub bytes_per_second: Option<u64>,
+    /// Drop the oldest bulk imagery once this much is waiting
+    pub max_bulk_bytes: usize,
+    /// How long to wait before reconnecting after an error
+    pub retry_delay: Duration,
+}
+
+impl Default for RelayOptions {
+    fn default() -> Self {
+        RelayOptions {
+            bytes_per_second: None,
+            max_bulk_bytes: 256 * 1024 * 1024,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Limits the average rate, while allowing up to a second's worth of bytes in a burst
+struct TokenBucket {
+    bytes_per_second: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_second: u64, now: Instant) -> TokenBucket {
+        TokenBucket {
+            bytes_per_second: bytes_per_second.max(1) as f64,
+            tokens: 0.0,
+            last: now,
+        }
+    }
+
+    /// Takes `bytes`, and returns how long to wait before sending them
+    fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.bytes_per_second);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second)
+        }
+    }
+}
+
+struct Product {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Products waiting to be sent, one queue per [`RelayPriority`]
+#[derive(Default)]
+struct RelayQueue {
+    queues: [VecDeque<Product>; 3],
+    bulk_bytes: usize,
+    max_bulk_bytes: usize,
+    dropped: u64,
+    shutdown: bool,
+}
+
+impl RelayQueue {
+    fn push(&mut self, priority: RelayPriority, product: Product) {
+        if priority == RelayPriority::Bulk {
+            self.bulk_bytes += product.data.len();
+        }
+        self.queues[priority as usize].push_back(product);
+
+        let bulk = &mut self.queues[RelayPriority::Bulk as usize];
+        while self.bulk_bytes > self.max_bulk_bytes && bulk.len() > 1 {
+            if let Some(old) = bulk.pop_front() {
+                self.bulk_bytes -= old.data.len();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    /// Puts back a product that couldn't be sent, to be sent next
+    fn push_front(&mut self, priority: RelayPriority, product: Product) {
+        if priority == RelayPriority::Bulk {
+            self.bulk_bytes += product.data.len();
+        }
+        self.queues[priority as usize].push_front(product);
+    }
+
+    fn pop(&mut self) -> Option<(RelayPriority, Product)> {
+        let priorities = [RelayPriority::Urgent, RelayPriority::Normal, RelayPriority::Bulk];
+        let priority = priorities
+            .iter()
+            .copied()
+            .find(|p| !self.queues[*p as usize].is_empty())?;
+        let product = self.queues[priority as usize].pop_front()?;
+        if priority == RelayPriority::Bulk {
+            self.bulk_bytes -= product.data.len();
+        }
+        Some((priority, product))
+    }
+}
+
+struct Shared {
+    queue: Mutex<RelayQueue>,
+    ready: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, RelayQueue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A handle for queueing products on a [`Relay`]
+///
+/// This is cheap to clone.
+#[derive(Clone)]
+pub struct RelaySender {
+    shared: Arc<Shared>,
+}
+
+impl RelaySender {
+    pub fn send(&self, priority: RelayPriority, name: impl Into<String>, data: Vec<u8>) {
+        self.shared.lock().push(
+            priority,
+            Product {
+                name: name.into(),
+                data,
+            },
+        );
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Sends products to a downstream receiver, see the [module docs](self)
+pub struct Relay {
+    sender: RelaySender,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Relay {
+    /// Starts the relay thread, which connects to `address` (like `remote.example.com:5010`)
+    ///
+    /// The connection is made when the first product is queued, and is remade after any error.
+    pub fn spawn(address: impl Into<String>, options: RelayOptions) -> io::Result<Relay> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(RelayQueue {
+                max_bulk_bytes: options.max_bulk_bytes,
+                ..Default::default()
+            }),
+            ready: Condvar::new(),
+        });
+        let address = address.into();
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("relay".to_string())
+            .spawn(move || run(&address, &options, &thread_shared))?;
+        Ok(Relay {
+            sender: RelaySender { shared },
+            thread: Some(thread),
+        })
+    }
+
+    pub fn sender(&self) -> RelaySender {
+        self.sender.clone()
+    }
+
+    /// How many bulk products have been dropped to keep the backlog under its limit
+    pub fn dropped(&self) -> u64 {
+        self.sender.shared.lock().dropped
+    }
+}
+
+impl Drop for Relay {
+    /// Stops the relay thread, after the product it's sending (if any); the rest are dropped
+    fn drop(&mut self) {
+        self.sender.shared.lock().shutdown = true;
+        self.sender.shared.ready.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(address: &str, options: &RelayOptions, shared: &Shared) {
+    let mut stream: Option<TcpStream> = None;
+    let mut bucket = options
+        .bytes_per_second
+        .map(|rate| TokenBucket::new(rate, Instant::now()));
+    loop {
+        let (priority, product) = {
+            let mut queue = shared.lock();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(next) = queue.pop() {
+                    break next;
+                }
+                queue = shared.ready.wait(queue).unwrap_or_else(|e| e.into_inner());
+            }
+        };
+
+        let result = match &mut stream {
+            Some(stream) => send(stream, &product, bucket.as_mut()),
+            None => TcpStream::connect(address).and_then(|mut new| {
+                info!("Relay connected to {}", address);
+                let result = send(&mut new, &product, bucket.as_mut());
+                stream = Some(new);
+                result
+            }),
+        };
+        if let Err(e) = result {
+            warn!("Failed to relay {} to {}: {}", product.name, address, e);
+            stream = None;
+            let mut queue = shared.lock();
+            queue.push_front(priority, product);
+            // wait before retrying, unless we're shut down in the meantime
+            let (queue, _) = shared
+                .ready
+                .wait_timeout_while(queue, options.retry_delay, |q| !q.shutdown)
+                .unwrap_or_else(|e| e.into_inner());
+            if queue.shutdown {
+                return;
+            }
+        }
+    }
+}
+
+fn send(stream: &mut TcpStream, product: &Product, mut bucket: Option<&mut TokenBucket>) -> io::Result<()> {
+    let name = product.name.as_bytes();
+    let mut header = Vec::with_capacity(name.len() + 6);
+    header.write_u16::<NetworkEndian>(name.len() as u16)?;
+    header.extend_from_slice(name);
+    header.write_u32::<NetworkEndian>(product.data.len() as u32)?;
+    stream.write_all(&header)?;
+
+    for chunk in product.data.chunks(CHUNK_BYTES) {
+        if let Some(bucket) = bucket.as_deref_mut() {
+            std::thread::sleep(bucket.take(chunk.len(), Instant::now()));
+        }
+        stream.write_all(chunk)?;
+    }
+    stream.flush()
+}
+
+/// Reads one product sent by a [`Relay`], returning its name and data
+pub fn read_product(reader: &mut impl Read) -> io::Result<(String, Vec<u8>)> {
+    let name_len = reader.read_u16::<NetworkEndian>()? as usize;
+    let mut name = vec![0; name_len];
+    reader.read_exact(&mut name)?;
+    let data_len = reader.read_u32::<NetworkEndian>()? as usize;
+    let mut data = vec![0; data_len];
+    reader.read_exact(&mut data)?;
+    let name = String::from_utf8(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((name, data))
+}
+
+/// Queues every LRIT file on a [`Relay`]
+///
+/// Files are sent whole (see [`LRIT::to_bytes`]), named after their annotation.
+pub struct RelayHandler {
+    sender: RelaySender,
+}
+
+impl RelayHandler {
+    pub fn new(sender: RelaySender) -> RelayHandler {
+        RelayHandler { sender }
+    }
+}
+
+impl Handler for RelayHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let name = match &lrit.headers.annotation {
+            Some(ann) => ann.text.clone(),
+            None => return Err(HandlerError::MissingHeader("annotation")),
+        };
+        self.sender.send(RelayPriority::of(lrit), name, lrit.to_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        time::{Duration, Instant},
+    };
+
+    use super::{read_product, Product, Relay, RelayOptions, RelayPriority, RelayQueue, TokenBucket};
+
+    #[test]
+    fn test_queue_and_bucket() {
+        let product = |name: &str, len| Product {
+            name: name.to_string(),
+            data: vec![0; len],
+        };
+        let mut queue = RelayQueue {
+            max_bulk_bytes: 100,
+            ..Default::default()
+        };
+        queue.push(RelayPriority::Bulk, product("fd1", 60));
+        queue.push(RelayPriority::Normal, product("afd", 10));
+        queue.push(RelayPriority::Bulk, product("fd2", 60));
+        queue.push(RelayPriority::Urgent, product("tor", 10));
+        let order: Vec<_> = std::iter::from_fn(|| queue.pop()).map(|(_, p)| p.name).collect();
+        // fd1 was dropped to make room for fd2
+        assert_eq!(order, vec!["tor", "afd", "fd2"]);
+        assert_eq!(queue.dropped, 1);
+        assert_eq!(queue.bulk_bytes, 0);
+
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+        assert_eq!(bucket.take(500, start), Duration::from_millis(500));
+        // the debt is paid off after half a second, and then there's room for a burst
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(bucket.take(1000, later), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let relay = Relay::spawn(listener.local_addr().unwrap().to_string(), RelayOptions::default()).unwrap();
+        relay.sender().send(RelayPriority::Normal, "first", b"hello".to_vec());
+        relay.sender().send(RelayPriority::Bulk, "second", vec![7; 100_000]);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(
+            read_product(&mut stream).unwrap(),
+            ("first".to_string(), b"hello".to_vec())
+        );
+        assert_eq!(
+            read_product(&mut stream).unwrap(),
+            ("second".to_string(), vec![7; 100_000])
+        );
+        drop(relay);
+    }
+}
@@ -0,0 +1,206 @@
+//! Re-parsing an EMWIN archive with the current parsers
+//!
+//! The EMWIN parsers only know the WMO codes that someone has added to them, and the downlink
+//! keeps finding new ones.  [`reparse`] walks an output root that goesbox has already written,
+//! parses the name of every EMWIN file again (and the body of every bulletin that one of the
+//! decoders handles), and counts what the parsers don't understand, so the gaps can be filled in.
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    annotation::is_emwin_name,
+    emwin::{
+        metar, shef, sounding,
+        swpc::SpaceWeatherMessage,
+        wmo::{WMODataTypeT2, WmoError},
+        EmwinNameError, ParsedEmwinName,
+    },
+    handlers::{is_shef_product, is_sounding_bulletin, is_surface_bulletin, is_swpc_bulletin},
+};
+
+/// What re-parsing an archive found
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReparseSummary {
+    /// EMWIN files that were looked at
+    pub files: usize,
+    /// Files whose names parsed without anything unknown
+    pub parsed: usize,
+    /// Files with a T2 code the parser doesn't know, counted by T1T2 (like "NQ")
+    pub unknown_t2: BTreeMap<String, usize>,
+    /// Files with an area designator the parser doesn't know, counted by T1T2A1A2 (like "FPQQ")
+    pub unknown_areas: BTreeMap<String, usize>,
+    /// Files whose names couldn't be parsed for some other reason, and why
+    pub failures: Vec<(PathBuf, String)>,
+    /// Bulletins that a decoder should handle but got nothing out of, and which decoder
+    pub undecoded: Vec<(PathBuf, &'static str)>,
+}
+
+impl ReparseSummary {
+    /// Re-parses one file, which should be named like an EMWIN product
+    pub fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        self.files += 1;
+        let parsed = match check_name(name) {
+            NameCheck::Parsed(parsed) => parsed,
+            NameCheck::UnknownT2(t1t2) => {
+                *self.unknown_t2.entry(t1t2).or_default() += 1;
+                return Ok(());
+            }
+            NameCheck::UnknownArea(ttaa) => {
+                *self.unknown_areas.entry(ttaa).or_default() += 1;
+                return Ok(());
+            }
+            NameCheck::Failed(error) => {
+                self.failures.push((path.to_path_buf(), error));
+                return Ok(());
+            }
+        };
+        match has_unknown_t2(&parsed) {
+            true => *self.unknown_t2.entry(name[2..4].to_string()).or_default() += 1,
+            false => self.parsed += 1,
+        }
+
+        if !parsed.legacy_filename.to_ascii_uppercase().ends_with(".TXT") {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&std::fs::read(path)?).into_owned();
+        if let Some((decoder, decoded)) = decode_body(name, &parsed, &text) {
+            if !decoded {
+                self.undecoded.push((path.to_path_buf(), decoder));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the decoder that handles a bulletin (if there is one), returning its name and whether it
+/// got anything out of the bulletin
+fn decode_body(name: &str, emwin: &ParsedEmwinName, text: &str) -> Option<(&'static str, bool)> {
+    if is_surface_bulletin(name) {
+        Some(("metar", !metar::parse_bulletin(text).is_empty()))
+    } else if is_sounding_bulletin(name) {
+        Some(("sounding", !sounding::parse_bulletin(text).is_empty()))
+    } else if is_swpc_bulletin(name) {
+        Some(("swpc", SpaceWeatherMessage::parse(text).is_some()))
+    } else if is_shef_product(&emwin.legacy_filename) {
+        Some(("shef", !shef::parse_bulletin(text, emwin.date).is_empty()))
+    } else {
+        None
+    }
+}
+
+/// What the filename parser made of an EMWIN filename
+pub(crate) enum NameCheck {
+    /// It parsed, though the T2 code might still be unknown (see [`has_unknown_t2`])
+    Parsed(ParsedEmwinName),
+    /// The parser doesn't know the T2 code, for this T1T2 (like "FY")
+    UnknownT2(String),
+    /// The parser doesn't know the area designator, for this T1T2A1A2 (like "FPQQ")
+    UnknownArea(String),
+    /// It couldn't be parsed for some other reason
+    Failed(String),
+}
+
+/// Parses an EMWIN filename, sorting out the codes the parser doesn't know from other failures
+pub(crate) fn check_name(name: &str) -> NameCheck {
+    match ParsedEmwinName::parse(name) {
+        Ok(parsed) => NameCheck::Parsed(parsed),
+        Err(EmwinNameError::Wmo(WmoError::UnknownT2(t1t2))) => NameCheck::UnknownT2(t1t2),
+        Err(EmwinNameError::Wmo(WmoError::UnknownArea(ttaa))) => NameCheck::UnknownArea(ttaa),
+        Err(e) => NameCheck::Failed(e.to_string()),
+    }
+}
+
+/// Returns true if the parser didn't know the T2 code of a name that it did parse
+pub(crate) fn has_unknown_t2(parsed: &ParsedEmwinName) -> bool {
+    matches!(
+        parsed.data_type_2,
+        WMODataTypeT2::UnknownAnalyses(_)
+            | WMODataTypeT2::UnknownClimate(_)
+            | WMODataTypeT2::UnknownNotice(_)
+            | WMODataTypeT2::UnknownSatellite(_)
+            | WMODataTypeT2::UnknownUpperAir(_)
+            | WMODataTypeT2::UnknownWarning(_)
+    )
+}
+
+/// Re-parses every EMWIN file under `root`
+///
+/// EMWIN files are the ones named like `A_...` or `Z_...`.  Header sidecars and debug files are
+/// skipped, and so are symlinks, since they point at files that are counted anyway.
+pub fn reparse(root: impl AsRef<Path>) -> io::Result<ReparseSummary> {
+    let mut summary = ReparseSummary::default();
+    let mut dirs = vec![root.as_ref().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = std::fs::read_dir(&dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let file_type = entry.file_type()?;
+            let path = entry.path();
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() && is_emwin_product(&entry.file_name().to_string_lossy()) {
+                summary.add_file(&path)?;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn is_emwin_product(name: &str) -> bool {
+    is_emwin_name(name) && !name.ends_with(".hdr") && !name.ends_with(".hdr.json") && !name.ends_with(".debug")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reparse;
+
+    #[test]
+    fn test_reparse() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, body: &str| std::fs::write(dir.path().join(name), body).unwrap();
+        write(
+            "A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT",
+            "SAUS70 KWBC 071200\r\r\nMETAR KBOS 071154Z 27010KT 10SM FEW050 18/05 A2992=\r\r\n",
+        );
+        write("A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT.hdr", "");
+        write(
+            "A_SAUS70KWBC071300_C_KWIN_20220507130113_106869-3-MTRBOSMA.TXT",
+            "garbled",
+        );
+        write("A_NQUS41KPHI041812_C_KWIN_20220504181303_881367-3-ADMPHIPA.TXT", "");
+        write("A_FPQQ20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT", "");
+        write("A_FYUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT", "");
+        std::fs::create_dir(dir.path().join("history")).unwrap();
+        write(
+            "history/A_XXUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT",
+            "",
+        );
+        write("history/A_bogus.TXT", "");
+        write("history/MTRBOSMA.log", "");
+
+        let summary = reparse(dir.path()).unwrap();
+        assert_eq!(summary.files, 6);
+        assert_eq!(summary.parsed, 2);
+        assert_eq!(
+            summary.unknown_t2.into_iter().collect::<Vec<_>>(),
+            [("FY".to_string(), 1), ("NQ".to_string(), 1)]
+        );
+        assert_eq!(summary.unknown_areas["FPQQ"], 1);
+        assert_eq!(summary.failures.len(), 1);
+        assert!(summary.failures[0]
+            .0
+            .ends_with("history/A_XXUS20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT"));
+        assert_eq!(summary.undecoded.len(), 1);
+        assert!(summary.undecoded[0]
+            .0
+            .ends_with("A_SAUS70KWBC071300_C_KWIN_20220507130113_106869-3-MTRBOSMA.TXT"));
+        assert_eq!(summary.undecoded[0].1, "metar");
+    }
+}
@@ -0,0 +1,137 @@
+//! Keeping replayed data apart from live data
+//!
+//! Now and then, data is sent again instead of live: VCDUs with the replay flag set (see
+//! [`VCDU::is_replay`](crate::lrit::VCDU::is_replay)), or stored mission data that's rebroadcast
+//! hours after it was taken.  Otherwise it looks just like live data, so it's easy to mistake
+//! hours-old imagery for the latest.
+//!
+//! LRIT files from replayed VCDUs are always marked (see [`LRIT::replay`]), and are tagged in the
+//! [product index](crate::index::IndexRecord::replay).  A [`ReplayFilter`] in the
+//! [`Dispatcher`] can also mark LRIT files that are too old to be live, and write replays with
+//! handlers of their own, into a separate tree.  (This is different from replaying a capture,
+//! which is live data received earlier.)
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    handlers::{Dispatcher, HandlerFailure},
+    lrit::LRIT,
+};
+
+/// Recognizes replayed LRIT files, and where they go instead
+pub struct ReplayFilter {
+    max_age: Option<Duration>,
+    handlers: Option<Box<Dispatcher>>,
+}
+
+impl Default for ReplayFilter {
+    fn default() -> Self {
+        ReplayFilter::new()
+    }
+}
+
+impl ReplayFilter {
+    /// Only LRIT files from replayed VCDUs are replays, and they're handled like any other
+    pub fn new() -> ReplayFilter {
+        ReplayFilter {
+            max_age: None,
+            handlers: None,
+        }
+    }
+
+    /// Also treat LRIT files whose time stamp header (or annotation, if there isn't one) is older
+    /// than this as replays
+    pub fn with_max_age(mut self, age: std::time::Duration) -> Self {
+        self.max_age = Duration::from_std(age).ok();
+        self
+    }
+
+    /// Handle replays with these handlers (like ones writing to another root), instead of the
+    /// usual ones
+    pub fn with_handlers(mut self, handlers: Dispatcher) -> Self {
+        self.handlers = Some(Box::new(handlers));
+        self
+    }
+
+    /// Returns true if this LRIT file was replayed, or is older than the maximum age at `now`
+    pub fn is_replay(&self, lrit: &LRIT, now: DateTime<Utc>) -> bool {
+        lrit.replay
+            || match (self.max_age, lrit.data_time()) {
+                (Some(max_age), Some(time)) => now - time > max_age,
+                _ => false,
+            }
+    }
+
+    /// A copy of an LRIT file that's marked as a replay, if it's one that isn't marked already
+    pub fn mark(&self, lrit: &LRIT, now: DateTime<Utc>) -> Option<LRIT> {
+        (!lrit.replay && self.is_replay(lrit, now)).then(|| LRIT {
+            replay: true,
+            ..lrit.clone()
+        })
+    }
+
+    /// Runs a replay through the replay handlers, if there are any
+    pub(crate) fn dispatch(&mut self, lrit: &LRIT) -> Option<Vec<HandlerFailure>> {
+        Some(self.handlers.as_mut()?.dispatch(lrit))
+    }
+
+    pub(crate) fn flush(&mut self) -> Vec<HandlerFailure> {
+        self.handlers.as_mut().map(|h| h.flush()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::ReplayFilter;
+    use crate::{
+        handlers::{Dispatcher, Handler, HandlerError},
+        lrit::LRIT,
+        sim::LritBuilder,
+    };
+
+    struct Names(Arc<Mutex<Vec<(String, bool)>>>);
+
+    impl Handler for Names {
+        fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+            let name = lrit.headers.annotation.as_ref().unwrap().text.clone();
+            self.0.lock().unwrap().push((name, lrit.replay));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replay_filter() {
+        let image = |name: &str| LRIT::from_bytes(13, &LritBuilder::new(0).annotation(name).build(b"")).unwrap();
+        let now = Utc.with_ymd_and_hms(2022, 5, 4, 18, 20, 0).unwrap();
+        let live = image("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit");
+        let stored = image("OR_ABI-L2-CMIPF-M6C13_G16_s20221241400205_e20221241409513_c20221241409580.lrit");
+        let mut flagged = live.clone();
+        flagged.replay = true;
+
+        let filter = ReplayFilter::new();
+        assert!(!filter.is_replay(&live, now));
+        assert!(!filter.is_replay(&stored, now));
+        assert!(filter.is_replay(&flagged, now));
+        let filter = ReplayFilter::new().with_max_age(std::time::Duration::from_secs(3600));
+        assert!(!filter.is_replay(&live, now));
+        assert!(filter.mark(&stored, now).unwrap().replay);
+        // it's already marked
+        assert!(filter.mark(&flagged, now).is_none());
+
+        // replays go to their own handlers
+        let (usual, replays) = (Arc::new(Mutex::new(Vec::new())), Arc::new(Mutex::new(Vec::new())));
+        let mut replay_handlers = Dispatcher::new();
+        replay_handlers.push(Box::new(Names(replays.clone())));
+        let mut dispatcher = Dispatcher::new().with_replay_filter(ReplayFilter::new().with_handlers(replay_handlers));
+        dispatcher.push(Box::new(Names(usual.clone())));
+        for lrit in [&live, &stored, &flagged] {
+            assert!(dispatcher.dispatch(lrit).is_empty());
+        }
+        let name = |lrit: &LRIT| lrit.headers.annotation.as_ref().unwrap().text.clone();
+        assert_eq!(*usual.lock().unwrap(), [(name(&live), false), (name(&stored), false)]);
+        assert_eq!(*replays.lock().unwrap(), [(name(&flagged), true)]);
+    }
+}
@@ -0,0 +1,222 @@
+//! Daily product completeness reports
+//!
+//! Many products are broadcast on a predictable schedule (full disk imagery every 10 minutes,
+//! hourly EMWIN products, etc).  By comparing the [`ProductIndex`](crate::index::ProductIndex)
+//! against these expectations, we can find gaps in reception, which usually point to downtime or
+//! antenna problems.
+use std::{collections::HashSet, io::Write};
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::index::IndexRecord;
+
+/// A product that is expected to be received on a regular interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Expectation {
+    /// Human readable name, used in the report
+    pub name: String,
+    /// Any product whose annotation contains this string counts towards this expectation
+    pub pattern: String,
+    /// How often this product is expected to be received, in minutes
+    pub interval_minutes: u32,
+}
+
+impl Expectation {
+    pub fn new(name: impl Into<String>, pattern: impl Into<String>, interval_minutes: u32) -> Expectation {
+        Expectation {
+            name: name.into(),
+            pattern: pattern.into(),
+            interval_minutes,
+        }
+    }
+}
+
+/// A reasonable default schedule for the GOES-East HRIT feed
+pub fn default_expectations() -> Vec<Expectation> {
+    let mut exp: Vec<Expectation> = [2, 7, 8, 9, 13, 14, 15]
+        .iter()
+        .map(|band| {
+            Expectation::new(
+                format!("ABI full disk band {}", band),
+                format!("CMIPF-M6C{:02}", band),
+                10,
+            )
+        })
+        .collect();
+    exp.push(Expectation::new("EMWIN regional weather roundups", "-RWR", 60));
+    exp
+}
+
+#[derive(Debug)]
+pub struct ExpectationResult {
+    pub name: String,
+    /// How many products we should have received
+    pub expected: usize,
+    /// How many distinct products were actually received
+    pub received: usize,
+    /// Periods of time (longer than the expected interval) where nothing was received
+    pub gaps: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl ExpectationResult {
+    /// Percent of expected products that were received (capped at 100)
+    pub fn completeness(&self) -> f32 {
+        if self.expected == 0 {
+            return 100.0;
+        }
+        100.0 * self.received.min(self.expected) as f32 / self.expected as f32
+    }
+}
+
+#[derive(Debug)]
+pub struct CompletenessReport {
+    pub date: NaiveDate,
+    pub results: Vec<ExpectationResult>,
+}
+
+impl CompletenessReport {
+    /// Compare the records from one day against a list of expectations
+    pub fn generate(date: NaiveDate, records: &[IndexRecord], expectations: &[Expectation]) -> CompletenessReport {
+        let day_start = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).expect("midnight"));
+        let day_end = day_start + Duration::days(1);
+
+        let results = expectations
+            .iter()
+            .map(|exp| {
+                let interval = Duration::minutes(exp.interval_minutes as i64);
+                // allow some slack before calling something a gap
+                let tolerance = interval + interval / 2;
+
+                // segmented products will show up in the index once per segment, so only count
+                // the first time we see each annotation
+                let mut seen = HashSet::new();
+                let mut times: Vec<_> = records
+                    .iter()
+                    .filter(|r| r.received >= day_start && r.received < day_end)
+                    .filter(|r| r.annotation.contains(&exp.pattern))
+                    .filter(|r| seen.insert(r.annotation.as_str()))
+                    .map(|r| r.received)
+                    .collect();
+                times.sort();
+
+                let mut gaps = Vec::new();
+                let mut cursor = day_start;
+                for t in times.iter().copied().chain(std::iter::once(day_end)) {
+                    if t - cursor > tolerance {
+                        gaps.push((cursor, t));
+                    }
+                    cursor = t;
+                }
+
+                let expected = (24 * 60u32).checked_div(exp.interval_minutes).unwrap_or(0) as usize;
+
+                ExpectationResult {
+                    name: exp.name.clone(),
+                    expected,
+                    received: times.len(),
+                    gaps,
+                }
+            })
+            .collect();
+
+        CompletenessReport { date, results }
+    }
+
+    /// Writes a human readable version of this report
+    pub fn write(&self, w: &mut impl Write) -> std::io::Result<()> {
+        writeln!(w, "Product completeness report for {}", self.date)?;
+        writeln!(w)?;
+        for res in &self.results {
+            writeln!(
+                w,
+                "{}: {} of {} received ({:.1}%)",
+                res.name,
+                res.received,
+                res.expected,
+                res.completeness()
+            )?;
+            for (start, end) in &res.gaps {
+                writeln!(
+                    w,
+                    "    gap {} - {} ({} minutes)",
+                    start.format("%H:%M"),
+                    end.format("%H:%M"),
+                    (*end - *start).num_minutes()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, TimeZone, Utc};
+
+    use super::{CompletenessReport, Expectation};
+    use crate::index::IndexRecord;
+
+    fn record(date: NaiveDate, minutes: i64, annotation: &str) -> IndexRecord {
+        IndexRecord {
+            received: Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap()) + Duration::minutes(minutes),
+            vcid: 13,
+            filetype_code: 0,
+            product_id: None,
+            annotation: annotation.to_string(),
+            archive: None,
+            segments: None,
+            sector: None,
+            decompression: None,
+            timing: None,
+            duplicate: None,
+            collision: None,
+            suspect: None,
+            decoder_version: None,
+            build: None,
+            admin: None,
+            replay: false,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let date = NaiveDate::from_ymd_opt(2022, 5, 4).unwrap();
+        let mut records = Vec::new();
+        for i in 0..144 {
+            // skip 2 hours in the middle of the day
+            if (60..72).contains(&i) {
+                continue;
+            }
+            let ann = format!("OR_ABI-L2-CMIPF-M6C13_G16_s{:04}", i);
+            // each image arrives in several segments
+            records.push(record(date, i * 10 + 1, &ann));
+            records.push(record(date, i * 10 + 2, &ann));
+        }
+        records.push(record(date, 5, "unrelated"));
+
+        let exp = vec![Expectation::new("band 13", "CMIPF-M6C13", 10)];
+        let report = CompletenessReport::generate(date, &records, &exp);
+        let res = &report.results[0];
+        assert_eq!(res.expected, 144);
+        assert_eq!(res.received, 132);
+        assert_eq!(res.gaps.len(), 1);
+        assert_eq!((res.gaps[0].1 - res.gaps[0].0).num_minutes(), 130);
+
+        let mut out = Vec::new();
+        report.write(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("band 13: 132 of 144 received (91.7%)"));
+    }
+
+    #[test]
+    fn test_nothing_received() {
+        let date = NaiveDate::from_ymd_opt(2022, 5, 4).unwrap();
+        let exp = vec![Expectation::new("band 2", "CMIPF-M6C02", 10)];
+        let report = CompletenessReport::generate(date, &[], &exp);
+        let res = &report.results[0];
+        assert_eq!(res.received, 0);
+        assert_eq!(res.gaps.len(), 1);
+        assert_eq!((res.gaps[0].1 - res.gaps[0].0).num_hours(), 24);
+    }
+}
@@ -0,0 +1,279 @@
+//! Products that are expected on a schedule
+//!
+//! Most NWS text products go out at set times, like an Area Forecast Discussion every six hours.
+//! A [`Schedule`] lists the products that should be arriving (by AWIPS ID, like `AFDPHI`, or WMO
+//! heading, like `FXUS61 KPHI`) with a cron-like [`Cadence`], remembers when each one was last
+//! received, and reports the ones that are [overdue](Schedule::overdue).
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::annotation::LritFilename;
+
+/// How far back [`Cadence::previous`] looks for a scheduled time
+const MAX_LOOKBACK_DAYS: i64 = 366;
+
+/// When a product is expected, as a 5 field cron expression (in UTC)
+///
+/// The fields are minute, hour, day of month, month, and day of week (0 is Sunday).  Each field
+/// is `*`, a number, a range like `1-5`, a step like `*/6` or `0-30/10`, or a list of those
+/// separated by commas.  Like cron, if both the day of month and day of week are restricted, a day
+/// matching either one is a match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cadence {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cadence {
+    pub fn parse(s: &str) -> Result<Cadence, String> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("{:?} should have 5 fields (minute hour day month weekday)", s));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 is also Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Cadence {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches_day(&self, date: DateTime<Utc>) -> bool {
+        if !self.months[date.month() as usize] {
+            return false;
+        }
+        let day = self.days[date.day() as usize];
+        let weekday = self.weekdays[date.weekday().num_days_from_sunday() as usize];
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The latest scheduled time at or before `time`, if there was one in the last year
+    pub fn previous(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let midnight = Utc
+            .timestamp_opt(time.timestamp() - time.timestamp().rem_euclid(86400), 0)
+            .single()?;
+        for days_back in 0..MAX_LOOKBACK_DAYS {
+            let day = midnight - ChronoDuration::days(days_back);
+            if !self.matches_day(day) {
+                continue;
+            }
+            // on the first day, only times up to `time` count
+            let (last_hour, last_minute) = if days_back == 0 {
+                (time.hour(), time.minute())
+            } else {
+                (23, 59)
+            };
+            for hour in (0..=last_hour).rev().filter(|h| self.hours[*h as usize]) {
+                let last_minute = if hour == last_hour { last_minute } else { 59 };
+                if let Some(minute) = (0..=last_minute).rev().find(|m| self.minutes[*m as usize]) {
+                    return Some(day + ChronoDuration::minutes((hour * 60 + minute) as i64));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses one cron field into a table of which values (from 0 to `max`) match
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut matches = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| format!("bad step in {:?}", field))?,
+            ),
+            None => (part, 1),
+        };
+        let number = |s: &str| {
+            s.parse::<u32>()
+                .ok()
+                .filter(|n| (min..=max).contains(n))
+                .ok_or_else(|| format!("{:?} should be a number from {} to {}", s, min, max))
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                // like cron, `5/10` means starting at 5
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        for value in (start..=end).step_by(step as usize) {
+            matches[value as usize] = true;
+        }
+    }
+    Ok(matches)
+}
+
+/// A product that's expected on a schedule
+#[derive(Debug, Clone)]
+pub struct Expected {
+    /// An AWIPS ID (like `AFDPHI`) or WMO heading (like `FXUS61 KPHI`)
+    pub product: String,
+    pub cadence: Cadence,
+    /// How late a product can be before it's overdue
+    pub grace: Duration,
+}
+
+impl Expected {
+    /// Whether a product with this AWIPS ID and WMO heading is this one
+    fn matches(&self, awips_id: &str, heading: &str) -> bool {
+        let product = self.product.to_ascii_uppercase();
+        product == awips_id || product.replace(' ', "") == heading.replace(' ', "")
+    }
+}
+
+/// A product that hasn't arrived when it should have
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overdue {
+    pub product: String,
+    /// The scheduled time that was missed
+    pub expected: DateTime<Utc>,
+    /// When the product was last received, if it has been since goesbox started
+    pub last_received: Option<DateTime<Utc>>,
+}
+
+/// Expected products, and when they were last received; see the [module docs](self)
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    expected: Vec<Expected>,
+    /// When each expected product was last received, keyed by [`Expected::product`]
+    last_received: HashMap<String, DateTime<Utc>>,
+    /// Products that were due before this aren't reported as overdue
+    started: DateTime<Utc>,
+}
+
+impl Schedule {
+    /// An empty schedule, that starts watching at `started`
+    pub fn new(started: DateTime<Utc>) -> Schedule {
+        Schedule {
+            expected: Vec::new(),
+            last_received: HashMap::new(),
+            started,
+        }
+    }
+
+    /// Adds a product to watch for
+    pub fn expect(&mut self, expected: Expected) {
+        self.expected.push(expected);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expected.is_empty()
+    }
+
+    /// Notes that a product arrived, given its annotation (filename)
+    ///
+    /// Only EMWIN text products are recognized.
+    pub fn received(&mut self, annotation: &str, time: DateTime<Utc>) {
+        let emwin = match LritFilename::parse(annotation) {
+            LritFilename::Emwin(emwin) => emwin,
+            _ => return,
+        };
+        // like `FXUS61KPHI` and `AFDPHI`
+        let heading = &annotation[2..12];
+        let awips_id = emwin.legacy_filename.get(..6).unwrap_or_default().trim_end_matches('_');
+        for expected in &self.expected {
+            if expected.matches(awips_id, heading) {
+                self.last_received.insert(expected.product.clone(), time);
+            }
+        }
+    }
+
+    /// Products that missed their last scheduled time (plus the grace period), as of `now`
+    pub fn overdue(&self, now: DateTime<Utc>) -> Vec<Overdue> {
+        self.expected
+            .iter()
+            .filter_map(|expected| {
+                let grace = ChronoDuration::from_std(expected.grace).ok()?;
+                let due = expected.cadence.previous(now - grace)?;
+                let last_received = self.last_received.get(&expected.product).copied();
+                // a product received up to the grace period early still counts
+                if due - grace <= last_received.unwrap_or(self.started) {
+                    return None;
+                }
+                Some(Overdue {
+                    product: expected.product.clone(),
+                    expected: due,
+                    last_received,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{Cadence, Expected, Schedule};
+
+    #[test]
+    fn test_schedule() {
+        // 2022-05-07, a Saturday
+        let t = |h: i64, m: i64| Utc.timestamp_opt(1651881600 + h * 3600 + m * 60, 0).unwrap();
+        let every_6_hours = Cadence::parse("30 */6 * * *").unwrap();
+        assert_eq!(every_6_hours.previous(t(13, 0)), Some(t(12, 30)));
+        assert_eq!(every_6_hours.previous(t(12, 30)), Some(t(12, 30)));
+        assert_eq!(
+            every_6_hours.previous(t(0, 10)),
+            Some(t(0, 30) - chrono::Duration::hours(6))
+        );
+        let weekdays = Cadence::parse("0 12 * * 1-5").unwrap();
+        assert_eq!(weekdays.previous(t(13, 0)), Some(t(12, 0) - chrono::Duration::days(1)));
+        assert!(Cadence::parse("0 24 * * *").is_err());
+        assert!(Cadence::parse("0 12 * *").is_err());
+
+        let mut schedule = Schedule::new(t(6, 0));
+        schedule.expect(Expected {
+            product: "AFDPHI".to_string(),
+            cadence: every_6_hours,
+            grace: Duration::from_secs(15 * 60),
+        });
+        schedule.expect(Expected {
+            product: "FTUS80 KWBC".to_string(),
+            cadence: Cadence::parse("0 * * * *").unwrap(),
+            grace: Duration::from_secs(10 * 60),
+        });
+        // nothing was due yet since starting
+        assert!(schedule.overdue(t(6, 5)).is_empty());
+
+        schedule.received("A_FTUS80KWBC070600_C_KWIN_20220507060104_839346-2-TAFALLUS", t(6, 1));
+        schedule.received("A_FXUS61KPHI070620_C_KWIN_20220507062013_881367-3-AFDPHIPA", t(6, 20));
+        assert!(schedule.overdue(t(6, 50)).is_empty());
+        let overdue = schedule.overdue(t(7, 15));
+        assert_eq!(overdue.len(), 1);
+        assert_eq!(overdue[0].product, "FTUS80 KWBC");
+        assert_eq!(overdue[0].expected, t(7, 0));
+        assert_eq!(overdue[0].last_received, Some(t(6, 1)));
+
+        let overdue = schedule.overdue(t(12, 50));
+        assert_eq!(overdue.len(), 2);
+        assert_eq!(overdue[0].expected, t(12, 30));
+    }
+}
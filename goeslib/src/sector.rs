@@ -0,0 +1,106 @@
+//! Mesoscale sectors
+//!
+//! The two ABI mesoscale sectors are moved around to follow the weather, so two `CMIPM1` images
+//! can show completely different places.  A [`Sector`] identifies where a mesoscale image is, by
+//! the latitude and longitude of its center (worked out from the image navigation header), so
+//! images of the same place can be kept together and looped without mixing in other places.
+use crate::{annotation::LritFilename, lrit::LRIT};
+
+/// Where a mesoscale image is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sector {
+    /// "M1" or "M2"
+    pub region: String,
+    /// Latitude of the image center, in degrees north
+    pub lat: f64,
+    /// Longitude of the image center, in degrees east
+    pub lon: f64,
+}
+
+impl Sector {
+    /// Works out the sector of a mesoscale image (or one segment of it)
+    ///
+    /// Returns `None` for other images, or if the navigation header is missing or doesn't use the
+    /// geostationary projection.
+    pub fn from_lrit(lrit: &LRIT) -> Option<Sector> {
+        let region = match lrit.headers.annotation.as_ref()?.parsed() {
+            LritFilename::GoesR(goes) if goes.product.ends_with("M1") => "M1",
+            LritFilename::GoesR(goes) if goes.product.ends_with("M2") => "M2",
+            _ => return None,
+        };
+        let nav = lrit.headers.img_navigation.as_ref()?;
+        // like the XMP metadata, the navigation header is taken to describe the whole image
+        let (columns, lines) = match (&lrit.headers.img_segment, &lrit.headers.img_strucutre) {
+            (Some(seg), _) => (seg.max_column, seg.max_row),
+            (None, Some(ihs)) => (ihs.num_columns, ihs.num_lines),
+            (None, None) => return None,
+        };
+        let (lat, lon) = nav.pixel_to_latlon(columns as f64 / 2.0, lines as f64 / 2.0)?;
+        Some(Sector {
+            region: region.to_string(),
+            lat,
+            lon,
+        })
+    }
+
+    /// A name for this sector, like `M1_35.2N_097.5W`
+    ///
+    /// The center is rounded to a tenth of a degree, so every image of a sector gets the same name
+    /// until it's moved.
+    pub fn key(&self) -> String {
+        format!(
+            "{}_{:04.1}{}_{:05.1}{}",
+            self.region,
+            self.lat.abs(),
+            if self.lat < 0.0 { 'S' } else { 'N' },
+            self.lon.abs(),
+            if self.lon < 0.0 { 'W' } else { 'E' }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sector;
+    use crate::{
+        lrit::{geos_to_lat_lon, ImageNavigationRecord, LRIT},
+        sim::LritBuilder,
+    };
+
+    fn meso(annotation: &str, column_offset: i32, line_offset: i32) -> LRIT {
+        let bytes = LritBuilder::new(0)
+            .image_structure(8, 500, 500)
+            .annotation(annotation)
+            .build(&[0; 16]);
+        let mut lrit = LRIT::from_bytes(1, &bytes).unwrap();
+        lrit.headers.img_navigation = Some(ImageNavigationRecord {
+            header_type: 2,
+            header_record_lenth: 51,
+            projection_name: "GEOS(-75.0)".to_string(),
+            column_scaling_factor: 40932549,
+            line_scaling_factor: 40932549,
+            column_offset,
+            line_offset,
+        });
+        lrit
+    }
+
+    #[test]
+    fn test_sector() {
+        let (lat, lon) = geos_to_lat_lon(0.0, 0.0, -75.0).unwrap();
+        assert!(lat.abs() < 1e-9 && (lon + 75.0).abs() < 1e-9);
+        // looking north and east of the sub-satellite point
+        let (lat, lon) = geos_to_lat_lon(2f64.to_radians(), (-5f64).to_radians(), -75.0).unwrap();
+        assert!(lat > 30.0 && lon > -75.0, "{} {}", lat, lon);
+        assert!(geos_to_lat_lon(0.2, 0.0, -75.0).is_none());
+
+        let name = "OR_ABI-L2-CMIPM1-M6C02_G16_s20221241800205.lrit";
+        let centered = Sector::from_lrit(&meso(name, 250, 250)).unwrap();
+        assert_eq!(centered.key(), "M1_00.0N_075.0W");
+        let moved = Sector::from_lrit(&meso(name, 250 - 1200, 250 + 3000)).unwrap();
+        assert!(moved.lat > 20.0 && moved.lon > -75.0, "{:?}", moved);
+        assert_ne!(moved.key(), centered.key());
+
+        assert!(Sector::from_lrit(&meso("OR_ABI-L2-CMIPF-M6C02_G16_s20221241800205.lrit", 250, 250)).is_none());
+    }
+}
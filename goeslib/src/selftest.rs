@@ -0,0 +1,335 @@
+//! Checking that a build decodes correctly on this platform
+//!
+//! [`run`] sends known-good vectors, built into the library, through the decoder, the parsers,
+//! and the handlers, and compares what comes out against what it's known to be: CRCs, header
+//! fields, and the CRC-32 of every file the handlers write.  These are the same captures the
+//! tests use (from `testdata`), so a failure here that the tests don't show points to the
+//! platform (a different endianness, a broken compression library, a filesystem that mangles
+//! names) rather than the code.
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::Path,
+};
+
+use chrono::Utc;
+
+use crate::{
+    capture::CaptureReader,
+    crc::{calc_crc16, calc_crc32},
+    handlers::{DcsBlock, DcsHandler, DcsHeader, Dispatcher, Handler, RawLritHandler, RetryPolicy, TextHandler},
+    lrit::{VirtualChannel, LRIT, VCDU},
+    reparse::{check_name, NameCheck},
+    stats::Stats,
+};
+
+/// A minute of simulated downlink, with text products, DCS files, and one small image
+const CAPTURE: &[u8] = include_bytes!("../testdata/replay/simulated.vcdu");
+/// The files the text and raw LRIT handlers write for [`CAPTURE`]: the CRC-32, size, and path of
+/// each, with `YYYYMMDD` in place of the day they were received
+const PRODUCTS: &str = include_str!("../testdata/replay/simulated.products");
+
+/// One of the checks, and how it went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    /// What was checked if it passed, or what was wrong if it didn't
+    pub detail: String,
+    pub passed: bool,
+}
+
+/// Runs every check, writing the handlers' output under `dir` (which should be empty)
+pub fn run(dir: impl AsRef<Path>) -> Vec<Check> {
+    let dir = dir.as_ref();
+    let mut checks = Vec::new();
+    let mut lrits = Vec::new();
+    check(&mut checks, "decoder", || {
+        lrits = decode()?;
+        Ok(format!("{} LRIT files", lrits.len()))
+    });
+    check(&mut checks, "LRIT headers", || check_headers(&lrits));
+    check(&mut checks, "EMWIN names", || check_emwin(&lrits));
+    check(&mut checks, "DCS", || check_dcs(&lrits, &dir.join("dcs")));
+    check(&mut checks, "products", || {
+        check_products(&lrits, &dir.join("products"))
+    });
+    #[cfg(feature = "image")]
+    check(&mut checks, "images", || check_image(&dir.join("images")));
+    checks
+}
+
+/// Runs one check, counting a panic as a failure
+fn check(checks: &mut Vec<Check>, name: &'static str, f: impl FnOnce() -> Result<String, String>) {
+    let result = catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| Err("panicked".to_string()));
+    let passed = result.is_ok();
+    checks.push(Check {
+        name,
+        detail: result.unwrap_or_else(|e| e),
+        passed,
+    });
+}
+
+/// The lines of [`PRODUCTS`]
+fn expected_products() -> impl Iterator<Item = (u32, usize, &'static str)> {
+    PRODUCTS.lines().filter_map(|line| {
+        let mut parts = line.splitn(3, ' ');
+        let crc = u32::from_str_radix(parts.next()?, 16).ok()?;
+        let size = parts.next()?.parse().ok()?;
+        Some((crc, size, parts.next()?))
+    })
+}
+
+/// Decodes [`CAPTURE`], which should come through without a single error
+fn decode() -> Result<Vec<LRIT>, String> {
+    let mut stats = Stats::new();
+    let mut channels: HashMap<u8, VirtualChannel> = HashMap::new();
+    let mut lrits = Vec::new();
+    for frame in CaptureReader::new(CAPTURE) {
+        let frame = frame.map_err(|e| e.to_string())?;
+        let vcdu = VCDU::new(&frame);
+        if vcdu.is_fill() {
+            continue;
+        }
+        let (id, counter) = (vcdu.vcid(), vcdu.counter());
+        let channel = channels.entry(id).or_insert_with(|| VirtualChannel::new(id, counter));
+        lrits.extend(channel.process_vcdu(vcdu, &mut stats));
+    }
+    if stats.corrupt_packets + stats.decoder_errors + stats.decompression_errors > 0 {
+        return Err(format!(
+            "{} corrupt packets, {} decoder errors, {} decompression errors",
+            stats.corrupt_packets, stats.decoder_errors, stats.decompression_errors
+        ));
+    }
+    // the raw LRIT handler writes one file for each name (the segments of an image share one)
+    let names: HashSet<&str> = lrits.iter().map(annotation).collect();
+    let expected = expected_products()
+        .filter(|(_, _, path)| path.starts_with("raw/"))
+        .count();
+    if names.len() != expected {
+        return Err(format!("{} LRIT files were named, expected {}", names.len(), expected));
+    }
+    Ok(lrits)
+}
+
+fn annotation(lrit: &LRIT) -> &str {
+    lrit.headers
+        .annotation
+        .as_ref()
+        .map_or("(no annotation)", |a| a.text.as_str())
+}
+
+/// Checks the primary header of each LRIT file against its data, and that each has an annotation
+fn check_headers(lrits: &[LRIT]) -> Result<String, String> {
+    for lrit in lrits {
+        let primary = &lrit.headers.primary;
+        if primary.header_type != 0 || primary.header_record_lenth != 16 {
+            return Err(format!("{}: bad primary header {:?}", annotation(lrit), primary));
+        }
+        if primary.total_header_length as usize != lrit.raw_headers.len() {
+            return Err(format!(
+                "{}: {} header bytes, the primary header says {}",
+                annotation(lrit),
+                lrit.raw_headers.len(),
+                primary.total_header_length
+            ));
+        }
+        if primary.data_field_bits != lrit.data.len() as u64 * 8 {
+            return Err(format!(
+                "{}: {} data bytes, the primary header says {} bits",
+                annotation(lrit),
+                lrit.data.len(),
+                primary.data_field_bits
+            ));
+        }
+        if lrit.headers.annotation.is_none() {
+            return Err(format!("a file of type {} has no annotation", primary.filetype_code));
+        }
+    }
+    Ok(format!("{} LRIT files", lrits.len()))
+}
+
+/// Parses the name of each EMWIN text product
+fn check_emwin(lrits: &[LRIT]) -> Result<String, String> {
+    let mut parsed = 0;
+    for lrit in lrits.iter().filter(|lrit| lrit.headers.primary.filetype_code == 2) {
+        let name = annotation(lrit);
+        match check_name(name) {
+            NameCheck::Parsed(emwin) if emwin.nws_product.is_some() => parsed += 1,
+            NameCheck::Parsed(_) => return Err(format!("{}: unknown NWS product", name)),
+            NameCheck::UnknownT2(t1t2) => return Err(format!("{}: unknown T2 code in {}", name, t1t2)),
+            NameCheck::UnknownArea(ttaa) => return Err(format!("{}: unknown area in {}", name, ttaa)),
+            NameCheck::Failed(error) => return Err(format!("{}: {}", name, error)),
+        }
+    }
+    if parsed == 0 {
+        return Err("no EMWIN products".to_string());
+    }
+    Ok(format!("{} names", parsed))
+}
+
+/// Checks the file and block CRCs of each DCS file (which the parser only warns about), and
+/// runs them through the DCS handler
+fn check_dcs(lrits: &[LRIT], dir: &Path) -> Result<String, String> {
+    let mut handler = DcsHandler::new(dir);
+    let (mut files, mut blocks) = (0, 0);
+    for lrit in lrits.iter().filter(|lrit| lrit.headers.primary.filetype_code == 130) {
+        let (name, data) = (annotation(lrit), &lrit.data);
+        let header = DcsHeader::parse(data).map_err(|e| format!("{}: {}", name, e))?;
+        if header.payload_type != "DCSH" {
+            return Err(format!("{}: payload type {:?}", name, header.payload_type));
+        }
+        if calc_crc32(&data[..60]) != header.header_crc || calc_crc32(&data[..data.len() - 4]) != header.file_crc {
+            return Err(format!("{}: CRC mismatch", name));
+        }
+        // each block ends in a CRC-16 of the rest of it
+        let mut offset = 64;
+        while offset + 3 <= data.len() - 4 {
+            let len = u16::from_le_bytes([data[offset + 1], data[offset + 2]]) as usize;
+            let block = data.get(offset..offset + len).filter(|_| len >= 5);
+            let block = block.ok_or_else(|| format!("{}: truncated block at {}", name, offset))?;
+            let (body, crc) = block.split_at(len - 2);
+            if block[0] == 1 && calc_crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+                return Err(format!("{}: block CRC mismatch at {}", name, offset));
+            }
+            blocks += 1;
+            offset += len;
+        }
+        let parsed = DcsBlock::parse(&data[64..]).map_err(|e| format!("{}: {}", name, e))?;
+        if parsed.is_empty() {
+            return Err(format!("{}: no messages", name));
+        }
+        handler.handle(lrit).map_err(|e| format!("{}: {}", name, e))?;
+        files += 1;
+    }
+    if files == 0 {
+        return Err("no DCS files".to_string());
+    }
+    Ok(format!("{} files, {} blocks", files, blocks))
+}
+
+/// Writes the text products and raw LRIT files under `dir`, and compares them to [`PRODUCTS`]
+fn check_products(lrits: &[LRIT], dir: &Path) -> Result<String, String> {
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut handlers = Dispatcher::new().with_retry_policy(RetryPolicy::none());
+    handlers.push(Box::new(TextHandler::new(dir)));
+    handlers.push(Box::new(RawLritHandler::new(dir)));
+    for lrit in lrits {
+        if let Some(failure) = handlers.dispatch(lrit).first() {
+            return Err(format!(
+                "{}: {} failed: {}",
+                annotation(lrit),
+                failure.handler,
+                failure.error
+            ));
+        }
+    }
+    if let Some(failure) = handlers.flush().first() {
+        return Err(format!("{} failed: {}", failure.handler, failure.error));
+    }
+
+    // raw LRIT files go in a directory named after the day they were received
+    let today = Utc::now().format("%Y%m%d").to_string();
+    let mut written = HashMap::new();
+    for path in list_files(dir).map_err(|e| format!("{}: {}", dir.display(), e))? {
+        let data = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace(&today, "YYYYMMDD");
+        written.insert(name, (calc_crc32(&data), data.len()));
+    }
+    let mut count = 0;
+    for (crc, size, path) in expected_products() {
+        match written.remove(path) {
+            Some(found) if found == (crc, size) => count += 1,
+            Some((found_crc, found_size)) => {
+                return Err(format!(
+                    "{}: CRC {:08x} and {} bytes, expected {:08x} and {} bytes",
+                    path, found_crc, found_size, crc, size
+                ))
+            }
+            None => return Err(format!("{} wasn't written", path)),
+        }
+    }
+    if let Some(extra) = written.keys().next() {
+        return Err(format!("{} was written, but not expected", extra));
+    }
+    Ok(format!("{} files", count))
+}
+
+/// Every file under `dir`, however deep (but not the "latest" symlinks, which only have to point
+/// at one of them)
+fn list_files(dir: &Path) -> io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let (path, file_type) = (entry.path(), entry.file_type()?);
+        if file_type.is_symlink() {
+            std::fs::metadata(&path)?;
+        } else if file_type.is_dir() {
+            files.extend(list_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Assembles a segmented image under `dir`, and compares it to the image it should be (allowing
+/// for JPEG noise, since the encoder might not be the same everywhere)
+#[cfg(feature = "image")]
+fn check_image(dir: &Path) -> Result<String, String> {
+    const SEGMENTS: [&[u8]; 4] = [
+        include_bytes!("../testdata/segmented/0.lrit"),
+        include_bytes!("../testdata/segmented/1.lrit"),
+        include_bytes!("../testdata/segmented/2.lrit"),
+        include_bytes!("../testdata/segmented/3.lrit"),
+    ];
+    const GOLDEN: &[u8] = include_bytes!("../testdata/segmented/golden.png");
+
+    std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut handler = crate::handlers::ImageHandler::new(dir);
+    let mut out = None;
+    for segment in SEGMENTS {
+        let lrit = LRIT::from_bytes(13, segment).map_err(|e| format!("{:?}", e))?;
+        out = Some(dir.join(annotation(&lrit)).with_extension("jpg"));
+        handler.handle(&lrit).map_err(|e| e.to_string())?;
+    }
+    let out = out.unwrap_or_default();
+    let image = image::open(&out)
+        .map_err(|e| format!("{}: {}", out.display(), e))?
+        .to_luma8();
+    let golden = image::load_from_memory(GOLDEN).map_err(|e| e.to_string())?.to_luma8();
+    if image.dimensions() != golden.dimensions() {
+        return Err(format!("{:?}, expected {:?}", image.dimensions(), golden.dimensions()));
+    }
+    let diffs: Vec<u32> = (image.pixels().zip(golden.pixels()))
+        .map(|(a, b)| (a.0[0] as i32 - b.0[0] as i32).unsigned_abs())
+        .collect();
+    let max = diffs.iter().copied().max().unwrap_or_default();
+    let mean = diffs.iter().sum::<u32>() as f64 / diffs.len() as f64;
+    if max > 24 || mean > 3.0 {
+        return Err(format!("max difference {}, mean difference {:.1}", max, mean));
+    }
+    Ok(format!(
+        "{}x{}, mean difference {:.1}",
+        image.width(),
+        image.height(),
+        mean
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_selftest() {
+        let dir = tempfile::tempdir().unwrap();
+        let checks = super::run(dir.path());
+        for check in &checks {
+            assert!(check.passed, "{}: {}", check.name, check.detail);
+        }
+        assert!(checks.iter().any(|check| check.name == "products"));
+    }
+}
@@ -0,0 +1,812 @@
+//! A synthetic HRIT downlink
+//!
+//! This module does the reverse of [`crate::lrit`]: it builds LRIT files, splits them into TP_PDUs,
+//! and packs those into VCDUs, exactly as the decoder expects to receive them.  Together with the
+//! [`Simulator`] (which generates a plausible mix of imagery, EMWIN text, and DCS files on a
+//! schedule), this makes it possible to run the whole application without an antenna.
+//!
+//! Ref: 4_LRIT_Transmitter-specs.pdf
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use chrono::{DateTime, Datelike, Duration, Timelike, Utc};
+
+use crate::{
+    crc,
+    lrit::{FILL_APID, PREAMBLE_LEN},
+};
+
+/// Size of a VCDU, in bytes
+pub const VCDU_LEN: usize = 892;
+
+/// Size of the M_PDU packet zone (the VCDU data, minus the 2 byte M_PDU header)
+const PACKET_ZONE_LEN: usize = 884;
+
+/// The largest TP_PDU user data field (not counting the 2 byte CRC)
+const MAX_TP_PDU_DATA: usize = 8190;
+
+/// The smallest TP_PDU we can make: a 6 byte header plus a 2 byte CRC
+const MIN_TP_PDU_LEN: usize = 8;
+
+/// Each 892 byte VCDU goes out as a 1024 byte CADU (sync marker and Reed-Solomon parity included)
+const CADU_LEN: usize = 1024;
+
+/// The GOES-R HRIT downlink rate, in bits per second
+pub const HRIT_BITS_PER_SECOND: u32 = crate::lrit::DownlinkMode::Hrit.bits_per_second();
+
+/// How long it takes to transmit a single VCDU at the given rate
+pub fn vcdu_interval(bits_per_second: u32) -> std::time::Duration {
+    std::time::Duration::from_secs_f64((CADU_LEN * 8) as f64 / bits_per_second as f64)
+}
+
+/// Builds the bytes of an LRIT file, one header at a time
+///
+/// The primary header is added by [`LritBuilder::build`], once the total header length is known.
+pub struct LritBuilder {
+    filetype_code: u8,
+    headers: Vec<u8>,
+}
+
+impl LritBuilder {
+    pub fn new(filetype_code: u8) -> LritBuilder {
+        LritBuilder {
+            filetype_code,
+            headers: Vec::new(),
+        }
+    }
+
+    fn record(mut self, header_type: u8, body: &[u8]) -> Self {
+        self.headers.push(header_type);
+        self.headers.extend_from_slice(&(body.len() as u16 + 3).to_be_bytes());
+        self.headers.extend_from_slice(body);
+        self
+    }
+
+    pub fn image_structure(self, bits_per_pixel: u8, columns: u16, lines: u16) -> Self {
+        let mut body = vec![bits_per_pixel];
+        body.extend_from_slice(&columns.to_be_bytes());
+        body.extend_from_slice(&lines.to_be_bytes());
+        body.push(0);
+        self.record(1, &body)
+    }
+
+    pub fn annotation(self, text: &str) -> Self {
+        self.record(4, text.as_bytes())
+    }
+
+    pub fn ancillary_text(self, text: &str) -> Self {
+        self.record(6, text.as_bytes())
+    }
+
+    /// Adds an image segment identification header
+    ///
+    /// `max_row` is the number of lines in the complete image
+    pub fn segment(
+        self,
+        image_id: u16,
+        seq: u16,
+        start_line: u16,
+        max_segment: u16,
+        max_column: u16,
+        max_row: u16,
+    ) -> Self {
+        let mut body = Vec::with_capacity(14);
+        for val in [image_id, seq, 0, start_line, max_segment, max_column, max_row] {
+            body.extend_from_slice(&val.to_be_bytes());
+        }
+        self.record(128, &body)
+    }
+
+    pub fn noaa(self, product_id: u16, product_subid: u16, parameter: u16, compression: u8) -> Self {
+        let mut body = b"NOAA".to_vec();
+        body.extend_from_slice(&product_id.to_be_bytes());
+        body.extend_from_slice(&product_subid.to_be_bytes());
+        body.extend_from_slice(&parameter.to_be_bytes());
+        body.push(compression);
+        self.record(129, &body)
+    }
+
+    pub fn rice_compression(self, flags: u16, pixels_per_block: u8, scanlines_per_packet: u8) -> Self {
+        let mut body = flags.to_be_bytes().to_vec();
+        body.extend_from_slice(&[pixels_per_block, scanlines_per_packet]);
+        self.record(131, &body)
+    }
+
+    /// Returns the complete LRIT file (headers followed by `data`)
+    pub fn build(self, data: &[u8]) -> Vec<u8> {
+        let total_header_len = 16 + self.headers.len();
+        let mut bytes = Vec::with_capacity(total_header_len + data.len());
+        bytes.push(0);
+        bytes.extend_from_slice(&16u16.to_be_bytes());
+        bytes.push(self.filetype_code);
+        bytes.extend_from_slice(&(total_header_len as u32).to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+        bytes.extend_from_slice(&self.headers);
+        bytes.extend_from_slice(data);
+        bytes
+    }
+}
+
+/// The TP_PDU stream for a single virtual channel
+#[derive(Default)]
+struct ChannelEncoder {
+    /// VCDU counter for the next VCDU on this channel
+    counter: u32,
+    /// TP_PDU bytes that haven't been put into a VCDU yet
+    stream: VecDeque<u8>,
+    /// Offsets into `stream` where a TP_PDU starts
+    starts: VecDeque<usize>,
+    /// The next sequence count, per APID
+    sequence: HashMap<u16, u16>,
+}
+
+impl ChannelEncoder {
+    fn push_tp_pdu(&mut self, apid: u16, flags: u8, data: &[u8]) {
+        let seq = self.sequence.entry(apid).or_insert(0);
+        // the length field counts the CRC, minus one
+        let len = (data.len() + 1) as u16;
+
+        self.starts.push_back(self.stream.len());
+        self.stream.extend([
+            (apid >> 8) as u8 & 0b111,
+            apid as u8,
+            flags << 6 | (*seq >> 8) as u8,
+            *seq as u8,
+        ]);
+        self.stream.extend(len.to_be_bytes());
+        self.stream.extend(data);
+        self.stream.extend(crc::calc_crc16(data).to_be_bytes());
+
+        *seq = (*seq + 1) & 0x3fff;
+    }
+
+    /// Splits an LRIT file into TP_PDUs
+    fn push_file(&mut self, apid: u16, file: &[u8]) {
+        // the primary header comes after a few bytes of garbage
+        let mut payload = vec![0; PREAMBLE_LEN];
+        payload.extend_from_slice(file);
+        self.push_packets(apid, payload.chunks(MAX_TP_PDU_DATA));
+    }
+
+    /// Sends one file as the given TP_PDUs (the first of which must start with the preamble)
+    fn push_packets<'a>(&mut self, apid: u16, packets: impl ExactSizeIterator<Item = &'a [u8]>) {
+        let num_chunks = packets.len();
+        for (idx, chunk) in packets.enumerate() {
+            let flags = match (idx == 0, idx + 1 == num_chunks) {
+                (true, true) => 3,
+                (true, false) => 1,
+                (false, false) => 0,
+                (false, true) => 2,
+            };
+            self.push_tp_pdu(apid, flags, chunk);
+        }
+    }
+
+    /// Pads the stream with a fill TP_PDU, so that it ends on a VCDU boundary
+    fn pad(&mut self) {
+        let rem = self.stream.len() % PACKET_ZONE_LEN;
+        if rem == 0 {
+            return;
+        }
+        let mut fill = PACKET_ZONE_LEN - rem;
+        if fill < MIN_TP_PDU_LEN {
+            // too small for a TP_PDU, so spill over into the next VCDU
+            fill += PACKET_ZONE_LEN;
+        }
+        self.push_tp_pdu(FILL_APID, 3, &vec![0; fill - MIN_TP_PDU_LEN]);
+    }
+
+    fn next_vcdu(&mut self, scid: u8, vcid: u8) -> [u8; VCDU_LEN] {
+        if self.stream.len() < PACKET_ZONE_LEN {
+            self.pad();
+        }
+
+        // the first header pointer is the offset to the first TP_PDU that starts in this VCDU
+        let mut first_header = FILL_APID as usize;
+        while let Some(&start) = self.starts.front() {
+            if start >= PACKET_ZONE_LEN {
+                break;
+            }
+            first_header = first_header.min(start);
+            self.starts.pop_front();
+        }
+        for start in self.starts.iter_mut() {
+            *start -= PACKET_ZONE_LEN;
+        }
+
+        let mut vcdu = vcdu_header(scid, vcid, self.counter);
+        vcdu[6] = (first_header >> 8) as u8;
+        vcdu[7] = first_header as u8;
+        for (dst, src) in vcdu[8..].iter_mut().zip(self.stream.drain(..PACKET_ZONE_LEN)) {
+            *dst = src;
+        }
+        self.counter = (self.counter + 1) & 0xff_ffff;
+        vcdu
+    }
+}
+
+fn vcdu_header(scid: u8, vcid: u8, counter: u32) -> [u8; VCDU_LEN] {
+    let mut vcdu = [0; VCDU_LEN];
+    // version 1, then the spacecraft ID, then the virtual channel ID
+    vcdu[0] = 0b0100_0000 | scid >> 2;
+    vcdu[1] = (scid & 0b11) << 6 | (vcid & 0x3f);
+    vcdu[2..5].copy_from_slice(&counter.to_be_bytes()[1..]);
+    vcdu
+}
+
+/// Packs LRIT files into a stream of VCDUs
+///
+/// Virtual channels with data waiting are serviced round-robin, one VCDU at a time.  When there's
+/// nothing to send, fill VCDUs (VCID 63) are produced.
+pub struct Transmitter {
+    scid: u8,
+    channels: BTreeMap<u8, ChannelEncoder>,
+    /// The VCID that was serviced last
+    last_vcid: u8,
+    fill_counter: u32,
+}
+
+impl Transmitter {
+    pub fn new() -> Transmitter {
+        Transmitter {
+            // GOES-16
+            scid: 0x49,
+            channels: BTreeMap::new(),
+            last_vcid: 0,
+            fill_counter: 0,
+        }
+    }
+
+    /// Queues up an LRIT file for transmission
+    pub fn send(&mut self, vcid: u8, apid: u16, file: &[u8]) {
+        self.channels.entry(vcid).or_default().push_file(apid, file);
+    }
+
+    /// Queues up an LRIT file that's already split into TP_PDUs
+    ///
+    /// Rice compressed images are sent like this, with the headers in the first TP_PDU and then one
+    /// compressed scanline in each of the rest.
+    pub fn send_packets(&mut self, vcid: u8, apid: u16, headers: &[u8], packets: &[Vec<u8>]) {
+        let mut first = vec![0; PREAMBLE_LEN];
+        first.extend_from_slice(headers);
+        let packets = std::iter::once(&first[..]).chain(packets.iter().map(|p| &p[..]));
+        self.channels
+            .entry(vcid)
+            .or_default()
+            .push_packets(apid, packets.collect::<Vec<_>>().into_iter());
+    }
+
+    /// Queues up a single TP_PDU that isn't part of an LRIT file, like a CFDP PDU (see
+    /// [`crate::cfdp`])
+    pub fn send_pdu(&mut self, vcid: u8, apid: u16, data: &[u8]) {
+        self.channels.entry(vcid).or_default().push_tp_pdu(apid, 3, data);
+    }
+
+    /// True if there's no queued data left to send
+    pub fn is_idle(&self) -> bool {
+        self.channels.values().all(|c| c.stream.is_empty())
+    }
+
+    pub fn next_vcdu(&mut self) -> [u8; VCDU_LEN] {
+        let last = self.last_vcid;
+        let next = self
+            .channels
+            .range(last + 1..)
+            .chain(self.channels.range(..=last))
+            .find(|(_, c)| !c.stream.is_empty())
+            .map(|(vcid, _)| *vcid);
+
+        match next {
+            Some(vcid) => {
+                self.last_vcid = vcid;
+                let scid = self.scid;
+                self.channels.get_mut(&vcid).unwrap().next_vcdu(scid, vcid)
+            }
+            None => {
+                let mut vcdu = vcdu_header(self.scid, 63, self.fill_counter);
+                vcdu[6] = (FILL_APID >> 8) as u8;
+                vcdu[7] = FILL_APID as u8;
+                self.fill_counter = (self.fill_counter + 1) & 0xff_ffff;
+                vcdu
+            }
+        }
+    }
+}
+
+impl Default for Transmitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Virtual channels and APIDs used by the simulator
+const IMAGE_VCID: u8 = 13;
+const EMWIN_VCID: u8 = 20;
+const DCS_VCID: u8 = 30;
+
+/// A few real EMWIN product headings, to make the generated names look believable
+const EMWIN_HEADINGS: &[(&str, &str)] = &[
+    ("ASUS41KPHI", "RWRPHIPA"),
+    ("FTUS80KWBC", "TAFALLUS"),
+    ("FPUS20KWBN", "SCSWBNUS"),
+    ("SXAK58PACR", "HYDACRAK"),
+];
+
+/// Generates a synthetic downlink: full disk images, EMWIN text, and DCS files
+///
+/// Call [`Simulator::queue_due`] periodically to add any products whose time has come, and
+/// [`Simulator::next_vcdu`] at the downlink rate.
+pub struct Simulator {
+    tx: Transmitter,
+    image_size: u16,
+    image_segments: u16,
+    image_interval: Duration,
+    text_interval: Duration,
+    dcs_interval: Duration,
+    next_image: Option<DateTime<Utc>>,
+    next_text: Option<DateTime<Utc>>,
+    next_dcs: Option<DateTime<Utc>>,
+    image_id: u16,
+    product_count: u32,
+}
+
+impl Simulator {
+    /// Sends a 1024x1024 image every 5 minutes, a text product every 10 seconds, and a DCS
+    /// file every 30 seconds
+    pub fn new() -> Simulator {
+        Simulator {
+            tx: Transmitter::new(),
+            image_size: 1024,
+            image_segments: 8,
+            image_interval: Duration::minutes(5),
+            text_interval: Duration::seconds(10),
+            dcs_interval: Duration::seconds(30),
+            next_image: None,
+            next_text: None,
+            next_dcs: None,
+            image_id: 0,
+            product_count: 0,
+        }
+    }
+
+    /// Image width and height (images are square), and the number of segments
+    pub fn image_size(mut self, size: u16, segments: u16) -> Self {
+        self.image_size = size;
+        self.image_segments = segments.max(1);
+        self
+    }
+
+    pub fn image_interval(mut self, interval: Duration) -> Self {
+        self.image_interval = interval;
+        self
+    }
+
+    /// Queues all products that are due at `now`
+    pub fn queue_due(&mut self, now: DateTime<Utc>) {
+        if !matches!(self.next_image, Some(t) if now < t) {
+            self.queue_image(now);
+            self.next_image = Some(now + self.image_interval);
+        }
+        if !matches!(self.next_text, Some(t) if now < t) {
+            self.queue_text(now);
+            self.next_text = Some(now + self.text_interval);
+        }
+        if !matches!(self.next_dcs, Some(t) if now < t) {
+            self.queue_dcs(now);
+            self.next_dcs = Some(now + self.dcs_interval);
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.tx.is_idle()
+    }
+
+    pub fn next_vcdu(&mut self) -> [u8; VCDU_LEN] {
+        self.tx.next_vcdu()
+    }
+
+    /// Queues all segments of a band 13 full disk image
+    pub fn queue_image(&mut self, now: DateTime<Utc>) {
+        self.image_id = self.image_id.wrapping_add(1);
+        let size = self.image_size;
+        let pixels = synthetic_disk(size as usize, now.timestamp() as f32 / 600.0);
+
+        let product = format!(
+            "OR_ABI-L2-CMIPF-M6C13_G16_s{}_e{}_c{}.lrit",
+            scan_time(now),
+            scan_time(now + Duration::seconds(590)),
+            scan_time(now + Duration::seconds(600))
+        );
+        let lines_per_segment = size.div_ceil(self.image_segments);
+        for seq in 0..self.image_segments {
+            let start_line = seq * lines_per_segment;
+            let lines = lines_per_segment.min(size - start_line);
+            let start = start_line as usize * size as usize;
+            let data = &pixels[start..start + lines as usize * size as usize];
+
+            let file = LritBuilder::new(0)
+                .image_structure(8, size, lines)
+                .annotation(&product)
+                .ancillary_text("Segmented=yes;Channel=13")
+                .noaa(16, 13, 0, 0)
+                .segment(self.image_id, seq, start_line, self.image_segments, size, size)
+                .build(data);
+            self.tx.send(IMAGE_VCID, 13, &file);
+        }
+    }
+
+    /// Queues a (short) EMWIN text product
+    pub fn queue_text(&mut self, now: DateTime<Utc>) {
+        self.product_count += 1;
+        let (heading, awips) = EMWIN_HEADINGS[self.product_count as usize % EMWIN_HEADINGS.len()];
+        let name = format!(
+            "A_{}{}_C_KWIN_{}_{:06}-2-{}.TXT",
+            heading,
+            now.format("%d%H%M"),
+            now.format("%Y%m%d%H%M%S"),
+            self.product_count,
+            awips
+        );
+        let text = format!(
+            "{} {} {}\n{}\n\nSIMULATED PRODUCT {} GENERATED BY GOESBOX\nNOT FOR OPERATIONAL USE\n",
+            &heading[..6],
+            &heading[6..],
+            now.format("%d%H%M"),
+            awips,
+            self.product_count
+        );
+
+        let file = LritBuilder::new(2).annotation(&name).build(text.as_bytes());
+        self.tx.send(EMWIN_VCID, 1, &file);
+    }
+
+    /// Queues a DCS file with a single message
+    pub fn queue_dcs(&mut self, now: DateTime<Utc>) {
+        self.product_count += 1;
+        let name = format!("pH-{}-A.dcs", now.format("%y%j%H%M%S"));
+        let message = format!("SIM{:05} 12.6 45.1 1013.2", self.product_count);
+        let data = dcs_file(&name, self.product_count, now, b"NP", message.as_bytes());
+
+        let file = LritBuilder::new(130).annotation(&name).noaa(8, 0, 0, 0).build(&data);
+        self.tx.send(DCS_VCID, 1, &file);
+    }
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A small seedable PRNG (SplitMix64), so that damaged streams can be reproduced exactly
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns true with probability `p`
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
+
+/// Damages a stream of VCDUs, to see how the decoder copes with a poor signal
+///
+/// Each rate is the probability (from 0 to 1) that a given VCDU is affected.  The same seed always
+/// produces the same damage.
+pub struct LossInjector {
+    rng: SplitMix64,
+    drop_rate: f64,
+    bit_flip_rate: f64,
+    reorder_rate: f64,
+    /// A VCDU that is being held back, to be sent after the next one
+    held: Option<[u8; VCDU_LEN]>,
+}
+
+impl LossInjector {
+    /// Creates an injector that doesn't do anything until some rates are set
+    pub fn new(seed: u64) -> LossInjector {
+        LossInjector {
+            rng: SplitMix64(seed),
+            drop_rate: 0.0,
+            bit_flip_rate: 0.0,
+            reorder_rate: 0.0,
+            held: None,
+        }
+    }
+
+    /// The chance that a VCDU is lost entirely
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    /// The chance that a VCDU has a single bit flipped (anywhere, including its header)
+    pub fn bit_flip_rate(mut self, rate: f64) -> Self {
+        self.bit_flip_rate = rate;
+        self
+    }
+
+    /// The chance that a VCDU is swapped with the one after it
+    pub fn reorder_rate(mut self, rate: f64) -> Self {
+        self.reorder_rate = rate;
+        self
+    }
+
+    /// Passes a VCDU through the injector, returning the VCDUs that should be sent in its place
+    pub fn process(&mut self, mut vcdu: [u8; VCDU_LEN]) -> Vec<[u8; VCDU_LEN]> {
+        if self.rng.chance(self.drop_rate) {
+            return Vec::new();
+        }
+        if self.rng.chance(self.bit_flip_rate) {
+            let bit = (self.rng.next_u64() % (VCDU_LEN as u64 * 8)) as usize;
+            vcdu[bit / 8] ^= 1 << (bit % 8);
+        }
+        if self.held.is_none() && self.rng.chance(self.reorder_rate) {
+            self.held = Some(vcdu);
+            return Vec::new();
+        }
+
+        let mut out = vec![vcdu];
+        out.extend(self.held.take());
+        out
+    }
+}
+
+/// Formats a scan time like `20221241800205` (year, day of year, hour, minute, second, tenths)
+fn scan_time(t: DateTime<Utc>) -> String {
+    format!(
+        "{:04}{:03}{:02}{:02}{:02}{}",
+        t.year(),
+        t.ordinal(),
+        t.hour(),
+        t.minute(),
+        t.second(),
+        t.nanosecond() / 100_000_000
+    )
+}
+
+/// A grayscale Earth disk, with some slowly moving bands of "cloud"
+///
+/// `t` shifts the clouds, so that consecutive images look a little different
+pub fn synthetic_disk(size: usize, t: f32) -> Vec<u8> {
+    let center = size as f32 / 2.0;
+    let radius = center * 0.95;
+    let mut pixels = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = ((x as f32 - center) / radius, (y as f32 - center) / radius);
+            let r2 = dx * dx + dy * dy;
+            if r2 > 1.0 {
+                pixels.push(0);
+                continue;
+            }
+            // limb darkening
+            let surface = 60.0 + 60.0 * (1.0 - r2).sqrt();
+            let clouds = (dx * 9.0 + t).sin() * (dy * 7.0 - 0.7 * t).cos() + ((dx + dy) * 5.0 + 1.3 * t).sin();
+            pixels.push((surface + 50.0 * clouds.max(0.0)).min(255.0) as u8);
+        }
+    }
+    pixels
+}
+
+/// Encodes a time as the 7 byte BCD timestamp used in DCS blocks
+fn dcs_time(t: DateTime<Utc>) -> [u8; 7] {
+    let bcd = |hi: u32, lo: u32| ((hi % 10) << 4 | (lo % 10)) as u8;
+    let (year, day) = (t.year() as u32 % 100, t.ordinal());
+    let millis = t.timestamp_subsec_millis();
+    [
+        bcd(millis % 10, millis / 10),
+        bcd(t.second(), millis / 100),
+        bcd(t.minute(), t.second() / 10),
+        bcd(t.hour(), t.minute() / 10),
+        bcd(day, t.hour() / 10),
+        bcd(day / 100, day / 10),
+        bcd(year / 10, year),
+    ]
+}
+
+/// Builds a DCS file containing a single message block, uplinked from `source` (like `b"NP"`)
+///
+/// Ref: HRIT_DCS_File_Format_Rev1.pdf
+pub(crate) fn dcs_file(name: &str, sequence: u32, now: DateTime<Utc>, source: &[u8; 2], message: &[u8]) -> Vec<u8> {
+    let mut block = vec![1];
+    block.extend_from_slice(&(41 + message.len() as u16).to_le_bytes());
+    block.extend_from_slice(&sequence.to_le_bytes()[..3]);
+    // 300 baud, CS2 platform, no message flags
+    block.push(0b1010);
+    // no abnormal received message flags
+    block.push(0);
+    block.extend_from_slice(&(0xCE00_0000 | sequence).to_le_bytes());
+    block.extend_from_slice(&dcs_time(now - Duration::seconds(2)));
+    block.extend_from_slice(&dcs_time(now));
+    // 44.5 dBm, +12.0 Hz, 1.50° RMS phase noise, 98% good phase
+    block.extend_from_slice(&445u16.to_le_bytes());
+    block.extend_from_slice(&120i16.to_le_bytes());
+    block.extend_from_slice(&150u16.to_le_bytes());
+    block.push(196);
+    // GOES East, channel 42
+    block.extend_from_slice(&(1u16 << 12 | 42).to_le_bytes());
+    block.extend_from_slice(source);
+    block.extend_from_slice(&0u16.to_le_bytes());
+    block.extend_from_slice(message);
+    block.extend_from_slice(&crc::calc_crc16(&block).to_le_bytes());
+
+    let total_len = 64 + block.len() + 4;
+    let mut file = format!("{:<32}{:08}NSOFDCSH", name, total_len).into_bytes();
+    file.resize(60, 0);
+    file.extend_from_slice(&crc::calc_crc32(&file).to_le_bytes());
+    file.extend_from_slice(&block);
+    file.extend_from_slice(&crc::calc_crc32(&file).to_le_bytes());
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    use super::{LossInjector, LritBuilder, Simulator, Transmitter, VCDU_LEN};
+    #[cfg(feature = "image")]
+    use crate::handlers::ImageHandler;
+    use crate::{
+        handlers::{DcsBlock, DcsHandler, DcsHeader, Dispatcher, RetryPolicy, TextHandler},
+        lrit::{VirtualChannel, LRIT, VCDU},
+        stats::Stats,
+    };
+
+    fn time(h: u32, m: u32, s: u32) -> DateTime<Utc> {
+        Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2022, 5, 4)
+                .unwrap()
+                .and_hms_opt(h, m, s)
+                .unwrap(),
+        )
+    }
+
+    /// Runs VCDUs through the decoder, the same way the UI does
+    fn decode(vcdus: &[[u8; VCDU_LEN]]) -> Vec<LRIT> {
+        let mut stats = Stats::new();
+        let mut vcs: HashMap<u8, VirtualChannel> = HashMap::new();
+        let mut lrits = Vec::new();
+        for bytes in vcdus {
+            let vcdu = VCDU::new(bytes);
+            if vcdu.is_fill() {
+                continue;
+            }
+            let vc = vcs
+                .entry(vcdu.vcid())
+                .or_insert_with(|| VirtualChannel::new(vcdu.vcid(), vcdu.counter()));
+            lrits.extend(vc.process_vcdu(vcdu, &mut stats));
+        }
+        lrits
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let big: Vec<u8> = (0..30_000u32).map(|x| (x % 251) as u8).collect();
+        let files = [
+            (13, LritBuilder::new(0).annotation("big").build(&big)),
+            (20, LritBuilder::new(2).annotation("small").build(b"hello")),
+            // ends just short of a VCDU boundary, to exercise the fill packet spill-over
+            (20, LritBuilder::new(2).annotation("odd").build(&[7; 793])),
+            (13, LritBuilder::new(2).annotation("tiny").build(b"")),
+        ];
+
+        let mut tx = Transmitter::new();
+        for (vcid, file) in &files {
+            tx.send(*vcid, 5, file);
+        }
+        let mut vcdus = Vec::new();
+        while !tx.is_idle() {
+            vcdus.push(tx.next_vcdu());
+        }
+        // once idle, only fill is sent
+        assert!(VCDU::new(&tx.next_vcdu()).is_fill());
+
+        let lrits = decode(&vcdus);
+        assert_eq!(lrits.len(), files.len());
+        for (vcid, file) in &files {
+            assert!(
+                lrits.iter().any(|l| l.vcid == *vcid && &l.to_bytes() == file),
+                "missing {:?}",
+                LRIT::from_bytes(*vcid, file).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_simulator_products() {
+        let mut sim = Simulator::new().image_size(64, 4);
+        sim.queue_due(time(18, 0, 0));
+
+        let mut vcdus = Vec::new();
+        while !sim.is_idle() {
+            vcdus.push(sim.next_vcdu());
+        }
+        let lrits = decode(&vcdus);
+
+        let segments: Vec<_> = lrits.iter().filter(|l| l.headers.primary.filetype_code == 0).collect();
+        assert_eq!(segments.len(), 4);
+        for seg in &segments {
+            let ann = &seg.headers.annotation.as_ref().unwrap().text;
+            assert!(ann.starts_with("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800000_"), "{}", ann);
+            assert_eq!(seg.data.len(), 64 * 16);
+        }
+
+        let text = lrits.iter().find(|l| l.headers.primary.filetype_code == 2).unwrap();
+        assert!(text.headers.annotation.as_ref().unwrap().text.starts_with("A_"));
+
+        let dcs = lrits.iter().find(|l| l.headers.primary.filetype_code == 130).unwrap();
+        let header = DcsHeader::parse(&dcs.data).unwrap();
+        assert_eq!(header.payload_type, "DCSH");
+        assert_eq!(header.payload_len as usize, dcs.data.len());
+        assert_eq!(header.header_crc, crate::crc::calc_crc32(&dcs.data[..60]));
+        let blocks = DcsBlock::parse(&dcs.data[64..]).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].carrier_end.timestamp(), time(18, 0, 0).timestamp());
+    }
+
+    #[test]
+    fn test_damaged_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut handlers = Dispatcher::new().with_retry_policy(RetryPolicy::none());
+        #[cfg(feature = "image")]
+        handlers.push(Box::new(ImageHandler::new(dir.path())));
+        handlers.push(Box::new(TextHandler::new(dir.path())));
+        handlers.push(Box::new(DcsHandler::new(dir.path())));
+
+        // none of this should panic, no matter how the stream is damaged
+        for seed in 0..40 {
+            let mut sim = Simulator::new().image_size(256, 4);
+            for minute in 0..3 {
+                sim.queue_image(time(18, minute, 0));
+                sim.queue_text(time(18, minute, 0));
+                sim.queue_dcs(time(18, minute, 0));
+            }
+            let mut injector = LossInjector::new(seed)
+                .drop_rate(0.02)
+                .bit_flip_rate(0.2)
+                .reorder_rate(0.02);
+
+            let mut vcdus = Vec::new();
+            while !sim.is_idle() {
+                vcdus.extend(injector.process(sim.next_vcdu()));
+            }
+            for lrit in decode(&vcdus) {
+                handlers.dispatch(&lrit);
+            }
+        }
+    }
+
+    #[test]
+    fn test_injector_is_reproducible() {
+        let run = |seed| {
+            let mut injector = LossInjector::new(seed)
+                .drop_rate(0.1)
+                .bit_flip_rate(0.5)
+                .reorder_rate(0.1);
+            let mut out = Vec::new();
+            for i in 0..200u32 {
+                let mut vcdu = [0; VCDU_LEN];
+                vcdu[..4].copy_from_slice(&i.to_be_bytes());
+                out.extend(injector.process(vcdu));
+            }
+            out
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+
+        let out = run(1);
+        assert!(out.len() < 200 && out.len() > 150);
+        // nothing is changed when all rates are zero
+        let mut injector = LossInjector::new(1);
+        assert_eq!(injector.process([7; VCDU_LEN]), vec![[7; VCDU_LEN]]);
+    }
+}
@@ -1,8 +1,26 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
+    hash::Hash,
+    io::{self, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    admin::{AdminNotice, OutageSchedule},
+    clock::{Clock, ClockSkew, SkewReport, SystemClock},
+    handlers::{DcsBlock, DcsSource, DcsSpacescraft},
+    lrit::{Decompression, DownlinkMode, Vcid},
+    schedule::{Expected, Overdue, Schedule},
+    sim::VCDU_LEN,
+    telemetry::UnknownCodes,
+};
+
 pub enum Stat {
     Packet,
     /// A packet for a specific vcid
@@ -13,8 +31,87 @@ pub enum Stat {
     /// A packet full of TP_PDU data, but we had no previous header for it
     DiscardedDataPacket,
 
-    /// A packet for a specific APID
-    APID(u16),
+    /// A TP_PDU for a specific APID (and the vcid it was received on), and its size in bytes
+    APID(u8, u16, usize),
+
+    /// A fill TP_PDU (APID 2047) on a virtual channel, and its size in bytes
+    FillTpPdu(u8, usize),
+
+    /// A scanline that failed to decompress, and was replaced with zeros
+    DecompressionError,
+
+    /// A rice compressed LRIT file (with this NOAA product ID, if it has a NOAA header) was
+    /// decompressed
+    Decompression(Option<u16>, Decompression),
+
+    /// A VCDU or TP_PDU that was dropped because it was corrupt (or failed its CRC)
+    CorruptPacket,
+
+    /// A VCDU that was dropped because another receiver already delivered it
+    DuplicatePacket,
+
+    /// VCDUs that never arrived, from a gap in the VCDU counter of a virtual channel
+    DroppedPackets(usize),
+
+    /// Data that was dropped because one of the decoder's checks failed, see
+    /// [`DecodeMode::Tolerant`](crate::lrit::DecodeMode::Tolerant)
+    DecoderError,
+
+    /// A VCDU from the source with this index (see [`Stats::add_source`])
+    SourcePacket(usize),
+
+    /// A source stopped delivering VCDUs, because of this error
+    SourceError(usize, String),
+
+    /// A complete LRIT file with this file type code (and NOAA product ID, if it has a NOAA
+    /// header) was handled
+    Product(u8, Option<u16>),
+
+    /// The annotation (filename) of a handled LRIT file, for products that are expected on a
+    /// schedule
+    Annotation(String),
+
+    /// A notice from an admin message, see [`Stats::outages`]
+    AdminNotice(AdminNotice),
+
+    /// An LRIT file was dropped because it had a header of this type, which the parser doesn't know
+    UnknownHeaderType(u8),
+
+    /// The time the data in a live (not replayed) LRIT file was taken, see [`Stats::clock_skew`]
+    ProductTime(DateTime<Utc>),
+}
+
+/// How long a source can go without sending anything before it's considered idle
+pub const SOURCE_IDLE: Duration = Duration::from_secs(5);
+
+/// A receiver that VCDUs come from, like `tcp://localhost:5004`
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub address: String,
+    pub packets: usize,
+    pub last_packet: Option<Instant>,
+    /// Why the source stopped, if it did
+    pub error: Option<String>,
+}
+
+impl Source {
+    /// A short description of the source's state as of `now`, like "receiving" or "idle for 12s"
+    pub fn status(&self, now: Instant) -> String {
+        match (&self.error, self.last_packet) {
+            (Some(e), _) => format!("failed: {}", e),
+            (None, None) => "no data yet".to_string(),
+            (None, Some(last)) if now - last < SOURCE_IDLE => "receiving".to_string(),
+            (None, Some(last)) => format!("idle for {}s", (now - last).as_secs()),
+        }
+    }
+}
+
+/// A [`Source`], in a form that can be serialized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceSnapshot {
+    pub address: String,
+    pub packets: usize,
+    pub status: String,
 }
 
 pub struct Stats {
@@ -23,22 +120,501 @@ pub struct Stats {
     pub bytes: usize,
     pub fills: usize,
     pub discards: usize,
-    pub vcdu_packets: VecDeque<(Instant, HashMap<u8, usize>)>,
+    pub decompression_errors: usize,
+    pub corrupt_packets: usize,
+    pub duplicates: usize,
+    pub decoder_errors: usize,
+    pub dropped_packets: usize,
+    pub vcdu_packets: RecentCounts<u8>,
     //vcdu_packets: HashMap<u8, usize>,
-    pub apid: HashMap<u16, usize>,
+    /// Recent packet counts for each (vcid, apid), in one second buckets (newest first)
+    pub apid_packets: RecentCounts<(u8, u16)>,
+    /// Recent byte counts for each (vcid, apid), like `apid_packets`
+    pub apid_bytes: RecentCounts<(u8, u16)>,
+    /// Recent dropped and corrupt packet counts, like `apid_packets`
+    link_problems: RecentCounts<LinkProblem>,
+    /// Packet counts, keyed by (vcid, apid)
+    pub apid: HashMap<(u8, u16), usize>,
+    /// Fill TP_PDUs, keyed by vcid (fill VCDUs are counted in `fills`)
+    pub fill_tp_pdus: BTreeMap<u8, FillCounts>,
+    /// Recent fill TP_PDU bytes, like `apid_packets`
+    fill_bytes: RecentCounts<u8>,
+    /// How long each handler takes, keyed by handler name
+    pub handler_times: BTreeMap<String, TimeHistogram>,
+    /// How many times each handler has crashed, keyed by handler name
+    pub handler_crashes: BTreeMap<String, usize>,
+    /// Handled LRIT files, keyed by file type code
+    pub products: BTreeMap<u8, usize>,
+    /// Handled LRIT files in each hour, keyed by (file type code, NOAA product ID)
+    pub product_mix: HourlyCounts<(u8, Option<u16>)>,
+    /// Rice decompression totals, keyed by NOAA product ID
+    pub compression: BTreeMap<Option<u16>, CompressionStats>,
+    /// DCS messages, by source and spacecraft (give a clone of this to the DCS handler)
+    pub dcs: DcsStats,
+    /// Codes the parsers don't know (give a clone of this to the
+    /// [`UnknownCodeHandler`](crate::telemetry::UnknownCodeHandler))
+    pub unknown_codes: UnknownCodes,
+    pub sources: Vec<Source>,
+    /// The downlink mode, if it's been configured instead of detected
+    fixed_mode: Option<DownlinkMode>,
+    /// Products that are expected on a schedule
+    schedule: Schedule,
+    /// Outages announced in admin messages
+    outages: OutageSchedule,
+    /// How far the local clock is from product times
+    skew: ClockSkew,
+    clock: Arc<dyn Clock>,
+}
+
+/// Counts in one hour buckets, newest first, keyed by the start of the hour
+pub type HourlyCounts<K> = VecDeque<(DateTime<Utc>, BTreeMap<K, usize>)>;
+
+/// How many seconds of VCDUs the downlink mode is detected from
+const MODE_WINDOW_SECS: u64 = 10;
+
+/// How many hours of the product mix are kept
+const PRODUCT_MIX_HOURS: usize = 24;
+
+/// The count of one kind of product in one hour, from [`Stats::product_mix`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductMixEntry {
+    pub hour: DateTime<Utc>,
+    pub filetype_code: u8,
+    pub product_id: Option<u16>,
+    pub count: usize,
+}
+
+/// Rice decompression totals for one kind of product, see [`Stat::Decompression`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CompressionStats {
+    pub product_id: Option<u16>,
+    pub files: usize,
+    pub compressed_bytes: usize,
+    pub decompressed_bytes: usize,
+    pub scanlines: usize,
+    /// Scanlines that failed to decompress, and were replaced with zeros
+    pub failed_scanlines: usize,
+    /// How long each file took to decompress
+    pub time: TimeHistogram,
+}
+
+impl CompressionStats {
+    /// Decompressed size over compressed size
+    pub fn ratio(&self) -> f64 {
+        self.decompressed_bytes as f64 / self.compressed_bytes.max(1) as f64
+    }
+}
+
+/// Fill TP_PDUs received on one virtual channel, see [`Stat::FillTpPdu`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FillCounts {
+    pub vcid: Vcid,
+    pub tp_pdus: usize,
+    pub bytes: usize,
+}
+
+/// DCS message totals for one uplink site and spacecraft, see [`DcsStats`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DcsSourceStats {
+    /// The uplink site's two letter code, like "UP"
+    pub source: String,
+    /// Like "GOES-East"
+    pub spacecraft: String,
+    pub messages: usize,
+    /// Size of the message data
+    pub bytes: usize,
+    /// Messages received with parity errors
+    pub parity_errors: usize,
+    /// Mean signal strength, in dBm
+    pub signal_strength: f64,
+    /// The carrier end time of the newest message
+    pub last: Option<DateTime<Utc>>,
+}
+
+/// DCS message counts by source and spacecraft
+///
+/// These are counted by the [`DcsHandler`](crate::handlers::DcsHandler), which can run on another
+/// thread, so this is cheap to clone and every clone shares the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct DcsStats {
+    counts: Arc<Mutex<BTreeMap<(DcsSource, DcsSpacescraft), DcsSourceStats>>>,
+}
+
+impl DcsStats {
+    pub fn record(&self, block: &DcsBlock) {
+        let mut counts = self.counts.lock().unwrap();
+        let stats = counts
+            .entry((block.source_platform, block.space_platform))
+            .or_insert_with(|| DcsSourceStats {
+                source: block.source_platform.to_string(),
+                spacecraft: block.space_platform.to_string(),
+                ..Default::default()
+            });
+        stats.messages += 1;
+        stats.bytes += block.data.len();
+        if block.parity_errors {
+            stats.parity_errors += 1;
+        }
+        stats.signal_strength += (block.signal_strength as f64 - stats.signal_strength) / stats.messages as f64;
+        stats.last = stats.last.max(Some(block.carrier_end));
+    }
+
+    /// Totals for every source and spacecraft, in source order
+    pub fn totals(&self) -> Vec<DcsSourceStats> {
+        self.counts.lock().unwrap().values().cloned().collect()
+    }
+
+    /// Message counts for each source, across all spacecraft
+    pub fn messages_by_source(&self) -> BTreeMap<DcsSource, usize> {
+        let mut by_source = BTreeMap::new();
+        for ((source, _), stats) in self.counts.lock().unwrap().iter() {
+            *by_source.entry(*source).or_insert(0) += stats.messages;
+        }
+        by_source
+    }
+
+    pub fn clear(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+}
+
+/// Packet counts in one second buckets, newest first
+pub type RecentCounts<K> = VecDeque<(Instant, HashMap<K, usize>)>;
+
+/// How long per-second packet counts are kept for
+const RECENT_HISTORY: Duration = Duration::from_secs(60);
+
+/// Adds to the count in the current one second bucket, and forgets buckets that are too old
+fn count_recent<K: Hash + Eq>(buckets: &mut RecentCounts<K>, now: Instant, key: K, amount: usize) {
+    while matches!(buckets.back(), Some((inst, _)) if now - *inst > RECENT_HISTORY) {
+        buckets.pop_back();
+    }
+
+    // if the first bucket is less than 1 second old, use it
+    // else, push a new bucket on the front
+    if let Some((inst, map)) = buckets.front_mut() {
+        if now - *inst < Duration::from_secs(1) {
+            *map.entry(key).or_insert(0) += amount;
+            return;
+        }
+    }
+    buckets.push_front((now, HashMap::from([(key, amount)])));
+}
+
+/// Adds up the counts of all buckets from the `window` before `now` (see [`Stats::now`])
+pub fn recent_counts<K: Hash + Eq + Copy>(
+    buckets: &RecentCounts<K>,
+    now: Instant,
+    window: Duration,
+) -> HashMap<K, usize> {
+    let mut total = HashMap::new();
+    for (_, map) in buckets
+        .iter()
+        .take_while(|(inst, _)| now.saturating_duration_since(*inst) <= window)
+    {
+        for (key, count) in map {
+            *total.entry(*key).or_insert(0) += count;
+        }
+    }
+    total
+}
+
+/// Upper bounds (in milliseconds) of the buckets of a [`TimeHistogram`]
+pub const TIME_BUCKETS_MS: [u64; 13] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000];
+
+/// A histogram of how long something took
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeHistogram {
+    pub count: u64,
+    pub total_ms: f64,
+    pub max_ms: f64,
+    /// Counts for each of [`TIME_BUCKETS_MS`], plus one more for anything longer
+    pub buckets: Vec<u64>,
+}
+
+impl TimeHistogram {
+    pub fn record(&mut self, time: Duration) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; TIME_BUCKETS_MS.len() + 1];
+        }
+        let ms = time.as_secs_f64() * 1000.0;
+        let bucket = TIME_BUCKETS_MS
+            .iter()
+            .position(|&max| ms <= max as f64)
+            .unwrap_or(TIME_BUCKETS_MS.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_ms += ms;
+        self.max_ms = self.max_ms.max(ms);
+    }
+
+    pub fn mean_ms(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.total_ms / self.count as f64
+    }
+
+    /// The upper bound of the bucket that holds the given percentile (0 to 100)
+    ///
+    /// Returns the maximum if the percentile falls in the last (unbounded) bucket.
+    pub fn percentile_ms(&self, percentile: f64) -> f64 {
+        let target = (self.count as f64 * percentile / 100.0).ceil() as u64;
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                return TIME_BUCKETS_MS.get(i).map_or(self.max_ms, |&max| max as f64);
+            }
+        }
+        self.max_ms
+    }
+}
+
+/// The counters from [`Stats`], in a form that can be serialized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    /// Seconds since the stats were started (or reset)
+    pub seconds: f64,
+    pub packets: usize,
+    pub bytes: usize,
+    pub fills: usize,
+    pub discards: usize,
+    pub decompression_errors: usize,
+    pub corrupt_packets: usize,
+    #[serde(default)]
+    pub duplicates: usize,
+    #[serde(default)]
+    pub decoder_errors: usize,
+    #[serde(default)]
+    pub dropped_packets: usize,
+    /// Packet counts, as (vcid, apid, count)
+    pub apids: Vec<(u8, u16, usize)>,
+    #[serde(default)]
+    pub handler_times: BTreeMap<String, TimeHistogram>,
+    #[serde(default)]
+    pub handler_crashes: BTreeMap<String, usize>,
+    /// Handled LRIT files, keyed by file type code
+    #[serde(default)]
+    pub products: BTreeMap<u8, usize>,
+    #[serde(default)]
+    pub sources: Vec<SourceSnapshot>,
+    /// Handled LRIT files in each of the last 24 hours, oldest first
+    #[serde(default)]
+    pub product_mix: Vec<ProductMixEntry>,
+    #[serde(default)]
+    pub mode: Option<DownlinkMode>,
+    /// Receive rates over the last [`RATE_WINDOW`]
+    #[serde(default)]
+    pub rates: Rates,
+    /// Expected products that haven't arrived on time
+    #[serde(default)]
+    pub overdue: Vec<Overdue>,
+    /// Outages announced in admin messages that are going on, or still to come
+    #[serde(default)]
+    pub outages: Vec<AdminNotice>,
+    /// Rice decompression totals, in NOAA product ID order
+    #[serde(default)]
+    pub compression: Vec<CompressionStats>,
+    /// DCS message totals, in source order
+    #[serde(default)]
+    pub dcs: Vec<DcsSourceStats>,
+    /// Fill TP_PDUs, in VCID order
+    #[serde(default)]
+    pub fill_tp_pdus: Vec<FillCounts>,
+    /// The fraction of the downlink that carried real data, see [`Stats::utilization`]
+    #[serde(default)]
+    pub utilization: f64,
+    /// How far the local clock is from product times
+    #[serde(default)]
+    pub clock_skew: SkewReport,
+}
+
+/// The virtual channel of fill VCDUs
+const FILL_VCID: u8 = 63;
+
+/// The size of the data zone (the M_PDU) of a VCDU
+const VCDU_DATA_LEN: usize = 886;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum LinkProblem {
+    Dropped,
+    Corrupt,
+}
+
+/// Signal quality over a short window, for pointing an antenna (see [`Stats::link_quality`])
+///
+/// There's no access to the demodulator, so these stand in for a signal quality reading: a
+/// weak signal drops VCDUs (which shows up as gaps in their counters) and corrupts others.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LinkQuality {
+    pub window_secs: f64,
+    /// VCDUs received per second, including fill
+    pub vcdus_per_second: f64,
+    /// The fraction of VCDUs that were sent, but never arrived
+    pub drop_rate: f64,
+    /// The fraction of received VCDUs that were fill
+    pub fill_ratio: f64,
+    /// Corrupt VCDUs and TP_PDUs (like those that failed their CRC), as a fraction of received
+    /// VCDUs
+    pub error_rate: f64,
+    /// The fraction of received data that wasn't fill, see [`Stats::utilization`]
+    #[serde(default)]
+    pub utilization: f64,
+}
+
+/// How long the rates in a [`StatsSnapshot`] are averaged over
+pub const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Receive rates, averaged over a recent window (see [`Stats::rates`])
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Rates {
+    pub window_secs: f64,
+    /// VCDUs per second, including fill
+    pub packets_per_second: f64,
+    pub bits_per_second: f64,
+    /// Rates for each virtual channel, in VCID order
+    pub vcs: Vec<VcRate>,
+    /// Rates for each APID, in (VCID, APID) order
+    pub apids: Vec<ApidRate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VcRate {
+    pub vcid: Vcid,
+    /// VCDUs per second
+    pub packets_per_second: f64,
+    pub bits_per_second: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApidRate {
+    pub vcid: Vcid,
+    pub apid: u16,
+    /// TP_PDUs per second
+    pub packets_per_second: f64,
+    pub bits_per_second: f64,
 }
 
 impl Stats {
     pub fn new() -> Stats {
+        Stats::with_clock(Arc::new(SystemClock))
+    }
+
+    /// Stats that get the time from `clock`, instead of the system clock
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Stats {
         Stats {
-            time: Instant::now(),
+            time: clock.now(),
             packets: 0,
             bytes: 0,
             fills: 0,
             discards: 0,
+            decompression_errors: 0,
+            corrupt_packets: 0,
+            duplicates: 0,
+            decoder_errors: 0,
+            dropped_packets: 0,
             vcdu_packets: VecDeque::new(),
+            apid_packets: VecDeque::new(),
+            apid_bytes: VecDeque::new(),
+            link_problems: VecDeque::new(),
             apid: HashMap::new(),
+            fill_tp_pdus: BTreeMap::new(),
+            fill_bytes: VecDeque::new(),
+            handler_times: BTreeMap::new(),
+            handler_crashes: BTreeMap::new(),
+            products: BTreeMap::new(),
+            product_mix: VecDeque::new(),
+            compression: BTreeMap::new(),
+            dcs: DcsStats::default(),
+            unknown_codes: UnknownCodes::new(clock.utc()),
+            sources: Vec::new(),
+            fixed_mode: None,
+            schedule: Schedule::new(clock.utc()),
+            outages: OutageSchedule::new(),
+            skew: ClockSkew::new(),
+            clock,
+        }
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// The current time, according to the stats' clock
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// The current wall clock time, from the stats' clock, or from product times if they're
+    /// trusted and the clock looks wrong (see [`Stats::set_clock_skew`])
+    pub fn utc(&self) -> DateTime<Utc> {
+        self.skew.utc(self.clock.utc())
+    }
+
+    /// Compares product times with the clock using these settings (instead of the defaults)
+    pub fn set_clock_skew(&mut self, skew: ClockSkew) {
+        self.skew = skew;
+    }
+
+    /// How far the clock is from the times of recently received products
+    pub fn clock_skew(&self) -> SkewReport {
+        self.skew.report()
+    }
+
+    /// How long since the stats were started (or reset)
+    pub fn uptime(&self) -> Duration {
+        self.now() - self.time
+    }
+
+    /// Use this downlink mode, instead of detecting it
+    pub fn set_mode(&mut self, mode: DownlinkMode) {
+        self.fixed_mode = Some(mode);
+    }
+
+    /// The downlink mode, if it's been set or can be detected yet (see [`DownlinkMode::detect`])
+    ///
+    /// The VCDU rate is only used once there's been enough time to measure it.
+    pub fn mode(&self) -> Option<DownlinkMode> {
+        if self.fixed_mode.is_some() {
+            return self.fixed_mode;
         }
+        let window = Duration::from_secs(MODE_WINDOW_SECS);
+        let rate = if self.uptime() >= window {
+            recent_counts(&self.vcdu_packets, self.now(), window)
+                .values()
+                .sum::<usize>() as f64
+                / MODE_WINDOW_SECS as f64
+        } else {
+            0.0
+        };
+        DownlinkMode::detect(rate, self.apid.keys().map(|(vcid, _)| *vcid))
+    }
+
+    /// Watches for a product that's expected on a schedule, see [`Stats::overdue`]
+    pub fn expect(&mut self, expected: Expected) {
+        self.schedule.expect(expected);
+    }
+
+    /// Expected products that haven't arrived on time
+    pub fn overdue(&self) -> Vec<Overdue> {
+        self.schedule.overdue(self.utc())
+    }
+
+    /// Outages announced in admin messages that are going on, or still to come, soonest first
+    pub fn outages(&self) -> Vec<AdminNotice> {
+        self.outages.upcoming(self.utc())
+    }
+
+    /// Adds a source to keep track of, and returns its index for [`Stat::SourcePacket`]
+    pub fn add_source(&mut self, address: impl Into<String>) -> usize {
+        self.sources.push(Source {
+            address: address.into(),
+            packets: 0,
+            last_packet: None,
+            error: None,
+        });
+        self.sources.len() - 1
     }
     pub fn record(&mut self, stat: Stat) {
         match stat {
@@ -46,47 +622,640 @@ impl Stats {
             Stat::Bytes(b) => self.bytes += b,
             Stat::FillPacket => self.fills += 1,
             Stat::DiscardedDataPacket => self.discards += 1,
-            Stat::VCDUPacket(id) => {
-                // if the first bucket in vcdu_packets is less than 1 second old, use it
-                // else, push a new bucket on the front
-                if let Some((inst, map)) = self.vcdu_packets.front_mut() {
-                    if inst.elapsed() < Duration::from_secs(1) {
-                        *map.entry(id).or_insert(0) += 1;
-                        return;
-                    }
+            Stat::VCDUPacket(id) => count_recent(&mut self.vcdu_packets, self.clock.now(), id, 1),
+            Stat::APID(vcid, id, bytes) => {
+                *self.apid.entry((vcid, id)).or_insert(0) += 1;
+                count_recent(&mut self.apid_packets, self.clock.now(), (vcid, id), 1);
+                count_recent(&mut self.apid_bytes, self.clock.now(), (vcid, id), bytes);
+            }
+            Stat::FillTpPdu(vcid, bytes) => {
+                let counts = self.fill_tp_pdus.entry(vcid).or_insert_with(|| FillCounts {
+                    vcid: vcid.into(),
+                    tp_pdus: 0,
+                    bytes: 0,
+                });
+                counts.tp_pdus += 1;
+                counts.bytes += bytes;
+                count_recent(&mut self.fill_bytes, self.clock.now(), vcid, bytes);
+            }
+            Stat::DecompressionError => self.decompression_errors += 1,
+            Stat::Decompression(product_id, d) => {
+                let totals = self.compression.entry(product_id).or_insert_with(|| CompressionStats {
+                    product_id,
+                    ..Default::default()
+                });
+                totals.files += 1;
+                totals.compressed_bytes += d.compressed_bytes;
+                totals.decompressed_bytes += d.decompressed_bytes;
+                totals.scanlines += d.scanlines;
+                totals.failed_scanlines += d.failed_scanlines;
+                totals.time.record(Duration::from_secs_f64(d.time_ms / 1000.0));
+            }
+            Stat::CorruptPacket => {
+                self.corrupt_packets += 1;
+                count_recent(&mut self.link_problems, self.clock.now(), LinkProblem::Corrupt, 1);
+            }
+            Stat::DroppedPackets(count) => {
+                self.dropped_packets += count;
+                count_recent(&mut self.link_problems, self.clock.now(), LinkProblem::Dropped, count);
+            }
+            Stat::DuplicatePacket => self.duplicates += 1,
+            Stat::DecoderError => self.decoder_errors += 1,
+            Stat::SourcePacket(i) => {
+                if let Some(source) = self.sources.get_mut(i) {
+                    source.packets += 1;
+                    source.last_packet = Some(self.clock.now());
                 }
-
-                self.vcdu_packets.push_front((Instant::now(), {
-                    let mut map = HashMap::new();
-                    map.insert(id, 1);
-                    map
-                }));
             }
-            Stat::APID(id) => *self.apid.entry(id).or_insert(0) += 1,
+            Stat::SourceError(i, e) => {
+                if let Some(source) = self.sources.get_mut(i) {
+                    source.error = Some(e);
+                }
+            }
+            Stat::Product(code, product_id) => {
+                *self.products.entry(code).or_insert(0) += 1;
+                self.count_product(self.utc(), code, product_id);
+            }
+            Stat::Annotation(text) => self.schedule.received(&text, self.utc()),
+            Stat::AdminNotice(notice) => {
+                self.outages.prune(self.utc());
+                self.outages.add(notice);
+            }
+            Stat::UnknownHeaderType(header_type) => {
+                self.unknown_codes.record_header_type(header_type, self.clock.utc())
+            }
+            Stat::ProductTime(time) => self.skew.record(time, self.clock.utc()),
+        }
+    }
+
+    /// Counts a product in the bucket for the hour of `now`
+    fn count_product(&mut self, now: DateTime<Utc>, code: u8, product_id: Option<u16>) {
+        let hour = Utc
+            .timestamp_opt(now.timestamp() - now.timestamp().rem_euclid(3600), 0)
+            .unwrap();
+        if !matches!(self.product_mix.front(), Some((h, _)) if *h == hour) {
+            self.product_mix.push_front((hour, BTreeMap::new()));
+            self.product_mix.truncate(PRODUCT_MIX_HOURS);
+        }
+        if let Some((_, counts)) = self.product_mix.front_mut() {
+            *counts.entry((code, product_id)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let mut apids: Vec<_> = self
+            .apid
+            .iter()
+            .map(|((vcid, apid), count)| (*vcid, *apid, *count))
+            .collect();
+        apids.sort_unstable();
+        StatsSnapshot {
+            seconds: self.uptime().as_secs_f64(),
+            packets: self.packets,
+            bytes: self.bytes,
+            fills: self.fills,
+            discards: self.discards,
+            decompression_errors: self.decompression_errors,
+            corrupt_packets: self.corrupt_packets,
+            duplicates: self.duplicates,
+            decoder_errors: self.decoder_errors,
+            dropped_packets: self.dropped_packets,
+            apids,
+            handler_times: self.handler_times.clone(),
+            handler_crashes: self.handler_crashes.clone(),
+            products: self.products.clone(),
+            product_mix: self
+                .product_mix
+                .iter()
+                .rev()
+                .flat_map(|(hour, counts)| {
+                    counts
+                        .iter()
+                        .map(move |((filetype_code, product_id), count)| ProductMixEntry {
+                            hour: *hour,
+                            filetype_code: *filetype_code,
+                            product_id: *product_id,
+                            count: *count,
+                        })
+                })
+                .collect(),
+            sources: self
+                .sources
+                .iter()
+                .map(|s| SourceSnapshot {
+                    address: s.address.clone(),
+                    packets: s.packets,
+                    status: s.status(self.now()),
+                })
+                .collect(),
+            mode: self.mode(),
+            rates: self.rates(RATE_WINDOW),
+            overdue: self.overdue(),
+            outages: self.outages(),
+            compression: self.compression.values().cloned().collect(),
+            dcs: self.dcs.totals(),
+            fill_tp_pdus: self.fill_tp_pdus.values().cloned().collect(),
+            utilization: self.utilization(),
+            clock_skew: self.clock_skew(),
         }
     }
 
-    pub fn print(&self) {
-        let secs = self.time.elapsed().as_millis() as f32 / 1000.0;
-        println!("==============");
-        println!("Total packets: {:0.2} pps", self.packets as f32 / secs);
-        println!("Discards: {:0.2} pps", self.discards as f32 / secs);
-        println!("VC stats:");
-        //for (vcid, count) in self.vcdu_packets.iter() {
-        //    println!("  VC {}: {:0.2} pps", vcid, *count as f32 / secs);
-        //}
-        //println!("APID stats:");
-        //for (id, count) in self.apid.iter() {
-        //    println!("  APID {}: {:0.2} pps", id, *count as f32 / secs);
-        //}
+    /// The fraction of the downlink that carried real data (since the stats were started), which
+    /// is everything but fill VCDUs and fill TP_PDUs
+    ///
+    /// The downlink always runs at the same rate, so this shows how busy the broadcast is.
+    pub fn utilization(&self) -> f64 {
+        let fill_bytes: usize = self.fill_tp_pdus.values().map(|f| f.bytes).sum();
+        utilization(self.packets, self.fills, fill_bytes)
+    }
+
+    /// Receive rates over the last `window` (or since the stats were started, if that's shorter)
+    pub fn rates(&self, window: Duration) -> Rates {
+        let now = self.now();
+        // the rates are from whole one second buckets, so don't divide by less than one
+        let secs = self.uptime().min(window).as_secs_f64().max(1.0);
+        let vcdu_bits = (VCDU_LEN * 8) as f64;
+
+        let mut vcs: Vec<_> = recent_counts(&self.vcdu_packets, now, window)
+            .into_iter()
+            .map(|(vcid, count)| VcRate {
+                vcid: vcid.into(),
+                packets_per_second: count as f64 / secs,
+                bits_per_second: count as f64 * vcdu_bits / secs,
+            })
+            .collect();
+        vcs.sort_unstable_by_key(|r| r.vcid);
+
+        let bytes = recent_counts(&self.apid_bytes, now, window);
+        let mut apids: Vec<_> = recent_counts(&self.apid_packets, now, window)
+            .into_iter()
+            .map(|((vcid, apid), count)| ApidRate {
+                vcid: vcid.into(),
+                apid,
+                packets_per_second: count as f64 / secs,
+                bits_per_second: bytes.get(&(vcid, apid)).copied().unwrap_or(0) as f64 * 8.0 / secs,
+            })
+            .collect();
+        apids.sort_unstable_by_key(|r| (r.vcid, r.apid));
+
+        Rates {
+            window_secs: secs,
+            packets_per_second: vcs.iter().map(|r| r.packets_per_second).sum(),
+            bits_per_second: vcs.iter().map(|r| r.bits_per_second).sum(),
+            vcs,
+            apids,
+        }
+    }
+
+    /// Signal quality over the last `window` (or since the stats were started, if that's shorter)
+    pub fn link_quality(&self, window: Duration) -> LinkQuality {
+        let now = self.now();
+        let secs = self.uptime().min(window).as_secs_f64().max(1.0);
+        let vcdus = recent_counts(&self.vcdu_packets, now, window);
+        let received = vcdus.values().sum::<usize>() as f64;
+        let fill = vcdus.get(&FILL_VCID).copied().unwrap_or(0) as f64;
+        let problems = recent_counts(&self.link_problems, now, window);
+        let dropped = problems.get(&LinkProblem::Dropped).copied().unwrap_or(0) as f64;
+        let corrupt = problems.get(&LinkProblem::Corrupt).copied().unwrap_or(0) as f64;
+        let fill_bytes = recent_counts(&self.fill_bytes, now, window).values().sum();
+        let ratio = |count: f64, total: f64| if total > 0.0 { count / total } else { 0.0 };
+        LinkQuality {
+            window_secs: secs,
+            vcdus_per_second: received / secs,
+            drop_rate: ratio(dropped, received + dropped),
+            fill_ratio: ratio(fill, received),
+            error_rate: ratio(corrupt, received),
+            utilization: utilization(received as usize, fill as usize, fill_bytes),
+        }
     }
 
     pub fn reset(&mut self) {
-        self.time = Instant::now();
+        self.time = self.clock.now();
         self.packets = 0;
         self.bytes = 0;
         self.fills = 0;
         self.discards = 0;
+        self.decompression_errors = 0;
+        self.corrupt_packets = 0;
+        self.duplicates = 0;
+        self.decoder_errors = 0;
+        self.dropped_packets = 0;
+        self.fill_tp_pdus.clear();
+        self.dcs.clear();
         //self.vcdu_packets = HashMap::new();
     }
 }
+
+/// The fraction of the data zones of `vcdus` that weren't fill VCDUs or fill TP_PDUs
+fn utilization(vcdus: usize, fill_vcdus: usize, fill_tp_pdu_bytes: usize) -> f64 {
+    if vcdus == 0 {
+        return 0.0;
+    }
+    let total = (vcdus * VCDU_DATA_LEN) as f64;
+    let fill = (fill_vcdus * VCDU_DATA_LEN + fill_tp_pdu_bytes) as f64;
+    (1.0 - fill / total).max(0.0)
+}
+
+/// One line of output from a [`StatsSink`]
+#[derive(Serialize)]
+struct StatsLine {
+    time: DateTime<Utc>,
+    #[serde(flatten)]
+    stats: StatsSnapshot,
+}
+
+enum SinkOutput {
+    File(PathBuf),
+    Socket {
+        path: PathBuf,
+        listener: UnixListener,
+        clients: Vec<UnixStream>,
+    },
+}
+
+/// Writes a [`StatsSnapshot`] every few seconds, for external monitoring (like MRTG or collectd)
+///
+/// Each snapshot is a single line of JSON.  A file is replaced with the latest snapshot every time,
+/// so it can be read at any time.  A Unix socket accepts any number of clients, and sends each of
+/// them a line per snapshot; clients that don't keep up are disconnected.
+pub struct StatsSink {
+    output: SinkOutput,
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl StatsSink {
+    pub fn file(path: impl AsRef<Path>) -> StatsSink {
+        StatsSink::with_output(SinkOutput::File(path.as_ref().to_path_buf()))
+    }
+
+    /// Listens on a Unix socket, replacing any stale socket file at the same path
+    pub fn unix_socket(path: impl AsRef<Path>) -> io::Result<StatsSink> {
+        let path = path.as_ref().to_path_buf();
+        if UnixStream::connect(&path).is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(StatsSink::with_output(SinkOutput::Socket {
+            path,
+            listener,
+            clients: Vec::new(),
+        }))
+    }
+
+    fn with_output(output: SinkOutput) -> StatsSink {
+        StatsSink {
+            output,
+            interval: Duration::from_secs(10),
+            last: None,
+        }
+    }
+
+    /// How often to write a snapshot (10 seconds by default)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Writes a snapshot, if it's been long enough since the last one
+    pub fn tick(&mut self, stats: &Stats) -> io::Result<()> {
+        let now = stats.now();
+        if matches!(self.last, Some(last) if now - last < self.interval) {
+            return Ok(());
+        }
+        self.last = Some(now);
+        self.write(stats)
+    }
+
+    /// Writes a snapshot right away
+    pub fn write(&mut self, stats: &Stats) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&StatsLine {
+            time: stats.clock().utc(),
+            stats: stats.snapshot(),
+        })?;
+        line.push(b'\n');
+
+        match &mut self.output {
+            SinkOutput::File(path) => {
+                let mut tmp = path.clone().into_os_string();
+                tmp.push(".tmp");
+                std::fs::write(&tmp, &line)?;
+                std::fs::rename(&tmp, path)
+            }
+            SinkOutput::Socket { listener, clients, .. } => {
+                loop {
+                    match listener.accept() {
+                        Ok((client, _)) => {
+                            client.set_nonblocking(true)?;
+                            clients.push(client);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    }
+                }
+                clients.retain_mut(|client| client.write_all(&line).is_ok());
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for StatsSink {
+    fn drop(&mut self) {
+        if let SinkOutput::Socket { path, .. } = &self.output {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixStream,
+        sync::Arc,
+        time::Duration,
+    };
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use super::{
+        recent_counts, ApidRate, Decompression, DownlinkMode, LinkQuality, ProductMixEntry, Stat, Stats, StatsSink,
+        TimeHistogram, Vcid, RATE_WINDOW,
+    };
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn test_stats_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut stats = Stats::new();
+        stats.record(Stat::Packet);
+        stats.record(Stat::APID(13, 1000, 100));
+
+        let path = dir.path().join("stats.json");
+        let mut sink = StatsSink::file(&path).interval(Duration::from_secs(3600));
+        sink.tick(&stats).unwrap();
+        stats.record(Stat::Packet);
+        // too soon for another snapshot
+        sink.tick(&stats).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["packets"], 1);
+        assert_eq!(json["apids"][0], serde_json::json!([13, 1000, 1]));
+        assert!(json["time"].is_string());
+
+        let path = dir.path().join("stats.sock");
+        let mut sink = StatsSink::unix_socket(&path).unwrap();
+        let mut client = BufReader::new(UnixStream::connect(&path).unwrap());
+        sink.write(&stats).unwrap();
+        let mut line = String::new();
+        client.read_line(&mut line).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["packets"], 2);
+
+        drop(sink);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_recent_counts() {
+        let mut stats = Stats::new();
+        for apid in [100, 100, 200] {
+            stats.record(Stat::APID(13, apid, 100));
+        }
+        let recent = recent_counts(&stats.apid_packets, stats.now(), Duration::from_secs(10));
+        assert_eq!((recent[&(13, 100)], recent[&(13, 200)]), (2, 1));
+        assert_eq!(stats.apid_packets.len(), 1);
+    }
+
+    #[test]
+    fn test_product_mix() {
+        let mut stats = Stats::new();
+        let at = |hour, minute| {
+            Utc.from_utc_datetime(
+                &NaiveDate::from_ymd_opt(2022, 5, 7)
+                    .unwrap()
+                    .and_hms_opt(hour, minute, 0)
+                    .unwrap(),
+            )
+        };
+        stats.count_product(at(11, 59), 0, Some(16));
+        stats.count_product(at(12, 0), 2, Some(6));
+        stats.count_product(at(12, 30), 2, Some(6));
+        stats.count_product(at(12, 59), 130, None);
+
+        let snapshot = stats.snapshot();
+        let entry = |hour, filetype_code, product_id, count| ProductMixEntry {
+            hour: at(hour, 0),
+            filetype_code,
+            product_id,
+            count,
+        };
+        assert_eq!(
+            snapshot.product_mix,
+            vec![
+                entry(11, 0, Some(16), 1),
+                entry(12, 2, Some(6), 2),
+                entry(12, 130, None, 1)
+            ]
+        );
+
+        // only the last day is kept
+        for hour in 13..24 {
+            stats.count_product(at(hour, 0), 0, Some(16));
+        }
+        let next_day = Utc.from_utc_datetime(
+            &NaiveDate::from_ymd_opt(2022, 5, 8)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        );
+        for hour in 0..13 {
+            stats.count_product(next_day + chrono::Duration::hours(hour), 0, Some(16));
+        }
+        assert_eq!(stats.product_mix.len(), 24);
+        assert_eq!(stats.product_mix.back().unwrap().0, at(13, 0));
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(DownlinkMode::detect(0.0, vec![20, 63]), None);
+        assert_eq!(DownlinkMode::detect(48.0, vec![20, 63]), Some(DownlinkMode::Hrit));
+        assert_eq!(DownlinkMode::detect(15.0, vec![20, 63]), Some(DownlinkMode::Lrit));
+        // full disk imagery is only on HRIT, however fast the VCDUs arrive
+        assert_eq!(DownlinkMode::detect(0.0, vec![13]), Some(DownlinkMode::Hrit));
+        assert_eq!(DownlinkMode::Hrit.max_vcdus_per_second(), 49);
+        assert_eq!(DownlinkMode::Lrit.max_vcdus_per_second(), 16);
+
+        let mut stats = Stats::new();
+        assert_eq!(stats.mode(), None);
+        stats.record(Stat::APID(2, 32, 100));
+        assert_eq!(stats.snapshot().mode, Some(DownlinkMode::Hrit));
+        stats.set_mode(DownlinkMode::Lrit);
+        assert_eq!(stats.mode(), Some(DownlinkMode::Lrit));
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut stats = Stats::new();
+        let a = stats.add_source("tcp://a:5004");
+        let b = stats.add_source("tcp://b:5004");
+        assert_eq!(stats.sources[a].status(stats.now()), "no data yet");
+        stats.record(Stat::SourcePacket(a));
+        stats.record(Stat::SourceError(b, "connection reset".to_string()));
+        stats.record(Stat::Product(2, Some(6)));
+        stats.record(Stat::Product(2, Some(6)));
+        let decompression = Decompression {
+            compressed_bytes: 300,
+            decompressed_bytes: 1000,
+            scanlines: 2,
+            failed_scanlines: 1,
+            time_ms: 3.0,
+            ..Default::default()
+        };
+        stats.record(Stat::Decompression(Some(6), decompression.clone()));
+        stats.record(Stat::Decompression(Some(6), decompression));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.sources[a].status, "receiving");
+        assert_eq!(snapshot.sources[a].packets, 1);
+        assert_eq!(snapshot.sources[b].status, "failed: connection reset");
+        assert_eq!(snapshot.products[&2], 2);
+        assert_eq!(snapshot.compression.len(), 1);
+        assert_eq!(snapshot.compression[0].product_id, Some(6));
+        assert_eq!(snapshot.compression[0].failed_scanlines, 2);
+        assert_eq!(snapshot.compression[0].time.count, 2);
+        assert!((snapshot.compression[0].ratio() - 1000.0 / 300.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = ManualClock::new(Utc.with_ymd_and_hms(2022, 5, 7, 11, 59, 59).unwrap());
+        let mut stats = Stats::with_clock(Arc::new(clock.clone()));
+        let a = stats.add_source("tcp://a:5004");
+        for _ in 0..16 {
+            stats.record(Stat::VCDUPacket(2));
+            stats.record(Stat::SourcePacket(a));
+            clock.advance(Duration::from_millis(500));
+        }
+        stats.record(Stat::Product(2, Some(6)));
+
+        // 8 seconds of packets, in one second buckets
+        assert_eq!(stats.vcdu_packets.len(), 8);
+        assert_eq!(
+            recent_counts(&stats.vcdu_packets, stats.now(), Duration::from_secs(3))[&2],
+            6
+        );
+        assert_eq!(
+            stats.snapshot().product_mix[0].hour,
+            Utc.with_ymd_and_hms(2022, 5, 7, 12, 0, 0).unwrap()
+        );
+        // 2 VCDUs a second is too slow for HRIT, but only once there's been time to measure it
+        assert_eq!(stats.mode(), None);
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(stats.mode(), Some(DownlinkMode::Lrit));
+        assert_eq!(stats.snapshot().sources[a].status, "receiving");
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(stats.snapshot().sources[a].status, "idle for 12s");
+
+        // old buckets are forgotten
+        clock.advance(Duration::from_secs(60));
+        stats.record(Stat::VCDUPacket(2));
+        assert_eq!(stats.vcdu_packets.len(), 1);
+        assert_eq!(stats.uptime(), Duration::from_secs(80));
+    }
+
+    #[test]
+    fn test_rates() {
+        let clock = ManualClock::new(Utc::now());
+        let mut stats = Stats::with_clock(Arc::new(clock.clone()));
+        for _ in 0..20 {
+            for _ in 0..4 {
+                stats.record(Stat::VCDUPacket(13));
+                stats.record(Stat::APID(13, 1, 500));
+            }
+            stats.record(Stat::VCDUPacket(63));
+            clock.advance(Duration::from_secs(1));
+        }
+
+        let rates = stats.snapshot().rates;
+        assert_eq!(rates.window_secs, 10.0);
+        let vc13 = &rates.vcs[0];
+        assert_eq!((vc13.vcid, vc13.packets_per_second), (Vcid::FullDisk(13), 4.0));
+        assert_eq!(vc13.bits_per_second, 4.0 * 892.0 * 8.0);
+        assert_eq!(rates.vcs[1].vcid, Vcid::Fill);
+        assert_eq!(rates.packets_per_second, 5.0);
+        assert_eq!(
+            rates.apids,
+            vec![ApidRate {
+                vcid: Vcid::FullDisk(13),
+                apid: 1,
+                packets_per_second: 4.0,
+                bits_per_second: 4.0 * 500.0 * 8.0
+            }]
+        );
+
+        // a second after starting, the rate is per second, not per 10
+        let mut stats = Stats::with_clock(Arc::new(clock.clone()));
+        stats.record(Stat::VCDUPacket(13));
+        assert_eq!(stats.rates(RATE_WINDOW).packets_per_second, 1.0);
+    }
+
+    #[test]
+    fn test_link_quality() {
+        let clock = ManualClock::new(Utc::now());
+        let mut stats = Stats::with_clock(Arc::new(clock.clone()));
+        for _ in 0..4 {
+            for _ in 0..6 {
+                stats.record(Stat::VCDUPacket(13));
+            }
+            stats.record(Stat::VCDUPacket(63));
+            stats.record(Stat::VCDUPacket(63));
+            stats.record(Stat::DroppedPackets(2));
+            stats.record(Stat::CorruptPacket);
+            clock.advance(Duration::from_secs(1));
+        }
+        stats.record(Stat::FillTpPdu(13, 886));
+        stats.record(Stat::FillTpPdu(13, 886));
+        let quality = stats.link_quality(Duration::from_secs(2));
+        assert_eq!(quality.window_secs, 2.0);
+        // 2 of 16 VCDUs worth of fill TP_PDUs, and 4 fill VCDUs
+        assert_eq!(quality.utilization, 0.625);
+        assert_eq!(quality.vcdus_per_second, 8.0);
+        assert_eq!(quality.fill_ratio, 0.25);
+        assert_eq!(quality.drop_rate, 0.2);
+        assert_eq!(quality.error_rate, 0.125);
+        assert_eq!(stats.snapshot().dropped_packets, 8);
+        let mut stats = Stats::new();
+        for _ in 0..4 {
+            stats.record(Stat::Packet);
+        }
+        stats.record(Stat::FillPacket);
+        stats.record(Stat::FillTpPdu(2, 443));
+        assert_eq!(stats.utilization(), 0.625);
+        assert_eq!(stats.snapshot().fill_tp_pdus[0].tp_pdus, 1);
+
+        let quality = Stats::new().link_quality(Duration::from_secs(2));
+        assert_eq!(
+            quality,
+            LinkQuality {
+                window_secs: 1.0,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_time_histogram() {
+        let mut h = TimeHistogram::default();
+        assert_eq!(h.percentile_ms(95.0), 0.0);
+        for ms in [1, 3, 3, 4, 40, 60, 60, 80, 90, 20000] {
+            h.record(Duration::from_millis(ms));
+        }
+        assert_eq!(h.count, 10);
+        assert_eq!(h.mean_ms(), 2034.1);
+        assert_eq!(h.percentile_ms(50.0), 50.0);
+        assert_eq!(h.percentile_ms(90.0), 100.0);
+        assert_eq!(h.percentile_ms(100.0), 20000.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Where products are written
+//!
+//! Handlers write their products through a [`Storage`], rather than straight to the filesystem,
+//! so the same handlers can write to local disk ([`LocalStorage`], the default), to an S3 bucket
+//! ([`S3Storage`]), or to memory ([`MemoryStorage`], for tests that shouldn't touch the disk).
+//! Paths are the same whichever storage is used: the output root joined with the product's name.
+//!
+//! Only the products themselves (and their header sidecars) go through the storage.  The product
+//! index, history logs, symlinks, and the other bookkeeping files next to them are always on local
+//! disk.
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+};
+
+/// Somewhere products can be written, see the [module docs](self)
+pub trait Storage: Send + Sync {
+    /// Writes (or replaces) a whole file
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Makes `to` another name for the file at `from`, replacing anything that's already there
+    ///
+    /// Storage without links just copies the file.
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.write(to, &self.read(from)?)
+    }
+
+    /// Makes sure a file that was written is durable, for storage where that's a separate step
+    fn sync(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A storage that can be shared by every handler
+pub type SharedStorage = Arc<dyn Storage>;
+
+/// The local filesystem, which is what handlers use unless they're given something else
+pub fn local() -> SharedStorage {
+    Arc::new(LocalStorage)
+}
+
+/// Parses a storage URL, like `s3://bucket/prefix`, for products under `root`
+///
+/// Anything that isn't an `s3://` URL means the local filesystem.
+pub fn parse_storage(url: &str, root: impl AsRef<Path>) -> SharedStorage {
+    if url.starts_with("s3://") {
+        return Arc::new(S3Storage::new(url, root));
+    }
+    local()
+}
+
+/// Files on the local filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        std::fs::write(path, data)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if to.exists() {
+            std::fs::remove_file(to)?;
+        }
+        std::fs::hard_link(from, to)
+    }
+
+    fn sync(&self, path: &Path) -> io::Result<()> {
+        std::fs::File::open(path)?.sync_data()
+    }
+}
+
+/// Files kept in memory, keyed by path
+///
+/// Clones share the same files, so a test can give a clone to a handler and look at what it
+/// wrote afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    /// The paths of every file, in order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// The contents of a file, if it's there
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path.as_ref()).cloned()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.files.lock().unwrap().insert(path.to_path_buf(), data.to_vec());
+        Ok(())
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path.display().to_string()))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match self.files.lock().unwrap().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, path.display().to_string())),
+        }
+    }
+}
+
+/// An S3 bucket (or anything S3 compatible), used with the `aws` command
+///
+/// A file's key is its path relative to the output root, after the URL's prefix.  Credentials,
+/// the region, and the endpoint come from the usual AWS config and environment variables, like
+/// with mirroring to S3.
+#[derive(Debug, Clone)]
+pub struct S3Storage {
+    url: String,
+    root: PathBuf,
+}
+
+impl S3Storage {
+    /// `url` is like `s3://bucket/prefix`, and `root` is the output root
+    pub fn new(url: impl Into<String>, root: impl AsRef<Path>) -> S3Storage {
+        S3Storage {
+            url: url.into(),
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// The URL of the object for a path
+    pub fn key_url(&self, path: &Path) -> String {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let key: Vec<_> = relative
+            .components()
+            .filter_map(|c| match c {
+                std::path::Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        format!("{}/{}", self.url.trim_end_matches('/'), key.join("/"))
+    }
+}
+
+impl Storage for S3Storage {
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let url = self.key_url(path);
+        let mut command = Command::new("aws");
+        command.args(["s3", "cp", "--only-show-errors", "-"]).arg(&url);
+        pipe_to(&mut command, "aws", &url, data)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let url = self.key_url(path);
+        let output = Command::new("aws")
+            .args(["s3", "cp", "--only-show-errors"])
+            .arg(&url)
+            .arg("-")
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, url));
+        }
+        Ok(output.stdout)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        // `aws s3 ls` matches by prefix, so `A.TXT` would "exist" if only `A.TXT.gz` did
+        let url = self.key_url(path);
+        let Some((bucket, key)) = bucket_key(&url) else {
+            return false;
+        };
+        let status = Command::new("aws")
+            .args(["s3api", "head-object", "--bucket", bucket, "--key", key])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        status.is_ok_and(|s| s.success())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let url = self.key_url(path);
+        let status = Command::new("aws")
+            .args(["s3", "rm", "--only-show-errors"])
+            .arg(&url)
+            .status()?;
+        check_status("aws", &url, status)
+    }
+}
+
+/// Splits an `s3://bucket/key` URL into its bucket and key
+fn bucket_key(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("s3://")?.split_once('/')
+}
+
+/// Runs `command` with `data` on its stdin, turning a failure into an error
+///
+/// The command is always waited for, even when writing to it fails, so it isn't left a zombie.
+pub(crate) fn pipe_to(command: &mut Command, name: &str, dest: &str, data: &[u8]) -> io::Result<()> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(data),
+        None => Ok(()),
+    };
+    let status = child.wait()?;
+    check_status(name, dest, status)?;
+    written
+}
+
+/// Turns a failed command into an error
+pub(crate) fn check_status(command: &str, dest: &str, status: ExitStatus) -> io::Result<()> {
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("{} to {} failed: {}", command, dest, status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use std::process::Command;
+
+    use super::{bucket_key, local, pipe_to, MemoryStorage, S3Storage, Storage};
+
+    #[test]
+    fn test_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = MemoryStorage::new();
+        let storages: [Arc<dyn Storage>; 2] = [local(), Arc::new(memory.clone())];
+        for storage in storages {
+            let path = dir.path().join("product.txt");
+            storage.write(&path, b"hello").unwrap();
+            assert!(storage.exists(&path));
+            assert_eq!(storage.read(&path).unwrap(), b"hello");
+            storage.sync(&path).unwrap();
+            storage.link(&path, &dir.path().join("link.txt")).unwrap();
+            storage.link(&path, &dir.path().join("link.txt")).unwrap();
+            assert_eq!(storage.read(&dir.path().join("link.txt")).unwrap(), b"hello");
+            storage.remove(&path).unwrap();
+            assert!(!storage.exists(&path));
+            assert!(storage.read(&path).is_err());
+        }
+        // writes to memory never touch the disk
+        memory.write(&dir.path().join("a.txt"), b"a").unwrap();
+        assert_eq!(memory.paths(), [dir.path().join("a.txt"), dir.path().join("link.txt")]);
+        assert!(!dir.path().join("a.txt").exists());
+
+        let s3 = S3Storage::new("s3://goes/station1/", "/srv/goes");
+        assert_eq!(
+            s3.key_url(Path::new("/srv/goes/emwin/A_FXUS61KPHI.TXT")),
+            "s3://goes/station1/emwin/A_FXUS61KPHI.TXT"
+        );
+        assert_eq!(
+            bucket_key("s3://goes/station1/emwin/A_FXUS61KPHI.TXT"),
+            Some(("goes", "station1/emwin/A_FXUS61KPHI.TXT"))
+        );
+        assert_eq!(bucket_key("/srv/goes/A.TXT"), None);
+
+        // a command that exits without reading its stdin is still waited for, and its failure
+        // is what's reported
+        let data = vec![0; 1 << 20];
+        pipe_to(Command::new("sh").args(["-c", "cat > /dev/null"]), "sh", "null", &data).unwrap();
+        let err = pipe_to(Command::new("sh").args(["-c", "exit 3"]), "sh", "null", &data).unwrap_err();
+        assert!(err.to_string().starts_with("sh to null failed"), "{}", err);
+    }
+}
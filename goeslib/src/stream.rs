@@ -0,0 +1,234 @@
+//! Products as a stream of records, for using goesbox as one stage of a pipeline
+//!
+//! A [`StreamHandler`] writes a record for every product (or every product of the selected
+//! [`ProductClass`]es) to a writer, usually stdout.  Each record is the product's metadata as
+//! JSON, with the product data either inline (base64 encoded) or saved to a directory and given
+//! as a path.
+//!
+//! Records are either newline-delimited JSON ([`StreamFormat::Json`]), or each JSON record is
+//! preceded by its length as a 4 byte big-endian integer ([`StreamFormat::LengthPrefixed`]), which
+//! is easier to split in some languages.
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use byteorder::{BigEndian, WriteBytesExt};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::CachedProduct,
+    handlers::{Handler, HandlerError},
+    lrit::LRIT,
+    quota::ProductClass,
+};
+
+/// How records are separated, see the [module docs](self)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StreamFormat {
+    #[default]
+    Json,
+    LengthPrefixed,
+}
+
+impl std::str::FromStr for StreamFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StreamFormat::Json),
+            "length-prefixed" => Ok(StreamFormat::LengthPrefixed),
+            _ => Err("stream format must be json or length-prefixed"),
+        }
+    }
+}
+
+/// One product in the stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamRecord {
+    /// The annotation text (which is the original filename of the product)
+    pub name: String,
+    pub class: ProductClass,
+    pub received: DateTime<Utc>,
+    pub vcid: u8,
+    pub filetype_code: u8,
+    pub product_id: Option<u16>,
+    /// The size of the product data, in bytes
+    pub size: usize,
+    /// The product data (without the LRIT headers), base64 encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// Where the product data was saved, instead of being included in the record
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// Writes a record for every product to a writer; see the [module docs](self)
+pub struct StreamHandler<W> {
+    out: W,
+    format: StreamFormat,
+    /// Every class, if it's empty
+    classes: Vec<ProductClass>,
+    save_dir: Option<PathBuf>,
+}
+
+impl<W: Write + Send> StreamHandler<W> {
+    pub fn new(out: W) -> StreamHandler<W> {
+        StreamHandler {
+            out,
+            format: StreamFormat::Json,
+            classes: Vec::new(),
+            save_dir: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: StreamFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Only stream products of these classes (every product is streamed by default)
+    pub fn with_classes(mut self, classes: &[ProductClass]) -> Self {
+        self.classes = classes.to_vec();
+        self
+    }
+
+    /// Save product data to this directory, and give its path in each record, instead of
+    /// including the data in the record
+    pub fn with_save_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.save_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Writes the product data into the save directory, all at once, so that a reader never
+    /// sees part of a product
+    fn save(&self, dir: &Path, product: &CachedProduct) -> Result<PathBuf, HandlerError> {
+        // annotations are filenames, but make sure nothing is written outside of the directory
+        let name = Path::new(&product.name)
+            .file_name()
+            .ok_or(HandlerError::Parse("product name is not a filename"))?;
+        let path = dir.join(name);
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, &product.data)?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(path)
+    }
+}
+
+impl<W: Write + Send> Handler for StreamHandler<W> {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let product = CachedProduct::from_lrit(lrit, Utc::now()).ok_or(HandlerError::MissingHeader("annotation"))?;
+        if !self.classes.is_empty() && !self.classes.contains(&product.class) {
+            return Err(HandlerError::Skipped);
+        }
+        let (data, path) = match &self.save_dir {
+            Some(dir) => (None, Some(self.save(dir, &product)?)),
+            None => (Some(base64(&product.data)), None),
+        };
+        let record = StreamRecord {
+            name: product.name,
+            class: product.class,
+            received: product.received,
+            vcid: product.vcid,
+            filetype_code: product.filetype_code,
+            product_id: product.product_id,
+            size: product.data.len(),
+            data,
+            path,
+        };
+        let json = serde_json::to_vec(&record).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        match self.format {
+            StreamFormat::Json => {
+                self.out.write_all(&json)?;
+                self.out.write_all(b"\n")?;
+            }
+            StreamFormat::LengthPrefixed => {
+                self.out.write_u32::<BigEndian>(json.len() as u32)?;
+                self.out.write_all(&json)?;
+            }
+        }
+        // the next stage should get each product as soon as it's decoded
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        Ok(self.out.flush()?)
+    }
+}
+
+/// Standard base64, with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read};
+
+    use byteorder::{BigEndian, ReadBytesExt};
+
+    use super::{base64, StreamFormat, StreamHandler, StreamRecord};
+    use crate::{handlers::Handler, lrit::LRIT, quota::ProductClass, sim::LritBuilder};
+
+    fn lrit(filetype: u8, name: &str, data: &[u8]) -> LRIT {
+        LRIT::from_bytes(1, &LritBuilder::new(filetype).annotation(name).build(data)).unwrap()
+    }
+
+    #[test]
+    fn test_stream_handler() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+
+        let mut handler = StreamHandler::new(Vec::new()).with_classes(&[ProductClass::Text]);
+        handler.handle(&lrit(2, "text1", b"hello")).unwrap();
+        assert!(handler.handle(&lrit(0, "img1", b"pixels")).is_err());
+        let out = handler.into_inner();
+        let lines: Vec<String> = out.lines().map(Result::unwrap).collect();
+        assert_eq!(lines.len(), 1);
+        let record: StreamRecord = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!((record.name.as_str(), record.class), ("text1", ProductClass::Text));
+        assert_eq!((record.size, record.data.as_deref()), (5, Some("aGVsbG8=")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = StreamHandler::new(Vec::new())
+            .with_format(StreamFormat::LengthPrefixed)
+            .with_save_dir(dir.path());
+        handler.handle(&lrit(0, "img1", b"pixels")).unwrap();
+        handler.handle(&lrit(2, "text1", b"hello")).unwrap();
+        let out = handler.into_inner();
+        let mut reader = &out[..];
+        let mut records = Vec::new();
+        while let Ok(len) = reader.read_u32::<BigEndian>() {
+            let mut json = vec![0; len as usize];
+            reader.read_exact(&mut json).unwrap();
+            records.push(serde_json::from_slice::<StreamRecord>(&json).unwrap());
+        }
+        assert_eq!(records.len(), 2);
+        assert!(records[0].data.is_none());
+        assert_eq!(std::fs::read(records[0].path.as_ref().unwrap()).unwrap(), b"pixels");
+    }
+}
@@ -0,0 +1,263 @@
+//! Surveying which kinds of products are on the downlink
+//!
+//! New products show up on the downlink from time to time, and nothing handles them until someone
+//! notices.  A [`SurveyHandler`] counts every kind of LRIT file it sees (by file type, NOAA product
+//! ID, APID, and WMO heading), with a few example annotations of each, and writes a report at the
+//! end of every window.  Kinds that no handler knows about stand out in the report.
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    annotation::is_emwin_name,
+    emwin::wmo::AbbreviatedHeading,
+    handlers::{Handler, HandlerError},
+    lrit::{filetype_name, LRIT},
+};
+
+/// How many example annotations to keep for each kind of product
+const EXAMPLES: usize = 3;
+
+/// What makes one kind of product different from another
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ProductKind {
+    pub filetype_code: u8,
+    pub product_id: Option<u16>,
+    pub apid: Option<u16>,
+    /// The WMO heading of a text product, without its time (like "WFUS51 KPHI")
+    pub wmo_heading: Option<String>,
+}
+
+impl ProductKind {
+    pub fn of(lrit: &LRIT) -> ProductKind {
+        ProductKind {
+            filetype_code: lrit.headers.primary.filetype_code,
+            product_id: lrit.headers.noaa.as_ref().map(|n| n.product_id),
+            apid: lrit.apid,
+            wmo_heading: wmo_heading(lrit),
+        }
+    }
+}
+
+/// The WMO heading of a product, from an EMWIN style annotation (`A_WFUS51KPHI041812_C_...`), or
+/// from the first lines of an uncompressed text product
+fn wmo_heading(lrit: &LRIT) -> Option<String> {
+    if let Some(annotation) = &lrit.headers.annotation {
+        let text = &annotation.text;
+        if is_emwin_name(text) {
+            if let (Some(ttaaii), Some(cccc)) = (text.get(2..8), text.get(8..12)) {
+                return Some(format!("{} {}", ttaaii, cccc));
+            }
+        }
+    }
+    let compressed = matches!(&lrit.headers.noaa, Some(n) if n.noaa_compression != 0);
+    if compressed || !matches!(lrit.headers.primary.filetype_code, 1 | 2) {
+        return None;
+    }
+    let start = &lrit.data[..lrit.data.len().min(256)];
+    String::from_utf8_lossy(start)
+        .lines()
+        .take(3)
+        .find_map(|line| AbbreviatedHeading::parse(line.trim()))
+        .map(|heading| format!("{} {}", heading.ttaaii, heading.cccc))
+}
+
+/// How often a kind of product was seen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KindSummary {
+    #[serde(flatten)]
+    pub kind: ProductKind,
+    pub count: usize,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// The first few annotations, to tell what the product is
+    pub examples: Vec<String>,
+}
+
+/// Every kind of product seen since `start`
+#[derive(Debug, Clone)]
+pub struct ProductSurvey {
+    pub start: DateTime<Utc>,
+    kinds: BTreeMap<ProductKind, KindSummary>,
+}
+
+impl ProductSurvey {
+    pub fn new(start: DateTime<Utc>) -> ProductSurvey {
+        ProductSurvey {
+            start,
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, lrit: &LRIT, now: DateTime<Utc>) {
+        let kind = ProductKind::of(lrit);
+        let summary = self.kinds.entry(kind.clone()).or_insert_with(|| KindSummary {
+            kind,
+            count: 0,
+            first_seen: now,
+            last_seen: now,
+            examples: Vec::new(),
+        });
+        summary.count += 1;
+        summary.last_seen = now;
+        if let Some(annotation) = &lrit.headers.annotation {
+            if summary.examples.len() < EXAMPLES && !summary.examples.contains(&annotation.text) {
+                summary.examples.push(annotation.text.clone());
+            }
+        }
+    }
+
+    /// Every kind of product, ordered by file type, product ID, APID, and heading
+    pub fn kinds(&self) -> impl Iterator<Item = &KindSummary> {
+        self.kinds.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinds.is_empty()
+    }
+
+    /// Writes a human readable version of the survey, up to `end`
+    pub fn write(&self, w: &mut impl Write, end: DateTime<Utc>) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "Product survey from {} to {}",
+            self.start.format("%Y-%m-%d %H:%M"),
+            end.format("%Y-%m-%d %H:%M UTC")
+        )?;
+        writeln!(w)?;
+        for summary in self.kinds() {
+            let kind = &summary.kind;
+            let mut line = match filetype_name(kind.filetype_code) {
+                Some(name) => format!("{} (type {})", name, kind.filetype_code),
+                None => format!("type {}", kind.filetype_code),
+            };
+            if let Some(product_id) = kind.product_id {
+                line += &format!(", product {}", product_id);
+            }
+            if let Some(apid) = kind.apid {
+                line += &format!(", APID {}", apid);
+            }
+            if let Some(heading) = &kind.wmo_heading {
+                line += &format!(", {}", heading);
+            }
+            writeln!(w, "{}: {} files", line, summary.count)?;
+            for example in &summary.examples {
+                writeln!(w, "    {}", example)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps a [`ProductSurvey`] of every LRIT file, writing it to the `survey` directory of the output
+/// root at the end of each window (a day, by default)
+///
+/// Each window is written as a text report and as JSON, named after the time the window started.
+/// Whatever has been surveyed is also written when the handler is flushed.
+pub struct SurveyHandler {
+    dir: PathBuf,
+    window: Duration,
+    survey: ProductSurvey,
+}
+
+impl SurveyHandler {
+    pub fn new(root: impl AsRef<Path>) -> SurveyHandler {
+        SurveyHandler {
+            dir: root.as_ref().join("survey"),
+            window: Duration::days(1),
+            survey: ProductSurvey::new(Utc::now()),
+        }
+    }
+
+    /// Write a report and start a new survey this often (rounded down to whole seconds, and at
+    /// least one second)
+    pub fn with_window(mut self, window: std::time::Duration) -> Self {
+        self.window = Duration::seconds(window.as_secs().max(1) as i64);
+        self
+    }
+
+    fn write(&self, end: DateTime<Utc>) -> Result<(), HandlerError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let stem = self.dir.join(self.survey.start.format("%Y%m%dT%H%M").to_string());
+        let mut text = Vec::new();
+        self.survey.write(&mut text, end)?;
+        std::fs::write(stem.with_extension("txt"), text)?;
+        let kinds: Vec<_> = self.survey.kinds().collect();
+        let json = serde_json::to_vec_pretty(&kinds).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        std::fs::write(stem.with_extension("json"), json)?;
+        info!(
+            "Wrote a survey of {} kinds of products to {}",
+            kinds.len(),
+            stem.display()
+        );
+        Ok(())
+    }
+}
+
+impl Handler for SurveyHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let now = Utc::now();
+        if now - self.survey.start >= self.window {
+            if !self.survey.is_empty() {
+                self.write(now)?;
+            }
+            self.survey = ProductSurvey::new(now);
+        }
+        self.survey.record(lrit, now);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        if self.survey.is_empty() {
+            return Ok(());
+        }
+        self.write(Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{ProductKind, ProductSurvey};
+    use crate::{lrit::LRIT, sim::LritBuilder};
+
+    #[test]
+    fn test_survey() {
+        let start = Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap();
+        let mut survey = ProductSurvey::new(start);
+
+        let text = |annotation: &str, data: &str| {
+            LRIT::from_bytes(20, &LritBuilder::new(2).annotation(annotation).build(data.as_bytes())).unwrap()
+        };
+        let tor = text("A_WFUS51KPHI041812_C_KWIN_20220504181303_881368-1-TORPHIPA.TXT", "...");
+        let gts = text("bulletin.txt", "\r\r\n001\r\r\nSAUS70 KWBC 041800\r\r\nMETAR...");
+        for minute in 0..3 {
+            survey.record(&tor, start + Duration::minutes(minute));
+        }
+        survey.record(&gts, start + Duration::minutes(5));
+
+        let kinds: Vec<_> = survey.kinds().collect();
+        assert_eq!(kinds.len(), 2);
+        let tor_kind = kinds.iter().find(|k| k.kind == ProductKind::of(&tor)).unwrap();
+        assert_eq!(tor_kind.kind.wmo_heading.as_deref(), Some("WFUS51 KPHI"));
+        assert_eq!(tor_kind.count, 3);
+        assert_eq!(tor_kind.examples.len(), 1);
+        assert_eq!(tor_kind.last_seen - tor_kind.first_seen, Duration::minutes(2));
+        assert_eq!(ProductKind::of(&gts).wmo_heading.as_deref(), Some("SAUS70 KWBC"));
+        let map = text("Z_MAPFILE_20220504.png", "...");
+        assert_eq!(ProductKind::of(&map).wmo_heading, None);
+
+        let mut out = Vec::new();
+        survey.write(&mut out, start + Duration::hours(1)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Product survey from 2022-05-04 12:00 to 2022-05-04 13:00 UTC\n"));
+        assert!(out.contains("Text (type 2), WFUS51 KPHI: 3 files\n    A_WFUS51KPHI041812"));
+    }
+}
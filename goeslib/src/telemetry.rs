@@ -0,0 +1,276 @@
+//! Counting the codes the parsers don't know
+//!
+//! The WMO lookup tables and the LRIT header parser only know what someone has added to them.
+//! [`UnknownCodes`] counts the T2 codes and area designators in EMWIN filenames that the parser
+//! doesn't know, and the LRIT header types that made files get dropped, with a few example
+//! filenames of each.  An [`UnknownCodeHandler`] writes the counts to the `telemetry` directory of
+//! the output root now and then, so they can be attached to a bug report.
+//!
+//! Nothing is sent anywhere; the report is only written locally, and only when the handler is
+//! set up (it's opt-in, with `[telemetry]` in the goesbox config).
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    annotation::is_emwin_name,
+    emwin::{wmo::WmoError, EmwinNameError, ParsedEmwinName},
+    handlers::{Handler, HandlerError},
+    lrit::LRIT,
+    reparse::has_unknown_t2,
+};
+
+/// How many example filenames to keep for each code
+const EXAMPLES: usize = 3;
+
+/// How often an unknown code was seen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodeCount {
+    pub count: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// The first few filenames it was seen in
+    pub examples: Vec<String>,
+}
+
+impl CodeCount {
+    fn record(counts: &mut BTreeMap<String, CodeCount>, code: String, example: Option<&str>, now: DateTime<Utc>) {
+        let count = counts.entry(code).or_insert_with(|| CodeCount {
+            count: 0,
+            first_seen: now,
+            last_seen: now,
+            examples: Vec::new(),
+        });
+        count.count += 1;
+        count.last_seen = now;
+        if let Some(example) = example {
+            if count.examples.len() < EXAMPLES && !count.examples.iter().any(|e| e == example) {
+                count.examples.push(example.to_string());
+            }
+        }
+    }
+}
+
+/// Every unknown code seen since `start`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnknownCodeReport {
+    pub start: DateTime<Utc>,
+    /// Keyed by T1T2, like "NQ"
+    pub t2_codes: BTreeMap<String, CodeCount>,
+    /// Keyed by T1T2A1A2, like "FPQQ"
+    pub area_designators: BTreeMap<String, CodeCount>,
+    /// Keyed by header type
+    pub header_types: BTreeMap<String, CodeCount>,
+}
+
+impl UnknownCodeReport {
+    pub fn is_empty(&self) -> bool {
+        self.t2_codes.is_empty() && self.area_designators.is_empty() && self.header_types.is_empty()
+    }
+
+    /// Writes a human readable version of the report, up to `end`
+    pub fn write(&self, w: &mut impl Write, end: DateTime<Utc>) -> std::io::Result<()> {
+        writeln!(
+            w,
+            "Unknown codes from {} to {}",
+            self.start.format("%Y-%m-%d %H:%M"),
+            end.format("%Y-%m-%d %H:%M UTC")
+        )?;
+        for (title, counts) in [
+            ("T2 codes (T1T2)", &self.t2_codes),
+            ("Area designators (T1T2A1A2)", &self.area_designators),
+            ("LRIT header types", &self.header_types),
+        ] {
+            if counts.is_empty() {
+                continue;
+            }
+            writeln!(w)?;
+            writeln!(w, "{}:", title)?;
+            for (code, count) in counts {
+                writeln!(w, "{}: {} times", code, count.count)?;
+                for example in &count.examples {
+                    writeln!(w, "    {}", example)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Counts of the codes the parsers don't know
+///
+/// Header types are counted by the decoder (in [`Stats`](crate::stats::Stats)) and filenames by
+/// the [`UnknownCodeHandler`], which can run on another thread, so this is cheap to clone and
+/// every clone shares the same counts.
+#[derive(Debug, Clone)]
+pub struct UnknownCodes {
+    report: Arc<Mutex<UnknownCodeReport>>,
+}
+
+impl Default for UnknownCodes {
+    fn default() -> Self {
+        UnknownCodes::new(Utc::now())
+    }
+}
+
+impl UnknownCodes {
+    pub fn new(start: DateTime<Utc>) -> UnknownCodes {
+        UnknownCodes {
+            report: Arc::new(Mutex::new(UnknownCodeReport {
+                start,
+                t2_codes: BTreeMap::new(),
+                area_designators: BTreeMap::new(),
+                header_types: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Counts the unknown codes (if any) in an EMWIN filename, like `A_NQUS41KPHI041812_C_...`
+    ///
+    /// Anything that isn't named like an EMWIN product is ignored.
+    pub fn record_name(&self, name: &str, now: DateTime<Utc>) {
+        if !is_emwin_name(name) {
+            return;
+        }
+        let mut report = self.report.lock().unwrap();
+        match ParsedEmwinName::parse(name) {
+            Ok(parsed) if has_unknown_t2(&parsed) => {
+                CodeCount::record(&mut report.t2_codes, name[2..4].to_string(), Some(name), now)
+            }
+            Err(EmwinNameError::Wmo(WmoError::UnknownT2(t1t2))) => {
+                CodeCount::record(&mut report.t2_codes, t1t2, Some(name), now)
+            }
+            Err(EmwinNameError::Wmo(WmoError::UnknownArea(ttaa))) => {
+                CodeCount::record(&mut report.area_designators, ttaa, Some(name), now)
+            }
+            Ok(_) | Err(_) => {}
+        }
+    }
+
+    /// Counts an LRIT header type the header parser doesn't know
+    pub fn record_header_type(&self, header_type: u8, now: DateTime<Utc>) {
+        let mut report = self.report.lock().unwrap();
+        CodeCount::record(&mut report.header_types, header_type.to_string(), None, now);
+    }
+
+    pub fn report(&self) -> UnknownCodeReport {
+        self.report.lock().unwrap().clone()
+    }
+}
+
+/// Writes the [`UnknownCodes`] report to the `telemetry` directory of the output root every so
+/// often (an hour, by default), counting the filenames of the LRIT files it's given
+///
+/// The report covers everything since the handler started, and is written as text and as JSON
+/// (`unknown-codes.txt` and `unknown-codes.json`), replacing the last one.  It's also written when
+/// the handler is flushed.
+pub struct UnknownCodeHandler {
+    dir: PathBuf,
+    codes: UnknownCodes,
+    interval: Duration,
+    last_write: DateTime<Utc>,
+}
+
+impl UnknownCodeHandler {
+    /// `codes` should be shared with the [`Stats`](crate::stats::Stats) of the decoder, so
+    /// unknown header types are counted too
+    pub fn new(root: impl AsRef<Path>, codes: UnknownCodes) -> UnknownCodeHandler {
+        UnknownCodeHandler {
+            dir: root.as_ref().join("telemetry"),
+            codes,
+            interval: Duration::hours(1),
+            last_write: Utc::now(),
+        }
+    }
+
+    /// Write the report this often (rounded down to whole seconds, and at least one second)
+    pub fn with_interval(mut self, interval: std::time::Duration) -> Self {
+        self.interval = Duration::seconds(interval.as_secs().max(1) as i64);
+        self
+    }
+
+    fn write(&mut self, now: DateTime<Utc>) -> Result<(), HandlerError> {
+        self.last_write = now;
+        let report = self.codes.report();
+        if report.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        let mut text = Vec::new();
+        report.write(&mut text, now)?;
+        std::fs::write(self.dir.join("unknown-codes.txt"), text)?;
+        let json = serde_json::to_vec_pretty(&report).map_err(|e| HandlerError::Other(Box::new(e)))?;
+        std::fs::write(self.dir.join("unknown-codes.json"), json)?;
+        info!("Wrote a report of unknown codes to {}", self.dir.display());
+        Ok(())
+    }
+}
+
+impl Handler for UnknownCodeHandler {
+    fn handle(&mut self, lrit: &LRIT) -> Result<(), HandlerError> {
+        let now = Utc::now();
+        if let Some(annotation) = &lrit.headers.annotation {
+            self.codes.record_name(&annotation.text, now);
+        }
+        if now - self.last_write >= self.interval {
+            self.write(now)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), HandlerError> {
+        self.write(Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{UnknownCodeHandler, UnknownCodes};
+    use crate::{handlers::Handler, lrit::LRIT, sim::LritBuilder};
+
+    #[test]
+    fn test_unknown_codes() {
+        let start = Utc.with_ymd_and_hms(2022, 5, 4, 12, 0, 0).unwrap();
+        let codes = UnknownCodes::new(start);
+        let notice = "A_NQUS41KPHI041812_C_KWIN_20220504181303_881367-3-ADMPHIPA.TXT";
+        codes.record_name(notice, start);
+        codes.record_name(notice, start + Duration::minutes(5));
+        codes.record_name("A_FPQQ20KWBN071250_C_KWIN_20220507125113_106868-3-SCSWBNUS.TXT", start);
+        codes.record_name("A_SAUS70KWBC071200_C_KWIN_20220507120113_106868-3-MTRBOSMA.TXT", start);
+        codes.record_name("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit", start);
+        codes.record_header_type(7, start);
+
+        let report = codes.report();
+        let nq = &report.t2_codes["NQ"];
+        assert_eq!((nq.count, nq.examples.len()), (2, 1));
+        assert_eq!(nq.last_seen - nq.first_seen, Duration::minutes(5));
+        assert_eq!(report.area_designators["FPQQ"].count, 1);
+        assert_eq!(report.header_types["7"].count, 1);
+        assert_eq!(report.t2_codes.len() + report.area_designators.len(), 2);
+
+        let mut out = Vec::new();
+        report.write(&mut out, start + Duration::hours(1)).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("T2 codes (T1T2):\nNQ: 2 times\n    A_NQUS41KPHI041812"));
+        assert!(out.contains("LRIT header types:\n7: 1 times\n"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut handler = UnknownCodeHandler::new(dir.path(), codes.clone());
+        let lrit = LRIT::from_bytes(20, &LritBuilder::new(2).annotation(notice).build(b"...")).unwrap();
+        handler.handle(&lrit).unwrap();
+        handler.flush().unwrap();
+        assert_eq!(codes.report().t2_codes["NQ"].count, 3);
+        let json = std::fs::read_to_string(dir.path().join("telemetry/unknown-codes.json")).unwrap();
+        assert!(json.contains("\"FPQQ\""));
+        assert!(dir.path().join("telemetry/unknown-codes.txt").exists());
+    }
+}
@@ -0,0 +1,145 @@
+//! Keeping test products out of the archive
+//!
+//! The broadcast carries test products now and then: the required weekly and monthly tests of the
+//! alerting system (RWT and RMT), and practice warnings (DMO).  They're sent just like real
+//! warnings, so without a filter they end up in the archive next to everything else, and can set
+//! off notifications.  A [`TestProductFilter`] in the
+//! [`Dispatcher`](crate::handlers::Dispatcher) keeps them away from the handlers, and either
+//! drops them or saves them in a `test` directory of their own.
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{annotation::LritFilename, handlers::text_files, lrit::LRIT};
+
+/// The NWS product categories that are tests, by default
+pub const TEST_CATEGORIES: [&str; 3] = ["RWT", "RMT", "DMO"];
+
+/// Recognizes test products, by the NWS product category in their EMWIN filename
+pub struct TestProductFilter {
+    categories: Vec<String>,
+    dir: Option<PathBuf>,
+}
+
+impl Default for TestProductFilter {
+    fn default() -> Self {
+        TestProductFilter::new()
+    }
+}
+
+impl TestProductFilter {
+    /// Drops products in the [`TEST_CATEGORIES`]
+    pub fn new() -> TestProductFilter {
+        TestProductFilter {
+            categories: TEST_CATEGORIES.iter().map(|c| c.to_string()).collect(),
+            dir: None,
+        }
+    }
+
+    /// Treat products in these categories (like `RWT`) as tests instead
+    pub fn with_categories(mut self, categories: Vec<String>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Save test products in a `test` directory under `root`, instead of dropping them
+    pub fn with_dir(mut self, root: impl AsRef<Path>) -> Self {
+        self.dir = Some(root.as_ref().join("test"));
+        self
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Returns true if this is a test product
+    pub fn is_test(&self, lrit: &LRIT) -> bool {
+        let annotation = match &lrit.headers.annotation {
+            Some(annotation) => &annotation.text,
+            None => return false,
+        };
+        match LritFilename::parse(annotation) {
+            LritFilename::Emwin(emwin) => self
+                .categories
+                .iter()
+                .any(|category| emwin.legacy_filename.starts_with(category.as_str())),
+            _ => false,
+        }
+    }
+
+    /// Saves a test product in the `test` directory, if there is one, returning the paths of the
+    /// files that were written
+    ///
+    /// Compressed products are decompressed first, like the text handler does.  If that fails,
+    /// the product is saved as it was received.
+    pub fn divert(&self, lrit: &LRIT) -> io::Result<Vec<PathBuf>> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return Ok(Vec::new()),
+        };
+        let annotation = lrit
+            .headers
+            .annotation
+            .as_ref()
+            .map(|a| a.text.as_str())
+            .unwrap_or_default();
+        let files = text_files(lrit, annotation).unwrap_or_else(|_| vec![(annotation.to_string(), lrit.data.clone())]);
+
+        std::fs::create_dir_all(dir)?;
+        let mut paths = Vec::new();
+        for (name, data) in files {
+            // names from inside a ZIP file could have directories in them
+            let name = match Path::new(&name).file_name() {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let path = dir.join(name);
+            std::fs::write(&path, data)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestProductFilter;
+    use crate::{lrit::LRIT, sim::LritBuilder};
+
+    fn text(name: &str, data: &[u8]) -> LRIT {
+        LRIT::from_bytes(20, &LritBuilder::new(2).annotation(name).build(data)).unwrap()
+    }
+
+    #[test]
+    fn test_test_products() {
+        let rwt = text(
+            "A_NPUS51KPHI071000_C_KWIN_20220507100012_000001-3-RWTPHIPA.TXT",
+            b"REQUIRED WEEKLY TEST",
+        );
+        let afd = text(
+            "A_FXUS61KPHI071005_C_KWIN_20220507100512_000002-3-AFDPHIPA.TXT",
+            b"AREA FORECAST DISCUSSION",
+        );
+        let filter = TestProductFilter::new();
+        assert!(filter.is_test(&rwt));
+        assert!(!filter.is_test(&afd));
+        assert!(!filter.is_test(&text("not an emwin name", b"")));
+        // without a directory, test products are dropped
+        assert!(filter.divert(&rwt).unwrap().is_empty());
+
+        let filter = TestProductFilter::new().with_categories(vec!["AFD".into()]);
+        assert!(!filter.is_test(&rwt));
+        assert!(filter.is_test(&afd));
+
+        let dir = tempfile::tempdir().unwrap();
+        let filter = TestProductFilter::new().with_dir(dir.path());
+        let paths = filter.divert(&rwt).unwrap();
+        let expected = dir
+            .path()
+            .join("test")
+            .join("A_NPUS51KPHI071000_C_KWIN_20220507100012_000001-3-RWTPHIPA.TXT");
+        assert_eq!(std::fs::read(&expected).unwrap(), b"REQUIRED WEEKLY TEST");
+        assert_eq!(paths, [expected]);
+    }
+}
@@ -0,0 +1,294 @@
+//! Building time-lapse animations out of saved images
+//!
+//! Frames are chosen from the [`ProductIndex`](crate::index::ProductIndex), and can optionally be
+//! filtered to skip "night" frames.  This is mostly useful for visible band loops, which would
+//! otherwise contain hours of black frames.
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use image::{codecs::gif::GifEncoder, Delay, Frame};
+use log::warn;
+
+use crate::{annotation::GoesRFilename, index::IndexRecord};
+
+/// Computes the elevation of the sun (in degrees above the horizon) at a given time and location
+///
+/// Latitude and longitude are in degrees, with east longitudes being positive.
+///
+/// This uses the NOAA "General Solar Position" approximation, which is accurate to within a
+/// fraction of a degree -- more than enough to decide if it's day or night.
+pub fn solar_elevation(time: DateTime<Utc>, lat: f64, lon: f64) -> f64 {
+    use std::f64::consts::PI;
+
+    let hour = time.hour() as f64 + time.minute() as f64 / 60.0 + time.second() as f64 / 3600.0;
+
+    // fractional year, in radians
+    let gamma = 2.0 * PI / 365.0 * (time.ordinal0() as f64 + (hour - 12.0) / 24.0);
+
+    // equation of time, in minutes
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+
+    // solar declination, in radians
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+
+    // true solar time, in minutes
+    let tst = hour * 60.0 + eqtime + 4.0 * lon;
+    let hour_angle = (tst / 4.0 - 180.0).to_radians();
+
+    let lat = lat.to_radians();
+    let cos_zenith = lat.sin() * decl.sin() + lat.cos() * decl.cos() * hour_angle.cos();
+
+    90.0 - cos_zenith.clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+/// A single frame of a time-lapse
+#[derive(Debug, Clone)]
+pub struct TimelapseFrame {
+    pub time: DateTime<Utc>,
+    pub path: PathBuf,
+    /// The mesoscale sector, if it's a mesoscale image
+    pub sector: Option<String>,
+}
+
+/// Chooses and renders frames for a time-lapse animation
+pub struct Timelapse {
+    /// Longitude of the sub-satellite point, if night frames should be skipped
+    skip_night_lon: Option<f64>,
+    /// Frames are skipped if the sun is lower than this (in degrees) at the sub-satellite point
+    min_sun_elevation: f64,
+    /// Frames are scaled down so that neither dimension is larger than this
+    max_dimension: u32,
+    frame_delay_ms: u32,
+}
+
+impl Timelapse {
+    pub fn new() -> Timelapse {
+        Timelapse {
+            skip_night_lon: None,
+            min_sun_elevation: 0.0,
+            max_dimension: 1024,
+            frame_delay_ms: 100,
+        }
+    }
+
+    /// Skip frames when the sun is below the horizon at the sub-satellite point
+    ///
+    /// The sub-satellite point is always on the equator, so only the satellite longitude is needed
+    /// (for example, -75.2 for GOES-East and -137.2 for GOES-West)
+    pub fn skip_night(mut self, satellite_lon: f64) -> Self {
+        self.skip_night_lon = Some(satellite_lon);
+        self
+    }
+
+    /// The sun elevation (in degrees) below which frames are considered to be "night"
+    pub fn min_sun_elevation(mut self, degrees: f64) -> Self {
+        self.min_sun_elevation = degrees;
+        self
+    }
+
+    pub fn max_dimension(mut self, pixels: u32) -> Self {
+        self.max_dimension = pixels;
+        self
+    }
+
+    pub fn frame_delay_ms(mut self, ms: u32) -> Self {
+        self.frame_delay_ms = ms;
+        self
+    }
+
+    /// Returns true if a frame taken at the given time should be included
+    pub fn want_frame(&self, time: DateTime<Utc>) -> bool {
+        match self.skip_night_lon {
+            Some(lon) => solar_elevation(time, 0.0, lon) >= self.min_sun_elevation,
+            None => true,
+        }
+    }
+
+    /// Picks frames for all images whose annotation contains `pattern`
+    ///
+    /// Images are expected to have been written by the `ImageHandler` into `output_root`.  The
+    /// returned frames are sorted by time, and each image is only included once.
+    pub fn select_frames(&self, output_root: &Path, records: &[IndexRecord], pattern: &str) -> Vec<TimelapseFrame> {
+        let mut frames: Vec<TimelapseFrame> = Vec::new();
+        for rec in records.iter().filter(|r| r.annotation.contains(pattern)) {
+            let path = output_root.join(&rec.annotation).with_extension("jpg");
+            if frames.iter().any(|f| f.path == path) {
+                // later segments of an image we've already got
+                continue;
+            }
+            if self.want_frame(rec.received) && path.exists() {
+                frames.push(TimelapseFrame {
+                    time: rec.received,
+                    path,
+                    sector: rec.sector.clone(),
+                });
+            }
+        }
+        frames.sort_by_key(|f| f.time);
+        frames
+    }
+
+    /// Picks frames for every image in a directory (like `suvi/Fe195`)
+    ///
+    /// Frames are timed by the scan time in their GOES-R file name, or by when they were written.
+    pub fn frames_in_dir(&self, dir: &Path) -> io::Result<Vec<TimelapseFrame>> {
+        let mut frames = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !matches!(path.extension().and_then(|e| e.to_str()), Some("png" | "jpg" | "gif")) {
+                continue;
+            }
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let time = match GoesRFilename::parse(&name).and_then(|g| g.scan_start) {
+                Some(time) => time,
+                None => DateTime::<Utc>::from(std::fs::metadata(&path)?.modified()?),
+            };
+            if self.want_frame(time) {
+                frames.push(TimelapseFrame {
+                    time,
+                    path,
+                    sector: None,
+                });
+            }
+        }
+        frames.sort_by_key(|f| f.time);
+        Ok(frames)
+    }
+
+    /// Splits frames (sorted by time) into one loop for each place a mesoscale sector was
+    ///
+    /// A sector that moves starts a new loop, so frames of different places aren't mixed.  Frames
+    /// of the two mesoscale sectors go in separate loops, and frames that aren't of a mesoscale
+    /// sector all go in one loop.
+    pub fn sector_loops(frames: Vec<TimelapseFrame>) -> Vec<Vec<TimelapseFrame>> {
+        let mut loops: Vec<Vec<TimelapseFrame>> = Vec::new();
+        for frame in frames {
+            let latest = loops
+                .iter_mut()
+                .rev()
+                .find(|l| sector_region(&l[0]) == sector_region(&frame));
+            match latest {
+                Some(l) if l[0].sector == frame.sector => l.push(frame),
+                _ => loops.push(vec![frame]),
+            }
+        }
+        loops
+    }
+
+    /// Renders a list of frames into an animated GIF
+    ///
+    /// Frames that fail to load are skipped with a warning
+    pub fn write_gif(&self, frames: &[TimelapseFrame], output: impl AsRef<Path>) -> image::ImageResult<()> {
+        let file = std::fs::File::create(output)?;
+        let mut encoder = GifEncoder::new(file);
+
+        for frame in frames {
+            let img = match image::open(&frame.path) {
+                Ok(img) => img,
+                Err(e) => {
+                    warn!("Skipping frame {}: {}", frame.path.display(), e);
+                    continue;
+                }
+            };
+            let img = img.thumbnail(self.max_dimension, self.max_dimension).to_rgba8();
+            encoder.encode_frame(Frame::from_parts(
+                img,
+                0,
+                0,
+                Delay::from_numer_denom_ms(self.frame_delay_ms, 1),
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+/// The mesoscale sector a frame is of, like "M1" (from a sector like "M1_35.2N_097.5W")
+fn sector_region(frame: &TimelapseFrame) -> Option<&str> {
+    frame.sector.as_deref().and_then(|s| s.split('_').next())
+}
+
+impl Default for Timelapse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    use std::path::PathBuf;
+
+    use super::{solar_elevation, Timelapse, TimelapseFrame};
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.from_utc_datetime(&NaiveDate::from_ymd_opt(y, m, d).unwrap().and_hms_opt(h, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn test_solar_elevation() {
+        // near the march equinox, the sun is nearly overhead at the equator at local noon
+        let noon = utc(2022, 3, 20, 12);
+        assert!(solar_elevation(noon, 0.0, 0.0) > 85.0);
+        let midnight = utc(2022, 3, 20, 0);
+        assert!(solar_elevation(midnight, 0.0, 0.0) < -85.0);
+
+        // at the june solstice, the noon sun at the tropic of cancer is overhead
+        let solstice = utc(2022, 6, 21, 12);
+        assert!((solar_elevation(solstice, 23.44, 0.0) - 90.0).abs() < 1.0);
+
+        // local noon for GOES-East is around 17:00 UTC
+        let goes_east_noon = utc(2022, 5, 4, 17);
+        assert!(solar_elevation(goes_east_noon, 0.0, -75.2) > 70.0);
+    }
+
+    #[test]
+    fn test_skip_night() {
+        let tl = Timelapse::new().skip_night(-75.2);
+        assert!(tl.want_frame(utc(2022, 5, 4, 17)));
+        assert!(!tl.want_frame(utc(2022, 5, 4, 5)));
+
+        let tl = Timelapse::new();
+        assert!(tl.want_frame(utc(2022, 5, 4, 5)));
+    }
+
+    #[test]
+    fn test_sector_loops() {
+        let frame = |h, sector: &str| TimelapseFrame {
+            time: utc(2022, 5, 4, h),
+            path: PathBuf::from(format!("{}.jpg", h)),
+            sector: Some(sector.to_string()),
+        };
+        let frames = vec![
+            frame(1, "M1_35.2N_097.5W"),
+            frame(2, "M2_20.0N_080.0W"),
+            frame(3, "M1_35.2N_097.5W"),
+            frame(4, "M1_40.0N_090.0W"),
+            frame(5, "M2_20.0N_080.0W"),
+            frame(6, "M1_35.2N_097.5W"),
+        ];
+        let loops: Vec<Vec<_>> = Timelapse::sector_loops(frames)
+            .iter()
+            .map(|l| l.iter().map(|f| f.path.to_string_lossy().into_owned()).collect())
+            .collect();
+        assert_eq!(
+            loops,
+            vec![
+                vec!["1.jpg", "3.jpg"],
+                vec!["2.jpg", "5.jpg"],
+                vec!["4.jpg"],
+                vec!["6.jpg"],
+            ]
+        );
+    }
+}
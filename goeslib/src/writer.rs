@@ -0,0 +1,501 @@
+//! Batched file writes on a dedicated IO thread
+//!
+//! Text products arrive as a steady trickle of tiny files, and writing each one synchronously from
+//! the decoding thread means lots of small writes (and, with `fsync`, lots of flash erase cycles on
+//! an SD card).  A [`BatchWriter`] collects writes for a short time, then writes them all at once
+//! from its own thread, optionally syncing them to disk once per batch.
+//!
+//! `O_DIRECT` isn't offered: it needs block-aligned buffers and lengths, which small text products
+//! never have, and the page cache is exactly what makes batching cheap.
+//!
+//! When the disk fills up or is remounted read-only, writes that fail are kept in a spool in
+//! memory instead of being lost.  After a few failures in a row the writer stops trying the disk,
+//! spools everything, and only checks every so often whether the disk has recovered, at which point
+//! the spool is written out.  See [`BatchWriter::health`].
+use std::{
+    collections::VecDeque,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+
+use crate::storage::{self, SharedStorage, Storage};
+
+/// When written files are synced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the OS
+    None,
+    /// Sync every file as soon as it's written
+    EachFile,
+    /// Sync all files of a batch after the whole batch is written
+    EndOfBatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Write the batch once it holds this many bytes
+    pub max_bytes: usize,
+    /// Write the batch once its oldest write has waited this long
+    pub max_delay: Duration,
+    /// How many writes can be queued before `write` blocks
+    pub queue_len: usize,
+    pub sync: SyncPolicy,
+    /// How much to keep in memory while the disk is full or read-only; the oldest files are
+    /// dropped past this
+    pub spool_bytes: usize,
+    /// Stop trying the disk after this many full or read-only failures in a row
+    pub failure_threshold: u32,
+    /// How often to check whether the disk has recovered, once the writer has stopped trying it
+    pub probe_interval: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            max_bytes: 1024 * 1024,
+            max_delay: Duration::from_secs(2),
+            queue_len: 1024,
+            sync: SyncPolicy::None,
+            spool_bytes: 64 * 1024 * 1024,
+            failure_threshold: 3,
+            probe_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+enum Job {
+    Write(PathBuf, Vec<u8>),
+    Flush(mpsc::Sender<()>),
+    Shutdown,
+}
+
+/// A handle for queueing writes on a [`BatchWriter`]
+///
+/// This is cheap to clone, so each handler can have its own.
+#[derive(Clone)]
+pub struct WriteQueue {
+    sender: SyncSender<Job>,
+}
+
+impl WriteQueue {
+    /// Queues `data` to be written to `path`
+    ///
+    /// Errors from the write itself are logged by the IO thread (and counted by
+    /// [`BatchWriter::failed_writes`]); this only fails if the IO thread is gone.
+    pub fn write(&self, path: impl Into<PathBuf>, data: Vec<u8>) -> io::Result<()> {
+        self.sender
+            .send(Job::Write(path.into(), data))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "IO thread has stopped"))
+    }
+}
+
+/// Writes a file through the queue if there is one, or right away to `storage` if not
+pub(crate) fn write_file(
+    queue: Option<&WriteQueue>,
+    storage: &dyn Storage,
+    path: &Path,
+    data: &[u8],
+) -> io::Result<()> {
+    match queue {
+        Some(queue) => queue.write(path, data.to_vec()),
+        None => storage.write(path, data),
+    }
+}
+
+/// Whether the disk is taking writes, from [`BatchWriter::health`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriterHealth {
+    /// When the writer stopped trying the disk (because it's full or read-only), or `None` if
+    /// writes are going through
+    pub degraded_since: Option<DateTime<Utc>>,
+    /// The last full or read-only error
+    pub last_error: Option<String>,
+    /// Writes that are waiting in memory for the disk to recover
+    pub spooled_files: usize,
+    pub spooled_bytes: usize,
+    /// Spooled writes that were dropped because the spool was full
+    pub dropped_files: u64,
+}
+
+impl WriterHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.degraded_since.is_none() && self.spooled_files == 0
+    }
+}
+
+pub struct BatchWriter {
+    queue: WriteQueue,
+    failed: Arc<AtomicU64>,
+    health: Arc<Mutex<WriterHealth>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BatchWriter {
+    /// Starts the IO thread, writing to the local filesystem
+    pub fn spawn(options: BatchOptions) -> io::Result<BatchWriter> {
+        BatchWriter::spawn_with_storage(options, storage::local())
+    }
+
+    /// Starts the IO thread, writing to `storage`
+    pub fn spawn_with_storage(options: BatchOptions, storage: SharedStorage) -> io::Result<BatchWriter> {
+        let (sender, receiver) = mpsc::sync_channel(options.queue_len);
+        let failed = Arc::new(AtomicU64::new(0));
+        let health = Arc::new(Mutex::new(WriterHealth::default()));
+        let worker = Worker {
+            options,
+            storage,
+            failed: failed.clone(),
+            health: health.clone(),
+            spool: VecDeque::new(),
+            spool_bytes: 0,
+            failures_in_a_row: 0,
+            next_probe: None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("batch-writer".to_string())
+            .spawn(move || worker.run(receiver))?;
+        Ok(BatchWriter {
+            queue: WriteQueue { sender },
+            failed,
+            health,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn queue(&self) -> WriteQueue {
+        self.queue.clone()
+    }
+
+    /// Writes everything that has been queued so far, and waits for it to finish
+    ///
+    /// Spooled writes are tried again, even if the disk hasn't been checked yet.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.queue.sender.send(Job::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// How many writes have failed since the writer was started
+    ///
+    /// Writes that are spooled because the disk is full or read-only only count once they're
+    /// dropped.
+    pub fn failed_writes(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Whether writes are going to disk, or being spooled in memory
+    pub fn health(&self) -> WriterHealth {
+        self.health.lock().unwrap().clone()
+    }
+}
+
+impl Drop for BatchWriter {
+    /// Writes anything still queued before returning
+    fn drop(&mut self) {
+        let _ = self.queue.sender.send(Job::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns true for errors that will keep happening until someone fixes the disk
+fn is_disk_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::StorageFull | io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::QuotaExceeded
+    )
+}
+
+/// The IO thread
+struct Worker {
+    options: BatchOptions,
+    storage: SharedStorage,
+    failed: Arc<AtomicU64>,
+    health: Arc<Mutex<WriterHealth>>,
+    /// Writes that failed because the disk is full or read-only, oldest first
+    spool: VecDeque<(PathBuf, Vec<u8>)>,
+    spool_bytes: usize,
+    failures_in_a_row: u32,
+    /// When to check the disk again, if the writer has stopped trying it
+    next_probe: Option<Instant>,
+}
+
+impl Worker {
+    fn run(mut self, receiver: Receiver<Job>) {
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let wake = match (deadline, self.next_probe) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let job = match wake {
+                Some(wake) => receiver.recv_timeout(wake.saturating_duration_since(Instant::now())),
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match job {
+                Ok(Job::Write(path, data)) => {
+                    batch_bytes += data.len();
+                    batch.push((path, data));
+                    deadline.get_or_insert_with(|| Instant::now() + self.options.max_delay);
+                    if batch_bytes < self.options.max_bytes {
+                        continue;
+                    }
+                }
+                Ok(Job::Flush(done)) => {
+                    self.retry_spool();
+                    self.write_batch(&mut batch);
+                    let _ = done.send(());
+                }
+                Ok(Job::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                    self.retry_spool();
+                    self.write_batch(&mut batch);
+                    if !self.spool.is_empty() {
+                        error!(
+                            "Lost {} spooled files ({} bytes), the disk never recovered",
+                            self.spool.len(),
+                            self.spool_bytes
+                        );
+                    }
+                    return;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if matches!(self.next_probe, Some(probe) if probe <= Instant::now()) {
+                        self.retry_spool();
+                    }
+                    if !matches!(deadline, Some(deadline) if deadline <= Instant::now()) {
+                        continue;
+                    }
+                }
+            }
+            self.write_batch(&mut batch);
+            batch_bytes = 0;
+            deadline = None;
+        }
+    }
+
+    fn write_batch(&mut self, batch: &mut Vec<(PathBuf, Vec<u8>)>) {
+        let sync = self.options.sync;
+        let mut to_sync = Vec::new();
+        for (path, data) in batch.drain(..) {
+            if self.next_probe.is_some() {
+                // the disk is known to be bad, so don't bother trying it
+                self.push_spool(path, data);
+                continue;
+            }
+            let result = self.storage.write(&path, &data).and_then(|()| {
+                match sync {
+                    SyncPolicy::None => {}
+                    SyncPolicy::EachFile => self.storage.sync(&path)?,
+                    SyncPolicy::EndOfBatch => to_sync.push(path.clone()),
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => self.failures_in_a_row = 0,
+                Err(e) if is_disk_error(&e) => {
+                    warn!("Failed to write {}, keeping it in memory: {}", path.display(), e);
+                    self.disk_failed(&e);
+                    self.push_spool(path, data);
+                }
+                Err(e) => {
+                    warn!("Failed to write {}: {}", path.display(), e);
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        for path in to_sync {
+            if let Err(e) = self.storage.sync(&path) {
+                warn!("Failed to sync {}: {}", path.display(), e);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.update_health();
+    }
+
+    /// Counts a full or read-only failure, and stops trying the disk if there have been too many
+    fn disk_failed(&mut self, e: &io::Error) {
+        self.failures_in_a_row += 1;
+        self.health.lock().unwrap().last_error = Some(e.to_string());
+        if self.failures_in_a_row >= self.options.failure_threshold && self.next_probe.is_none() {
+            error!(
+                "Writes keep failing ({}), spooling them in memory until the disk recovers",
+                e
+            );
+            self.next_probe = Some(Instant::now() + self.options.probe_interval);
+            self.health.lock().unwrap().degraded_since = Some(Utc::now());
+        }
+    }
+
+    fn push_spool(&mut self, path: PathBuf, data: Vec<u8>) {
+        self.spool_bytes += data.len();
+        self.spool.push_back((path, data));
+        while self.spool_bytes > self.options.spool_bytes {
+            let (path, data) = match self.spool.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            warn!("Spool is full, dropping {}", path.display());
+            self.spool_bytes -= data.len();
+            self.failed.fetch_add(1, Ordering::Relaxed);
+            self.health.lock().unwrap().dropped_files += 1;
+        }
+    }
+
+    /// Tries to write out the spool, oldest first, stopping at the first full or read-only failure
+    fn retry_spool(&mut self) {
+        if self.spool.is_empty() {
+            return;
+        }
+        let mut written = 0;
+        while let Some((path, data)) = self.spool.pop_front() {
+            match self.storage.write(&path, &data) {
+                Ok(()) => written += 1,
+                Err(e) if is_disk_error(&e) => {
+                    self.spool.push_front((path, data));
+                    self.health.lock().unwrap().last_error = Some(e.to_string());
+                    break;
+                }
+                Err(e) => {
+                    warn!("Failed to write spooled {}: {}", path.display(), e);
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            self.spool_bytes -= data.len();
+        }
+
+        if self.spool.is_empty() {
+            if self.next_probe.take().is_some() {
+                info!("The disk has recovered, wrote {} spooled files", written);
+                self.health.lock().unwrap().degraded_since = None;
+            }
+            self.failures_in_a_row = 0;
+        } else if self.next_probe.is_some() {
+            self.next_probe = Some(Instant::now() + self.options.probe_interval);
+        }
+        self.update_health();
+    }
+
+    fn update_health(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.spooled_files = self.spool.len();
+        health.spooled_bytes = self.spool_bytes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BatchOptions, BatchWriter, SyncPolicy, Worker};
+
+    #[test]
+    fn test_batch_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = BatchWriter::spawn(BatchOptions {
+            max_delay: Duration::from_secs(3600),
+            sync: SyncPolicy::EndOfBatch,
+            ..Default::default()
+        })
+        .unwrap();
+        let queue = writer.queue();
+
+        for i in 0..10 {
+            queue.write(dir.path().join(format!("{}.txt", i)), vec![i; 10]).unwrap();
+        }
+        // a bad path fails on the IO thread, without affecting the rest of the batch
+        queue.write(dir.path().join("missing/x.txt"), vec![1]).unwrap();
+        writer.flush();
+        assert_eq!(std::fs::read(dir.path().join("3.txt")).unwrap(), vec![3; 10]);
+        assert_eq!(writer.failed_writes(), 1);
+
+        // anything still queued is written when the writer is dropped
+        queue.write(dir.path().join("last.txt"), b"last".to_vec()).unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read(dir.path().join("last.txt")).unwrap(), b"last");
+        assert!(queue.write(dir.path().join("late.txt"), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_batch_size_and_delay() {
+        let dir = tempfile::tempdir().unwrap();
+        let writer = BatchWriter::spawn(BatchOptions {
+            max_bytes: 100,
+            max_delay: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // a full batch is written right away
+        let big = dir.path().join("big");
+        writer.queue().write(&big, vec![0; 100]).unwrap();
+        // and a small one once it has waited long enough
+        let small = dir.path().join("small");
+        writer.queue().write(&small, vec![0; 10]).unwrap();
+
+        let start = std::time::Instant::now();
+        while !(big.exists() && small.exists()) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_spool_while_disk_is_full() {
+        // every write to /dev/full fails with ENOSPC
+        let writer = BatchWriter::spawn(BatchOptions {
+            max_delay: Duration::from_secs(3600),
+            spool_bytes: 25,
+            failure_threshold: 2,
+            probe_interval: Duration::from_secs(3600),
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..3 {
+            writer.queue().write("/dev/full", vec![i; 10]).unwrap();
+        }
+        writer.flush();
+        let health = writer.health();
+        assert!(health.degraded_since.is_some() && !health.is_healthy());
+        // the oldest file was dropped to keep the spool under 25 bytes
+        assert_eq!(
+            (health.spooled_files, health.spooled_bytes, health.dropped_files),
+            (2, 20, 1)
+        );
+        assert_eq!(writer.failed_writes(), 1);
+
+        // once the disk recovers, the spool is written out
+        let dir = tempfile::tempdir().unwrap();
+        let mut worker = Worker {
+            options: Default::default(),
+            storage: crate::storage::local(),
+            failed: Default::default(),
+            health: Default::default(),
+            spool: Default::default(),
+            spool_bytes: 0,
+            failures_in_a_row: 3,
+            next_probe: Some(std::time::Instant::now()),
+        };
+        worker.push_spool(dir.path().join("a.txt"), b"a".to_vec());
+        worker.push_spool(dir.path().join("b.txt"), b"b".to_vec());
+        worker.retry_spool();
+        assert_eq!(std::fs::read(dir.path().join("b.txt")).unwrap(), b"b");
+        assert!(worker.next_probe.is_none());
+        assert!(worker.health.lock().unwrap().is_healthy());
+    }
+}
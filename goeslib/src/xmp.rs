@@ -0,0 +1,245 @@
+//! Embedding product metadata into saved images
+//!
+//! Saved images are just pixels, so once they've been copied somewhere else it can be hard to tell
+//! which satellite, band, or time they came from.  This module builds an XMP packet describing the
+//! product and inserts it into JPEG files as an APP1 segment, which most photo managers and tools
+//! like `exiftool` understand.
+//!
+//! Ref: XMP Specification Part 3, section 1.1.3 (JPEG)
+use chrono::{DateTime, Utc};
+
+use crate::{annotation::GoesRFilename, calibration, lrit::LRIT};
+
+/// The signature that identifies an XMP APP1 segment in a JPEG file
+const XMP_JPEG_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Namespace for goesbox specific XMP properties
+const GOESBOX_NS: &str = "https://github.com/eminence/goesbox/ns/1.0/";
+
+/// Product metadata that is embedded into saved images
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImageMetadata {
+    /// Original product filename (from the annotation header)
+    pub product: String,
+    /// Satellite identifier, like "G16"
+    pub satellite: Option<String>,
+    /// ABI band, like "C13"
+    pub band: Option<String>,
+    /// Scan region, like "Full Disk" or "Mesoscale 1"
+    pub region: Option<String>,
+    /// Start of the scan that produced this image
+    pub scan_start: Option<DateTime<Utc>>,
+    /// Projection name from the image navigation header, like "GEOS(-75.0)"
+    pub projection: Option<String>,
+    pub column_scaling_factor: Option<i32>,
+    pub line_scaling_factor: Option<i32>,
+    pub column_offset: Option<i32>,
+    pub line_offset: Option<i32>,
+}
+
+impl ImageMetadata {
+    /// Collect metadata from the headers of an image LRIT file
+    ///
+    /// For segmented images, any segment will do.
+    pub fn from_lrit(lrit: &LRIT) -> ImageMetadata {
+        let product = lrit
+            .headers
+            .annotation
+            .as_ref()
+            .map(|a| a.text.clone())
+            .unwrap_or_default();
+        let mut meta = ImageMetadata::from_product_name(&product);
+
+        if let Some(nav) = &lrit.headers.img_navigation {
+            meta.projection = Some(nav.projection_name.clone());
+            meta.column_scaling_factor = Some(nav.column_scaling_factor);
+            meta.line_scaling_factor = Some(nav.line_scaling_factor);
+            meta.column_offset = Some(nav.column_offset);
+            meta.line_offset = Some(nav.line_offset);
+        }
+
+        // products that don't use GOES-R names can still say what they are in the ancillary text
+        if let Some(text) = &lrit.headers.text {
+            let mut params = text.params();
+            meta.satellite = meta.satellite.or_else(|| params.remove("Satellite"));
+            meta.band = meta.band.or_else(|| params.remove("Channel"));
+            meta.region = meta.region.or_else(|| params.remove("Region"));
+        }
+
+        meta
+    }
+
+    /// Extracts what we can from a GOES-R product filename
+    ///
+    /// These look like `OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit`.
+    /// Filenames that don't follow this pattern will only have the `product` field set.
+    pub fn from_product_name(product: &str) -> ImageMetadata {
+        let mut meta = ImageMetadata {
+            product: product.to_string(),
+            ..Default::default()
+        };
+
+        if let Some(goes) = GoesRFilename::parse(product) {
+            meta.region = goes.region().map(|r| r.to_string());
+            meta.satellite = Some(goes.satellite);
+            meta.band = goes.band;
+            meta.scan_start = goes.scan_start;
+        }
+
+        meta
+    }
+
+    /// Render this metadata as an XMP packet
+    pub fn to_xmp(&self) -> String {
+        let mut props = String::new();
+        let mut add = |name: &str, value: &str| {
+            props.push_str(&format!("   <{}>{}</{}>\n", name, escape_xml(value), name));
+        };
+
+        add("dc:title", &self.product);
+        if let Some(t) = &self.scan_start {
+            let t = t.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string();
+            add("xmp:CreateDate", &t);
+            add("exif:DateTimeOriginal", &t);
+        }
+        if let Some(s) = &self.satellite {
+            add("goesbox:Satellite", s);
+        }
+        if let Some(b) = &self.band {
+            add("goesbox:Band", b);
+            if let Some(band) = calibration::band(b) {
+                add("goesbox:Wavelength", &band.wavelength.to_string());
+            }
+        }
+        if let Some(r) = &self.region {
+            add("goesbox:Region", r);
+        }
+        if let Some(p) = &self.projection {
+            add("goesbox:Projection", p);
+        }
+        for (name, val) in [
+            ("goesbox:ColumnScalingFactor", self.column_scaling_factor),
+            ("goesbox:LineScalingFactor", self.line_scaling_factor),
+            ("goesbox:ColumnOffset", self.column_offset),
+            ("goesbox:LineOffset", self.line_offset),
+        ] {
+            if let Some(val) = val {
+                add(name, &val.to_string());
+            }
+        }
+
+        format!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+             <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+             <rdf:Description rdf:about=\"\"\n\
+             \x20   xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n\
+             \x20   xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n\
+             \x20   xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"\n\
+             \x20   xmlns:goesbox=\"{}\">\n\
+             {}\
+             </rdf:Description>\n\
+             </rdf:RDF>\n\
+             </x:xmpmeta>\n\
+             <?xpacket end=\"w\"?>",
+            GOESBOX_NS, props
+        )
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Inserts an XMP packet into an encoded JPEG image
+///
+/// The XMP APP1 segment is placed right after the SOI marker (and after a JFIF APP0 segment, if
+/// present).  Returns `None` if `jpeg` doesn't look like a JPEG file, or if the packet is too
+/// large to fit into a single segment.
+pub fn embed_in_jpeg(jpeg: &[u8], xmp: &str) -> Option<Vec<u8>> {
+    if jpeg.len() < 4 || jpeg[0..2] != [0xff, 0xd8] {
+        return None;
+    }
+
+    // segment length includes the 2 length bytes themselves
+    let seg_len = 2 + XMP_JPEG_SIGNATURE.len() + xmp.len();
+    if seg_len > u16::MAX as usize {
+        return None;
+    }
+
+    // skip over the JFIF header, since some readers expect it to come first
+    let mut insert_at = 2;
+    if jpeg[2..4] == [0xff, 0xe0] && jpeg.len() >= 6 {
+        let app0_len = u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        insert_at = (4 + app0_len).min(jpeg.len());
+    }
+
+    let mut out = Vec::with_capacity(jpeg.len() + seg_len + 2);
+    out.extend_from_slice(&jpeg[..insert_at]);
+    out.extend_from_slice(&[0xff, 0xe1]);
+    out.extend_from_slice(&(seg_len as u16).to_be_bytes());
+    out.extend_from_slice(XMP_JPEG_SIGNATURE);
+    out.extend_from_slice(xmp.as_bytes());
+    out.extend_from_slice(&jpeg[insert_at..]);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Timelike};
+
+    use super::ImageMetadata;
+
+    #[test]
+    fn test_from_product_name() {
+        let meta = ImageMetadata::from_product_name(
+            "OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205_e20221241809513_c20221241809580.lrit",
+        );
+        assert_eq!(meta.satellite.as_deref(), Some("G16"));
+        assert_eq!(meta.band.as_deref(), Some("C13"));
+        assert_eq!(meta.region.as_deref(), Some("Full Disk"));
+        let start = meta.scan_start.unwrap();
+        assert_eq!(start.naive_utc().date(), NaiveDate::from_ymd_opt(2022, 5, 4).unwrap());
+        assert_eq!((start.hour(), start.minute(), start.second()), (18, 0, 20));
+
+        let meta = ImageMetadata::from_product_name("OR_ABI-L2-CMIPM1-M6C02_G16_s20221241800205.lrit");
+        assert_eq!(meta.region.as_deref(), Some("Mesoscale 1"));
+        assert_eq!(meta.band.as_deref(), Some("C02"));
+
+        let meta = ImageMetadata::from_product_name("some_other_image.lrit");
+        assert_eq!(meta.satellite, None);
+        assert_eq!(meta.scan_start, None);
+        assert_eq!(meta.product, "some_other_image.lrit");
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_embed_in_jpeg() {
+        use super::embed_in_jpeg;
+
+        let img = image::GrayImage::new(8, 8);
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode_image(&img)
+            .unwrap();
+
+        let meta = ImageMetadata::from_product_name("OR_ABI-L2-CMIPF-M6C13_G16_s20221241800205.lrit");
+        let xmp = meta.to_xmp();
+        assert!(xmp.contains("<goesbox:Band>C13</goesbox:Band>"));
+        assert!(xmp.contains("<goesbox:Wavelength>10.3</goesbox:Wavelength>"));
+
+        let out = embed_in_jpeg(&jpeg, &xmp).unwrap();
+        assert_eq!(out.len(), jpeg.len() + xmp.len() + 4 + 29);
+        let needle = b"http://ns.adobe.com/xap/1.0/\0";
+        assert!(out.windows(needle.len()).any(|w| w == needle));
+
+        // still decodes fine
+        let decoded = image::load_from_memory(&out).unwrap();
+        assert_eq!(decoded.width(), 8);
+
+        assert!(embed_in_jpeg(b"not a jpeg", &xmp).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+//! Replays the captures in `testdata/replay` through the whole pipeline, and checks that exactly
+//! the expected products come out
+//!
+//! Each `<name>.vcdu` capture has a `<name>.products` manifest next to it, with a line for every
+//! file that's written: its CRC-32, its size, and its path under the output root.  After adding a
+//! capture (or a change that's meant to change what's written), write the manifests again with
+//!
+//! ```text
+//! GOESBOX_BLESS=1 cargo test -p goeslib --test replay
+//! ```
+//!
+//! and look over the differences before checking them in.
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{Duration, TimeZone, Utc};
+use goeslib::{
+    capture::CaptureReader,
+    crc::calc_crc32,
+    handlers::{Dispatcher, RawLritHandler, RetryPolicy, TextHandler},
+    lrit::{VirtualChannel, VCDU},
+    sim::{LossInjector, Simulator},
+    stats::Stats,
+    storage::{MemoryStorage, SharedStorage},
+};
+
+fn replay_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/replay")
+}
+
+/// Runs a capture through the decoder and the handlers whose output doesn't depend on an encoder
+/// (so not images), returning a manifest line for every file written
+fn replay(capture: &Path) -> Vec<String> {
+    let root = tempfile::tempdir().unwrap();
+    let storage = MemoryStorage::new();
+    let shared: SharedStorage = Arc::new(storage.clone());
+    let mut handlers = Dispatcher::new().with_retry_policy(RetryPolicy::none());
+    handlers.push(Box::new(TextHandler::new(root.path()).with_storage(shared.clone())));
+    handlers.push(Box::new(RawLritHandler::new(root.path()).with_storage(shared)));
+
+    let mut stats = Stats::new();
+    let mut channels: HashMap<u8, VirtualChannel> = HashMap::new();
+    for frame in CaptureReader::new(File::open(capture).unwrap()) {
+        let frame = frame.unwrap();
+        let vcdu = VCDU::new(&frame);
+        if vcdu.is_fill() {
+            continue;
+        }
+        let (id, counter) = (vcdu.vcid(), vcdu.counter());
+        let channel = channels.entry(id).or_insert_with(|| VirtualChannel::new(id, counter));
+        for lrit in channel.process_vcdu(vcdu, &mut stats) {
+            let failures = handlers.dispatch(&lrit);
+            assert!(failures.is_empty(), "{:?}: {:?}", lrit.headers.annotation, failures);
+        }
+    }
+    assert!(handlers.flush().is_empty());
+
+    // raw LRIT files go in a directory named after the day they were received
+    let today = Utc::now().format("%Y%m%d").to_string();
+    storage
+        .paths()
+        .into_iter()
+        .map(|path| {
+            let data = storage.get(&path).unwrap();
+            let name = path
+                .strip_prefix(root.path())
+                .unwrap()
+                .to_string_lossy()
+                .replace(&today, "YYYYMMDD");
+            format!("{:08x} {} {}", calc_crc32(&data), data.len(), name)
+        })
+        .collect()
+}
+
+#[test]
+fn test_replay_captures() {
+    let bless = std::env::var_os("GOESBOX_BLESS").is_some();
+    let mut captures: Vec<PathBuf> = std::fs::read_dir(replay_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("vcdu".as_ref()))
+        .collect();
+    captures.sort();
+    assert!(!captures.is_empty());
+
+    for capture in captures {
+        let products = replay(&capture);
+        let manifest = capture.with_extension("products");
+        if bless {
+            std::fs::write(&manifest, products.join("\n") + "\n").unwrap();
+            continue;
+        }
+        let expected = std::fs::read_to_string(&manifest)
+            .unwrap_or_else(|e| panic!("{}: {} (run with GOESBOX_BLESS=1 to write it)", manifest.display(), e));
+        assert_eq!(products, expected.lines().collect::<Vec<_>>(), "{}", capture.display());
+    }
+}
+
+/// Records the simulated captures again (they're checked in, so this only needs to be run if the
+/// simulator changes): `cargo test -p goeslib --test replay -- --ignored`
+#[test]
+#[ignore]
+fn record_simulated_captures() {
+    let start = Utc.with_ymd_and_hms(2022, 5, 4, 18, 0, 0).unwrap();
+    // a minute of downlink, with one small image
+    let mut sim = Simulator::new().image_size(64, 2).image_interval(Duration::minutes(5));
+    let mut frames = Vec::new();
+    for second in 0..60 {
+        sim.queue_due(start + Duration::seconds(second));
+        while !sim.is_idle() {
+            frames.push(sim.next_vcdu());
+        }
+    }
+
+    std::fs::write(replay_dir().join("simulated.vcdu"), frames.concat()).unwrap();
+    // and the same minute, received badly
+    let mut loss = LossInjector::new(5).drop_rate(0.05).reorder_rate(0.05);
+    let lossy: Vec<_> = frames.into_iter().flat_map(|frame| loss.process(frame)).collect();
+    std::fs::write(replay_dir().join("simulated-lossy.vcdu"), lossy.concat()).unwrap();
+}